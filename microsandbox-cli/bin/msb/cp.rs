@@ -0,0 +1,318 @@
+//! Copying files between the host and a running sandbox.
+//!
+//! Files are transferred through the sandbox server, which forwards the `sandbox.fs.*` methods to
+//! the portal running inside the sandbox. Large files are streamed in chunks, and directories are
+//! copied recursively with their permission bits.
+
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use microsandbox_cli::{MicrosandboxCliError, MicrosandboxCliResult};
+use serde_json::json;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use super::rpc::SandboxClient;
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// Prefix marking a path as being inside a sandbox
+const SANDBOX_PREFIX: &str = "sandbox:";
+
+/// Optional prefix marking a path as being on the host
+const HOST_PREFIX: &str = "host:";
+
+/// Size of each chunk a file is streamed in
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// One side of a copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyLocation {
+    /// A path on the host
+    Host(PathBuf),
+
+    /// A path inside a sandbox
+    Sandbox {
+        /// Name of the sandbox
+        name: String,
+
+        /// Path inside the sandbox
+        path: String,
+    },
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Copy a file or directory from the host into a sandbox
+///
+/// If the destination is an existing directory, the source is copied into it.
+pub async fn copy_to_sandbox(
+    client: &SandboxClient,
+    source: &Path,
+    destination: &str,
+) -> MicrosandboxCliResult<()> {
+    let metadata = fs::metadata(source).await?;
+    let destination = match stat(client, destination).await? {
+        Some((kind, _)) if kind == "directory" => join_guest(destination, &file_name(source)?),
+        _ => destination.to_string(),
+    };
+
+    if !metadata.is_dir() {
+        return upload_file(client, source, &destination, metadata.permissions().mode()).await;
+    }
+
+    // Walk the tree, creating each directory before its contents
+    let mut pending = vec![(source.to_path_buf(), destination)];
+    while let Some((host_dir, guest_dir)) = pending.pop() {
+        let mode = fs::metadata(&host_dir).await?.permissions().mode() & 0o7777;
+        client
+            .call(
+                "sandbox.fs.mkdir",
+                json!({ "path": guest_dir, "mode": mode }),
+            )
+            .await?;
+
+        let mut read_dir = fs::read_dir(&host_dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let guest_path = join_guest(&guest_dir, &entry.file_name().to_string_lossy());
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push((entry.path(), guest_path));
+            } else if file_type.is_file() {
+                let mode = entry.metadata().await?.permissions().mode();
+                upload_file(client, &entry.path(), &guest_path, mode).await?;
+            } else {
+                tracing::warn!("skipping {}: not a regular file", entry.path().display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy a file or directory from a sandbox to the host
+///
+/// If the destination is an existing directory, the source is copied into it.
+pub async fn copy_from_sandbox(
+    client: &SandboxClient,
+    source: &str,
+    destination: &Path,
+) -> MicrosandboxCliResult<()> {
+    let Some((kind, mode)) = stat(client, source).await? else {
+        return Err(MicrosandboxCliError::NotFound(format!(
+            "{} in sandbox {}",
+            source,
+            client.sandbox()
+        )));
+    };
+
+    let destination = if destination.is_dir() {
+        destination.join(guest_file_name(source)?)
+    } else {
+        destination.to_path_buf()
+    };
+
+    if kind != "directory" {
+        return download_file(client, source, &destination, mode).await;
+    }
+
+    fs::create_dir_all(&destination).await?;
+    let listing = client
+        .call("sandbox.fs.list", json!({ "path": source }))
+        .await?;
+
+    // Directories are listed before their contents, so parents always exist
+    for entry in listing["entries"].as_array().into_iter().flatten() {
+        let path = entry["path"].as_str().unwrap_or_default();
+        let mode = entry["mode"].as_u64().unwrap_or(0o644) as u32;
+        let host_path = destination.join(listed_path(path)?);
+        match entry["kind"].as_str() {
+            Some("directory") => {
+                fs::create_dir_all(&host_path).await?;
+                fs::set_permissions(&host_path, std::fs::Permissions::from_mode(mode)).await?;
+            }
+            Some("file") => {
+                download_file(client, &join_guest(source, path), &host_path, mode).await?;
+            }
+            _ => tracing::warn!("skipping {}: not a regular file", path),
+        }
+    }
+
+    fs::set_permissions(&destination, std::fs::Permissions::from_mode(mode)).await?;
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Get the kind and mode of a sandbox path, or None if it doesn't exist
+async fn stat(client: &SandboxClient, path: &str) -> MicrosandboxCliResult<Option<(String, u32)>> {
+    match client
+        .call("sandbox.fs.stat", json!({ "path": path }))
+        .await
+    {
+        Ok(entry) => Ok(Some((
+            entry["kind"].as_str().unwrap_or_default().to_string(),
+            entry["mode"].as_u64().unwrap_or(0o644) as u32,
+        ))),
+        Err(MicrosandboxCliError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Stream a host file into the sandbox, applying its mode once it is complete
+async fn upload_file(
+    client: &SandboxClient,
+    source: &Path,
+    destination: &str,
+    mode: u32,
+) -> MicrosandboxCliResult<()> {
+    let mut file = fs::File::open(source).await?;
+    let size = file.metadata().await?.len();
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut offset = 0u64;
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        let last = read == 0 || offset + read as u64 >= size;
+        let mut params = json!({
+            "path": destination,
+            "offset": offset,
+            "data": BASE64.encode(&buffer[..read]),
+        });
+        if last {
+            params["mode"] = json!(mode & 0o7777);
+        }
+
+        client.call("sandbox.fs.write", params).await?;
+        offset += read as u64;
+
+        if last {
+            return Ok(());
+        }
+    }
+}
+
+/// Stream a sandbox file to the host, applying its mode once it is complete
+async fn download_file(
+    client: &SandboxClient,
+    source: &str,
+    destination: &Path,
+    mode: u32,
+) -> MicrosandboxCliResult<()> {
+    if let Some(parent) = destination.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let mut file = fs::File::create(destination).await?;
+    let mut offset = 0u64;
+
+    loop {
+        let chunk = client
+            .call(
+                "sandbox.fs.read",
+                json!({ "path": source, "offset": offset, "length": CHUNK_SIZE }),
+            )
+            .await?;
+
+        let data = BASE64
+            .decode(chunk["data"].as_str().unwrap_or_default())
+            .map_err(|e| MicrosandboxCliError::ServerRequest(e.to_string()))?;
+        file.write_all(&data).await?;
+        offset += data.len() as u64;
+
+        if chunk["eof"].as_bool().unwrap_or(true) || data.is_empty() {
+            break;
+        }
+    }
+
+    file.flush().await?;
+    fs::set_permissions(destination, std::fs::Permissions::from_mode(mode)).await?;
+    Ok(())
+}
+
+/// Join a relative path onto a sandbox path
+fn join_guest(base: &str, relative: &str) -> String {
+    format!("{}/{}", base.trim_end_matches('/'), relative)
+}
+
+/// Check that a path from a sandbox listing stays inside the directory it is copied into
+///
+/// The listing comes from the guest, so an absolute path or a `..` component could otherwise
+/// write anywhere on the host.
+fn listed_path(path: &str) -> MicrosandboxCliResult<&Path> {
+    let relative = Path::new(path);
+    let mut components = relative.components().peekable();
+    if components.peek().is_none()
+        || !components.all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(MicrosandboxCliError::InvalidArgument(format!(
+            "sandbox listed an unsafe path: {}",
+            path
+        )));
+    }
+
+    Ok(relative)
+}
+
+/// Get the final component of a host path
+fn file_name(path: &Path) -> MicrosandboxCliResult<String> {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| {
+            MicrosandboxCliError::InvalidArgument(format!("{} has no file name", path.display()))
+        })
+}
+
+/// Get the final component of a sandbox path
+fn guest_file_name(path: &str) -> MicrosandboxCliResult<String> {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .ok_or_else(|| MicrosandboxCliError::InvalidArgument(format!("{} has no file name", path)))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl FromStr for CopyLocation {
+    type Err = MicrosandboxCliError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some(rest) = s.strip_prefix(SANDBOX_PREFIX) else {
+            let path = s.strip_prefix(HOST_PREFIX).unwrap_or(s);
+            return Ok(CopyLocation::Host(PathBuf::from(path)));
+        };
+
+        match rest.split_once(':') {
+            Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+                Ok(CopyLocation::Sandbox {
+                    name: name.to_string(),
+                    path: path.to_string(),
+                })
+            }
+            _ => Err(MicrosandboxCliError::InvalidArgument(format!(
+                "expected sandbox:<name>:<path>, got {}",
+                s
+            ))),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Running interactive commands in a sandbox.
+//!
+//! The command runs in a pseudo-terminal inside the sandbox, driven through the `sandbox.pty.*`
+//! methods. The local terminal is switched to raw mode so every keystroke, including control
+//! characters, reaches the command, and window size changes are passed along as they happen.
+
+use std::io::{IsTerminal, Write};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use crossterm::terminal;
+use microsandbox_cli::{MicrosandboxCliError, MicrosandboxCliResult};
+use serde_json::json;
+use tokio::{
+    io::AsyncReadExt,
+    signal::unix::{SignalKind, signal},
+};
+
+use super::rpc::SandboxClient;
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How long each read waits for output, in milliseconds
+const READ_TIMEOUT_MS: u64 = 1000;
+
+/// Size of the buffer input is read into
+const INPUT_BUFFER_SIZE: usize = 1024;
+
+/// Terminal size used when stdout is not a terminal
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Restores the local terminal when dropped
+struct RawModeGuard;
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Run a command interactively in a sandbox
+///
+/// ## Returns
+///
+/// The exit code of the command.
+pub async fn exec_interactive(
+    client: &SandboxClient,
+    command: String,
+    args: Vec<String>,
+) -> MicrosandboxCliResult<i32> {
+    let (cols, rows) = terminal_size();
+    let opened = client
+        .call(
+            "sandbox.pty.open",
+            json!({ "command": command, "args": args, "rows": rows, "cols": cols }),
+        )
+        .await?;
+    let session = opened["session"]
+        .as_str()
+        .ok_or_else(|| MicrosandboxCliError::ServerRequest("missing session id".to_string()))?
+        .to_string();
+
+    let guard = if std::io::stdin().is_terminal() {
+        terminal::enable_raw_mode()?;
+        Some(RawModeGuard)
+    } else {
+        None
+    };
+
+    let input = tokio::spawn(forward_input(client.clone(), session.clone()));
+    let resize = tokio::spawn(forward_resize(client.clone(), session.clone()));
+    let result = forward_output(client, &session).await;
+
+    input.abort();
+    resize.abort();
+    let _ = client
+        .call("sandbox.pty.close", json!({ "session": session }))
+        .await;
+    drop(guard);
+
+    result
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Write the command's output to stdout until it exits
+async fn forward_output(client: &SandboxClient, session: &str) -> MicrosandboxCliResult<i32> {
+    let mut stdout = std::io::stdout();
+    loop {
+        let output = client
+            .call(
+                "sandbox.pty.read",
+                json!({ "session": session, "timeout_ms": READ_TIMEOUT_MS }),
+            )
+            .await?;
+
+        let data = BASE64
+            .decode(output["data"].as_str().unwrap_or_default())
+            .map_err(|e| MicrosandboxCliError::ServerRequest(e.to_string()))?;
+        if !data.is_empty() {
+            stdout.write_all(&data)?;
+            stdout.flush()?;
+        }
+
+        if let Some(code) = output["exit_code"].as_i64() {
+            return Ok(code as i32);
+        }
+    }
+}
+
+/// Send stdin to the command until stdin is closed
+async fn forward_input(client: SandboxClient, session: String) -> MicrosandboxCliResult<()> {
+    let mut stdin = tokio::io::stdin();
+    let mut buffer = vec![0; INPUT_BUFFER_SIZE];
+    loop {
+        let read = stdin.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok(());
+        }
+
+        client
+            .call(
+                "sandbox.pty.write",
+                json!({ "session": session, "data": BASE64.encode(&buffer[..read]) }),
+            )
+            .await?;
+    }
+}
+
+/// Pass local window size changes on to the command's terminal
+async fn forward_resize(client: SandboxClient, session: String) -> MicrosandboxCliResult<()> {
+    let mut window_change = signal(SignalKind::window_change())?;
+    while window_change.recv().await.is_some() {
+        let (cols, rows) = terminal_size();
+        client
+            .call(
+                "sandbox.pty.resize",
+                json!({ "session": session, "rows": rows, "cols": cols }),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Get the size of the local terminal as columns and rows
+fn terminal_size() -> (u16, u16) {
+    terminal::size().unwrap_or(DEFAULT_SIZE)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Drop for RawModeGuard {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
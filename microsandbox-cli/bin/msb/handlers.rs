@@ -1,20 +1,38 @@
 use clap::{CommandFactory, error::ErrorKind};
 use microsandbox_cli::{
-    AnsiStyles, MicrosandboxArgs, MicrosandboxCliError, MicrosandboxCliResult, SelfAction,
+    AnsiStyles, InspectOutput, MicrosandboxArgs, MicrosandboxCliError, MicrosandboxCliResult,
+    PullOutput, SelfAction,
 };
 use microsandbox_core::{
-    config::START_SCRIPT_NAME,
+    MicrosandboxError,
+    config::{LabelSelector, NetworkMode, RootfsMode, START_SCRIPT_NAME},
     management::{
         config::{self, Component, ComponentType, SandboxConfig},
-        home, menv, orchestra, sandbox, toolchain,
+        doctor::{self, CheckStatus},
+        home,
+        image::{self, ExportFormat},
+        menv::{self, ListFormat},
+        orchestra::{self, SandboxLiveness},
+        sandbox, toolchain,
     },
-    oci::Reference,
+    oci::{
+        Image, PullTimingLayer, Reference,
+        credential_store::{CredentialStore, FileCredentialStore},
+        docker_config,
+    },
+    runtime,
 };
 use microsandbox_server::MicrosandboxServerResult;
 use microsandbox_utils::{PROJECTS_SUBDIR, env};
 use std::{collections::HashMap, path::PathBuf};
 use typed_path::Utf8UnixPathBuf;
 
+use super::{
+    cp::{self, CopyLocation},
+    exec,
+    rpc::SandboxClient,
+};
+
 //--------------------------------------------------------------------------------------------------
 // Constants
 //--------------------------------------------------------------------------------------------------
@@ -27,23 +45,16 @@ const SANDBOX_SCRIPT_SEPARATOR: char = '~';
 
 /// Set the log level based on the command line arguments
 pub fn log_level(args: &MicrosandboxArgs) {
-    let level = if args.trace {
-        Some("trace")
-    } else if args.debug {
-        Some("debug")
-    } else if args.info {
-        Some("info")
-    } else if args.warn {
-        Some("warn")
-    } else if args.error {
-        Some("error")
-    } else {
-        None
-    };
-
     // Set RUST_LOG environment variable only if a level is specified
-    if let Some(level) = level {
-        unsafe { std::env::set_var("RUST_LOG", format!("microsandbox={},msb={}", level, level)) };
+    if let Some(rust_log) = args.rust_log() {
+        unsafe { std::env::set_var("RUST_LOG", rust_log) };
+    }
+}
+
+/// Set the default registry for this invocation based on the command line arguments
+pub fn registry(args: &MicrosandboxArgs) {
+    if let Some(registry) = args.registry.as_deref() {
+        env::set_oci_registry(registry);
     }
 }
 
@@ -58,7 +69,7 @@ pub async fn add_subcommand(
     volumes: Vec<String>,
     ports: Vec<String>,
     envs: Vec<String>,
-    env_file: Option<Utf8UnixPathBuf>,
+    env_file: Vec<Utf8UnixPathBuf>,
     depends_on: Vec<String>,
     workdir: Option<Utf8UnixPathBuf>,
     shell: Option<String>,
@@ -67,6 +78,7 @@ pub async fn add_subcommand(
     imports: Vec<(String, String)>,
     exports: Vec<(String, String)>,
     scope: Option<String>,
+    rootfs_mode: Option<RootfsMode>,
     path: Option<PathBuf>,
     config: Option<String>,
 ) -> MicrosandboxCliResult<()> {
@@ -94,6 +106,7 @@ pub async fn add_subcommand(
         imports: imports.into_iter().map(|(k, v)| (k, v.into())).collect(),
         exports: exports.into_iter().map(|(k, v)| (k, v.into())).collect(),
         scope,
+        rootfs_mode,
     }));
 
     config::add(&names, &component, path.as_deref(), config.as_deref()).await?;
@@ -126,6 +139,7 @@ pub async fn list_subcommand(
     sandbox: bool,
     build: bool,
     file: Option<PathBuf>,
+    format: ListFormat,
 ) -> MicrosandboxCliResult<()> {
     validate_build_sandbox_conflict(build, sandbox, "list", None, None);
     unsupported_build_error(build, "list", None);
@@ -134,7 +148,7 @@ pub async fn list_subcommand(
     let (config, _, _) = config::load_config(path.as_deref(), config.as_deref()).await?;
 
     // Use the new show_list function to display sandboxes
-    menv::show_list(config.get_sandboxes());
+    menv::show_list(config.get_sandboxes(), format)?;
 
     Ok(())
 }
@@ -144,6 +158,7 @@ pub async fn init_subcommand(path: Option<PathBuf>) -> MicrosandboxCliResult<()>
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn run_subcommand(
     sandbox: bool,
     build: bool,
@@ -151,6 +166,10 @@ pub async fn run_subcommand(
     file: Option<PathBuf>,
     detach: bool,
     exec: Option<String>,
+    entrypoint: Option<String>,
+    console_log: bool,
+    rootfs_mode: Option<RootfsMode>,
+    network: Option<NetworkMode>,
     args: Vec<String>,
 ) -> MicrosandboxCliResult<()> {
     validate_build_sandbox_conflict(build, sandbox, "run", Some("[NAME]"), Some("<ARGS>"));
@@ -158,14 +177,19 @@ pub async fn run_subcommand(
     unsupported_build_error(build, "run", Some("[NAME]"));
 
     let (sandbox, script) = parse_name_and_script(&name);
-    if matches!((script, &exec), (Some(_), Some(_))) {
+    let conflicting_option = match (script, &exec, &entrypoint) {
+        (Some(_), Some(_), _) => Some("--exec"),
+        (Some(_), _, Some(_)) => Some("--entrypoint"),
+        _ => None,
+    };
+    if let Some(option) = conflicting_option {
         MicrosandboxArgs::command()
             .override_usage(usage("run", Some("[NAME[~SCRIPT]]"), Some("<ARGS>")))
             .error(
                 ErrorKind::ArgumentConflict,
                 format!(
                     "cannot specify both a script and an `{}` option.",
-                    "--exec".placeholder()
+                    option.placeholder()
                 ),
             )
             .exit();
@@ -180,7 +204,12 @@ pub async fn run_subcommand(
         args,
         detach,
         exec.as_deref(),
+        entrypoint.map(|entrypoint| vec![entrypoint]),
         true,
+        None,
+        console_log,
+        rootfs_mode,
+        network,
     )
     .await?;
 
@@ -209,7 +238,12 @@ pub async fn script_run_subcommand(
         args,
         detach,
         None,
+        None,
         true,
+        None,
+        false,
+        None,
+        None,
     )
     .await?;
 
@@ -227,6 +261,7 @@ pub async fn exe_subcommand(
     workdir: Option<Utf8UnixPathBuf>,
     scope: Option<String>,
     exec: Option<String>,
+    console_log: bool,
     args: Vec<String>,
 ) -> MicrosandboxCliResult<()> {
     let (image, script) = parse_name_and_script(&name);
@@ -245,7 +280,8 @@ pub async fn exe_subcommand(
             .exit();
     }
 
-    sandbox::run_temp(
+    let mut console_output = String::new();
+    let result = sandbox::run_temp(
         &image,
         script,
         cpus,
@@ -258,8 +294,17 @@ pub async fn exe_subcommand(
         exec.as_deref(),
         args,
         true,
+        console_log.then_some(&mut console_output),
     )
-    .await?;
+    .await;
+
+    // The console is shown whether or not the sandbox succeeded, as it helps debug failed boots
+    if !console_output.is_empty() {
+        eprintln!("--- guest console output ---");
+        eprint!("{}", console_output);
+    }
+
+    result?;
 
     Ok(())
 }
@@ -268,14 +313,25 @@ pub async fn up_subcommand(
     sandbox: bool,
     build: bool,
     names: Vec<String>,
+    labels: Vec<LabelSelector>,
     file: Option<PathBuf>,
     detach: bool,
+    max_concurrent_sandboxes: Option<usize>,
 ) -> MicrosandboxCliResult<()> {
     validate_build_sandbox_conflict(build, sandbox, "up", Some("[NAMES]"), None);
     unsupported_build_error(build, "up", Some("[NAMES]"));
 
     let (path, config) = parse_file_path(file);
-    orchestra::up(names, path.as_deref(), config.as_deref(), detach).await?;
+    orchestra::up(
+        names,
+        &labels,
+        path.as_deref(),
+        config.as_deref(),
+        detach,
+        None,
+        max_concurrent_sandboxes,
+    )
+    .await?;
 
     Ok(())
 }
@@ -284,13 +340,14 @@ pub async fn down_subcommand(
     sandbox: bool,
     build: bool,
     names: Vec<String>,
+    labels: Vec<LabelSelector>,
     file: Option<PathBuf>,
 ) -> MicrosandboxCliResult<()> {
     validate_build_sandbox_conflict(build, sandbox, "down", Some("[NAMES]"), None);
     unsupported_build_error(build, "down", Some("[NAMES]"));
 
     let (path, config) = parse_file_path(file);
-    orchestra::down(names, path.as_deref(), config.as_deref()).await?;
+    orchestra::down(names, &labels, path.as_deref(), config.as_deref()).await?;
 
     Ok(())
 }
@@ -300,13 +357,67 @@ pub async fn status_subcommand(
     sandbox: bool,
     build: bool,
     names: Vec<String>,
+    labels: Vec<LabelSelector>,
     file: Option<PathBuf>,
 ) -> MicrosandboxCliResult<()> {
     validate_build_sandbox_conflict(build, sandbox, "status", Some("[NAMES]"), None);
     unsupported_build_error(build, "status", Some("[NAMES]"));
 
     let (path, config) = parse_file_path(file);
-    orchestra::show_status(&names, path.as_deref(), config.as_deref()).await?;
+    orchestra::show_status(&names, &labels, path.as_deref(), config.as_deref()).await?;
+
+    Ok(())
+}
+
+/// Handle the healthcheck subcommand, which exits with a code telling whether a sandbox is alive
+pub async fn healthcheck_subcommand(name: String, file: Option<PathBuf>) -> ! {
+    let (path, config) = parse_file_path(file);
+    let exit_code = match orchestra::liveness(&name, path.as_deref(), config.as_deref()).await {
+        Ok(liveness) => liveness.exit_code(),
+        Err(e) => {
+            tracing::debug!("failed to check liveness of sandbox {}: {}", name, e);
+            SandboxLiveness::CHECK_FAILED_EXIT_CODE
+        }
+    };
+
+    std::process::exit(exit_code);
+}
+
+/// Handle the inspect subcommand to print the effective configuration of a sandbox
+pub async fn inspect_subcommand(
+    name: String,
+    file: Option<PathBuf>,
+    output: InspectOutput,
+) -> MicrosandboxCliResult<()> {
+    let (path, config) = parse_file_path(file);
+    let effective = config::inspect(&name, path.as_deref(), config.as_deref(), true).await?;
+
+    let rendered = match output {
+        InspectOutput::Yaml => {
+            serde_yaml::to_string(&effective).map_err(MicrosandboxError::from)?
+        }
+        InspectOutput::Json => {
+            serde_json::to_string_pretty(&effective).map_err(MicrosandboxError::from)?
+        }
+    };
+    println!("{}", rendered.trim_end());
+
+    Ok(())
+}
+
+/// Handle the top subcommand to show the combined resource usage of specified sandboxes
+pub async fn top_subcommand(
+    sandbox: bool,
+    build: bool,
+    names: Vec<String>,
+    labels: Vec<LabelSelector>,
+    file: Option<PathBuf>,
+) -> MicrosandboxCliResult<()> {
+    validate_build_sandbox_conflict(build, sandbox, "top", Some("[NAMES]"), None);
+    unsupported_build_error(build, "top", Some("[NAMES]"));
+
+    let (path, config) = parse_file_path(file);
+    orchestra::show_top(&names, &labels, path.as_deref(), config.as_deref()).await?;
 
     Ok(())
 }
@@ -319,24 +430,11 @@ pub async fn log_subcommand(
     file: Option<PathBuf>,
     follow: bool,
     tail: Option<usize>,
+    console: bool,
 ) -> MicrosandboxCliResult<()> {
     validate_build_sandbox_conflict(build, sandbox, "log", Some("[NAME]"), None);
     unsupported_build_error(build, "log", Some("[NAME]"));
 
-    // Check if tail command exists when follow mode is requested
-    if follow {
-        let tail_exists = which::which("tail").is_ok();
-        if !tail_exists {
-            MicrosandboxArgs::command()
-                .override_usage(usage("log", Some("[NAME]"), None))
-                .error(
-                    ErrorKind::InvalidValue,
-                    "'tail' command not found. Please install it to use the follow (-f) option.",
-                )
-                .exit();
-        }
-    }
-
     let (project_dir, config_file) = parse_file_path(file);
     menv::show_log(
         project_dir.as_ref(),
@@ -344,21 +442,40 @@ pub async fn log_subcommand(
         &name,
         follow,
         tail,
+        console,
     )
     .await?;
 
     Ok(())
 }
 
-/// Handles the clean subcommand, which removes the .menv directory from a project
+/// Handles the clean subcommand, which removes the .menv directory from a project, or stops
+/// orphaned runtime processes when `orphans` is set
 pub async fn clean_subcommand(
     _sandbox: bool,
     name: Option<String>,
     user: bool,
     all: bool,
     file: Option<PathBuf>,
+    orphans: bool,
     force: bool,
 ) -> MicrosandboxCliResult<()> {
+    if orphans {
+        let reaped = runtime::reap_orphans(force).await?;
+        if reaped.is_empty() {
+            println!("No orphaned processes found");
+        }
+
+        for process in reaped {
+            println!(
+                "Stopped orphaned {:?} process {}",
+                process.kind, process.pid
+            );
+        }
+
+        return Ok(());
+    }
+
     if user || all {
         // User-level cleanup - clean the microsandbox home directory
         home::clean(force).await?;
@@ -390,16 +507,30 @@ pub async fn clean_subcommand(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn server_start_subcommand(
     host: Option<String>,
     port: Option<u16>,
     project_dir: Option<PathBuf>,
     dev_mode: bool,
+    dev_key: bool,
     key: Option<String>,
     detach: bool,
     reset_key: bool,
+    stop_sandboxes_on_exit: bool,
 ) -> MicrosandboxCliResult<()> {
-    microsandbox_server::start(key, host, port, project_dir, dev_mode, detach, reset_key).await?;
+    microsandbox_server::start(
+        key,
+        host,
+        port,
+        project_dir,
+        dev_mode,
+        dev_key,
+        detach,
+        reset_key,
+        stop_sandboxes_on_exit,
+    )
+    .await?;
     Ok(())
 }
 
@@ -408,7 +539,11 @@ pub async fn server_stop_subcommand() -> MicrosandboxServerResult<()> {
     Ok(())
 }
 
-pub async fn server_keygen_subcommand(expire: Option<String>) -> MicrosandboxCliResult<()> {
+pub async fn server_keygen_subcommand(
+    expire: Option<String>,
+    dev: bool,
+    project_dir: Option<PathBuf>,
+) -> MicrosandboxCliResult<()> {
     // Convert the string duration to chrono::Duration
     let duration = if let Some(expire_str) = expire {
         Some(parse_duration_string(&expire_str)?)
@@ -416,7 +551,7 @@ pub async fn server_keygen_subcommand(expire: Option<String>) -> MicrosandboxCli
         None
     };
 
-    microsandbox_server::keygen(duration).await?;
+    microsandbox_server::keygen(duration, dev, project_dir).await?;
 
     Ok(())
 }
@@ -432,6 +567,156 @@ pub async fn server_ssh_subcommand(_sandbox: bool, _name: String) -> Microsandbo
         .exit();
 }
 
+/// Handle the config validate subcommand, which reports every problem in the configuration file
+pub async fn config_validate_subcommand(
+    project_dir: Option<PathBuf>,
+    config_file: Option<String>,
+) -> MicrosandboxCliResult<()> {
+    let (_, _, config_path) =
+        config::resolve_config_paths(project_dir.as_deref(), config_file.as_deref()).await?;
+    let contents = tokio::fs::read_to_string(&config_path).await.map_err(|e| {
+        MicrosandboxCliError::ConfigError(format!("{}: {}", config_path.display(), e))
+    })?;
+
+    let diagnostics = microsandbox_core::config::validate(&contents);
+    if diagnostics.is_empty() {
+        println!("{} {}", "✓".valid(), config_path.display());
+        return Ok(());
+    }
+
+    for diagnostic in &diagnostics {
+        eprintln!("{} {}", "error:".error(), diagnostic);
+    }
+
+    Err(MicrosandboxCliError::ConfigError(format!(
+        "found {} problem(s) in {}",
+        diagnostics.len(),
+        config_path.display()
+    )))
+}
+
+/// Handle the config schema subcommand, which prints the JSON Schema of the configuration file
+pub fn config_schema_subcommand() -> MicrosandboxCliResult<()> {
+    let schema = microsandbox_core::config::json_schema();
+    let schema = serde_json::to_string_pretty(&schema)
+        .map_err(|e| MicrosandboxCliError::ConfigError(e.to_string()))?;
+    println!("{}", schema);
+    Ok(())
+}
+
+/// Handle the cp subcommand, which copies files between the host and a running sandbox
+pub async fn cp_subcommand(
+    source: String,
+    destination: String,
+    server: Option<String>,
+) -> MicrosandboxCliResult<()> {
+    match (
+        source.parse::<CopyLocation>()?,
+        destination.parse::<CopyLocation>()?,
+    ) {
+        (CopyLocation::Host(source), CopyLocation::Sandbox { name, path }) => {
+            let client = SandboxClient::new(server, name);
+            cp::copy_to_sandbox(&client, &source, &path).await
+        }
+        (CopyLocation::Sandbox { name, path }, CopyLocation::Host(destination)) => {
+            let client = SandboxClient::new(server, name);
+            cp::copy_from_sandbox(&client, &path, &destination).await
+        }
+        _ => MicrosandboxArgs::command()
+            .override_usage(usage("cp", Some("<SOURCE> <DESTINATION>"), None))
+            .error(
+                ErrorKind::InvalidValue,
+                "exactly one of the paths must be a sandbox path: sandbox:<name>:<path>",
+            )
+            .exit(),
+    }
+}
+
+/// Handle the exec subcommand, which runs a command interactively in a running sandbox
+pub async fn exec_subcommand(
+    name: String,
+    command: String,
+    args: Vec<String>,
+    server: Option<String>,
+) -> MicrosandboxCliResult<()> {
+    let client = SandboxClient::new(server, name);
+    let exit_code = exec::exec_interactive(&client, command, args).await?;
+
+    // Exit with the command's code, without waiting on the blocked stdin reader
+    std::process::exit(exit_code);
+}
+
+/// Handle the resize subcommand, which changes the memory of a running sandbox
+pub async fn resize_subcommand(
+    name: String,
+    memory: u32,
+    file: Option<PathBuf>,
+) -> MicrosandboxCliResult<()> {
+    let (path, config) = parse_file_path(file);
+    orchestra::resize(&name, memory, path.as_deref(), config.as_deref()).await?;
+    println!("Sandbox {} resized to {} MiB", name, memory);
+    Ok(())
+}
+
+/// Handle the snapshot subcommand, which saves a sandbox's read-write layer
+pub async fn snapshot_subcommand(
+    name: String,
+    snapshot: String,
+    file: Option<PathBuf>,
+) -> MicrosandboxCliResult<()> {
+    let (path, config) = parse_file_path(file);
+    let snapshot_path =
+        sandbox::snapshot(&name, &snapshot, path.as_deref(), config.as_deref()).await?;
+    println!(
+        "Snapshot {} of sandbox {} saved to {}",
+        snapshot,
+        name,
+        snapshot_path.display()
+    );
+    Ok(())
+}
+
+/// Handle the restore subcommand, which replaces a sandbox's read-write layer with a snapshot
+pub async fn restore_subcommand(
+    name: String,
+    snapshot: String,
+    file: Option<PathBuf>,
+) -> MicrosandboxCliResult<()> {
+    let (path, config) = parse_file_path(file);
+    sandbox::restore(&name, &snapshot, path.as_deref(), config.as_deref()).await?;
+    println!("Sandbox {} restored from snapshot {}", name, snapshot);
+    Ok(())
+}
+
+/// Handle the doctor subcommand, which checks that this host can run sandboxes
+///
+/// Exits with 1 if any check fails.
+pub async fn doctor_subcommand() {
+    let report = doctor::run().await;
+    for check in report.get_checks() {
+        let label = match check.get_status() {
+            CheckStatus::Pass => "pass:".valid(),
+            CheckStatus::Warn => "warn:".header(),
+            CheckStatus::Fail => "fail:".error(),
+        };
+        println!("{} {}: {}", label, check.get_name(), check.get_message());
+        if let Some(remediation) = check.get_remediation() {
+            println!("      {}", remediation);
+        }
+    }
+
+    println!(
+        "\n{} passed, {} warnings, {} failed",
+        report.count(CheckStatus::Pass),
+        report.count(CheckStatus::Warn),
+        report.count(CheckStatus::Fail)
+    );
+
+    if report.status() == CheckStatus::Fail {
+        std::process::exit(1);
+    }
+}
+
 /// Handle the self subcommand, which manages microsandbox itself
 pub async fn self_subcommand(action: SelfAction) -> MicrosandboxCliResult<()> {
     match action {
@@ -452,6 +737,52 @@ pub async fn self_subcommand(action: SelfAction) -> MicrosandboxCliResult<()> {
             // Then uninstall the binaries and libraries
             toolchain::uninstall().await?;
         }
+        SelfAction::List => {
+            let aliases = toolchain::list().await?;
+            if aliases.is_empty() {
+                println!("No installed aliases found");
+                return Ok(());
+            }
+
+            let width = aliases
+                .iter()
+                .map(|alias| alias.get_name().len())
+                .max()
+                .unwrap_or_default()
+                .max("ALIAS".len());
+            println!("{:<width$}  {:<10}  IMAGE", "ALIAS", "SCRIPT");
+            for alias in aliases {
+                let image = match alias.get_image() {
+                    Some(image) => image.to_string(),
+                    None => "(not installed)".to_string(),
+                };
+                println!(
+                    "{:<width$}  {:<10}  {}",
+                    alias.get_name(),
+                    alias.get_script().as_deref().unwrap_or("-"),
+                    image
+                );
+            }
+        }
+        SelfAction::Doctor => {
+            let aliases = toolchain::list().await?;
+            let broken = toolchain::doctor().await?;
+            if broken.is_empty() {
+                println!("All {} installed aliases work", aliases.len());
+                return Ok(());
+            }
+
+            for (alias, problem) in &broken {
+                println!("{} {}: {}", "broken:".error(), alias.get_name(), problem);
+            }
+            println!(
+                "\n{} of {} installed aliases are broken. Reinstall them with `{}` or remove them with `{}`.",
+                broken.len(),
+                aliases.len(),
+                "msb install".literal(),
+                "msb uninstall <ALIAS>".literal()
+            );
+        }
     }
 
     Ok(())
@@ -558,7 +889,7 @@ pub async fn server_log_subcommand(
     }
 
     // Reuse the same log viewing functionality
-    menv::show_log(Some(project_path), None, &name, follow, tail).await?;
+    menv::show_log(Some(project_path), None, &name, follow, tail, false).await?;
 
     Ok(())
 }
@@ -579,7 +910,7 @@ pub async fn server_list_subcommand() -> MicrosandboxCliResult<()> {
     match config_result {
         Ok((config, _, _)) => {
             // Use the common show_list function to display sandboxes
-            menv::show_list(config.get_sandboxes());
+            menv::show_list(config.get_sandboxes(), ListFormat::Table)?;
         }
         Err(err) => {
             return Err(MicrosandboxCliError::ConfigError(format!(
@@ -606,12 +937,99 @@ pub async fn server_status_subcommand(
         ));
     }
 
-    orchestra::show_status(&names, Some(project_path.as_path()), None).await?;
+    orchestra::show_status(&names, &[], Some(project_path.as_path()), None).await?;
+
+    Ok(())
+}
+
+pub async fn pull_subcommand(
+    name: Reference,
+    layer_path: Option<PathBuf>,
+    no_cache: bool,
+    keep_download: bool,
+    output: PullOutput,
+    timing: Option<PullTimingLayer>,
+) -> MicrosandboxCliResult<()> {
+    let summary = Image::pull(name, layer_path, no_cache, keep_download).await?;
+
+    if let Some(timing) = timing {
+        eprint!("{}", timing.summary());
+    }
+
+    if output == PullOutput::Json {
+        let summary = serde_json::to_string_pretty(&summary).map_err(MicrosandboxError::from)?;
+        println!("{}", summary);
+    }
+
+    Ok(())
+}
+
+/// Handle the prewarm subcommand, which pulls the images of a project's sandboxes ahead of time
+pub async fn prewarm_subcommand(
+    file: Option<PathBuf>,
+    max_concurrent_pulls: Option<usize>,
+) -> MicrosandboxCliResult<()> {
+    let (path, config) = parse_file_path(file);
+    let summaries =
+        orchestra::prewarm(path.as_deref(), config.as_deref(), max_concurrent_pulls).await?;
+
+    for summary in summaries {
+        if summary.is_cached() {
+            println!("{} (already cached)", summary.get_reference());
+        } else {
+            println!(
+                "{} (pulled {} layers, {} bytes)",
+                summary.get_reference(),
+                summary.get_layers_pulled(),
+                summary.get_bytes_downloaded()
+            );
+        }
+    }
 
     Ok(())
 }
 
-pub async fn login_subcommand() -> MicrosandboxCliResult<()> {
+pub async fn login_subcommand(
+    list: bool,
+    write_docker_config: bool,
+    registry: Option<String>,
+) -> MicrosandboxCliResult<()> {
+    let store = FileCredentialStore::from_env();
+    if list {
+        let registries = store.list_registries().await?;
+        if registries.is_empty() {
+            println!("Not logged into any registry");
+        }
+
+        for registry in registries {
+            println!("{}", registry);
+        }
+
+        return Ok(());
+    }
+
+    if write_docker_config {
+        let registries = match registry {
+            Some(registry) => vec![registry],
+            None => store.list_registries().await?,
+        };
+
+        for registry in registries {
+            let auth = store.get(&registry).await?.ok_or_else(|| {
+                MicrosandboxCliError::NotFound(format!("no saved credentials for {}", registry))
+            })?;
+
+            let config_path = docker_config::export_credentials(&registry, &auth).await?;
+            println!(
+                "Wrote credentials for {} to {}",
+                registry,
+                config_path.display()
+            );
+        }
+
+        return Ok(());
+    }
+
     println!(
         "{} login functionality is not yet implemented",
         "error:".error()
@@ -619,6 +1037,18 @@ pub async fn login_subcommand() -> MicrosandboxCliResult<()> {
     Ok(())
 }
 
+/// Handle the image export subcommand, which writes a pulled image out as an OCI image layout or
+/// a `docker save` tarball
+pub async fn image_export_subcommand(
+    name: Reference,
+    dest: PathBuf,
+    format: ExportFormat,
+) -> MicrosandboxCliResult<()> {
+    image::export(&name, &dest, format).await?;
+    println!("Image {} exported to {}", name, dest.display());
+    Ok(())
+}
+
 pub async fn push_subcommand(_image: bool, _name: String) -> MicrosandboxCliResult<()> {
     println!(
         "{} push functionality is not yet implemented",
@@ -3,10 +3,12 @@ mod msb;
 
 use clap::{CommandFactory, Parser};
 use microsandbox_cli::{
-    AnsiStyles, MicrosandboxArgs, MicrosandboxCliResult, MicrosandboxSubcommand, ServerSubcommand,
+    AnsiStyles, ConfigSubcommand, ImageSubcommand, MicrosandboxArgs, MicrosandboxCliResult,
+    MicrosandboxSubcommand, ServerSubcommand,
 };
-use microsandbox_core::{management::orchestra, oci::Image};
+use microsandbox_core::{management::orchestra, oci::PullTimingLayer};
 use msb::handlers;
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -21,10 +23,32 @@ const SHELL_SCRIPT: &str = "shell";
 #[tokio::main]
 async fn main() -> MicrosandboxCliResult<()> {
     // Parse command line arguments
-    let args = MicrosandboxArgs::parse();
+    let mut args = MicrosandboxArgs::parse();
+
+    // Image references are qualified with the default registry as they are parsed, so parse again
+    // once `--registry` has set it
+    if args.registry.is_some() {
+        handlers::registry(&args);
+        args = MicrosandboxArgs::parse();
+    }
 
     handlers::log_level(&args);
-    tracing_subscriber::fmt::init();
+
+    // Phase timings are collected only when they are printed
+    let pull_timing = match args.subcommand {
+        Some(MicrosandboxSubcommand::Pull { timing: true, .. }) => Some(PullTimingLayer::new()),
+        _ => None,
+    };
+
+    // Logs go to stderr, leaving stdout to command output such as `pull --output json`
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_filter(EnvFilter::from_default_env()),
+        )
+        .with(pull_timing.clone())
+        .init();
 
     // Print version if requested
     if args.version {
@@ -56,12 +80,32 @@ async fn main() -> MicrosandboxCliResult<()> {
             imports,
             exports,
             scope,
+            rootfs_mode,
             file,
         }) => {
             let (path, config) = handlers::parse_file_path(file);
             handlers::add_subcommand(
-                sandbox, build, names, image, memory, cpus, volumes, ports, envs, env_file,
-                depends_on, workdir, shell, scripts, start, imports, exports, scope, path, config,
+                sandbox,
+                build,
+                names,
+                image,
+                memory,
+                cpus,
+                volumes,
+                ports,
+                envs,
+                env_file,
+                depends_on,
+                workdir,
+                shell,
+                scripts,
+                start,
+                imports,
+                exports,
+                scope,
+                rootfs_mode,
+                path,
+                config,
             )
             .await?;
         }
@@ -77,11 +121,33 @@ async fn main() -> MicrosandboxCliResult<()> {
             sandbox,
             build,
             file,
+            format,
+        }) => {
+            handlers::list_subcommand(sandbox, build, file, format).await?;
+        }
+        Some(MicrosandboxSubcommand::Pull {
+            name,
+            layer_path,
+            no_cache,
+            keep_download,
+            output,
+            timing: _,
         }) => {
-            handlers::list_subcommand(sandbox, build, file).await?;
+            handlers::pull_subcommand(
+                name,
+                layer_path,
+                no_cache,
+                keep_download,
+                output,
+                pull_timing,
+            )
+            .await?;
         }
-        Some(MicrosandboxSubcommand::Pull { name, layer_path }) => {
-            Image::pull(name, layer_path).await?;
+        Some(MicrosandboxSubcommand::Prewarm {
+            file,
+            max_concurrent_pulls,
+        }) => {
+            handlers::prewarm_subcommand(file, max_concurrent_pulls).await?;
         }
         Some(MicrosandboxSubcommand::Run {
             sandbox,
@@ -90,9 +156,26 @@ async fn main() -> MicrosandboxCliResult<()> {
             file,
             detach,
             exec,
+            entrypoint,
+            console_log,
+            rootfs_mode,
+            network,
             args,
         }) => {
-            handlers::run_subcommand(sandbox, build, name, file, detach, exec, args).await?;
+            handlers::run_subcommand(
+                sandbox,
+                build,
+                name,
+                file,
+                detach,
+                exec,
+                entrypoint,
+                console_log,
+                rootfs_mode,
+                network,
+                args,
+            )
+            .await?;
         }
         Some(MicrosandboxSubcommand::Shell {
             sandbox,
@@ -124,10 +207,21 @@ async fn main() -> MicrosandboxCliResult<()> {
             workdir,
             scope,
             exec,
+            console_log,
             args,
         }) => {
             handlers::exe_subcommand(
-                name, cpus, memory, volumes, ports, envs, workdir, scope, exec, args,
+                name,
+                cpus,
+                memory,
+                volumes,
+                ports,
+                envs,
+                workdir,
+                scope,
+                exec,
+                console_log,
+                args,
             )
             .await?;
         }
@@ -153,34 +247,62 @@ async fn main() -> MicrosandboxCliResult<()> {
         Some(MicrosandboxSubcommand::Uninstall { script }) => {
             handlers::uninstall_subcommand(script).await?;
         }
-        Some(MicrosandboxSubcommand::Apply { file, detach }) => {
+        Some(MicrosandboxSubcommand::Apply { file, detach, yes }) => {
             let (path, config) = handlers::parse_file_path(file);
-            orchestra::apply(path.as_deref(), config.as_deref(), detach).await?;
+            orchestra::apply(path.as_deref(), config.as_deref(), detach, yes).await?;
         }
         Some(MicrosandboxSubcommand::Up {
             sandbox,
             build,
             names,
+            labels,
             file,
             detach,
+            max_concurrent_sandboxes,
         }) => {
-            handlers::up_subcommand(sandbox, build, names, file, detach).await?;
+            handlers::up_subcommand(
+                sandbox,
+                build,
+                names,
+                labels,
+                file,
+                detach,
+                max_concurrent_sandboxes,
+            )
+            .await?;
         }
         Some(MicrosandboxSubcommand::Down {
             sandbox,
             build,
             names,
+            labels,
             file,
         }) => {
-            handlers::down_subcommand(sandbox, build, names, file).await?;
+            handlers::down_subcommand(sandbox, build, names, labels, file).await?;
         }
         Some(MicrosandboxSubcommand::Status {
             sandbox,
             build,
             names,
+            labels,
+            file,
+        }) => {
+            handlers::status_subcommand(sandbox, build, names, labels, file).await?;
+        }
+        Some(MicrosandboxSubcommand::Healthcheck { name, file }) => {
+            handlers::healthcheck_subcommand(name, file).await;
+        }
+        Some(MicrosandboxSubcommand::Inspect { name, file, output }) => {
+            handlers::inspect_subcommand(name, file, output).await?;
+        }
+        Some(MicrosandboxSubcommand::Top {
+            sandbox,
+            build,
+            names,
+            labels,
             file,
         }) => {
-            handlers::status_subcommand(sandbox, build, names, file).await?;
+            handlers::top_subcommand(sandbox, build, names, labels, file).await?;
         }
         Some(MicrosandboxSubcommand::Log {
             sandbox,
@@ -189,8 +311,9 @@ async fn main() -> MicrosandboxCliResult<()> {
             file,
             follow,
             tail,
+            console,
         }) => {
-            handlers::log_subcommand(sandbox, build, name, file, follow, tail).await?;
+            handlers::log_subcommand(sandbox, build, name, file, follow, tail, console).await?;
         }
         Some(MicrosandboxSubcommand::Clean {
             sandbox,
@@ -198,9 +321,13 @@ async fn main() -> MicrosandboxCliResult<()> {
             user,
             all,
             file,
+            orphans,
             force,
         }) => {
-            handlers::clean_subcommand(sandbox, name, user, all, file, force).await?;
+            handlers::clean_subcommand(sandbox, name, user, all, file, orphans, force).await?;
+        }
+        Some(MicrosandboxSubcommand::Doctor) => {
+            handlers::doctor_subcommand().await;
         }
         Some(MicrosandboxSubcommand::Self_ { action }) => {
             handlers::self_subcommand(action).await?;
@@ -211,26 +338,34 @@ async fn main() -> MicrosandboxCliResult<()> {
                 port,
                 project_dir,
                 dev_mode,
+                dev_key,
                 key,
                 detach,
                 reset_key,
+                stop_sandboxes_on_exit,
             } => {
                 handlers::server_start_subcommand(
                     host,
                     port,
                     project_dir,
                     dev_mode,
+                    dev_key,
                     key,
                     detach,
                     reset_key,
+                    stop_sandboxes_on_exit,
                 )
                 .await?;
             }
             ServerSubcommand::Stop => {
                 handlers::server_stop_subcommand().await?;
             }
-            ServerSubcommand::Keygen { expire } => {
-                handlers::server_keygen_subcommand(expire).await?;
+            ServerSubcommand::Keygen {
+                expire,
+                dev,
+                project_dir,
+            } => {
+                handlers::server_keygen_subcommand(expire, dev, project_dir).await?;
             }
             ServerSubcommand::Log {
                 sandbox,
@@ -250,12 +385,62 @@ async fn main() -> MicrosandboxCliResult<()> {
                 handlers::server_ssh_subcommand(sandbox, name).await?;
             }
         },
-        Some(MicrosandboxSubcommand::Login) => {
-            handlers::login_subcommand().await?;
+        Some(MicrosandboxSubcommand::Config { subcommand }) => match subcommand {
+            ConfigSubcommand::Validate { file } => {
+                let (path, config) = handlers::parse_file_path(file);
+                handlers::config_validate_subcommand(path, config).await?;
+            }
+            ConfigSubcommand::Schema => {
+                handlers::config_schema_subcommand()?;
+            }
+        },
+        Some(MicrosandboxSubcommand::Login {
+            list,
+            write_docker_config,
+            registry,
+        }) => {
+            handlers::login_subcommand(list, write_docker_config, registry).await?;
         }
         Some(MicrosandboxSubcommand::Push { image, name }) => {
             handlers::push_subcommand(image, name).await?;
         }
+        Some(MicrosandboxSubcommand::Image { subcommand }) => match subcommand {
+            ImageSubcommand::Export { name, dest, format } => {
+                handlers::image_export_subcommand(name, dest, format).await?;
+            }
+        },
+        Some(MicrosandboxSubcommand::Cp {
+            source,
+            destination,
+            server,
+        }) => {
+            handlers::cp_subcommand(source, destination, server).await?;
+        }
+        Some(MicrosandboxSubcommand::Exec {
+            name,
+            command,
+            args,
+            server,
+        }) => {
+            handlers::exec_subcommand(name, command, args, server).await?;
+        }
+        Some(MicrosandboxSubcommand::Resize { name, memory, file }) => {
+            handlers::resize_subcommand(name, memory, file).await?;
+        }
+        Some(MicrosandboxSubcommand::Snapshot {
+            name,
+            snapshot,
+            file,
+        }) => {
+            handlers::snapshot_subcommand(name, snapshot, file).await?;
+        }
+        Some(MicrosandboxSubcommand::Restore {
+            name,
+            snapshot,
+            file,
+        }) => {
+            handlers::restore_subcommand(name, snapshot, file).await?;
+        }
         Some(_) => (), // TODO: implement other subcommands
         None => {
             MicrosandboxArgs::command().print_help()?;
@@ -2,4 +2,7 @@
 // Exports
 //--------------------------------------------------------------------------------------------------
 
+pub mod cp;
+pub mod exec;
 pub mod handlers;
+pub mod rpc;
@@ -0,0 +1,104 @@
+//! JSON-RPC client for the methods a sandbox exposes through the sandbox server.
+//!
+//! The server forwards the `sandbox.*` methods to the portal running inside the named sandbox, so
+//! the CLI can drive a sandbox without talking to the portal directly.
+
+use microsandbox_cli::{MicrosandboxCliError, MicrosandboxCliResult};
+use microsandbox_utils::{
+    DEFAULT_SERVER_HOST, DEFAULT_SERVER_PORT, MSB_API_KEY_ENV_VAR, MSB_SERVER_URL_ENV_VAR,
+    PORTAL_NOT_FOUND_ERROR_CODE,
+};
+use serde_json::{Value, json};
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Client for the methods of a sandbox, called through the server
+#[derive(Clone)]
+pub struct SandboxClient {
+    /// The HTTP client
+    client: reqwest::Client,
+
+    /// The server's JSON-RPC endpoint
+    rpc_url: String,
+
+    /// The API key to authenticate with, if any
+    api_key: Option<String>,
+
+    /// The name of the sandbox
+    sandbox: String,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl SandboxClient {
+    /// Create a new client for a sandbox
+    ///
+    /// The server URL falls back to `MSB_SERVER_URL`, then to the local server. The API key is
+    /// read from `MSB_API_KEY`.
+    pub fn new(server_url: Option<String>, sandbox: String) -> Self {
+        let server_url = server_url
+            .or_else(|| std::env::var(MSB_SERVER_URL_ENV_VAR).ok())
+            .unwrap_or_else(|| format!("http://{}:{}", DEFAULT_SERVER_HOST, DEFAULT_SERVER_PORT));
+
+        Self {
+            client: reqwest::Client::new(),
+            rpc_url: format!("{}/api/v1/rpc", server_url.trim_end_matches('/')),
+            api_key: std::env::var(MSB_API_KEY_ENV_VAR).ok(),
+            sandbox,
+        }
+    }
+
+    /// Get the name of the sandbox
+    pub fn sandbox(&self) -> &str {
+        &self.sandbox
+    }
+
+    /// Call a method on the sandbox
+    ///
+    /// A path that doesn't exist in the sandbox is reported as [`MicrosandboxCliError::NotFound`].
+    pub async fn call(&self, method: &str, mut params: Value) -> MicrosandboxCliResult<Value> {
+        params["sandbox"] = json!(self.sandbox);
+        let request = json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+            "id": 1,
+        });
+
+        let mut builder = self.client.post(&self.rpc_url).json(&request);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+
+        let response: Value = builder
+            .send()
+            .await
+            .map_err(|e| MicrosandboxCliError::ServerRequest(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| MicrosandboxCliError::ServerRequest(e.to_string()))?;
+
+        if let Some(error) = response.get("error") {
+            // JSON-RPC errors carry a message, while server errors are a plain string
+            let message = error
+                .get("message")
+                .and_then(Value::as_str)
+                .or_else(|| error.as_str())
+                .map(str::to_string)
+                .unwrap_or_else(|| error.to_string());
+            if error.get("code").and_then(Value::as_i64)
+                == Some(i64::from(PORTAL_NOT_FOUND_ERROR_CODE))
+            {
+                return Err(MicrosandboxCliError::NotFound(message));
+            }
+
+            return Err(MicrosandboxCliError::ServerRequest(message));
+        }
+
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+}
@@ -20,9 +20,11 @@
 //!     --num-vcpus=2 \
 //!     --memory-mib=1024 \
 //!     --workdir-path=/app \
+//!     --console-log-path=/path/to/console.log \
 //!     --exec-path=/usr/bin/python3 \
 //!     --mapped-dirs=/host/path:/guest/path \
 //!     --port-maps=8080:80 \
+//!     --network=host \
 //!     --scope=public \
 //!     --ip=192.168.1.1 \
 //!     --subnet=192.168.1.0/24 \
@@ -44,16 +46,23 @@
 //!     --num-vcpus=2 \
 //!     --memory-mib=1024 \
 //!     --workdir-path=/app \
+//!     --console-log-path=/path/to/console.log \
 //!     --exec-path=/usr/bin/python3 \
 //!     --mapped-dirs=/host/path:/guest/path \
 //!     --port-maps=8080:80 \
 //!     --envs=KEY=VALUE \
 //!     --forward-output \
+//!     --network=host \
 //!     --scope=public \
 //!     --ip=192.168.1.1 \
 //!     --subnet=192.168.1.0/24 \
 //!     -- -m http.server 8080
 //! ```
+//!
+//! In both modes, environment variables can also be given in `MSBRUN_ENVS` as a JSON array of
+//! `KEY=VALUE` strings. Sandboxes are started this way, and the supervisor passes them on to its
+//! child the same way, so that their values (which may be resolved secrets) never appear on a
+//! command line. Only the names of environment variables are logged.
 
 use std::env;
 
@@ -65,7 +74,7 @@ use microsandbox_core::{
     runtime::MicroVmMonitor,
     vm::{MicroVm, Rootfs},
 };
-use microsandbox_utils::runtime::Supervisor;
+use microsandbox_utils::{MSBRUN_ENVS_ENV_VAR, runtime::Supervisor};
 
 //--------------------------------------------------------------------------------------------------
 // Functions: main
@@ -84,16 +93,20 @@ async fn main() -> Result<()> {
             num_vcpus,
             memory_mib,
             workdir_path,
+            console_log_path,
             exec_path,
             env,
+            kernel_arg,
             mapped_dir,
             port_map,
+            network,
             scope,
             ip,
             subnet,
             args,
         } => {
             tracing_subscriber::fmt::init();
+            let env = collect_envs(env)?;
 
             tracing::debug!("log_level: {:#?}", log_level);
             tracing::debug!("native_rootfs: {:#?}", native_rootfs);
@@ -101,10 +114,13 @@ async fn main() -> Result<()> {
             tracing::debug!("num_vcpus: {:#?}", num_vcpus);
             tracing::debug!("memory_mib: {:#?}", memory_mib);
             tracing::debug!("workdir_path: {:#?}", workdir_path);
+            tracing::debug!("console_log_path: {:#?}", console_log_path);
             tracing::debug!("exec_path: {:#?}", exec_path);
-            tracing::debug!("env: {:#?}", env);
+            tracing::debug!("env: {:#?}", env_names(&env));
+            tracing::debug!("kernel_arg: {:#?}", kernel_arg);
             tracing::debug!("mapped_dir: {:#?}", mapped_dir);
             tracing::debug!("port_map: {:#?}", port_map);
+            tracing::debug!("network: {:#?}", network);
             tracing::debug!("scope: {:#?}", scope);
             tracing::debug!("ip: {:#?}", ip);
             tracing::debug!("subnet: {:#?}", subnet);
@@ -162,6 +178,11 @@ async fn main() -> Result<()> {
                 builder = builder.workdir_path(workdir_path);
             }
 
+            // Capture the guest console output if requested
+            if let Some(console_log_path) = console_log_path {
+                builder = builder.console_output(console_log_path.to_string_lossy().to_string());
+            }
+
             // Set mapped dirs if provided
             if !mapped_dir.is_empty() {
                 builder = builder.mapped_dirs(mapped_dir);
@@ -172,6 +193,11 @@ async fn main() -> Result<()> {
                 builder = builder.port_map(port_map);
             }
 
+            // Set network mode if provided
+            if let Some(network) = network {
+                builder = builder.network(network.parse()?);
+            }
+
             // Set scope if provided
             if let Some(scope) = scope {
                 builder = builder.scope(scope.parse()?);
@@ -192,6 +218,11 @@ async fn main() -> Result<()> {
                 builder = builder.env(env);
             }
 
+            // Set kernel args if provided
+            if !kernel_arg.is_empty() {
+                builder = builder.kernel_args(kernel_arg.iter().map(|s| s.as_str()));
+            }
+
             // Set args if provided
             if !args.is_empty() {
                 builder = builder.args(args.iter().map(|s| s.as_str()));
@@ -216,10 +247,13 @@ async fn main() -> Result<()> {
             num_vcpus,
             memory_mib,
             workdir_path,
+            console_log_path,
             exec_path,
             env,
+            kernel_arg,
             mapped_dir,
             port_map,
+            network,
             scope,
             ip,
             subnet,
@@ -227,6 +261,7 @@ async fn main() -> Result<()> {
         } => {
             tracing_subscriber::fmt::init();
             tracing::info!("setting up supervisor");
+            let env = collect_envs(env)?;
 
             // Get current executable path
             let child_exe = env::current_exe()?;
@@ -277,6 +312,11 @@ async fn main() -> Result<()> {
                 child_args.push(format!("--workdir-path={}", workdir_path));
             }
 
+            // Set console log path if provided
+            if let Some(console_log_path) = console_log_path {
+                child_args.push(format!("--console-log-path={}", console_log_path.display()));
+            }
+
             // Set native rootfs if provided
             if let Some(native_rootfs) = native_rootfs {
                 child_args.push(format!("--native-rootfs={}", native_rootfs.display()));
@@ -289,11 +329,9 @@ async fn main() -> Result<()> {
                 }
             }
 
-            // Set env if provided
-            if !env.is_empty() {
-                for env in env {
-                    child_args.push(format!("--env={}", env));
-                }
+            // Set kernel args if provided
+            for kernel_arg in kernel_arg {
+                child_args.push(format!("--kernel-arg={}", kernel_arg));
             }
 
             // Set mapped dirs if provided
@@ -310,6 +348,11 @@ async fn main() -> Result<()> {
                 }
             }
 
+            // Set network mode if provided
+            if let Some(network) = network {
+                child_args.push(format!("--network={}", network));
+            }
+
             // Set scope if provided
             if let Some(scope) = scope {
                 child_args.push(format!("--scope={}", scope));
@@ -341,6 +384,15 @@ async fn main() -> Result<()> {
             // Compose child environment variables
             let mut child_envs = Vec::<(String, String)>::new();
 
+            // Pass the sandbox's environment variables in the child's environment, keeping their
+            // values off its command line
+            if !env.is_empty() {
+                child_envs.push((
+                    MSBRUN_ENVS_ENV_VAR.to_string(),
+                    serde_json::to_string(&env)?,
+                ));
+            }
+
             // Only pass RUST_LOG if it's set in the environment
             if let Ok(rust_log) = std::env::var("RUST_LOG") {
                 tracing::debug!("using existing RUST_LOG: {:?}", rust_log);
@@ -359,3 +411,24 @@ async fn main() -> Result<()> {
     // Otherwise, the process will not exit by itself and will wait for enter key to be pressed.
     std::process::exit(0);
 }
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Returns the environment variables given with `--env` followed by those given in
+/// `MSBRUN_ENVS`.
+fn collect_envs(mut env: Vec<String>) -> Result<Vec<String>> {
+    if let Ok(encoded) = env::var(MSBRUN_ENVS_ENV_VAR) {
+        env.extend(serde_json::from_str::<Vec<String>>(&encoded)?);
+    }
+
+    Ok(env)
+}
+
+/// Returns the names of environment variables, so they can be logged without their values.
+fn env_names(env: &[String]) -> Vec<&str> {
+    env.iter()
+        .map(|env| env.split_once('=').map_or(env.as_str(), |(name, _)| name))
+        .collect()
+}
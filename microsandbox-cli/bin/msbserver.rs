@@ -1,15 +1,13 @@
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::RwLock;
 
-use axum::http::{
-    Method,
-    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
-};
 use clap::Parser;
 use microsandbox_cli::{MicrosandboxCliResult, MsbserverArgs};
-use microsandbox_server::{Config, port::PortManager, route, state::AppState};
+use microsandbox_server::{
+    Config, CorsConfig, MetricsHistoryConfig, RateLimitConfig, idle, management, metrics,
+    port::PortManager, route, state::AppState,
+};
 use microsandbox_utils::CHECKMARK;
-use tower_http::cors::{Any, CorsLayer};
 
 //--------------------------------------------------------------------------------------------------
 // Functions: Main
@@ -39,6 +37,16 @@ pub async fn main() -> MicrosandboxCliResult<()> {
         args.port,
         args.project_dir.clone(),
         args.dev_mode,
+        args.stop_sandboxes_on_exit,
+        RateLimitConfig::new(
+            args.rate_limit_rps,
+            args.rate_limit_burst,
+            args.lifecycle_rate_limit_rps,
+            args.lifecycle_rate_limit_burst,
+        ),
+        CorsConfig::new(args.cors_origins, args.cors_methods, args.cors_headers),
+        MetricsHistoryConfig::new(args.metrics_history_interval, args.metrics_history_depth),
+        args.max_request_body,
     )?);
 
     // Get project directory from config
@@ -55,14 +63,14 @@ pub async fn main() -> MicrosandboxCliResult<()> {
     // Create application state
     let state = AppState::new(config.clone(), port_manager);
 
-    // Configure CORS
-    let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
-        .allow_headers([AUTHORIZATION, ACCEPT, CONTENT_TYPE])
-        .allow_origin(Any);
+    // Sample sandbox resource usage into the metrics history, if enabled
+    tokio::spawn(metrics::sample_metrics(state.clone()));
+
+    // Stop sandboxes that have gone past their idle timeout
+    tokio::spawn(idle::stop_idle_sandboxes(state.clone()));
 
     // Build application
-    let app = route::create_router(state).layer(cors);
+    let app = route::create_router(state.clone());
 
     // Start server
     tracing::info!("Starting server on {}", config.get_addr());
@@ -74,7 +82,18 @@ pub async fn main() -> MicrosandboxCliResult<()> {
 
     let listener = tokio::net::TcpListener::bind(config.get_addr()).await?;
 
-    axum::serve(listener, app).await?;
+    // Stop accepting new connections on shutdown and drain in-flight requests
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(management::shutdown_signal())
+    .await?;
+
+    if *config.get_stop_sandboxes_on_exit() {
+        tracing::info!("stopping sandboxes started by the server");
+        management::stop_managed_sandboxes(&state).await;
+    }
 
     Ok(())
 }
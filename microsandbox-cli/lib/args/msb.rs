@@ -2,9 +2,37 @@ use std::{error::Error, path::PathBuf};
 
 use crate::styles;
 use clap::Parser;
-use microsandbox_core::oci::Reference;
+use microsandbox_core::{
+    config::{LabelSelector, NetworkMode, RootfsMode},
+    management::{image::ExportFormat, menv::ListFormat},
+    oci::Reference,
+};
+use tracing::level_filters::LevelFilter;
 use typed_path::Utf8UnixPathBuf;
 
+//-------------------------------------------------------------------------------------------------
+// Constants
+//-------------------------------------------------------------------------------------------------
+
+/// Log levels from least to most verbose, stepped through by `--verbose` and `--quiet`
+const LOG_LEVELS: [LevelFilter; 6] = [
+    LevelFilter::OFF,
+    LevelFilter::ERROR,
+    LevelFilter::WARN,
+    LevelFilter::INFO,
+    LevelFilter::DEBUG,
+    LevelFilter::TRACE,
+];
+
+/// The log level used when no level flag is given, matching tracing's default
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::ERROR;
+
+/// The most verbose level logs of dependencies such as reqwest and sqlx are shown at
+const DEPENDENCY_MAX_LOG_LEVEL: LevelFilter = LevelFilter::WARN;
+
+/// The log targets of microsandbox itself
+const DEFAULT_LOG_TARGETS: [&str; 2] = ["microsandbox", "msb"];
+
 //-------------------------------------------------------------------------------------------------
 // Types
 //-------------------------------------------------------------------------------------------------
@@ -40,6 +68,22 @@ pub struct MicrosandboxArgs {
     /// Show logs with trace level
     #[arg(long, global = true)]
     pub trace: bool,
+
+    /// Show more logs, one level per occurrence
+    #[arg(long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Show fewer logs, one level per occurrence
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Comma-separated log targets the log level applies to, instead of microsandbox's own
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub log_targets: Vec<String>,
+
+    /// The registry images are pulled from when their reference doesn't name one
+    #[arg(long, global = true, value_name = "HOST")]
+    pub registry: Option<String>,
 }
 
 /// Available subcommands for managing services
@@ -88,13 +132,13 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long = "port", name = "PORT")]
         ports: Vec<String>,
 
-        /// Environment variables, format: <key>=<value>
+        /// Environment variables, format: <key>=<value>, or <key> to inherit it from the host
         #[arg(long = "env", name = "ENV")]
         envs: Vec<String>,
 
-        /// Environment file
-        #[arg(long)]
-        env_file: Option<Utf8UnixPathBuf>,
+        /// Environment files, loaded in order with later files overriding earlier ones
+        #[arg(long = "env-file", name = "ENV_FILE")]
+        env_file: Vec<Utf8UnixPathBuf>,
 
         /// Dependencies
         #[arg(long)]
@@ -128,6 +172,10 @@ pub enum MicrosandboxSubcommand {
         #[arg(long)]
         scope: Option<String>,
 
+        /// How the root filesystem is put together, options: auto, overlay, native
+        #[arg(long)]
+        rootfs_mode: Option<RootfsMode>,
+
         /// Path to the sandbox file or the project directory
         #[arg(short, long)]
         file: Option<PathBuf>,
@@ -167,6 +215,10 @@ pub enum MicrosandboxSubcommand {
         /// Path to the sandbox file or the project directory
         #[arg(short, long)]
         file: Option<PathBuf>,
+
+        /// Output format: table, json or yaml
+        #[arg(long, default_value = "table")]
+        format: ListFormat,
     },
 
     /// Show logs of a build or sandbox
@@ -188,13 +240,17 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long)]
         file: Option<PathBuf>,
 
-        /// Follow the logs
-        #[arg(short = 'F', long)]
+        /// Follow the logs, including across log rotations and sandbox restarts
+        #[arg(short = 'F', long, visible_alias = "watch")]
         follow: bool,
 
         /// Number of lines to show from the end
         #[arg(short, long)]
         tail: Option<usize>,
+
+        /// Show the captured guest console output instead of the logs
+        #[arg(long)]
+        console: bool,
     },
 
     /// Show tree of layers that make up a sandbox
@@ -244,7 +300,24 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long, short_alias = 'x')]
         exec: Option<String>,
 
-        /// Additional arguments after `--`. Passed to the script or exec.
+        /// Run this program in place of the sandbox's or image's entrypoint, with the arguments
+        /// after `--` as its command
+        #[arg(long, conflicts_with = "exec")]
+        entrypoint: Option<String>,
+
+        /// Capture the guest console output to a log file, for debugging boots
+        #[arg(long)]
+        console_log: bool,
+
+        /// Override the sandbox's rootfs mode, options: auto, overlay, native
+        #[arg(long)]
+        rootfs_mode: Option<RootfsMode>,
+
+        /// Override the sandbox's network mode, options: none, host, nat
+        #[arg(long)]
+        network: Option<NetworkMode>,
+
+        /// Additional arguments after `--`. Passed to the script, exec or entrypoint.
         #[arg(last = true)]
         args: Vec<String>,
     },
@@ -304,7 +377,7 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long = "port", name = "PORT")]
         ports: Vec<String>,
 
-        /// Environment variables, format: <key>=<value>
+        /// Environment variables, format: <key>=<value>, or <key> to inherit it from the host
         #[arg(long = "env", name = "ENV")]
         envs: Vec<String>,
 
@@ -320,6 +393,10 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long, short_alias = 'x')]
         exec: Option<String>,
 
+        /// Capture the guest console output and print it once the sandbox exits
+        #[arg(long)]
+        console_log: bool,
+
         /// Additional arguments after `--`. Passed to the script or exec.
         #[arg(last = true)]
         args: Vec<String>,
@@ -356,7 +433,7 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long = "port", name = "PORT")]
         ports: Vec<String>,
 
-        /// Environment variables, format: <key>=<value>
+        /// Environment variables, format: <key>=<value>, or <key> to inherit it from the host
         #[arg(long = "env", name = "ENV")]
         envs: Vec<String>,
 
@@ -394,6 +471,10 @@ pub enum MicrosandboxSubcommand {
         /// Run sandboxes in the background
         #[arg(short, long)]
         detach: bool,
+
+        /// Stop sandboxes no longer in the config without asking for confirmation
+        #[arg(short, long, visible_alias = "force")]
+        yes: bool,
     },
 
     /// Run a project's sandboxes
@@ -410,6 +491,10 @@ pub enum MicrosandboxSubcommand {
         /// Names of components to start. If omitted, starts all sandboxes defined in the configuration.
         names: Vec<String>,
 
+        /// Only include sandboxes with this label, given as `key=value`. Can be repeated.
+        #[arg(short, long = "label")]
+        labels: Vec<LabelSelector>,
+
         /// Path to the sandbox file or the project directory
         #[arg(short, long)]
         file: Option<PathBuf>,
@@ -417,6 +502,10 @@ pub enum MicrosandboxSubcommand {
         /// Run sandboxes in the background
         #[arg(short, long)]
         detach: bool,
+
+        /// Maximum number of sandboxes to start at once. Defaults to the number of CPUs.
+        #[arg(long)]
+        max_concurrent_sandboxes: Option<usize>,
     },
 
     /// Stop a project's sandboxes
@@ -433,6 +522,10 @@ pub enum MicrosandboxSubcommand {
         /// Names of components to stop. If omitted, stops all sandboxes defined in the configuration.
         names: Vec<String>,
 
+        /// Only include sandboxes with this label, given as `key=value`. Can be repeated.
+        #[arg(short, long = "label")]
+        labels: Vec<LabelSelector>,
+
         /// Path to the sandbox file or the project directory
         #[arg(short, long)]
         file: Option<PathBuf>,
@@ -453,6 +546,68 @@ pub enum MicrosandboxSubcommand {
         #[arg()]
         names: Vec<String>,
 
+        /// Only include sandboxes with this label, given as `key=value`. Can be repeated.
+        #[arg(short, long = "label")]
+        labels: Vec<LabelSelector>,
+
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Check whether a sandbox is running, for use as a liveness probe
+    ///
+    /// Prints nothing and exits with 0 if the sandbox is running, 1 if it is stopped, 2 if it is
+    /// not in the configuration, and 3 if the check itself failed.
+    #[command(name = "healthcheck")]
+    Healthcheck {
+        /// Name of the sandbox
+        #[arg(required = true)]
+        name: String,
+
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Show the fully-resolved configuration a sandbox would run with, without starting it
+    ///
+    /// Image defaults are merged in if the image has already been pulled, env files and inherited
+    /// environment variables are resolved, and defaults fill in anything left unset.
+    #[command(name = "inspect")]
+    Inspect {
+        /// Name of the sandbox
+        #[arg(required = true)]
+        name: String,
+
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = InspectOutput::Yaml)]
+        output: InspectOutput,
+    },
+
+    /// Show the combined resource usage of a project's sandboxes
+    #[command(name = "top")]
+    Top {
+        /// Whether command should apply to a sandbox
+        #[arg(short, long)]
+        sandbox: bool,
+
+        /// Whether command should apply to a build sandbox
+        #[arg(short, long)]
+        build: bool,
+
+        /// Names of components to include
+        #[arg()]
+        names: Vec<String>,
+
+        /// Only include sandboxes with this label, given as `key=value`. Can be repeated.
+        #[arg(short, long = "label")]
+        labels: Vec<LabelSelector>,
+
         /// Path to the sandbox file or the project directory
         #[arg(short, long)]
         file: Option<PathBuf>,
@@ -481,6 +636,11 @@ pub enum MicrosandboxSubcommand {
         #[arg(short, long)]
         file: Option<PathBuf>,
 
+        /// Stop supervisor and microVM processes that no longer have a running sandbox record.
+        /// With --force they are killed instead of asked to terminate
+        #[arg(long)]
+        orphans: bool,
+
         /// Force clean
         #[arg(short = 'F', long)]
         force: bool,
@@ -513,14 +673,54 @@ pub enum MicrosandboxSubcommand {
         #[arg(required = true)]
         name: Reference,
 
-        /// Path to store the layer files
-        #[arg(short = 'L', long)]
+        /// Path to store the layer files, along with a microsandbox-index.json describing the image
+        #[arg(short = 'L', long, visible_alias = "output-dir")]
         layer_path: Option<PathBuf>,
+
+        /// Download and extract every layer again, even if it is already cached
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Keep the temporary download directory for debugging instead of removing it
+        #[arg(long)]
+        keep_download: bool,
+
+        /// Output format: text, or json to print a summary of the pull to stdout
+        #[arg(long, value_enum, default_value_t = PullOutput::Text)]
+        output: PullOutput,
+
+        /// Print how long each phase of the pull took to stderr
+        #[arg(long)]
+        timing: bool,
+    },
+
+    /// Pull the images of a project's sandboxes ahead of starting them
+    #[command(name = "prewarm")]
+    Prewarm {
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+
+        /// Maximum number of images to pull at once. Defaults to the number of CPUs.
+        #[arg(long)]
+        max_concurrent_pulls: Option<usize>,
     },
 
     /// Login to a registry
     #[command(name = "login")]
-    Login,
+    Login {
+        /// List the registries with saved credentials instead of logging in
+        #[arg(long)]
+        list: bool,
+
+        /// Write the saved credentials into Docker's config.json, for tools that only read it
+        #[arg(long, conflicts_with = "list")]
+        write_docker_config: bool,
+
+        /// The registry to write credentials for. Defaults to every registry with saved credentials.
+        #[arg(requires = "write_docker_config")]
+        registry: Option<String>,
+    },
 
     /// Push image to a registry
     #[command(name = "push")]
@@ -534,6 +734,116 @@ pub enum MicrosandboxSubcommand {
         name: String,
     },
 
+    /// Work with pulled images
+    #[command(name = "image")]
+    Image {
+        /// The subcommand to run
+        #[command(subcommand)]
+        subcommand: ImageSubcommand,
+    },
+
+    /// Copy files between the host and a running sandbox
+    ///
+    /// Sandbox paths are written as `sandbox:<name>:<path>`, and the other side is a host path.
+    #[command(name = "cp")]
+    Cp {
+        /// Path to copy from
+        #[arg(required = true)]
+        source: String,
+
+        /// Path to copy to
+        #[arg(required = true)]
+        destination: String,
+
+        /// URL of the sandbox server. Defaults to `MSB_SERVER_URL` or the local server.
+        #[arg(long)]
+        server: Option<String>,
+    },
+
+    /// Run a command interactively in a running sandbox
+    #[command(name = "exec")]
+    Exec {
+        /// Name of the sandbox
+        #[arg(required = true)]
+        name: String,
+
+        /// Command to run
+        #[arg(required = true)]
+        command: String,
+
+        /// Additional arguments after `--`. Passed to the command.
+        #[arg(last = true)]
+        args: Vec<String>,
+
+        /// URL of the sandbox server. Defaults to `MSB_SERVER_URL` or the local server.
+        #[arg(long)]
+        server: Option<String>,
+    },
+
+    /// Change the memory of a running sandbox
+    #[command(name = "resize")]
+    Resize {
+        /// Name of the sandbox
+        #[arg(required = true)]
+        name: String,
+
+        /// Memory size to resize to, in MiB
+        #[arg(long, required = true)]
+        memory: u32,
+
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Save a sandbox's read-write layer as a named snapshot
+    #[command(name = "snapshot")]
+    Snapshot {
+        /// Name of the sandbox
+        #[arg(required = true)]
+        name: String,
+
+        /// Name to save the snapshot under
+        #[arg(required = true)]
+        snapshot: String,
+
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Replace a stopped sandbox's read-write layer with a snapshot
+    #[command(name = "restore")]
+    Restore {
+        /// Name of the sandbox
+        #[arg(required = true)]
+        name: String,
+
+        /// Name of the snapshot to restore
+        #[arg(required = true)]
+        snapshot: String,
+
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Work with the sandbox configuration file
+    #[command(name = "config")]
+    Config {
+        /// The subcommand to run
+        #[command(subcommand)]
+        subcommand: ConfigSubcommand,
+    },
+
+    /// Check that this host can run sandboxes
+    ///
+    /// Checks the virtualization backend, the microsandbox home directory, the default registry,
+    /// the helper programs and the free disk space, with a hint for each problem found. Exits with
+    /// 1 if any check fails.
+    #[command(name = "doctor")]
+    Doctor,
+
     /// Manage microsandbox itself
     #[command(name = "self")]
     Self_ {
@@ -576,6 +886,12 @@ pub enum ServerSubcommand {
         #[arg(long = "dev")]
         dev_mode: bool,
 
+        /// In development mode, require API keys signed with a stable key derived for the
+        /// project, see `msb server keygen --dev`. Without it, a development server accepts
+        /// unauthenticated requests.
+        #[arg(long, requires = "dev_mode")]
+        dev_key: bool,
+
         /// Set secret key for server. Automatically generated if not provided.
         #[arg(short, long)]
         key: Option<String>,
@@ -587,6 +903,10 @@ pub enum ServerSubcommand {
         /// Reset the server key
         #[arg(short, long)]
         reset_key: bool,
+
+        /// Stop the sandboxes started by the server when it shuts down
+        #[arg(long)]
+        stop_sandboxes_on_exit: bool,
     },
 
     /// Stop the sandbox server
@@ -598,6 +918,14 @@ pub enum ServerSubcommand {
         /// Token expiration duration. format: 1s, 2m, 3h, 4d, 5w, 6mo, 7y
         #[arg(long)]
         expire: Option<String>,
+
+        /// Generate the key for a server started with `--dev`
+        #[arg(long)]
+        dev: bool,
+
+        /// Project directory of the development server
+        #[arg(short = 'p', long = "path", requires = "dev")]
+        project_dir: Option<PathBuf>,
     },
 
     /// Show logs of a sandbox
@@ -649,6 +977,64 @@ pub enum ServerSubcommand {
     },
 }
 
+/// Subcommands for the config subcommand
+#[derive(Debug, Parser)]
+pub enum ConfigSubcommand {
+    /// Check the configuration file for errors without starting anything
+    #[command(name = "validate")]
+    Validate {
+        /// Path to the sandbox file or the project directory
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Print the JSON Schema of the configuration file
+    #[command(name = "schema", hide = true)]
+    Schema,
+}
+
+/// Subcommands for the image subcommand
+#[derive(Debug, Parser)]
+pub enum ImageSubcommand {
+    /// Export a pulled image to an OCI image layout or a `docker save` tarball
+    #[command(name = "export")]
+    Export {
+        /// Name of the image
+        #[arg(required = true)]
+        name: Reference,
+
+        /// Directory to write the OCI layout to, or path of the tarball to write
+        #[arg(required = true)]
+        dest: PathBuf,
+
+        /// Output format: oci or docker-archive
+        #[arg(long, default_value = "oci")]
+        format: ExportFormat,
+    },
+}
+
+/// Output formats of the pull subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum PullOutput {
+    /// Progress and logs only
+    #[default]
+    Text,
+
+    /// A JSON summary of the pull on stdout, with progress and logs on stderr
+    Json,
+}
+
+/// Output formats of the inspect subcommand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum InspectOutput {
+    /// YAML, in the shape of a sandbox entry in the configuration file
+    #[default]
+    Yaml,
+
+    /// JSON
+    Json,
+}
+
 /// Actions for the self subcommand
 #[derive(Debug, Clone, clap::ValueEnum)]
 pub enum SelfAction {
@@ -657,6 +1043,82 @@ pub enum SelfAction {
 
     /// Uninstall microsandbox
     Uninstall,
+
+    /// List the sandbox aliases installed with `msb install`
+    List,
+
+    /// Find installed sandbox aliases that no longer work
+    Doctor,
+}
+
+//-------------------------------------------------------------------------------------------------
+// Methods
+//-------------------------------------------------------------------------------------------------
+
+impl MicrosandboxArgs {
+    /// Returns the log level asked for on the command line, if any
+    ///
+    /// The level starts at the explicit level flag, e.g. `--debug`, or at the default level, and
+    /// is raised by one for each `--verbose` and lowered by one for each `--quiet`.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        let explicit = if self.trace {
+            Some(LevelFilter::TRACE)
+        } else if self.debug {
+            Some(LevelFilter::DEBUG)
+        } else if self.info {
+            Some(LevelFilter::INFO)
+        } else if self.warn {
+            Some(LevelFilter::WARN)
+        } else if self.error {
+            Some(LevelFilter::ERROR)
+        } else {
+            None
+        };
+
+        if explicit.is_none() && self.verbose == 0 && self.quiet == 0 && self.log_targets.is_empty()
+        {
+            return None;
+        }
+
+        let base = explicit.unwrap_or(DEFAULT_LOG_LEVEL);
+        let index = LOG_LEVELS
+            .iter()
+            .position(|level| *level == base)
+            .unwrap_or(0) as isize
+            + self.verbose as isize
+            - self.quiet as isize;
+
+        Some(LOG_LEVELS[index.clamp(0, LOG_LEVELS.len() as isize - 1) as usize])
+    }
+
+    /// Returns the `RUST_LOG` filter for the log level asked for on the command line, if any
+    ///
+    /// The level applies to the `--log-targets`, or to microsandbox's own targets by default.
+    /// Everything else, which is mostly dependencies like reqwest and sqlx, is kept at warnings
+    /// at most so it doesn't drown out microsandbox's own logs.
+    pub fn rust_log(&self) -> Option<String> {
+        let level = self.log_level()?;
+        if level == LevelFilter::OFF {
+            return Some("off".to_string());
+        }
+
+        let targets: Vec<&str> = if self.log_targets.is_empty() {
+            DEFAULT_LOG_TARGETS.to_vec()
+        } else {
+            self.log_targets.iter().map(String::as_str).collect()
+        };
+
+        let dependency_level = level.min(DEPENDENCY_MAX_LOG_LEVEL).to_string();
+        let level = level.to_string();
+        let mut directives = vec![dependency_level.to_lowercase()];
+        directives.extend(
+            targets
+                .iter()
+                .map(|target| format!("{}={}", target, level.to_lowercase())),
+        );
+
+        Some(directives.join(","))
+    }
 }
 
 //-------------------------------------------------------------------------------------------------
@@ -676,3 +1138,83 @@ where
 
     Ok((s[..pos].parse()?, s[pos + 1..].parse()?))
 }
+
+//-------------------------------------------------------------------------------------------------
+// Tests
+//-------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> MicrosandboxArgs {
+        MicrosandboxArgs::parse_from(std::iter::once("msb").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn test_log_level_from_verbosity_flags() {
+        assert_eq!(parse(&[]).log_level(), None);
+        assert_eq!(parse(&["--verbose"]).log_level(), Some(LevelFilter::WARN));
+        assert_eq!(
+            parse(&["--verbose", "--verbose"]).log_level(),
+            Some(LevelFilter::INFO)
+        );
+        assert_eq!(parse(&["-q"]).log_level(), Some(LevelFilter::OFF));
+        assert_eq!(parse(&["-qqq"]).log_level(), Some(LevelFilter::OFF));
+
+        // Explicit level flags keep working and are adjusted by the verbosity flags
+        assert_eq!(parse(&["--debug"]).log_level(), Some(LevelFilter::DEBUG));
+        assert_eq!(
+            parse(&["--debug", "--verbose", "--verbose"]).log_level(),
+            Some(LevelFilter::TRACE)
+        );
+        assert_eq!(
+            parse(&["--info", "-q"]).log_level(),
+            Some(LevelFilter::WARN)
+        );
+
+        // The flags are global, so they can follow the subcommand
+        assert_eq!(parse(&["list", "-qq"]).log_level(), Some(LevelFilter::OFF));
+    }
+
+    #[test]
+    fn test_rust_log_from_verbosity_flags() {
+        assert_eq!(parse(&[]).rust_log(), None);
+        assert_eq!(
+            parse(&["--verbose", "--verbose"]).rust_log().as_deref(),
+            Some("warn,microsandbox=info,msb=info")
+        );
+        assert_eq!(
+            parse(&["--trace"]).rust_log().as_deref(),
+            Some("warn,microsandbox=trace,msb=trace")
+        );
+        assert_eq!(
+            parse(&["--error"]).rust_log().as_deref(),
+            Some("error,microsandbox=error,msb=error")
+        );
+        assert_eq!(parse(&["-q"]).rust_log().as_deref(), Some("off"));
+        assert_eq!(
+            parse(&["--debug", "--log-targets", "sqlx,msb"])
+                .rust_log()
+                .as_deref(),
+            Some("warn,sqlx=debug,msb=debug")
+        );
+    }
+
+    #[test]
+    fn test_registry_flag_is_global() {
+        assert_eq!(parse(&[]).registry, None);
+        assert_eq!(
+            parse(&["--registry", "mirror.internal", "pull", "alpine"])
+                .registry
+                .as_deref(),
+            Some("mirror.internal")
+        );
+        assert_eq!(
+            parse(&["pull", "alpine", "--registry", "mirror.internal"])
+                .registry
+                .as_deref(),
+            Some("mirror.internal")
+        );
+    }
+}
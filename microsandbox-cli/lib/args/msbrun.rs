@@ -47,6 +47,10 @@ pub enum McrunSubcommand {
         #[arg(long)]
         workdir_path: Option<String>,
 
+        /// Path to capture the guest console output to
+        #[arg(long)]
+        console_log_path: Option<PathBuf>,
+
         /// Executable path
         #[arg(long, required = true)]
         exec_path: String,
@@ -55,6 +59,10 @@ pub enum McrunSubcommand {
         #[arg(long)]
         env: Vec<String>,
 
+        /// Extra guest kernel command line arguments
+        #[arg(long)]
+        kernel_arg: Vec<String>,
+
         /// Directory mappings (host:guest format)
         #[arg(long)]
         mapped_dir: Vec<String>,
@@ -63,6 +71,10 @@ pub enum McrunSubcommand {
         #[arg(long)]
         port_map: Vec<String>,
 
+        /// Network mode: none, host or nat
+        #[arg(long)]
+        network: Option<String>,
+
         /// Network communication scope
         #[arg(long)]
         scope: Option<String>,
@@ -131,6 +143,10 @@ pub enum McrunSubcommand {
         #[arg(long)]
         workdir_path: Option<String>,
 
+        /// Path to capture the guest console output to
+        #[arg(long)]
+        console_log_path: Option<PathBuf>,
+
         /// Executable path
         #[arg(long, required = true)]
         exec_path: String,
@@ -139,6 +155,10 @@ pub enum McrunSubcommand {
         #[arg(long)]
         env: Vec<String>,
 
+        /// Extra guest kernel command line arguments
+        #[arg(long)]
+        kernel_arg: Vec<String>,
+
         /// Directory mappings (host:guest format)
         #[arg(long)]
         mapped_dir: Vec<String>,
@@ -147,6 +167,10 @@ pub enum McrunSubcommand {
         #[arg(long)]
         port_map: Vec<String>,
 
+        /// Network mode: none, host or nat
+        #[arg(long)]
+        network: Option<String>,
+
         /// Network communication scope
         #[arg(long)]
         scope: Option<String>,
@@ -32,4 +32,52 @@ pub struct MsbserverArgs {
     /// Run in development mode
     #[arg(long = "dev", default_value_t = false)]
     pub dev_mode: bool,
+
+    /// Stop the sandboxes started by the server when it shuts down
+    #[arg(long, default_value_t = false)]
+    pub stop_sandboxes_on_exit: bool,
+
+    /// Requests per second allowed per client [env: MSB_RATE_LIMIT_RPS]
+    #[arg(long)]
+    pub rate_limit_rps: Option<f64>,
+
+    /// Requests a client can make in a burst [env: MSB_RATE_LIMIT_BURST]
+    #[arg(long)]
+    pub rate_limit_burst: Option<u32>,
+
+    /// Sandbox start/stop requests per second allowed per client [env: MSB_LIFECYCLE_RATE_LIMIT_RPS]
+    #[arg(long)]
+    pub lifecycle_rate_limit_rps: Option<f64>,
+
+    /// Sandbox start/stop requests a client can make in a burst [env: MSB_LIFECYCLE_RATE_LIMIT_BURST]
+    #[arg(long)]
+    pub lifecycle_rate_limit_burst: Option<u32>,
+
+    /// Seconds between samples of sandbox resource usage kept for `sandbox.metrics.history`.
+    /// No history is kept when unset or zero [env: MSB_METRICS_HISTORY_INTERVAL]
+    #[arg(long)]
+    pub metrics_history_interval: Option<u64>,
+
+    /// Resource usage samples kept per sandbox [env: MSB_METRICS_HISTORY_DEPTH]
+    #[arg(long)]
+    pub metrics_history_depth: Option<usize>,
+
+    /// Maximum size, in bytes, of a JSON-RPC or MCP request body. Larger requests are rejected
+    /// with 413 [env: MSB_MAX_REQUEST_BODY]
+    #[arg(long)]
+    pub max_request_body: Option<usize>,
+
+    /// Origin allowed to make cross-origin requests, `*` for any. Can be repeated
+    /// [env: MSB_CORS_ALLOWED_ORIGINS]
+    #[arg(long = "cors-origin")]
+    pub cors_origins: Vec<String>,
+
+    /// Method allowed in cross-origin requests, GET, POST, PUT, DELETE and OPTIONS by default.
+    /// Can be repeated [env: MSB_CORS_ALLOWED_METHODS]
+    #[arg(long = "cors-method")]
+    pub cors_methods: Vec<String>,
+
+    /// Header allowed in cross-origin requests. Can be repeated [env: MSB_CORS_ALLOWED_HEADERS]
+    #[arg(long = "cors-header")]
+    pub cors_headers: Vec<String>,
 }
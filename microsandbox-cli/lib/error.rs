@@ -37,4 +37,8 @@ pub enum MicrosandboxCliError {
     /// Configuration error
     #[error("configuration error: {0}")]
     ConfigError(String),
+
+    /// Error returned by the sandbox server
+    #[error("server error: {0}")]
+    ServerRequest(String),
 }
@@ -0,0 +1,192 @@
+use std::path::Path;
+
+use typed_path::Utf8UnixPathBuf;
+
+use crate::{MicrosandboxError, MicrosandboxResult};
+
+use super::EnvPair;
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Loads environment files in order and merges them with explicit environment variables.
+///
+/// Later files override keys from earlier ones, and `envs` override every file. Variables keep
+/// the position they were first defined at, so the result is stable across runs.
+///
+/// ## Arguments
+///
+/// * `base_dir` - The directory relative env file paths are resolved against
+/// * `env_files` - The env files to load, in order
+/// * `envs` - Environment variables that take precedence over every file
+///
+/// ## Returns
+///
+/// The merged environment variables, or an error naming the first env file that could not be found
+pub async fn load_env_files(
+    base_dir: &Path,
+    env_files: &[Utf8UnixPathBuf],
+    envs: &[EnvPair],
+) -> MicrosandboxResult<Vec<EnvPair>> {
+    let mut merged: Vec<EnvPair> = Vec::new();
+
+    for env_file in env_files {
+        let path = base_dir.join(env_file.as_str());
+        let contents = tokio::fs::read_to_string(&path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                MicrosandboxError::EnvFileNotFound(path.display().to_string())
+            } else {
+                e.into()
+            }
+        })?;
+
+        for env in parse_env_file(&contents)? {
+            merge_env(&mut merged, env);
+        }
+    }
+
+    for env in envs {
+        merge_env(&mut merged, env.clone());
+    }
+
+    Ok(merged)
+}
+
+/// Parses the contents of an env file.
+///
+/// Each line is a `KEY=VALUE` pair, optionally prefixed with `export`. Blank lines and lines
+/// starting with `#` are ignored, and values wrapped in matching quotes are unquoted.
+pub fn parse_env_file(contents: &str) -> MicrosandboxResult<Vec<EnvPair>> {
+    let mut envs = Vec::new();
+
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+        let (name, value) = line
+            .split_once('=')
+            .ok_or_else(|| MicrosandboxError::InvalidEnvPair(line.to_string()))?;
+
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(MicrosandboxError::InvalidEnvPair(line.to_string()));
+        }
+
+        envs.push(EnvPair::new(name, unquote(value.trim())));
+    }
+
+    Ok(envs)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Replaces an existing variable with the same name, or appends the variable if it is new.
+fn merge_env(envs: &mut Vec<EnvPair>, env: EnvPair) {
+    match envs
+        .iter_mut()
+        .find(|existing| existing.get_name() == env.get_name())
+    {
+        Some(existing) => *existing = env,
+        None => envs.push(env),
+    }
+}
+
+/// Strips a matching pair of single or double quotes from a value.
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+
+    value
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_env_file_parse() -> anyhow::Result<()> {
+        let contents = r#"
+            # Database settings
+            DB_HOST=localhost
+            export DB_PORT=5432
+            DB_NAME="app db"
+            EMPTY=
+        "#;
+
+        let envs = parse_env_file(contents)?;
+        assert_eq!(
+            envs,
+            vec![
+                EnvPair::new("DB_HOST", "localhost"),
+                EnvPair::new("DB_PORT", "5432"),
+                EnvPair::new("DB_NAME", "app db"),
+                EnvPair::new("EMPTY", ""),
+            ]
+        );
+
+        assert!(parse_env_file("NO_VALUE").is_err());
+        assert!(parse_env_file("=value").is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_env_file_later_files_override_earlier() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        tokio::fs::write(
+            dir.path().join("base.env"),
+            "HOST=base\nPORT=80\nMODE=dev\n",
+        )
+        .await?;
+        tokio::fs::write(dir.path().join("override.env"), "PORT=8080\n").await?;
+
+        let env_files = vec![
+            Utf8UnixPathBuf::from("base.env"),
+            Utf8UnixPathBuf::from("override.env"),
+        ];
+        let envs = vec![EnvPair::new("MODE", "prod")];
+
+        let merged = load_env_files(dir.path(), &env_files, &envs).await?;
+        assert_eq!(
+            merged,
+            vec![
+                EnvPair::new("HOST", "base"),
+                EnvPair::new("PORT", "8080"),
+                EnvPair::new("MODE", "prod"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_env_file_missing_file_names_path() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let env_files = vec![Utf8UnixPathBuf::from("missing.env")];
+
+        let error = load_env_files(dir.path(), &env_files, &[])
+            .await
+            .unwrap_err();
+        match error {
+            MicrosandboxError::EnvFileNotFound(path) => assert!(path.ends_with("missing.env")),
+            error => panic!("unexpected error: {error}"),
+        }
+
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
-use crate::MicrosandboxError;
+use crate::{MicrosandboxError, MicrosandboxResult};
 use getset::Getters;
+use microsandbox_utils::INHERITED_ENV_CHECK_ENV_VAR;
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
 use serde::{Deserialize, Serialize};
-use std::{fmt, str::FromStr};
+use std::{borrow::Cow, fmt, str::FromStr};
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -12,6 +14,9 @@ use std::{fmt, str::FromStr};
 /// This struct encapsulates a variable name and its corresponding value.
 /// It is used to manage environment variables for processes.
 ///
+/// A name without a value, e.g. `HTTP_PROXY`, inherits the value of the variable from the host
+/// environment when the sandbox is started. See [`resolve_inherited_envs`].
+///
 /// ## Examples
 ///
 /// ```
@@ -29,6 +34,12 @@ use std::{fmt, str::FromStr};
 ///
 /// assert_eq!(env_pair.get_name(), "USER");
 /// assert_eq!(env_pair.get_value(), "alice");
+///
+/// // A name alone inherits its value from the host
+/// let env_pair = EnvPair::from_str("HTTP_PROXY").unwrap();
+///
+/// assert!(env_pair.is_inherited());
+/// assert_eq!(env_pair.to_string(), "HTTP_PROXY");
 /// ```
 #[derive(Debug, Hash, Clone, PartialEq, Eq, Getters)]
 #[getset(get = "pub with_prefix")]
@@ -37,7 +48,24 @@ pub struct EnvPair {
     name: String,
 
     /// The value of the environment variable.
+    ///
+    /// This is empty for a variable inherited from the host until it is resolved.
     value: String,
+
+    /// Whether the value is inherited from the host environment.
+    #[getset(skip)]
+    inherited: bool,
+}
+
+/// How an inherited environment variable that isn't set on the host is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InheritedEnvMode {
+    /// Log a warning and leave the variable out of the sandbox.
+    #[default]
+    Warn,
+
+    /// Refuse to start the sandbox.
+    Error,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -65,8 +93,105 @@ impl EnvPair {
         Self {
             name: name.into(),
             value: value.into(),
+            inherited: false,
+        }
+    }
+
+    /// Creates an `EnvPair` that inherits the value of the variable from the host environment.
+    ///
+    /// ## Examples
+    ///
+    /// ```
+    /// use microsandbox_core::config::EnvPair;
+    ///
+    /// let env_pair = EnvPair::inherit("HTTP_PROXY");
+    /// assert_eq!(env_pair.get_name(), "HTTP_PROXY");
+    /// assert!(env_pair.is_inherited());
+    /// ```
+    pub fn inherit(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: String::new(),
+            inherited: true,
+        }
+    }
+
+    /// Returns whether the value is inherited from the host environment.
+    pub fn is_inherited(&self) -> bool {
+        self.inherited
+    }
+}
+
+impl InheritedEnvMode {
+    /// Reads the mode from the `MSB_INHERITED_ENV_CHECK` environment variable.
+    ///
+    /// Returns [`InheritedEnvMode::Error`] if it is set to `error`, and the default of
+    /// [`InheritedEnvMode::Warn`] otherwise.
+    pub fn from_env() -> Self {
+        match std::env::var(INHERITED_ENV_CHECK_ENV_VAR) {
+            Ok(value) if value.trim().eq_ignore_ascii_case("error") => Self::Error,
+            _ => Self::Warn,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Resolves the environment variables inherited from the host to their current values.
+///
+/// Variables with an explicit value are returned as they are.
+///
+/// ## Arguments
+///
+/// * `envs` - The environment variables of the sandbox
+/// * `mode` - Whether an inherited variable that isn't set on the host is a warning or an error
+///
+/// ## Returns
+///
+/// The environment variables with every inherited one given its host value, and those missing
+/// from the host left out, or a [`MicrosandboxError::MissingHostEnv`] if `mode` is
+/// [`InheritedEnvMode::Error`].
+pub fn resolve_inherited_envs(
+    envs: Vec<EnvPair>,
+    mode: InheritedEnvMode,
+) -> MicrosandboxResult<Vec<EnvPair>> {
+    resolve_inherited_envs_with(envs, mode, |name| std::env::var(name).ok())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Resolves the inherited environment variables, looking up host values with `lookup`.
+fn resolve_inherited_envs_with(
+    envs: Vec<EnvPair>,
+    mode: InheritedEnvMode,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> MicrosandboxResult<Vec<EnvPair>> {
+    let mut resolved = Vec::with_capacity(envs.len());
+    for env in envs {
+        if !env.inherited {
+            resolved.push(env);
+            continue;
+        }
+
+        match (lookup(&env.name), mode) {
+            (Some(value), _) => resolved.push(EnvPair::new(env.name, value)),
+            (None, InheritedEnvMode::Warn) => {
+                tracing::warn!(
+                    "host environment variable {} is not set, leaving it out of the sandbox",
+                    env.name
+                );
+            }
+            (None, InheritedEnvMode::Error) => {
+                return Err(MicrosandboxError::MissingHostEnv(env.name));
+            }
         }
     }
+
+    Ok(resolved)
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -77,22 +202,31 @@ impl FromStr for EnvPair {
     type Err = MicrosandboxError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (var, value) = s
-            .split_once('=')
-            .ok_or_else(|| MicrosandboxError::InvalidEnvPair(s.to_string()))?;
+        let (var, value) = match s.split_once('=') {
+            Some((var, value)) => (var, Some(value)),
+            None => (s, None),
+        };
 
         if var.is_empty() {
             return Err(MicrosandboxError::InvalidEnvPair(s.to_string()));
         }
 
-        Ok(Self::new(var, value))
+        Ok(match value {
+            Some(value) => Self::new(var, value),
+            None => Self::inherit(var),
+        })
     }
 }
 
 impl fmt::Display for EnvPair {
-    /// Formats the environment variable pair following the format "<var>=<value>".
+    /// Formats the environment variable pair following the format "<var>=<value>", or "<var>"
+    /// for a variable inherited from the host.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}={}", self.name, self.value)
+        if self.inherited {
+            write!(f, "{}", self.name)
+        } else {
+            write!(f, "{}={}", self.name, self.value)
+        }
     }
 }
 
@@ -115,6 +249,20 @@ impl<'de> Deserialize<'de> for EnvPair {
     }
 }
 
+impl JsonSchema for EnvPair {
+    fn schema_name() -> Cow<'static, str> {
+        "EnvPair".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "An environment variable in the format `NAME=VALUE`, or `NAME` to inherit its value from the host.",
+            "pattern": "^[^=]+",
+        })
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
@@ -140,8 +288,8 @@ mod tests {
         assert_eq!(env_pair.name, String::from("VAR"));
         assert_eq!(env_pair.value, String::from(""));
 
-        assert!("VAR".parse::<EnvPair>().is_err());
         assert!("=VALUE".parse::<EnvPair>().is_err());
+        assert!("".parse::<EnvPair>().is_err());
 
         Ok(())
     }
@@ -186,4 +334,70 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_env_pair_inherit_from_str() -> anyhow::Result<()> {
+        let env_pair: EnvPair = "HTTP_PROXY".parse()?;
+        assert_eq!(env_pair, EnvPair::inherit("HTTP_PROXY"));
+        assert!(env_pair.is_inherited());
+        assert_eq!(env_pair.to_string(), "HTTP_PROXY");
+
+        // An empty value is explicit, not inherited
+        let env_pair: EnvPair = "HTTP_PROXY=".parse()?;
+        assert!(!env_pair.is_inherited());
+
+        let serialized = serde_json::to_string(&EnvPair::inherit("HTTP_PROXY"))?;
+        assert_eq!(serialized, "\"HTTP_PROXY\"");
+        assert!(serde_json::from_str::<EnvPair>(&serialized)?.is_inherited());
+
+        Ok(())
+    }
+
+    fn host(name: &str) -> Option<String> {
+        (name == "HTTP_PROXY").then(|| "http://proxy:3128".to_string())
+    }
+
+    #[test]
+    fn test_resolve_inherited_envs_present() -> anyhow::Result<()> {
+        let envs = vec![EnvPair::inherit("HTTP_PROXY"), EnvPair::new("MODE", "dev")];
+        let resolved = resolve_inherited_envs_with(envs, InheritedEnvMode::Error, host)?;
+
+        assert_eq!(
+            resolved,
+            vec![
+                EnvPair::new("HTTP_PROXY", "http://proxy:3128"),
+                EnvPair::new("MODE", "dev"),
+            ]
+        );
+        assert_eq!(resolved[0].to_string(), "HTTP_PROXY=http://proxy:3128");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_inherited_envs_missing() -> anyhow::Result<()> {
+        let envs = vec![EnvPair::inherit("NO_PROXY"), EnvPair::new("MODE", "dev")];
+
+        // A missing host variable is left out with a warning by default
+        let resolved = resolve_inherited_envs_with(envs.clone(), InheritedEnvMode::Warn, host)?;
+        assert_eq!(resolved, vec![EnvPair::new("MODE", "dev")]);
+
+        let result = resolve_inherited_envs_with(envs, InheritedEnvMode::Error, host);
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::MissingHostEnv(name)) if name == "NO_PROXY"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_inherited_envs_keeps_explicit_values() -> anyhow::Result<()> {
+        // An explicit value is used even if the host has a different one
+        let envs = vec![EnvPair::from_str("HTTP_PROXY=http://other:8080")?];
+        let resolved = resolve_inherited_envs_with(envs.clone(), InheritedEnvMode::Error, host)?;
+        assert_eq!(resolved, envs);
+
+        Ok(())
+    }
 }
@@ -0,0 +1,146 @@
+use crate::MicrosandboxError;
+use getset::Getters;
+use std::{fmt, str::FromStr};
+
+use super::Sandbox;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Selects sandboxes by one of their labels.
+///
+/// A selector matches a sandbox when the sandbox has a label with the selector's key and value.
+/// Selectors are written as `key=value`, as passed to `--label` on the command line.
+///
+/// ## Examples
+///
+/// ```
+/// use microsandbox_core::config::LabelSelector;
+/// use std::str::FromStr;
+///
+/// let selector = LabelSelector::from_str("tier=web").unwrap();
+///
+/// assert_eq!(selector.get_key(), "tier");
+/// assert_eq!(selector.get_value(), "web");
+/// ```
+#[derive(Debug, Hash, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct LabelSelector {
+    /// The label key.
+    key: String,
+
+    /// The value the label must have.
+    value: String,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl LabelSelector {
+    /// Creates a new `LabelSelector` matching the label `key` with the given value.
+    pub fn new<S: Into<String>>(key: S, value: S) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    /// Returns whether the sandbox has the selected label.
+    pub fn matches(&self, sandbox: &Sandbox) -> bool {
+        sandbox.get_labels().get(&self.key) == Some(&self.value)
+    }
+
+    /// Returns whether the sandbox matches every one of the selectors.
+    pub fn matches_all(selectors: &[LabelSelector], sandbox: &Sandbox) -> bool {
+        selectors.iter().all(|selector| selector.matches(sandbox))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl FromStr for LabelSelector {
+    type Err = MicrosandboxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| MicrosandboxError::InvalidLabelSelector(s.to_string()))?;
+
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(MicrosandboxError::InvalidLabelSelector(s.to_string()));
+        }
+
+        Ok(Self::new(key, value.trim()))
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    /// Formats the selector following the format "<key>=<value>".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_label_selector_from_str() -> anyhow::Result<()> {
+        let selector: LabelSelector = "tier=web".parse()?;
+        assert_eq!(selector, LabelSelector::new("tier", "web"));
+
+        let selector: LabelSelector = " tier = web ".parse()?;
+        assert_eq!(selector, LabelSelector::new("tier", "web"));
+
+        let selector: LabelSelector = "release=".parse()?;
+        assert_eq!(selector, LabelSelector::new("release", ""));
+
+        assert!("tier".parse::<LabelSelector>().is_err());
+        assert!("=web".parse::<LabelSelector>().is_err());
+
+        assert_eq!(LabelSelector::new("tier", "web").to_string(), "tier=web");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_label_selector_matches() -> anyhow::Result<()> {
+        let sandbox: Sandbox = serde_yaml::from_str(
+            r#"
+            image: "nginx:latest"
+            labels:
+              tier: web
+              team: shop
+        "#,
+        )?;
+
+        assert!(LabelSelector::new("tier", "web").matches(&sandbox));
+        assert!(!LabelSelector::new("tier", "db").matches(&sandbox));
+        assert!(!LabelSelector::new("env", "web").matches(&sandbox));
+
+        let selectors = [
+            LabelSelector::new("tier", "web"),
+            LabelSelector::new("team", "shop"),
+        ];
+        assert!(LabelSelector::matches_all(&selectors, &sandbox));
+        assert!(LabelSelector::matches_all(&[], &sandbox));
+
+        let selectors = [
+            LabelSelector::new("tier", "web"),
+            LabelSelector::new("team", "blog"),
+        ];
+        assert!(!LabelSelector::matches_all(&selectors, &sandbox));
+
+        Ok(())
+    }
+}
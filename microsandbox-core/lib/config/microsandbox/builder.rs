@@ -9,7 +9,10 @@ use crate::{
     config::{EnvPair, PathPair, PortPair, ReferenceOrPath},
 };
 
-use super::{Build, Meta, Microsandbox, Module, NetworkScope, Sandbox};
+use super::{
+    Build, DnsMode, Meta, Microsandbox, Module, NetworkMode, NetworkScope, ReadinessProbe,
+    RootfsMode, Sandbox,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -39,38 +42,54 @@ pub struct MicrosandboxBuilder {
 /// ### Optional fields:
 /// - `version`: The version of the sandbox
 /// - `meta`: The metadata for the sandbox
+/// - `labels`: The labels used to group and select sandboxes
 /// - `memory`: The maximum amount of memory allowed for the sandbox
 /// - `cpus`: The maximum number of CPUs allowed for the sandbox
 /// - `volumes`: The volumes to mount
 /// - `ports`: The ports to expose
 /// - `envs`: The environment variables to use
-/// - `env_file`: The environment file to use
+/// - `env_file`: The environment files to load, in order
 /// - `depends_on`: The sandboxes to depend on
 /// - `workdir`: The working directory to use
 /// - `shell`: The shell to use
 /// - `scripts`: The scripts available in the sandbox
+/// - `entrypoint`: The program to run in place of the image's entrypoint
 /// - `imports`: The files to import
 /// - `exports`: The files to export
 /// - `scope`: The network scope for the sandbox
+/// - `network`: How the sandbox is connected to the network
+/// - `dns`: Where the sandbox gets its DNS resolvers from
+/// - `rootfs_mode`: How the root filesystem of the sandbox is put together
+/// - `readiness`: The probe that has to succeed before the sandbox is up
+/// - `console_log`: Whether to capture the guest console output
+/// - `kernel_args`: Extra arguments for the guest kernel command line
 /// - `proxy`: The proxy to use
 pub struct SandboxBuilder<I> {
     version: Option<Version>,
     meta: Option<Meta>,
+    labels: HashMap<String, String>,
     image: I,
     memory: Option<u32>,
     cpus: Option<u8>,
     volumes: Vec<PathPair>,
     ports: Vec<PortPair>,
     envs: Vec<EnvPair>,
-    env_file: Option<Utf8UnixPathBuf>,
+    env_file: Vec<Utf8UnixPathBuf>,
     depends_on: Vec<String>,
     workdir: Option<Utf8UnixPathBuf>,
     shell: Option<String>,
     scripts: HashMap<String, String>,
     command: Vec<String>,
+    entrypoint: Vec<String>,
     imports: HashMap<String, Utf8UnixPathBuf>,
     exports: HashMap<String, Utf8UnixPathBuf>,
-    scope: NetworkScope,
+    scope: Option<NetworkScope>,
+    network: Option<NetworkMode>,
+    dns: Option<DnsMode>,
+    rootfs_mode: Option<RootfsMode>,
+    readiness: Option<ReadinessProbe>,
+    console_log: bool,
+    kernel_args: Vec<String>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -133,11 +152,21 @@ impl<I> SandboxBuilder<I> {
         self
     }
 
+    /// Sets the labels for the sandbox
+    pub fn labels(
+        mut self,
+        labels: impl IntoIterator<Item = (String, String)>,
+    ) -> SandboxBuilder<I> {
+        self.labels = labels.into_iter().collect();
+        self
+    }
+
     /// Sets the image for the sandbox
     pub fn image(self, image: impl Into<ReferenceOrPath>) -> SandboxBuilder<ReferenceOrPath> {
         SandboxBuilder {
             version: self.version,
             meta: self.meta,
+            labels: self.labels,
             image: image.into(),
             memory: self.memory,
             cpus: self.cpus,
@@ -150,9 +179,16 @@ impl<I> SandboxBuilder<I> {
             shell: self.shell,
             scripts: self.scripts,
             command: self.command,
+            entrypoint: self.entrypoint,
             imports: self.imports,
             exports: self.exports,
             scope: self.scope,
+            network: self.network,
+            dns: self.dns,
+            rootfs_mode: self.rootfs_mode,
+            readiness: self.readiness,
+            console_log: self.console_log,
+            kernel_args: self.kernel_args,
         }
     }
 
@@ -186,9 +222,12 @@ impl<I> SandboxBuilder<I> {
         self
     }
 
-    /// Sets the environment file for the sandbox
-    pub fn env_file(mut self, env_file: impl Into<Utf8UnixPathBuf>) -> SandboxBuilder<I> {
-        self.env_file = Some(env_file.into());
+    /// Sets the environment files for the sandbox, loaded in order
+    pub fn env_file(
+        mut self,
+        env_file: impl IntoIterator<Item = impl Into<Utf8UnixPathBuf>>,
+    ) -> SandboxBuilder<I> {
+        self.env_file = env_file.into_iter().map(Into::into).collect();
         self
     }
 
@@ -225,6 +264,12 @@ impl<I> SandboxBuilder<I> {
         self
     }
 
+    /// Sets the entrypoint that replaces the image's entrypoint for the sandbox
+    pub fn entrypoint(mut self, entrypoint: impl IntoIterator<Item = String>) -> SandboxBuilder<I> {
+        self.entrypoint = entrypoint.into_iter().collect();
+        self
+    }
+
     /// Sets the files to import for the sandbox
     pub fn imports(
         mut self,
@@ -245,7 +290,46 @@ impl<I> SandboxBuilder<I> {
 
     /// Sets the network scope for the sandbox
     pub fn scope(mut self, scope: NetworkScope) -> SandboxBuilder<I> {
-        self.scope = scope;
+        self.scope = Some(scope);
+        self
+    }
+
+    /// Sets how the sandbox is connected to the network
+    pub fn network(mut self, network: NetworkMode) -> SandboxBuilder<I> {
+        self.network = Some(network);
+        self
+    }
+
+    /// Sets where the sandbox gets its DNS resolvers from
+    pub fn dns(mut self, dns: DnsMode) -> SandboxBuilder<I> {
+        self.dns = Some(dns);
+        self
+    }
+
+    /// Sets how the root filesystem of the sandbox is put together
+    pub fn rootfs_mode(mut self, rootfs_mode: RootfsMode) -> SandboxBuilder<I> {
+        self.rootfs_mode = Some(rootfs_mode);
+        self
+    }
+
+    /// Sets the probe that has to succeed before the sandbox is reported as up
+    pub fn readiness(mut self, readiness: ReadinessProbe) -> SandboxBuilder<I> {
+        self.readiness = Some(readiness);
+        self
+    }
+
+    /// Sets whether to capture the guest console output to a log file
+    pub fn console_log(mut self, console_log: bool) -> SandboxBuilder<I> {
+        self.console_log = console_log;
+        self
+    }
+
+    /// Sets extra arguments to append to the guest kernel command line
+    pub fn kernel_args(
+        mut self,
+        kernel_args: impl IntoIterator<Item = String>,
+    ) -> SandboxBuilder<I> {
+        self.kernel_args = kernel_args.into_iter().collect();
         self
     }
 }
@@ -256,20 +340,29 @@ impl SandboxBuilder<ReferenceOrPath> {
         Sandbox {
             version: self.version,
             meta: self.meta,
+            labels: self.labels,
             image: self.image,
             memory: self.memory,
             cpus: self.cpus,
             volumes: self.volumes,
             ports: self.ports,
             envs: self.envs,
+            env_file: self.env_file,
             depends_on: self.depends_on,
             workdir: self.workdir,
             shell: self.shell,
             scripts: self.scripts,
             command: self.command,
+            entrypoint: self.entrypoint,
             imports: self.imports,
             exports: self.exports,
             scope: self.scope,
+            network: self.network,
+            dns: self.dns,
+            rootfs_mode: self.rootfs_mode,
+            readiness: self.readiness,
+            console_log: self.console_log,
+            kernel_args: self.kernel_args,
         }
     }
 }
@@ -283,21 +376,29 @@ impl Default for SandboxBuilder<()> {
         Self {
             version: None,
             meta: None,
+            labels: HashMap::new(),
             image: (),
             memory: None,
             cpus: None,
             volumes: Vec::new(),
             ports: Vec::new(),
             envs: Vec::new(),
-            env_file: None,
+            env_file: Vec::new(),
             depends_on: Vec::new(),
             workdir: None,
             shell: Some(DEFAULT_SHELL.to_string()),
             scripts: HashMap::new(),
             command: Vec::new(),
+            entrypoint: Vec::new(),
             imports: HashMap::new(),
             exports: HashMap::new(),
-            scope: NetworkScope::default(),
+            scope: None,
+            network: None,
+            dns: None,
+            rootfs_mode: None,
+            readiness: None,
+            console_log: false,
+            kernel_args: Vec::new(),
         }
     }
 }
@@ -3,10 +3,16 @@
 use std::{
     collections::HashMap,
     fmt::{self, Display},
+    path::Path,
     str::FromStr,
+    time::Duration,
 };
 
 use getset::{Getters, Setters};
+use microsandbox_utils::{
+    DEFAULT_DNS_ENV_VAR, DEFAULT_READINESS_INTERVAL_SECS, DEFAULT_READINESS_TIMEOUT_SECS, env,
+};
+use schemars::JsonSchema;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use typed_builder::TypedBuilder;
@@ -14,7 +20,7 @@ use typed_path::Utf8UnixPathBuf;
 
 use crate::{
     MicrosandboxError, MicrosandboxResult,
-    config::{EnvPair, PathPair, PortPair, ReferenceOrPath},
+    config::{EnvPair, PathPair, PortPair, ReferenceOrPath, load_env_files},
 };
 
 use super::{MicrosandboxBuilder, SandboxBuilder};
@@ -29,12 +35,15 @@ pub const START_SCRIPT_NAME: &str = "start";
 /// The default network scope for a sandbox.
 pub const DEFAULT_NETWORK_SCOPE: NetworkScope = NetworkScope::Public;
 
+/// The path an HTTP readiness probe requests by default.
+pub const DEFAULT_READINESS_HTTP_PATH: &str = "/";
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
 
 /// The microsandbox configuration.
-#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Getters)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema, PartialEq, Getters)]
 #[getset(get = "pub with_prefix")]
 pub struct Microsandbox {
     /// The metadata about the configuration.
@@ -43,19 +52,28 @@ pub struct Microsandbox {
 
     /// The modules to import.
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    #[schemars(with = "Option<HashMap<String, Module>>")]
     pub(crate) modules: HashMap<String, Module>,
 
     /// The builds to run.
     #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    #[schemars(with = "Option<HashMap<String, Build>>")]
     pub(crate) builds: HashMap<String, Build>,
 
     /// The sandboxes to run.
-    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    #[serde(
+        skip_serializing_if = "HashMap::is_empty",
+        default,
+        deserialize_with = "deserialize_sandboxes"
+    )]
+    #[schemars(with = "Option<HashMap<String, Sandbox>>")]
     pub(crate) sandboxes: HashMap<String, Sandbox>,
 }
 
 /// The metadata about the configuration.
-#[derive(Debug, Default, Clone, Serialize, Deserialize, TypedBuilder, PartialEq, Eq, Getters)]
+#[derive(
+    Debug, Default, Clone, Serialize, Deserialize, JsonSchema, TypedBuilder, PartialEq, Eq, Getters,
+)]
 #[getset(get = "pub with_prefix")]
 pub struct Meta {
     /// The authors of the configuration.
@@ -85,6 +103,7 @@ pub struct Meta {
         serialize_with = "serialize_optional_path",
         deserialize_with = "deserialize_optional_path"
     )]
+    #[schemars(with = "Option<String>")]
     #[builder(default, setter(strip_option))]
     pub(crate) readme: Option<Utf8UnixPathBuf>,
 
@@ -100,12 +119,13 @@ pub struct Meta {
         serialize_with = "serialize_optional_path",
         deserialize_with = "deserialize_optional_path"
     )]
+    #[schemars(with = "Option<String>")]
     #[builder(default, setter(strip_option))]
     pub(crate) icon: Option<Utf8UnixPathBuf>,
 }
 
 /// Component mapping for imports.
-#[derive(Debug, Clone, Serialize, Deserialize, TypedBuilder, PartialEq, Getters)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, TypedBuilder, PartialEq, Getters)]
 #[getset(get = "pub with_prefix")]
 pub struct ComponentMapping {
     /// The alias for the component.
@@ -115,11 +135,11 @@ pub struct ComponentMapping {
 }
 
 /// Module import configuration.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
 pub struct Module(pub HashMap<String, Option<ComponentMapping>>);
 
 /// A build to run.
-#[derive(Debug, Clone, Serialize, Deserialize, TypedBuilder, PartialEq, Getters)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, TypedBuilder, PartialEq, Getters)]
 #[getset(get = "pub with_prefix")]
 pub struct Build {
     /// The image to use. This can be a path to a local rootfs or an OCI image reference.
@@ -162,6 +182,7 @@ pub struct Build {
         serialize_with = "serialize_optional_path",
         deserialize_with = "deserialize_optional_path"
     )]
+    #[schemars(with = "Option<String>")]
     #[builder(default, setter(strip_option))]
     pub(crate) workdir: Option<Utf8UnixPathBuf>,
 
@@ -187,6 +208,7 @@ pub struct Build {
         serialize_with = "serialize_path_map",
         deserialize_with = "deserialize_path_map"
     )]
+    #[schemars(with = "HashMap<String, String>")]
     #[builder(default)]
     pub(crate) imports: HashMap<String, Utf8UnixPathBuf>,
 
@@ -197,12 +219,13 @@ pub struct Build {
         serialize_with = "serialize_path_map",
         deserialize_with = "deserialize_path_map"
     )]
+    #[schemars(with = "HashMap<String, String>")]
     #[builder(default)]
     pub(crate) exports: HashMap<String, Utf8UnixPathBuf>,
 }
 
 /// Network scope configuration for a sandbox.
-#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Default)]
 #[repr(u8)]
 pub enum NetworkScope {
     /// Sandboxes cannot communicate with any other sandboxes
@@ -223,8 +246,125 @@ pub enum NetworkScope {
     Any = 3,
 }
 
+/// How a sandbox is connected to the network.
+///
+/// ```yaml
+/// sandboxes:
+///   untrusted:
+///     image: python
+///     network: none
+/// ```
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// No network interface. The guest can't open any connection, so only the ports the sandbox
+    /// publishes are reachable, and only from the host.
+    None,
+
+    /// Guest sockets are carried over the host's network stack, limited by the sandbox's `scope`.
+    #[default]
+    Host,
+
+    /// A virtual network interface whose traffic is translated by a `passt` process on the host.
+    ///
+    /// `passt` has to be installed on the host.
+    Nat,
+}
+
+/// Where a sandbox gets its DNS resolvers from when its image doesn't configure any.
+///
+/// ```yaml
+/// sandboxes:
+///   app:
+///     image: python
+///     dns: inherit
+/// ```
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsMode {
+    /// Use the public resolvers of Cloudflare and Google.
+    #[default]
+    Public,
+
+    /// Use the resolvers the host uses, which also works where only internal DNS is reachable.
+    ///
+    /// The public resolvers are used if the host has none the guest can reach.
+    Inherit,
+}
+
+/// How the root filesystem of a sandbox is put together.
+///
+/// ```yaml
+/// sandboxes:
+///   app:
+///     image: python
+///     rootfs_mode: native
+/// ```
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RootfsMode {
+    /// Overlay when the image has layers, which is the case for OCI images, and native for a
+    /// rootfs directory.
+    #[default]
+    Auto,
+
+    /// Compose the image layers with overlayfs, writing changes to a separate read-write layer.
+    ///
+    /// A rootfs directory is used as the single lower layer, so it is left untouched.
+    Overlay,
+
+    /// Pass a single directory through as the root filesystem, which changes are written to.
+    ///
+    /// The layers of an OCI image are merged into a copy owned by the sandbox first.
+    Native,
+}
+
+/// A probe that has to succeed before a started sandbox is reported as up.
+///
+/// ```yaml
+/// readiness:
+///   http:
+///     port: 8080
+///     path: /health
+///   interval: 1
+///   timeout: 60
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct ReadinessProbe {
+    /// The check to run.
+    #[serde(flatten)]
+    pub(crate) check: ReadinessCheck,
+
+    /// The number of seconds to wait between attempts.
+    #[serde(default = "default_readiness_interval")]
+    pub(crate) interval: u64,
+
+    /// The number of seconds the probe has to succeed within.
+    #[serde(default = "default_readiness_timeout")]
+    pub(crate) timeout: u64,
+}
+
+/// The check a readiness probe runs.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessCheck {
+    /// An HTTP GET on a guest port, which succeeds on a 2xx response.
+    Http {
+        /// The guest port to request. It has to be exposed through `ports`.
+        port: u16,
+
+        /// The path to request.
+        #[serde(default = "default_readiness_http_path")]
+        path: String,
+    },
+
+    /// A command run in the sandbox through the portal, which succeeds when it exits with 0.
+    Exec(Vec<String>),
+}
+
 /// The sandbox to run.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Getters, Setters)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Getters, Setters)]
 #[getset(get = "pub with_prefix", set = "pub with_prefix")]
 pub struct Sandbox {
     /// The version of the sandbox.
@@ -235,6 +375,10 @@ pub struct Sandbox {
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub(crate) meta: Option<Meta>,
 
+    /// The labels used to group and select sandboxes, e.g. `tier: web`.
+    #[serde(skip_serializing_if = "HashMap::is_empty", default)]
+    pub(crate) labels: HashMap<String, String>,
+
     /// The image to use. This can be a path to a local rootfs or an OCI image reference.
     pub(crate) image: ReferenceOrPath,
 
@@ -258,6 +402,17 @@ pub struct Sandbox {
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub(crate) envs: Vec<EnvPair>,
 
+    /// The environment files to load, in order. Later files override earlier ones and `envs`
+    /// overrides them all. Accepts a single path or a list of paths.
+    #[serde(
+        skip_serializing_if = "Vec::is_empty",
+        default,
+        serialize_with = "serialize_path_list",
+        deserialize_with = "deserialize_path_list"
+    )]
+    #[schemars(with = "PathList")]
+    pub(crate) env_file: Vec<Utf8UnixPathBuf>,
+
     /// The sandboxes to depend on.
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub(crate) depends_on: Vec<String>,
@@ -269,6 +424,7 @@ pub struct Sandbox {
         serialize_with = "serialize_optional_path",
         deserialize_with = "deserialize_optional_path"
     )]
+    #[schemars(with = "Option<String>")]
     pub(crate) workdir: Option<Utf8UnixPathBuf>,
 
     /// The shell to use.
@@ -280,9 +436,18 @@ pub struct Sandbox {
     pub(crate) scripts: HashMap<String, String>,
 
     /// The command to run. This is a list of command and arguments.
+    ///
+    /// With an `entrypoint`, these are the arguments the entrypoint is run with.
     #[serde(skip_serializing_if = "Vec::is_empty", default)]
     pub(crate) command: Vec<String>,
 
+    /// The program to run in place of the image's entrypoint, with its leading arguments.
+    ///
+    /// Like `docker run --entrypoint`, setting it drops the image's `CMD` as well, so the
+    /// entrypoint runs with `command` as its arguments.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) entrypoint: Vec<String>,
+
     /// The files to import.
     #[serde(
         skip_serializing_if = "HashMap::is_empty",
@@ -290,6 +455,7 @@ pub struct Sandbox {
         serialize_with = "serialize_path_map",
         deserialize_with = "deserialize_path_map"
     )]
+    #[schemars(with = "HashMap<String, String>")]
     pub(crate) imports: HashMap<String, Utf8UnixPathBuf>,
 
     /// The artifacts produced by the sandbox.
@@ -299,11 +465,47 @@ pub struct Sandbox {
         serialize_with = "serialize_path_map",
         deserialize_with = "deserialize_path_map"
     )]
+    #[schemars(with = "HashMap<String, String>")]
     pub(crate) exports: HashMap<String, Utf8UnixPathBuf>,
 
     /// The network scope for the sandbox.
-    #[serde(default)]
-    pub(crate) scope: NetworkScope,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) scope: Option<NetworkScope>,
+
+    /// How the sandbox is connected to the network.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) network: Option<NetworkMode>,
+
+    /// Where the sandbox gets its DNS resolvers from when its image doesn't configure any.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) dns: Option<DnsMode>,
+
+    /// How the root filesystem of the sandbox is put together.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) rootfs_mode: Option<RootfsMode>,
+
+    /// The probe that has to succeed before the sandbox is reported as up.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub(crate) readiness: Option<ReadinessProbe>,
+
+    /// Whether to capture the guest console output to a log file, for debugging boots.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub(crate) console_log: bool,
+
+    /// Extra arguments to append to the guest kernel command line, e.g. `loglevel=7`.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub(crate) kernel_args: Vec<String>,
+}
+
+/// A single path or a list of paths.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum PathList {
+    /// A single path.
+    One(String),
+
+    /// A list of paths.
+    Many(Vec<String>),
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -340,6 +542,30 @@ impl Microsandbox {
     pub fn builder() -> MicrosandboxBuilder {
         MicrosandboxBuilder::default()
     }
+
+    /// Layers another configuration on top of this one.
+    ///
+    /// Sandboxes with the same name are merged with [`Sandbox::merge`], and sandboxes only in
+    /// `other` are added. Modules and builds with the same name are replaced as a whole, and
+    /// `meta` is replaced if `other` has one.
+    pub fn merge(mut self, other: Microsandbox) -> Microsandbox {
+        if other.meta.is_some() {
+            self.meta = other.meta;
+        }
+
+        self.modules.extend(other.modules);
+        self.builds.extend(other.builds);
+
+        for (name, sandbox) in other.sandboxes {
+            let merged = match self.sandboxes.remove(&name) {
+                Some(base) => base.merge(sandbox),
+                None => sandbox,
+            };
+            self.sandboxes.insert(name, merged);
+        }
+
+        self
+    }
 }
 
 impl Sandbox {
@@ -360,6 +586,242 @@ impl Sandbox {
             return Err(MicrosandboxError::MissingStartOrExecOrShell);
         }
 
+        if let Some(readiness) = &self.readiness {
+            readiness.validate(&self.ports)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the memory in MiB the sandbox runs with.
+    ///
+    /// This is `memory` when it's set, then `MSB_DEFAULT_MEMORY_MIB`, then the built-in
+    /// [`DEFAULT_MEMORY_MIB`](microsandbox_utils::DEFAULT_MEMORY_MIB).
+    pub fn memory_or_default(&self) -> u32 {
+        self.memory.unwrap_or_else(env::get_default_memory_mib)
+    }
+
+    /// Returns the number of vCPUs the sandbox runs with.
+    ///
+    /// This is `cpus` when it's set, then `MSB_DEFAULT_CPUS`, then the built-in
+    /// [`DEFAULT_NUM_VCPUS`](microsandbox_utils::DEFAULT_NUM_VCPUS).
+    pub fn cpus_or_default(&self) -> u8 {
+        self.cpus.unwrap_or_else(env::get_default_num_vcpus)
+    }
+
+    /// Returns where the sandbox gets its DNS resolvers from.
+    ///
+    /// This is `dns` when it's set, then `MSB_DEFAULT_DNS`, then [`DnsMode::Public`].
+    pub fn dns_or_default(&self) -> DnsMode {
+        self.dns.unwrap_or_else(DnsMode::from_env)
+    }
+
+    /// Returns the network scope of the sandbox.
+    ///
+    /// This is `scope` when it's set, then [`NetworkScope::Public`].
+    pub fn scope_or_default(&self) -> NetworkScope {
+        self.scope.unwrap_or_default()
+    }
+
+    /// Returns how the sandbox is connected to the network.
+    ///
+    /// This is `network` when it's set, then [`NetworkMode::Host`].
+    pub fn network_or_default(&self) -> NetworkMode {
+        self.network.unwrap_or_default()
+    }
+
+    /// Returns how the root filesystem of the sandbox is put together.
+    ///
+    /// This is `rootfs_mode` when it's set, then [`RootfsMode::Auto`].
+    pub fn rootfs_mode_or_default(&self) -> RootfsMode {
+        self.rootfs_mode.unwrap_or_default()
+    }
+
+    /// Layers another sandbox definition on top of this one.
+    ///
+    /// The rules are:
+    /// - Scalar fields (`image`, `memory`, `cpus`, `workdir`, `shell`, `scope`, `network`,
+    ///   `rootfs_mode`, ...) are overridden when set in `other`, even to their default value.
+    /// - `console_log` is enabled when enabled in either sandbox.
+    /// - List fields (`volumes`, `ports`, `envs`, `env_file`, `depends_on`, `command`,
+    ///   `entrypoint`, `kernel_args`) are replaced as a whole when non-empty in `other`, never appended to.
+    /// - Map fields (`labels`, `scripts`, `imports`, `exports`) are merged key by key, with entries from
+    ///   `other` taking precedence.
+    pub fn merge(mut self, other: Sandbox) -> Sandbox {
+        fn replace_if_set<T>(base: &mut Option<T>, other: Option<T>) {
+            if other.is_some() {
+                *base = other;
+            }
+        }
+
+        fn replace_if_non_empty<T>(base: &mut Vec<T>, other: Vec<T>) {
+            if !other.is_empty() {
+                *base = other;
+            }
+        }
+
+        // Destructure so that new fields can't be forgotten here
+        let Sandbox {
+            version,
+            meta,
+            labels,
+            image,
+            memory,
+            cpus,
+            volumes,
+            ports,
+            envs,
+            env_file,
+            depends_on,
+            workdir,
+            shell,
+            scripts,
+            command,
+            entrypoint,
+            imports,
+            exports,
+            scope,
+            network,
+            dns,
+            rootfs_mode,
+            readiness,
+            console_log,
+            kernel_args,
+        } = other;
+
+        replace_if_set(&mut self.version, version);
+        replace_if_set(&mut self.meta, meta);
+        self.labels.extend(labels);
+        self.image = image;
+        replace_if_set(&mut self.memory, memory);
+        replace_if_set(&mut self.cpus, cpus);
+        replace_if_non_empty(&mut self.volumes, volumes);
+        replace_if_non_empty(&mut self.ports, ports);
+        replace_if_non_empty(&mut self.envs, envs);
+        replace_if_non_empty(&mut self.env_file, env_file);
+        replace_if_non_empty(&mut self.depends_on, depends_on);
+        replace_if_set(&mut self.workdir, workdir);
+        replace_if_set(&mut self.shell, shell);
+        self.scripts.extend(scripts);
+        replace_if_non_empty(&mut self.command, command);
+        replace_if_non_empty(&mut self.entrypoint, entrypoint);
+        self.imports.extend(imports);
+        self.exports.extend(exports);
+        replace_if_set(&mut self.scope, scope);
+        replace_if_set(&mut self.network, network);
+        replace_if_set(&mut self.dns, dns);
+        replace_if_set(&mut self.rootfs_mode, rootfs_mode);
+        replace_if_set(&mut self.readiness, readiness);
+        self.console_log |= console_log;
+        replace_if_non_empty(&mut self.kernel_args, kernel_args);
+
+        self
+    }
+
+    /// Resolves the environment variables of the sandbox.
+    ///
+    /// Env files are loaded in order relative to `project_dir`, then `envs` are applied on top.
+    pub async fn resolve_envs(&self, project_dir: &Path) -> MicrosandboxResult<Vec<EnvPair>> {
+        load_env_files(project_dir, &self.env_file, &self.envs).await
+    }
+}
+
+impl NetworkMode {
+    /// Returns whether guest sockets are carried over the host's network stack, the default.
+    pub fn is_host(&self) -> bool {
+        *self == NetworkMode::Host
+    }
+}
+
+impl DnsMode {
+    /// Reads the mode from the `MSB_DEFAULT_DNS` environment variable.
+    ///
+    /// Returns [`DnsMode::Inherit`] if it is set to `inherit`, and the default of
+    /// [`DnsMode::Public`] otherwise.
+    pub fn from_env() -> Self {
+        match std::env::var(DEFAULT_DNS_ENV_VAR) {
+            Ok(value) if value.trim().eq_ignore_ascii_case("inherit") => Self::Inherit,
+            _ => Self::Public,
+        }
+    }
+}
+
+impl RootfsMode {
+    /// Returns whether the mode is left for the image to decide.
+    pub fn is_auto(&self) -> bool {
+        *self == RootfsMode::Auto
+    }
+
+    /// Picks the mode a sandbox running `image` uses.
+    ///
+    /// This is never [`RootfsMode::Auto`]: it resolves to [`RootfsMode::Overlay`] for OCI images,
+    /// which have layers to compose, and to [`RootfsMode::Native`] for a rootfs directory.
+    pub fn select(self, image: &ReferenceOrPath) -> RootfsMode {
+        match (self, image) {
+            (RootfsMode::Auto, ReferenceOrPath::Reference(_)) => RootfsMode::Overlay,
+            (RootfsMode::Auto, ReferenceOrPath::Path(_)) => RootfsMode::Native,
+            (mode, _) => mode,
+        }
+    }
+}
+
+impl ReadinessProbe {
+    /// Creates a readiness probe with the default interval and timeout.
+    pub fn new(check: ReadinessCheck) -> Self {
+        Self {
+            check,
+            interval: DEFAULT_READINESS_INTERVAL_SECS,
+            timeout: DEFAULT_READINESS_TIMEOUT_SECS,
+        }
+    }
+
+    /// Sets the number of seconds to wait between attempts.
+    pub fn with_interval(mut self, interval: u64) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the number of seconds the probe has to succeed within.
+    pub fn with_timeout(mut self, timeout: u64) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The time to wait between attempts.
+    pub fn interval_duration(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    /// The time the probe has to succeed within.
+    pub fn timeout_duration(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    /// Validates the probe against the ports the sandbox exposes.
+    pub fn validate(&self, ports: &[PortPair]) -> MicrosandboxResult<()> {
+        if self.interval == 0 || self.timeout == 0 {
+            return Err(MicrosandboxError::InvalidReadinessProbe(
+                "interval and timeout must be at least one second".to_string(),
+            ));
+        }
+
+        match &self.check {
+            ReadinessCheck::Http { port, .. } => {
+                if !ports.iter().any(|p| p.get_guest() == *port) {
+                    return Err(MicrosandboxError::InvalidReadinessProbe(format!(
+                        "guest port {port} is not exposed in ports"
+                    )));
+                }
+            }
+            ReadinessCheck::Exec(command) => {
+                if command.is_empty() {
+                    return Err(MicrosandboxError::InvalidReadinessProbe(
+                        "exec command is empty".to_string(),
+                    ));
+                }
+            }
+        }
+
         Ok(())
     }
 }
@@ -423,10 +885,113 @@ impl TryFrom<u8> for NetworkScope {
     }
 }
 
+impl TryFrom<&str> for NetworkMode {
+    type Error = MicrosandboxError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(NetworkMode::None),
+            "host" => Ok(NetworkMode::Host),
+            "nat" => Ok(NetworkMode::Nat),
+            _ => Err(MicrosandboxError::InvalidNetworkMode(s.to_string())),
+        }
+    }
+}
+
+impl Display for NetworkMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::Nat => write!(f, "nat"),
+        }
+    }
+}
+
+impl FromStr for NetworkMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(NetworkMode::try_from(s)?)
+    }
+}
+
+impl TryFrom<&str> for DnsMode {
+    type Error = MicrosandboxError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "public" => Ok(DnsMode::Public),
+            "inherit" => Ok(DnsMode::Inherit),
+            _ => Err(MicrosandboxError::InvalidDnsMode(s.to_string())),
+        }
+    }
+}
+
+impl Display for DnsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsMode::Public => write!(f, "public"),
+            DnsMode::Inherit => write!(f, "inherit"),
+        }
+    }
+}
+
+impl FromStr for DnsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(DnsMode::try_from(s)?)
+    }
+}
+
+impl TryFrom<&str> for RootfsMode {
+    type Error = MicrosandboxError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        match s.to_lowercase().as_str() {
+            "auto" => Ok(RootfsMode::Auto),
+            "overlay" => Ok(RootfsMode::Overlay),
+            "native" => Ok(RootfsMode::Native),
+            _ => Err(MicrosandboxError::InvalidRootfsMode(s.to_string())),
+        }
+    }
+}
+
+impl Display for RootfsMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RootfsMode::Auto => write!(f, "auto"),
+            RootfsMode::Overlay => write!(f, "overlay"),
+            RootfsMode::Native => write!(f, "native"),
+        }
+    }
+}
+
+impl FromStr for RootfsMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(RootfsMode::try_from(s)?)
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Serialization helpers
 //--------------------------------------------------------------------------------------------------
 
+fn default_readiness_interval() -> u64 {
+    DEFAULT_READINESS_INTERVAL_SECS
+}
+
+fn default_readiness_timeout() -> u64 {
+    DEFAULT_READINESS_TIMEOUT_SECS
+}
+
+fn default_readiness_http_path() -> String {
+    DEFAULT_READINESS_HTTP_PATH.to_string()
+}
+
 fn serialize_optional_path<S>(
     path: &Option<Utf8UnixPathBuf>,
     serializer: S,
@@ -449,6 +1014,28 @@ where
         .transpose()
 }
 
+fn serialize_path_list<S>(paths: &[Utf8UnixPathBuf], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    use serde::ser::SerializeSeq;
+    let mut seq_ser = serializer.serialize_seq(Some(paths.len()))?;
+    for path in paths {
+        seq_ser.serialize_element(path.as_str())?;
+    }
+    seq_ser.end()
+}
+
+fn deserialize_path_list<'de, D>(deserializer: D) -> Result<Vec<Utf8UnixPathBuf>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match PathList::deserialize(deserializer)? {
+        PathList::One(path) => vec![Utf8UnixPathBuf::from(path)],
+        PathList::Many(paths) => paths.into_iter().map(Utf8UnixPathBuf::from).collect(),
+    })
+}
+
 fn serialize_path_map<S>(
     map: &HashMap<String, Utf8UnixPathBuf>,
     serializer: S,
@@ -478,6 +1065,46 @@ where
     })
 }
 
+fn deserialize_sandboxes<'de, D>(deserializer: D) -> Result<HashMap<String, Sandbox>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    HashMap::<String, serde_yaml::Value>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, mut sandbox)| {
+            migrate_legacy_network(&mut sandbox);
+            let sandbox = serde_yaml::from_value(sandbox)
+                .map_err(|e| D::Error::custom(format!("sandbox {name}: {e}")))?;
+            Ok((name, sandbox))
+        })
+        .collect()
+}
+
+/// Moves the scope of a sandbox written by older versions of `msb add --scope`, which nested it
+/// as `network: { scope: ... }`, to the sandbox's own `scope`.
+///
+/// That mapping was ignored before `network` took the network mode, so the sandbox keeps the
+/// default mode. A `scope` set on the sandbox itself takes precedence.
+fn migrate_legacy_network(sandbox: &mut serde_yaml::Value) {
+    let Some(sandbox) = sandbox.as_mapping_mut() else {
+        return;
+    };
+    if !sandbox
+        .get("network")
+        .is_some_and(serde_yaml::Value::is_mapping)
+    {
+        return;
+    }
+
+    let network = sandbox.remove("network");
+    if let Some(scope) = network.as_ref().and_then(|network| network.get("scope"))
+        && !sandbox.contains_key("scope")
+    {
+        sandbox.insert("scope".into(), scope.clone());
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
@@ -523,6 +1150,159 @@ mod tests {
         assert!(config.sandboxes.is_empty());
     }
 
+    #[test]
+    fn test_rootfs_mode_selection() {
+        let image = ReferenceOrPath::from_str("alpine:latest").unwrap();
+        let rootfs_dir = ReferenceOrPath::from_str("./rootfs").unwrap();
+
+        // Auto composes an image's layers and passes a rootfs directory through
+        assert_eq!(RootfsMode::Auto.select(&image), RootfsMode::Overlay);
+        assert_eq!(RootfsMode::Auto.select(&rootfs_dir), RootfsMode::Native);
+
+        // An explicit mode is kept for either
+        for image in [&image, &rootfs_dir] {
+            assert_eq!(RootfsMode::Overlay.select(image), RootfsMode::Overlay);
+            assert_eq!(RootfsMode::Native.select(image), RootfsMode::Native);
+        }
+
+        let yaml = r#"
+            sandboxes:
+              default:
+                image: "alpine:latest"
+              copied:
+                image: "alpine:latest"
+                rootfs_mode: native
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.sandboxes["default"].rootfs_mode.is_none());
+        assert_eq!(
+            config.sandboxes["default"].rootfs_mode_or_default(),
+            RootfsMode::Auto
+        );
+        assert_eq!(
+            config.sandboxes["copied"].rootfs_mode,
+            Some(RootfsMode::Native)
+        );
+        assert_eq!(
+            "Overlay".parse::<RootfsMode>().unwrap(),
+            RootfsMode::Overlay
+        );
+        assert!("merged".parse::<RootfsMode>().is_err());
+
+        // An unset mode is left out when serialized
+        let serialized = serde_yaml::to_string(&config.sandboxes["default"]).unwrap();
+        assert!(!serialized.contains("rootfs_mode"));
+    }
+
+    #[test]
+    fn test_network_mode_parsing() {
+        let yaml = r#"
+            sandboxes:
+              default:
+                image: "alpine:latest"
+              isolated:
+                image: "alpine:latest"
+                network: none
+              translated:
+                image: "alpine:latest"
+                network: nat
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+        assert!(config.sandboxes["default"].network.is_none());
+        assert_eq!(
+            config.sandboxes["default"].network_or_default(),
+            NetworkMode::Host
+        );
+        assert_eq!(
+            config.sandboxes["isolated"].network,
+            Some(NetworkMode::None)
+        );
+        assert_eq!(
+            config.sandboxes["translated"].network,
+            Some(NetworkMode::Nat)
+        );
+        assert_eq!("NONE".parse::<NetworkMode>().unwrap(), NetworkMode::None);
+        assert!("bridge".parse::<NetworkMode>().is_err());
+
+        let yaml = r#"
+            sandboxes:
+              app:
+                image: "alpine:latest"
+                network: bridge
+        "#;
+        assert!(serde_yaml::from_str::<Microsandbox>(yaml).is_err());
+
+        // An unset mode is left out when serialized, and set modes survive a round trip
+        let serialized = serde_yaml::to_string(&config.sandboxes["default"]).unwrap();
+        assert!(!serialized.contains("network"));
+        let serialized = serde_yaml::to_string(&config.sandboxes["isolated"]).unwrap();
+        let sandbox: Sandbox = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(sandbox.network, Some(NetworkMode::None));
+    }
+
+    #[test]
+    fn test_network_legacy_scope_mapping() {
+        // Older versions of `msb add --scope` nested the scope under `network`
+        let yaml = r#"
+            sandboxes:
+              added:
+                image: "alpine:latest"
+                network:
+                  scope: any
+              both:
+                image: "alpine:latest"
+                scope: none
+                network:
+                  scope: any
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+        let added = &config.sandboxes["added"];
+        assert_eq!(added.scope, Some(NetworkScope::Any));
+        assert!(added.network.is_none());
+
+        // The sandbox's own scope wins
+        assert_eq!(config.sandboxes["both"].scope, Some(NetworkScope::None));
+
+        // An invalid legacy scope is still an error
+        let yaml = r#"
+            sandboxes:
+              app:
+                image: "alpine:latest"
+                network:
+                  scope: everywhere
+        "#;
+        assert!(serde_yaml::from_str::<Microsandbox>(yaml).is_err());
+    }
+
+    #[test]
+    fn test_dns_mode_parsing() {
+        let yaml = r#"
+            sandboxes:
+              default:
+                image: "alpine:latest"
+              corporate:
+                image: "alpine:latest"
+                dns: inherit
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(config.sandboxes["default"].dns, None);
+        assert_eq!(config.sandboxes["corporate"].dns, Some(DnsMode::Inherit));
+        assert_eq!(
+            config.sandboxes["corporate"].dns_or_default(),
+            DnsMode::Inherit
+        );
+        assert_eq!("Public".parse::<DnsMode>().unwrap(), DnsMode::Public);
+        assert!("google".parse::<DnsMode>().is_err());
+
+        // An unset mode is left out when serialized
+        let serialized = serde_yaml::to_string(&config.sandboxes["default"]).unwrap();
+        assert!(!serialized.contains("dns"));
+    }
+
     #[test]
     fn test_microsandbox_config_minimal_sandbox_config() {
         let yaml = r#"
@@ -538,13 +1318,15 @@ mod tests {
         assert!(sandbox.version.is_none());
         assert!(sandbox.memory.is_none());
         assert!(sandbox.cpus.is_none());
+        assert_eq!(sandbox.memory_or_default(), env::get_default_memory_mib());
+        assert_eq!(sandbox.cpus_or_default(), env::get_default_num_vcpus());
         assert!(sandbox.volumes.is_empty());
         assert!(sandbox.ports.is_empty());
         assert!(sandbox.envs.is_empty());
         assert!(sandbox.workdir.is_none());
         assert!(sandbox.shell.is_none());
         assert!(sandbox.scripts.is_empty());
-        assert_eq!(sandbox.scope, NetworkScope::Public);
+        assert_eq!(sandbox.scope_or_default(), NetworkScope::Public);
     }
 
     #[test]
@@ -554,7 +1336,7 @@ mod tests {
             .image(ReferenceOrPath::Reference("alpine:latest".parse().unwrap()))
             .shell("/bin/sh")
             .build();
-        assert_eq!(sandbox.scope, NetworkScope::Public);
+        assert_eq!(sandbox.scope_or_default(), NetworkScope::Public);
 
         // Test default scope in YAML
         let yaml = r#"
@@ -568,7 +1350,191 @@ mod tests {
         let sandboxes = &config.sandboxes;
         let sandbox = sandboxes.get("test").unwrap();
 
-        assert_eq!(sandbox.scope, NetworkScope::Public);
+        assert_eq!(sandbox.scope_or_default(), NetworkScope::Public);
+    }
+
+    #[test]
+    fn test_microsandbox_config_merge_overrides_scalars() {
+        let base: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "python:3.11"
+                memory: 1024
+                cpus: 2
+                workdir: "/app"
+                scripts:
+                  start: "python app.py"
+                  test: "pytest"
+        "#,
+        )
+        .unwrap();
+        let overlay: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "python:3.12"
+                memory: 4096
+                scope: "any"
+                scripts:
+                  start: "gunicorn app:app"
+        "#,
+        )
+        .unwrap();
+
+        let merged = base.merge(overlay);
+        let app = merged.get_sandbox("app").unwrap();
+
+        assert_eq!(app.image, "python:3.12".parse::<ReferenceOrPath>().unwrap());
+        assert_eq!(app.memory, Some(4096));
+        assert_eq!(app.cpus, Some(2));
+        assert_eq!(app.workdir, Some(Utf8UnixPathBuf::from("/app")));
+        assert_eq!(app.scope, Some(NetworkScope::Any));
+        assert_eq!(app.scripts.get("start").unwrap(), "gunicorn app:app");
+        assert_eq!(app.scripts.get("test").unwrap(), "pytest");
+    }
+
+    #[test]
+    fn test_microsandbox_config_merge_overrides_back_to_defaults() {
+        let base: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "python:3.11"
+                scope: "any"
+                network: none
+                rootfs_mode: native
+        "#,
+        )
+        .unwrap();
+        let overlay: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "python:3.11"
+                scope: "public"
+                network: host
+                rootfs_mode: auto
+        "#,
+        )
+        .unwrap();
+        let unset: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "python:3.11"
+        "#,
+        )
+        .unwrap();
+
+        // Setting the default value overrides, leaving it unset doesn't
+        let merged = base.clone().merge(overlay);
+        let app = merged.get_sandbox("app").unwrap();
+        assert_eq!(app.scope, Some(NetworkScope::Public));
+        assert_eq!(app.network, Some(NetworkMode::Host));
+        assert_eq!(app.rootfs_mode, Some(RootfsMode::Auto));
+
+        let merged = base.merge(unset);
+        let app = merged.get_sandbox("app").unwrap();
+        assert_eq!(app.scope, Some(NetworkScope::Any));
+        assert_eq!(app.network, Some(NetworkMode::None));
+        assert_eq!(app.rootfs_mode, Some(RootfsMode::Native));
+    }
+
+    #[test]
+    fn test_microsandbox_config_merge_adds_new_sandboxes() {
+        let base: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "alpine"
+        "#,
+        )
+        .unwrap();
+        let overlay: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              db:
+                image: "postgres:16"
+        "#,
+        )
+        .unwrap();
+
+        let merged = base.merge(overlay);
+        assert_eq!(merged.sandboxes.len(), 2);
+        assert!(merged.get_sandbox("app").is_some());
+        assert!(merged.get_sandbox("db").is_some());
+    }
+
+    #[test]
+    fn test_microsandbox_config_merge_replaces_lists() {
+        let base: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "alpine"
+                ports:
+                  - "8080:80"
+                  - "8443:443"
+                envs:
+                  - "DEBUG=true"
+                depends_on:
+                  - db
+        "#,
+        )
+        .unwrap();
+        let overlay: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              app:
+                image: "alpine"
+                ports:
+                  - "9090:80"
+        "#,
+        )
+        .unwrap();
+
+        let merged = base.merge(overlay);
+        let app = merged.get_sandbox("app").unwrap();
+
+        // Lists set in the overlay replace the base list, while unset lists are kept
+        assert_eq!(app.ports, vec!["9090:80".parse().unwrap()]);
+        assert_eq!(app.envs, vec![EnvPair::new("DEBUG", "true")]);
+        assert_eq!(app.depends_on, vec!["db".to_string()]);
+    }
+
+    #[test]
+    fn test_microsandbox_config_env_file_single_or_list() {
+        let yaml = r#"
+            sandboxes:
+              single:
+                image: "alpine:latest"
+                env_file: ".env"
+              layered:
+                image: "alpine:latest"
+                env_file:
+                  - "base.env"
+                  - "override.env"
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+
+        let single = config.get_sandbox("single").unwrap();
+        assert_eq!(single.env_file, vec![Utf8UnixPathBuf::from(".env")]);
+
+        let layered = config.get_sandbox("layered").unwrap();
+        assert_eq!(
+            layered.env_file,
+            vec![
+                Utf8UnixPathBuf::from("base.env"),
+                Utf8UnixPathBuf::from("override.env")
+            ]
+        );
+
+        // Lists are written back out as lists
+        let serialized = serde_yaml::to_string(layered).unwrap();
+        let reparsed: Sandbox = serde_yaml::from_str(&serialized).unwrap();
+        assert_eq!(reparsed.env_file, layered.env_file);
     }
 
     #[test]
@@ -744,7 +1710,7 @@ mod tests {
         assert_eq!(api.memory.unwrap(), 1024);
         assert_eq!(api.cpus.unwrap(), 1);
         assert_eq!(api.depends_on, vec!["database", "cache"]);
-        assert_eq!(api.scope, NetworkScope::Public);
+        assert_eq!(api.scope, Some(NetworkScope::Public));
     }
 
     #[test]
@@ -795,4 +1761,80 @@ mod tests {
         "#;
         assert!(serde_yaml::from_str::<Microsandbox>(yaml).is_err());
     }
+
+    #[test]
+    fn test_microsandbox_config_readiness_probe() {
+        let yaml = r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+                shell: "/bin/sh"
+                ports:
+                  - "8080:80"
+                readiness:
+                  http:
+                    port: 80
+                    path: /health
+                  timeout: 30
+              db:
+                image: "postgres:latest"
+                shell: "/bin/sh"
+                readiness:
+                  exec: ["pg_isready", "-U", "postgres"]
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+        config.validate().unwrap();
+
+        let web = config
+            .sandboxes
+            .get("web")
+            .unwrap()
+            .readiness
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            web.check,
+            ReadinessCheck::Http {
+                port: 80,
+                path: "/health".to_string()
+            }
+        );
+        assert_eq!(web.interval, DEFAULT_READINESS_INTERVAL_SECS);
+        assert_eq!(web.timeout, 30);
+
+        let db = config
+            .sandboxes
+            .get("db")
+            .unwrap()
+            .readiness
+            .as_ref()
+            .unwrap();
+        assert_eq!(
+            db.check,
+            ReadinessCheck::Exec(vec![
+                "pg_isready".to_string(),
+                "-U".to_string(),
+                "postgres".to_string()
+            ])
+        );
+        assert_eq!(db.timeout, DEFAULT_READINESS_TIMEOUT_SECS);
+
+        // Probing a port that isn't exposed is rejected
+        let yaml = r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+                shell: "/bin/sh"
+                readiness:
+                  http:
+                    port: 80
+        "#;
+
+        let config: Microsandbox = serde_yaml::from_str(yaml).unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(MicrosandboxError::InvalidReadinessProbe(_))
+        ));
+    }
 }
@@ -1,19 +1,29 @@
 //! Configuration types and helpers.
 
+mod env_file;
 mod env_pair;
+mod label_selector;
 mod microsandbox;
 mod path_pair;
 mod path_segment;
 mod port_pair;
 mod reference_path;
+mod schema;
+mod secret;
+mod validation;
 
 //--------------------------------------------------------------------------------------------------
 // Exports
 //--------------------------------------------------------------------------------------------------
 
+pub use env_file::*;
 pub use env_pair::*;
+pub use label_selector::*;
 pub use microsandbox::*;
 pub use path_pair::*;
 pub use path_segment::*;
 pub use port_pair::*;
 pub use reference_path::*;
+pub use schema::*;
+pub use secret::*;
+pub use validation::*;
@@ -1,5 +1,6 @@
-use std::{fmt, str::FromStr};
+use std::{borrow::Cow, fmt, str::FromStr};
 
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use typed_path::Utf8UnixPathBuf;
 
@@ -144,6 +145,20 @@ impl<'de> Deserialize<'de> for PathPair {
     }
 }
 
+impl JsonSchema for PathPair {
+    fn schema_name() -> Cow<'static, str> {
+        "PathPair".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "A volume mapping in the format `HOST:GUEST`, or a single path mounted at the same location.",
+            "pattern": "^[^:]+(:[^:]+)?$",
+        })
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
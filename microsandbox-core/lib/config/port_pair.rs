@@ -1,5 +1,6 @@
-use std::{fmt, str::FromStr};
+use std::{borrow::Cow, fmt, str::FromStr};
 
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
 use serde::{Deserialize, Serialize};
 
 use crate::MicrosandboxError;
@@ -148,6 +149,20 @@ impl<'de> Deserialize<'de> for PortPair {
     }
 }
 
+impl JsonSchema for PortPair {
+    fn schema_name() -> Cow<'static, str> {
+        "PortPair".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "A port mapping in the format `HOST:GUEST`, or a single port mapped to itself.",
+            "pattern": "^[0-9]+(:[0-9]+)?$",
+        })
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
@@ -1,9 +1,12 @@
 use std::{
+    borrow::Cow,
     fmt::{self, Display},
     path::PathBuf,
     str::FromStr,
 };
 
+use schemars::{JsonSchema, Schema, SchemaGenerator, json_schema};
+
 use crate::{MicrosandboxError, oci::Reference};
 
 //--------------------------------------------------------------------------------------------------
@@ -89,6 +92,19 @@ impl From<ReferenceOrPath> for String {
     }
 }
 
+impl JsonSchema for ReferenceOrPath {
+    fn schema_name() -> Cow<'static, str> {
+        "ReferenceOrPath".into()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        json_schema!({
+            "type": "string",
+            "description": "An OCI image reference, or a path to a local rootfs starting with `.` or `/`.",
+        })
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
@@ -0,0 +1,100 @@
+//! JSON Schema for Microsandbox configuration files.
+//!
+//! The schema is derived from the same types the configuration is deserialized into, so editors
+//! and external validators always see the fields the CLI actually accepts.
+
+use schemars::schema_for;
+use serde_json::Value;
+
+use super::Microsandbox;
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Generates the JSON Schema for a Microsandbox configuration file.
+pub fn json_schema() -> Value {
+    schema_for!(Microsandbox).to_value()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use microsandbox_utils::DEFAULT_CONFIG;
+
+    use super::*;
+
+    fn yaml_to_json(yaml: &str) -> Value {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_schema_accepts_valid_configs() {
+        let validator = jsonschema::validator_for(&json_schema()).unwrap();
+
+        assert!(validator.is_valid(&yaml_to_json(DEFAULT_CONFIG)));
+
+        let config = r#"
+            meta:
+              description: "A test project"
+            sandboxes:
+              app:
+                version: "1.0.0"
+                image: "python:3.11"
+                memory: 1024
+                cpus: 2
+                volumes:
+                  - "./src:/app/src"
+                ports:
+                  - "8080:80"
+                envs:
+                  - "DEBUG=true"
+                env_file:
+                  - "base.env"
+                  - "override.env"
+                depends_on:
+                  - db
+                workdir: "/app"
+                scripts:
+                  start: "python app.py"
+                scope: "public"
+              db:
+                image: "postgres:16"
+                env_file: ".env"
+        "#;
+        assert!(validator.is_valid(&yaml_to_json(config)));
+    }
+
+    #[test]
+    fn test_schema_rejects_invalid_configs() {
+        let validator = jsonschema::validator_for(&json_schema()).unwrap();
+
+        // Sandboxes must have an image
+        let missing_image = r#"
+            sandboxes:
+              app:
+                shell: "/bin/sh"
+        "#;
+        assert!(!validator.is_valid(&yaml_to_json(missing_image)));
+
+        let invalid_scope = r#"
+            sandboxes:
+              app:
+                image: "alpine"
+                scope: "everywhere"
+        "#;
+        assert!(!validator.is_valid(&yaml_to_json(invalid_scope)));
+
+        let invalid_port = r#"
+            sandboxes:
+              app:
+                image: "alpine"
+                ports:
+                  - "http"
+        "#;
+        assert!(!validator.is_valid(&yaml_to_json(invalid_port)));
+    }
+}
@@ -0,0 +1,190 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::{MicrosandboxError, MicrosandboxResult};
+
+use super::{EnvPair, parse_env_file};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The scheme that marks an environment variable value as a reference to a secret.
+pub const SECRET_SCHEME: &str = "secret://";
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Secrets that `secret://name` environment variable values are resolved against.
+///
+/// Configurations only ever store the references. The values are looked up when a sandbox
+/// starts, right before the environment is handed to the guest, so they are never written to
+/// the configuration file or the sandbox database.
+///
+/// ## Examples
+///
+/// ```
+/// use microsandbox_core::config::{EnvPair, SecretStore};
+///
+/// let store = SecretStore::from_secrets([("api_token", "s3cr3t")]);
+/// let envs = store
+///     .resolve_envs(vec![EnvPair::new("TOKEN", "secret://api_token")])
+///     .unwrap();
+///
+/// assert_eq!(envs[0].get_value(), "s3cr3t");
+/// ```
+#[derive(Default, Clone)]
+pub struct SecretStore {
+    /// The secret values by name.
+    secrets: HashMap<String, String>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl SecretStore {
+    /// Creates a secret store from name and value pairs.
+    pub fn from_secrets(
+        secrets: impl IntoIterator<Item = (impl Into<String>, impl Into<String>)>,
+    ) -> Self {
+        Self {
+            secrets: secrets
+                .into_iter()
+                .map(|(name, value)| (name.into(), value.into()))
+                .collect(),
+        }
+    }
+
+    /// Loads secrets from a file in env file format, with one `NAME=VALUE` pair per line.
+    ///
+    /// A missing file is treated as an empty store, so references only fail once they are
+    /// actually resolved.
+    pub async fn load(path: &Path) -> MicrosandboxResult<Self> {
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let secrets = parse_env_file(&contents)?
+            .into_iter()
+            .map(|env| (env.get_name().clone(), env.get_value().clone()));
+
+        Ok(Self::from_secrets(secrets))
+    }
+
+    /// Replaces every `secret://name` value with the secret it refers to.
+    ///
+    /// Values without the scheme are passed through unchanged.
+    pub fn resolve_envs(&self, envs: Vec<EnvPair>) -> MicrosandboxResult<Vec<EnvPair>> {
+        envs.into_iter()
+            .map(|env| match secret_name(env.get_value()) {
+                Some(name) => self
+                    .secrets
+                    .get(name)
+                    .map(|value| EnvPair::new(env.get_name().clone(), value.clone()))
+                    .ok_or_else(|| {
+                        MicrosandboxError::SecretNotFound(name.to_string(), env.get_name().clone())
+                    }),
+                None => Ok(env),
+            })
+            .collect()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Returns whether any environment variable refers to a secret.
+pub fn has_secret_references(envs: &[EnvPair]) -> bool {
+    envs.iter()
+        .any(|env| secret_name(env.get_value()).is_some())
+}
+
+/// Returns the name of the secret a value refers to, if it is a secret reference.
+fn secret_name(value: &str) -> Option<&str> {
+    value.strip_prefix(SECRET_SCHEME)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl std::fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Only show the names so secret values never end up in logs
+        f.debug_struct("SecretStore")
+            .field("secrets", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::config::{ReferenceOrPath, Sandbox};
+
+    fn sandbox_with_secret() -> Sandbox {
+        Sandbox::builder()
+            .image(ReferenceOrPath::Reference("alpine:latest".parse().unwrap()))
+            .envs([
+                EnvPair::new("MODE", "prod"),
+                EnvPair::new("API_TOKEN", "secret://api_token"),
+            ])
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_secret_reference_resolves() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let secrets_path = dir.path().join("secrets.env");
+        tokio::fs::write(&secrets_path, "api_token=tok_plaintext_123\n").await?;
+
+        let sandbox = sandbox_with_secret();
+        let store = SecretStore::load(&secrets_path).await?;
+        let envs = store.resolve_envs(sandbox.get_envs().clone())?;
+
+        assert_eq!(
+            envs,
+            vec![
+                EnvPair::new("MODE", "prod"),
+                EnvPair::new("API_TOKEN", "tok_plaintext_123"),
+            ]
+        );
+
+        // The stored configuration only ever contains the reference
+        let serialized = serde_yaml::to_string(&sandbox)?;
+        assert!(serialized.contains("secret://api_token"));
+        assert!(!serialized.contains("tok_plaintext_123"));
+        assert!(!format!("{:?}", store).contains("tok_plaintext_123"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_secret_reference_unresolvable() -> anyhow::Result<()> {
+        let dir = TempDir::new()?;
+        let store = SecretStore::load(&dir.path().join("missing.env")).await?;
+
+        let sandbox = sandbox_with_secret();
+        assert!(has_secret_references(sandbox.get_envs()));
+
+        match store.resolve_envs(sandbox.get_envs().clone()) {
+            Err(MicrosandboxError::SecretNotFound(name, env)) => {
+                assert_eq!(name, "api_token");
+                assert_eq!(env, "API_TOKEN");
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        Ok(())
+    }
+}
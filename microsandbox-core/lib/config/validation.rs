@@ -0,0 +1,832 @@
+//! Up-front validation of Microsandbox configuration files.
+//!
+//! Loading a configuration stops at the first field that fails to deserialize, and problems such
+//! as missing dependencies only surface once a sandbox is started. The functions here walk the
+//! raw YAML instead so every problem in the file can be reported at once, each with the path of
+//! the field it was found at.
+
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use getset::Getters;
+use microsandbox_utils::RESOURCE_CHECK_ENV_VAR;
+use serde_yaml::{Mapping, Value};
+
+use crate::{MicrosandboxError, MicrosandboxResult};
+
+use super::{
+    DnsMode, EnvPair, Microsandbox, NetworkMode, NetworkScope, PathPair, PortPair, ReferenceOrPath,
+    Sandbox,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// A single problem found while validating a configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct ConfigDiagnostic {
+    /// The path of the field the problem was found at, e.g. `sandboxes.app.ports[0]`.
+    path: String,
+
+    /// A description of the problem.
+    message: String,
+}
+
+/// The resources available on the host, used to bound `memory` and `cpus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct HostLimits {
+    /// The total memory of the host in MiB.
+    memory_mib: u64,
+
+    /// The number of CPUs available on the host.
+    cpus: u64,
+}
+
+/// How sandboxes that need more resources than the host has are handled when they are started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResourceCheckMode {
+    /// Log a warning and start the sandboxes anyway.
+    #[default]
+    Warn,
+
+    /// Refuse to start the sandboxes.
+    Error,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl ConfigDiagnostic {
+    /// Creates a new diagnostic for the field at `path`.
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl HostLimits {
+    /// Creates host limits from explicit values.
+    pub fn new(memory_mib: u64, cpus: u64) -> Self {
+        Self { memory_mib, cpus }
+    }
+
+    /// Detects the resources of the current host.
+    pub fn detect() -> Self {
+        let cpus = std::thread::available_parallelism()
+            .map(|n| n.get() as u64)
+            .unwrap_or(1);
+
+        // SAFETY: sysconf only reads system configuration values
+        let (pages, page_size) = unsafe {
+            (
+                libc::sysconf(libc::_SC_PHYS_PAGES),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        let memory_mib = if pages > 0 && page_size > 0 {
+            (pages as u64 * page_size as u64) / (1024 * 1024)
+        } else {
+            u64::MAX
+        };
+
+        Self { memory_mib, cpus }
+    }
+}
+
+impl ResourceCheckMode {
+    /// Reads the mode from the `MSB_RESOURCE_CHECK` environment variable.
+    ///
+    /// Returns [`ResourceCheckMode::Error`] if it is set to `error`, and the default of
+    /// [`ResourceCheckMode::Warn`] otherwise.
+    pub fn from_env() -> Self {
+        match std::env::var(RESOURCE_CHECK_ENV_VAR) {
+            Ok(value) if value.trim().eq_ignore_ascii_case("error") => Self::Error,
+            _ => Self::Warn,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Checks that the given sandboxes fit within the host's resources.
+///
+/// Each sandbox's `memory` and `cpus` must not exceed the host's total, and neither may their sum
+/// across all the sandboxes. Sandboxes that don't set `memory` or `cpus` are counted with the
+/// defaults they will boot with.
+///
+/// ## Arguments
+///
+/// * `sandboxes` - The names and configurations of the sandboxes about to be started
+/// * `limits` - The resources available on the host
+/// * `mode` - Whether exceeding the host's resources is a warning or an error
+///
+/// ## Returns
+///
+/// The problems found, which have already been logged as warnings, or a
+/// [`MicrosandboxError::InsufficientHostResources`] listing them if `mode` is
+/// [`ResourceCheckMode::Error`].
+pub fn check_host_capacity<'a>(
+    sandboxes: impl IntoIterator<Item = (&'a str, &'a Sandbox)>,
+    limits: &HostLimits,
+    mode: ResourceCheckMode,
+) -> MicrosandboxResult<Vec<ConfigDiagnostic>> {
+    let mut diagnostics = Vec::new();
+    let mut total_memory_mib = 0;
+    let mut total_cpus = 0;
+    let mut count = 0;
+
+    for (name, sandbox) in sandboxes {
+        let memory_mib = sandbox.memory_or_default() as u64;
+        let cpus = sandbox.cpus_or_default() as u64;
+
+        if memory_mib > limits.memory_mib {
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("sandboxes.{}.memory", name),
+                format!(
+                    "{} MiB exceeds the host's {} MiB of memory",
+                    memory_mib, limits.memory_mib
+                ),
+            ));
+        }
+        if cpus > limits.cpus {
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("sandboxes.{}.cpus", name),
+                format!("{} exceeds the host's {} CPUs", cpus, limits.cpus),
+            ));
+        }
+
+        total_memory_mib += memory_mib;
+        total_cpus += cpus;
+        count += 1;
+    }
+
+    // A single sandbox over the limit has already been reported above
+    if count > 1 {
+        if total_memory_mib > limits.memory_mib {
+            diagnostics.push(ConfigDiagnostic::new(
+                "sandboxes",
+                format!(
+                    "{} sandboxes need {} MiB of memory in total, but the host has {} MiB",
+                    count, total_memory_mib, limits.memory_mib
+                ),
+            ));
+        }
+        if total_cpus > limits.cpus {
+            diagnostics.push(ConfigDiagnostic::new(
+                "sandboxes",
+                format!(
+                    "{} sandboxes need {} CPUs in total, but the host has {}",
+                    count, total_cpus, limits.cpus
+                ),
+            ));
+        }
+    }
+
+    if diagnostics.is_empty() {
+        return Ok(diagnostics);
+    }
+
+    match mode {
+        ResourceCheckMode::Warn => {
+            for diagnostic in &diagnostics {
+                tracing::warn!("{}", diagnostic);
+            }
+            Ok(diagnostics)
+        }
+        ResourceCheckMode::Error => Err(MicrosandboxError::InsufficientHostResources(
+            diagnostics
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+        )),
+    }
+}
+
+/// Validates a Microsandbox configuration against the resources of the current host.
+///
+/// See [`validate_with_limits`] for the checks that are performed.
+pub fn validate(config: &str) -> Vec<ConfigDiagnostic> {
+    validate_with_limits(config, &HostLimits::detect())
+}
+
+/// Validates a Microsandbox configuration and collects every problem found.
+///
+/// For each sandbox, this checks that:
+/// - the image is a valid image reference or rootfs path
+/// - `memory` and `cpus` are non-zero and within the host's limits
+/// - every volume, port and environment variable parses
+/// - every `depends_on` target exists and dependencies contain no cycles
+/// - script names are valid
+///
+/// ## Arguments
+///
+/// * `config` - The contents of the configuration file
+/// * `limits` - The host resources that `memory` and `cpus` must fit within
+///
+/// ## Returns
+///
+/// The problems found, in the order they appear in the file. An empty list means the
+/// configuration is valid.
+pub fn validate_with_limits(config: &str, limits: &HostLimits) -> Vec<ConfigDiagnostic> {
+    let mut diagnostics = Vec::new();
+
+    let root: Value = match serde_yaml::from_str(config) {
+        Ok(root) => root,
+        Err(e) => {
+            diagnostics.push(ConfigDiagnostic::new("", format!("invalid YAML: {}", e)));
+            return diagnostics;
+        }
+    };
+
+    let sandboxes = match &root {
+        Value::Null => return diagnostics,
+        Value::Mapping(root) => match root.get("sandboxes") {
+            None | Some(Value::Null) => None,
+            Some(Value::Mapping(sandboxes)) => Some(sandboxes),
+            Some(_) => {
+                diagnostics.push(ConfigDiagnostic::new("sandboxes", "expected a mapping"));
+                None
+            }
+        },
+        _ => {
+            diagnostics.push(ConfigDiagnostic::new("", "expected a mapping"));
+            return diagnostics;
+        }
+    };
+
+    if let Some(sandboxes) = sandboxes {
+        for (name, sandbox) in sandboxes {
+            let name = key_to_string(name);
+            let path = format!("sandboxes.{}", name);
+            match sandbox {
+                Value::Mapping(sandbox) => {
+                    validate_sandbox(&path, sandbox, sandboxes, limits, &mut diagnostics)
+                }
+                _ => diagnostics.push(ConfigDiagnostic::new(path, "expected a mapping")),
+            }
+        }
+
+        validate_dependency_cycles(sandboxes, &mut diagnostics);
+    }
+
+    // Catch anything the checks above don't cover, such as fields of the wrong type
+    if diagnostics.is_empty()
+        && let Err(e) = serde_yaml::from_value::<Microsandbox>(root)
+    {
+        diagnostics.push(ConfigDiagnostic::new("", e.to_string()));
+    }
+
+    diagnostics
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Validates a single sandbox.
+fn validate_sandbox(
+    path: &str,
+    sandbox: &Mapping,
+    sandboxes: &Mapping,
+    limits: &HostLimits,
+    diagnostics: &mut Vec<ConfigDiagnostic>,
+) {
+    match sandbox.get("image") {
+        None | Some(Value::Null) => {
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("{}.image", path),
+                "missing image",
+            ));
+        }
+        Some(Value::String(image)) => {
+            if let Err(e) = ReferenceOrPath::from_str(image) {
+                diagnostics.push(ConfigDiagnostic::new(
+                    format!("{}.image", path),
+                    e.to_string(),
+                ));
+            }
+        }
+        Some(_) => diagnostics.push(ConfigDiagnostic::new(
+            format!("{}.image", path),
+            "expected a string",
+        )),
+    }
+
+    validate_limit(
+        path,
+        "memory",
+        sandbox,
+        limits.memory_mib,
+        "MiB of memory",
+        diagnostics,
+    );
+    validate_limit(
+        path,
+        "cpus",
+        sandbox,
+        limits.cpus.min(u8::MAX as u64),
+        "CPUs",
+        diagnostics,
+    );
+
+    validate_list::<PathPair>(path, "volumes", sandbox, diagnostics);
+    validate_list::<PortPair>(path, "ports", sandbox, diagnostics);
+    validate_list::<EnvPair>(path, "envs", sandbox, diagnostics);
+
+    for (index, dependency) in string_list(path, "depends_on", sandbox, diagnostics) {
+        if !sandboxes.contains_key(dependency) {
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("{}.depends_on[{}]", path, index),
+                format!("unknown sandbox '{}'", dependency),
+            ));
+        }
+    }
+
+    match sandbox.get("scripts") {
+        None | Some(Value::Null) => {}
+        Some(Value::Mapping(scripts)) => {
+            for (script_name, script) in scripts {
+                let script_name = key_to_string(script_name);
+                let script_path = format!("{}.scripts.{}", path, script_name);
+                if !is_valid_script_name(&script_name) {
+                    diagnostics.push(ConfigDiagnostic::new(
+                        script_path.clone(),
+                        "invalid script name, only letters, digits, '-', '_' and '.' are allowed",
+                    ));
+                }
+                if !script.is_string() {
+                    diagnostics.push(ConfigDiagnostic::new(script_path, "expected a string"));
+                }
+            }
+        }
+        Some(_) => diagnostics.push(ConfigDiagnostic::new(
+            format!("{}.scripts", path),
+            "expected a mapping",
+        )),
+    }
+
+    validate_value::<NetworkScope>(path, "scope", sandbox, diagnostics);
+    match sandbox.get("network") {
+        // Older versions of `msb add --scope` nested the scope under `network`
+        Some(Value::Mapping(network)) => validate_value::<NetworkScope>(
+            &format!("{}.network", path),
+            "scope",
+            network,
+            diagnostics,
+        ),
+        _ => validate_value::<NetworkMode>(path, "network", sandbox, diagnostics),
+    }
+    validate_value::<DnsMode>(path, "dns", sandbox, diagnostics);
+}
+
+/// Validates that a resource field is a non-zero integer within the host's limit.
+fn validate_limit(
+    path: &str,
+    field: &str,
+    sandbox: &Mapping,
+    limit: u64,
+    unit: &str,
+    diagnostics: &mut Vec<ConfigDiagnostic>,
+) {
+    let field_path = format!("{}.{}", path, field);
+    match sandbox.get(field) {
+        None | Some(Value::Null) => {}
+        Some(value) => match value.as_u64() {
+            Some(0) => {
+                diagnostics.push(ConfigDiagnostic::new(field_path, "must be greater than 0"))
+            }
+            Some(amount) if amount > limit => diagnostics.push(ConfigDiagnostic::new(
+                field_path,
+                format!("{} exceeds the host limit of {} {}", amount, limit, unit),
+            )),
+            Some(_) => {}
+            None => diagnostics.push(ConfigDiagnostic::new(
+                field_path,
+                "expected a positive integer",
+            )),
+        },
+    }
+}
+
+/// Validates that a string field parses as `T`.
+fn validate_value<T>(
+    path: &str,
+    field: &str,
+    sandbox: &Mapping,
+    diagnostics: &mut Vec<ConfigDiagnostic>,
+) where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let field_path = format!("{}.{}", path, field);
+    match sandbox.get(field) {
+        None | Some(Value::Null) => {}
+        Some(Value::String(value)) => {
+            if let Err(e) = value.parse::<T>() {
+                diagnostics.push(ConfigDiagnostic::new(field_path, e.to_string()));
+            }
+        }
+        Some(_) => diagnostics.push(ConfigDiagnostic::new(field_path, "expected a string")),
+    }
+}
+
+/// Validates that every entry of a list field parses as `T`.
+fn validate_list<T>(
+    path: &str,
+    field: &str,
+    sandbox: &Mapping,
+    diagnostics: &mut Vec<ConfigDiagnostic>,
+) where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    for (index, entry) in string_list(path, field, sandbox, diagnostics) {
+        if let Err(e) = entry.parse::<T>() {
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("{}.{}[{}]", path, field, index),
+                e.to_string(),
+            ));
+        }
+    }
+}
+
+/// Gets the string entries of a list field, reporting entries that aren't strings.
+fn string_list<'a>(
+    path: &str,
+    field: &str,
+    sandbox: &'a Mapping,
+    diagnostics: &mut Vec<ConfigDiagnostic>,
+) -> Vec<(usize, &'a str)> {
+    match sandbox.get(field) {
+        None | Some(Value::Null) => Vec::new(),
+        Some(Value::Sequence(entries)) => entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| match entry.as_str() {
+                Some(entry) => Some((index, entry)),
+                None => {
+                    diagnostics.push(ConfigDiagnostic::new(
+                        format!("{}.{}[{}]", path, field, index),
+                        "expected a string",
+                    ));
+                    None
+                }
+            })
+            .collect(),
+        Some(_) => {
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("{}.{}", path, field),
+                "expected a list",
+            ));
+            Vec::new()
+        }
+    }
+}
+
+/// Reports every dependency cycle between sandboxes once.
+fn validate_dependency_cycles(sandboxes: &Mapping, diagnostics: &mut Vec<ConfigDiagnostic>) {
+    let dependencies = |name: &str| -> Vec<String> {
+        sandboxes
+            .get(name)
+            .and_then(|sandbox| sandbox.get("depends_on"))
+            .and_then(Value::as_sequence)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .filter(|dependency| sandboxes.contains_key(*dependency))
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let mut visited = HashSet::new();
+    let mut reported = HashSet::new();
+    for name in sandboxes.keys().map(key_to_string) {
+        let mut stack = Vec::new();
+        find_cycles(
+            &name,
+            &dependencies,
+            &mut stack,
+            &mut visited,
+            &mut reported,
+            diagnostics,
+        );
+    }
+}
+
+/// Walks the dependencies of a sandbox depth-first, reporting any cycle found along the way.
+fn find_cycles(
+    name: &str,
+    dependencies: &dyn Fn(&str) -> Vec<String>,
+    stack: &mut Vec<String>,
+    visited: &mut HashSet<String>,
+    reported: &mut HashSet<Vec<String>>,
+    diagnostics: &mut Vec<ConfigDiagnostic>,
+) {
+    if let Some(start) = stack.iter().position(|entry| entry == name) {
+        let cycle = stack[start..].to_vec();
+
+        // Report each cycle once, no matter which sandbox it was entered from
+        let mut key = cycle.clone();
+        key.sort();
+        if reported.insert(key) {
+            let mut chain = cycle.clone();
+            chain.push(name.to_string());
+            diagnostics.push(ConfigDiagnostic::new(
+                format!("sandboxes.{}.depends_on", cycle[0]),
+                format!("dependency cycle: {}", chain.join(" -> ")),
+            ));
+        }
+        return;
+    }
+
+    if !visited.insert(name.to_string()) {
+        return;
+    }
+
+    stack.push(name.to_string());
+    for dependency in dependencies(name) {
+        if stack.contains(&dependency) || !visited.contains(&dependency) {
+            find_cycles(
+                &dependency,
+                dependencies,
+                stack,
+                visited,
+                reported,
+                diagnostics,
+            );
+        }
+    }
+    stack.pop();
+}
+
+/// Checks whether a script name only contains letters, digits, `-`, `_` and `.`.
+fn is_valid_script_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Converts a mapping key into a string for use in field paths.
+fn key_to_string(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => serde_yaml::to_string(key)
+            .map(|key| key.trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> HostLimits {
+        HostLimits::new(4096, 4)
+    }
+
+    fn paths(diagnostics: &[ConfigDiagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|d| d.path.as_str()).collect()
+    }
+
+    #[test]
+    fn test_validation_valid_config() {
+        let config = r#"
+            sandboxes:
+              db:
+                image: "postgres:16"
+                memory: 1024
+                cpus: 2
+                ports:
+                  - "5432:5432"
+              app:
+                image: "./rootfs"
+                volumes:
+                  - "./src:/app/src"
+                envs:
+                  - "DEBUG=true"
+                depends_on:
+                  - db
+                scripts:
+                  start: "python app.py"
+                  run-tests: "pytest"
+                scope: "public"
+                network: "none"
+                dns: "inherit"
+        "#;
+
+        assert_eq!(validate_with_limits(config, &limits()), vec![]);
+    }
+
+    #[test]
+    fn test_validation_collects_all_errors() {
+        let config = r#"
+            sandboxes:
+              app:
+                memory: 8192
+                cpus: 0
+                volumes:
+                  - "./src:"
+                ports:
+                  - "8080:http"
+                  - "3000"
+                envs:
+                  - "=missing-name"
+                depends_on:
+                  - db
+                scripts:
+                  "bad name": "echo hi"
+                scope: "everywhere"
+                network: "bridge"
+                dns: "google"
+        "#;
+
+        let diagnostics = validate_with_limits(config, &limits());
+        assert_eq!(
+            paths(&diagnostics),
+            vec![
+                "sandboxes.app.image",
+                "sandboxes.app.memory",
+                "sandboxes.app.cpus",
+                "sandboxes.app.volumes[0]",
+                "sandboxes.app.ports[0]",
+                "sandboxes.app.envs[0]",
+                "sandboxes.app.depends_on[0]",
+                "sandboxes.app.scripts.bad name",
+                "sandboxes.app.scope",
+                "sandboxes.app.network",
+                "sandboxes.app.dns",
+            ]
+        );
+        assert!(
+            diagnostics[1]
+                .message
+                .contains("exceeds the host limit of 4096")
+        );
+        assert!(diagnostics[6].message.contains("unknown sandbox 'db'"));
+    }
+
+    #[test]
+    fn test_validation_invalid_image_reference() {
+        let config = r#"
+            sandboxes:
+              app:
+                image: "UPPERCASE/Not Valid"
+        "#;
+
+        let diagnostics = validate_with_limits(config, &limits());
+        assert_eq!(paths(&diagnostics), vec!["sandboxes.app.image"]);
+    }
+
+    #[test]
+    fn test_validation_dependency_cycles() {
+        let config = r#"
+            sandboxes:
+              a:
+                image: "alpine"
+                depends_on: [b]
+              b:
+                image: "alpine"
+                depends_on: [c]
+              c:
+                image: "alpine"
+                depends_on: [a]
+              self:
+                image: "alpine"
+                depends_on: [self]
+              ok:
+                image: "alpine"
+                depends_on: [a]
+        "#;
+
+        let diagnostics = validate_with_limits(config, &limits());
+        assert_eq!(
+            diagnostics,
+            vec![
+                ConfigDiagnostic::new(
+                    "sandboxes.a.depends_on",
+                    "dependency cycle: a -> b -> c -> a"
+                ),
+                ConfigDiagnostic::new(
+                    "sandboxes.self.depends_on",
+                    "dependency cycle: self -> self"
+                ),
+            ]
+        );
+    }
+
+    fn sandbox(memory: u32, cpus: u8) -> Sandbox {
+        Sandbox::builder()
+            .image(ReferenceOrPath::from_str("alpine").unwrap())
+            .memory(memory)
+            .cpus(cpus)
+            .build()
+    }
+
+    #[test]
+    fn test_check_host_capacity_within_limits() {
+        let (a, b) = (sandbox(2048, 2), sandbox(2048, 2));
+        let sandboxes = [("a", &a), ("b", &b)];
+
+        for mode in [ResourceCheckMode::Warn, ResourceCheckMode::Error] {
+            let diagnostics = check_host_capacity(sandboxes, &limits(), mode).unwrap();
+            assert_eq!(diagnostics, vec![]);
+        }
+    }
+
+    #[test]
+    fn test_check_host_capacity_single_sandbox_exceeds_host() {
+        let big = sandbox(8192, 8);
+
+        let diagnostics =
+            check_host_capacity([("big", &big)], &limits(), ResourceCheckMode::Warn).unwrap();
+        assert_eq!(
+            paths(&diagnostics),
+            vec!["sandboxes.big.memory", "sandboxes.big.cpus"]
+        );
+
+        let err =
+            check_host_capacity([("big", &big)], &limits(), ResourceCheckMode::Error).unwrap_err();
+        assert!(matches!(
+            err,
+            MicrosandboxError::InsufficientHostResources(message)
+                if message.contains("sandboxes.big.memory") && message.contains("sandboxes.big.cpus")
+        ));
+    }
+
+    #[test]
+    fn test_check_host_capacity_sum_exceeds_host() {
+        let (a, b, c) = (sandbox(2048, 1), sandbox(2048, 1), sandbox(1024, 1));
+
+        // Exactly at the host's capacity is allowed
+        let diagnostics =
+            check_host_capacity([("a", &a), ("b", &b)], &limits(), ResourceCheckMode::Error)
+                .unwrap();
+        assert_eq!(diagnostics, vec![]);
+
+        let sandboxes = [("a", &a), ("b", &b), ("c", &c)];
+        let diagnostics =
+            check_host_capacity(sandboxes, &limits(), ResourceCheckMode::Warn).unwrap();
+        assert_eq!(
+            diagnostics,
+            vec![ConfigDiagnostic::new(
+                "sandboxes",
+                "3 sandboxes need 5120 MiB of memory in total, but the host has 4096 MiB"
+            )]
+        );
+
+        assert!(matches!(
+            check_host_capacity(sandboxes, &limits(), ResourceCheckMode::Error),
+            Err(MicrosandboxError::InsufficientHostResources(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_host_capacity_counts_defaults() {
+        let unset = Sandbox::builder()
+            .image(ReferenceOrPath::from_str("alpine").unwrap())
+            .build();
+        let limits = HostLimits::new(unset.memory_or_default() as u64 * 2, 8);
+        let sandboxes = [("a", &unset), ("b", &unset), ("c", &unset)];
+
+        let diagnostics = check_host_capacity(sandboxes, &limits, ResourceCheckMode::Warn).unwrap();
+        assert_eq!(paths(&diagnostics), vec!["sandboxes"]);
+    }
+
+    #[test]
+    fn test_validation_invalid_yaml() {
+        let diagnostics = validate_with_limits("sandboxes: [unclosed", &limits());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, "");
+        assert!(diagnostics[0].message.starts_with("invalid YAML"));
+    }
+}
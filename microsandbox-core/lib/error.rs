@@ -1,11 +1,11 @@
 use microsandbox_utils::MicrosandboxUtilsError;
-use oci_client::errors::OciDistributionError;
+use oci_client::errors::{OciDistributionError, OciErrorCode};
 use sqlx::migrate::MigrateError;
 use std::{
     error::Error,
     fmt::{self, Display},
     path::{PathBuf, StripPrefixError},
-    time::SystemTimeError,
+    time::{Duration, SystemTimeError},
 };
 use thiserror::Error;
 
@@ -59,6 +59,36 @@ pub enum MicrosandboxError {
     #[error("image layer download failed: {0}")]
     ImageLayerDownloadFailed(String),
 
+    /// An error that occurred when a registry returned a response that could not be used.
+    #[error("registry returned status {status}: {hint} (response: {body_snippet})")]
+    RegistryResponse {
+        /// The HTTP status of the response
+        status: u16,
+        /// The start of the response body
+        body_snippet: String,
+        /// What the response most likely means and how to address it
+        hint: String,
+    },
+
+    /// An error that occurred when a registry did not answer a request in time
+    #[error(
+        "registry did not answer in time: {url}; raise OCI_REGISTRY_CONNECT_TIMEOUT_SECS or OCI_REGISTRY_READ_TIMEOUT_SECS for slow registries"
+    )]
+    RegistryTimeout {
+        /// The URL of the request that timed out
+        url: String,
+    },
+
+    /// An error that occurred when a registry kept rate limiting requests
+    #[error(
+        "rate limited by the registry{}; run `msb login` to pull with a higher limit",
+        .retry_after.map(|d| format!(", retry after {d:?}")).unwrap_or_default()
+    )]
+    RateLimited {
+        /// How long the registry asked to wait before retrying, if it said
+        retry_after: Option<Duration>,
+    },
+
     /// An error that occurred when an invalid path pair was used.
     #[error("invalid path pair: {0}")]
     InvalidPathPair(String),
@@ -71,6 +101,14 @@ pub enum MicrosandboxError {
     #[error("invalid environment variable pair: {0}")]
     InvalidEnvPair(String),
 
+    /// An error that occurred when an environment variable to inherit is not set on the host.
+    #[error("environment variable {0} is inherited from the host, but is not set there")]
+    MissingHostEnv(String),
+
+    /// An error that occurred when an invalid label selector was used.
+    #[error("invalid label selector, expected key=value: {0}")]
+    InvalidLabelSelector(String),
+
     /// An error that occurred when an invalid MicroVm configuration was used.
     #[error("invalid MicroVm configuration: {0}")]
     InvalidMicroVMConfig(InvalidMicroVMConfigError),
@@ -132,6 +170,14 @@ pub enum MicrosandboxError {
     #[error("path does not exist: {0}")]
     PathNotFound(String),
 
+    /// An error that occurred when an environment file does not exist
+    #[error("env file not found: {0}")]
+    EnvFileNotFound(String),
+
+    /// An error that occurred when a `secret://` environment reference could not be resolved
+    #[error("secret '{0}' referenced by environment variable {1} was not found")]
+    SecretNotFound(String, String),
+
     /// An error that occurred when a rootfs path does not exist
     #[error("rootfs path does not exist: {0}")]
     RootFsPathNotFound(String),
@@ -144,6 +190,10 @@ pub enum MicrosandboxError {
     #[error("failed to start VM: {0}")]
     StartVmFailed(i32),
 
+    /// An error that occurred when passt, which provides the `nat` network, could not be started
+    #[error("failed to start passt for the nat network, make sure it is installed: {0}")]
+    PasstStartFailed(#[source] std::io::Error),
+
     /// An error that occurred when waiting for a process to exit
     #[error("process wait error: {0}")]
     ProcessWaitError(String),
@@ -152,6 +202,24 @@ pub enum MicrosandboxError {
     #[error("supervisor error: {0}")]
     SupervisorError(String),
 
+    /// An error that occurred when a sandbox did not become ready within its start timeout
+    #[error("sandbox '{sandbox}' did not start within {elapsed:?}")]
+    StartTimeout {
+        /// The name of the sandbox that failed to start
+        sandbox: String,
+        /// How long the start was waited on before giving up
+        elapsed: Duration,
+    },
+
+    /// An error that occurred when a sandbox's readiness probe did not succeed within its timeout
+    #[error("sandbox '{sandbox}' did not become ready within {elapsed:?}")]
+    ReadinessTimeout {
+        /// The name of the sandbox that did not become ready
+        sandbox: String,
+        /// How long the probe was retried before giving up
+        elapsed: Duration,
+    },
+
     /// An error that occurred when failed to kill process
     #[error("failed to kill process: {0}")]
     ProcessKillError(String),
@@ -181,9 +249,16 @@ pub enum MicrosandboxError {
     SystemTime(#[from] SystemTimeError),
 
     /// An error that occurred during layer extraction.
-    /// This typically happens when the join handle for the blocking task fails.
-    #[error("layer extraction error: {0}")]
-    LayerExtraction(String),
+    /// Holds the error that caused it, when there is one, so the cause can be reached through
+    /// [`Error::source`].
+    #[error("layer extraction error: {message}")]
+    LayerExtraction {
+        /// What went wrong during extraction
+        message: String,
+        /// The underlying error that caused the failure
+        #[source]
+        source: Option<Box<dyn Error + Send + Sync>>,
+    },
 
     /// An error that occurred during layer handling operations like opening files or unpacking archives.
     /// Contains both the underlying IO error and the path to the layer being processed.
@@ -195,6 +270,21 @@ pub enum MicrosandboxError {
         layer: String,
     },
 
+    /// An error that occurred when the file system being written to ran out of space.
+    #[error(
+        "not enough disk space in {}{}; free up space or use a directory on another disk",
+        path.display(),
+        disk_space_details(*needed, *available)
+    )]
+    DiskFull {
+        /// The path that was being written to
+        path: PathBuf,
+        /// The number of bytes that were needed, if known
+        needed: Option<u64>,
+        /// The number of bytes that were free, if known
+        available: Option<u64>,
+    },
+
     /// An error that occurred when a configuration file was not found
     #[error("configuration file not found: {0}")]
     ConfigNotFound(String),
@@ -207,6 +297,10 @@ pub enum MicrosandboxError {
     #[error("invalid image reference: {0}")]
     ImageReferenceError(String),
 
+    /// An error that occurred when an image has not been pulled
+    #[error("image not found: {0}")]
+    ImageNotFound(String),
+
     /// An error that occurred when trying to remove running services
     #[error("Cannot remove running services: {0}")]
     ServiceStillRunning(String),
@@ -243,6 +337,54 @@ pub enum MicrosandboxError {
     #[error("migration error: {0}")]
     MigrationError(#[from] MigrateError),
 
+    /// An error that occurred when the virtualization backend is not usable on this host
+    #[error("virtualization backend unavailable: {reason}. {remediation}")]
+    BackendUnavailable {
+        /// What is missing or wrong, e.g. the library that could not be found
+        reason: String,
+        /// What the user can do to fix it
+        remediation: String,
+    },
+
+    /// An error that occurred when an operation needs a running sandbox that is not running
+    #[error("sandbox is not running: {0}")]
+    SandboxNotRunning(String),
+
+    /// An error that occurred when an operation needs a stopped sandbox that is still running
+    #[error("sandbox is still running: {0}")]
+    SandboxStillRunning(String),
+
+    /// An error that occurred when a sandbox snapshot was not found
+    #[error("snapshot not found: {0}")]
+    SnapshotNotFound(String),
+
+    /// An error that occurred when a sandbox name is defined in more than one project
+    #[error(
+        "sandbox {sandbox} is defined in multiple projects ({}); specify the project to use",
+        projects.join(", ")
+    )]
+    AmbiguousSandbox {
+        /// The sandbox name that was requested
+        sandbox: String,
+        /// The projects that define a sandbox with that name
+        projects: Vec<String>,
+    },
+
+    /// An error that occurred when a memory resize was requested outside the allowed range
+    #[error("requested memory {requested} MiB is outside the allowed range of {min}-{max} MiB")]
+    InvalidMemoryResize {
+        /// The requested memory size in MiB
+        requested: u32,
+        /// The smallest allowed memory size in MiB
+        min: u32,
+        /// The largest allowed memory size in MiB
+        max: u32,
+    },
+
+    /// An error that occurred when the backend cannot resize the memory of a running sandbox
+    #[error("memory resize not supported: {0}")]
+    MemoryResizeNotSupported(String),
+
     /// An error that occurred when a feature is not yet implemented
     #[error("feature not yet implemented: {0}")]
     NotImplemented(String),
@@ -251,6 +393,10 @@ pub enum MicrosandboxError {
     #[error("cannot find sandbox: '{0}' in '{1}'")]
     SandboxNotFoundInConfig(String, PathBuf),
 
+    /// An error that occurred when a configuration does not define any sandboxes
+    #[error("no sandboxes defined in '{0}'; run `msb add` to create one")]
+    NoSandboxesInConfig(PathBuf),
+
     /// An error that occurs when an invalid log level is used.
     #[error("invalid log level: {0}")]
     InvalidLogLevel(u8),
@@ -275,10 +421,69 @@ pub enum MicrosandboxError {
     #[error("invalid network scope: {0}")]
     InvalidNetworkScope(String),
 
+    /// An error that occurred when an invalid rootfs mode was used.
+    #[error("invalid rootfs mode: {0}, expected auto, overlay or native")]
+    InvalidRootfsMode(String),
+
+    /// An error that occurred when an invalid DNS mode was used.
+    #[error("invalid DNS mode: {0}, expected public or inherit")]
+    InvalidDnsMode(String),
+
+    /// An error that occurred when an invalid network mode was used.
+    #[error("invalid network mode: {0}, expected none, host or nat")]
+    InvalidNetworkMode(String),
+
     /// An error that occurred when a start script or exec command or shell is missing.
     #[error("missing start script or exec command or shell")]
     MissingStartOrExecOrShell,
 
+    /// An error that occurred when the sandboxes being started need more resources than the host has.
+    #[error("insufficient host resources: {0}")]
+    InsufficientHostResources(String),
+
+    /// An error that occurred when an apply that would stop sandboxes was not confirmed.
+    #[error("apply not confirmed: {0}")]
+    ApplyNotConfirmed(String),
+
+    /// An error that occurred when a readiness probe is misconfigured.
+    #[error("invalid readiness probe: {0}")]
+    InvalidReadinessProbe(String),
+
+    /// An error that occurred when the credential store file cannot be read.
+    #[error("invalid credential store: {0}")]
+    InvalidCredentialStore(String),
+
+    /// An error that occurred when credentials are stored for an invalid registry host.
+    #[error("invalid registry host: {0}")]
+    InvalidRegistryHost(String),
+
+    /// An error that occurred when the credential store was written by a newer version of msb.
+    #[error(
+        "credential store version {version} is newer than the supported version {supported}; downgrading msb is not supported, upgrade it to use these credentials"
+    )]
+    UnsupportedCredentialStoreVersion {
+        /// The version of the credential store file
+        version: u64,
+        /// The newest version this build can read
+        supported: u64,
+    },
+
+    /// An error that occurred when the Docker configuration file cannot be updated.
+    #[error("invalid docker config: {0}")]
+    InvalidDockerConfig(String),
+
+    /// An error that occurred when an OCI image layout on disk cannot be read.
+    #[error("invalid OCI layout: {0}")]
+    InvalidOciLayout(String),
+
+    /// An error that occurred when an image rewrite rule cannot be parsed or applied.
+    #[error("invalid image rewrite rule: {0}")]
+    InvalidImageRewrite(String),
+
+    /// An error that occurred when a pulled image cannot be exported.
+    #[error("failed to export image: {0}")]
+    ImageExport(String),
+
     /// An error that occurred when trying to install a script with the same name as an existing command.
     #[error("command already exists: {0}")]
     CommandExists(String),
@@ -324,6 +529,19 @@ pub enum InvalidMicroVMConfigError {
     /// An error that occurs when conflicting guest paths are detected.
     #[error("Conflicting guest paths: '{0}' and '{1}' overlap")]
     ConflictingGuestPaths(String, String),
+
+    /// A kernel argument is empty, contains whitespace or is the `--` separator.
+    #[error("invalid kernel argument: '{0}'")]
+    InvalidKernelArg(String),
+
+    /// A kernel argument sets a parameter that microsandbox or libkrun already sets.
+    #[error("kernel argument '{0}' overrides a parameter set by microsandbox")]
+    ReservedKernelArg(String),
+
+    /// A kernel argument sets the same parameter as another kernel argument or an environment
+    /// variable.
+    #[error("kernel argument '{0}' is set more than once")]
+    DuplicateKernelArg(String),
 }
 
 /// An error that can represent any error.
@@ -336,6 +554,54 @@ pub struct AnyError {
 // Methods
 //--------------------------------------------------------------------------------------------------
 
+impl MicrosandboxError {
+    /// Returns whether the registry reported that the requested image or tag doesn't exist.
+    ///
+    /// This covers a `404` response and a `MANIFEST_UNKNOWN` or `NAME_UNKNOWN` error, whether or
+    /// not the registry client error was mapped to [`MicrosandboxError::RegistryResponse`].
+    pub fn is_image_not_found(&self) -> bool {
+        match self {
+            MicrosandboxError::ImageNotFound(_)
+            | MicrosandboxError::ManifestNotFound
+            | MicrosandboxError::RegistryResponse { status: 404, .. } => true,
+            MicrosandboxError::OciDistribution(error) => match error {
+                OciDistributionError::ImageManifestNotFoundError(_)
+                | OciDistributionError::ServerError { code: 404, .. } => true,
+                OciDistributionError::RegistryError { envelope, .. } => {
+                    envelope.errors.iter().any(|e| {
+                        matches!(
+                            e.code,
+                            OciErrorCode::ManifestUnknown | OciErrorCode::NameUnknown
+                        )
+                    })
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Returns the image a missing image error refers to, if the error names it.
+    ///
+    /// Registry client errors only carry the URL of the failed request, so the image is read
+    /// from a manifest URL, e.g. `library/nginx:nope` from `.../v2/library/nginx/manifests/nope`.
+    pub fn missing_image(&self) -> Option<String> {
+        let url = match self {
+            MicrosandboxError::ImageNotFound(image) => return Some(image.clone()),
+            MicrosandboxError::OciDistribution(
+                OciDistributionError::ServerError { url, .. }
+                | OciDistributionError::RegistryError { url, .. },
+            ) => url,
+            _ => return None,
+        };
+
+        let (_, path) = url.split_once("/v2/")?;
+        let (repository, reference) = path.split_once("/manifests/")?;
+        let separator = if reference.contains(':') { '@' } else { ':' };
+        Some(format!("{repository}{separator}{reference}"))
+    }
+}
+
 impl AnyError {
     /// Downcasts the error to a `T`.
     pub fn downcast<T>(&self) -> Option<&T>
@@ -356,6 +622,18 @@ pub fn Ok<T>(value: T) -> MicrosandboxResult<T> {
     Result::Ok(value)
 }
 
+/// Describes the space needed and available for a [`MicrosandboxError::DiskFull`] error.
+fn disk_space_details(needed: Option<u64>, available: Option<u64>) -> String {
+    match (needed, available) {
+        (Some(needed), Some(available)) => {
+            format!(" ({needed} bytes needed, {available} bytes available)")
+        }
+        (Some(needed), None) => format!(" ({needed} bytes needed)"),
+        (None, Some(available)) => format!(" ({available} bytes available)"),
+        (None, None) => String::new(),
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Trait Implementations
 //--------------------------------------------------------------------------------------------------
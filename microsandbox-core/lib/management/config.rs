@@ -3,24 +3,45 @@
 //! This module provides structures and utilities for modifying Microsandbox
 //! configuration.
 
-use microsandbox_utils::{DEFAULT_SHELL, MICROSANDBOX_CONFIG_FILENAME};
+use getset::Getters;
+use microsandbox_utils::{DEFAULT_SHELL, MICROSANDBOX_CONFIG_FILENAME, OCI_DB_FILENAME, env};
 use nondestructive::yaml;
+use serde::Serialize;
 use sqlx::{Pool, Sqlite};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
+    io::Write,
     path::{Path, PathBuf},
 };
-use tokio::fs;
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt},
+    sync::OnceCell,
+};
 use typed_path::Utf8UnixPathBuf;
 
 use crate::{
     MicrosandboxError, MicrosandboxResult,
-    config::{EnvPair, Microsandbox, PathSegment, PortPair, Sandbox},
+    config::{
+        DnsMode, EnvPair, InheritedEnvMode, Microsandbox, NetworkMode, NetworkScope, PathSegment,
+        PortPair, ReferenceOrPath, RootfsMode, Sandbox, resolve_inherited_envs,
+    },
     oci::Reference,
 };
 
 use super::db;
 
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The config file name that reads the configuration from stdin instead of a file, as in
+/// `msb up --file -`.
+pub const STDIN_CONFIG_FILE: &str = "-";
+
+/// The configuration read from stdin, which can only be read once per process.
+static STDIN_CONFIG: OnceCell<Microsandbox> = OnceCell::const_new();
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
@@ -46,8 +67,8 @@ pub struct SandboxConfig {
     /// The environment variables to use.
     pub envs: Vec<String>,
 
-    /// The environment file to use.
-    pub env_file: Option<Utf8UnixPathBuf>,
+    /// The environment files to load, in order. Later files override earlier ones.
+    pub env_file: Vec<Utf8UnixPathBuf>,
 
     /// The dependencies to use for the sandbox.
     pub depends_on: Vec<String>,
@@ -69,6 +90,78 @@ pub struct SandboxConfig {
 
     /// The network scope to use for the sandbox.
     pub scope: Option<String>,
+
+    /// How the root filesystem of the sandbox is put together.
+    pub rootfs_mode: Option<RootfsMode>,
+}
+
+/// The configuration a sandbox runs with once everything that feeds into it is resolved.
+///
+/// Image defaults are merged in, env files and inherited environment variables are resolved, and
+/// defaults stand in for anything left unset. Secret references are kept as they are, so their
+/// values are never shown.
+#[derive(Debug, Clone, PartialEq, Serialize, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct EffectiveSandboxConfig {
+    /// The name of the sandbox.
+    sandbox: String,
+
+    /// The image the sandbox runs.
+    image: String,
+
+    /// Whether the defaults of the image were merged in.
+    ///
+    /// This is `false` when image defaults are disabled, the image is a rootfs directory, or the
+    /// image hasn't been pulled yet.
+    image_defaults_applied: bool,
+
+    /// How the root filesystem of the sandbox is put together.
+    rootfs_mode: RootfsMode,
+
+    /// The amount of memory in MiB.
+    memory: u32,
+
+    /// The number of virtual CPUs.
+    cpus: u8,
+
+    /// The working directory.
+    workdir: Option<String>,
+
+    /// The shell.
+    shell: Option<String>,
+
+    /// The command run when the sandbox starts.
+    command: Vec<String>,
+
+    /// The program run in place of the image's entrypoint, with `command` as its arguments.
+    entrypoint: Vec<String>,
+
+    /// The environment variables, as `KEY=VALUE`.
+    envs: Vec<String>,
+
+    /// The port mappings, as `host:guest`.
+    ports: Vec<String>,
+
+    /// The volume mappings, as `host:guest`.
+    volumes: Vec<String>,
+
+    /// The sandboxes this sandbox depends on.
+    depends_on: Vec<String>,
+
+    /// The network scope.
+    scope: NetworkScope,
+
+    /// How the sandbox is connected to the network.
+    network: NetworkMode,
+
+    /// Where the sandbox gets its DNS resolvers from.
+    dns: DnsMode,
+
+    /// The scripts, by name.
+    scripts: BTreeMap<String, String>,
+
+    /// The extra arguments appended to the guest kernel command line.
+    kernel_args: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -211,9 +304,15 @@ pub async fn add(
                     }
                 }
 
-                // Add env_file if provided
-                if let Some(env_file_path) = &config.env_file {
-                    sandbox_mapping.insert_str("env_file", env_file_path);
+                // Add env files if any
+                if !config.env_file.is_empty() {
+                    let mut env_file_sequence = sandbox_mapping
+                        .insert("env_file", yaml::Separator::Auto)
+                        .make_sequence();
+
+                    for env_file_path in &config.env_file {
+                        env_file_sequence.push_string(env_file_path.as_str());
+                    }
                 }
 
                 // Add depends_on if any
@@ -267,11 +366,12 @@ pub async fn add(
 
                 // Add network scope if provided
                 if let Some(scope_value) = &config.scope {
-                    let mut network_mapping = sandbox_mapping
-                        .insert("network", yaml::Separator::Auto)
-                        .make_mapping();
+                    sandbox_mapping.insert_str("scope", scope_value);
+                }
 
-                    network_mapping.insert_str("scope", scope_value);
+                // Add rootfs mode if provided
+                if let Some(rootfs_mode) = config.rootfs_mode {
+                    sandbox_mapping.insert_str("rootfs_mode", &rootfs_mode.to_string());
                 }
             }
             Component::Build {} => {}
@@ -280,12 +380,7 @@ pub async fn add(
     }
 
     // Write the modified YAML back to the file, preserving formatting
-    let modified_content = doc.to_string();
-
-    // TODO: Validate config before writing
-    fs::write(full_config_path, modified_content).await?;
-
-    Ok(())
+    write_config(&full_config_path, &doc.to_string()).await
 }
 
 /// Removes a component from the Microsandbox configuration.
@@ -347,12 +442,7 @@ pub async fn remove(
     }
 
     // Write the modified YAML back to the file, preserving formatting
-    let modified_content = doc.to_string();
-
-    // TODO: Validate config before writing
-    fs::write(full_config_path, modified_content).await?;
-
-    Ok(())
+    write_config(&full_config_path, &doc.to_string()).await
 }
 
 /// Lists components in the Microsandbox configuration.
@@ -383,10 +473,175 @@ pub async fn list(
     }
 }
 
+/// Resolves the configuration a sandbox would run with, without starting it.
+///
+/// Image defaults are only merged in if the image has already been pulled, as nothing is
+/// downloaded here.
+///
+/// ## Arguments
+///
+/// * `sandbox_name` - The name of the sandbox to inspect
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
+/// * `use_image_defaults` - Whether to merge in the defaults of the image
+///
+/// ## Returns
+///
+/// The effective configuration of the sandbox, or an error if the config can't be loaded, the
+/// sandbox isn't in it, or its environment can't be resolved
+pub async fn inspect(
+    sandbox_name: &str,
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+    use_image_defaults: bool,
+) -> MicrosandboxResult<EffectiveSandboxConfig> {
+    let (config, canonical_project_dir, config_file) =
+        load_config(project_dir, config_file).await?;
+    let Some(sandbox) = config.get_sandbox(sandbox_name).cloned() else {
+        return Err(MicrosandboxError::SandboxNotFoundInConfig(
+            sandbox_name.to_string(),
+            canonical_project_dir.join(config_file),
+        ));
+    };
+
+    // Only read image defaults from a database that already exists, so inspecting has no side effects
+    let db_path = env::get_microsandbox_home_path().join(OCI_DB_FILENAME);
+    let oci_db = if use_image_defaults && db_path.exists() {
+        Some(db::get_or_create_pool(&db_path, &db::OCI_DB_MIGRATOR).await?)
+    } else {
+        None
+    };
+
+    resolve_effective_config(
+        sandbox_name,
+        sandbox,
+        &canonical_project_dir,
+        oci_db.as_ref(),
+    )
+    .await
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Helpers
 //--------------------------------------------------------------------------------------------------
 
+/// Resolves the effective configuration of a sandbox, merging in image defaults from `oci_db`
+/// when it is given.
+async fn resolve_effective_config(
+    sandbox_name: &str,
+    mut sandbox: Sandbox,
+    project_dir: &Path,
+    oci_db: Option<&Pool<Sqlite>>,
+) -> MicrosandboxResult<EffectiveSandboxConfig> {
+    let mut image_defaults_applied = false;
+    if let (ReferenceOrPath::Reference(reference), Some(oci_db)) =
+        (sandbox.get_image().clone(), oci_db)
+    {
+        if db::get_image_config(oci_db, &reference.as_db_key())
+            .await?
+            .is_some()
+        {
+            apply_image_defaults(&mut sandbox, &reference, oci_db).await?;
+            image_defaults_applied = true;
+        } else {
+            tracing::warn!(
+                "image {} has not been pulled, so its defaults are not included",
+                reference
+            );
+        }
+    }
+
+    let envs = resolve_inherited_envs(
+        sandbox.resolve_envs(project_dir).await?,
+        InheritedEnvMode::Warn,
+    )?;
+
+    Ok(EffectiveSandboxConfig {
+        sandbox: sandbox_name.to_string(),
+        image: sandbox.get_image().to_string(),
+        image_defaults_applied,
+        rootfs_mode: sandbox.rootfs_mode_or_default().select(sandbox.get_image()),
+        memory: sandbox.memory_or_default(),
+        cpus: sandbox.cpus_or_default(),
+        workdir: sandbox.get_workdir().as_ref().map(|dir| dir.to_string()),
+        shell: sandbox.get_shell().clone(),
+        command: sandbox.get_command().clone(),
+        entrypoint: sandbox.get_entrypoint().clone(),
+        envs: envs.iter().map(|env| env.to_string()).collect(),
+        ports: sandbox
+            .get_ports()
+            .iter()
+            .map(|port| port.to_string())
+            .collect(),
+        volumes: sandbox
+            .get_volumes()
+            .iter()
+            .map(|volume| volume.to_string())
+            .collect(),
+        depends_on: sandbox.get_depends_on().clone(),
+        scope: sandbox.scope_or_default(),
+        network: sandbox.network_or_default(),
+        dns: sandbox.dns_or_default(),
+        scripts: sandbox.get_scripts().clone().into_iter().collect(),
+        kernel_args: sandbox.get_kernel_args().clone(),
+    })
+}
+
+/// Replaces the contents of a Microsandbox configuration file without ever leaving it half
+/// written.
+///
+/// The new contents must parse back as a configuration, otherwise the file is left untouched.
+/// They are written to a temporary file next to the configuration file, which then replaces it
+/// in a single rename, so a failure part way through leaves the original file as it was. The
+/// file keeps its permissions, and a symlink keeps pointing at the file it pointed at.
+///
+/// ## Arguments
+///
+/// * `config_path` - The path of the configuration file to replace
+/// * `contents` - The new contents of the file
+pub async fn write_config(config_path: &Path, contents: &str) -> MicrosandboxResult<()> {
+    // Refuse contents that would leave the project without a loadable configuration
+    serde_yaml::from_str::<Microsandbox>(contents).map_err(|e| {
+        MicrosandboxError::ConfigValidation(format!(
+            "the updated configuration does not parse, leaving {} untouched: {}",
+            config_path.display(),
+            e
+        ))
+    })?;
+
+    // Replace the file a symlink points at rather than the symlink itself
+    let config_path = match fs::canonicalize(config_path).await {
+        Ok(path) => path,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => config_path.to_path_buf(),
+        Err(e) => return Err(e.into()),
+    };
+    let config_dir = config_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let file_name = config_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| MICROSANDBOX_CONFIG_FILENAME.to_string());
+
+    // The temporary file is removed when it is dropped without being persisted
+    let mut temp_file = tempfile::Builder::new()
+        .prefix(&format!(".{}.", file_name))
+        .suffix(".tmp")
+        .tempfile_in(config_dir)?;
+    temp_file.write_all(contents.as_bytes())?;
+    temp_file.as_file().sync_all()?;
+
+    if let Ok(metadata) = fs::metadata(&config_path).await {
+        fs::set_permissions(temp_file.path(), metadata.permissions()).await?;
+    }
+
+    temp_file.persist(&config_path).map_err(|e| e.error)?;
+
+    tracing::debug!("wrote configuration to {}", config_path.display());
+    Ok(())
+}
+
 /// Loads a Microsandbox configuration from a file.
 ///
 /// This function handles all the common steps for loading a Microsandbox configuration, including:
@@ -412,6 +667,9 @@ pub async fn list(
 /// - The config file does not exist
 /// - The config file cannot be read
 /// - The config file contains invalid YAML
+///
+/// A config file of [`STDIN_CONFIG_FILE`] reads the configuration from stdin instead. Stdin is
+/// only read the first time, and later loads in the same process reuse that configuration.
 pub async fn load_config(
     project_dir: Option<&Path>,
     config_file: Option<&str>,
@@ -420,8 +678,17 @@ pub async fn load_config(
     let project_dir = project_dir.unwrap_or_else(|| Path::new("."));
     let canonical_project_dir = fs::canonicalize(project_dir).await?;
 
-    // Validate the config file path
+    // Read the configuration piped in on stdin
     let config_file = config_file.unwrap_or(MICROSANDBOX_CONFIG_FILENAME);
+    if config_file == STDIN_CONFIG_FILE {
+        let config = STDIN_CONFIG
+            .get_or_try_init(|| read_config(tokio::io::stdin()))
+            .await?
+            .clone();
+        return Ok((config, canonical_project_dir, config_file.to_string()));
+    }
+
+    // Validate the config file path
     let _ = PathSegment::try_from(config_file)?;
     let full_config_path = canonical_project_dir.join(config_file);
 
@@ -439,6 +706,58 @@ pub async fn load_config(
     Ok((config, canonical_project_dir, config_file.to_string()))
 }
 
+/// Reads a Microsandbox configuration from a reader, such as stdin.
+///
+/// ## Arguments
+///
+/// * `reader` - The reader the YAML configuration is read from, until it is exhausted
+///
+/// ## Returns
+///
+/// The parsed configuration, or a `MicrosandboxError` if it cannot be read or is invalid YAML
+pub async fn read_config(mut reader: impl AsyncRead + Unpin) -> MicrosandboxResult<Microsandbox> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).await?;
+
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Loads several Microsandbox configuration files and layers them in order.
+///
+/// The first file is the base configuration and each following file is merged on top of the
+/// result with [`Microsandbox::merge`], so later files override earlier ones. Every file must be
+/// a valid configuration on its own.
+///
+/// ## Arguments
+///
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_files` - The config files to load, in order. If empty, uses the default filename
+///
+/// ## Returns
+///
+/// Returns a tuple containing:
+/// - The merged Microsandbox configuration
+/// - The canonical project directory path
+/// - The base config file name
+pub async fn load_layered_config(
+    project_dir: Option<&Path>,
+    config_files: &[&str],
+) -> MicrosandboxResult<(Microsandbox, PathBuf, String)> {
+    let Some((base_file, overlay_files)) = config_files.split_first() else {
+        return load_config(project_dir, None).await;
+    };
+
+    let (mut config, canonical_project_dir, config_file) =
+        load_config(project_dir, Some(base_file)).await?;
+
+    for overlay_file in overlay_files {
+        let (overlay, _, _) = load_config(Some(&canonical_project_dir), Some(overlay_file)).await?;
+        config = config.merge(overlay);
+    }
+
+    Ok((config, canonical_project_dir, config_file))
+}
+
 /// Resolves the paths for a Microsandbox configuration.
 ///
 /// This function is similar to `load_config` but without actually loading the file.
@@ -463,8 +782,16 @@ pub async fn resolve_config_paths(
     let project_dir = project_dir.unwrap_or_else(|| Path::new("."));
     let canonical_project_dir = fs::canonicalize(project_dir).await?;
 
-    // Validate the config file path
+    // A configuration piped in on stdin has no file to resolve or modify
     let config_file = config_file.unwrap_or(MICROSANDBOX_CONFIG_FILENAME);
+    if config_file == STDIN_CONFIG_FILE {
+        return Err(MicrosandboxError::ConfigValidation(
+            "a configuration read from stdin has no file to modify; pass a config file instead"
+                .to_string(),
+        ));
+    }
+
+    // Validate the config file path
     let _ = PathSegment::try_from(config_file)?;
     let full_config_path = canonical_project_dir.join(config_file);
 
@@ -488,8 +815,11 @@ pub async fn resolve_config_paths(
 /// configuration when they are not explicitly defined in the sandbox config.
 ///
 /// The following defaults are applied:
-/// - Script: Uses the entrypoint and cmd from the image if a script is missing
-/// - Environment variables: Combines image env variables with sandbox env variables
+/// - Command: Uses the entrypoint and cmd from the image if neither a command nor an entrypoint
+///   is defined. A sandbox entrypoint replaces the image's entrypoint and, like with Docker, its
+///   cmd too
+/// - Environment variables: Combines image env variables with sandbox env variables, where the
+///   sandbox value wins for variables defined in both
 /// - Working directory: Uses the image's working directory if not specified
 /// - Exposed ports: Combines image exposed ports with sandbox ports
 ///
@@ -497,7 +827,7 @@ pub async fn resolve_config_paths(
 ///
 /// * `sandbox_config` - Mutable reference to the sandbox configuration to enhance
 /// * `reference` - OCI image reference to get defaults from
-/// * `script_name` - The name of the script we're trying to run
+/// * `oci_db` - The OCI database the image configuration is read from
 ///
 /// ## Returns
 ///
@@ -510,7 +840,7 @@ pub async fn apply_image_defaults(
     oci_db: &Pool<Sqlite>,
 ) -> MicrosandboxResult<()> {
     // Get the image configuration
-    if let Some(config) = db::get_image_config(oci_db, &reference.to_string()).await? {
+    if let Some(config) = db::get_image_config(oci_db, &reference.as_db_key()).await? {
         tracing::info!("applying defaults from image configuration");
 
         // Apply working directory if not set in sandbox
@@ -528,7 +858,12 @@ pub async fn apply_image_defaults(
         {
             let mut image_env_pairs = Vec::new();
             for env_var in image_env_vars {
-                if let Ok(env_pair) = env_var.parse::<EnvPair>() {
+                if let Ok(env_pair) = env_var.parse::<EnvPair>()
+                    && !sandbox_config
+                        .get_envs()
+                        .iter()
+                        .any(|env| env.get_name() == env_pair.get_name())
+                {
                     image_env_pairs.push(env_pair);
                 }
             }
@@ -540,8 +875,8 @@ pub async fn apply_image_defaults(
             sandbox_config.envs = combined_env;
         }
 
-        // Apply entrypoint and cmd as command if no command is defined
-        if sandbox_config.get_command().is_empty() {
+        // Apply entrypoint and cmd as command if no command or entrypoint is defined
+        if sandbox_config.get_command().is_empty() && sandbox_config.get_entrypoint().is_empty() {
             let mut command_vec: Vec<String> = Vec::new();
             let mut has_entrypoint_or_cmd = false;
 
@@ -622,3 +957,308 @@ pub async fn apply_image_defaults(
 
     Ok(())
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use oci_client::manifest::OciImageManifest;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{config::PathPair, management::sandbox::determine_exec_path_and_args};
+
+    async fn oci_db_with_image(root: &Path, reference: &Reference) -> anyhow::Result<Pool<Sqlite>> {
+        let pool = db::get_or_create_pool(&root.join("db"), &db::OCI_DB_MIGRATOR).await?;
+        let image_id = db::save_or_update_image(&pool, &reference.as_db_key(), 0).await?;
+        let manifest_id = db::save_manifest(&pool, image_id, &OciImageManifest::default()).await?;
+        let config = serde_json::from_value(serde_json::json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {
+                "Env": ["PATH=/usr/bin", "MODE=image"],
+                "Entrypoint": ["/entrypoint.sh"],
+                "Cmd": ["serve"],
+                "WorkingDir": "/app",
+                "ExposedPorts": { "8080/tcp": {}, "9090/tcp": {} },
+            },
+            "rootfs": { "type": "layers", "diff_ids": [] },
+        }))?;
+        db::save_config(&pool, manifest_id, &config).await?;
+        Ok(pool)
+    }
+
+    #[tokio::test]
+    async fn test_config_apply_image_defaults() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let reference = Reference::from_str("localhost:5000/app:1.0")?;
+        let pool = oci_db_with_image(temp_dir.path(), &reference).await?;
+
+        let mut sandbox = Sandbox::builder()
+            .image(ReferenceOrPath::Reference(reference.clone()))
+            .build();
+        apply_image_defaults(&mut sandbox, &reference, &pool).await?;
+
+        assert_eq!(sandbox.get_command(), &["/entrypoint.sh", "serve"]);
+        assert_eq!(
+            sandbox.get_workdir().as_ref().map(|dir| dir.as_str()),
+            Some("/app")
+        );
+        assert_eq!(
+            sandbox.get_envs(),
+            &[
+                EnvPair::new("PATH", "/usr/bin"),
+                EnvPair::new("MODE", "image")
+            ]
+        );
+        let mut guest_ports = sandbox
+            .get_ports()
+            .iter()
+            .map(|port| port.get_guest())
+            .collect::<Vec<_>>();
+        guest_ports.sort();
+        assert_eq!(guest_ports, [8080, 9090]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_apply_image_defaults_keeps_overrides() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let reference = Reference::from_str("localhost:5000/app:1.0")?;
+        let pool = oci_db_with_image(temp_dir.path(), &reference).await?;
+
+        let mut sandbox = Sandbox::builder()
+            .image(ReferenceOrPath::Reference(reference.clone()))
+            .command(vec!["run".to_string()])
+            .workdir(Utf8UnixPathBuf::from("/srv"))
+            .envs(vec![EnvPair::new("MODE", "sandbox")])
+            .ports(vec![PortPair::with_distinct(3000, 8080)])
+            .build();
+        apply_image_defaults(&mut sandbox, &reference, &pool).await?;
+
+        assert_eq!(sandbox.get_command(), &["run"]);
+        assert_eq!(
+            sandbox.get_workdir().as_ref().map(|dir| dir.as_str()),
+            Some("/srv")
+        );
+        assert_eq!(
+            sandbox.get_envs(),
+            &[
+                EnvPair::new("PATH", "/usr/bin"),
+                EnvPair::new("MODE", "sandbox")
+            ]
+        );
+        let ports = sandbox
+            .get_ports()
+            .iter()
+            .map(|port| (port.get_host(), port.get_guest()))
+            .collect::<Vec<_>>();
+        assert_eq!(ports, [(3000, 8080), (9090, 9090)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_apply_image_defaults_with_entrypoint_override() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let reference = Reference::from_str("localhost:5000/app:1.0")?;
+        let pool = oci_db_with_image(temp_dir.path(), &reference).await?;
+
+        // The override replaces the image's entrypoint and its cmd
+        let mut sandbox = Sandbox::builder()
+            .image(ReferenceOrPath::Reference(reference.clone()))
+            .entrypoint(vec!["/bin/sh".to_string(), "-c".to_string()])
+            .command(vec!["echo hi".to_string()])
+            .build();
+        apply_image_defaults(&mut sandbox, &reference, &pool).await?;
+
+        assert_eq!(
+            determine_exec_path_and_args(None, None, &sandbox, "app")?,
+            (
+                "/bin/sh".to_string(),
+                vec!["-c".to_string(), "echo hi".to_string()]
+            )
+        );
+        assert_eq!(
+            sandbox.get_workdir().as_ref().map(|dir| dir.as_str()),
+            Some("/app")
+        );
+
+        // Without an override, the image's entrypoint and cmd are used
+        let mut sandbox = Sandbox::builder()
+            .image(ReferenceOrPath::Reference(reference.clone()))
+            .build();
+        apply_image_defaults(&mut sandbox, &reference, &pool).await?;
+
+        assert_eq!(
+            determine_exec_path_and_args(None, None, &sandbox, "app")?,
+            ("/entrypoint.sh".to_string(), vec!["serve".to_string()])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_read_config_from_reader() -> anyhow::Result<()> {
+        let yaml = "sandboxes:\n  app:\n    image: alpine\n    shell: /bin/sh\n    ports:\n      - 8080:80\n";
+        let config = read_config(yaml.as_bytes()).await?;
+
+        let sandbox = config.get_sandbox("app").expect("sandbox should be loaded");
+        assert_eq!(sandbox.get_shell(), &Some("/bin/sh".to_string()));
+        assert_eq!(sandbox.get_ports().len(), 1);
+        assert!(config.validate().is_ok());
+
+        // Invalid YAML is rejected
+        assert!(read_config("sandboxes: [1, 2]\n".as_bytes()).await.is_err());
+
+        // A configuration from stdin has no file for the mutating commands to write to
+        let dir = tempdir()?;
+        let result = resolve_config_paths(Some(dir.path()), Some(STDIN_CONFIG_FILE)).await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::ConfigValidation(_))
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_write_config_rejects_invalid_contents() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join(MICROSANDBOX_CONFIG_FILENAME);
+        let original = "# hand-edited\nsandboxes:\n  app:\n    image: alpine\n";
+        fs::write(&config_path, original).await?;
+
+        let result = write_config(&config_path, "sandboxes: [1, 2]\n").await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::ConfigValidation(_))
+        ));
+
+        // The original is untouched and no temporary file is left behind
+        assert_eq!(fs::read_to_string(&config_path).await?, original);
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_add_keeps_config_on_invalid_result() -> anyhow::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir()?;
+        let config_path = temp_dir.path().join(MICROSANDBOX_CONFIG_FILENAME);
+        let original = "# hand-edited\nsandboxes:\n  app:\n    image: alpine # pinned\n";
+        fs::write(&config_path, original).await?;
+        fs::set_permissions(&config_path, std::fs::Permissions::from_mode(0o640)).await?;
+
+        let sandbox_config = |ports: Vec<String>| {
+            Component::Sandbox(Box::new(SandboxConfig {
+                image: "python".to_string(),
+                memory: None,
+                cpus: None,
+                volumes: Vec::new(),
+                ports,
+                envs: Vec::new(),
+                env_file: Vec::new(),
+                depends_on: Vec::new(),
+                workdir: None,
+                shell: None,
+                scripts: HashMap::new(),
+                imports: HashMap::new(),
+                exports: HashMap::new(),
+                scope: None,
+                rootfs_mode: None,
+            }))
+        };
+
+        // A port that doesn't parse makes the updated configuration invalid
+        let result = add(
+            &["web".to_string()],
+            &sandbox_config(vec!["not-a-port".to_string()]),
+            Some(temp_dir.path()),
+            None,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::ConfigValidation(_))
+        ));
+        assert_eq!(fs::read_to_string(&config_path).await?, original);
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+
+        // A valid addition keeps the hand edits and the permissions of the file
+        add(
+            &["web".to_string()],
+            &sandbox_config(vec!["8080:80".to_string()]),
+            Some(temp_dir.path()),
+            None,
+        )
+        .await?;
+        let contents = fs::read_to_string(&config_path).await?;
+        assert!(contents.starts_with("# hand-edited\n"));
+        assert!(contents.contains("image: alpine # pinned"));
+        let config: Microsandbox = serde_yaml::from_str(&contents)?;
+        assert!(config.get_sandbox("web").is_some());
+        assert_eq!(
+            std::fs::metadata(&config_path)?.permissions().mode() & 0o777,
+            0o640
+        );
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_config_resolve_effective_config() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let reference = Reference::from_str("localhost:5000/app:1.0")?;
+        let pool = oci_db_with_image(temp_dir.path(), &reference).await?;
+        fs::write(temp_dir.path().join(".env"), "LOG_LEVEL=debug\n").await?;
+
+        let sandbox = Sandbox::builder()
+            .image(ReferenceOrPath::Reference(reference.clone()))
+            .memory(1024)
+            .cpus(2)
+            .envs(vec![EnvPair::new("MODE", "sandbox")])
+            .env_file(vec![Utf8UnixPathBuf::from(".env")])
+            .ports(vec![PortPair::with_distinct(3000, 8080)])
+            .volumes(vec!["./data:/data".parse::<PathPair>()?])
+            .scripts(HashMap::from([("test".to_string(), "pytest".to_string())]))
+            .build();
+
+        let effective =
+            resolve_effective_config("app", sandbox, temp_dir.path(), Some(&pool)).await?;
+        assert_eq!(
+            serde_json::to_value(&effective)?,
+            serde_json::json!({
+                "sandbox": "app",
+                "image": reference.to_string(),
+                "image_defaults_applied": true,
+                "rootfs_mode": "overlay",
+                "memory": 1024,
+                "cpus": 2,
+                "workdir": "/app",
+                "shell": null,
+                "command": ["/entrypoint.sh", "serve"],
+                "entrypoint": [],
+                "envs": ["LOG_LEVEL=debug", "PATH=/usr/bin", "MODE=sandbox"],
+                "ports": ["3000:8080", "9090:9090"],
+                "volumes": ["./data:/data"],
+                "depends_on": [],
+                "scope": "public",
+                "network": "host",
+                "dns": "public",
+                "scripts": { "test": "pytest" },
+                "kernel_args": [],
+            })
+        );
+
+        Ok(())
+    }
+}
@@ -103,6 +103,16 @@ pub async fn get_or_create_pool(
     initialize(&db_path, migrator).await
 }
 
+/// Checks that the database behind the pool is reachable by running a trivial query.
+///
+/// ## Arguments
+///
+/// * `pool` - The database connection pool to check
+pub async fn ping(pool: &Pool<Sqlite>) -> MicrosandboxResult<()> {
+    sqlx::query("SELECT 1").execute(pool).await?;
+    Ok(())
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Sandboxes
 //--------------------------------------------------------------------------------------------------
@@ -248,6 +258,42 @@ pub(crate) async fn update_sandbox_status(
     Ok(())
 }
 
+/// Gets every sandbox recorded as running, across all config files
+pub(crate) async fn get_running_sandboxes(pool: &Pool<Sqlite>) -> MicrosandboxResult<Vec<Sandbox>> {
+    let records = sqlx::query(
+        r#"
+        SELECT id, name, config_file, config_last_modified, status,
+               supervisor_pid, microvm_pid, rootfs_paths,
+               created_at, modified_at
+        FROM sandboxes
+        WHERE status = ?
+        ORDER BY created_at DESC
+        "#,
+    )
+    .bind(SANDBOX_STATUS_RUNNING)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|row| Sandbox {
+            id: row.get("id"),
+            name: row.get("name"),
+            config_file: row.get("config_file"),
+            config_last_modified: row
+                .get::<String, _>("config_last_modified")
+                .parse::<DateTime<Utc>>()
+                .unwrap(),
+            status: row.get("status"),
+            supervisor_pid: row.get("supervisor_pid"),
+            microvm_pid: row.get("microvm_pid"),
+            rootfs_paths: row.get("rootfs_paths"),
+            created_at: parse_sqlite_datetime(&row.get::<String, _>("created_at")),
+            modified_at: parse_sqlite_datetime(&row.get::<String, _>("modified_at")),
+        })
+        .collect())
+}
+
 /// Gets all sandboxes associated with a specific config file
 pub(crate) async fn get_running_config_sandboxes(
     pool: &Pool<Sqlite>,
@@ -429,6 +475,11 @@ pub(crate) async fn save_config(
             .config
             .as_ref()
             .and_then(|c| c.user.as_ref().map(String::from)),
+        config_labels_json: config
+            .config
+            .as_ref()
+            .and_then(|c| c.labels.as_ref())
+            .map(|labels| serde_json::to_string(labels).unwrap_or_default()),
         rootfs_type: config.rootfs.r#type.to_string(),
         rootfs_diff_ids_json: Some(
             serde_json::to_string(&config.rootfs.diff_ids).unwrap_or_default(),
@@ -445,10 +496,10 @@ pub(crate) async fn save_config(
             os, os_variant, config_env_json, config_cmd_json,
             config_working_dir, config_entrypoint_json,
             config_volumes_json, config_exposed_ports_json,
-            config_user, rootfs_type, rootfs_diff_ids_json,
-            history_json
+            config_user, config_labels_json, rootfs_type,
+            rootfs_diff_ids_json, history_json
         )
-        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
         RETURNING id
         "#,
     )
@@ -465,6 +516,7 @@ pub(crate) async fn save_config(
     .bind(&config_model.config_volumes_json)
     .bind(&config_model.config_exposed_ports_json)
     .bind(&config_model.config_user)
+    .bind(&config_model.config_labels_json)
     .bind(&config_model.rootfs_type)
     .bind(&config_model.rootfs_diff_ids_json)
     .bind(&config_model.history_json)
@@ -671,8 +723,8 @@ pub(crate) async fn get_image_config(
                c.os, c.os_variant, c.config_env_json, c.config_cmd_json,
                c.config_working_dir, c.config_entrypoint_json,
                c.config_volumes_json, c.config_exposed_ports_json,
-               c.config_user, c.rootfs_type, c.rootfs_diff_ids_json,
-               c.history_json, c.created_at, c.modified_at
+               c.config_user, c.config_labels_json, c.rootfs_type,
+               c.rootfs_diff_ids_json, c.history_json, c.created_at, c.modified_at
         FROM configs c
         JOIN manifests m ON c.manifest_id = m.id
         JOIN images i ON m.image_id = i.id
@@ -701,6 +753,7 @@ pub(crate) async fn get_image_config(
         config_volumes_json: null_to_none(row.get("config_volumes_json")),
         config_exposed_ports_json: null_to_none(row.get("config_exposed_ports_json")),
         config_user: row.get("config_user"),
+        config_labels_json: null_to_none(row.get("config_labels_json")),
         rootfs_type: row.get("rootfs_type"),
         rootfs_diff_ids_json: row.get("rootfs_diff_ids_json"),
         history_json: null_to_none(row.get("history_json")),
@@ -709,6 +762,37 @@ pub(crate) async fn get_image_config(
     }))
 }
 
+/// Gets the annotations of the manifest stored for an image.
+///
+/// ## Arguments
+///
+/// * `pool` - SQLite connection pool
+/// * `reference` - OCI image reference string (e.g., "ubuntu:latest")
+///
+/// ## Returns
+///
+/// Returns the JSON string containing the annotations, or None if the image has no manifest or
+/// the manifest has no annotations
+pub(crate) async fn get_image_manifest_annotations(
+    pool: &Pool<Sqlite>,
+    reference: &str,
+) -> MicrosandboxResult<Option<String>> {
+    let record = sqlx::query(
+        r#"
+        SELECT m.annotations_json
+        FROM manifests m
+        JOIN images i ON m.image_id = i.id
+        WHERE i.reference = ?
+        LIMIT 1
+        "#,
+    )
+    .bind(reference)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(record.and_then(|row| null_to_none(row.get("annotations_json"))))
+}
+
 /// Saves or updates an image in the database.
 /// If the image exists, it updates the size_bytes and last_used_at.
 /// If it doesn't exist, creates a new record.
@@ -850,6 +934,52 @@ pub(crate) async fn get_image_layer_digests(
         .collect())
 }
 
+/// Gets the digests of the layers that only the given image uses.
+///
+/// Layers are shared between images that have them in common, so these are the layers that can
+/// be removed from disk without affecting any other image.
+///
+/// ## Arguments
+///
+/// * `pool` - SQLite connection pool
+/// * `reference` - OCI image reference string (e.g., "ubuntu:latest")
+///
+/// ## Returns
+///
+/// Returns a `MicrosandboxResult` containing a vector of layer digest strings
+pub(crate) async fn get_exclusive_image_layer_digests(
+    pool: &Pool<Sqlite>,
+    reference: &str,
+) -> MicrosandboxResult<Vec<String>> {
+    let records = sqlx::query(
+        r#"
+        SELECT l.digest
+        FROM layers l
+        JOIN manifest_layers ml ON l.id = ml.layer_id
+        JOIN manifests m ON ml.manifest_id = m.id
+        JOIN images i ON m.image_id = i.id
+        WHERE i.reference = ?
+        AND NOT EXISTS (
+            SELECT 1
+            FROM manifest_layers oml
+            JOIN manifests om ON oml.manifest_id = om.id
+            JOIN images oi ON om.image_id = oi.id
+            WHERE oml.layer_id = l.id AND oi.reference != ?
+        )
+        ORDER BY l.id ASC
+        "#,
+    )
+    .bind(reference)
+    .bind(reference)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(records
+        .into_iter()
+        .map(|row| row.get::<String, _>("digest"))
+        .collect())
+}
+
 /// Associates a layer with a manifest in the database.
 ///
 /// If the layer doesn't exist, it will be created first, before being
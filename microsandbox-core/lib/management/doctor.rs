@@ -0,0 +1,660 @@
+//! Environment diagnostics for `msb doctor`.
+//!
+//! Each check looks at one thing microsandbox needs from the host, such as the virtualization
+//! backend or the registry images are pulled from, and classifies it as passing, worth a warning
+//! or failing, with a hint at how to fix it. The checks take what they look at as arguments, so
+//! [`run`] is the only place that inspects the live host.
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use getset::Getters;
+use microsandbox_utils::{
+    DEFAULT_MSBRUN_EXE_PATH, DEFAULT_OCI_REGISTRY, LAYERS_SUBDIR, MSBRUN_EXE_ENV_VAR,
+    OCI_DB_FILENAME, env,
+};
+use reqwest::StatusCode;
+
+use crate::{
+    MicrosandboxError, MicrosandboxResult, management::db, runtime, utils::available_disk_space,
+    vm::PASST_EXE,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The host that serves the registry API of Docker Hub, which is known as `docker.io` in image
+/// references.
+const DOCKER_HUB_REGISTRY_HOST: &str = "registry-1.docker.io";
+
+/// How long the registry has to answer before it is considered unreachable.
+const REGISTRY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Free space in the layers directory below which pulls are likely to fail.
+const MIN_FREE_DISK_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Free space in the layers directory below which a warning is shown.
+const LOW_FREE_DISK_BYTES: u64 = 5 * 1024 * 1024 * 1024;
+
+/// The helper programs microsandbox starts, and whether sandboxes can run without them.
+pub const HELPER_BINARIES: &[HelperBinary] = &[
+    HelperBinary {
+        name: "msbrun",
+        required: true,
+        remediation: "Reinstall microsandbox with `curl -sSL https://get.microsandbox.dev | sh`, or set MSBRUN_EXE to the path of msbrun",
+    },
+    HelperBinary {
+        name: PASST_EXE,
+        required: false,
+        remediation: "Install passt from your package manager to use the `nat` network mode",
+    },
+];
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// The outcome of a single check, ordered from best to worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    /// Nothing to do.
+    Pass,
+
+    /// Microsandbox works, but something may get in the way.
+    Warn,
+
+    /// Microsandbox can't work until this is fixed.
+    Fail,
+}
+
+/// The result of a single check.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct Check {
+    /// What was checked, e.g. `backend`.
+    name: String,
+
+    /// The outcome of the check.
+    status: CheckStatus,
+
+    /// What was found.
+    message: String,
+
+    /// How to fix what was found, if the check didn't pass.
+    remediation: Option<String>,
+}
+
+/// The results of all checks.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct DoctorReport {
+    /// The results in the order the checks ran.
+    checks: Vec<Check>,
+}
+
+/// A helper program microsandbox starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HelperBinary {
+    /// The name of the program.
+    name: &'static str,
+
+    /// Whether sandboxes can't be started at all without it.
+    required: bool,
+
+    /// How to install the program.
+    remediation: &'static str,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl Check {
+    /// Creates a passing check.
+    fn pass(name: &str, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            message: message.into(),
+            remediation: None,
+        }
+    }
+
+    /// Creates a check that didn't pass, with how to fix it.
+    fn problem(
+        name: &str,
+        status: CheckStatus,
+        message: impl Into<String>,
+        remediation: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            message: message.into(),
+            remediation: Some(remediation.into()),
+        }
+    }
+}
+
+impl DoctorReport {
+    /// Creates a report from the results of the checks.
+    pub fn new(checks: Vec<Check>) -> Self {
+        Self { checks }
+    }
+
+    /// Returns the worst outcome of any check, which is [`CheckStatus::Pass`] if there are none.
+    pub fn status(&self) -> CheckStatus {
+        self.checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass)
+    }
+
+    /// Returns how many checks had the given outcome.
+    pub fn count(&self, status: CheckStatus) -> usize {
+        self.checks
+            .iter()
+            .filter(|check| check.status == status)
+            .count()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckStatus::Pass => write!(f, "pass"),
+            CheckStatus::Warn => write!(f, "warn"),
+            CheckStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Runs all checks against this host.
+///
+/// The checks cover the virtualization backend, the microsandbox home directory and its image
+/// database, the registry images are pulled from by default, the helper programs and the free
+/// disk space for image layers.
+///
+/// ## Returns
+///
+/// The report of all checks. A check that can't be carried out fails rather than returning an
+/// error, so the other checks still run.
+pub async fn run() -> DoctorReport {
+    let home_path = env::get_microsandbox_home_path();
+    let layers_dir = home_path.join(LAYERS_SUBDIR);
+    let registry = env::get_oci_registry();
+    let insecure = env::get_oci_insecure_registries().contains(&registry);
+
+    let registry_response = probe_registry(&registry_url(&registry, insecure)).await;
+
+    DoctorReport::new(vec![
+        check_backend(runtime::probe_backend()),
+        check_home(&home_path).await,
+        check_registry(&registry, registry_response),
+        check_helper_binaries(HELPER_BINARIES, find_helper_binary),
+        check_disk_space(&layers_dir, available_disk_space(&layers_dir)),
+    ])
+}
+
+/// Classifies the result of [`runtime::probe_backend`].
+pub fn check_backend(probe: MicrosandboxResult<()>) -> Check {
+    match probe {
+        Ok(()) => Check::pass("backend", "the virtualization backend is usable"),
+        Err(MicrosandboxError::BackendUnavailable {
+            reason,
+            remediation,
+        }) => Check::problem("backend", CheckStatus::Fail, reason, remediation),
+        Err(e) => Check::problem(
+            "backend",
+            CheckStatus::Fail,
+            e.to_string(),
+            "Check that libkrun is installed and that virtualization is enabled on this host",
+        ),
+    }
+}
+
+/// Checks that the microsandbox home directory can be written to and its image database opened.
+///
+/// A home directory that doesn't exist yet only passes, since it is created on first use.
+///
+/// ## Arguments
+///
+/// * `home_path` - The microsandbox home directory
+pub async fn check_home(home_path: &Path) -> Check {
+    const NAME: &str = "home";
+    let remediation = format!(
+        "Make sure {} is a directory your user can write to, or point MICROSANDBOX_HOME at one",
+        home_path.display()
+    );
+
+    let metadata = match tokio::fs::metadata(home_path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Check::pass(
+                NAME,
+                format!(
+                    "{} doesn't exist yet and will be created on first use",
+                    home_path.display()
+                ),
+            );
+        }
+        Err(e) => {
+            return Check::problem(
+                NAME,
+                CheckStatus::Fail,
+                format!("cannot access {}: {}", home_path.display(), e),
+                remediation,
+            );
+        }
+    };
+
+    if !metadata.is_dir() {
+        return Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("{} is not a directory", home_path.display()),
+            remediation,
+        );
+    }
+
+    let probe_path = home_path.join(".doctor-probe");
+    if let Err(e) = tokio::fs::write(&probe_path, b"").await {
+        return Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("cannot write to {}: {}", home_path.display(), e),
+            remediation,
+        );
+    }
+    let _ = tokio::fs::remove_file(&probe_path).await;
+
+    let db_path = home_path.join(OCI_DB_FILENAME);
+    if !db_path.exists() {
+        return Check::pass(NAME, format!("{} is writable", home_path.display()));
+    }
+
+    let database = async {
+        let pool = db::get_pool(&db_path).await?;
+        let result = db::ping(&pool).await;
+        pool.close().await;
+        result
+    }
+    .await;
+
+    match database {
+        Ok(()) => Check::pass(
+            NAME,
+            format!(
+                "{} is writable and its image database can be opened",
+                home_path.display()
+            ),
+        ),
+        Err(e) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!(
+                "cannot open the image database {}: {}",
+                db_path.display(),
+                e
+            ),
+            format!(
+                "Remove {} to have it recreated, or run `msb clean --all`",
+                db_path.display()
+            ),
+        ),
+    }
+}
+
+/// Classifies the response of the registry images are pulled from by default.
+///
+/// A registry is reachable if it answers its API's base endpoint with success or by asking for
+/// credentials, which is what public registries do.
+///
+/// ## Arguments
+///
+/// * `registry` - The registry as it appears in image references, e.g. `docker.io`
+/// * `response` - The status the registry answered with, or why it couldn't be reached
+pub fn check_registry(registry: &str, response: Result<StatusCode, String>) -> Check {
+    const NAME: &str = "registry";
+    match response {
+        Ok(status) if status.is_success() || status == StatusCode::UNAUTHORIZED => {
+            Check::pass(NAME, format!("{} is reachable", registry))
+        }
+        Ok(status) => Check::problem(
+            NAME,
+            CheckStatus::Warn,
+            format!("{} answered with {}", registry, status),
+            "Pulls may fail until the registry recovers. Use `--registry` to pull from another one",
+        ),
+        Err(e) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("cannot reach {}: {}", registry, e),
+            "Check your network connection and proxy settings, or use `--registry` to pull from a registry you can reach",
+        ),
+    }
+}
+
+/// Checks that the helper programs microsandbox starts can be found.
+///
+/// A missing program that sandboxes need fails, while one that only some features need is a
+/// warning.
+///
+/// ## Arguments
+///
+/// * `helpers` - The helper programs to look for
+/// * `find` - Returns the path of a helper program, or `None` if it can't be found
+pub fn check_helper_binaries(
+    helpers: &[HelperBinary],
+    find: impl Fn(&HelperBinary) -> Option<PathBuf>,
+) -> Check {
+    const NAME: &str = "helpers";
+    let missing: Vec<&HelperBinary> = helpers
+        .iter()
+        .filter(|helper| find(helper).is_none())
+        .collect();
+
+    if missing.is_empty() {
+        let names: Vec<&str> = helpers.iter().map(|helper| helper.name).collect();
+        return Check::pass(NAME, format!("found {}", names.join(", ")));
+    }
+
+    let status = if missing.iter().any(|helper| helper.required) {
+        CheckStatus::Fail
+    } else {
+        CheckStatus::Warn
+    };
+    let names: Vec<&str> = missing.iter().map(|helper| helper.name).collect();
+    let remediation: Vec<&str> = missing.iter().map(|helper| helper.remediation).collect();
+
+    Check::problem(
+        NAME,
+        status,
+        format!("cannot find {}", names.join(", ")),
+        remediation.join(". "),
+    )
+}
+
+/// Classifies the free disk space for image layers.
+///
+/// ## Arguments
+///
+/// * `layers_dir` - The directory image layers are extracted to
+/// * `available` - The free bytes on its file system, or `None` if they couldn't be determined
+pub fn check_disk_space(layers_dir: &Path, available: Option<u64>) -> Check {
+    const NAME: &str = "disk";
+    let remediation = format!(
+        "Free up space on the file system of {}, e.g. by removing unused images with `msb clean`",
+        layers_dir.display()
+    );
+
+    let Some(available) = available else {
+        return Check::problem(
+            NAME,
+            CheckStatus::Warn,
+            format!(
+                "cannot determine the free space for {}",
+                layers_dir.display()
+            ),
+            remediation,
+        );
+    };
+
+    let message = format!(
+        "{:.1} GiB free for {}",
+        available as f64 / (1024.0 * 1024.0 * 1024.0),
+        layers_dir.display()
+    );
+
+    if available < MIN_FREE_DISK_BYTES {
+        Check::problem(NAME, CheckStatus::Fail, message, remediation)
+    } else if available < LOW_FREE_DISK_BYTES {
+        Check::problem(NAME, CheckStatus::Warn, message, remediation)
+    } else {
+        Check::pass(NAME, message)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Returns the base endpoint of the registry API of a registry.
+fn registry_url(registry: &str, insecure: bool) -> String {
+    let host = if registry == DEFAULT_OCI_REGISTRY {
+        DOCKER_HUB_REGISTRY_HOST
+    } else {
+        registry
+    };
+    let scheme = if insecure { "http" } else { "https" };
+
+    format!("{}://{}/v2/", scheme, host)
+}
+
+/// Requests the base endpoint of a registry's API.
+async fn probe_registry(url: &str) -> Result<StatusCode, String> {
+    let client = reqwest::Client::builder()
+        .timeout(REGISTRY_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    client
+        .get(url)
+        .send()
+        .await
+        .map(|response| response.status())
+        .map_err(|e| e.to_string())
+}
+
+/// Returns the path of a helper program.
+///
+/// msbrun is looked up the way sandboxes are started, and other programs in `PATH`.
+fn find_helper_binary(helper: &HelperBinary) -> Option<PathBuf> {
+    if helper.name == "msbrun" {
+        return microsandbox_utils::path::resolve_env_path(
+            MSBRUN_EXE_ENV_VAR,
+            &*DEFAULT_MSBRUN_EXE_PATH,
+        )
+        .ok();
+    }
+
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(helper.name))
+        .find(|path| path.is_file())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    const GIB: u64 = 1024 * 1024 * 1024;
+
+    #[test]
+    fn test_report_status_is_worst_check() {
+        let pass = Check::pass("backend", "ok");
+        let warn = check_disk_space(Path::new("/layers"), Some(2 * GIB));
+        let fail = check_registry("docker.io", Err("connection refused".to_string()));
+
+        assert_eq!(DoctorReport::new(vec![]).status(), CheckStatus::Pass);
+        assert_eq!(
+            DoctorReport::new(vec![pass.clone()]).status(),
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            DoctorReport::new(vec![pass.clone(), warn.clone()]).status(),
+            CheckStatus::Warn
+        );
+
+        let report = DoctorReport::new(vec![fail, pass, warn]);
+        assert_eq!(report.status(), CheckStatus::Fail);
+        assert_eq!(report.count(CheckStatus::Pass), 1);
+        assert_eq!(report.count(CheckStatus::Warn), 1);
+        assert_eq!(report.count(CheckStatus::Fail), 1);
+    }
+
+    #[test]
+    fn test_check_backend() {
+        let check = check_backend(Ok(()));
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert_eq!(check.remediation, None);
+
+        let check = check_backend(Err(MicrosandboxError::BackendUnavailable {
+            reason: "cannot open /dev/kvm: permission denied".to_string(),
+            remediation: "sudo usermod -aG kvm $USER".to_string(),
+        }));
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert_eq!(check.message, "cannot open /dev/kvm: permission denied");
+        assert_eq!(
+            check.remediation.as_deref(),
+            Some("sudo usermod -aG kvm $USER")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_home() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+
+        // A home directory that doesn't exist yet is created on first use
+        let check = check_home(&temp_dir.path().join("missing")).await;
+        assert_eq!(check.status, CheckStatus::Pass);
+
+        // An existing one has to be writable, and its image database has to open
+        db::get_or_create_pool(temp_dir.path().join(OCI_DB_FILENAME), &db::OCI_DB_MIGRATOR)
+            .await?
+            .close()
+            .await;
+        let check = check_home(temp_dir.path()).await;
+        assert_eq!(check.status, CheckStatus::Pass);
+        assert!(check.message.contains("image database"));
+        assert!(!temp_dir.path().join(".doctor-probe").exists());
+
+        // A corrupt image database fails
+        let corrupt_home = tempdir()?;
+        std::fs::write(
+            corrupt_home.path().join(OCI_DB_FILENAME),
+            "not a sqlite database",
+        )?;
+        let check = check_home(corrupt_home.path()).await;
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.message.contains(OCI_DB_FILENAME));
+
+        // So does a home path that is a file
+        let file_path = temp_dir.path().join("file");
+        std::fs::write(&file_path, "")?;
+        let check = check_home(&file_path).await;
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(
+            check
+                .remediation
+                .as_deref()
+                .is_some_and(|remediation| remediation.contains("MICROSANDBOX_HOME"))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_check_registry() -> anyhow::Result<()> {
+        assert_eq!(
+            check_registry("docker.io", Ok(StatusCode::OK)).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            check_registry("docker.io", Ok(StatusCode::UNAUTHORIZED)).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            check_registry("docker.io", Ok(StatusCode::SERVICE_UNAVAILABLE)).status,
+            CheckStatus::Warn
+        );
+
+        // A registry nothing listens on can't be reached
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let host = listener.local_addr()?.to_string();
+        drop(listener);
+
+        let response = probe_registry(&registry_url(&host, true)).await;
+        let check = check_registry(&host, response);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert!(check.message.contains(&host));
+
+        assert_eq!(
+            registry_url("docker.io", false),
+            "https://registry-1.docker.io/v2/"
+        );
+        assert_eq!(
+            registry_url("localhost:5000", true),
+            "http://localhost:5000/v2/"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_helper_binaries() {
+        let found = |_: &HelperBinary| Some(PathBuf::from("/usr/bin/helper"));
+        let check = check_helper_binaries(HELPER_BINARIES, found);
+        assert_eq!(check.status, CheckStatus::Pass);
+
+        // Only an optional helper missing is a warning
+        let without_passt =
+            |helper: &HelperBinary| (helper.name != PASST_EXE).then(|| PathBuf::from("/bin/x"));
+        let check = check_helper_binaries(HELPER_BINARIES, without_passt);
+        assert_eq!(check.status, CheckStatus::Warn);
+        assert_eq!(check.message, "cannot find passt");
+
+        // A required helper missing fails
+        let check = check_helper_binaries(HELPER_BINARIES, |_| None);
+        assert_eq!(check.status, CheckStatus::Fail);
+        assert_eq!(check.message, "cannot find msbrun, passt");
+        assert!(
+            check
+                .remediation
+                .as_deref()
+                .is_some_and(|remediation| remediation.contains("MSBRUN_EXE"))
+        );
+    }
+
+    #[test]
+    fn test_check_disk_space() {
+        let layers_dir = Path::new("/home/user/.microsandbox/layers");
+
+        assert_eq!(
+            check_disk_space(layers_dir, Some(20 * GIB)).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            check_disk_space(layers_dir, Some(2 * GIB)).status,
+            CheckStatus::Warn
+        );
+        assert_eq!(
+            check_disk_space(layers_dir, Some(GIB / 2)).status,
+            CheckStatus::Fail
+        );
+        assert_eq!(check_disk_space(layers_dir, None).status, CheckStatus::Warn);
+
+        // The free space of a directory that doesn't exist yet is that of its parent
+        let temp_dir = tempdir().unwrap();
+        assert!(available_disk_space(&temp_dir.path().join("layers")).is_some());
+    }
+}
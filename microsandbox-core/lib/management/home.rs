@@ -194,7 +194,7 @@ pub async fn install(
     let alias_name = alias
         .map(|a| a.to_string())
         .or_else(|| script.map(|s| s.to_string()))
-        .unwrap_or_else(|| extract_name_from_reference(image));
+        .unwrap_or_else(|| image.name().to_string());
 
     tracing::info!("Setting up alias: {}", alias_name);
 
@@ -250,7 +250,7 @@ pub async fn install(
     // Apply image configuration defaults if enabled
     if use_image_defaults {
         // Pull the image from the registry if not already pulled
-        Image::pull(image.clone(), None).await?;
+        Image::pull(image.clone(), None, false, false).await?;
 
         // Get the OCI database path and create a connection pool
         let db_path = home_path.join(OCI_DB_FILENAME);
@@ -402,28 +402,8 @@ fn command_exists(command: &str) -> bool {
         .unwrap_or(false)
 }
 
-/// Extracts a simple name from an OCI image reference
-///
-/// For example:
-/// - "docker.io/library/ubuntu:latest" -> "ubuntu"
-/// - "registry.com/org/app:v1.0" -> "app"
-/// - "myapp:stable" -> "myapp"
-fn extract_name_from_reference(reference: &Reference) -> String {
-    let image_str = reference.to_string();
-
-    // Split the image string by '/' and take the last part
-    let name_with_tag = image_str.rsplit('/').next().unwrap_or(&image_str);
-
-    // Split by ':' to remove the tag and take the first part
-    name_with_tag
-        .split(':')
-        .next()
-        .unwrap_or(name_with_tag)
-        .to_string()
-}
-
 /// Generate the content for the alias script based on the alias name and optional script.
-fn generate_alias_script(alias: &str, script: Option<&str>) -> String {
+pub(crate) fn generate_alias_script(alias: &str, script: Option<&str>) -> String {
     let run_command = if let Some(script_name) = script {
         format!(
             "exec \"$MSB_PATH\" run \"{}~{}\" -f \"$HOME/{}\" \"$@\"",
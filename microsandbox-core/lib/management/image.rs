@@ -0,0 +1,1083 @@
+//! Image inspection and export for Microsandbox.
+//!
+//! This module surfaces what is recorded about pulled images in the OCI database, such as the
+//! image labels and manifest annotations, and the defaults the image configuration provides for
+//! sandboxes.
+//!
+//! Pulled images can also be exported, either as an OCI image layout directory or as a tarball in
+//! the format of `docker save`. Only the extracted layers are kept after a pull, so the layers are
+//! repacked from their extracted directories, and the manifest and configuration are rebuilt from
+//! the database with the diff IDs of the repacked layers.
+//!
+//! Images pulled into a custom layer directory get an index written next to their layers, which
+//! maps each pulled reference to its layers and configuration so the directory describes itself.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, hash_map::Entry},
+    fs::File,
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use flate2::{Compression, write::GzEncoder};
+use getset::Getters;
+use microsandbox_utils::{
+    EXTRACTED_LAYER_SUFFIX, EXTRACTION_COMPLETE_SUFFIX, LAYERS_SUBDIR, OCI_DB_FILENAME, env,
+};
+use nix::fcntl::{Flock, FlockArg};
+use oci_spec::image::MediaType;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+use sha2::{Digest, Sha256};
+use sqlx::{Pool, Sqlite};
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
+
+use crate::{
+    MicrosandboxError, MicrosandboxResult,
+    management::db,
+    models::{Config, Layer},
+    oci::Reference,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The xattr the original ownership and mode of extracted entries are stored in.
+const STAT_OVERRIDE_XATTR: &str = "user.containers.override_stat";
+
+/// The version of the OCI image layout format that is written.
+const OCI_LAYOUT_VERSION: &str = "1.0.0";
+
+/// The annotation of an OCI layout index entry that holds the tag of the image.
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// The name of the file that lists the images in a `docker save` tarball.
+const DOCKER_ARCHIVE_MANIFEST: &str = "manifest.json";
+
+/// The name of the file that lists the images pulled into a custom layer directory.
+///
+/// This is not `index.json`, so the directory is not mistaken for an OCI image layout.
+const LAYER_INDEX_FILENAME: &str = "microsandbox-index.json";
+
+/// The name of the lock file that serializes updates to the layer index.
+const LAYER_INDEX_LOCK_FILENAME: &str = "microsandbox-index.json.lock";
+
+/// The version of the layer index format that is written.
+const LAYER_INDEX_VERSION: u32 = 1;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Details of a pulled image.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct ImageDetails {
+    /// The image reference.
+    reference: String,
+
+    /// The architecture the image was built for.
+    architecture: String,
+
+    /// The operating system the image was built for.
+    os: String,
+
+    /// The labels of the image configuration, e.g. `org.opencontainers.image.version`.
+    labels: BTreeMap<String, String>,
+
+    /// The annotations of the image manifest.
+    annotations: BTreeMap<String, String>,
+
+    /// The default environment variables, in `NAME=value` form.
+    env: Vec<String>,
+
+    /// The default entrypoint.
+    entrypoint: Vec<String>,
+
+    /// The default command, or the arguments to the entrypoint.
+    cmd: Vec<String>,
+
+    /// The default working directory.
+    working_dir: Option<String>,
+
+    /// The exposed ports, e.g. `80/tcp`.
+    exposed_ports: Vec<String>,
+}
+
+/// The format a pulled image is exported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExportFormat {
+    /// An OCI image layout directory, as read by `msb pull oci:<path>` and `skopeo`.
+    #[default]
+    Oci,
+
+    /// A tarball in the format written by `docker save`, as read by `docker load`.
+    DockerArchive,
+}
+
+/// A layer written into an OCI image layout.
+struct PackedLayer {
+    /// The digest of the compressed layer.
+    digest: String,
+
+    /// The digest of the uncompressed layer tarball.
+    diff_id: String,
+
+    /// The size of the compressed layer in bytes.
+    size: u64,
+}
+
+/// A writer that hashes everything written through it with SHA-256.
+struct HashingWriter<W> {
+    /// The writer the data is passed on to.
+    inner: W,
+
+    /// The hash of the data written so far.
+    hasher: Sha256,
+
+    /// The number of bytes written so far.
+    size: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl<W: Write> HashingWriter<W> {
+    /// Creates a writer that hashes the data it passes on to `inner`.
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Returns the inner writer, along with the `sha256:` digest and size of the data written.
+    fn finish(self) -> (W, String, u64) {
+        let digest = format!("sha256:{}", hex::encode(self.hasher.finalize()));
+        (self.inner, digest, self.size)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl FromStr for ExportFormat {
+    type Err = MicrosandboxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "oci" => Ok(ExportFormat::Oci),
+            "docker-archive" => Ok(ExportFormat::DockerArchive),
+            _ => Err(MicrosandboxError::InvalidArgument(format!(
+                "invalid export format: {s}, expected one of oci, docker-archive"
+            ))),
+        }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Inspects a pulled image.
+///
+/// ## Arguments
+///
+/// * `reference` - The reference of the image to inspect
+///
+/// ## Returns
+///
+/// The details recorded for the image, or `MicrosandboxError::ImageNotFound` if the image has not
+/// been pulled
+pub async fn inspect(reference: &Reference) -> MicrosandboxResult<ImageDetails> {
+    let db_path = env::get_microsandbox_home_path().join(OCI_DB_FILENAME);
+    let pool = db::get_or_create_pool(&db_path, &db::OCI_DB_MIGRATOR).await?;
+    get_image_details(&pool, reference).await
+}
+
+/// Exports a pulled image to an OCI image layout or a `docker save` tarball.
+///
+/// ## Arguments
+///
+/// * `reference` - The reference of the image to export
+/// * `dest` - The directory to write the OCI image layout to, which must not exist or be empty,
+///   or the path of the tarball to write
+/// * `format` - The format to export the image in
+///
+/// ## Returns
+///
+/// `MicrosandboxError::ImageNotFound` if the image has not been pulled
+pub async fn export(
+    reference: &Reference,
+    dest: &Path,
+    format: ExportFormat,
+) -> MicrosandboxResult<()> {
+    let microsandbox_home_path = env::get_microsandbox_home_path();
+    let db_path = microsandbox_home_path.join(OCI_DB_FILENAME);
+    let pool = db::get_or_create_pool(&db_path, &db::OCI_DB_MIGRATOR).await?;
+    let layers_dir = microsandbox_home_path.join(LAYERS_SUBDIR);
+    export_image(&pool, &layers_dir, reference, dest, format).await
+}
+
+/// Records a pulled image in the index of the layer directory it was pulled into.
+///
+/// The index is a JSON file named `microsandbox-index.json` in `layers_dir`, mapping each image
+/// reference to its layers from the lowest up, with the directory each is extracted to, and to its
+/// configuration. An existing entry for the reference is replaced and the other entries are kept,
+/// so several images can share a directory. The index is updated under a lock, so concurrent
+/// pulls into the same directory don't drop each other's entries.
+///
+/// ## Arguments
+///
+/// * `pool` - The OCI database the image is recorded in
+/// * `layers_dir` - The directory the layers of the image are extracted in
+/// * `reference` - The reference of the pulled image
+pub(crate) async fn write_layer_index(
+    pool: &Pool<Sqlite>,
+    layers_dir: &Path,
+    reference: &Reference,
+) -> MicrosandboxResult<()> {
+    let Some(config) = db::get_image_config(pool, &reference.as_db_key()).await? else {
+        return Err(MicrosandboxError::ImageNotFound(reference.to_string()));
+    };
+
+    let layers = get_extracted_layers(pool, layers_dir, reference, &config).await?;
+    let mut image_config = image_config_json(&config);
+    let diff_ids: Vec<&str> = layers
+        .iter()
+        .map(|(layer, _)| layer.diff_id.as_str())
+        .collect();
+    image_config.insert(
+        "rootfs".to_string(),
+        json!({ "type": "layers", "diff_ids": diff_ids }),
+    );
+
+    let layer_entries: Vec<Value> = layers
+        .iter()
+        .map(|(layer, layer_dir)| {
+            json!({
+                "mediaType": layer.media_type,
+                "digest": layer.digest,
+                "diffId": layer.diff_id,
+                "size": layer.size_bytes,
+                "path": layer_dir.file_name().map(|name| name.to_string_lossy()),
+            })
+        })
+        .collect();
+    let entry = json!({
+        "config": image_config,
+        "layers": layer_entries,
+    });
+
+    let layers_dir = layers_dir.to_path_buf();
+    let reference = reference.to_string();
+    tokio::task::spawn_blocking(move || {
+        let lock_file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(layers_dir.join(LAYER_INDEX_LOCK_FILENAME))?;
+        let _lock = Flock::lock(lock_file, FlockArg::LockExclusive).map_err(|(_, errno)| errno)?;
+
+        let index_path = layers_dir.join(LAYER_INDEX_FILENAME);
+        let mut images = match std::fs::read(&index_path) {
+            Ok(data) => serde_json::from_slice::<Value>(&data)?
+                .get("images")
+                .and_then(Value::as_object)
+                .cloned()
+                .unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+            Err(e) => return Err(e.into()),
+        };
+        images.insert(reference, entry);
+
+        let index = json!({
+            "version": LAYER_INDEX_VERSION,
+            "images": images,
+        });
+
+        // Replace the index in one step so a reader never sees it half written
+        let mut temp_file = NamedTempFile::new_in(&layers_dir)?;
+        serde_json::to_writer_pretty(&mut temp_file, &index)?;
+        temp_file.persist(&index_path).map_err(|e| e.error)?;
+
+        Ok(())
+    })
+    .await?
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Reads the details of an image from the OCI database.
+async fn get_image_details(
+    pool: &Pool<Sqlite>,
+    reference: &Reference,
+) -> MicrosandboxResult<ImageDetails> {
+    let key = reference.as_db_key();
+    let Some(config) = db::get_image_config(pool, &key).await? else {
+        return Err(MicrosandboxError::ImageNotFound(reference.to_string()));
+    };
+
+    let annotations = db::get_image_manifest_annotations(pool, &key).await?;
+
+    // Ports are stored as an object keyed by `<port>/<protocol>`
+    let exposed_ports = parse_json::<BTreeMap<String, serde_json::Value>>(
+        config.config_exposed_ports_json.as_deref(),
+    )
+    .map(|ports| ports.into_keys().collect())
+    .unwrap_or_default();
+
+    Ok(ImageDetails {
+        reference: reference.to_string(),
+        architecture: config.architecture,
+        os: config.os,
+        labels: parse_json(config.config_labels_json.as_deref()).unwrap_or_default(),
+        annotations: parse_json(annotations.as_deref()).unwrap_or_default(),
+        env: parse_json(config.config_env_json.as_deref()).unwrap_or_default(),
+        entrypoint: parse_json(config.config_entrypoint_json.as_deref()).unwrap_or_default(),
+        cmd: parse_json(config.config_cmd_json.as_deref()).unwrap_or_default(),
+        working_dir: config.config_working_dir.filter(|dir| !dir.is_empty()),
+        exposed_ports,
+    })
+}
+
+/// Parses an optional JSON column, treating unparsable values as missing.
+fn parse_json<T: serde::de::DeserializeOwned>(json: Option<&str>) -> Option<T> {
+    json.and_then(|json| serde_json::from_str(json).ok())
+}
+
+/// Exports a pulled image whose layers are extracted in `layers_dir`.
+async fn export_image(
+    pool: &Pool<Sqlite>,
+    layers_dir: &Path,
+    reference: &Reference,
+    dest: &Path,
+    format: ExportFormat,
+) -> MicrosandboxResult<()> {
+    let key = reference.as_db_key();
+    let Some(config) = db::get_image_config(pool, &key).await? else {
+        return Err(MicrosandboxError::ImageNotFound(reference.to_string()));
+    };
+
+    let annotations: Option<Map<String, Value>> = parse_json(
+        db::get_image_manifest_annotations(pool, &key)
+            .await?
+            .as_deref(),
+    );
+    let layer_dirs = get_extracted_layers(pool, layers_dir, reference, &config)
+        .await?
+        .into_iter()
+        .map(|(_, layer_dir)| layer_dir)
+        .collect::<Vec<_>>();
+    let image_config = image_config_json(&config);
+
+    // Repacking the layers is blocking work on the filesystem
+    let reference = reference.clone();
+    let dest = dest.to_path_buf();
+    tokio::task::spawn_blocking(move || match format {
+        ExportFormat::Oci => {
+            write_oci_layout(&dest, &reference, image_config, annotations, &layer_dirs)
+        }
+        ExportFormat::DockerArchive => {
+            write_docker_archive(&dest, &reference, image_config, &layer_dirs)
+        }
+    })
+    .await?
+}
+
+/// Returns the layers of an image with their extracted directories, from the lowest layer up.
+///
+/// The layer records of an image aren't stored in order, so the layers are ordered by the diff IDs
+/// in the image configuration.
+async fn get_extracted_layers(
+    pool: &Pool<Sqlite>,
+    layers_dir: &Path,
+    reference: &Reference,
+    config: &Config,
+) -> MicrosandboxResult<Vec<(Layer, PathBuf)>> {
+    let digests = db::get_image_layer_digests(pool, &reference.as_db_key()).await?;
+    let layers = db::get_layers_by_digest(pool, &digests).await?;
+    let diff_ids: Vec<String> =
+        parse_json(config.rootfs_diff_ids_json.as_deref()).unwrap_or_default();
+
+    let mut extracted = Vec::with_capacity(diff_ids.len());
+    for diff_id in &diff_ids {
+        let Some(layer) = layers.iter().find(|layer| &layer.diff_id == diff_id) else {
+            return Err(MicrosandboxError::ImageExport(format!(
+                "no layer of {reference} has diff ID {diff_id}, pull the image again"
+            )));
+        };
+
+        let layer_dir = layers_dir.join(format!("{}.{}", layer.digest, EXTRACTED_LAYER_SUFFIX));
+        let marker_path = layers_dir.join(format!(
+            "{}.{}.{}",
+            layer.digest, EXTRACTED_LAYER_SUFFIX, EXTRACTION_COMPLETE_SUFFIX
+        ));
+        if !layer_dir.exists() || !marker_path.exists() {
+            return Err(MicrosandboxError::ImageExport(format!(
+                "layer {} of {reference} is not extracted, pull the image again",
+                layer.digest
+            )));
+        }
+
+        extracted.push((layer.clone(), layer_dir));
+    }
+
+    Ok(extracted)
+}
+
+/// Rebuilds the configuration of an image from its database record.
+///
+/// The root filesystem is left out, since the diff IDs change when the layers are repacked.
+fn image_config_json(config: &Config) -> Map<String, Value> {
+    let mut container_config = Map::new();
+    let json_columns = [
+        ("Env", &config.config_env_json),
+        ("Entrypoint", &config.config_entrypoint_json),
+        ("Cmd", &config.config_cmd_json),
+        ("Volumes", &config.config_volumes_json),
+        ("ExposedPorts", &config.config_exposed_ports_json),
+        ("Labels", &config.config_labels_json),
+    ];
+    for (key, column) in json_columns {
+        match parse_json::<Value>(column.as_deref()) {
+            None | Some(Value::Null) => {}
+            // Sets are objects with empty values in image configurations
+            Some(Value::Array(items)) if matches!(key, "Volumes" | "ExposedPorts") => {
+                let set = items
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|item| (item.to_string(), json!({})))
+                    .collect();
+                container_config.insert(key.to_string(), Value::Object(set));
+            }
+            Some(value) => {
+                container_config.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    let string_columns = [
+        ("WorkingDir", &config.config_working_dir),
+        ("User", &config.config_user),
+    ];
+    for (key, column) in string_columns {
+        if let Some(value) = column.as_ref().filter(|value| !value.is_empty()) {
+            container_config.insert(key.to_string(), json!(value));
+        }
+    }
+
+    let mut image_config = Map::new();
+    if let Some(created) = config.created {
+        image_config.insert("created".to_string(), json!(created.to_rfc3339()));
+    }
+    image_config.insert("architecture".to_string(), json!(config.architecture));
+    image_config.insert("os".to_string(), json!(config.os));
+    image_config.insert("config".to_string(), Value::Object(container_config));
+    if let Some(history) =
+        parse_json::<Value>(config.history_json.as_deref()).filter(|history| !history.is_null())
+    {
+        image_config.insert("history".to_string(), history);
+    }
+
+    image_config
+}
+
+/// Writes an image as an OCI image layout in `dest`, with its layers gzip-compressed.
+fn write_oci_layout(
+    dest: &Path,
+    reference: &Reference,
+    mut image_config: Map<String, Value>,
+    annotations: Option<Map<String, Value>>,
+    layer_dirs: &[PathBuf],
+) -> MicrosandboxResult<()> {
+    match std::fs::read_dir(dest) {
+        Ok(mut entries) => {
+            if entries.next().is_some() {
+                return Err(MicrosandboxError::ImageExport(format!(
+                    "{} already exists and is not empty",
+                    dest.display()
+                )));
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => std::fs::create_dir_all(dest)?,
+        Err(e) => return Err(e.into()),
+    }
+
+    let blobs_dir = dest.join("blobs").join("sha256");
+    std::fs::create_dir_all(&blobs_dir)?;
+
+    let mut layers = Vec::with_capacity(layer_dirs.len());
+    for layer_dir in layer_dirs {
+        // The digest of a layer is only known once it is written, so it is named afterwards
+        let temp_file = NamedTempFile::new_in(&blobs_dir)?;
+        let encoder = GzEncoder::new(HashingWriter::new(temp_file), Compression::default());
+        let (encoder, diff_id) = pack_layer(layer_dir, encoder)?;
+        let (temp_file, digest, size) = encoder.finish()?.finish();
+        temp_file
+            .persist(blob_path(&blobs_dir, &digest))
+            .map_err(|e| e.error)?;
+
+        layers.push(PackedLayer {
+            digest,
+            diff_id,
+            size,
+        });
+    }
+
+    let diff_ids: Vec<&str> = layers.iter().map(|layer| layer.diff_id.as_str()).collect();
+    image_config.insert(
+        "rootfs".to_string(),
+        json!({ "type": "layers", "diff_ids": diff_ids }),
+    );
+    let config_descriptor = write_json_blob(
+        &blobs_dir,
+        &MediaType::ImageConfig,
+        &Value::Object(image_config),
+    )?;
+
+    let layer_descriptors: Vec<Value> = layers
+        .iter()
+        .map(|layer| {
+            json!({
+                "mediaType": MediaType::ImageLayerGzip.to_string(),
+                "digest": layer.digest,
+                "size": layer.size,
+            })
+        })
+        .collect();
+    let mut manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MediaType::ImageManifest.to_string(),
+        "config": config_descriptor,
+        "layers": layer_descriptors,
+    });
+    if let Some(annotations) = annotations.filter(|annotations| !annotations.is_empty()) {
+        manifest["annotations"] = Value::Object(annotations);
+    }
+
+    let mut manifest_descriptor =
+        write_json_blob(&blobs_dir, &MediaType::ImageManifest, &manifest)?;
+    if let Some(tag) = reference.tag() {
+        manifest_descriptor["annotations"] = json!({});
+        manifest_descriptor["annotations"][REF_NAME_ANNOTATION] = json!(tag);
+    }
+
+    let index = json!({
+        "schemaVersion": 2,
+        "mediaType": MediaType::ImageIndex.to_string(),
+        "manifests": [manifest_descriptor],
+    });
+    std::fs::write(dest.join("index.json"), serde_json::to_vec(&index)?)?;
+    std::fs::write(
+        dest.join("oci-layout"),
+        serde_json::to_vec(&json!({ "imageLayoutVersion": OCI_LAYOUT_VERSION }))?,
+    )?;
+
+    Ok(())
+}
+
+/// Writes an image as a `docker save` tarball at `dest`, with its layers uncompressed.
+fn write_docker_archive(
+    dest: &Path,
+    reference: &Reference,
+    mut image_config: Map<String, Value>,
+    layer_dirs: &[PathBuf],
+) -> MicrosandboxResult<()> {
+    // The size of an entry must be known before it is added, so layers are staged next to the
+    // tarball first
+    let parent_dir = dest
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let staging_dir = tempfile::tempdir_in(parent_dir)?;
+
+    let mut staged_layers = Vec::with_capacity(layer_dirs.len());
+    for (index, layer_dir) in layer_dirs.iter().enumerate() {
+        let staged_path = staging_dir.path().join(format!("{index}.tar"));
+        let (file, diff_id) = pack_layer(layer_dir, File::create(&staged_path)?)?;
+        file.sync_all()?;
+        staged_layers.push((staged_path, diff_id));
+    }
+
+    let diff_ids: Vec<&str> = staged_layers
+        .iter()
+        .map(|(_, diff_id)| diff_id.as_str())
+        .collect();
+    image_config.insert(
+        "rootfs".to_string(),
+        json!({ "type": "layers", "diff_ids": diff_ids }),
+    );
+    let image_config = serde_json::to_vec(&Value::Object(image_config))?;
+    let config_name = format!("{}.json", hex::encode(Sha256::digest(&image_config)));
+
+    let mut builder = tar::Builder::new(NamedTempFile::new_in(parent_dir)?);
+    let mut layer_names = Vec::with_capacity(staged_layers.len());
+    let mut appended = HashSet::new();
+    for (staged_path, diff_id) in &staged_layers {
+        let name = format!("{}/layer.tar", hex_part(diff_id));
+        // Identical layers are stored once and listed for each place they are used
+        if appended.insert(name.clone()) {
+            builder.append_path_with_name(staged_path, &name)?;
+        }
+        layer_names.push(name);
+    }
+
+    let manifest = json!([{
+        "Config": config_name,
+        "RepoTags": docker_repo_tags(reference),
+        "Layers": layer_names,
+    }]);
+    append_file_data(&mut builder, &config_name, &image_config)?;
+    append_file_data(
+        &mut builder,
+        DOCKER_ARCHIVE_MANIFEST,
+        &serde_json::to_vec(&manifest)?,
+    )?;
+
+    let temp_file = builder.into_inner()?;
+    temp_file.as_file().sync_all()?;
+    temp_file.persist(dest).map_err(|e| e.error)?;
+
+    Ok(())
+}
+
+/// Packs an extracted layer directory into a tarball written to `writer`.
+///
+/// Entries get back the ownership and mode recorded in their stat override xattr when they were
+/// extracted, and files that share an inode are packed as hard links to the first of them.
+///
+/// ## Returns
+///
+/// The writer, and the diff ID of the layer, which is the digest of the tarball
+fn pack_layer<W: Write>(layer_dir: &Path, writer: W) -> MicrosandboxResult<(W, String)> {
+    let mut builder = tar::Builder::new(HashingWriter::new(writer));
+    let mut inodes: HashMap<(u64, u64), PathBuf> = HashMap::new();
+
+    for entry in WalkDir::new(layer_dir)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let relative_path = path
+            .strip_prefix(layer_dir)
+            .map_err(|e| anyhow::anyhow!("failed to pack {}: {e}", path.display()))?
+            .to_path_buf();
+
+        let metadata = entry.metadata()?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata_in_mode(&metadata, tar::HeaderMode::Complete);
+        if let Some((uid, gid, mode)) = read_stat_override(path)? {
+            header.set_uid(uid);
+            header.set_gid(gid);
+            header.set_mode(mode & 0o7777);
+        }
+
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            builder.append_link(&mut header, &relative_path, std::fs::read_link(path)?)?;
+        } else if file_type.is_file() && metadata.nlink() > 1 {
+            match inodes.entry((metadata.dev(), metadata.ino())) {
+                Entry::Occupied(target) => {
+                    header.set_entry_type(tar::EntryType::Link);
+                    header.set_size(0);
+                    builder.append_link(&mut header, &relative_path, target.get())?;
+                }
+                Entry::Vacant(vacant) => {
+                    vacant.insert(relative_path.clone());
+                    builder.append_data(&mut header, &relative_path, File::open(path)?)?;
+                }
+            }
+        } else if file_type.is_file() {
+            builder.append_data(&mut header, &relative_path, File::open(path)?)?;
+        } else {
+            // Directories and special files such as device nodes have no data
+            builder.append_data(&mut header, &relative_path, io::empty())?;
+        }
+    }
+
+    let (writer, diff_id, _) = builder.into_inner()?.finish();
+    Ok((writer, diff_id))
+}
+
+/// Reads the ownership and mode recorded in the stat override xattr of an extracted entry.
+///
+/// ## Returns
+///
+/// The uid, gid and mode, including the file type bits, or None if the entry has no valid
+/// override
+fn read_stat_override(path: &Path) -> MicrosandboxResult<Option<(u64, u64, u32)>> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(None);
+    }
+
+    let Some(value) = xattr::get(path, STAT_OVERRIDE_XATTR)? else {
+        return Ok(None);
+    };
+
+    // The override is stored as `<uid>:<gid>:0<octal mode>`
+    let value = String::from_utf8_lossy(&value);
+    let fields: Vec<&str> = value.split(':').collect();
+    let stat = match fields[..] {
+        [uid, gid, mode] => match (
+            uid.parse::<u64>(),
+            gid.parse::<u64>(),
+            u32::from_str_radix(mode, 8),
+        ) {
+            (Ok(uid), Ok(gid), Ok(mode)) => Some((uid, gid, mode)),
+            _ => None,
+        },
+        _ => None,
+    };
+
+    if stat.is_none() {
+        tracing::warn!(
+            "ignoring invalid stat override {:?} on {}",
+            value,
+            path.display()
+        );
+    }
+
+    Ok(stat)
+}
+
+/// Writes a JSON document as a blob of an OCI image layout.
+///
+/// ## Returns
+///
+/// The descriptor of the blob
+fn write_json_blob(
+    blobs_dir: &Path,
+    media_type: &MediaType,
+    document: &Value,
+) -> MicrosandboxResult<Value> {
+    let blob = serde_json::to_vec(document)?;
+    let digest = format!("sha256:{}", hex::encode(Sha256::digest(&blob)));
+    std::fs::write(blob_path(blobs_dir, &digest), &blob)?;
+
+    Ok(json!({
+        "mediaType": media_type.to_string(),
+        "digest": digest,
+        "size": blob.len(),
+    }))
+}
+
+/// Returns the path of a `sha256:` blob in the blobs directory of an OCI image layout.
+fn blob_path(blobs_dir: &Path, digest: &str) -> PathBuf {
+    blobs_dir.join(hex_part(digest))
+}
+
+/// Returns the hex part of a digest such as `sha256:abc...`.
+fn hex_part(digest: &str) -> &str {
+    digest.split_once(':').map_or(digest, |(_, hex)| hex)
+}
+
+/// Adds a regular file with the given contents to a tarball.
+fn append_file_data<W: Write>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    data: &[u8],
+) -> MicrosandboxResult<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Returns the names `docker load` should tag an image with, e.g. `docker.io/library/alpine:3.20`.
+///
+/// Images pulled from an OCI image layout are named after the layout directory, and images pulled
+/// by digest alone aren't tagged.
+fn docker_repo_tags(reference: &Reference) -> Vec<String> {
+    let Some(tag) = reference.tag() else {
+        return Vec::new();
+    };
+
+    match reference.layout() {
+        Some(_) => vec![format!("{}:{tag}", reference.repository())],
+        None => vec![format!(
+            "{}/{}:{tag}",
+            reference.registry(),
+            reference.repository()
+        )],
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use oci_client::manifest::OciImageManifest;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::oci::mocks::{LayoutFixture, mock_registry_and_db};
+
+    #[tokio::test]
+    async fn test_image_inspect_labels_and_annotations() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let pool =
+            db::get_or_create_pool(&temp_dir.path().join("db"), &db::OCI_DB_MIGRATOR).await?;
+        let reference = Reference::from_str("localhost:5000/app:1.0")?;
+
+        assert!(matches!(
+            get_image_details(&pool, &reference).await,
+            Err(MicrosandboxError::ImageNotFound(_))
+        ));
+
+        let image_id = db::save_or_update_image(&pool, &reference.as_db_key(), 0).await?;
+        let manifest: OciImageManifest = serde_json::from_value(serde_json::json!({
+            "schemaVersion": 2,
+            "config": { "mediaType": "application/vnd.oci.image.config.v1+json", "digest": "sha256:00", "size": 0 },
+            "layers": [],
+            "annotations": { "org.opencontainers.image.source": "https://example.com/app" },
+        }))?;
+        let manifest_id = db::save_manifest(&pool, image_id, &manifest).await?;
+        let config = serde_json::from_value(serde_json::json!({
+            "architecture": "amd64",
+            "os": "linux",
+            "config": {
+                "Env": ["PATH=/usr/bin"],
+                "Cmd": ["serve"],
+                "WorkingDir": "/app",
+                "ExposedPorts": { "8080/tcp": {} },
+                "Labels": { "org.opencontainers.image.version": "1.0.3" },
+            },
+            "rootfs": { "type": "layers", "diff_ids": [] },
+        }))?;
+        db::save_config(&pool, manifest_id, &config).await?;
+
+        let details = get_image_details(&pool, &reference).await?;
+        assert_eq!(
+            details.get_labels().get("org.opencontainers.image.version"),
+            Some(&"1.0.3".to_string())
+        );
+        assert_eq!(
+            details
+                .get_annotations()
+                .get("org.opencontainers.image.source"),
+            Some(&"https://example.com/app".to_string())
+        );
+        assert_eq!(details.get_env(), &["PATH=/usr/bin"]);
+        assert_eq!(details.get_cmd(), &["serve"]);
+        assert!(details.get_entrypoint().is_empty());
+        assert_eq!(details.get_working_dir().as_deref(), Some("/app"));
+        assert_eq!(details.get_exposed_ports(), &["8080/tcp"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_export_oci_layout_round_trip() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(Some("1.0"))?;
+        let (registry, db, temp_dir) = mock_registry_and_db().await;
+        let reference = fixture.reference("1.0")?;
+        registry.pull_image(&reference, false).await?;
+
+        let export_dir = tempdir()?;
+        let layout_dir = export_dir.path().join("layout");
+        let layers_dir = temp_dir.path().join("extracted");
+        export_image(&db, &layers_dir, &reference, &layout_dir, ExportFormat::Oci).await?;
+
+        // An existing layout isn't written over
+        assert!(matches!(
+            export_image(&db, &layers_dir, &reference, &layout_dir, ExportFormat::Oci).await,
+            Err(MicrosandboxError::ImageExport(_))
+        ));
+
+        // The exported layout is pulled into a fresh database and layer cache
+        let (registry, db, temp_dir) = mock_registry_and_db().await;
+        let exported = Reference::from_str(&format!("oci:{}:1.0", layout_dir.display()))?;
+        registry.pull_image(&exported, false).await?;
+
+        let digests = db::get_image_layer_digests(&db, &exported.as_db_key()).await?;
+        assert_eq!(digests.len(), 1);
+        let layer_dir = temp_dir
+            .path()
+            .join("extracted")
+            .join(format!("{}.{EXTRACTED_LAYER_SUFFIX}", digests[0]));
+        assert_eq!(
+            std::fs::read_to_string(layer_dir.join("hello.txt"))?,
+            "hello"
+        );
+
+        let details = get_image_details(&db, &exported).await?;
+        assert_eq!(details.get_os(), "linux");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_image_export_docker_archive() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(Some("1.0"))?;
+        let (registry, db, temp_dir) = mock_registry_and_db().await;
+        let reference = fixture.reference("1.0")?;
+        registry.pull_image(&reference, false).await?;
+
+        let export_dir = tempdir()?;
+        let archive_path = export_dir.path().join("image.tar");
+        let layers_dir = temp_dir.path().join("extracted");
+        export_image(
+            &db,
+            &layers_dir,
+            &reference,
+            &archive_path,
+            ExportFormat::DockerArchive,
+        )
+        .await?;
+
+        let mut entries = BTreeMap::new();
+        let mut archive = tar::Archive::new(File::open(&archive_path)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().into_owned();
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            entries.insert(path, data);
+        }
+
+        let manifest: Value = serde_json::from_slice(&entries[DOCKER_ARCHIVE_MANIFEST])?;
+        assert_eq!(
+            manifest[0]["RepoTags"],
+            json!([format!("{}:1.0", reference.repository())])
+        );
+
+        // The configuration lists the digest of each layer tarball as its diff ID
+        let config_name = manifest[0]["Config"].as_str().unwrap_or_default();
+        let config: Value = serde_json::from_slice(&entries[config_name])?;
+        let layer_name = manifest[0]["Layers"][0].as_str().unwrap_or_default();
+        let layer = &entries[layer_name];
+        assert_eq!(
+            config["rootfs"]["diff_ids"],
+            json!([format!("sha256:{}", hex::encode(Sha256::digest(layer)))])
+        );
+
+        // The layer holds the files of the image with their original mode
+        let mut layer = tar::Archive::new(layer.as_slice());
+        let mut entry = layer
+            .entries()?
+            .next()
+            .expect("layer should not be empty")?;
+        assert_eq!(entry.path()?.to_str(), Some("hello.txt"));
+        assert_eq!(entry.header().mode()?, 0o644);
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents)?;
+        assert_eq!(contents, "hello");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_layer_index_describes_custom_layer_dir() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(Some("1.0"))?;
+        let (registry, db, temp_dir) = mock_registry_and_db().await;
+        let reference = fixture.reference("1.0")?;
+        registry.pull_image(&reference, false).await?;
+
+        // The mocked registry extracts layers into a directory of its own, as with `--layer-path`
+        let layers_dir = temp_dir.path().join("extracted");
+        write_layer_index(&db, &layers_dir, &reference).await?;
+        // Writing the index again replaces the entry of the image
+        write_layer_index(&db, &layers_dir, &reference).await?;
+
+        let index: Value =
+            serde_json::from_slice(&std::fs::read(layers_dir.join(LAYER_INDEX_FILENAME))?)?;
+        assert_eq!(index["version"], json!(LAYER_INDEX_VERSION));
+        let images = index["images"]
+            .as_object()
+            .expect("images should be an object");
+        assert_eq!(images.len(), 1);
+
+        let image = &images[&reference.to_string()];
+        assert_eq!(image["config"]["os"], json!("linux"));
+
+        let digests = db::get_image_layer_digests(&db, &reference.as_db_key()).await?;
+        let layers = image["layers"]
+            .as_array()
+            .expect("layers should be an array");
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0]["digest"], json!(digests[0]));
+        assert_eq!(
+            image["config"]["rootfs"]["diff_ids"],
+            json!([layers[0]["diffId"]])
+        );
+
+        // Each layer points at its extracted directory, relative to the index
+        let layer_dir = layers_dir.join(layers[0]["path"].as_str().unwrap_or_default());
+        assert_eq!(
+            std::fs::read_to_string(layer_dir.join("hello.txt"))?,
+            "hello"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_layer_index_keeps_concurrent_entries() -> anyhow::Result<()> {
+        let fixtures = [
+            LayoutFixture::new(Some("1.0"))?,
+            LayoutFixture::new(Some("1.0"))?,
+        ];
+        let (registry, db, temp_dir) = mock_registry_and_db().await;
+        let mut references = Vec::new();
+        for fixture in &fixtures {
+            let reference = fixture.reference("1.0")?;
+            registry.pull_image(&reference, false).await?;
+            references.push(reference);
+        }
+
+        // Both pulls update the index of the same directory at once
+        let layers_dir = temp_dir.path().join("extracted");
+        let (first, second) = tokio::join!(
+            write_layer_index(&db, &layers_dir, &references[0]),
+            write_layer_index(&db, &layers_dir, &references[1]),
+        );
+        first?;
+        second?;
+
+        let index: Value =
+            serde_json::from_slice(&std::fs::read(layers_dir.join(LAYER_INDEX_FILENAME))?)?;
+        let images = index["images"]
+            .as_object()
+            .expect("images should be an object");
+        assert_eq!(images.len(), 2);
+        for reference in &references {
+            assert!(images.contains_key(&reference.to_string()));
+        }
+
+        // The directory is not mistaken for an OCI image layout
+        assert!(!layers_dir.join("index.json").exists());
+
+        Ok(())
+    }
+}
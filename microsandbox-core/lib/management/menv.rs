@@ -5,18 +5,33 @@
 //! necessary components for running sandboxes, including configuration files,
 //! databases, and log directories.
 
-use crate::{MicrosandboxError, MicrosandboxResult};
+use crate::{MicrosandboxError, MicrosandboxResult, config::Sandbox};
 
 #[cfg(feature = "cli")]
 use microsandbox_utils::term;
 use microsandbox_utils::{
-    DEFAULT_CONFIG, LOG_SUBDIR, MICROSANDBOX_CONFIG_FILENAME, MICROSANDBOX_ENV_DIR, PATCH_SUBDIR,
-    RW_SUBDIR, SANDBOX_DB_FILENAME,
+    CONFIG_TEMPLATE_FILE, CONSOLE_LOG_SUFFIX, DEFAULT_CONFIG, LOG_SUBDIR, LOG_SUFFIX,
+    MICROSANDBOX_CONFIG_FILENAME, MICROSANDBOX_ENV_DIR, PATCH_SUBDIR, ROOTFS_SUBDIR, RW_SUBDIR,
+    SANDBOX_DB_FILENAME, env, log,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::SeekFrom,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::mpsc,
 };
-use std::path::{Path, PathBuf};
-use tokio::{fs, io::AsyncWriteExt};
 
-use super::{config, db};
+use super::{config, db, orchestra};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -31,12 +46,97 @@ const CREATE_DEFAULT_CONFIG_MSG: &str = "Create default config file";
 #[cfg(feature = "cli")]
 const CLEAN_SANDBOX_MSG: &str = "Clean sandbox";
 
+/// The number of lines shown before following a log when no tail is given, matching `tail -F`.
+const DEFAULT_FOLLOW_TAIL_LINES: usize = 10;
+
+/// How often a followed log is checked for new lines when its directory can't be watched.
+const LOG_FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// The output format of a sandbox list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListFormat {
+    /// A human readable listing.
+    #[default]
+    Table,
+
+    /// A JSON array with one object per sandbox.
+    Json,
+
+    /// A YAML sequence with one mapping per sandbox.
+    Yaml,
+}
+
+/// A serializable view of a sandbox, as shown in machine readable sandbox lists.
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxListEntry {
+    /// The name of the sandbox.
+    pub name: String,
+
+    /// The image or rootfs path the sandbox runs.
+    pub image: String,
+
+    /// The number of vCPUs, if limited.
+    pub cpus: Option<u8>,
+
+    /// The memory in MiB, if limited.
+    pub memory: Option<u32>,
+
+    /// The network scope of the sandbox.
+    pub scope: String,
+
+    /// The port mappings, as `host:guest`.
+    pub ports: Vec<String>,
+
+    /// The volume mappings, as `host:guest`.
+    pub volumes: Vec<String>,
+
+    /// The labels of the sandbox, sorted by key.
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Streams the lines appended to a log file, surviving rotation and recreation.
+///
+/// The file is tracked by its device and inode, so when the path is rotated away or recreated on a
+/// sandbox restart, the rest of the old file is read before switching to the new one. Changes are
+/// picked up through the platform's file watcher (inotify or kqueue) on the log's directory.
+#[derive(Debug)]
+pub struct LogFollower {
+    /// The path of the followed log file.
+    path: PathBuf,
+
+    /// The currently open file, if the path existed when last checked.
+    file: Option<fs::File>,
+
+    /// The device and inode of the currently open file.
+    identity: Option<(u64, u64)>,
+
+    /// The offset up to which the current file has been read.
+    offset: u64,
+
+    /// The bytes after the last newline read so far.
+    partial: Vec<u8>,
+
+    /// The watcher of the log's directory, if the platform's file watcher could be set up.
+    watcher: Option<RecommendedWatcher>,
+
+    /// Receives a message whenever the watcher sees the log or one of its rotated files change.
+    changes: mpsc::UnboundedReceiver<()>,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
 
 /// Initialize a new microsandbox environment at the specified path
 ///
+/// A new config file is created from the template at
+/// `<MICROSANDBOX_HOME_DIR>/<CONFIG_TEMPLATE_FILE>` if there is one, or from the built-in
+/// default otherwise.
+///
 /// ## Arguments
 /// * `project_dir` - Optional path where the microsandbox environment will be initialized. If None, uses current directory
 ///
@@ -216,6 +316,7 @@ pub async fn clean(
     // Clean up sandbox-specific directories
     let rw_path = menv_path.join(RW_SUBDIR).join(&scoped_name);
     let patch_path = menv_path.join(PATCH_SUBDIR).join(&scoped_name);
+    let rootfs_path = menv_path.join(ROOTFS_SUBDIR).join(&scoped_name);
 
     // Remove sandbox directories if they exist
     if rw_path.exists() {
@@ -231,6 +332,14 @@ pub async fn clean(
         );
     }
 
+    if rootfs_path.exists() {
+        fs::remove_dir_all(&rootfs_path).await?;
+        tracing::info!(
+            "Removed sandbox rootfs directory at {}",
+            rootfs_path.display()
+        );
+    }
+
     // Remove log file if it exists
     let log_file = menv_path
         .join(LOG_SUBDIR)
@@ -242,6 +351,18 @@ pub async fn clean(
         tracing::info!("Removed sandbox log file at {}", log_file.display());
     }
 
+    // Remove the captured console output if there is any
+    let console_log_file =
+        get_console_log_path(&menv_path.join(LOG_SUBDIR), config_file, sandbox_name);
+
+    if console_log_file.exists() {
+        fs::remove_file(&console_log_file).await?;
+        tracing::info!(
+            "Removed sandbox console log file at {}",
+            console_log_file.display()
+        );
+    }
+
     // Remove sandbox from database
     let db_path = menv_path.join(SANDBOX_DB_FILENAME);
     if db_path.exists() {
@@ -259,16 +380,21 @@ pub async fn clean(
 /// Show logs for a sandbox
 ///
 /// This function can show logs for a sandbox in either follow mode or regular mode.
-/// In follow mode, it uses `tail -f` to continuously show new log entries.
-/// In regular mode, it shows either all logs or the last N lines.
+/// In follow mode, it shows the last N lines (10 by default) and then continuously shows new log
+/// entries, across rotations and sandbox restarts. In regular mode, it shows either all logs or
+/// the last N lines, including the lines in rotated log files.
+///
+/// With `console`, the guest console output captured for the sandbox is shown instead of its
+/// logs. It is only captured when the sandbox was started with console capture enabled.
 ///
 /// ## Arguments
 /// * `project_dir` - Optional path where the microsandbox environment is located.
 ///   If None, uses current directory
 /// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 /// * `sandbox_name` - Name of the sandbox to show logs for
-/// * `follow` - Whether to follow the log file (tail -f mode)
+/// * `follow` - Whether to keep following the log file for new entries
 /// * `tail` - Optional number of lines to show from the end
+/// * `console` - Whether to show the captured guest console output instead of the logs
 ///
 /// ## Example
 /// ```no_run
@@ -277,13 +403,16 @@ pub async fn clean(
 ///
 /// # async fn example() -> anyhow::Result<()> {
 /// // Show all logs for a sandbox
-/// menv::show_log(None::<&Path>, None, "my-sandbox", false, None).await?;
+/// menv::show_log(None::<&Path>, None, "my-sandbox", false, None, false).await?;
 ///
 /// // Show last 100 lines of logs
-/// menv::show_log(None::<&Path>, None, "my-sandbox", false, Some(100)).await?;
+/// menv::show_log(None::<&Path>, None, "my-sandbox", false, Some(100), false).await?;
 ///
 /// // Follow logs in real-time
-/// menv::show_log(None::<&Path>, None, "my-sandbox", true, None).await?;
+/// menv::show_log(None::<&Path>, None, "my-sandbox", true, None, false).await?;
+///
+/// // Show the guest console output captured while booting
+/// menv::show_log(None::<&Path>, None, "my-sandbox", false, None, true).await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -293,74 +422,54 @@ pub async fn show_log(
     sandbox_name: &str,
     follow: bool,
     tail: Option<usize>,
+    console: bool,
 ) -> MicrosandboxResult<()> {
-    // Check if tail command exists when follow mode is requested
-    if follow {
-        let tail_exists = which::which("tail").is_ok();
-        if !tail_exists {
-            return Err(MicrosandboxError::CommandNotFound(
-                "tail command not found. Please install it to use the follow (-f) option."
-                    .to_string(),
-            ));
-        }
-    }
-
     // Load the configuration to get canonical paths
     let (_, canonical_project_dir, config_file) =
         config::load_config(project_dir.as_ref().map(|p| p.as_ref()), config_file).await?;
 
     // Construct log file path using the hierarchical structure: <project_dir>/.menv/log/<config>/<sandbox>.log
-    let log_path = canonical_project_dir
+    let log_dir = canonical_project_dir
         .join(MICROSANDBOX_ENV_DIR)
-        .join(LOG_SUBDIR)
-        .join(&config_file)
-        .join(format!("{}.log", sandbox_name));
+        .join(LOG_SUBDIR);
+    let log_path = if console {
+        get_console_log_path(&log_dir, &config_file, sandbox_name)
+    } else {
+        get_log_path(&log_dir, &config_file, sandbox_name)
+    };
 
     // Check if log file exists
-    if !log_path.exists() {
-        return Err(MicrosandboxError::LogNotFound(format!(
-            "Log file not found at {}",
-            log_path.display()
-        )));
+    if log::log_segments(&log_path).is_empty() {
+        let message = if console {
+            format!(
+                "Console log not found at {}. Start the sandbox with console capture enabled to record it",
+                log_path.display()
+            )
+        } else {
+            format!("Log file not found at {}", log_path.display())
+        };
+
+        return Err(MicrosandboxError::LogNotFound(message));
     }
 
     if follow {
-        // For follow mode, use tokio::process::Command to run `tail -f`
-        let mut child = tokio::process::Command::new("tail")
-            .arg("-f")
-            .arg(&log_path)
-            .stdout(std::process::Stdio::inherit())
-            .stderr(std::process::Stdio::inherit())
-            .spawn()?;
-
-        // Wait for the tail process
-        let status = child.wait().await?;
-        if !status.success() {
-            return Err(MicrosandboxError::ProcessWaitError(format!(
-                "tail process exited with status: {}",
-                status
-            )));
+        // Follow on from the end of the tail, so no line written in between is lost
+        let tail = tail.unwrap_or(DEFAULT_FOLLOW_TAIL_LINES);
+        let (mut follower, lines) = LogFollower::with_tail(&log_path, tail).await?;
+        for line in lines {
+            println!("{}", line);
         }
-    } else {
-        // Read the file contents
-        let contents = tokio::fs::read_to_string(&log_path).await?;
-
-        // Split into lines
-        let lines: Vec<&str> = contents.lines().collect();
-
-        // If tail is specified, only show the last N lines
-        let lines_to_print = if let Some(n) = tail {
-            if n >= lines.len() {
-                &lines[..]
-            } else {
-                &lines[lines.len() - n..]
-            }
-        } else {
-            &lines[..]
-        };
 
-        // Print the lines
-        for line in lines_to_print {
+        // Keep streaming new lines until interrupted
+        loop {
+            follower.changed().await;
+            for line in follower.poll().await? {
+                println!("{}", line);
+            }
+        }
+    } else {
+        // Print the lines, oldest rotated file first
+        for line in read_log(&log_path, tail).await? {
             println!("{}", line);
         }
     }
@@ -368,12 +477,37 @@ pub async fn show_log(
     Ok(())
 }
 
+/// Formats a list of sandboxes as JSON or YAML
+///
+/// Sandboxes are sorted by name so the output is stable across runs. [`ListFormat::Table`] is
+/// rendered as JSON here, since the table is only ever printed by [`show_list`].
+///
+/// ## Arguments
+/// * `sandboxes` - The sandbox names and configurations to format
+/// * `format` - The format to render the list in
+pub fn format_list<'a, I>(sandboxes: I, format: ListFormat) -> MicrosandboxResult<String>
+where
+    I: IntoIterator<Item = (&'a String, &'a Sandbox)>,
+{
+    let mut entries: Vec<SandboxListEntry> = sandboxes
+        .into_iter()
+        .map(|(name, sandbox)| SandboxListEntry::new(name, sandbox))
+        .collect();
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    match format {
+        ListFormat::Yaml => Ok(serde_yaml::to_string(&entries)?),
+        ListFormat::Json | ListFormat::Table => Ok(serde_json::to_string_pretty(&entries)?),
+    }
+}
+
 /// Show a formatted list of sandboxes
 ///
 /// This function can display sandbox information from any config in a standardized format.
 ///
 /// ## Arguments
 /// * `sandboxes` - A reference to a HashMap of sandbox configurations
+/// * `format` - Whether to print a human readable table, JSON or YAML
 ///
 /// ## Example
 /// ```no_run
@@ -384,29 +518,34 @@ pub async fn show_log(
 /// # async fn example() -> anyhow::Result<()> {
 /// // Show all sandboxes for a local project
 /// let (config, _, _) = config::load_config(None::<&Path>, None).await?;
-/// menv::show_list(config.get_sandboxes());
+/// menv::show_list(config.get_sandboxes(), menv::ListFormat::Table)?;
 ///
 /// // Show all sandboxes for a specific project directory
 /// let project_path = Path::new("/path/to/project");
 /// let (config, _, _) = config::load_config(Some(project_path), None).await?;
-/// menv::show_list(config.get_sandboxes());
+/// menv::show_list(config.get_sandboxes(), menv::ListFormat::Json)?;
 /// # Ok(())
 /// # }
 /// ```
 #[cfg(feature = "cli")]
-pub fn show_list<'a, I>(sandboxes: I)
+pub fn show_list<'a, I>(sandboxes: I, format: ListFormat) -> MicrosandboxResult<()>
 where
-    I: IntoIterator<Item = (&'a String, &'a crate::config::Sandbox)>,
+    I: IntoIterator<Item = (&'a String, &'a Sandbox)>,
 {
     use console::style;
     use std::collections::HashMap;
 
+    if format != ListFormat::Table {
+        println!("{}", format_list(sandboxes, format)?);
+        return Ok(());
+    }
+
     // Convert the iterator into a HashMap for easier processing
-    let sandboxes: HashMap<&String, &crate::config::Sandbox> = sandboxes.into_iter().collect();
+    let sandboxes: HashMap<&String, &Sandbox> = sandboxes.into_iter().collect();
 
     if sandboxes.is_empty() {
         println!("No sandboxes found");
-        return;
+        return Ok(());
     }
 
     for (i, (name, sandbox)) in sandboxes.iter().enumerate() {
@@ -432,8 +571,26 @@ where
             println!("   {}: {}", style("Resources").dim(), resources.join(", "));
         }
 
-        // Network
-        println!("   {}: {}", style("Network").dim(), sandbox.get_scope());
+        // Network, where the scope only applies to sockets carried over the host's network
+        let network = sandbox.network_or_default();
+        if network.is_host() {
+            println!(
+                "   {}: {}",
+                style("Network").dim(),
+                sandbox.scope_or_default()
+            );
+        } else {
+            println!("   {}: {}", style("Network").dim(), network);
+        }
+
+        // Labels
+        if !sandbox.get_labels().is_empty() {
+            println!(
+                "   {}: {}",
+                style("Labels").dim(),
+                orchestra::format_labels(sandbox.get_labels())
+            );
+        }
 
         // Ports
         if !sandbox.get_ports().is_empty() {
@@ -479,6 +636,134 @@ where
     }
 
     println!("\n{}: {}", style("Total").dim(), sandboxes.len());
+
+    Ok(())
+}
+
+/// Returns the path of the log the supervisor of a sandbox writes to.
+///
+/// The log is namespaced by config file: `<log_dir>/<config_file>/<sandbox_name>.log`.
+///
+/// ## Arguments
+/// * `log_dir` - The log directory of the microsandbox environment
+/// * `config_file` - The config file the sandbox is defined in
+/// * `sandbox_name` - The name of the sandbox
+pub(crate) fn get_log_path(log_dir: &Path, config_file: &str, sandbox_name: &str) -> PathBuf {
+    log_dir
+        .join(config_file)
+        .join(format!("{}.{}", sandbox_name, LOG_SUFFIX))
+}
+
+/// Returns the path of the file the guest console output of a sandbox is captured to.
+///
+/// The file sits next to the sandbox log: `<log_dir>/<config_file>/<sandbox_name>.console.log`.
+///
+/// ## Arguments
+/// * `log_dir` - The log directory of the microsandbox environment
+/// * `config_file` - The config file the sandbox is defined in
+/// * `sandbox_name` - The name of the sandbox
+pub(crate) fn get_console_log_path(
+    log_dir: &Path,
+    config_file: &str,
+    sandbox_name: &str,
+) -> PathBuf {
+    log_dir
+        .join(config_file)
+        .join(format!("{}.{}", sandbox_name, CONSOLE_LOG_SUFFIX))
+}
+
+/// Appends the lines of a single file of a rotating log to `lines`.
+///
+/// A file that was rotated away between listing and reading it is skipped.
+async fn read_segment(segment: &Path, lines: &mut Vec<String>) -> MicrosandboxResult<()> {
+    let contents = match fs::read(segment).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    lines.extend(
+        String::from_utf8_lossy(&contents)
+            .lines()
+            .map(|line| line.to_string()),
+    );
+
+    Ok(())
+}
+
+/// Drops all but the last `n` lines.
+fn keep_tail(lines: &mut Vec<String>, n: usize) {
+    if n < lines.len() {
+        lines.drain(..lines.len() - n);
+    }
+}
+
+/// Watches the directory of the log at `path` for changes to the log and its rotated files.
+///
+/// ## Returns
+/// The watcher, or None if the platform's file watcher couldn't be set up, and the receiver of a
+/// message for every change
+fn watch_log(path: &Path) -> (Option<RecommendedWatcher>, mpsc::UnboundedReceiver<()>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let name = path.file_name().map(|name| name.to_os_string());
+    let rotated = log::rotated_log_path(path, 0)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    let is_log = move |changed: &Path| {
+        changed.file_name().is_some_and(|changed| {
+            Some(changed) == name.as_deref()
+                || rotated
+                    .as_deref()
+                    .is_some_and(|rotated| changed.to_string_lossy().starts_with(rotated))
+        })
+    };
+
+    // Watcher errors wake the follower too, which then finds out for itself what changed
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if event.map_or(true, |event| event.paths.iter().any(|path| is_log(path))) {
+            let _ = tx.send(());
+        }
+    })
+    .and_then(|mut watcher| {
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        Ok(watcher)
+    })
+    .inspect_err(|e| {
+        tracing::debug!(
+            "cannot watch {}, checking it every {:?} instead: {}",
+            dir.display(),
+            LOG_FOLLOW_INTERVAL,
+            e
+        )
+    })
+    .ok();
+
+    (watcher, rx)
+}
+
+/// Reads the lines of a rotating log, including the lines in its rotated files.
+///
+/// ## Arguments
+/// * `log_path` - Path to the active log file
+/// * `tail` - Optional number of lines to return from the end
+///
+/// ## Returns
+/// The log lines in the order they were written
+pub async fn read_log(log_path: &Path, tail: Option<usize>) -> MicrosandboxResult<Vec<String>> {
+    let mut lines = Vec::new();
+    for segment in log::log_segments(log_path) {
+        read_segment(&segment, &mut lines).await?;
+    }
+
+    if let Some(n) = tail {
+        keep_tail(&mut lines, n);
+    }
+
+    Ok(lines)
 }
 
 /// Show a formatted list of sandboxes across multiple projects
@@ -604,7 +889,7 @@ pub async fn show_list_projects(projects_parent_dir: &std::path::Path) -> Micros
             // Only print if there are sandboxes
             if sandbox_count > 0 {
                 print_project_header(&data.name);
-                show_list(config.get_sandboxes());
+                show_list(config.get_sandboxes(), ListFormat::Table)?;
             }
         } else if let Some(err) = &data.error {
             print_project_header(&data.name);
@@ -661,18 +946,73 @@ pub(crate) async fn ensure_menv_files(menv_path: &Path) -> MicrosandboxResult<()
     Ok(())
 }
 
+/// Returns the contents new configuration files are created with
+///
+/// This is the template at `<MICROSANDBOX_HOME_DIR>/<CONFIG_TEMPLATE_FILE>` if there is one, and
+/// the built-in [`DEFAULT_CONFIG`] otherwise. A template that isn't a valid configuration is
+/// rejected rather than copied into new projects.
+pub async fn default_config() -> MicrosandboxResult<String> {
+    read_config_template(&config_template_path()).await
+}
+
+/// Returns the path of the template new configuration files are created from
+fn config_template_path() -> PathBuf {
+    env::get_microsandbox_home_path().join(CONFIG_TEMPLATE_FILE)
+}
+
+/// Reads a configuration template, falling back to the built-in default if it doesn't exist
+async fn read_config_template(template_path: &Path) -> MicrosandboxResult<String> {
+    let contents = match fs::read_to_string(template_path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(DEFAULT_CONFIG.to_string()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut problems: Vec<String> = crate::config::validate(&contents)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    if problems.is_empty()
+        && let Err(e) = serde_yaml::from_str::<crate::config::Microsandbox>(&contents)
+    {
+        problems.push(e.to_string());
+    }
+
+    if !problems.is_empty() {
+        return Err(MicrosandboxError::ConfigValidation(format!(
+            "config template {} is invalid: {}",
+            template_path.display(),
+            problems.join("; ")
+        )));
+    }
+
+    tracing::debug!("using config template {}", template_path.display());
+    Ok(contents)
+}
+
 /// Create a default microsandbox configuration file
 pub(crate) async fn create_default_config(project_dir: &Path) -> MicrosandboxResult<()> {
+    create_config_from_template(project_dir, &config_template_path()).await
+}
+
+/// Create a microsandbox configuration file from a template, unless one already exists
+async fn create_config_from_template(
+    project_dir: &Path,
+    template_path: &Path,
+) -> MicrosandboxResult<()> {
     let config_path = project_dir.join(MICROSANDBOX_CONFIG_FILENAME);
 
     // Only create if it doesn't exist
     if !config_path.exists() {
+        // Read the template first, so an invalid one doesn't leave an empty config file behind
+        let contents = read_config_template(template_path).await?;
+
         #[cfg(feature = "cli")]
         let create_default_config_sp =
             term::create_spinner(CREATE_DEFAULT_CONFIG_MSG.to_string(), None, None);
 
         let mut file = fs::File::create(&config_path).await?;
-        file.write_all(DEFAULT_CONFIG.as_bytes()).await?;
+        file.write_all(contents.as_bytes()).await?;
 
         #[cfg(feature = "cli")]
         create_default_config_sp.finish();
@@ -711,3 +1051,411 @@ pub(crate) async fn update_gitignore(project_dir: &Path) -> MicrosandboxResult<(
 
     Ok(())
 }
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl LogFollower {
+    /// Starts following the log at `path` from its current end.
+    ///
+    /// The file doesn't have to exist yet; it is picked up once it is created.
+    pub async fn new(path: impl AsRef<Path>) -> MicrosandboxResult<Self> {
+        let mut follower = Self::unopened(path.as_ref());
+
+        if let Some((file, identity)) = follower.open_current().await? {
+            follower.offset = file.metadata().await?.len();
+            follower.file = Some(file);
+            follower.identity = Some(identity);
+        }
+
+        Ok(follower)
+    }
+
+    /// Starts following the log at `path`, returning its last `tail` lines.
+    ///
+    /// The tail of the active file is read through the file the follower goes on reading, so a
+    /// line written while the tail is read is neither lost nor returned twice.
+    pub async fn with_tail(
+        path: impl AsRef<Path>,
+        tail: usize,
+    ) -> MicrosandboxResult<(Self, Vec<String>)> {
+        let path = path.as_ref();
+        let mut follower = Self::unopened(path);
+        let current = follower.open_current().await?;
+
+        let mut lines = Vec::new();
+        for segment in log::log_segments(path) {
+            if segment != path {
+                read_segment(&segment, &mut lines).await?;
+            }
+        }
+
+        if let Some((file, identity)) = current {
+            follower.file = Some(file);
+            follower.identity = Some(identity);
+            follower.read_new_lines(&mut lines).await?;
+        }
+
+        keep_tail(&mut lines, tail);
+        Ok((follower, lines))
+    }
+
+    /// Waits until the followed log may have changed.
+    ///
+    /// Changes are coalesced, so a burst of writes wakes the follower once. Without a file
+    /// watcher, this waits for a fixed interval instead.
+    pub async fn changed(&mut self) {
+        if self.watcher.is_none() || self.changes.recv().await.is_none() {
+            tokio::time::sleep(LOG_FOLLOW_INTERVAL).await;
+            return;
+        }
+
+        while self.changes.try_recv().is_ok() {}
+    }
+
+    /// Creates a follower of the log at `path` that hasn't opened the file yet.
+    ///
+    /// The log's directory is watched from here on, so no change made after this is missed.
+    fn unopened(path: &Path) -> Self {
+        let (watcher, changes) = watch_log(path);
+        Self {
+            path: path.to_path_buf(),
+            file: None,
+            identity: None,
+            offset: 0,
+            partial: Vec::new(),
+            watcher,
+            changes,
+        }
+    }
+
+    /// Returns the complete lines written since the last call.
+    ///
+    /// If the file was rotated or recreated, the lines left in the old file come first, followed
+    /// by the lines of the new file from its start.
+    pub async fn poll(&mut self) -> MicrosandboxResult<Vec<String>> {
+        let mut lines = Vec::new();
+
+        let current = self.open_current().await?;
+        let replaced = match &current {
+            Some((_, identity)) => self.identity != Some(*identity),
+            None => false,
+        };
+
+        if replaced {
+            // Drain what was written to the old file before it was rotated away
+            self.read_new_lines(&mut lines).await?;
+            self.flush_partial(&mut lines);
+
+            let (file, identity) = current.unwrap();
+            self.file = Some(file);
+            self.identity = Some(identity);
+            self.offset = 0;
+        } else if let Some(file) = &self.file
+            && file.metadata().await?.len() < self.offset
+        {
+            // The file was truncated in place
+            self.offset = 0;
+            self.partial.clear();
+        }
+
+        self.read_new_lines(&mut lines).await?;
+
+        Ok(lines)
+    }
+
+    /// Opens the file currently at the followed path, along with its device and inode.
+    async fn open_current(&self) -> MicrosandboxResult<Option<(fs::File, (u64, u64))>> {
+        let file = match fs::File::open(&self.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let metadata = file.metadata().await?;
+        Ok(Some((file, (metadata.dev(), metadata.ino()))))
+    }
+
+    /// Reads the complete lines appended to the current file since the last read.
+    async fn read_new_lines(&mut self, lines: &mut Vec<String>) -> MicrosandboxResult<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        let mut buf = Vec::new();
+        file.seek(SeekFrom::Start(self.offset)).await?;
+        self.offset += file.read_to_end(&mut buf).await? as u64;
+        self.partial.extend_from_slice(&buf);
+
+        if let Some(end) = self.partial.iter().rposition(|b| *b == b'\n') {
+            let complete: Vec<u8> = self.partial.drain(..=end).collect();
+            lines.extend(
+                String::from_utf8_lossy(&complete)
+                    .lines()
+                    .map(|line| line.to_string()),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Emits the unterminated last line of a file that will not be written to anymore.
+    fn flush_partial(&mut self, lines: &mut Vec<String>) {
+        if !self.partial.is_empty() {
+            lines.push(String::from_utf8_lossy(&self.partial).into_owned());
+            self.partial.clear();
+        }
+    }
+}
+
+impl SandboxListEntry {
+    /// Creates the list entry for the sandbox named `name`.
+    pub fn new(name: &str, sandbox: &Sandbox) -> Self {
+        Self {
+            name: name.to_string(),
+            image: sandbox.get_image().to_string(),
+            cpus: *sandbox.get_cpus(),
+            memory: *sandbox.get_memory(),
+            scope: sandbox.scope_or_default().to_string(),
+            ports: sandbox
+                .get_ports()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            volumes: sandbox
+                .get_volumes()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            labels: sandbox
+                .get_labels()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl FromStr for ListFormat {
+    type Err = MicrosandboxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "table" => Ok(ListFormat::Table),
+            "json" => Ok(ListFormat::Json),
+            "yaml" => Ok(ListFormat::Yaml),
+            _ => Err(MicrosandboxError::InvalidArgument(format!(
+                "invalid list format: {s}, expected one of table, json, yaml"
+            ))),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Microsandbox;
+
+    use super::*;
+
+    #[test]
+    fn test_menv_format_list_json() -> anyhow::Result<()> {
+        let config: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+                labels:
+                  tier: web
+                memory: 512
+                cpus: 2
+                ports:
+                  - "8080:80"
+                volumes:
+                  - "./site:/usr/share/nginx/html"
+              db:
+                image: "postgres:16"
+        "#,
+        )?;
+
+        let output = format_list(config.get_sandboxes(), ListFormat::Json)?;
+        let entries: serde_json::Value = serde_json::from_str(&output)?;
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        // Entries are sorted by name
+        let db = &entries[0];
+        assert_eq!(db["name"], "db");
+        assert!(db["image"].as_str().unwrap().contains("postgres"));
+        assert!(db["cpus"].is_null());
+        assert!(db["memory"].is_null());
+        assert_eq!(db["ports"], serde_json::json!([]));
+        assert_eq!(db["volumes"], serde_json::json!([]));
+
+        let web = &entries[1];
+        assert_eq!(web["name"], "web");
+        assert!(web["image"].as_str().unwrap().contains("nginx"));
+        assert_eq!(web["cpus"], 2);
+        assert_eq!(web["memory"], 512);
+        assert_eq!(web["ports"], serde_json::json!(["8080:80"]));
+        assert_eq!(
+            web["volumes"],
+            serde_json::json!(["./site:/usr/share/nginx/html"])
+        );
+        assert!(web.get("scope").is_some());
+        assert_eq!(web["labels"], serde_json::json!({"tier": "web"}));
+        assert_eq!(db["labels"], serde_json::json!({}));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_menv_read_log_across_rotated_files() -> anyhow::Result<()> {
+        use microsandbox_utils::RotatingLog;
+        use tokio::io::AsyncWriteExt;
+
+        let temp_dir = tempfile::tempdir()?;
+        let log_path = temp_dir.path().join("app.log");
+
+        // Every line past the first rotates the log, keeping two rotated files
+        let mut log = RotatingLog::with_rotation(&log_path, 16, 2).await?;
+        for i in 0..6 {
+            log.write_all(format!("line {i:08}\n").as_bytes()).await?;
+            log.flush().await?;
+        }
+
+        // Only the active file and the two most recent rotated files are kept
+        assert_eq!(log::log_segments(&log_path).len(), 3);
+        assert!(!log::rotated_log_path(&log_path, 2).exists());
+
+        assert_eq!(
+            read_log(&log_path, None).await?,
+            vec!["line 00000003", "line 00000004", "line 00000005"]
+        );
+        assert_eq!(
+            read_log(&log_path, Some(2)).await?,
+            vec!["line 00000004", "line 00000005"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_menv_log_follower_survives_rotation() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let log_path = temp_dir.path().join("app.log");
+        fs::write(&log_path, "before follow\n").await?;
+
+        // Only lines written after following starts are returned
+        let mut follower = LogFollower::new(&log_path).await?;
+        assert!(follower.poll().await?.is_empty());
+
+        let mut file = fs::OpenOptions::new().append(true).open(&log_path).await?;
+        file.write_all(b"first\nsecond\npart").await?;
+        assert_eq!(follower.poll().await?, vec!["first", "second"]);
+
+        // Lines left in a file that was rotated away are still read
+        file.write_all(b"ial\nlast old\n").await?;
+        drop(file);
+        fs::rename(&log_path, log::rotated_log_path(&log_path, 1)).await?;
+        assert_eq!(follower.poll().await?, vec!["partial", "last old"]);
+
+        // The recreated file is read from its start
+        fs::write(&log_path, "after rotation\n").await?;
+        assert_eq!(follower.poll().await?, vec!["after rotation"]);
+
+        // When the file is replaced between polls, the rest of the old file comes first
+        let mut file = fs::OpenOptions::new().append(true).open(&log_path).await?;
+        file.write_all(b"before restart\n").await?;
+        drop(file);
+        fs::rename(&log_path, log::rotated_log_path(&log_path, 1)).await?;
+        fs::write(&log_path, "after restart\n").await?;
+        assert_eq!(
+            follower.poll().await?,
+            vec!["before restart", "after restart"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_menv_log_follower_continues_from_tail() -> anyhow::Result<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let log_path = temp_dir.path().join("app.log");
+        fs::write(log::rotated_log_path(&log_path, 0), "zero\n").await?;
+        fs::write(&log_path, "one\ntwo\nthr").await?;
+
+        // The tail spans rotated files, and an unterminated line is left for the follower
+        let (mut follower, lines) = LogFollower::with_tail(&log_path, 2).await?;
+        assert_eq!(lines, vec!["one", "two"]);
+        assert_eq!(LogFollower::with_tail(&log_path, 10).await?.1.len(), 3);
+
+        // Lines written after the tail was read wake the follower, and are returned once
+        let mut file = fs::OpenOptions::new().append(true).open(&log_path).await?;
+        file.write_all(b"ee\nfour\n").await?;
+        tokio::time::timeout(Duration::from_secs(5), follower.changed()).await?;
+        assert_eq!(follower.poll().await?, vec!["three", "four"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_menv_list_format_from_str() {
+        assert_eq!("table".parse::<ListFormat>().unwrap(), ListFormat::Table);
+        assert_eq!("JSON".parse::<ListFormat>().unwrap(), ListFormat::Json);
+        assert_eq!("yaml".parse::<ListFormat>().unwrap(), ListFormat::Yaml);
+        assert!("xml".parse::<ListFormat>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_menv_create_config_from_template() -> anyhow::Result<()> {
+        let home = tempfile::tempdir()?;
+        let project = tempfile::tempdir()?;
+        let template_path = home.path().join(CONFIG_TEMPLATE_FILE);
+        let template = "# Team defaults\nsandboxes:\n  app:\n    image: registry.internal/base:1\n    memory: 512\n    labels:\n      team: platform\n";
+        fs::write(&template_path, template).await?;
+
+        create_config_from_template(project.path(), &template_path).await?;
+        let config_path = project.path().join(MICROSANDBOX_CONFIG_FILENAME);
+        assert_eq!(fs::read_to_string(&config_path).await?, template);
+
+        // An existing config file is left alone
+        fs::write(&config_path, DEFAULT_CONFIG).await?;
+        create_config_from_template(project.path(), &template_path).await?;
+        assert_eq!(fs::read_to_string(&config_path).await?, DEFAULT_CONFIG);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_menv_create_config_without_template() -> anyhow::Result<()> {
+        let home = tempfile::tempdir()?;
+        let project = tempfile::tempdir()?;
+        let template_path = home.path().join(CONFIG_TEMPLATE_FILE);
+
+        // Without a template, the built-in default is used
+        create_config_from_template(project.path(), &template_path).await?;
+        let config_path = project.path().join(MICROSANDBOX_CONFIG_FILENAME);
+        assert_eq!(fs::read_to_string(&config_path).await?, DEFAULT_CONFIG);
+
+        // An invalid template is rejected without creating a config file
+        fs::remove_file(&config_path).await?;
+        fs::write(&template_path, "sandboxes:\n  app:\n    memory: 0\n").await?;
+        let result = create_config_from_template(project.path(), &template_path).await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::ConfigValidation(_))
+        ));
+        assert!(!config_path.exists());
+
+        Ok(())
+    }
+}
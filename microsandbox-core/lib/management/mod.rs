@@ -7,11 +7,13 @@
 //!
 //! Key components:
 //! - `db`: Database management for storing container and sandbox metadata
+//! - `doctor`: Environment diagnostics
 //! - `image`: Container image handling and registry operations
 //! - `menv`: Microsandbox environment management
 //! - `rootfs`: Root filesystem operations for containers
 //! - `sandbox`: Sandbox creation and management
 //! - `orchestra`: Orchestra management for sandboxes
+//! - `readiness`: Readiness probes for started sandboxes
 //! - `home`: Home directory management
 //! - `toolchain`: Toolchain management
 
@@ -21,9 +23,12 @@
 
 pub mod config;
 pub mod db;
+pub mod doctor;
 pub mod home;
+pub mod image;
 pub mod menv;
 pub mod orchestra;
+pub mod readiness;
 pub mod rootfs;
 pub mod sandbox;
 pub mod toolchain;
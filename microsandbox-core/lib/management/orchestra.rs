@@ -9,32 +9,56 @@
 //! - `up`: Start up all sandboxes defined in configuration
 //! - `down`: Gracefully shut down all running sandboxes
 //! - `apply`: Reconcile running sandboxes with configuration
+//! - `prewarm`: Pull the images of the sandboxes ahead of starting them
+//! - `resize`: Change the memory of a running sandbox
+//! - `resolve_sandbox_project`: Find the project a sandbox name refers to across projects
 
 use crate::{
     MicrosandboxError, MicrosandboxResult,
-    config::{Microsandbox, START_SCRIPT_NAME},
+    config::{
+        HostLimits, LabelSelector, Microsandbox, PathSegment, ReferenceOrPath, ResourceCheckMode,
+        START_SCRIPT_NAME, Sandbox, check_host_capacity,
+    },
+    oci::{Image, PullSummary, Reference},
+    runtime::SANDBOX_STATUS_RUNNING,
+    vm,
 };
 
 #[cfg(feature = "cli")]
 use console::style;
+use futures::{StreamExt, TryStreamExt, stream};
 #[cfg(feature = "cli")]
 use microsandbox_utils::term;
-use microsandbox_utils::{MICROSANDBOX_ENV_DIR, SANDBOX_DB_FILENAME};
+use microsandbox_utils::{
+    DEFAULT_SHUTDOWN_GRACE_PERIOD, MICROSANDBOX_CONFIG_FILENAME, MICROSANDBOX_ENV_DIR,
+    SANDBOX_DB_FILENAME, env, terminate_child,
+};
 use nix::{
     sys::signal::{self, Signal},
     unistd::Pid,
 };
 use once_cell::sync::Lazy;
+use sqlx::{Pool, Sqlite};
 #[cfg(feature = "cli")]
 use std::io::{self, IsTerminal};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
+    future::Future,
+    os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
-    sync::RwLock,
+    sync::{
+        Mutex, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
     time::{Duration, Instant},
 };
+use tokio::{
+    process::Child,
+    sync::{Semaphore, watch},
+    task::JoinHandle,
+};
 
-use super::{config, db, menv, sandbox};
+use super::{config, db, menv, readiness, sandbox};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -43,6 +67,15 @@ use super::{config, db, menv, sandbox};
 /// TTL for cached directory sizes.
 const DISK_SIZE_TTL: Duration = Duration::from_secs(30);
 
+/// How often a sandbox started with prefixed output is checked for running.
+const RUNNING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where the procfs filesystem is mounted.
+const PROC_ROOT: &str = "/proc";
+
+/// Where the cgroup v2 hierarchy is mounted.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
 #[cfg(feature = "cli")]
 const APPLY_CONFIG_MSG: &str = "Applying sandbox configuration";
 
@@ -52,6 +85,9 @@ const START_SANDBOXES_MSG: &str = "Starting sandboxes";
 #[cfg(feature = "cli")]
 const STOP_SANDBOXES_MSG: &str = "Stopping sandboxes";
 
+/// The name the projects directory itself is listed under when resolving sandbox projects.
+pub const ROOT_PROJECT_NAME: &str = ".";
+
 /// Global cache path -> (size, last_updated)
 static DISK_SIZE_CACHE: Lazy<RwLock<HashMap<String, (u64, Instant)>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
@@ -86,6 +122,109 @@ pub struct SandboxStatus {
 
     /// Rootfs paths
     pub rootfs_paths: Option<String>,
+
+    /// The labels of the sandbox from the configuration
+    pub labels: HashMap<String, String>,
+}
+
+/// Resource usage aggregated over a set of sandboxes
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StatusSummary {
+    /// The number of sandboxes
+    pub total: usize,
+
+    /// The number of running sandboxes
+    pub running: usize,
+
+    /// Total CPU usage percentage
+    pub cpu_usage: f32,
+
+    /// Total memory usage in MiB
+    pub memory_usage: u64,
+
+    /// Total disk usage of the RW layers in bytes
+    pub disk_usage: u64,
+}
+
+/// The changes [`apply`] makes to bring the running sandboxes in line with the configuration
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyPlan {
+    /// Sandboxes in the configuration that are not running, sorted by name
+    pub to_start: Vec<String>,
+
+    /// Running sandboxes that are no longer in the configuration, sorted by name
+    pub to_stop: Vec<String>,
+}
+
+/// Whether a single sandbox is alive, as checked by [`liveness`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SandboxLiveness {
+    /// The sandbox is in the configuration and running
+    Running,
+
+    /// The sandbox is in the configuration but not running
+    Stopped,
+
+    /// The sandbox is not in the configuration
+    Unknown,
+}
+
+/// How far starting a set of sandboxes in dependency order has got
+#[derive(Debug, Default)]
+struct StartProgress {
+    /// The sandboxes that have started
+    started: HashSet<String>,
+
+    /// Whether a start has failed, after which no further sandboxes are started
+    failed: bool,
+}
+
+/// A sandbox running in the foreground, whose output is printed prefixed with its name
+struct PrefixedSandbox {
+    /// The name of the sandbox
+    name: String,
+
+    /// The supervisor process of the sandbox
+    child: Child,
+
+    /// The tasks printing the stdout and stderr of the supervisor
+    output_tasks: Vec<JoinHandle<()>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl ApplyPlan {
+    /// Whether the plan stops any sandboxes
+    pub fn is_destructive(&self) -> bool {
+        !self.to_stop.is_empty()
+    }
+
+    /// Renders the plan with one line per sandbox, `+` for those started and `-` for those
+    /// stopped. An empty plan renders as an empty string.
+    pub fn summary(&self) -> String {
+        let start = self.to_start.iter().map(|name| format!("+ start {}", name));
+        let stop = self.to_stop.iter().map(|name| format!("- stop  {}", name));
+        start.chain(stop).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl SandboxLiveness {
+    /// The exit code used when the liveness of a sandbox could not be checked at all, e.g.
+    /// because the configuration is missing or invalid
+    pub const CHECK_FAILED_EXIT_CODE: i32 = 3;
+
+    /// Returns the exit code a health check reports this liveness with
+    ///
+    /// Only a running sandbox exits with 0, so the code can be used directly as a liveness probe.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SandboxLiveness::Running => 0,
+            SandboxLiveness::Stopped => 1,
+            SandboxLiveness::Unknown => 2,
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -103,11 +242,16 @@ pub struct SandboxStatus {
 /// If another apply operation is in progress, this function will fail immediately.
 /// The lock is automatically released when the function completes or if it fails.
 ///
+/// Before anything is changed, the plan is printed. Since stopping sandboxes is destructive, it
+/// has to be confirmed at an interactive prompt unless `auto_approve` is set. Plans that only
+/// start sandboxes go ahead without prompting.
+///
 /// ## Arguments
 ///
 /// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
 /// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 /// * `detach` - Whether to run sandboxes in detached mode (true) or with prefixed output (false)
+/// * `auto_approve` - Whether to stop sandboxes no longer in the config without asking first
 ///
 /// ## Returns
 ///
@@ -115,6 +259,8 @@ pub struct SandboxStatus {
 /// - Config file not found or invalid
 /// - Database errors
 /// - Sandbox start/stop failures
+/// - Stopping sandboxes was declined, or could not be confirmed without a terminal,
+///   `MicrosandboxError::ApplyNotConfirmed`
 ///
 /// ## Example
 ///
@@ -125,13 +271,15 @@ pub struct SandboxStatus {
 /// #[tokio::main]
 /// async fn main() -> anyhow::Result<()> {
 ///     // Apply configuration changes from the default microsandbox.yaml
-///     orchestra::apply(None, None, true).await?;
+///     orchestra::apply(None, None, true, false).await?;
 ///
-///     // Or specify a custom project directory and config file, in non-detached mode
+///     // Or specify a custom project directory and config file, in non-detached mode,
+///     // stopping removed sandboxes without asking
 ///     orchestra::apply(
 ///         Some(&PathBuf::from("/path/to/project")),
 ///         Some("custom-config.yaml"),
 ///         false,
+///         true,
 ///     ).await?;
 ///     Ok(())
 /// }
@@ -140,6 +288,7 @@ pub async fn apply(
     project_dir: Option<&Path>,
     config_file: Option<&str>,
     detach: bool,
+    auto_approve: bool,
 ) -> MicrosandboxResult<()> {
     // Create spinner for CLI feedback
     #[cfg(feature = "cli")]
@@ -186,63 +335,100 @@ pub async fn apply(
     let running_sandbox_names: Vec<String> =
         running_sandboxes.iter().map(|s| s.name.clone()).collect();
 
+    let plan = plan_apply(config_sandboxes, &running_sandbox_names);
+
+    // A config without sandboxes is only worth applying when it stops the ones that were removed
+    if !plan.is_destructive()
+        && let Err(e) = ensure_sandboxes_defined(&config, &canonical_project_dir, &config_file)
+    {
+        #[cfg(feature = "cli")]
+        term::finish_with_error(&apply_config_sp);
+        return Err(e);
+    }
+
+    let summary = plan.summary();
+
+    // Show the plan and make sure stopping sandboxes is intended before changing anything
+    #[cfg(feature = "cli")]
+    let confirmed = apply_config_sp.suspend(|| {
+        if !summary.is_empty() {
+            println!("{}", summary);
+        }
+        confirm_apply(&plan, auto_approve, prompt_confirmation)
+    });
+    #[cfg(not(feature = "cli"))]
+    let confirmed = {
+        tracing::info!("apply plan:\n{}", summary);
+        confirm_apply(&plan, auto_approve, |_| None)
+    };
+    if let Err(e) = confirmed {
+        #[cfg(feature = "cli")]
+        term::finish_with_error(&apply_config_sp);
+        return Err(e);
+    }
+
     // Collect sandboxes that need to be started
-    let sandboxes_to_start: Vec<&String> = config_sandboxes
-        .keys()
-        .filter(|name| !running_sandbox_names.contains(*name))
-        .collect();
+    let sandboxes_to_start: Vec<&String> = plan.to_start.iter().collect();
 
     if sandboxes_to_start.is_empty() {
         tracing::info!("No new sandboxes to start");
     } else if detach {
-        // Start sandboxes in detached mode
-        for name in sandboxes_to_start {
-            tracing::info!("starting sandbox: {}", name);
-            sandbox::run(
-                name,
-                Some(START_SCRIPT_NAME),
-                Some(&canonical_project_dir),
-                Some(&config_file),
-                vec![],
-                true, // detached mode
-                None,
-                true,
-            )
-            .await?
+        // Start sandboxes in detached mode, as many at once as `up` would
+        let permits = Semaphore::new(default_max_concurrent_sandboxes());
+        let result =
+            start_in_dependency_order(&sandboxes_to_start, config_sandboxes, &permits, |name| {
+                let project_dir = canonical_project_dir.as_path();
+                let config_file = config_file.as_str();
+                async move {
+                    tracing::info!("starting sandbox: {}", name);
+                    sandbox::run(
+                        &name,
+                        Some(START_SCRIPT_NAME),
+                        Some(project_dir),
+                        Some(config_file),
+                        vec![],
+                        true, // detached mode
+                        None,
+                        None,
+                        true,
+                        None,
+                        false,
+                        None,
+                        None,
+                    )
+                    .await
+                }
+            })
+            .await;
+
+        if let Err(e) = result {
+            #[cfg(feature = "cli")]
+            term::finish_with_error(&apply_config_sp);
+            return Err(e);
         }
     } else {
-        // Start sandboxes in non-detached mode with multiplexed output
-        let sandbox_commands = match prepare_sandbox_commands(
+        // Finish the spinner before the sandboxes start printing their output
+        #[cfg(feature = "cli")]
+        apply_config_sp.finish();
+
+        // Start sandboxes in non-detached mode with multiplexed output, as many at once as `up`
+        // would
+        let permits = Semaphore::new(default_max_concurrent_sandboxes());
+        return run_with_prefixed_output(
             &sandboxes_to_start,
+            config_sandboxes,
             Some(START_SCRIPT_NAME),
             &canonical_project_dir,
             &config_file,
+            &pool,
+            &permits,
         )
-        .await
-        {
-            Ok(commands) => commands,
-            Err(e) => {
-                #[cfg(feature = "cli")]
-                term::finish_with_error(&apply_config_sp);
-                return Err(e);
-            }
-        };
-
-        if !sandbox_commands.is_empty() {
-            // Finish the spinner before running commands with output
-            #[cfg(feature = "cli")]
-            apply_config_sp.finish();
-
-            run_commands_with_prefixed_output(sandbox_commands).await?;
-
-            // Return early as we've already finished the spinner
-            return Ok(());
-        }
+        .await;
     }
 
     // Stop sandboxes that are active but not in config
     for sandbox in running_sandboxes {
-        if !config_sandboxes.contains_key(&sandbox.name) {
+        if plan.to_stop.contains(&sandbox.name) {
             tracing::info!("stopping sandbox: {}", sandbox.name);
             if let Err(e) = signal::kill(
                 Pid::from_raw(sandbox.supervisor_pid as i32),
@@ -267,19 +453,33 @@ pub async fn apply(
 /// - Starting any specified sandboxes that are in the config but not running
 /// - Ignoring sandboxes that are not specified or already running
 ///
+/// In detached mode, sandboxes with a readiness probe are only reported as up once the probe
+/// succeeds, see [`readiness::wait_until_ready`].
+///
+/// Sandboxes are started at most `max_concurrent` at a time, so bringing up a large project
+/// doesn't overwhelm the host. A sandbox is only started once the sandboxes it depends on that
+/// are started with it are up, which in detached mode includes their readiness probes passing.
+///
 /// ## Arguments
 ///
 /// * `sandbox_names` - List of sandbox names to start
+/// * `labels` - Label selectors the sandboxes must all match. A selector that matches no
+///   sandbox selects nothing.
 /// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
 /// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 /// * `detach` - Whether to run sandboxes in detached mode (true) or with prefixed output (false)
+/// * `start_timeout` - Optional limit on how long each detached sandbox may take to start
+/// * `max_concurrent` - Optional limit on how many sandboxes are started at once. If None, defaults
+///   to [`default_max_concurrent_sandboxes`]
 ///
 /// ## Returns
 ///
 /// Returns `MicrosandboxResult<()>` indicating success or failure. Possible failures include:
 /// - Config file not found or invalid
+/// - Dependency cycles between the sandboxes to start, `MicrosandboxError::ConfigValidation`
 /// - Database errors
-/// - Sandbox start failures
+/// - Sandbox start failures, including `MicrosandboxError::StartTimeout`
+/// - Readiness probes that do not succeed in time, `MicrosandboxError::ReadinessTimeout`
 ///
 /// ## Example
 ///
@@ -290,23 +490,73 @@ pub async fn apply(
 /// #[tokio::main]
 /// async fn main() -> anyhow::Result<()> {
 ///     // Start specific sandboxes from the default microsandbox.yaml in detached mode
-///     orchestra::up(vec!["sandbox1".to_string(), "sandbox2".to_string()], None, None, true).await?;
+///     orchestra::up(vec!["sandbox1".to_string(), "sandbox2".to_string()], &[], None, None, true, None, None).await?;
+///
+///     // Or start every sandbox labelled `tier: web`, two at a time
+///     orchestra::up(vec![], &["tier=web".parse()?], None, None, true, None, Some(2)).await?;
 ///
 ///     // Or specify a custom project directory and config file, in non-detached mode
 ///     orchestra::up(
 ///         vec!["sandbox1".to_string()],
+///         &[],
 ///         Some(&PathBuf::from("/path/to/project")),
 ///         Some("custom-config.yaml"),
 ///         false,
+///         None,
+///         None,
 ///     ).await?;
 ///     Ok(())
 /// }
 /// ```
+#[allow(clippy::too_many_arguments)]
 pub async fn up(
     sandbox_names: Vec<String>,
+    labels: &[LabelSelector],
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+    detach: bool,
+    start_timeout: Option<Duration>,
+    max_concurrent: Option<usize>,
+) -> MicrosandboxResult<()> {
+    let max_concurrent = max_concurrent
+        .filter(|max| *max > 0)
+        .unwrap_or_else(default_max_concurrent_sandboxes);
+
+    up_with_permits(
+        sandbox_names,
+        labels,
+        project_dir,
+        config_file,
+        detach,
+        start_timeout,
+        &Semaphore::new(max_concurrent),
+    )
+    .await
+}
+
+/// Starts specified sandboxes like [`up`], taking a permit from `permits` for each sandbox while
+/// it starts.
+///
+/// Sharing `permits` between calls caps how many sandboxes start at once across all of them, e.g.
+/// across the requests a server handles, rather than within each call.
+///
+/// ## Arguments
+///
+/// * `sandbox_names` - List of sandbox names to start
+/// * `labels` - Label selectors the sandboxes must all match
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
+/// * `detach` - Whether to run sandboxes in detached mode (true) or with prefixed output (false)
+/// * `start_timeout` - Optional limit on how long each detached sandbox may take to start
+/// * `permits` - The permits a sandbox holds from when it starts until it is running
+pub async fn up_with_permits(
+    sandbox_names: Vec<String>,
+    labels: &[LabelSelector],
     project_dir: Option<&Path>,
     config_file: Option<&str>,
     detach: bool,
+    start_timeout: Option<Duration>,
+    permits: &Semaphore,
 ) -> MicrosandboxResult<()> {
     // Create spinner for CLI feedback
     #[cfg(feature = "cli")]
@@ -323,24 +573,23 @@ pub async fn up(
             }
         };
 
+    if let Err(e) = ensure_sandboxes_defined(&config, &canonical_project_dir, &config_file) {
+        #[cfg(feature = "cli")]
+        term::finish_with_error(&start_sandboxes_sp);
+        return Err(e);
+    }
+
     // Get all sandboxes defined in config
     let config_sandboxes = config.get_sandboxes();
 
-    // Use all sandbox names from config if no names were specified
-    let sandbox_names_to_start = if sandbox_names.is_empty() {
-        // Use all sandbox names from config
-        config_sandboxes.keys().cloned().collect()
-    } else {
-        // Validate all sandbox names exist in config before proceeding
-        validate_sandbox_names(
-            &sandbox_names,
-            &config,
-            &canonical_project_dir,
-            &config_file,
-        )?;
-
-        sandbox_names
-    };
+    // Narrow the named sandboxes, or all of them if none were named, down by label
+    let sandbox_names_to_start = select_sandbox_names(
+        sandbox_names,
+        labels,
+        &config,
+        &canonical_project_dir,
+        &config_file,
+    )?;
 
     // Ensure menv files exist
     let menv_path = canonical_project_dir.join(MICROSANDBOX_ENV_DIR);
@@ -384,50 +633,74 @@ pub async fn up(
         return Ok(());
     }
 
+    // Make sure the sandboxes fit on the host together before starting any of them
+    if let Err(e) = check_host_capacity(
+        sandboxes_to_start
+            .iter()
+            .map(|name| (name.as_str(), &config_sandboxes[*name])),
+        &HostLimits::detect(),
+        ResourceCheckMode::from_env(),
+    ) {
+        #[cfg(feature = "cli")]
+        term::finish_with_error(&start_sandboxes_sp);
+        return Err(e);
+    }
+
+    let project_dir = canonical_project_dir.as_path();
+    let config_file = config_file.as_str();
+
     if detach {
-        // Start specified sandboxes in detached mode
-        for name in sandboxes_to_start {
-            tracing::info!("starting sandbox: {}", name);
-            sandbox::run(
-                name,
-                None,
-                Some(&canonical_project_dir),
-                Some(&config_file),
-                vec![],
-                true, // detached mode
-                None,
-                true,
-            )
-            .await?
-        }
-    } else {
-        // Start sandboxes in non-detached mode with multiplexed output
-        let sandbox_commands = match prepare_sandbox_commands(
+        // Start specified sandboxes in detached mode, only reporting each as up once its
+        // readiness probe passes
+        let result = start_in_dependency_order(
             &sandboxes_to_start,
-            None, // Start script is None for normal up
-            &canonical_project_dir,
-            &config_file,
+            config_sandboxes,
+            permits,
+            |name| async move {
+                tracing::info!("starting sandbox: {}", name);
+                sandbox::run(
+                    &name,
+                    None,
+                    Some(project_dir),
+                    Some(config_file),
+                    vec![],
+                    true, // detached mode
+                    None,
+                    None,
+                    true,
+                    start_timeout,
+                    false,
+                    None,
+                    None,
+                )
+                .await?;
+
+                readiness::wait_until_ready(&name, &config_sandboxes[&name]).await
+            },
         )
-        .await
-        {
-            Ok(commands) => commands,
-            Err(e) => {
-                #[cfg(feature = "cli")]
-                term::finish_with_error(&start_sandboxes_sp);
-                return Err(e);
-            }
-        };
+        .await;
 
-        if !sandbox_commands.is_empty() {
-            // Finish the spinner before running commands with output
+        if let Err(e) = result {
             #[cfg(feature = "cli")]
-            start_sandboxes_sp.finish();
-
-            run_commands_with_prefixed_output(sandbox_commands).await?;
-
-            // Return early as we've already finished the spinner
-            return Ok(());
+            term::finish_with_error(&start_sandboxes_sp);
+            return Err(e);
         }
+    } else {
+        // Finish the spinner before the sandboxes start printing their output
+        #[cfg(feature = "cli")]
+        start_sandboxes_sp.finish();
+
+        // Start sandboxes in non-detached mode with multiplexed output
+        return run_with_prefixed_output(
+            &sandboxes_to_start,
+            config_sandboxes,
+            None,
+            project_dir,
+            config_file,
+            &pool,
+            permits,
+        )
+        .await;
     }
 
     #[cfg(feature = "cli")]
@@ -436,6 +709,70 @@ pub async fn up(
     Ok(())
 }
 
+/// Returns how many sandboxes [`up`] starts at once when no limit is given.
+///
+/// This is the value of the `MSB_MAX_CONCURRENT_SANDBOXES` environment variable if it is set, and
+/// the number of CPUs on the host otherwise, since a starting sandbox keeps a CPU busy preparing
+/// its root filesystem and booting its microVM.
+pub fn default_max_concurrent_sandboxes() -> usize {
+    env::get_max_concurrent_sandboxes()
+        .unwrap_or_else(|| *HostLimits::detect().get_cpus() as usize)
+        .max(1)
+}
+
+/// Pulls every image the sandboxes of a project use, so that starting them later is fast and
+/// doesn't need the registry.
+///
+/// An image several sandboxes share is pulled once, and sandboxes whose root filesystem is a
+/// local path have nothing to pull. Images that are already cached aren't downloaded again.
+///
+/// ## Arguments
+///
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
+/// * `max_concurrent` - Optional limit on how many images are pulled at once. If None, defaults
+///   to [`default_max_concurrent_sandboxes`]
+///
+/// ## Returns
+///
+/// A summary of the pull of each image, ordered by reference. An image that was already cached
+/// reports no layers pulled, see [`PullSummary::is_cached`]. Possible failures include:
+/// - Config file not found or invalid
+/// - Image pull failures, which stop the images still waiting to be pulled
+///
+/// ## Example
+///
+/// ```no_run
+/// use microsandbox_core::management::orchestra;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     // Pull the images of the default microsandbox.yaml, two at a time
+///     for summary in orchestra::prewarm(None, None, Some(2)).await? {
+///         println!("{} cached: {}", summary.get_reference(), summary.is_cached());
+///     }
+///     Ok(())
+/// }
+/// ```
+pub async fn prewarm(
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+    max_concurrent: Option<usize>,
+) -> MicrosandboxResult<Vec<PullSummary>> {
+    let (config, canonical_project_dir, config_file) =
+        config::load_config(project_dir, config_file).await?;
+    ensure_sandboxes_defined(&config, &canonical_project_dir, &config_file)?;
+
+    let max_concurrent = max_concurrent
+        .filter(|max| *max > 0)
+        .unwrap_or_else(default_max_concurrent_sandboxes);
+    pull_images(config_images(&config), max_concurrent, |image| {
+        tracing::info!(%image, "prewarming image");
+        Image::pull(image, None, false, false)
+    })
+    .await
+}
+
 /// Stops specified sandboxes that are both in the configuration and currently running.
 ///
 /// This function ensures that the specified sandboxes are stopped by:
@@ -445,6 +782,8 @@ pub async fn up(
 /// ## Arguments
 ///
 /// * `sandbox_names` - List of sandbox names to stop
+/// * `labels` - Label selectors the sandboxes must all match. A selector that matches no
+///   sandbox selects nothing.
 /// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
 /// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 ///
@@ -464,11 +803,12 @@ pub async fn up(
 /// #[tokio::main]
 /// async fn main() -> anyhow::Result<()> {
 ///     // Stop specific sandboxes from the default microsandbox.yaml
-///     orchestra::down(vec!["sandbox1".to_string(), "sandbox2".to_string()], None, None).await?;
+///     orchestra::down(vec!["sandbox1".to_string(), "sandbox2".to_string()], &[], None, None).await?;
 ///
 ///     // Or specify a custom project directory and config file
 ///     orchestra::down(
 ///         vec!["sandbox1".to_string()],
+///         &[],
 ///         Some(&PathBuf::from("/path/to/project")),
 ///         Some("custom-config.yaml"),
 ///     ).await?;
@@ -477,6 +817,7 @@ pub async fn up(
 /// ```
 pub async fn down(
     sandbox_names: Vec<String>,
+    labels: &[LabelSelector],
     project_dir: Option<&Path>,
     config_file: Option<&str>,
 ) -> MicrosandboxResult<()> {
@@ -498,21 +839,14 @@ pub async fn down(
     // Get all sandboxes defined in config
     let config_sandboxes = config.get_sandboxes();
 
-    // Use all sandbox names from config if no names were specified
-    let sandbox_names_to_stop = if sandbox_names.is_empty() {
-        // Use all sandbox names from config
-        config_sandboxes.keys().cloned().collect()
-    } else {
-        // Validate all sandbox names exist in config before proceeding
-        validate_sandbox_names(
-            &sandbox_names,
-            &config,
-            &canonical_project_dir,
-            &config_file,
-        )?;
-
-        sandbox_names
-    };
+    // Narrow the named sandboxes, or all of them if none were named, down by label
+    let sandbox_names_to_stop = select_sandbox_names(
+        sandbox_names,
+        labels,
+        &config,
+        &canonical_project_dir,
+        &config_file,
+    )?;
 
     // Ensure menv files exist
     let menv_path = canonical_project_dir.join(MICROSANDBOX_ENV_DIR);
@@ -572,6 +906,8 @@ pub async fn down(
 /// ## Arguments
 ///
 /// * `sandbox_names` - List of sandbox names to get status for. If empty, all sandboxes in config are included.
+/// * `labels` - Label selectors the sandboxes must all match. A selector that matches no
+///   sandbox selects nothing.
 /// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
 /// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 ///
@@ -593,6 +929,7 @@ pub async fn down(
 ///     // Get status of specific sandboxes from the default microsandbox.yaml
 ///     let statuses = orchestra::status(
 ///         vec!["sandbox1".to_string(), "sandbox2".to_string()],
+///         &[],
 ///         None,
 ///         None
 ///     ).await?;
@@ -600,6 +937,7 @@ pub async fn down(
 ///     // Or get status of all sandboxes from the default microsandbox.yaml
 ///     let all_statuses = orchestra::status(
 ///         vec![], // empty list means get all sandboxes
+///         &[],
 ///         None,
 ///         None
 ///     ).await?;
@@ -617,31 +955,26 @@ pub async fn down(
 /// ```
 pub async fn status(
     sandbox_names: Vec<String>,
+    labels: &[LabelSelector],
     project_dir: Option<&Path>,
     config_file: Option<&str>,
 ) -> MicrosandboxResult<Vec<SandboxStatus>> {
     // Load the configuration first to validate it exists
     let (config, canonical_project_dir, config_file) =
         config::load_config(project_dir, config_file).await?;
+    ensure_sandboxes_defined(&config, &canonical_project_dir, &config_file)?;
 
     // Get all sandboxes defined in config
     let config_sandboxes = config.get_sandboxes();
 
-    // Use all sandbox names from config if no names were specified
-    let sandbox_names_to_check = if sandbox_names.is_empty() {
-        // Use all sandbox names from config
-        config_sandboxes.keys().cloned().collect()
-    } else {
-        // Validate all sandbox names exist in config before proceeding
-        validate_sandbox_names(
-            &sandbox_names,
-            &config,
-            &canonical_project_dir,
-            &config_file,
-        )?;
-
-        sandbox_names
-    };
+    // Narrow the named sandboxes, or all of them if none were named, down by label
+    let sandbox_names_to_check = select_sandbox_names(
+        sandbox_names,
+        labels,
+        &config,
+        &canonical_project_dir,
+        &config_file,
+    )?;
 
     // Ensure menv files exist
     let menv_path = canonical_project_dir.join(MICROSANDBOX_ENV_DIR);
@@ -676,6 +1009,7 @@ pub async fn status(
                 memory_usage: None,
                 disk_usage: None,
                 rootfs_paths: None,
+                labels: config_sandboxes[sandbox_name].get_labels().clone(),
             };
 
             // If the sandbox is running, get additional stats
@@ -694,10 +1028,12 @@ pub async fn status(
                     }
 
                     // Memory usage
-                    if let Ok(memory_info) = process.memory_info() {
-                        // Convert bytes to MiB
-                        sandbox_status.memory_usage = Some(memory_info.rss() / (1024 * 1024));
-                    }
+                    sandbox_status.memory_usage = get_memory_usage(
+                        &process,
+                        &[sandbox.supervisor_pid, sandbox.microvm_pid],
+                        Path::new(PROC_ROOT),
+                        Path::new(CGROUP_ROOT),
+                    );
                 }
 
                 // Get disk usage of the RW layer if it's an overlayfs
@@ -739,56 +1075,295 @@ pub async fn status(
     Ok(statuses)
 }
 
-/// Show the status of the sandboxes
+/// Checks whether a single sandbox is alive, using [`status`].
+///
+/// Unlike [`status`], a sandbox that is not in the configuration is not an error, so callers can
+/// tell it apart from one that is stopped. A sandbox recorded as running only counts as running
+/// while its supervisor process exists, so a record left behind by a crashed supervisor reads as
+/// stopped.
 ///
 /// ## Arguments
 ///
-/// * `names` - The names of the sandboxes to show the status of
-/// * `path` - The path to the microsandbox config file
-/// * `config` - The config file to use
+/// * `sandbox_name` - The name of the sandbox to check
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 ///
 /// ## Returns
 ///
-/// Returns `MicrosandboxResult<()>` indicating success or failure. Possible failures include:
-/// - Config file not found or invalid
-/// - Database errors
-/// - Sandbox status retrieval failures
+/// The liveness of the sandbox, or an error if the config file is not found or invalid
+pub async fn liveness(
+    sandbox_name: &str,
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+) -> MicrosandboxResult<SandboxLiveness> {
+    let (config, _, _) = config::load_config(project_dir, config_file).await?;
+    if !config.get_sandboxes().contains_key(sandbox_name) {
+        return Ok(SandboxLiveness::Unknown);
+    }
+
+    let statuses = status(
+        vec![sandbox_name.to_string()],
+        &[],
+        project_dir,
+        config_file,
+    )
+    .await?;
+    let running = statuses.iter().any(|status| {
+        status.name == sandbox_name
+            && status.running
+            && status.supervisor_pid.is_some_and(process_exists)
+    });
+
+    Ok(if running {
+        SandboxLiveness::Running
+    } else {
+        SandboxLiveness::Stopped
+    })
+}
+
+/// Requests a memory resize for a running sandbox.
 ///
-/// ## Example
+/// The requested size must lie between [`MIN_BALLOON_MEMORY_MIB`] and the memory the sandbox was
+/// configured with, since that is what its microVM booted with.
 ///
-/// ```no_run
-/// use std::path::PathBuf;
-/// use microsandbox_core::management::orchestra;
+/// ## Arguments
 ///
-/// #[tokio::main]
-/// async fn main() -> anyhow::Result<()> {
-///     orchestra::show_status(
-///         &["sandbox1".to_string(), "sandbox2".to_string()],
-///         None,
-///         None
-///     ).await?;
-///     Ok(())
-/// }
-/// ```
-#[cfg(feature = "cli")]
-pub async fn show_status(
-    names: &[String],
-    path: Option<&Path>,
-    config: Option<&str>,
+/// * `sandbox_name` - The name of the sandbox to resize
+/// * `memory_mib` - The memory size to resize to in MiB
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
+///
+/// ## Returns
+///
+/// Returns `MicrosandboxResult<()>` indicating success or failure. Possible failures include:
+/// - Config file not found or invalid, or the sandbox is not in it
+/// - The sandbox is not running
+/// - `MicrosandboxError::InvalidMemoryResize` if the size is out of range
+/// - `MicrosandboxError::MemoryResizeNotSupported` if the backend can't resize running sandboxes
+///
+/// [`MIN_BALLOON_MEMORY_MIB`]: crate::vm::MIN_BALLOON_MEMORY_MIB
+pub async fn resize(
+    sandbox_name: &str,
+    memory_mib: u32,
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
 ) -> MicrosandboxResult<()> {
-    // Check if we're in a TTY to determine if we should do live updates
-    let is_tty = io::stdin().is_terminal();
-    let live_view = is_tty;
-    let update_interval = std::time::Duration::from_secs(2);
+    let (config, canonical_project_dir, config_file) =
+        config::load_config(project_dir, config_file).await?;
 
-    if live_view {
+    let Some(sandbox_config) = config.get_sandbox(sandbox_name) else {
+        return Err(MicrosandboxError::SandboxNotFoundInConfig(
+            sandbox_name.to_string(),
+            canonical_project_dir.join(&config_file),
+        ));
+    };
+
+    // Get database connection pool
+    let menv_path = canonical_project_dir.join(MICROSANDBOX_ENV_DIR);
+    menv::ensure_menv_files(&menv_path).await?;
+    let db_path = menv_path.join(SANDBOX_DB_FILENAME);
+    let pool = db::get_or_create_pool(&db_path, &db::SANDBOX_DB_MIGRATOR).await?;
+
+    let running = db::get_running_config_sandboxes(&pool, &config_file)
+        .await?
+        .iter()
+        .any(|s| s.name == sandbox_name);
+    if !running {
+        return Err(MicrosandboxError::SandboxNotRunning(
+            sandbox_name.to_string(),
+        ));
+    }
+
+    let boot_memory_mib = sandbox_config.memory_or_default();
+    vm::resize_memory(
+        vm::MemoryResizeRange::for_boot_memory(boot_memory_mib),
+        memory_mib,
+    )
+}
+
+/// Lists the projects under a projects directory that define a sandbox with the given name.
+///
+/// The projects directory's own config is checked along with the config of each project
+/// directory directly under it. The directory itself is listed as [`ROOT_PROJECT_NAME`], and
+/// projects whose config can't be loaded are skipped.
+///
+/// ## Arguments
+///
+/// * `sandbox_name` - The name of the sandbox to look for
+/// * `projects_parent_dir` - The directory containing the projects
+///
+/// ## Returns
+///
+/// The names of the projects defining the sandbox, sorted alphabetically
+pub async fn find_sandbox_projects(
+    sandbox_name: &str,
+    projects_parent_dir: &Path,
+) -> MicrosandboxResult<Vec<String>> {
+    let mut projects = Vec::new();
+    for (project, project_dir) in list_projects(projects_parent_dir).await? {
+        match config::load_config(Some(&project_dir), None).await {
+            Ok((config, _, _)) if config.get_sandbox(sandbox_name).is_some() => {
+                projects.push(project)
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!("skipping project {}: {}", project_dir.display(), e);
+            }
+        }
+    }
+
+    projects.sort();
+    Ok(projects)
+}
+
+/// Lists the projects under a projects directory, i.e. the directory itself and each directory
+/// directly under it that has a config file.
+///
+/// The directory itself is listed as [`ROOT_PROJECT_NAME`].
+///
+/// ## Arguments
+///
+/// * `projects_parent_dir` - The directory containing the projects
+///
+/// ## Returns
+///
+/// The name and directory of each project, the directory itself first and the others in
+/// alphabetical order
+pub async fn list_projects(
+    projects_parent_dir: &Path,
+) -> MicrosandboxResult<Vec<(String, PathBuf)>> {
+    let mut projects = Vec::new();
+    let mut entries = tokio::fs::read_dir(projects_parent_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.is_dir()
+            && let Some(name) = path.file_name().and_then(|n| n.to_str())
+            && name != MICROSANDBOX_ENV_DIR
+        {
+            projects.push((name.to_string(), path));
+        }
+    }
+    projects.sort();
+    projects.insert(
+        0,
+        (
+            ROOT_PROJECT_NAME.to_string(),
+            projects_parent_dir.to_path_buf(),
+        ),
+    );
+
+    projects.retain(|(_, project_dir)| project_dir.join(MICROSANDBOX_CONFIG_FILENAME).exists());
+    Ok(projects)
+}
+
+/// Resolves the project directory a sandbox operation applies to.
+///
+/// When `project` is given it names the project to use, either a directory under
+/// `projects_parent_dir` or [`ROOT_PROJECT_NAME`] for the directory itself. Otherwise the
+/// sandbox is looked up by name across all projects; a name defined in exactly one project
+/// resolves to that project, and a name defined nowhere resolves to the projects directory
+/// itself so new sandboxes are created there.
+///
+/// ## Arguments
+///
+/// * `sandbox_name` - The name of the sandbox the operation applies to
+/// * `projects_parent_dir` - The directory containing the projects
+/// * `project` - The optional project to disambiguate the sandbox name with
+///
+/// ## Returns
+///
+/// Returns [`MicrosandboxError::AmbiguousSandbox`] listing the candidate projects if the name is
+/// defined in more than one project and no project was given.
+pub async fn resolve_sandbox_project(
+    sandbox_name: &str,
+    projects_parent_dir: &Path,
+    project: Option<&str>,
+) -> MicrosandboxResult<PathBuf> {
+    if let Some(project) = project {
+        if project == ROOT_PROJECT_NAME {
+            return Ok(projects_parent_dir.to_path_buf());
+        }
+
+        let _ = PathSegment::try_from(project)?;
+        return Ok(projects_parent_dir.join(project));
+    }
+
+    if !projects_parent_dir.exists() {
+        return Ok(projects_parent_dir.to_path_buf());
+    }
+
+    let mut projects = find_sandbox_projects(sandbox_name, projects_parent_dir).await?;
+    match projects.len() {
+        0 => Ok(projects_parent_dir.to_path_buf()),
+        1 => {
+            let project = projects.remove(0);
+            if project == ROOT_PROJECT_NAME {
+                Ok(projects_parent_dir.to_path_buf())
+            } else {
+                Ok(projects_parent_dir.join(project))
+            }
+        }
+        _ => Err(MicrosandboxError::AmbiguousSandbox {
+            sandbox: sandbox_name.to_string(),
+            projects,
+        }),
+    }
+}
+
+/// Show the status of the sandboxes
+///
+/// ## Arguments
+///
+/// * `names` - The names of the sandboxes to show the status of
+/// * `labels` - Label selectors the sandboxes must all match. A selector that matches no
+///   sandbox selects nothing.
+/// * `path` - The path to the microsandbox config file
+/// * `config` - The config file to use
+///
+/// ## Returns
+///
+/// Returns `MicrosandboxResult<()>` indicating success or failure. Possible failures include:
+/// - Config file not found or invalid
+/// - Database errors
+/// - Sandbox status retrieval failures
+///
+/// ## Example
+///
+/// ```no_run
+/// use std::path::PathBuf;
+/// use microsandbox_core::management::orchestra;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     orchestra::show_status(
+///         &["sandbox1".to_string(), "sandbox2".to_string()],
+///         &[],
+///         None,
+///         None
+///     ).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(feature = "cli")]
+pub async fn show_status(
+    names: &[String],
+    labels: &[LabelSelector],
+    path: Option<&Path>,
+    config: Option<&str>,
+) -> MicrosandboxResult<()> {
+    // Check if we're in a TTY to determine if we should do live updates
+    let is_tty = io::stdin().is_terminal();
+    let live_view = is_tty;
+    let update_interval = std::time::Duration::from_secs(2);
+
+    if live_view {
         println!("{}", style("Press Ctrl+C to exit live view").dim());
         // Use a loop with tokio sleep for live updates
         loop {
             // Clear the screen by printing ANSI escape code
             print!("\x1B[2J\x1B[1;1H");
 
-            display_status(names, path, config).await?;
+            display_status(names, labels, path, config).await?;
 
             // Show update message
             println!(
@@ -801,7 +1376,7 @@ pub async fn show_status(
         }
     } else {
         // Just display once for non-TTY
-        display_status(names, path, config).await?;
+        display_status(names, labels, path, config).await?;
     }
 
     Ok(())
@@ -883,10 +1458,174 @@ pub async fn show_status_projects(
     Ok(())
 }
 
+/// Show the combined resource usage of a project's sandboxes
+///
+/// This displays a header with the number of running sandboxes and their total CPU, memory and
+/// disk usage, followed by the same per-sandbox table as [`show_status`]. In a TTY the view is
+/// refreshed live; otherwise it is printed once.
+///
+/// ## Arguments
+///
+/// * `names` - The names of the sandboxes to include. If empty, includes all sandboxes.
+/// * `labels` - Label selectors the sandboxes must all match. A selector that matches no
+///   sandbox selects nothing.
+/// * `path` - The path to the microsandbox config file
+/// * `config` - The config file to use
+///
+/// ## Example
+///
+/// ```no_run
+/// use microsandbox_core::management::orchestra;
+///
+/// #[tokio::main]
+/// async fn main() -> anyhow::Result<()> {
+///     orchestra::show_top(&[], &[], None, None).await?;
+///     Ok(())
+/// }
+/// ```
+#[cfg(feature = "cli")]
+pub async fn show_top(
+    names: &[String],
+    labels: &[LabelSelector],
+    path: Option<&Path>,
+    config: Option<&str>,
+) -> MicrosandboxResult<()> {
+    // Check if we're in a TTY to determine if we should do live updates
+    let is_tty = io::stdin().is_terminal();
+    let live_view = is_tty;
+    let update_interval = std::time::Duration::from_secs(2);
+
+    if live_view {
+        println!("{}", style("Press Ctrl+C to exit live view").dim());
+        // Use a loop with tokio sleep for live updates
+        loop {
+            // Clear the screen by printing ANSI escape code
+            print!("\x1B[2J\x1B[1;1H");
+
+            display_top(names, labels, path, config).await?;
+
+            // Show update message
+            println!(
+                "\n{}",
+                style("Updating every 2 seconds. Press Ctrl+C to exit.").dim()
+            );
+
+            // Wait for the update interval
+            tokio::time::sleep(update_interval).await;
+        }
+    } else {
+        // Just display once for non-TTY
+        display_top(names, labels, path, config).await?;
+    }
+
+    Ok(())
+}
+
+/// Aggregates the resource usage of a set of sandboxes.
+///
+/// Sandboxes with no reading for a resource, such as stopped ones, don't contribute to its total.
+///
+/// ## Arguments
+///
+/// * `statuses` - The statuses to aggregate, as returned by [`status`]
+pub fn summarize_status(statuses: &[SandboxStatus]) -> StatusSummary {
+    statuses
+        .iter()
+        .fold(StatusSummary::default(), |mut summary, status| {
+            summary.total += 1;
+            if status.running {
+                summary.running += 1;
+            }
+            summary.cpu_usage += status.cpu_usage.unwrap_or(0.0);
+            summary.memory_usage += status.memory_usage.unwrap_or(0);
+            summary.disk_usage += status.disk_usage.unwrap_or(0);
+            summary
+        })
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Helpers
 //--------------------------------------------------------------------------------------------------
 
+/// Checks whether a process with the given PID exists, by sending it the null signal.
+///
+/// A process owned by another user can't be signalled but still exists. PIDs that don't fit a
+/// `pid_t`, or that `kill` would treat as a process group, never match a process.
+fn process_exists(pid: u32) -> bool {
+    match i32::try_from(pid) {
+        Ok(pid) if pid > 0 => matches!(
+            signal::kill(Pid::from_raw(pid), None),
+            Ok(()) | Err(nix::Error::EPERM)
+        ),
+        _ => false,
+    }
+}
+
+/// Works out which sandboxes [`apply`] has to start and stop
+fn plan_apply(config_sandboxes: &HashMap<String, Sandbox>, running: &[String]) -> ApplyPlan {
+    let mut to_start: Vec<String> = config_sandboxes
+        .keys()
+        .filter(|name| !running.contains(*name))
+        .cloned()
+        .collect();
+    let mut to_stop: Vec<String> = running
+        .iter()
+        .filter(|name| !config_sandboxes.contains_key(*name))
+        .cloned()
+        .collect();
+    to_start.sort();
+    to_stop.sort();
+
+    ApplyPlan { to_start, to_stop }
+}
+
+/// Checks that a plan may go ahead
+///
+/// Plans that don't stop any sandboxes, and any plan when `auto_approve` is set, are always
+/// allowed. Otherwise `prompt` is asked, and returns `None` when there is no one to ask.
+fn confirm_apply(
+    plan: &ApplyPlan,
+    auto_approve: bool,
+    prompt: impl FnOnce(&str) -> Option<bool>,
+) -> MicrosandboxResult<()> {
+    if auto_approve || !plan.is_destructive() {
+        return Ok(());
+    }
+
+    let question = format!(
+        "Stop {} sandbox(es) no longer in the config? [y/N] ",
+        plan.to_stop.len()
+    );
+    match prompt(&question) {
+        Some(true) => Ok(()),
+        Some(false) => Err(MicrosandboxError::ApplyNotConfirmed(
+            "stopping sandboxes was declined".to_string(),
+        )),
+        None => Err(MicrosandboxError::ApplyNotConfirmed(format!(
+            "refusing to stop {} without confirmation, pass --yes to stop them",
+            plan.to_stop.join(", ")
+        ))),
+    }
+}
+
+/// Asks a yes/no question on the terminal, returning `None` if stdin is not a terminal
+#[cfg(feature = "cli")]
+fn prompt_confirmation(question: &str) -> Option<bool> {
+    use std::io::Write;
+
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+
+    print!("{}", question);
+    io::stdout().flush().ok()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).ok()?;
+    let answer = answer.trim().to_lowercase();
+    Some(answer == "y" || answer == "yes")
+}
+
 // Helper function to prepare commands for multiple sandboxes
 async fn prepare_sandbox_commands(
     sandbox_names: &[&String],
@@ -907,7 +1646,11 @@ async fn prepare_sandbox_commands(
             vec![],
             false, // non-detached
             None,
+            None,
             true,
+            false,
+            None,
+            None,
         )
         .await?;
 
@@ -917,132 +1660,328 @@ async fn prepare_sandbox_commands(
     Ok(commands)
 }
 
-// Helper function to run multiple commands with prefixed output
-async fn run_commands_with_prefixed_output(
-    commands: Vec<(String, tokio::process::Command)>,
+/// Starts sandboxes with `start`, each holding a permit from `permits` while it starts and only
+/// once the sandboxes it depends on have started.
+///
+/// Only dependencies on the other sandboxes in `sandbox_names` are waited for, since the rest are
+/// either already running or not being started. Once a start fails, the sandboxes that haven't
+/// started yet are skipped.
+///
+/// ## Arguments
+///
+/// * `sandbox_names` - The sandboxes to start
+/// * `config_sandboxes` - The sandboxes in the configuration, to look up dependencies in
+/// * `permits` - The permits that cap how many sandboxes may be starting at once
+/// * `start` - Starts a sandbox, resolving once it counts as started
+///
+/// ## Returns
+///
+/// An error a start failed with, or `MicrosandboxError::ConfigValidation` if the
+/// sandboxes depend on each other in a cycle
+async fn start_in_dependency_order<F, Fut>(
+    sandbox_names: &[&String],
+    config_sandboxes: &HashMap<String, Sandbox>,
+    permits: &Semaphore,
+    start: F,
+) -> MicrosandboxResult<()>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<()>>,
+{
+    use futures::future::join_all;
+
+    let dependencies: HashMap<&str, Vec<&str>> = sandbox_names
+        .iter()
+        .map(|name| {
+            let depends_on = config_sandboxes
+                .get(name.as_str())
+                .map(|sandbox| sandbox.get_depends_on().as_slice())
+                .unwrap_or_default()
+                .iter()
+                .map(String::as_str)
+                .filter(|dependency| sandbox_names.iter().any(|name| name == dependency))
+                .collect();
+            (name.as_str(), depends_on)
+        })
+        .collect();
+
+    // A cycle would leave its sandboxes waiting on each other forever, so refuse to start any
+    let mut ordered = HashSet::new();
+    while ordered.len() < dependencies.len() {
+        let ready: Vec<&str> = dependencies
+            .iter()
+            .filter(|(name, depends_on)| {
+                !ordered.contains(*name) && depends_on.iter().all(|d| ordered.contains(d))
+            })
+            .map(|(name, _)| *name)
+            .collect();
+
+        if ready.is_empty() {
+            let mut cyclic: Vec<&str> = dependencies
+                .keys()
+                .filter(|name| !ordered.contains(*name))
+                .copied()
+                .collect();
+            cyclic.sort_unstable();
+            return Err(MicrosandboxError::ConfigValidation(format!(
+                "dependency cycle between sandboxes: {}",
+                cyclic.join(", ")
+            )));
+        }
+
+        ordered.extend(ready);
+    }
+
+    let (progress_tx, progress_rx) = watch::channel(StartProgress::default());
+
+    let starts = sandbox_names.iter().map(|name| {
+        let depends_on = &dependencies[name.as_str()];
+        let progress_tx = &progress_tx;
+        let mut progress_rx = progress_rx.clone();
+        let start = &start;
+
+        async move {
+            // The sender outlives every start, so waiting can't fail
+            let failed = progress_rx
+                .wait_for(|progress| {
+                    progress.failed
+                        || depends_on
+                            .iter()
+                            .all(|dependency| progress.started.contains(*dependency))
+                })
+                .await
+                .map(|progress| progress.failed)
+                .unwrap_or(true);
+            if failed {
+                return Ok(());
+            }
+
+            let _permit = permits
+                .acquire()
+                .await
+                .map_err(|e| anyhow::anyhow!("failed to acquire start permit: {}", e))?;
+
+            // Another start may have failed while this one waited for a permit
+            if progress_rx.borrow().failed {
+                return Ok(());
+            }
+
+            let result = start(name.to_string()).await;
+            progress_tx.send_modify(|progress| match &result {
+                Ok(()) => {
+                    progress.started.insert(name.to_string());
+                }
+                Err(_) => progress.failed = true,
+            });
+
+            result
+        }
+    });
+
+    join_all(starts).await.into_iter().collect()
+}
+
+// Helper function to start sandboxes with their output printed prefixed with their names, each
+// holding a permit until its supervisor reports it running, and wait for them to exit. The
+// sandboxes that did start are stopped if another one fails to
+#[allow(clippy::too_many_arguments)]
+async fn run_with_prefixed_output(
+    sandbox_names: &[&String],
+    config_sandboxes: &HashMap<String, Sandbox>,
+    script_name: Option<&str>,
+    project_dir: &Path,
+    config_file: &str,
+    pool: &Pool<Sqlite>,
+    permits: &Semaphore,
 ) -> MicrosandboxResult<()> {
+    let spawned = Mutex::new(Vec::new());
+    let result = start_in_dependency_order(sandbox_names, config_sandboxes, permits, |name| {
+        let spawned = &spawned;
+        let index = sandbox_names
+            .iter()
+            .position(|candidate| **candidate == name)
+            .unwrap_or_default();
+
+        async move {
+            let commands =
+                prepare_sandbox_commands(&[&name], script_name, project_dir, config_file).await?;
+            for (name, command) in commands {
+                let mut sandbox = spawn_with_prefixed_output(index, name, command)?;
+                let running =
+                    wait_for_prefixed_sandbox_running(&mut sandbox, pool, config_file).await;
+                spawned.lock().unwrap().push(sandbox);
+                running?;
+            }
+
+            Ok(())
+        }
+    })
+    .await;
+
+    let spawned = spawned.into_inner().unwrap();
+    if let Err(e) = result {
+        // Don't leave the sandboxes that did start running without anyone printing their output
+        stop_prefixed_sandboxes(spawned).await;
+        return Err(e);
+    }
+
+    wait_for_prefixed_sandboxes(spawned).await
+}
+
+// Helper function to wait until the supervisor of a sandbox spawned with prefixed output records
+// it as running, or exits. An exit is reported once every sandbox is waited for
+async fn wait_for_prefixed_sandbox_running(
+    sandbox: &mut PrefixedSandbox,
+    pool: &Pool<Sqlite>,
+    config_file: &str,
+) -> MicrosandboxResult<()> {
+    let supervisor_pid = sandbox.child.id();
+    loop {
+        if let Some(record) = db::get_sandbox(pool, &sandbox.name, config_file).await?
+            && record.status == SANDBOX_STATUS_RUNNING
+            && Some(record.supervisor_pid) == supervisor_pid
+        {
+            return Ok(());
+        }
+
+        if sandbox.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+
+        tokio::time::sleep(RUNNING_POLL_INTERVAL).await;
+    }
+}
+
+// Helper function to spawn a command whose output is printed prefixed with the sandbox name, in
+// the colour picked by `i`
+fn spawn_with_prefixed_output(
+    i: usize,
+    sandbox_name: String,
+    mut command: tokio::process::Command,
+) -> MicrosandboxResult<PrefixedSandbox> {
     use console::style;
-    use futures::future::join_all;
     use std::process::Stdio;
     use tokio::io::{AsyncBufReadExt, BufReader};
 
-    // Exit early if no commands to run
-    if commands.is_empty() {
-        return Ok(());
-    }
+    // Configure command to pipe stdout and stderr
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+
+    // Spawn the child process
+    let mut child = command.spawn()?;
+    let sandbox_name_clone = sandbox_name.clone();
+
+    // Style the sandbox name based on index
+    let styled_name = match i % 7 {
+        0 => style(&sandbox_name).green().bold(),
+        1 => style(&sandbox_name).blue().bold(),
+        2 => style(&sandbox_name).red().bold(),
+        3 => style(&sandbox_name).yellow().bold(),
+        4 => style(&sandbox_name).magenta().bold(),
+        5 => style(&sandbox_name).cyan().bold(),
+        _ => style(&sandbox_name).white().bold(),
+    };
 
-    // This will hold our child process handles and associated tasks
-    let mut children = Vec::new();
-    let mut output_tasks = Vec::new();
+    // Apply the same color to the separator bar
+    let styled_separator = match i % 7 {
+        0 => style("|").green(),
+        1 => style("|").blue(),
+        2 => style("|").red(),
+        3 => style("|").yellow(),
+        4 => style("|").magenta(),
+        5 => style("|").cyan(),
+        _ => style("|").white(),
+    };
 
-    // Spawn all child processes
-    for (i, (sandbox_name, mut command)) in commands.into_iter().enumerate() {
-        // Configure command to pipe stdout and stderr
-        command.stdout(Stdio::piped());
-        command.stderr(Stdio::piped());
-
-        // Spawn the child process
-        let mut child = command.spawn()?;
-        let sandbox_name_clone = sandbox_name.clone();
-
-        // Style the sandbox name based on index
-        let styled_name = match i % 7 {
-            0 => style(&sandbox_name).green().bold(),
-            1 => style(&sandbox_name).blue().bold(),
-            2 => style(&sandbox_name).red().bold(),
-            3 => style(&sandbox_name).yellow().bold(),
-            4 => style(&sandbox_name).magenta().bold(),
-            5 => style(&sandbox_name).cyan().bold(),
-            _ => style(&sandbox_name).white().bold(),
-        };
+    tracing::info!(
+        "{} {} started supervisor process with PID: {}",
+        styled_name,
+        styled_separator,
+        child.id().unwrap_or(0)
+    );
 
-        // Apply the same color to the separator bar
-        let styled_separator = match i % 7 {
-            0 => style("|").green(),
-            1 => style("|").blue(),
-            2 => style("|").red(),
-            3 => style("|").yellow(),
-            4 => style("|").magenta(),
-            5 => style("|").cyan(),
-            _ => style("|").white(),
-        };
+    // Create task to handle stdout
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let name_stdout = sandbox_name.clone();
+    let color_index = i;
+    let stdout_task = tokio::spawn(async move {
+        let mut reader = BufReader::new(stdout).lines();
+        while let Ok(Some(line)) = reader.next_line().await {
+            // Style the sandbox name and separator with color, but leave the message as plain text
+            let styled_name = match color_index % 7 {
+                0 => style(&name_stdout).green().bold(),
+                1 => style(&name_stdout).blue().bold(),
+                2 => style(&name_stdout).red().bold(),
+                3 => style(&name_stdout).yellow().bold(),
+                4 => style(&name_stdout).magenta().bold(),
+                5 => style(&name_stdout).cyan().bold(),
+                _ => style(&name_stdout).white().bold(),
+            };
 
-        tracing::info!(
-            "{} {} started supervisor process with PID: {}",
-            styled_name,
-            styled_separator,
-            child.id().unwrap_or(0)
-        );
+            // Apply the same color to the separator bar
+            let styled_separator = match color_index % 7 {
+                0 => style("|").green(),
+                1 => style("|").blue(),
+                2 => style("|").red(),
+                3 => style("|").yellow(),
+                4 => style("|").magenta(),
+                5 => style("|").cyan(),
+                _ => style("|").white(),
+            };
 
-        // Create task to handle stdout
-        let stdout = child.stdout.take().expect("Failed to capture stdout");
-        let name_stdout = sandbox_name.clone();
-        let color_index = i;
-        let stdout_task = tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                // Style the sandbox name and separator with color, but leave the message as plain text
-                let styled_name = match color_index % 7 {
-                    0 => style(&name_stdout).green().bold(),
-                    1 => style(&name_stdout).blue().bold(),
-                    2 => style(&name_stdout).red().bold(),
-                    3 => style(&name_stdout).yellow().bold(),
-                    4 => style(&name_stdout).magenta().bold(),
-                    5 => style(&name_stdout).cyan().bold(),
-                    _ => style(&name_stdout).white().bold(),
-                };
+            println!("{} {} {}", styled_name, styled_separator, line);
+        }
+    });
 
-                // Apply the same color to the separator bar
-                let styled_separator = match color_index % 7 {
-                    0 => style("|").green(),
-                    1 => style("|").blue(),
-                    2 => style("|").red(),
-                    3 => style("|").yellow(),
-                    4 => style("|").magenta(),
-                    5 => style("|").cyan(),
-                    _ => style("|").white(),
-                };
+    // Create task to handle stderr
+    let stderr = child.stderr.take().expect("Failed to capture stderr");
+    let color_index = i;
+    let stderr_task = tokio::spawn(async move {
+        let mut reader = BufReader::new(stderr).lines();
+        while let Ok(Some(line)) = reader.next_line().await {
+            // Style the sandbox name and separator with color, but leave the message as plain text
+            let styled_name = match color_index % 7 {
+                0 => style(&sandbox_name_clone).green().bold(),
+                1 => style(&sandbox_name_clone).blue().bold(),
+                2 => style(&sandbox_name_clone).red().bold(),
+                3 => style(&sandbox_name_clone).yellow().bold(),
+                4 => style(&sandbox_name_clone).magenta().bold(),
+                5 => style(&sandbox_name_clone).cyan().bold(),
+                _ => style(&sandbox_name_clone).white().bold(),
+            };
 
-                println!("{} {} {}", styled_name, styled_separator, line);
-            }
-        });
+            // Apply the same color to the separator bar
+            let styled_separator = match color_index % 7 {
+                0 => style("|").green(),
+                1 => style("|").blue(),
+                2 => style("|").red(),
+                3 => style("|").yellow(),
+                4 => style("|").magenta(),
+                5 => style("|").cyan(),
+                _ => style("|").white(),
+            };
 
-        // Create task to handle stderr
-        let stderr = child.stderr.take().expect("Failed to capture stderr");
-        let color_index = i;
-        let stderr_task = tokio::spawn(async move {
-            let mut reader = BufReader::new(stderr).lines();
-            while let Ok(Some(line)) = reader.next_line().await {
-                // Style the sandbox name and separator with color, but leave the message as plain text
-                let styled_name = match color_index % 7 {
-                    0 => style(&sandbox_name_clone).green().bold(),
-                    1 => style(&sandbox_name_clone).blue().bold(),
-                    2 => style(&sandbox_name_clone).red().bold(),
-                    3 => style(&sandbox_name_clone).yellow().bold(),
-                    4 => style(&sandbox_name_clone).magenta().bold(),
-                    5 => style(&sandbox_name_clone).cyan().bold(),
-                    _ => style(&sandbox_name_clone).white().bold(),
-                };
+            eprintln!("{} {} {}", styled_name, styled_separator, line);
+        }
+    });
 
-                // Apply the same color to the separator bar
-                let styled_separator = match color_index % 7 {
-                    0 => style("|").green(),
-                    1 => style("|").blue(),
-                    2 => style("|").red(),
-                    3 => style("|").yellow(),
-                    4 => style("|").magenta(),
-                    5 => style("|").cyan(),
-                    _ => style("|").white(),
-                };
+    Ok(PrefixedSandbox {
+        name: sandbox_name,
+        child,
+        output_tasks: vec![stdout_task, stderr_task],
+    })
+}
 
-                eprintln!("{} {} {}", styled_name, styled_separator, line);
-            }
-        });
+// Helper function to wait for sandboxes spawned with prefixed output to exit
+async fn wait_for_prefixed_sandboxes(sandboxes: Vec<PrefixedSandbox>) -> MicrosandboxResult<()> {
+    use console::style;
+    use futures::future::join_all;
 
-        // Add to our collections
-        children.push((sandbox_name, child));
-        output_tasks.push(stdout_task);
-        output_tasks.push(stderr_task);
+    let mut children = Vec::new();
+    let mut output_tasks = Vec::new();
+    for sandbox in sandboxes {
+        children.push((sandbox.name, sandbox.child));
+        output_tasks.extend(sandbox.output_tasks);
     }
 
     // Create task to monitor child processes
@@ -1120,15 +2059,76 @@ async fn run_commands_with_prefixed_output(
     Ok(())
 }
 
-// Extracted the status display logic to a separate function
+// Helper function to stop sandboxes spawned with prefixed output, e.g. when the rest of the
+// sandboxes they were started with failed to start
+async fn stop_prefixed_sandboxes(sandboxes: Vec<PrefixedSandbox>) {
+    for mut sandbox in sandboxes {
+        tracing::info!("stopping sandbox: {}", sandbox.name);
+        if let Err(e) = terminate_child(&mut sandbox.child, DEFAULT_SHUTDOWN_GRACE_PERIOD).await {
+            tracing::error!("failed to stop sandbox {}: {}", sandbox.name, e);
+        }
+
+        futures::future::join_all(sandbox.output_tasks).await;
+    }
+}
+
+// Extracted the status display logic to a separate function
+#[cfg(feature = "cli")]
+async fn display_status(
+    names: &[String],
+    labels: &[LabelSelector],
+    path: Option<&Path>,
+    config: Option<&str>,
+) -> MicrosandboxResult<()> {
+    let statuses = status(names.to_vec(), labels, path, config).await?;
+
+    print_timestamp();
+    print_status_table(statuses);
+
+    Ok(())
+}
+
+// Display the combined resource usage of sandboxes above their status table
+#[cfg(feature = "cli")]
+async fn display_top(
+    names: &[String],
+    labels: &[LabelSelector],
+    path: Option<&Path>,
+    config: Option<&str>,
+) -> MicrosandboxResult<()> {
+    let statuses = status(names.to_vec(), labels, path, config).await?;
+    let summary = summarize_status(&statuses);
+
+    print_timestamp();
+    println!(
+        "\n{} {}/{}   {} {:.1}%   {} {} MiB   {} {}",
+        style("RUNNING").bold(),
+        summary.running,
+        summary.total,
+        style("CPU").bold(),
+        summary.cpu_usage,
+        style("MEMORY").bold(),
+        summary.memory_usage,
+        style("DISK").bold(),
+        format_bytes(summary.disk_usage)
+    );
+
+    print_status_table(statuses);
+
+    Ok(())
+}
+
+/// Prints when the status display was last updated
 #[cfg(feature = "cli")]
-async fn display_status(
-    names: &[String],
-    path: Option<&Path>,
-    config: Option<&str>,
-) -> MicrosandboxResult<()> {
-    let mut statuses = status(names.to_vec(), path, config).await?;
+fn print_timestamp() {
+    let now = chrono::Local::now();
+    let timestamp = now.format("%Y-%m-%d %H:%M:%S");
+    println!("{}", style(format!("Last updated: {}", timestamp)).dim());
+}
 
+/// Prints a table of sandbox statuses in a stable order
+#[cfg(feature = "cli")]
+fn print_status_table(mut statuses: Vec<SandboxStatus>) {
     // Sort the statuses in a stable order to prevent entries from moving around between updates
     // Order by: running status (running first), CPU usage (highest first),
     // memory usage (highest first), disk usage (highest first), and finally name (alphabetical)
@@ -1170,41 +2170,34 @@ async fn display_status(
         a.name.cmp(&b.name)
     });
 
-    // Get current timestamp
-    let now = chrono::Local::now();
-    let timestamp = now.format("%Y-%m-%d %H:%M:%S");
-
-    // Display timestamp
-    println!("{}", style(format!("Last updated: {}", timestamp)).dim());
-
     // Print a table-like output with status information
     println!(
-        "\n{:<15} {:<10} {:<15} {:<12} {:<12} {:<12}",
+        "\n{:<15} {:<10} {:<15} {:<12} {:<12} {:<12} {}",
         style("SANDBOX").bold(),
         style("STATUS").bold(),
         style("PIDS").bold(),
         style("CPU").bold(),
         style("MEMORY").bold(),
-        style("DISK").bold()
+        style("DISK").bold(),
+        style("LABELS").bold()
     );
 
-    println!("{}", style("─".repeat(80)).dim());
+    println!("{}", style("─".repeat(100)).dim());
 
     for status in statuses {
         let (status_text, pids, cpu, memory, disk) = format_status_columns(&status);
 
         println!(
-            "{:<15} {:<10} {:<15} {:<12} {:<12} {:<12}",
+            "{:<15} {:<10} {:<15} {:<12} {:<12} {:<12} {}",
             style(&status.name).bold(),
             status_text,
             pids,
             cpu,
             memory,
-            disk
+            disk,
+            format_labels(&status.labels)
         );
     }
-
-    Ok(())
 }
 
 // Display status of sandboxes across multiple projects
@@ -1262,7 +2255,7 @@ async fn display_status_projects(
         project_count += 1;
 
         // Get statuses for this project
-        match status(names.to_vec(), Some(project_dir), None).await {
+        match status(names.to_vec(), &[], Some(project_dir), None).await {
             Ok(statuses) => {
                 // Add project info to each status
                 for status in statuses {
@@ -1272,6 +2265,8 @@ async fn display_status_projects(
                     });
                 }
             }
+            // A project without sandboxes has nothing to show
+            Err(MicrosandboxError::NoSandboxesInConfig(_)) => {}
             Err(e) => {
                 // Log error but continue with other projects
                 tracing::warn!("Error getting status for project {}: {}", project, e);
@@ -1506,15 +2501,7 @@ fn format_status_columns(
     };
 
     let disk = if let Some(disk_usage) = status.disk_usage {
-        if disk_usage > 1024 * 1024 * 1024 {
-            format!("{:.2} GB", disk_usage as f64 / (1024.0 * 1024.0 * 1024.0))
-        } else if disk_usage > 1024 * 1024 {
-            format!("{:.2} MB", disk_usage as f64 / (1024.0 * 1024.0))
-        } else if disk_usage > 1024 {
-            format!("{:.2} KB", disk_usage as f64 / 1024.0)
-        } else {
-            format!("{} B", disk_usage)
-        }
+        format_bytes(disk_usage)
     } else {
         "-".to_string()
     };
@@ -1522,6 +2509,146 @@ fn format_status_columns(
     (status_text, pids, cpu, memory, disk)
 }
 
+/// Formats labels as comma separated `key=value` pairs sorted by key, or `-` if there are none
+pub(crate) fn format_labels(labels: &HashMap<String, String>) -> String {
+    if labels.is_empty() {
+        return "-".to_string();
+    }
+
+    let mut labels: Vec<String> = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+    labels.sort();
+    labels.join(",")
+}
+
+/// Formats a size in bytes using the largest unit it exceeds
+#[cfg(feature = "cli")]
+fn format_bytes(bytes: u64) -> String {
+    if bytes > 1024 * 1024 * 1024 {
+        format!("{:.2} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    } else if bytes > 1024 * 1024 {
+        format!("{:.2} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes > 1024 {
+        format!("{:.2} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Returns the memory used by a sandbox's microVM process in MiB.
+///
+/// The memory charged to the process's cgroup v2 is preferred, since it includes the page cache
+/// and shared pages RSS leaves out and is what the kernel enforces limits against. It is only used
+/// when the cgroup holds nothing but the sandbox's own processes, as otherwise it would include
+/// unrelated memory. RSS is used in every other case.
+fn get_memory_usage(
+    process: &psutil::process::Process,
+    sandbox_pids: &[u32],
+    proc_root: &Path,
+    cgroup_root: &Path,
+) -> Option<u64> {
+    let bytes = match get_cgroup_memory_usage(process.pid(), sandbox_pids, proc_root, cgroup_root) {
+        Some(bytes) => bytes,
+        None => process.memory_info().ok()?.rss(),
+    };
+
+    // Convert bytes to MiB
+    Some(bytes / (1024 * 1024))
+}
+
+/// Reads `memory.current` of the cgroup v2 a process belongs to, in bytes.
+///
+/// Returns `None` if the process is not in a cgroup v2, the cgroup contains processes other than
+/// `sandbox_pids`, or the file can't be read.
+fn get_cgroup_memory_usage(
+    pid: u32,
+    sandbox_pids: &[u32],
+    proc_root: &Path,
+    cgroup_root: &Path,
+) -> Option<u64> {
+    // The unified hierarchy is the line with hierarchy ID 0, e.g. `0::/user.slice/msb-app`
+    let cgroups = std::fs::read_to_string(proc_root.join(pid.to_string()).join("cgroup")).ok()?;
+    let cgroup_path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))?
+        .trim_start_matches('/');
+    let cgroup_dir = cgroup_root.join(cgroup_path);
+
+    let procs = std::fs::read_to_string(cgroup_dir.join("cgroup.procs")).ok()?;
+    let exclusive = procs.lines().all(|proc| {
+        proc.trim()
+            .parse()
+            .is_ok_and(|pid| sandbox_pids.contains(&pid))
+    });
+    if !exclusive {
+        return None;
+    }
+
+    std::fs::read_to_string(cgroup_dir.join("memory.current"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Returns the images the sandboxes of a configuration use, each once, ordered by reference.
+///
+/// References that differ only in form, like `alpine` and `docker.io/library/alpine:latest`,
+/// name the same image.
+fn config_images(config: &Microsandbox) -> Vec<Reference> {
+    let mut images = BTreeMap::new();
+    for sandbox in config.get_sandboxes().values() {
+        if let ReferenceOrPath::Reference(reference) = sandbox.get_image() {
+            images
+                .entry(reference.normalize())
+                .or_insert_with(|| reference.clone());
+        }
+    }
+
+    images.into_values().collect()
+}
+
+/// Pulls images with `pull`, at most `max_concurrent` at a time.
+///
+/// ## Returns
+///
+/// The summaries of the pulls, in the order of `images`, or the first error
+async fn pull_images<F, Fut>(
+    images: Vec<Reference>,
+    max_concurrent: usize,
+    pull: F,
+) -> MicrosandboxResult<Vec<PullSummary>>
+where
+    F: FnMut(Reference) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<PullSummary>>,
+{
+    stream::iter(images)
+        .map(pull)
+        .buffered(max_concurrent.max(1))
+        .try_collect()
+        .await
+}
+
+/// Checks that the configuration defines at least one sandbox
+///
+/// Operating on every sandbox of a configuration without any would silently do nothing, which
+/// leaves first-time users without a hint of what is missing.
+fn ensure_sandboxes_defined(
+    config: &Microsandbox,
+    project_dir: &Path,
+    config_file: &str,
+) -> MicrosandboxResult<()> {
+    if config.get_sandboxes().is_empty() {
+        return Err(MicrosandboxError::NoSandboxesInConfig(
+            project_dir.join(config_file),
+        ));
+    }
+
+    Ok(())
+}
+
 /// Validate that all requested sandbox names exist in the configuration
 fn validate_sandbox_names(
     sandbox_names: &[String],
@@ -1547,6 +2674,37 @@ fn validate_sandbox_names(
     Ok(())
 }
 
+/// Resolves the sandboxes an operation applies to
+///
+/// The named sandboxes, or every sandbox in the config if none are named, are narrowed down to
+/// those matching all of the label selectors. With selectors, the result can be empty, in which
+/// case nothing is selected rather than everything.
+fn select_sandbox_names(
+    sandbox_names: Vec<String>,
+    labels: &[LabelSelector],
+    config: &Microsandbox,
+    project_dir: &Path,
+    config_file: &str,
+) -> MicrosandboxResult<Vec<String>> {
+    let config_sandboxes = config.get_sandboxes();
+
+    let mut names = if sandbox_names.is_empty() {
+        // Use all sandbox names from config
+        let mut names: Vec<String> = config_sandboxes.keys().cloned().collect();
+        names.sort();
+        names
+    } else {
+        // Validate all sandbox names exist in config before proceeding
+        validate_sandbox_names(&sandbox_names, config, project_dir, config_file)?;
+
+        sandbox_names
+    };
+
+    names.retain(|name| LabelSelector::matches_all(labels, &config_sandboxes[name]));
+
+    Ok(names)
+}
+
 /// Recursively calculate the size of a directory, but cache the result for a short period so that
 /// callers (status refresh every ~2 s) don't hammer the filesystem.
 async fn get_directory_size(path: &str) -> MicrosandboxResult<u64> {
@@ -1562,19 +2720,8 @@ async fn get_directory_size(path: &str) -> MicrosandboxResult<u64> {
 
     // Need to (re)compute – perform blocking walk in a separate thread so we don't block Tokio
     let path_buf = PathBuf::from(path);
-    let size = tokio::task::spawn_blocking(move || -> MicrosandboxResult<u64> {
-        use walkdir::WalkDir;
-
-        let mut total: u64 = 0;
-        for entry in WalkDir::new(&path_buf).follow_links(false) {
-            let entry = entry?; // propagates walkdir::Error (already covered in MicrosandboxError)
-            if entry.file_type().is_file() {
-                total += entry.metadata()?.len();
-            }
-        }
-        Ok(total)
-    })
-    .await??; // first ? = JoinError, second ? = inner MicrosandboxError
+    // first ? = JoinError, second ? = inner MicrosandboxError
+    let size = tokio::task::spawn_blocking(move || compute_directory_size(&path_buf)).await??;
 
     // Update cache
     {
@@ -1585,6 +2732,57 @@ async fn get_directory_size(path: &str) -> MicrosandboxResult<u64> {
     Ok(size)
 }
 
+/// Calculates the size of the files under a directory, walking its subtrees in parallel.
+///
+/// Each entry at the top of the directory is walked on its own, spread over up to as many threads
+/// as the host has cores. A file with several hard links is only counted once.
+fn compute_directory_size(path: &Path) -> MicrosandboxResult<u64> {
+    let roots = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let threads = std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(roots.len())
+        .max(1);
+    let next_root = AtomicUsize::new(0);
+    let linked_files = Mutex::new(HashSet::new());
+
+    // Each thread takes the next unwalked subtree until there are none left
+    let walk_roots = || -> MicrosandboxResult<u64> {
+        let mut total = 0;
+        while let Some(root) = roots.get(next_root.fetch_add(1, Ordering::Relaxed)) {
+            for entry in walkdir::WalkDir::new(root).follow_links(false) {
+                let entry = entry?; // walkdir::Error is covered by MicrosandboxError
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+
+                let metadata = entry.metadata()?;
+                if metadata.nlink() > 1
+                    && !linked_files
+                        .lock()
+                        .unwrap()
+                        .insert((metadata.dev(), metadata.ino()))
+                {
+                    continue;
+                }
+
+                total += metadata.len();
+            }
+        }
+        Ok(total)
+    };
+
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(walk_roots)).collect();
+        workers.into_iter().try_fold(0, |total, worker| {
+            Ok(total + worker.join().expect("directory size thread panicked")?)
+        })
+    })
+}
+
 /// Checks if specified sandboxes from the configuration are running.
 async fn _check_running(
     sandbox_names: Vec<String>,
@@ -1626,3 +2824,588 @@ async fn _check_running(
 
     Ok(statuses)
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    async fn write_project(projects_dir: &Path, project: &str, sandboxes: &[&str]) {
+        let project_dir = projects_dir.join(project);
+        tokio::fs::create_dir_all(&project_dir).await.unwrap();
+
+        let mut config = String::from("sandboxes:\n");
+        for sandbox in sandboxes {
+            config.push_str(&format!("  {sandbox}:\n    image: \"alpine:latest\"\n"));
+        }
+        tokio::fs::write(project_dir.join(MICROSANDBOX_CONFIG_FILENAME), config)
+            .await
+            .unwrap();
+    }
+
+    fn sandbox_status(
+        name: &str,
+        cpu_usage: Option<f32>,
+        memory_usage: Option<u64>,
+        disk_usage: Option<u64>,
+    ) -> SandboxStatus {
+        SandboxStatus {
+            name: name.to_string(),
+            running: cpu_usage.is_some(),
+            supervisor_pid: None,
+            microvm_pid: None,
+            cpu_usage,
+            memory_usage,
+            disk_usage,
+            rootfs_paths: None,
+            labels: HashMap::new(),
+        }
+    }
+
+    fn plan(to_start: &[&str], to_stop: &[&str]) -> ApplyPlan {
+        ApplyPlan {
+            to_start: to_start.iter().map(|name| name.to_string()).collect(),
+            to_stop: to_stop.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_orchestra_plan_apply() -> anyhow::Result<()> {
+        let config: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+              db:
+                image: "postgres:16"
+              cache:
+                image: "redis:7"
+        "#,
+        )?;
+        let running = vec!["web".to_string(), "old".to_string(), "legacy".to_string()];
+
+        let apply_plan = plan_apply(config.get_sandboxes(), &running);
+        assert_eq!(apply_plan, plan(&["cache", "db"], &["legacy", "old"]));
+        assert!(apply_plan.is_destructive());
+        assert_eq!(
+            apply_plan.summary(),
+            "+ start cache\n+ start db\n- stop  legacy\n- stop  old"
+        );
+
+        assert_eq!(plan(&[], &[]).summary(), "");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orchestra_start_in_dependency_order_respects_cap() -> anyhow::Result<()> {
+        let config: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+                depends_on: [db, cache]
+              db:
+                image: "postgres:16"
+              cache:
+                image: "redis:7"
+              worker:
+                image: "alpine:latest"
+              mail:
+                image: "alpine:latest"
+              search:
+                image: "alpine:latest"
+        "#,
+        )?;
+        let names: Vec<&String> = config.get_sandboxes().keys().collect();
+
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+        let order = Mutex::new(Vec::new());
+        start_in_dependency_order(&names, config.get_sandboxes(), &Semaphore::new(2), |name| {
+            let (in_flight, max_in_flight, order) = (&in_flight, &max_in_flight, &order);
+            async move {
+                let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max_in_flight.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                order.lock().unwrap().push(name);
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            }
+        })
+        .await?;
+
+        assert_eq!(max_in_flight.load(Ordering::SeqCst), 2);
+
+        let order = order.into_inner().unwrap();
+        assert_eq!(order.len(), 6);
+        let position = |name: &str| order.iter().position(|n| n == name).unwrap();
+        assert!(position("web") > position("db"));
+        assert!(position("web") > position("cache"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orchestra_start_in_dependency_order_stops_on_failure() -> anyhow::Result<()> {
+        let config: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+                depends_on: [db]
+              db:
+                image: "postgres:16"
+        "#,
+        )?;
+        let names: Vec<&String> = config.get_sandboxes().keys().collect();
+
+        let started = Mutex::new(Vec::new());
+        let result =
+            start_in_dependency_order(&names, config.get_sandboxes(), &Semaphore::new(4), |name| {
+                let started = &started;
+                async move {
+                    started.lock().unwrap().push(name.clone());
+                    match name.as_str() {
+                        "db" => Err(MicrosandboxError::SupervisorError("db crashed".to_string())),
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .await;
+
+        assert!(matches!(result, Err(MicrosandboxError::SupervisorError(_))));
+        assert_eq!(started.into_inner().unwrap(), vec!["db".to_string()]);
+
+        // Sandboxes that depend on each other can never start
+        let config: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              a:
+                image: "alpine:latest"
+                depends_on: [b]
+              b:
+                image: "alpine:latest"
+                depends_on: [a]
+        "#,
+        )?;
+        let names: Vec<&String> = config.get_sandboxes().keys().collect();
+        let result = start_in_dependency_order(
+            &names,
+            config.get_sandboxes(),
+            &Semaphore::new(4),
+            |_| async { Ok(()) },
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::ConfigValidation(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orchestra_select_sandbox_names_by_label() -> anyhow::Result<()> {
+        let config: Microsandbox = serde_yaml::from_str(
+            r#"
+            sandboxes:
+              web:
+                image: "nginx:latest"
+                labels:
+                  tier: web
+                  team: shop
+              admin:
+                image: "nginx:latest"
+                labels:
+                  tier: web
+                  team: ops
+              db:
+                image: "postgres:16"
+                labels:
+                  tier: data
+        "#,
+        )?;
+        let select = |names: &[&str], labels: &[&str]| -> MicrosandboxResult<Vec<String>> {
+            let labels = labels
+                .iter()
+                .map(|label| label.parse())
+                .collect::<MicrosandboxResult<Vec<LabelSelector>>>()?;
+            select_sandbox_names(
+                names.iter().map(|name| name.to_string()).collect(),
+                &labels,
+                &config,
+                Path::new("/project"),
+                MICROSANDBOX_CONFIG_FILENAME,
+            )
+        };
+
+        // Without selectors, all or only the named sandboxes are selected
+        assert_eq!(select(&[], &[])?, vec!["admin", "db", "web"]);
+        assert_eq!(select(&["db"], &[])?, vec!["db"]);
+
+        // Selectors expand to every matching sandbox, and all of them have to match
+        assert_eq!(select(&[], &["tier=web"])?, vec!["admin", "web"]);
+        assert_eq!(select(&[], &["tier=web", "team=shop"])?, vec!["web"]);
+
+        // Selectors narrow down named sandboxes
+        assert_eq!(select(&["web", "db"], &["tier=web"])?, vec!["web"]);
+
+        // A selector that matches nothing selects nothing, rather than everything
+        assert!(select(&[], &["tier=cache"])?.is_empty());
+        assert!(select(&[], &["owner=web"])?.is_empty());
+
+        // Unknown names are still rejected
+        assert!(matches!(
+            select(&["missing"], &["tier=web"]),
+            Err(MicrosandboxError::SandboxNotFoundInConfig(..))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orchestra_format_labels() {
+        assert_eq!(format_labels(&HashMap::new()), "-");
+
+        let labels = HashMap::from([
+            ("tier".to_string(), "web".to_string()),
+            ("team".to_string(), "shop".to_string()),
+        ]);
+        assert_eq!(format_labels(&labels), "team=shop,tier=web");
+    }
+
+    #[test]
+    fn test_orchestra_confirm_apply() {
+        let additions = plan(&["web"], &[]);
+        let removals = plan(&["web"], &["old"]);
+        let unreachable = |_: &str| -> Option<bool> { panic!("should not prompt") };
+
+        // Only starting sandboxes never prompts
+        assert!(confirm_apply(&additions, false, unreachable).is_ok());
+
+        // Auto-approval skips the prompt for stops
+        assert!(confirm_apply(&removals, true, unreachable).is_ok());
+
+        // Otherwise stops need an explicit yes
+        assert!(
+            confirm_apply(&removals, false, |question| {
+                assert!(question.contains("Stop 1 sandbox"));
+                Some(true)
+            })
+            .is_ok()
+        );
+        assert!(matches!(
+            confirm_apply(&removals, false, |_| Some(false)),
+            Err(MicrosandboxError::ApplyNotConfirmed(_))
+        ));
+        assert!(matches!(
+            confirm_apply(&removals, false, |_| None),
+            Err(MicrosandboxError::ApplyNotConfirmed(message)) if message.contains("--yes")
+        ));
+    }
+
+    #[test]
+    fn test_orchestra_summarize_status() {
+        let statuses = [
+            sandbox_status("web", Some(12.5), Some(256), Some(4096)),
+            sandbox_status("db", Some(30.0), Some(1024), Some(1 << 20)),
+            sandbox_status("worker", None, None, Some(512)),
+        ];
+
+        assert_eq!(
+            summarize_status(&statuses),
+            StatusSummary {
+                total: 3,
+                running: 2,
+                cpu_usage: 42.5,
+                memory_usage: 1280,
+                disk_usage: 4096 + (1 << 20) + 512,
+            }
+        );
+        assert_eq!(summarize_status(&[]), StatusSummary::default());
+    }
+
+    fn write_cgroup(root: &Path, pid: u32, procs: &[u32], memory_current: u64) {
+        let proc_dir = root.join("proc").join(pid.to_string());
+        std::fs::create_dir_all(&proc_dir).unwrap();
+        std::fs::write(proc_dir.join("cgroup"), "0::/msb/app\n").unwrap();
+
+        let cgroup_dir = root.join("cgroup/msb/app");
+        std::fs::create_dir_all(&cgroup_dir).unwrap();
+        let procs: Vec<String> = procs.iter().map(|pid| pid.to_string()).collect();
+        std::fs::write(cgroup_dir.join("cgroup.procs"), procs.join("\n")).unwrap();
+        std::fs::write(
+            cgroup_dir.join("memory.current"),
+            format!("{memory_current}\n"),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_orchestra_memory_usage_prefers_cgroup() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let (proc_root, cgroup_root) =
+            (temp_dir.path().join("proc"), temp_dir.path().join("cgroup"));
+        let process = psutil::process::Process::current()?;
+        let pid = process.pid();
+
+        // Without a cgroup, RSS is reported
+        let rss_mib = process.memory_info()?.rss() / (1024 * 1024);
+        let memory_usage = get_memory_usage(&process, &[pid], &proc_root, &cgroup_root);
+        assert!(memory_usage.is_some_and(|mib| mib < 64 * 1024 && mib.abs_diff(rss_mib) < 64));
+
+        // A cgroup holding only the sandbox's processes takes precedence
+        write_cgroup(temp_dir.path(), pid, &[1, pid], 64 * 1024 * 1024 * 1024);
+        assert_eq!(
+            get_memory_usage(&process, &[1, pid], &proc_root, &cgroup_root),
+            Some(64 * 1024)
+        );
+
+        // A cgroup shared with other processes is ignored
+        assert_eq!(
+            get_cgroup_memory_usage(pid, &[pid], &proc_root, &cgroup_root),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orchestra_liveness() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        write_project(temp_dir.path(), "shop", &["web", "api", "db"]).await;
+        let project_dir = temp_dir.path().join("shop");
+
+        // Record web as running and api as running under a supervisor that is gone, leaving db
+        // stopped
+        let menv_path = project_dir.join(MICROSANDBOX_ENV_DIR);
+        menv::ensure_menv_files(&menv_path).await?;
+        let pool = db::get_or_create_pool(
+            &menv_path.join(SANDBOX_DB_FILENAME),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await?;
+        for (name, pid) in [("web", std::process::id()), ("api", u32::MAX)] {
+            db::save_or_update_sandbox(
+                &pool,
+                name,
+                MICROSANDBOX_CONFIG_FILENAME,
+                &chrono::Utc::now(),
+                crate::runtime::SANDBOX_STATUS_RUNNING,
+                pid,
+                pid,
+                "native:/nonexistent",
+            )
+            .await?;
+        }
+
+        let web = liveness("web", Some(&project_dir), None).await?;
+        assert_eq!(web, SandboxLiveness::Running);
+        assert_eq!(web.exit_code(), 0);
+
+        let api = liveness("api", Some(&project_dir), None).await?;
+        assert_eq!(api, SandboxLiveness::Stopped);
+        assert_eq!(api.exit_code(), 1);
+
+        let db = liveness("db", Some(&project_dir), None).await?;
+        assert_eq!(db, SandboxLiveness::Stopped);
+        assert_eq!(db.exit_code(), 1);
+
+        let cache = liveness("cache", Some(&project_dir), None).await?;
+        assert_eq!(cache, SandboxLiveness::Unknown);
+        assert_eq!(cache.exit_code(), 2);
+
+        // Without a configuration the liveness can't be checked at all
+        assert!(liveness("web", Some(temp_dir.path()), None).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orchestra_up_and_status_without_sandboxes() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let project_dir = temp_dir.path();
+        let config_path = project_dir.join(MICROSANDBOX_CONFIG_FILENAME);
+        let expected_path = std::fs::canonicalize(project_dir)?.join(MICROSANDBOX_CONFIG_FILENAME);
+        let is_no_sandboxes = |result: MicrosandboxResult<_>| match result {
+            Err(MicrosandboxError::NoSandboxesInConfig(path)) => path == expected_path,
+            _ => false,
+        };
+
+        // Neither a config without a sandboxes section nor one with an empty one defines any
+        for contents in ["# Sandbox configurations\n", "sandboxes: {}\n"] {
+            tokio::fs::write(&config_path, contents).await?;
+
+            let result = up(vec![], &[], Some(project_dir), None, true, None, None).await;
+            assert!(is_no_sandboxes(result.map(|_| ())), "{contents:?}");
+
+            let result = status(vec![], &[], Some(project_dir), None).await;
+            assert!(is_no_sandboxes(result.map(|_| ())), "{contents:?}");
+
+            // Nothing was set up for the project
+            assert!(!project_dir.join(MICROSANDBOX_ENV_DIR).exists());
+        }
+
+        // With nothing running, there is nothing for apply to stop either
+        let result = apply(Some(project_dir), None, true, true).await;
+        assert!(is_no_sandboxes(result));
+
+        let error = MicrosandboxError::NoSandboxesInConfig(expected_path.clone());
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "no sandboxes defined in '{}'; run `msb add` to create one",
+                expected_path.display()
+            )
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orchestra_prewarm_pulls_each_image_once() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let config = r#"
+sandboxes:
+  web:
+    image: "python:3.12"
+  worker:
+    image: "docker.io/library/python:3.12"
+"#;
+        tokio::fs::write(temp_dir.path().join(MICROSANDBOX_CONFIG_FILENAME), config).await?;
+        let (config, _, _) = config::load_config(Some(temp_dir.path()), None).await?;
+
+        let pulled = Mutex::new(Vec::new());
+        let summaries = pull_images(config_images(&config), 2, |image| {
+            pulled.lock().unwrap().push(image.normalize());
+            async move { Ok(PullSummary::cached(&image, 3, Duration::ZERO)) }
+        })
+        .await?;
+
+        // Both sandboxes use the same image, in different forms
+        assert_eq!(*pulled.lock().unwrap(), ["docker.io/library/python:3.12"]);
+        assert_eq!(summaries.len(), 1);
+        assert!(summaries[0].is_cached());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orchestra_resolve_sandbox_project_ambiguous() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let projects_dir = temp_dir.path();
+        write_project(projects_dir, "shop", &["web", "db"]).await;
+        write_project(projects_dir, "blog", &["web"]).await;
+
+        match resolve_sandbox_project("web", projects_dir, None).await {
+            Err(MicrosandboxError::AmbiguousSandbox { sandbox, projects }) => {
+                assert_eq!(sandbox, "web");
+                assert_eq!(projects, vec!["blog".to_string(), "shop".to_string()]);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // Qualifying the name with a project resolves the ambiguity
+        assert_eq!(
+            resolve_sandbox_project("web", projects_dir, Some("blog")).await?,
+            projects_dir.join("blog")
+        );
+
+        // Names defined in a single project need no qualifier
+        assert_eq!(
+            resolve_sandbox_project("db", projects_dir, None).await?,
+            projects_dir.join("shop")
+        );
+
+        // Unknown names fall back to the projects directory itself
+        assert_eq!(
+            resolve_sandbox_project("cache", projects_dir, None).await?,
+            projects_dir.to_path_buf()
+        );
+
+        // Project names can't escape the projects directory
+        assert!(
+            resolve_sandbox_project("web", projects_dir, Some("../other"))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    fn serial_directory_size(path: &Path) -> anyhow::Result<u64> {
+        let mut total = 0;
+        for entry in walkdir::WalkDir::new(path) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                total += entry.metadata()?.len();
+            }
+        }
+        Ok(total)
+    }
+
+    #[test]
+    fn test_orchestra_compute_directory_size_matches_serial_walk() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+
+        std::fs::write(root.join("top"), vec![0u8; 10])?;
+        std::fs::create_dir_all(root.join("a/b/c"))?;
+        std::fs::write(root.join("a/one"), vec![0u8; 100])?;
+        std::fs::write(root.join("a/b/two"), vec![0u8; 1_000])?;
+        std::fs::write(root.join("a/b/c/three"), vec![0u8; 10_000])?;
+        std::fs::create_dir_all(root.join("d/empty"))?;
+        std::os::unix::fs::symlink(root.join("a/b/c/three"), root.join("d/link"))?;
+
+        assert_eq!(compute_directory_size(root)?, serial_directory_size(root)?);
+        assert_eq!(compute_directory_size(root)?, 11_110);
+
+        // A hard-linked file is only counted once
+        std::fs::hard_link(root.join("a/one"), root.join("d/one"))?;
+        assert_eq!(compute_directory_size(root)?, 11_110);
+
+        // An empty directory has no size
+        assert_eq!(compute_directory_size(&root.join("d/empty"))?, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_orchestra_compute_directory_size_on_large_tree() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let root = dir.path();
+
+        for i in 0..32 {
+            let subdir = root.join(format!("dir-{i}")).join("nested");
+            std::fs::create_dir_all(&subdir)?;
+            for j in 0..100 {
+                std::fs::write(subdir.join(format!("file-{j}")), vec![0u8; j])?;
+            }
+        }
+
+        let started = Instant::now();
+        let serial = serial_directory_size(root)?;
+        let serial_elapsed = started.elapsed();
+
+        let started = Instant::now();
+        let parallel = compute_directory_size(root)?;
+        let parallel_elapsed = started.elapsed();
+
+        assert_eq!(parallel, serial);
+        assert_eq!(parallel, 32 * (0..100).sum::<u64>());
+
+        // Roughly as fast or faster, leaving room for a noisy or single core machine
+        assert!(
+            parallel_elapsed <= serial_elapsed * 3 + Duration::from_millis(100),
+            "parallel walk took {parallel_elapsed:?}, serial walk took {serial_elapsed:?}"
+        );
+
+        Ok(())
+    }
+}
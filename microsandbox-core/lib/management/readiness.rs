@@ -0,0 +1,282 @@
+//! Readiness probes for started sandboxes.
+//!
+//! A sandbox's supervisor reports it running as soon as the microVM is up, which is before the
+//! service inside is listening. Sandboxes with a readiness probe are only reported as up once
+//! the probe succeeds.
+
+use std::{
+    future::Future,
+    net::Ipv4Addr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use microsandbox_utils::DEFAULT_PORTAL_GUEST_PORT;
+use serde_json::json;
+
+use crate::{
+    MicrosandboxError, MicrosandboxResult,
+    config::{PortPair, ReadinessCheck, ReadinessProbe, Sandbox},
+    runtime::{RetryPolicy, retry},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The longest a single probe attempt may take.
+const MAX_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Waits for the readiness probe of a started sandbox to succeed.
+///
+/// Sandboxes without a readiness probe are ready as soon as they are running.
+///
+/// ## Arguments
+///
+/// * `sandbox_name` - The name of the sandbox
+/// * `sandbox` - The configuration of the sandbox, which holds the probe and its port mappings
+///
+/// ## Returns
+///
+/// `Ok(())` once the probe succeeds, or `MicrosandboxError::ReadinessTimeout` if it does not
+/// succeed within the probe's timeout
+pub async fn wait_until_ready(sandbox_name: &str, sandbox: &Sandbox) -> MicrosandboxResult<()> {
+    let Some(probe) = sandbox.get_readiness() else {
+        return Ok(());
+    };
+
+    probe.validate(sandbox.get_ports())?;
+
+    let host_port = match probe.get_check() {
+        ReadinessCheck::Http { port, .. } => get_host_port(sandbox.get_ports(), *port),
+        ReadinessCheck::Exec(_) => get_host_port(sandbox.get_ports(), DEFAULT_PORTAL_GUEST_PORT),
+    }
+    .ok_or_else(|| {
+        MicrosandboxError::InvalidReadinessProbe(format!(
+            "sandbox {sandbox_name} does not expose the port its readiness probe needs"
+        ))
+    })?;
+
+    let client = reqwest::Client::builder()
+        .timeout(probe.interval_duration().min(MAX_ATTEMPT_TIMEOUT))
+        .build()?;
+
+    tracing::info!("waiting for sandbox {} to become ready", sandbox_name);
+    let attempts = poll_until_ready(
+        sandbox_name,
+        || probe_once(&client, probe, host_port),
+        probe.interval_duration(),
+        probe.timeout_duration(),
+    )
+    .await?;
+
+    tracing::info!(
+        "sandbox {} is ready after {} attempt(s)",
+        sandbox_name,
+        attempts
+    );
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Runs `probe` every `interval` until it succeeds or `timeout` has passed.
+///
+/// ## Returns
+///
+/// The number of attempts it took for the probe to succeed
+pub(crate) async fn poll_until_ready<F, Fut>(
+    sandbox_name: &str,
+    mut probe: F,
+    interval: Duration,
+    timeout: Duration,
+) -> MicrosandboxResult<usize>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    let policy = RetryPolicy::builder()
+        .deadline(timeout)
+        .initial_backoff(interval)
+        .max_backoff(interval)
+        .multiplier(1.0)
+        .jitter(0.0)
+        .build();
+
+    let started = Instant::now();
+    let attempts = &AtomicUsize::new(0);
+    let result = retry(&policy, || {
+        let ready = probe();
+        async move {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if ready.await {
+                return Ok(attempt);
+            }
+
+            tracing::debug!(
+                "readiness probe for sandbox {} failed (attempt {})",
+                sandbox_name,
+                attempt
+            );
+            Err(())
+        }
+    })
+    .await;
+
+    result.map_err(|()| MicrosandboxError::ReadinessTimeout {
+        sandbox: sandbox_name.to_string(),
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Runs a single attempt of the probe against the given host port.
+async fn probe_once(client: &reqwest::Client, probe: &ReadinessProbe, host_port: u16) -> bool {
+    let base_url = format!("http://{}:{}", Ipv4Addr::LOCALHOST, host_port);
+    match probe.get_check() {
+        ReadinessCheck::Http { path, .. } => {
+            let path = path.strip_prefix('/').unwrap_or(path);
+            client
+                .get(format!("{base_url}/{path}"))
+                .send()
+                .await
+                .is_ok_and(|response| response.status().is_success())
+        }
+        ReadinessCheck::Exec(command) => {
+            let request = json!({
+                "jsonrpc": "2.0",
+                "method": "sandbox.command.run",
+                "params": {
+                    "command": command[0],
+                    "args": &command[1..],
+                },
+                "id": "readiness",
+            });
+
+            let Ok(response) = client
+                .post(format!("{base_url}/api/v1/rpc"))
+                .json(&request)
+                .send()
+                .await
+            else {
+                return false;
+            };
+
+            response
+                .json::<serde_json::Value>()
+                .await
+                .is_ok_and(|body| body["result"]["success"].as_bool() == Some(true))
+        }
+    }
+}
+
+/// Returns the host port the given guest port is mapped to.
+fn get_host_port(ports: &[PortPair], guest_port: u16) -> Option<u16> {
+    ports
+        .iter()
+        .find(|port| port.get_guest() == guest_port)
+        .map(|port| port.get_host())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_readiness_succeeds_after_attempts() -> anyhow::Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        // A probe that only succeeds on its third attempt
+        let attempts = poll_until_ready(
+            "app",
+            || {
+                let calls = calls.clone();
+                async move { calls.fetch_add(1, Ordering::SeqCst) + 1 >= 3 }
+            },
+            Duration::from_millis(10),
+            Duration::from_secs(5),
+        )
+        .await?;
+
+        assert_eq!(attempts, 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_readiness_keeps_long_interval() -> anyhow::Result<()> {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let started = tokio::time::Instant::now();
+
+        // Intervals longer than the default backoff cap are kept between every attempt
+        let attempts = poll_until_ready(
+            "app",
+            || {
+                let calls = calls.clone();
+                async move { calls.fetch_add(1, Ordering::SeqCst) + 1 >= 3 }
+            },
+            Duration::from_secs(10),
+            Duration::from_secs(60),
+        )
+        .await?;
+
+        assert_eq!(attempts, 3);
+        assert_eq!(started.elapsed(), Duration::from_secs(20));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_readiness_times_out() {
+        let timeout = Duration::from_millis(100);
+        let result = poll_until_ready(
+            "app",
+            || async { false },
+            Duration::from_millis(10),
+            timeout,
+        )
+        .await;
+
+        match result {
+            Err(MicrosandboxError::ReadinessTimeout { sandbox, elapsed }) => {
+                assert_eq!(sandbox, "app");
+                assert!(elapsed >= timeout);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_readiness_probe_requires_exposed_port() {
+        let probe = ReadinessProbe::new(ReadinessCheck::Http {
+            port: 8080,
+            path: "/health".to_string(),
+        });
+
+        assert!(probe.validate(&[PortPair::with_same(8080)]).is_ok());
+        assert!(matches!(
+            probe.validate(&[PortPair::with_same(9090)]),
+            Err(MicrosandboxError::InvalidReadinessProbe(_))
+        ));
+        assert!(
+            ReadinessProbe::new(ReadinessCheck::Exec(Vec::new()))
+                .validate(&[])
+                .is_err()
+        );
+    }
+}
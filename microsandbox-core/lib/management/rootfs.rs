@@ -6,14 +6,21 @@
 
 use std::{
     collections::HashMap,
+    ffi::OsStr,
     fs::Permissions,
-    os::unix::fs::PermissionsExt,
+    net::IpAddr,
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
     path::{Path, PathBuf},
 };
 
 use tokio::fs;
 
-use crate::{MicrosandboxResult, config::PathPair, vm::VIRTIOFS_TAG_PREFIX};
+use crate::{
+    MicrosandboxResult,
+    config::{DnsMode, PathPair},
+    utils,
+    vm::VIRTIOFS_TAG_PREFIX,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -25,6 +32,19 @@ pub const OPAQUE_WHITEOUT_MARKER: &str = ".wh..wh..opq";
 /// The prefix for whiteout files in OCI layers.
 pub const WHITEOUT_PREFIX: &str = ".wh.";
 
+/// The resolv.conf files of the host, in the order they are tried for resolvers to inherit.
+///
+/// With systemd-resolved, `/etc/resolv.conf` only points at a stub resolver on the loopback
+/// address, and the resolvers it forwards to are listed in the second file.
+pub const HOST_RESOLV_CONF_PATHS: &[&str] =
+    &["/etc/resolv.conf", "/run/systemd/resolve/resolv.conf"];
+
+/// The public resolvers used when the guest has none configured and none are inherited.
+const PUBLIC_NAMESERVERS: &[&str] = &["1.1.1.1", "8.8.8.8"];
+
+/// The first line of the resolv.conf files written into the top layer of a rootfs.
+const RESOLV_CONF_HEADER: &str = "# /etc/resolv.conf: DNS resolver configuration";
+
 // The xattr name to set
 const XATTR_OVERRIDE_STATS_NAME: &str = "user.containers.override_stat";
 
@@ -245,6 +265,25 @@ async fn _patch_with_hostnames(
     Ok(())
 }
 
+/// Updates the /etc/resolv.conf file in the guest rootfs to add DNS servers if none exist.
+///
+/// This is [`patch_with_default_dns_settings`] for [`DnsMode::Public`], and
+/// [`patch_with_host_dns_settings`] with the host's [`HOST_RESOLV_CONF_PATHS`] for
+/// [`DnsMode::Inherit`].
+///
+/// ## Arguments
+/// * `root_paths` - List of root paths to check, ordered from bottom to top layer
+/// * `dns` - Where the resolvers come from
+pub async fn patch_with_dns_settings(
+    root_paths: &[PathBuf],
+    dns: DnsMode,
+) -> MicrosandboxResult<()> {
+    match dns {
+        DnsMode::Public => patch_with_default_dns_settings(root_paths).await,
+        DnsMode::Inherit => patch_with_host_dns_settings(root_paths, HOST_RESOLV_CONF_PATHS).await,
+    }
+}
+
 /// Updates the /etc/resolv.conf file in the guest rootfs to add default DNS servers if none exist.
 /// Creates the file if it doesn't exist.
 ///
@@ -254,6 +293,9 @@ async fn _patch_with_hostnames(
 /// 3. If no nameservers exist in any layer, adds default ones (1.1.1.1 and 8.8.8.8) to the top layer
 /// 4. Sets appropriate permissions on the resolv.conf file
 ///
+/// A resolv.conf that was written to the top layer by a previous patch doesn't count, so it is
+/// replaced when the DNS mode of the sandbox changes.
+///
 /// ## Format
 /// The resolv.conf file follows the standard format:
 /// ```text
@@ -272,53 +314,51 @@ async fn _patch_with_hostnames(
 /// - Cannot read or write the resolv.conf file
 /// - Cannot set permissions on the resolv.conf file
 pub async fn patch_with_default_dns_settings(root_paths: &[PathBuf]) -> MicrosandboxResult<()> {
-    if root_paths.is_empty() {
+    if root_paths.is_empty() || has_guest_nameserver(root_paths).await? {
         return Ok(());
     }
 
-    // Check all layers for existing nameserver entries
-    let mut has_nameserver = false;
-    for root_path in root_paths {
-        let resolv_path = root_path.join("etc/resolv.conf");
-        if resolv_path.exists() {
-            let content = fs::read_to_string(&resolv_path).await?;
-            if content
-                .lines()
-                .any(|line| line.trim_start().starts_with("nameserver "))
-            {
-                has_nameserver = true;
-                break;
-            }
-        }
+    write_resolv_conf(root_paths, &public_resolv_conf()).await
+}
+
+/// Updates the /etc/resolv.conf file in the guest rootfs with the DNS servers of the host if none
+/// exist.
+///
+/// The host's resolv.conf files are tried in order, and the first one with a nameserver the guest
+/// can reach is copied along with its search domains. Nameservers on the loopback address are
+/// left out, as they are only reachable from the host itself. The public resolvers of
+/// [`patch_with_default_dns_settings`] are used if none of the files has a nameserver left.
+///
+/// ## Arguments
+/// * `root_paths` - List of root paths to check, ordered from bottom to top layer
+/// * `host_resolv_confs` - The host's resolv.conf files, in the order they are tried
+///
+/// ## Errors
+/// Returns an error if:
+/// - Cannot create directories in the rootfs
+/// - Cannot read or write the resolv.conf file in the rootfs
+/// - Cannot set permissions on the resolv.conf file
+pub async fn patch_with_host_dns_settings(
+    root_paths: &[PathBuf],
+    host_resolv_confs: &[impl AsRef<Path>],
+) -> MicrosandboxResult<()> {
+    if root_paths.is_empty() || has_guest_nameserver(root_paths).await? {
+        return Ok(());
     }
 
-    // If no nameservers found in any layer, add defaults to the top layer
-    if !has_nameserver {
-        // Get the top layer (last in the list)
-        let top_layer = root_paths.last().unwrap();
-        let resolv_path = top_layer.join("etc/resolv.conf");
+    for host_resolv_conf in host_resolv_confs {
+        let host_resolv_conf = host_resolv_conf.as_ref();
+        let Ok(content) = fs::read_to_string(host_resolv_conf).await else {
+            continue;
+        };
 
-        // Create parent directories if they don't exist
-        if let Some(parent) = resolv_path.parent() {
-            fs::create_dir_all(parent).await?;
+        if let Some(resolv_content) = inherited_resolv_conf(host_resolv_conf, &content) {
+            return write_resolv_conf(root_paths, &resolv_content).await;
         }
-
-        // Create new resolv.conf with default nameservers
-        let mut resolv_content = String::from("# /etc/resolv.conf: DNS resolver configuration\n");
-        resolv_content.push_str("nameserver 1.1.1.1\n");
-        resolv_content.push_str("nameserver 8.8.8.8\n");
-
-        // Write the file
-        fs::write(&resolv_path, resolv_content).await?;
-
-        // Set proper permissions (644 - rw-r--r--)
-        let perms = fs::metadata(&resolv_path).await?.permissions();
-        let mut new_perms = perms;
-        new_perms.set_mode(0o644);
-        fs::set_permissions(&resolv_path, new_perms).await?;
     }
 
-    Ok(())
+    tracing::warn!("the host has no DNS resolvers the guest can reach, using public resolvers");
+    write_resolv_conf(root_paths, &public_resolv_conf()).await
 }
 
 /// Sets the user.containers.override_stat xattr on the rootfs directory.
@@ -363,6 +403,239 @@ pub async fn patch_with_stat_override(root_path: &Path) -> MicrosandboxResult<()
     }
 }
 
+/// Merges image layers into a single root filesystem at `dest`, the way overlayfs would show them.
+///
+/// Layers are applied from the bottom up with [`copy_dir_recursive`], so each layer's files
+/// replace those of the layers below it and its whiteouts hide their entries.
+///
+/// ## Arguments
+/// * `layer_paths` - The extracted layers, from the bottom up
+/// * `dest` - The directory to merge the layers into
+pub fn merge_layers(layer_paths: &[PathBuf], dest: &Path) -> MicrosandboxResult<()> {
+    for layer_path in layer_paths {
+        tracing::debug!(
+            "merging layer {} into {}",
+            layer_path.display(),
+            dest.display()
+        );
+        copy_dir_recursive(layer_path, dest)?;
+    }
+
+    Ok(())
+}
+
+/// Copies the contents of `src` into `dest`, merging them with what `dest` already holds.
+///
+/// `dest` is created if it doesn't exist. Symlinks are copied as links, and the modes and
+/// extended attributes of entries are kept, including the stat override attribute that records
+/// ownership inside the sandbox. OCI whiteouts in `src` are applied instead of copied:
+/// - `.wh.<name>` removes `<name>` from `dest`
+/// - `.wh..wh..opq` empties the directory it is in before the other entries next to it are copied
+///
+/// Copying the layers of an image into the same `dest` from the bottom up therefore produces the
+/// root filesystem overlayfs would show for them.
+///
+/// ## Arguments
+/// * `src` - The directory to copy from
+/// * `dest` - The directory to copy into
+///
+/// ## Returns
+/// `MicrosandboxError::DiskFull` if the file system of `dest` runs out of space
+pub fn copy_dir_recursive(src: &Path, dest: &Path) -> MicrosandboxResult<()> {
+    copy_dir_contents(src, dest).map_err(|e| utils::map_disk_full(e, dest, None))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Copies the contents of `src` into `dest` for [`copy_dir_recursive`].
+fn copy_dir_contents(src: &Path, dest: &Path) -> MicrosandboxResult<()> {
+    std::fs::create_dir_all(dest)?;
+
+    let entries = std::fs::read_dir(src)?.collect::<Result<Vec<_>, _>>()?;
+
+    // Empty an opaque directory first, so the entries next to the marker survive it
+    if entries
+        .iter()
+        .any(|entry| entry.file_name() == OPAQUE_WHITEOUT_MARKER)
+    {
+        for existing in std::fs::read_dir(dest)? {
+            remove_path(&existing?.path())?;
+        }
+    }
+
+    for entry in entries {
+        let name = entry.file_name();
+        if name == OPAQUE_WHITEOUT_MARKER {
+            continue;
+        }
+
+        if let Some(hidden) = name.as_bytes().strip_prefix(WHITEOUT_PREFIX.as_bytes()) {
+            remove_path(&dest.join(OsStr::from_bytes(hidden)))?;
+            continue;
+        }
+
+        let src_path = entry.path();
+        let dest_path = dest.join(&name);
+        let file_type = entry.file_type()?;
+        let existing = std::fs::symlink_metadata(&dest_path).ok();
+
+        if file_type.is_dir() {
+            // A directory replaces anything but a directory, and is merged with a directory
+            if existing.is_some_and(|metadata| !metadata.is_dir()) {
+                remove_path(&dest_path)?;
+            }
+
+            copy_dir_contents(&src_path, &dest_path)?;
+            continue;
+        }
+
+        if existing.is_some() {
+            remove_path(&dest_path)?;
+        }
+
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(std::fs::read_link(&src_path)?, &dest_path)?;
+        } else if file_type.is_file() {
+            std::fs::copy(&src_path, &dest_path)?;
+            copy_xattrs(&src_path, &dest_path)?;
+        } else {
+            tracing::warn!("skipping special file {}", src_path.display());
+        }
+    }
+
+    // Set the mode last, so a read-only directory doesn't stop its contents from being copied
+    std::fs::set_permissions(dest, std::fs::metadata(src)?.permissions())?;
+    copy_xattrs(src, dest)
+}
+
+/// Removes a file, symlink or directory tree if it exists.
+fn remove_path(path: &Path) -> MicrosandboxResult<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::remove_dir_all(path)?,
+        Ok(_) => std::fs::remove_file(path)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+/// Copies the extended attributes of `src` onto `dest`.
+fn copy_xattrs(src: &Path, dest: &Path) -> MicrosandboxResult<()> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(());
+    }
+
+    for name in xattr::list(src)? {
+        if let Some(value) = xattr::get(src, &name)? {
+            xattr::set(dest, &name, &value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks whether the guest already has a nameserver configured in any layer.
+///
+/// A resolv.conf written to the top layer by a previous patch doesn't count.
+async fn has_guest_nameserver(root_paths: &[PathBuf]) -> MicrosandboxResult<bool> {
+    let top_layer = root_paths.len() - 1;
+    for (index, root_path) in root_paths.iter().enumerate() {
+        let resolv_path = root_path.join("etc/resolv.conf");
+        if !resolv_path.exists() {
+            continue;
+        }
+
+        let content = fs::read_to_string(&resolv_path).await?;
+        if index == top_layer && content.starts_with(RESOLV_CONF_HEADER) {
+            continue;
+        }
+
+        if content
+            .lines()
+            .any(|line| line.trim_start().starts_with("nameserver "))
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Builds a resolv.conf with the public resolvers.
+fn public_resolv_conf() -> String {
+    let mut resolv_content = format!("{}\n", RESOLV_CONF_HEADER);
+    for nameserver in PUBLIC_NAMESERVERS {
+        resolv_content.push_str(&format!("nameserver {}\n", nameserver));
+    }
+
+    resolv_content
+}
+
+/// Builds a resolv.conf from the nameservers and search domains of a host's resolv.conf.
+///
+/// ## Returns
+/// `None` if the host's file has no nameserver the guest can reach.
+fn inherited_resolv_conf(host_resolv_conf: &Path, content: &str) -> Option<String> {
+    let mut nameservers = Vec::new();
+    let mut search = Vec::new();
+    for line in content.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => {
+                let reachable = fields
+                    .next()
+                    .and_then(|address| address.parse::<IpAddr>().ok())
+                    .filter(|address| !address.is_loopback());
+                if let Some(address) = reachable {
+                    nameservers.push(format!("nameserver {}", address));
+                }
+            }
+            Some("search" | "domain") => search.push(line.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    if nameservers.is_empty() {
+        return None;
+    }
+
+    let mut resolv_content = format!(
+        "{}\n# Inherited from the host's {}\n",
+        RESOLV_CONF_HEADER,
+        host_resolv_conf.display()
+    );
+    for line in search.iter().chain(&nameservers) {
+        resolv_content.push_str(line);
+        resolv_content.push('\n');
+    }
+
+    Some(resolv_content)
+}
+
+/// Writes a resolv.conf to the top layer, which is the last of `root_paths`.
+async fn write_resolv_conf(root_paths: &[PathBuf], resolv_content: &str) -> MicrosandboxResult<()> {
+    let top_layer = root_paths.last().unwrap();
+    let resolv_path = top_layer.join("etc/resolv.conf");
+
+    // Create parent directories if they don't exist
+    if let Some(parent) = resolv_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    fs::write(&resolv_path, resolv_content).await?;
+
+    // Set proper permissions (644 - rw-r--r--)
+    let perms = fs::metadata(&resolv_path).await?.permissions();
+    let mut new_perms = perms;
+    new_perms.set_mode(0o644);
+    fs::set_permissions(&resolv_path, new_perms).await?;
+
+    Ok(())
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
@@ -689,6 +962,113 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_patch_with_host_dns_settings() -> anyhow::Result<()> {
+        let host_dir = TempDir::new()?;
+        let stub_resolv_conf = host_dir.path().join("stub-resolv.conf");
+        fs::write(
+            &stub_resolv_conf,
+            "# Generated by systemd-resolved\nnameserver 127.0.0.53\nsearch corp.example\n",
+        )
+        .await?;
+        let host_resolv_conf = host_dir.path().join("resolv.conf");
+        fs::write(
+            &host_resolv_conf,
+            "nameserver 10.0.0.2\nnameserver fd00::1\noptions edns0\nsearch corp.example\n",
+        )
+        .await?;
+
+        // Test case 1: The loopback-only file is skipped for the next one
+        let root_dir = TempDir::new()?;
+        let root_path = root_dir.path().to_path_buf();
+        patch_with_host_dns_settings(
+            &[root_path.clone()],
+            &[stub_resolv_conf.clone(), host_resolv_conf.clone()],
+        )
+        .await?;
+
+        let resolv_path = root_path.join("etc/resolv.conf");
+        let content = fs::read_to_string(&resolv_path).await?;
+        assert!(content.starts_with(RESOLV_CONF_HEADER));
+        assert!(content.contains("search corp.example"));
+        assert!(content.contains("nameserver 10.0.0.2"));
+        assert!(content.contains("nameserver fd00::1"));
+        assert!(!content.contains("127.0.0.53"));
+        assert!(!content.contains("options"));
+        assert!(!content.contains("1.1.1.1"));
+        assert_eq!(
+            fs::metadata(&resolv_path).await?.permissions().mode() & 0o777,
+            0o644
+        );
+
+        // Test case 2: No reachable resolvers on the host falls back to public ones
+        let root_dir2 = TempDir::new()?;
+        let root_path2 = root_dir2.path().to_path_buf();
+        let missing_resolv_conf = host_dir.path().join("missing.conf");
+        patch_with_host_dns_settings(
+            &[root_path2.clone()],
+            &[missing_resolv_conf, stub_resolv_conf],
+        )
+        .await?;
+
+        let content2 = fs::read_to_string(root_path2.join("etc/resolv.conf")).await?;
+        assert!(content2.contains("nameserver 1.1.1.1"));
+        assert!(content2.contains("nameserver 8.8.8.8"));
+
+        // Test case 3: Nameservers in a lower layer are kept
+        let lower_dir = TempDir::new()?;
+        let patch_dir = TempDir::new()?;
+        let lower_layer = lower_dir.path().to_path_buf();
+        let patch_layer = patch_dir.path().to_path_buf();
+        fs::create_dir_all(lower_layer.join("etc")).await?;
+        fs::write(
+            lower_layer.join("etc/resolv.conf"),
+            "nameserver 192.168.1.1\n",
+        )
+        .await?;
+
+        patch_with_host_dns_settings(&[lower_layer, patch_layer.clone()], &[host_resolv_conf])
+            .await?;
+        assert!(!patch_layer.join("etc/resolv.conf").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_patch_with_dns_settings_switches_modes() -> anyhow::Result<()> {
+        let host_dir = TempDir::new()?;
+        let host_resolv_conf = host_dir.path().join("resolv.conf");
+        fs::write(&host_resolv_conf, "nameserver 10.0.0.2\n").await?;
+
+        let root_dir = TempDir::new()?;
+        let root_paths = [root_dir.path().to_path_buf()];
+        let resolv_path = root_dir.path().join("etc/resolv.conf");
+
+        // A file written for the public mode is replaced by the inherited resolvers
+        patch_with_dns_settings(&root_paths, DnsMode::Public).await?;
+        patch_with_host_dns_settings(&root_paths, &[&host_resolv_conf]).await?;
+        let content = fs::read_to_string(&resolv_path).await?;
+        assert!(content.contains("nameserver 10.0.0.2"));
+        assert!(!content.contains("nameserver 1.1.1.1"));
+
+        // And switching back restores the public resolvers
+        patch_with_dns_settings(&root_paths, DnsMode::Public).await?;
+        let content = fs::read_to_string(&resolv_path).await?;
+        assert!(content.contains("nameserver 1.1.1.1"));
+        assert!(!content.contains("nameserver 10.0.0.2"));
+
+        // A resolv.conf the image ships with is never replaced
+        fs::write(&resolv_path, "nameserver 192.168.1.1\n").await?;
+        patch_with_host_dns_settings(&root_paths, &[&host_resolv_conf]).await?;
+        patch_with_dns_settings(&root_paths, DnsMode::Public).await?;
+        assert_eq!(
+            fs::read_to_string(&resolv_path).await?,
+            "nameserver 192.168.1.1\n"
+        );
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_patch_with_stat_override() -> anyhow::Result<()> {
         // Skip this test if no xattr support
@@ -718,4 +1098,104 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_merge_layers_applies_whiteouts() -> anyhow::Result<()> {
+        let layers = TempDir::new()?;
+        let base = layers.path().join("base");
+        let middle = layers.path().join("middle");
+        let top = layers.path().join("top");
+        let merged = layers.path().join("merged");
+
+        // The base layer has a few files and directories
+        std::fs::create_dir_all(base.join("etc/conf.d"))?;
+        std::fs::write(base.join("etc/conf.d/a.conf"), "a")?;
+        std::fs::write(base.join("etc/conf.d/b.conf"), "b")?;
+        std::fs::create_dir_all(base.join("var/cache/apk"))?;
+        std::fs::write(base.join("var/cache/apk/index"), "index")?;
+        std::fs::write(base.join("etc/motd"), "welcome")?;
+
+        // The middle layer removes a file and a whole directory, and makes another opaque
+        std::fs::create_dir_all(middle.join("etc/conf.d"))?;
+        std::fs::write(middle.join("etc/.wh.motd"), "")?;
+        std::fs::write(middle.join("etc/conf.d").join(OPAQUE_WHITEOUT_MARKER), "")?;
+        std::fs::write(middle.join("etc/conf.d/c.conf"), "c")?;
+        std::fs::create_dir_all(middle.join("var/cache"))?;
+        std::fs::write(middle.join("var/cache/.wh.apk"), "")?;
+
+        // The top layer brings back a file the middle layer removed
+        std::fs::create_dir_all(top.join("etc"))?;
+        std::fs::write(top.join("etc/motd"), "welcome back")?;
+
+        merge_layers(&[base, middle, top], &merged)?;
+
+        let mut conf_files = std::fs::read_dir(merged.join("etc/conf.d"))?
+            .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        conf_files.sort();
+        assert_eq!(conf_files, ["c.conf"]);
+
+        assert!(!merged.join("var/cache/apk").exists());
+        assert!(merged.join("var/cache").is_dir());
+        assert!(!merged.join("var/cache/.wh.apk").exists());
+        assert_eq!(
+            std::fs::read_to_string(merged.join("etc/motd"))?,
+            "welcome back"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_dir_recursive_merges_layers() -> anyhow::Result<()> {
+        let lower = TempDir::new()?;
+        let upper = TempDir::new()?;
+        let merged = TempDir::new()?;
+        let merged_path = merged.path().join("rootfs");
+
+        std::fs::create_dir_all(lower.path().join("etc"))?;
+        std::fs::write(lower.path().join("etc/hostname"), "lower")?;
+        std::fs::write(lower.path().join("etc/removed"), "lower")?;
+        std::fs::create_dir_all(lower.path().join("opt/app"))?;
+        std::fs::write(lower.path().join("opt/app/old"), "lower")?;
+        std::os::unix::fs::symlink("hostname", lower.path().join("etc/name"))?;
+
+        // The upper layer overrides a file, removes another and makes a directory opaque
+        std::fs::create_dir_all(upper.path().join("etc"))?;
+        std::fs::write(upper.path().join("etc/hostname"), "upper")?;
+        std::fs::write(upper.path().join("etc/.wh.removed"), "")?;
+        std::fs::create_dir_all(upper.path().join("opt/app"))?;
+        std::fs::write(
+            upper.path().join("opt/app").join(OPAQUE_WHITEOUT_MARKER),
+            "",
+        )?;
+        std::fs::write(upper.path().join("opt/app/new"), "upper")?;
+
+        copy_dir_recursive(lower.path(), &merged_path)?;
+        copy_dir_recursive(upper.path(), &merged_path)?;
+
+        assert_eq!(
+            std::fs::read_to_string(merged_path.join("etc/hostname"))?,
+            "upper"
+        );
+        assert_eq!(
+            std::fs::read_link(merged_path.join("etc/name"))?,
+            PathBuf::from("hostname")
+        );
+        assert!(!merged_path.join("etc/removed").exists());
+        assert!(!merged_path.join("etc/.wh.removed").exists());
+        assert!(!merged_path.join("opt/app/old").exists());
+        assert!(
+            !merged_path
+                .join("opt/app")
+                .join(OPAQUE_WHITEOUT_MARKER)
+                .exists()
+        );
+        assert_eq!(
+            std::fs::read_to_string(merged_path.join("opt/app/new"))?,
+            "upper"
+        );
+
+        Ok(())
+    }
 }
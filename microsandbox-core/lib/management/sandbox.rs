@@ -5,28 +5,40 @@
 //! and execution based on the Microsandbox configuration file.
 
 use std::{
+    io::Read,
     path::{Path, PathBuf},
     process::Stdio,
+    time::{Duration, Instant},
 };
 
 use chrono::{DateTime, Utc};
 use microsandbox_utils::{
-    DEFAULT_MSBRUN_EXE_PATH, DEFAULT_SHELL, EXTRACTED_LAYER_SUFFIX, LAYERS_SUBDIR, LOG_SUBDIR,
-    MICROSANDBOX_CONFIG_FILENAME, MICROSANDBOX_ENV_DIR, MSBRUN_EXE_ENV_VAR, OCI_DB_FILENAME,
-    PATCH_SUBDIR, RW_SUBDIR, SANDBOX_DB_FILENAME, SANDBOX_DIR, SCRIPTS_DIR, SHELL_SCRIPT_NAME, env,
+    DEFAULT_MSBRUN_EXE_PATH, DEFAULT_SHELL, EXTRACTED_LAYER_SUFFIX, EXTRACTION_COMPLETE_SUFFIX,
+    LAYERS_SUBDIR, LOG_SUBDIR, MICROSANDBOX_CONFIG_FILENAME, MICROSANDBOX_ENV_DIR,
+    MSBRUN_ENVS_ENV_VAR, MSBRUN_EXE_ENV_VAR, OCI_DB_FILENAME, PATCH_SUBDIR, ROOTFS_SUBDIR,
+    RW_SUBDIR, SANDBOX_DB_FILENAME, SANDBOX_DIR, SCRIPTS_DIR, SHELL_SCRIPT_NAME, SNAPSHOTS_SUBDIR,
+    ShutdownSignals, env, terminate_child, wait_or_terminate,
 };
 use sqlx::{Pool, Sqlite};
 use tempfile;
-use tokio::{fs, process::Command};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    process::{Child, Command},
+};
 use typed_path::Utf8UnixPathBuf;
+use walkdir::WalkDir;
 
 use crate::{
     MicrosandboxError, MicrosandboxResult,
     config::{
-        EnvPair, Microsandbox, PathPair, PortPair, ReferenceOrPath, START_SCRIPT_NAME, Sandbox,
+        EnvPair, HostLimits, InheritedEnvMode, Microsandbox, NetworkMode, NetworkScope, PathPair,
+        PortPair, ReferenceOrPath, ResourceCheckMode, RootfsMode, START_SCRIPT_NAME, Sandbox,
+        SecretStore, check_host_capacity, has_secret_references, resolve_inherited_envs,
     },
     management::{config, db, menv, rootfs},
     oci::{Image, Reference},
+    runtime::{self, SANDBOX_STATUS_RUNNING},
     vm::Rootfs,
 };
 
@@ -36,6 +48,25 @@ use crate::{
 
 const TEMPORARY_SANDBOX_NAME: &str = "tmp";
 
+/// How often the sandbox database is checked while waiting for the supervisor to become ready.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a detached start without a start timeout waits for the supervisor to report the
+/// sandbox running before giving up on it.
+const DETACHED_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a supervisor being stopped is given to stop its microVM before it is killed.
+///
+/// This is longer than the grace period the supervisor gives the microVM itself, so the supervisor
+/// gets to kill a microVM that doesn't stop rather than leave it behind.
+const SUPERVISOR_STOP_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// The PAX record prefix extended attributes are stored under in snapshot tarballs.
+const PAX_XATTR_PREFIX: &str = "SCHILY.xattr.";
+
+/// The file extension of snapshot tarballs.
+const SNAPSHOT_EXTENSION: &str = "tar";
+
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
@@ -52,16 +83,29 @@ const TEMPORARY_SANDBOX_NAME: &str = "tmp";
 /// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
 /// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
 /// * `args` - Additional arguments to pass to the sandbox script
-/// * `detach` - Whether to run the sandbox in the background
+/// * `detach` - Whether to run the sandbox in the background. A detached start still waits
+///   briefly for the supervisor to report the sandbox running, so that a sandbox that fails to
+///   boot is reported rather than silently left behind
 /// * `exec` - Optional command to execute within the sandbox. Overrides `script` if provided.
+/// * `entrypoint` - Optional program, with its leading arguments, to run in place of the
+///   sandbox's or image's entrypoint. `args` are then passed to it in place of the command.
 /// * `use_image_defaults` - Whether to apply default settings from the OCI image configuration
+/// * `start_timeout` - Optional limit on how long preparing the sandbox and waiting for the
+///   supervisor to report it running may take. The spawned processes are stopped when it is exceeded.
+/// * `console_log` - Whether to capture the guest console to a file that `msb log --console`
+///   shows, even if the sandbox's `console_log` option is off
+/// * `rootfs_mode` - Optional rootfs mode overriding the sandbox's `rootfs_mode` option
+/// * `network` - Optional network mode overriding the sandbox's `network` option
 ///
 /// ## Returns
 ///
 /// Returns `Ok(())` if the sandbox runs and exits successfully, or a `MicrosandboxError` if:
+/// - libkrun or libkrunfw is missing or has the wrong ABI version
 /// - The config file is not found
 /// - The specified sandbox is not found in the config
 /// - The supervisor process fails to start or exits with an error
+/// - The sandbox does not become ready within `start_timeout`, or within a short window when
+///   detached without one
 /// - Any filesystem operations fail
 ///
 /// ## Example
@@ -81,7 +125,12 @@ const TEMPORARY_SANDBOX_NAME: &str = "tmp";
 ///         vec![],
 ///         false,
 ///         None,
-///         true
+///         None,
+///         true,
+///         None,
+///         false,
+///         None,
+///         None
 ///     ).await?;
 ///     Ok(())
 /// }
@@ -95,10 +144,20 @@ pub async fn run(
     args: Vec<String>,
     detach: bool,
     exec: Option<&str>,
+    entrypoint: Option<Vec<String>>,
     use_image_defaults: bool,
+    start_timeout: Option<Duration>,
+    console_log: bool,
+    rootfs_mode: Option<RootfsMode>,
+    network: Option<NetworkMode>,
 ) -> MicrosandboxResult<()> {
-    // Prepare the command
-    let (mut command, is_detached) = prepare_run(
+    let started = Instant::now();
+
+    // Fail early with an actionable error rather than when the supervisor can't load libkrun
+    runtime::check_backend_libraries()?;
+
+    // Prepare the command, which includes pulling the image if it is not available yet
+    let prepare = prepare_run(
         sandbox_name,
         script_name,
         project_dir,
@@ -106,9 +165,21 @@ pub async fn run(
         args,
         detach,
         exec,
+        entrypoint,
         use_image_defaults,
-    )
-    .await?;
+        console_log,
+        rootfs_mode,
+        network,
+    );
+    let (mut command, is_detached) = match start_timeout {
+        Some(start_timeout) => tokio::time::timeout(start_timeout, prepare)
+            .await
+            .map_err(|_| MicrosandboxError::StartTimeout {
+                sandbox: sandbox_name.to_string(),
+                elapsed: started.elapsed(),
+            })??,
+        None => prepare.await?,
+    };
 
     // Spawn the command
     let mut child = command.spawn()?;
@@ -118,13 +189,52 @@ pub async fn run(
         child.id().unwrap_or(0)
     );
 
+    // Wait for the supervisor to report the sandbox as running. A detached start nobody would
+    // otherwise hear back from gets a short window of its own when there is no start timeout
+    let ready_check = match start_timeout {
+        Some(start_timeout) => Some((started, start_timeout)),
+        None if is_detached => Some((Instant::now(), DETACHED_READY_TIMEOUT)),
+        None => None,
+    };
+
+    if let Some((since, timeout)) = ready_check {
+        let (_, canonical_project_dir, config_file) =
+            config::load_config(project_dir, config_file).await?;
+        let menv_path = canonical_project_dir.join(MICROSANDBOX_ENV_DIR);
+        let sandbox_pool = db::get_or_create_pool(
+            &menv_path.join(SANDBOX_DB_FILENAME),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await?;
+        let log_path = menv::get_log_path(&menv_path.join(LOG_SUBDIR), &config_file, sandbox_name);
+
+        wait_for_supervisor_start(
+            &mut child,
+            &sandbox_pool,
+            sandbox_name,
+            &config_file,
+            &log_path,
+            since,
+            timeout,
+        )
+        .await?;
+    }
+
     // If in detached mode, don't wait for the child process to complete
     if is_detached {
         return Ok(());
     }
 
-    // Wait for the child process to complete
-    let status = child.wait().await?;
+    // Wait for the child process to complete. Being interrupted or terminated stops the sandbox
+    // along with us, instead of leaving it running without a terminal
+    let mut signals = ShutdownSignals::new()?;
+    let (status, signal) =
+        wait_or_terminate(&mut child, signals.recv(), SUPERVISOR_STOP_GRACE_PERIOD).await?;
+    if let Some(signal) = signal {
+        tracing::info!("stopped sandbox {} after {}", sandbox_name, signal);
+        return Ok(());
+    }
+
     if !status.success() {
         tracing::error!(
             "child process — supervisor — exited with status: {}",
@@ -159,10 +269,14 @@ pub async fn prepare_run(
     script_name: Option<&str>,
     project_dir: Option<&Path>,
     config_file: Option<&str>,
-    args: Vec<String>,
+    mut args: Vec<String>,
     detach: bool,
     exec: Option<&str>,
+    entrypoint: Option<Vec<String>>,
     use_image_defaults: bool,
+    console_log: bool,
+    rootfs_mode: Option<RootfsMode>,
+    network: Option<NetworkMode>,
 ) -> MicrosandboxResult<(Command, bool)> {
     // Load the configuration
     let (config, canonical_project_dir, config_file) =
@@ -182,19 +296,42 @@ pub async fn prepare_run(
         ));
     };
 
+    // An entrypoint given for this run replaces the one in the sandbox config
+    if let Some(entrypoint) = entrypoint {
+        sandbox_config.entrypoint = entrypoint;
+    }
+
     tracing::debug!("original sandbox config: {:#?}", sandbox_config);
 
+    // Refuse or warn about a sandbox that asks for more than the host has
+    check_host_capacity(
+        [(sandbox_name, &sandbox_config)],
+        &HostLimits::detect(),
+        ResourceCheckMode::from_env(),
+    )?;
+
     // Sandbox database path
     let sandbox_db_path = menv_path.join(SANDBOX_DB_FILENAME);
 
     // Get sandbox database connection pool
     let sandbox_pool = db::get_or_create_pool(&sandbox_db_path, &db::SANDBOX_DB_MIGRATOR).await?;
 
-    // Get the config last modified timestamp
-    let config_last_modified: DateTime<Utc> = fs::metadata(&config_path).await?.modified()?.into();
+    // Get the config last modified timestamp, where a configuration read from stdin is always new
+    let config_last_modified: DateTime<Utc> = if config_file == config::STDIN_CONFIG_FILE {
+        Utc::now()
+    } else {
+        fs::metadata(&config_path).await?.modified()?.into()
+    };
 
-    let rootfs = match sandbox_config.get_image().clone() {
-        ReferenceOrPath::Path(root_path) => {
+    // Overlay composes the image layers, native passes a single directory through
+    let image = sandbox_config.get_image().clone();
+    let rootfs_mode = rootfs_mode
+        .unwrap_or(sandbox_config.rootfs_mode_or_default())
+        .select(&image);
+    tracing::info!("using {} rootfs", rootfs_mode);
+
+    let rootfs = match (image, rootfs_mode) {
+        (ReferenceOrPath::Path(root_path), RootfsMode::Native) => {
             setup_native_rootfs(
                 &canonical_project_dir.join(root_path),
                 sandbox_name,
@@ -202,24 +339,67 @@ pub async fn prepare_run(
                 &config_file,
                 &config_last_modified,
                 &sandbox_pool,
+                false,
+            )
+            .await?
+        }
+        (ReferenceOrPath::Path(root_path), _) => {
+            // The rootfs directory is the only lower layer, so the sandbox leaves it untouched
+            setup_overlay_rootfs(
+                vec![canonical_project_dir.join(root_path)],
+                sandbox_name,
+                &sandbox_config,
+                &menv_path,
+                &config_file,
+                &config_last_modified,
+                &sandbox_pool,
+            )
+            .await?
+        }
+        (ReferenceOrPath::Reference(reference), RootfsMode::Native) => {
+            let layer_paths =
+                get_image_layer_paths(&reference, &mut sandbox_config, use_image_defaults).await?;
+            let (root_path, merged) =
+                merge_image_layers(layer_paths, &menv_path, &config_file, sandbox_name).await?;
+
+            // A freshly merged rootfs has none of the sandbox's patches yet
+            setup_native_rootfs(
+                &root_path,
+                sandbox_name,
+                &sandbox_config,
+                &config_file,
+                &config_last_modified,
+                &sandbox_pool,
+                merged,
             )
             .await?
         }
-        ReferenceOrPath::Reference(ref reference) => {
-            setup_image_rootfs(
-                reference,
+        (ReferenceOrPath::Reference(reference), _) => {
+            let layer_paths =
+                get_image_layer_paths(&reference, &mut sandbox_config, use_image_defaults).await?;
+            setup_overlay_rootfs(
+                layer_paths,
                 sandbox_name,
-                &mut sandbox_config,
+                &sandbox_config,
                 &menv_path,
                 &config_file,
                 &config_last_modified,
                 &sandbox_pool,
-                use_image_defaults,
             )
             .await?
         }
     };
 
+    // Extra arguments replace the command an entrypoint runs with rather than the entrypoint's own
+    // arguments, as with `docker run --entrypoint`
+    if exec.is_none()
+        && script_name.is_none()
+        && !sandbox_config.get_entrypoint().is_empty()
+        && !args.is_empty()
+    {
+        sandbox_config.command = std::mem::take(&mut args);
+    }
+
     // Determine the exec path and args
     let (exec_path, exec_args) =
         determine_exec_path_and_args(exec, script_name, &sandbox_config, sandbox_name)?;
@@ -249,19 +429,25 @@ pub async fn prepare_run(
         .arg(config_last_modified.to_rfc3339())
         .arg("--sandbox-db-path")
         .arg(&sandbox_db_path)
-        .arg("--scope")
-        .arg(sandbox_config.get_scope().to_string())
         .arg("--exec-path")
         .arg(&exec_path);
 
-    // CPU
-    if let Some(cpus) = sandbox_config.get_cpus() {
-        command.arg("--num-vcpus").arg(cpus.to_string());
+    // CPU and memory, falling back to the configured defaults when the sandbox doesn't set them
+    command
+        .arg("--num-vcpus")
+        .arg(sandbox_config.cpus_or_default().to_string())
+        .arg("--memory-mib")
+        .arg(sandbox_config.memory_or_default().to_string());
+
+    // Guest console capture
+    if console_log || *sandbox_config.get_console_log() {
+        let console_log_path = menv::get_console_log_path(&log_dir, &config_file, sandbox_name);
+        add_console_log_args(&mut command, &console_log_path).await?;
     }
 
-    // Memory
-    if let Some(memory) = sandbox_config.get_memory() {
-        command.arg("--memory-mib").arg(memory.to_string());
+    // Extra kernel command line arguments
+    for kernel_arg in sandbox_config.get_kernel_args() {
+        command.arg("--kernel-arg").arg(kernel_arg);
     }
 
     // Workdir
@@ -269,15 +455,29 @@ pub async fn prepare_run(
         command.arg("--workdir-path").arg(workdir);
     }
 
-    // Env
-    for env in sandbox_config.get_envs() {
-        command.arg("--env").arg(env.to_string());
+    // Env, with env files loaded first and explicit envs taking precedence. Variables inherited
+    // from the host take their value now, and secret references are resolved last so their values
+    // only ever reach the guest.
+    let mut envs = resolve_inherited_envs(
+        sandbox_config.resolve_envs(&canonical_project_dir).await?,
+        InheritedEnvMode::from_env(),
+    )?;
+    if has_secret_references(&envs) {
+        let secrets = SecretStore::load(&env::get_secrets_file_path()).await?;
+        envs = secrets.resolve_envs(envs)?;
     }
 
-    // Ports
-    for port in sandbox_config.get_ports() {
-        command.arg("--port-map").arg(port.to_string());
-    }
+    add_env_vars(&mut command, &envs)?;
+
+    // Network and ports
+    let network = network.unwrap_or(sandbox_config.network_or_default());
+    tracing::info!("using {} network", network);
+    add_network_args(
+        &mut command,
+        network,
+        sandbox_config.scope_or_default(),
+        sandbox_config.get_ports(),
+    );
 
     // Volumes
     for volume in sandbox_config.get_volumes() {
@@ -397,6 +597,8 @@ pub async fn prepare_run(
 /// * `exec` - Optional command to execute within the sandbox. Overrides `script` if provided.
 /// * `args` - Additional arguments to pass to the specified script or command
 /// * `use_image_defaults` - Whether to apply default settings from the OCI image configuration
+/// * `console_output` - If given, the guest console is captured, for debugging boot failures, and
+///   its output is appended here once the sandbox exits, whether or not it succeeded
 ///
 /// # Returns
 ///
@@ -436,7 +638,8 @@ pub async fn prepare_run(
 ///         None,              // No network scope override
 ///         None,              // No exec command
 ///         vec![],            // No additional args
-///         true               // Use image defaults
+///         true,              // Use image defaults
+///         None               // Don't capture the guest console
 ///     ).await?;
 ///     Ok(())
 /// }
@@ -455,7 +658,10 @@ pub async fn run_temp(
     exec: Option<&str>,
     args: Vec<String>,
     use_image_defaults: bool,
+    console_output: Option<&mut String>,
 ) -> MicrosandboxResult<()> {
+    let console_log = console_output.is_some();
+
     // Create a temporary directory without losing the TempDir guard for automatic cleanup
     let temp_dir = tempfile::tempdir()?;
     let temp_dir_path = temp_dir.path().to_path_buf();
@@ -500,7 +706,7 @@ pub async fn run_temp(
             b = b.scope(scope.parse()?);
         }
 
-        b.build()
+        b.console_log(console_log).build()
     };
 
     // Create the microsandbox config with the temporary sandbox
@@ -513,7 +719,7 @@ pub async fn run_temp(
     tokio::fs::write(&config_path, serde_yaml::to_string(&config)?).await?;
 
     // Run the sandbox with the temporary configuration
-    run(
+    let result = run(
         TEMPORARY_SANDBOX_NAME,
         script,
         Some(&temp_dir_path),
@@ -521,9 +727,29 @@ pub async fn run_temp(
         args,
         false,
         exec,
+        None,
         use_image_defaults,
+        None,
+        console_log,
+        None,
+        None,
     )
-    .await?;
+    .await;
+
+    // The captured console goes away with the temporary directory, so read it before cleaning up
+    if let Some(console_output) = console_output {
+        let console_log_path = menv::get_console_log_path(
+            &temp_dir_path.join(MICROSANDBOX_ENV_DIR).join(LOG_SUBDIR),
+            MICROSANDBOX_CONFIG_FILENAME,
+            TEMPORARY_SANDBOX_NAME,
+        );
+
+        if let Ok(contents) = fs::read(&console_log_path).await {
+            console_output.push_str(&String::from_utf8_lossy(&contents));
+        }
+    }
+
+    result?;
 
     // Explicitly close the TempDir to clean up the temporary directory
     temp_dir.close()?;
@@ -532,23 +758,197 @@ pub async fn run_temp(
     Ok(())
 }
 
+/// Archives the read-write layer of a sandbox into a named snapshot.
+///
+/// The snapshot is written to `<MICROSANDBOX_HOME_DIR>/snapshots/<snapshot_name>.tar` and keeps
+/// the modes, symlinks and extended attributes of every entry, including the stat override
+/// attribute that records ownership inside the sandbox. A running sandbox can be snapshotted, but
+/// files it is writing to at the time may be captured half-written.
+///
+/// ## Arguments
+///
+/// * `sandbox_name` - The name of the sandbox whose read-write layer is archived
+/// * `snapshot_name` - The name to store the snapshot under. An existing snapshot with this name
+///   is replaced
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
+///
+/// ## Returns
+///
+/// The path of the snapshot tarball
+pub async fn snapshot(
+    sandbox_name: &str,
+    snapshot_name: &str,
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+) -> MicrosandboxResult<PathBuf> {
+    let snapshot_path = get_snapshot_path(snapshot_name)?;
+    let (rw_path, _, _) = get_rw_layer(sandbox_name, project_dir, config_file).await?;
+    fs::create_dir_all(&rw_path).await?;
+
+    let snapshots_dir = snapshot_path.parent().unwrap().to_path_buf();
+    fs::create_dir_all(&snapshots_dir).await?;
+
+    // Write to a temporary file first so a failed snapshot never replaces a good one
+    let partial_path = snapshot_path.with_extension(format!("{SNAPSHOT_EXTENSION}.partial"));
+    let (src, dest) = (rw_path.clone(), partial_path.clone());
+    let result = tokio::task::spawn_blocking(move || archive_with_xattrs(&src, &dest)).await?;
+    if let Err(e) = result {
+        let _ = fs::remove_file(&partial_path).await;
+        return Err(e);
+    }
+
+    fs::rename(&partial_path, &snapshot_path).await?;
+    tracing::info!(
+        "snapshotted {} to {}",
+        rw_path.display(),
+        snapshot_path.display()
+    );
+
+    Ok(snapshot_path)
+}
+
+/// Replaces the read-write layer of a sandbox with the contents of a snapshot.
+///
+/// The sandbox must be stopped, since its microVM would otherwise keep writing to the layer being
+/// replaced. The snapshot is unpacked next to the current layer and only swapped in once it has
+/// been fully restored.
+///
+/// ## Arguments
+///
+/// * `sandbox_name` - The name of the sandbox whose read-write layer is replaced
+/// * `snapshot_name` - The name of the snapshot to restore
+/// * `project_dir` - Optional path to the project directory. If None, defaults to current directory
+/// * `config_file` - Optional path to the Microsandbox config file. If None, uses default filename
+///
+/// ## Returns
+///
+/// Returns [`MicrosandboxError::SandboxStillRunning`] if the sandbox is running and
+/// [`MicrosandboxError::SnapshotNotFound`] if there is no snapshot with the given name.
+pub async fn restore(
+    sandbox_name: &str,
+    snapshot_name: &str,
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+) -> MicrosandboxResult<()> {
+    let snapshot_path = get_snapshot_path(snapshot_name)?;
+    let (rw_path, pool, config_file) = get_rw_layer(sandbox_name, project_dir, config_file).await?;
+
+    let running = db::get_running_config_sandboxes(&pool, &config_file)
+        .await?
+        .iter()
+        .any(|s| s.name == sandbox_name);
+    if running {
+        return Err(MicrosandboxError::SandboxStillRunning(
+            sandbox_name.to_string(),
+        ));
+    }
+
+    if !fs::try_exists(&snapshot_path).await? {
+        return Err(MicrosandboxError::SnapshotNotFound(
+            snapshot_name.to_string(),
+        ));
+    }
+
+    // Unpack beside the current layer so it stays intact if the snapshot can't be restored
+    let staging_path = rw_path.with_file_name(format!(".{sandbox_name}.restore"));
+    if fs::try_exists(&staging_path).await? {
+        fs::remove_dir_all(&staging_path).await?;
+    }
+
+    let (src, dest) = (snapshot_path.clone(), staging_path.clone());
+    let result = tokio::task::spawn_blocking(move || unpack_with_xattrs(&src, &dest)).await?;
+    if let Err(e) = result {
+        let _ = fs::remove_dir_all(&staging_path).await;
+        return Err(e);
+    }
+
+    if fs::try_exists(&rw_path).await? {
+        fs::remove_dir_all(&rw_path).await?;
+    }
+
+    fs::rename(&staging_path, &rw_path).await?;
+    tracing::info!(
+        "restored {} from {}",
+        rw_path.display(),
+        snapshot_path.display()
+    );
+
+    Ok(())
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Helpers
 //--------------------------------------------------------------------------------------------------
 
-#[allow(clippy::too_many_arguments)]
-async fn setup_image_rootfs(
+/// Makes the supervisor capture the guest console output to `console_log_path`.
+///
+/// The parent directory is created here since the file is only opened once the microVM boots.
+async fn add_console_log_args(
+    command: &mut Command,
+    console_log_path: &Path,
+) -> MicrosandboxResult<()> {
+    if let Some(parent) = console_log_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    command.arg("--console-log-path").arg(console_log_path);
+
+    Ok(())
+}
+
+/// Passes the environment variables of a sandbox to the supervisor.
+///
+/// They are passed in the supervisor's environment rather than its arguments, since any user on
+/// the host can read a process's arguments and the values may be resolved secrets.
+fn add_env_vars(command: &mut Command, envs: &[EnvPair]) -> MicrosandboxResult<()> {
+    if !envs.is_empty() {
+        let envs: Vec<String> = envs.iter().map(ToString::to_string).collect();
+        command.env(MSBRUN_ENVS_ENV_VAR, serde_json::to_string(&envs)?);
+    }
+
+    Ok(())
+}
+
+/// Passes the network mode, scope and port mappings of a sandbox to the supervisor.
+///
+/// The scope only limits sockets carried over the host's network, so it is left out for `nat`.
+/// With `none` it is always `none`, which leaves the guest unable to open any connection while the
+/// published ports can still be reached from the host.
+fn add_network_args(
+    command: &mut Command,
+    network: NetworkMode,
+    scope: NetworkScope,
+    ports: &[PortPair],
+) {
+    command.arg("--network").arg(network.to_string());
+
+    match network {
+        NetworkMode::None => {
+            command.arg("--scope").arg(NetworkScope::None.to_string());
+        }
+        NetworkMode::Host => {
+            command.arg("--scope").arg(scope.to_string());
+        }
+        NetworkMode::Nat => {}
+    }
+
+    for port in ports {
+        command.arg("--port-map").arg(port.to_string());
+    }
+}
+
+/// Pulls an image if needed and returns the paths of its extracted layers, from the bottom up.
+///
+/// The image's configuration defaults are applied to `sandbox_config` if `use_image_defaults` is
+/// set.
+async fn get_image_layer_paths(
     image: &Reference,
-    sandbox_name: &str,
     sandbox_config: &mut Sandbox,
-    menv_path: &Path,
-    config_file: &str,
-    config_last_modified: &DateTime<Utc>,
-    sandbox_pool: &Pool<Sqlite>,
     use_image_defaults: bool,
-) -> MicrosandboxResult<Rootfs> {
+) -> MicrosandboxResult<Vec<PathBuf>> {
     tracing::info!(?image, "pulling image");
-    Image::pull(image.clone(), None).await?;
+    Image::pull(image.clone(), None, false, false).await?;
 
     // Get the microsandbox home path and database path
     let microsandbox_home_path = env::get_microsandbox_home_path();
@@ -565,7 +965,7 @@ async fn setup_image_rootfs(
     }
 
     // Get the layers for the image
-    let digests = db::get_image_layer_digests(&pool, &image.to_string()).await?;
+    let digests = db::get_image_layer_digests(&pool, &image.as_db_key()).await?;
     let layers = db::get_layers_by_digest(&pool, &digests).await?;
     tracing::info!("found {} layers for image {}", layers.len(), image);
 
@@ -574,7 +974,11 @@ async fn setup_image_rootfs(
     let mut layer_paths = Vec::new();
     for layer in &layers {
         let layer_path = layers_dir.join(format!("{}.{}", layer.digest, EXTRACTED_LAYER_SUFFIX));
-        if !layer_path.exists() {
+        let marker_path = layers_dir.join(format!(
+            "{}.{}.{}",
+            layer.digest, EXTRACTED_LAYER_SUFFIX, EXTRACTION_COMPLETE_SUFFIX
+        ));
+        if !layer_path.exists() || !marker_path.exists() {
             return Err(MicrosandboxError::PathNotFound(format!(
                 "extracted layer {} not found at {}",
                 layer.digest,
@@ -585,11 +989,28 @@ async fn setup_image_rootfs(
         layer_paths.push(layer_path);
     }
 
+    Ok(layer_paths)
+}
+
+/// Sets up an overlayfs rootfs with `layer_paths` as its lower layers.
+///
+/// A patch layer with the sandbox's scripts and a read-write layer the sandbox writes its changes
+/// to are stacked on top of them.
+async fn setup_overlay_rootfs(
+    mut layer_paths: Vec<PathBuf>,
+    sandbox_name: &str,
+    sandbox_config: &Sandbox,
+    menv_path: &Path,
+    config_file: &str,
+    config_last_modified: &DateTime<Utc>,
+    sandbox_pool: &Pool<Sqlite>,
+) -> MicrosandboxResult<Rootfs> {
     // Get sandbox scoped name (config_file/sandbox_name)
     let scoped_name = PathBuf::from(config_file).join(sandbox_name);
 
-    // Create the scripts directory
+    // Create the scripts directory, noting whether the patch layer is new
     let patch_dir = menv_path.join(PATCH_SUBDIR).join(&scoped_name);
+    let new_patch_dir = !patch_dir.exists();
     let script_dir = patch_dir.join(SANDBOX_DIR).join(SCRIPTS_DIR);
     fs::create_dir_all(&script_dir).await?;
     tracing::info!("script_dir: {}", script_dir.display());
@@ -600,15 +1021,16 @@ async fn setup_image_rootfs(
     tracing::info!("top_rw_path: {}", top_rw_path.display());
 
     // Check if we need to patch rootfs (scripts, volumes, etc.)
-    let should_patch = has_sandbox_config_changed(
-        sandbox_pool,
-        sandbox_name,
-        config_file,
-        config_last_modified,
-    )
-    .await?;
+    let should_patch = new_patch_dir
+        || has_sandbox_config_changed(
+            sandbox_pool,
+            sandbox_name,
+            config_file,
+            config_last_modified,
+        )
+        .await?;
 
-    // Only patch if sandbox doesn't exist or config has changed
+    // Only patch if sandbox doesn't exist, config has changed or the patch layer is new
     if should_patch {
         tracing::info!("patching sandbox - config has changed");
 
@@ -629,10 +1051,10 @@ async fn setup_image_rootfs(
         )
         .await?;
 
-        // Patch with default DNS settings - check all layers
+        // Patch with DNS settings - check all layers
         let mut all_layers = layer_paths.clone();
         all_layers.push(patch_dir.clone());
-        rootfs::patch_with_default_dns_settings(&all_layers).await?;
+        rootfs::patch_with_dns_settings(&all_layers, sandbox_config.dns_or_default()).await?;
 
         // Patch with volume mounts if there are any volumes defined
         let volumes = &sandbox_config.get_volumes();
@@ -654,6 +1076,153 @@ async fn setup_image_rootfs(
     Ok(Rootfs::Overlayfs(layer_paths))
 }
 
+/// Waits until the supervisor records the sandbox as running in the sandbox database.
+///
+/// If the deadline passes first, the supervisor is asked to stop its microVM and is killed if it
+/// does not exit within a grace period.
+///
+/// ## Arguments
+///
+/// * `child` - The spawned supervisor process
+/// * `sandbox_pool` - The sandbox database the supervisor reports to
+/// * `sandbox_name` - The name of the sandbox being started
+/// * `config_file` - The config file the sandbox is defined in
+/// * `started` - When the start began
+/// * `start_timeout` - How long after `started` the sandbox must be running
+async fn wait_for_supervisor_ready(
+    child: &mut Child,
+    sandbox_pool: &Pool<Sqlite>,
+    sandbox_name: &str,
+    config_file: &str,
+    started: Instant,
+    start_timeout: Duration,
+) -> MicrosandboxResult<()> {
+    let deadline = started + start_timeout;
+    let supervisor_pid = child.id();
+
+    loop {
+        if let Some(sandbox) = db::get_sandbox(sandbox_pool, sandbox_name, config_file).await?
+            && sandbox.status == SANDBOX_STATUS_RUNNING
+            && Some(sandbox.supervisor_pid) == supervisor_pid
+        {
+            return Ok(());
+        }
+
+        // A short-lived sandbox may already have run to completion
+        if let Some(status) = child.try_wait()? {
+            if status.success() {
+                return Ok(());
+            }
+
+            return Err(MicrosandboxError::SupervisorError(format!(
+                "child process — supervisor — exited with status {} before the sandbox was ready",
+                status
+            )));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+
+        tokio::time::sleep(READY_POLL_INTERVAL.min(deadline - now)).await;
+    }
+
+    tracing::warn!(
+        "sandbox {} did not start within {:?}, stopping supervisor",
+        sandbox_name,
+        start_timeout
+    );
+    stop_supervisor(child).await;
+
+    Err(MicrosandboxError::StartTimeout {
+        sandbox: sandbox_name.to_string(),
+        elapsed: started.elapsed(),
+    })
+}
+
+/// Waits for the supervisor to report the sandbox running, recording a failure in the sandbox log.
+///
+/// The log is where a sandbox started in the background is looked into later, so a supervisor
+/// that exits or never becomes ready leaves the reason there as well as returning it.
+///
+/// ## Arguments
+/// * `child` - The spawned supervisor process
+/// * `sandbox_pool` - The sandbox database the supervisor records the sandbox in
+/// * `sandbox_name` - The name of the sandbox
+/// * `config_file` - The config file the sandbox is defined in
+/// * `log_path` - The path of the sandbox log
+/// * `started` - When the start began
+/// * `start_timeout` - How long after `started` the sandbox may take to become ready
+async fn wait_for_supervisor_start(
+    child: &mut Child,
+    sandbox_pool: &Pool<Sqlite>,
+    sandbox_name: &str,
+    config_file: &str,
+    log_path: &Path,
+    started: Instant,
+    start_timeout: Duration,
+) -> MicrosandboxResult<()> {
+    let result = wait_for_supervisor_ready(
+        child,
+        sandbox_pool,
+        sandbox_name,
+        config_file,
+        started,
+        start_timeout,
+    )
+    .await;
+
+    if let Err(e) = &result
+        && let Err(log_error) = record_start_failure(log_path, sandbox_name, e).await
+    {
+        tracing::warn!(
+            "failed to record start failure in {}: {}",
+            log_path.display(),
+            log_error
+        );
+    }
+
+    result
+}
+
+/// Appends the reason a sandbox failed to start to its log.
+async fn record_start_failure(
+    log_path: &Path,
+    sandbox_name: &str,
+    error: &MicrosandboxError,
+) -> std::io::Result<()> {
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let mut log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .await?;
+    let line = format!(
+        "{} sandbox {} failed to start: {}\n",
+        Utc::now().to_rfc3339(),
+        sandbox_name,
+        error
+    );
+    log.write_all(line.as_bytes()).await?;
+    log.flush().await
+}
+
+/// Stops a supervisor, giving it a chance to shut its microVM down before killing it.
+async fn stop_supervisor(child: &mut Child) {
+    // The supervisor forwards SIGTERM to the microVM before exiting
+    if let Err(e) = terminate_child(child, SUPERVISOR_STOP_GRACE_PERIOD).await {
+        tracing::error!("failed to stop supervisor: {}", e);
+    }
+}
+
+/// Sets up a native rootfs that passes `root_path` through to the sandbox.
+///
+/// The rootfs is patched with the sandbox's scripts when the sandbox is new, its config has
+/// changed or `force_patch` is set.
 async fn setup_native_rootfs(
     root_path: &Path,
     sandbox_name: &str,
@@ -661,19 +1230,21 @@ async fn setup_native_rootfs(
     config_file: &str,
     config_last_modified: &DateTime<Utc>,
     sandbox_pool: &Pool<Sqlite>,
+    force_patch: bool,
 ) -> MicrosandboxResult<Rootfs> {
     // Create the scripts directory
     let scripts_dir = root_path.join(SANDBOX_DIR).join(SCRIPTS_DIR);
     fs::create_dir_all(&scripts_dir).await?;
 
     // Check if we need to patch rootfs (scripts, volumes, etc.)
-    let should_patch = has_sandbox_config_changed(
-        sandbox_pool,
-        sandbox_name,
-        config_file,
-        config_last_modified,
-    )
-    .await?;
+    let should_patch = force_patch
+        || has_sandbox_config_changed(
+            sandbox_pool,
+            sandbox_name,
+            config_file,
+            config_last_modified,
+        )
+        .await?;
 
     // Only patch if sandbox doesn't exist or config has changed
     if should_patch {
@@ -690,8 +1261,12 @@ async fn setup_native_rootfs(
         )
         .await?;
 
-        // Patch with default DNS settings - for native rootfs, just pass the single root path
-        rootfs::patch_with_default_dns_settings(&[root_path.to_path_buf()]).await?;
+        // Patch with DNS settings - for native rootfs, just pass the single root path
+        rootfs::patch_with_dns_settings(
+            &[root_path.to_path_buf()],
+            sandbox_config.dns_or_default(),
+        )
+        .await?;
 
         // Patch with volume mounts if there are any volumes defined
         let volumes = &sandbox_config.get_volumes();
@@ -710,6 +1285,49 @@ async fn setup_native_rootfs(
     Ok(Rootfs::Native(root_path.to_path_buf()))
 }
 
+/// Merges the layers of an image into a rootfs directory owned by the sandbox.
+///
+/// The layers are merged into a temporary directory that is moved into place once done, so an
+/// interrupted merge is started over on the next run. An existing merged rootfs is reused, which
+/// keeps the changes the sandbox made to it.
+///
+/// ## Returns
+///
+/// The path of the merged rootfs, and whether it was merged just now
+async fn merge_image_layers(
+    layer_paths: Vec<PathBuf>,
+    menv_path: &Path,
+    config_file: &str,
+    sandbox_name: &str,
+) -> MicrosandboxResult<(PathBuf, bool)> {
+    let root_path = menv_path
+        .join(ROOTFS_SUBDIR)
+        .join(config_file)
+        .join(sandbox_name);
+    if root_path.exists() {
+        tracing::info!("reusing merged rootfs at {}", root_path.display());
+        return Ok((root_path, false));
+    }
+
+    let mut temp_path = root_path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    if temp_path.exists() {
+        fs::remove_dir_all(&temp_path).await?;
+    }
+
+    tracing::info!(
+        "merging {} layers into {}",
+        layer_paths.len(),
+        root_path.display()
+    );
+    let dest = temp_path.clone();
+    tokio::task::spawn_blocking(move || rootfs::merge_layers(&layer_paths, &dest)).await??;
+
+    fs::rename(&temp_path, &root_path).await?;
+    Ok((root_path, true))
+}
+
 /// Checks if a sandbox's configuration has changed by comparing the current config's last modified
 /// timestamp with the stored timestamp in the database. Returns true if the sandbox doesn't exist
 /// or if the config has been modified since the last run.
@@ -735,12 +1353,14 @@ async fn has_sandbox_config_changed(
 /// The function follows this priority order:
 /// 1. Use the explicit exec command if provided
 /// 2. Use the specified script name if provided
-/// 3. Use the start script if it exists
-/// 4. Use the exec command from sandbox config if it exists
-/// 5. Fall back to the shell command from sandbox config
+/// 3. Use the entrypoint from sandbox config if it is set, followed by the command as its arguments
+/// 4. Use the start script if it exists
+/// 5. Use the exec command from sandbox config if it exists
+/// 6. Fall back to the shell command from sandbox config
 ///
-/// Only the command from the sandbox config (get_command) is split into executable path and arguments.
-/// For all other sources, the command is treated as an executable path with no arguments.
+/// Only the entrypoint and command from the sandbox config are split into executable path and
+/// arguments. For all other sources, the command is treated as an executable path with no
+/// arguments.
 ///
 /// ## Arguments
 ///
@@ -776,6 +1396,15 @@ pub fn determine_exec_path_and_args(
                 let script_path = format!("{}/{}/{}", SANDBOX_DIR, SCRIPTS_DIR, script_name);
                 Ok((script_path, Vec::new()))
             }
+            None if !sandbox_config.get_entrypoint().is_empty() => {
+                let entrypoint = sandbox_config.get_entrypoint();
+                let args = entrypoint[1..]
+                    .iter()
+                    .chain(sandbox_config.get_command())
+                    .cloned()
+                    .collect();
+                Ok((entrypoint[0].clone(), args))
+            }
             None => match sandbox_config.get_scripts().get(START_SCRIPT_NAME) {
                 Some(_) => {
                     let script_path =
@@ -801,3 +1430,557 @@ pub fn determine_exec_path_and_args(
         },
     }
 }
+
+/// Resolves the read-write layer of a sandbox defined in the project's config.
+///
+/// ## Returns
+///
+/// The read-write layer path, the sandbox database pool and the config file name
+async fn get_rw_layer(
+    sandbox_name: &str,
+    project_dir: Option<&Path>,
+    config_file: Option<&str>,
+) -> MicrosandboxResult<(PathBuf, Pool<Sqlite>, String)> {
+    let (config, canonical_project_dir, config_file) =
+        config::load_config(project_dir, config_file).await?;
+
+    if config.get_sandbox(sandbox_name).is_none() {
+        return Err(MicrosandboxError::SandboxNotFoundInConfig(
+            sandbox_name.to_string(),
+            canonical_project_dir.join(&config_file),
+        ));
+    }
+
+    let menv_path = canonical_project_dir.join(MICROSANDBOX_ENV_DIR);
+    menv::ensure_menv_files(&menv_path).await?;
+    let db_path = menv_path.join(SANDBOX_DB_FILENAME);
+    let pool = db::get_or_create_pool(&db_path, &db::SANDBOX_DB_MIGRATOR).await?;
+
+    let rw_path = menv_path
+        .join(RW_SUBDIR)
+        .join(PathBuf::from(&config_file).join(sandbox_name));
+
+    Ok((rw_path, pool, config_file))
+}
+
+/// Returns the path of the tarball for the snapshot named `snapshot_name`.
+///
+/// Snapshot names become file names, so they can't be empty, contain path separators or start
+/// with a dot.
+fn get_snapshot_path(snapshot_name: &str) -> MicrosandboxResult<PathBuf> {
+    if snapshot_name.is_empty()
+        || snapshot_name.starts_with('.')
+        || snapshot_name.contains(['/', '\\'])
+    {
+        return Err(MicrosandboxError::InvalidArgument(format!(
+            "invalid snapshot name: {snapshot_name:?}"
+        )));
+    }
+
+    Ok(env::get_microsandbox_home_path()
+        .join(SNAPSHOTS_SUBDIR)
+        .join(format!("{snapshot_name}.{SNAPSHOT_EXTENSION}")))
+}
+
+/// Archives the contents of `src_dir` into a tarball at `dest`.
+///
+/// Symlinks are stored as links rather than followed, and the extended attributes of each entry
+/// are written as `SCHILY.xattr.*` PAX records ahead of it.
+fn archive_with_xattrs(src_dir: &Path, dest: &Path) -> MicrosandboxResult<()> {
+    let mut builder = tar::Builder::new(std::fs::File::create(dest)?);
+    builder.follow_symlinks(false);
+
+    for entry in WalkDir::new(src_dir)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+    {
+        let entry = entry?;
+        let relative_path = entry
+            .path()
+            .strip_prefix(src_dir)
+            .map_err(|e| anyhow::anyhow!("failed to archive {}: {e}", entry.path().display()))?;
+
+        let xattrs = read_xattrs(entry.path())?;
+        if !xattrs.is_empty() {
+            builder.append_pax_extensions(
+                xattrs
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_slice())),
+            )?;
+        }
+
+        builder.append_path_with_name(entry.path(), relative_path)?;
+    }
+
+    builder.into_inner()?.sync_all()?;
+    Ok(())
+}
+
+/// Reads the extended attributes of `path` as PAX records, without following symlinks.
+fn read_xattrs(path: &Path) -> MicrosandboxResult<Vec<(String, Vec<u8>)>> {
+    if !xattr::SUPPORTED_PLATFORM {
+        return Ok(Vec::new());
+    }
+
+    let mut records = Vec::new();
+    for name in xattr::list(path)? {
+        // PAX keys are UTF-8, so attributes with other names can't be stored
+        let Some(name_str) = name.to_str() else {
+            tracing::warn!("skipping non UTF-8 xattr {:?} on {}", name, path.display());
+            continue;
+        };
+
+        if let Some(value) = xattr::get(path, &name)? {
+            records.push((format!("{PAX_XATTR_PREFIX}{name_str}"), value));
+        }
+    }
+
+    Ok(records)
+}
+
+/// Unpacks a tarball created by [`archive_with_xattrs`] into `dest_dir`.
+///
+/// Modes and modification times are restored along with the extended attributes. Directories are
+/// unpacked after the files so a read-only directory doesn't stop its contents from being written.
+fn unpack_with_xattrs(archive_path: &Path, dest_dir: &Path) -> MicrosandboxResult<()> {
+    std::fs::create_dir_all(dest_dir)?;
+
+    let mut archive = tar::Archive::new(std::fs::File::open(archive_path)?);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let xattrs = read_entry_xattrs(&mut entry)?;
+        if entry.header().entry_type().is_dir() {
+            directories.push((entry, xattrs));
+        } else {
+            unpack_entry(&mut entry, &xattrs, dest_dir)?;
+        }
+    }
+
+    for (mut entry, xattrs) in directories {
+        unpack_entry(&mut entry, &xattrs, dest_dir)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the extended attributes stored in the PAX records of a tarball entry.
+fn read_entry_xattrs<R: Read>(
+    entry: &mut tar::Entry<'_, R>,
+) -> MicrosandboxResult<Vec<(String, Vec<u8>)>> {
+    let mut xattrs = Vec::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let Ok(key) = extension.key()
+                && let Some(name) = key.strip_prefix(PAX_XATTR_PREFIX)
+            {
+                xattrs.push((name.to_string(), extension.value_bytes().to_vec()));
+            }
+        }
+    }
+
+    Ok(xattrs)
+}
+
+/// Unpacks a single tarball entry into `dest_dir` and applies its extended attributes.
+fn unpack_entry<R: Read>(
+    entry: &mut tar::Entry<'_, R>,
+    xattrs: &[(String, Vec<u8>)],
+    dest_dir: &Path,
+) -> MicrosandboxResult<()> {
+    let path = dest_dir.join(entry.path()?);
+
+    // Entries that would land outside `dest_dir` are skipped by `unpack_in`
+    if !entry.unpack_in(dest_dir)? {
+        return Ok(());
+    }
+
+    if xattr::SUPPORTED_PLATFORM {
+        for (name, value) in xattrs {
+            xattr::set(&path, name, value)?;
+        }
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_console_log_args_point_supervisor_at_console_log() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let log_dir = temp_dir.path().join(LOG_SUBDIR);
+        let console_log_path =
+            menv::get_console_log_path(&log_dir, MICROSANDBOX_CONFIG_FILENAME, "app");
+        assert_eq!(
+            console_log_path,
+            log_dir
+                .join(MICROSANDBOX_CONFIG_FILENAME)
+                .join("app.console.log")
+        );
+
+        let mut command = Command::new("msbrun");
+        add_console_log_args(&mut command, &console_log_path).await?;
+
+        let args: Vec<_> = command.as_std().get_args().collect();
+        assert_eq!(
+            args,
+            [
+                std::ffi::OsStr::new("--console-log-path"),
+                console_log_path.as_os_str()
+            ]
+        );
+
+        // The directory exists before the microVM opens the file
+        assert!(console_log_path.parent().unwrap().is_dir());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_vars_stay_off_the_command_line() -> anyhow::Result<()> {
+        let envs = ["TOKEN=s3cr3t".parse::<EnvPair>()?, "MODE=a=b".parse()?];
+        let mut command = Command::new("msbrun");
+        add_env_vars(&mut command, &envs)?;
+
+        assert_eq!(command.as_std().get_args().count(), 0);
+        let (_, value) = command
+            .as_std()
+            .get_envs()
+            .find(|(name, _)| *name == MSBRUN_ENVS_ENV_VAR)
+            .unwrap();
+        let passed: Vec<String> = serde_json::from_str(&value.unwrap().to_string_lossy())?;
+        assert_eq!(passed, ["TOKEN=s3cr3t", "MODE=a=b"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_network_args_follow_network_mode() -> anyhow::Result<()> {
+        let ports = ["8080:80".parse::<PortPair>()?];
+        let args = |network| {
+            let mut command = Command::new("msbrun");
+            add_network_args(&mut command, network, NetworkScope::Any, &ports);
+            command
+                .as_std()
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            args(NetworkMode::Host),
+            [
+                "--network",
+                "host",
+                "--scope",
+                "any",
+                "--port-map",
+                "8080:80"
+            ]
+        );
+
+        // Without a network the guest can't reach anything, whatever the configured scope
+        assert_eq!(
+            args(NetworkMode::None),
+            [
+                "--network",
+                "none",
+                "--scope",
+                "none",
+                "--port-map",
+                "8080:80"
+            ]
+        );
+
+        // The scope doesn't apply to the translated network
+        assert_eq!(
+            args(NetworkMode::Nat),
+            ["--network", "nat", "--port-map", "8080:80"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rootfs_mode_produces_matching_rootfs_paths() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let menv_path = temp_dir.path().join(MICROSANDBOX_ENV_DIR);
+        let root_path = temp_dir.path().join("rootfs");
+        std::fs::create_dir_all(&root_path)?;
+        let pool = db::get_or_create_pool(
+            &temp_dir.path().join(SANDBOX_DB_FILENAME),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await?;
+
+        let image = ReferenceOrPath::Path(root_path.clone());
+        let sandbox = Sandbox::builder().image(image.clone()).build();
+        let config_last_modified = Utc::now();
+
+        // A rootfs directory is passed through as is by default
+        assert_eq!(RootfsMode::Auto.select(&image), RootfsMode::Native);
+        let rootfs = setup_native_rootfs(
+            &root_path,
+            "app",
+            &sandbox,
+            MICROSANDBOX_CONFIG_FILENAME,
+            &config_last_modified,
+            &pool,
+            false,
+        )
+        .await?;
+        assert_eq!(
+            rootfs.to_string(),
+            format!("native:{}", root_path.display())
+        );
+
+        // In overlay mode it becomes the lower layer, with the sandbox's own layers on top
+        assert_eq!(RootfsMode::Overlay.select(&image), RootfsMode::Overlay);
+        let rootfs = setup_overlay_rootfs(
+            vec![root_path.clone()],
+            "app",
+            &sandbox,
+            &menv_path,
+            MICROSANDBOX_CONFIG_FILENAME,
+            &config_last_modified,
+            &pool,
+        )
+        .await?;
+
+        let scoped_name = PathBuf::from(MICROSANDBOX_CONFIG_FILENAME).join("app");
+        assert_eq!(
+            rootfs,
+            Rootfs::Overlayfs(vec![
+                root_path.clone(),
+                menv_path.join(PATCH_SUBDIR).join(&scoped_name),
+                menv_path.join(RW_SUBDIR).join(&scoped_name),
+            ])
+        );
+        assert!(
+            rootfs
+                .to_string()
+                .starts_with(&format!("overlayfs:{}:", root_path.display()))
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_timeout_stops_supervisor_that_never_becomes_ready() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let pool = db::get_or_create_pool(
+            &temp_dir.path().join(SANDBOX_DB_FILENAME),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await?;
+
+        // A supervisor stub that stays alive without ever recording the sandbox as running
+        let mut child = Command::new("sleep").arg("30").spawn()?;
+
+        let start_timeout = Duration::from_millis(300);
+        let result = wait_for_supervisor_ready(
+            &mut child,
+            &pool,
+            "app",
+            MICROSANDBOX_CONFIG_FILENAME,
+            Instant::now(),
+            start_timeout,
+        )
+        .await;
+
+        match result {
+            Err(MicrosandboxError::StartTimeout { sandbox, elapsed }) => {
+                assert_eq!(sandbox, "app");
+                assert!(elapsed >= start_timeout);
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // The stub was stopped rather than left running
+        assert!(child.try_wait()?.is_some());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_detached_start_reports_supervisor_that_exits_early() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let pool = db::get_or_create_pool(
+            &temp_dir.path().join(SANDBOX_DB_FILENAME),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await?;
+        let log_path = menv::get_log_path(
+            &temp_dir.path().join(LOG_SUBDIR),
+            MICROSANDBOX_CONFIG_FILENAME,
+            "app",
+        );
+
+        // A supervisor stub that fails before the sandbox boots
+        let mut child = Command::new("sh").arg("-c").arg("exit 1").spawn()?;
+
+        let result = wait_for_supervisor_start(
+            &mut child,
+            &pool,
+            "app",
+            MICROSANDBOX_CONFIG_FILENAME,
+            &log_path,
+            Instant::now(),
+            DETACHED_READY_TIMEOUT,
+        )
+        .await;
+
+        let error = match result {
+            Err(e @ MicrosandboxError::SupervisorError(_)) => e,
+            result => panic!("unexpected result: {:?}", result),
+        };
+        assert!(error.to_string().contains("before the sandbox was ready"));
+
+        // The failure is left in the sandbox log for a detached start to be looked into later
+        let log = fs::read_to_string(&log_path).await?;
+        assert!(log.contains("sandbox app failed to start"));
+        assert!(log.contains(&error.to_string()));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_timeout_returns_once_supervisor_is_ready() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let pool = db::get_or_create_pool(
+            &temp_dir.path().join(SANDBOX_DB_FILENAME),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await?;
+
+        let mut child = Command::new("sleep").arg("30").spawn()?;
+        db::save_or_update_sandbox(
+            &pool,
+            "app",
+            MICROSANDBOX_CONFIG_FILENAME,
+            &Utc::now(),
+            SANDBOX_STATUS_RUNNING,
+            child.id().unwrap(),
+            0,
+            "native:/",
+        )
+        .await?;
+
+        wait_for_supervisor_ready(
+            &mut child,
+            &pool,
+            "app",
+            MICROSANDBOX_CONFIG_FILENAME,
+            Instant::now(),
+            Duration::from_secs(5),
+        )
+        .await?;
+
+        // A ready supervisor is left running
+        assert!(child.try_wait()?.is_none());
+        child.kill().await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_round_trips_permissions_and_xattrs() -> anyhow::Result<()> {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let temp_dir = tempdir()?;
+        let rw_dir = temp_dir.path().join("rw");
+        std::fs::create_dir_all(rw_dir.join("etc/app"))?;
+        std::fs::write(rw_dir.join("etc/app/config"), "key=value")?;
+        std::fs::write(rw_dir.join("run.sh"), "#!/bin/sh")?;
+        symlink("etc/app/config", rw_dir.join("config"))?;
+
+        let modes: &[(&str, u32)] = &[
+            ("etc/app/config", 0o640),
+            ("run.sh", 0o755),
+            ("etc/app", 0o750),
+            ("etc", 0o711),
+        ];
+        for &(path, mode) in modes {
+            std::fs::set_permissions(rw_dir.join(path), std::fs::Permissions::from_mode(mode))?;
+        }
+
+        let xattrs: &[(&str, &str)] = &[("etc/app/config", "0:0:0640"), ("etc/app", "0:0:040750")];
+        if xattr::SUPPORTED_PLATFORM {
+            for &(path, value) in xattrs {
+                xattr::set(
+                    rw_dir.join(path),
+                    "user.containers.override_stat",
+                    value.as_bytes(),
+                )?;
+            }
+        }
+
+        let archive_path = temp_dir.path().join("snapshot.tar");
+        archive_with_xattrs(&rw_dir, &archive_path)?;
+
+        let restored_dir = temp_dir.path().join("restored");
+        unpack_with_xattrs(&archive_path, &restored_dir)?;
+
+        assert_eq!(
+            std::fs::read_to_string(restored_dir.join("etc/app/config"))?,
+            "key=value"
+        );
+        assert_eq!(
+            std::fs::read_link(restored_dir.join("config"))?,
+            PathBuf::from("etc/app/config")
+        );
+
+        for &(path, mode) in modes {
+            let restored_mode = std::fs::metadata(restored_dir.join(path))?
+                .permissions()
+                .mode();
+            assert_eq!(restored_mode & 0o7777, mode, "mode of {path}");
+        }
+
+        if xattr::SUPPORTED_PLATFORM {
+            for &(path, value) in xattrs {
+                let restored =
+                    xattr::get(restored_dir.join(path), "user.containers.override_stat")?;
+                assert_eq!(
+                    restored.as_deref(),
+                    Some(value.as_bytes()),
+                    "xattr of {path}"
+                );
+            }
+            assert!(
+                xattr::get(restored_dir.join("run.sh"), "user.containers.override_stat")?.is_none()
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_name_validation() {
+        assert!(get_snapshot_path("before-upgrade").is_ok());
+
+        for name in ["", ".hidden", "../escape", "nested/name"] {
+            assert!(
+                matches!(
+                    get_snapshot_path(name),
+                    Err(MicrosandboxError::InvalidArgument(_))
+                ),
+                "{name:?} should be rejected"
+            );
+        }
+    }
+}
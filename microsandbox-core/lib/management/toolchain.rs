@@ -2,18 +2,137 @@
 //!
 //! This module provides functionality for managing the Microsandbox toolchain,
 //! including upgrades, and uninstallation. It handles the binaries and libraries
-//! that make up the Microsandbox runtime.
-
-use microsandbox_utils::{XDG_BIN_DIR, XDG_HOME_DIR, XDG_LIB_DIR};
-use std::path::{Path, PathBuf};
+//! that make up the Microsandbox runtime, and the sandbox aliases installed alongside them.
+
+use getset::Getters;
+use microsandbox_utils::{
+    MICROSANDBOX_CONFIG_FILENAME, OCI_DB_FILENAME, XDG_BIN_DIR, XDG_HOME_DIR, XDG_LIB_DIR, env,
+    path::INSTALLS_SUBDIR,
+};
+use sqlx::{Pool, Sqlite};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
 use tokio::fs;
 
-use crate::MicrosandboxResult;
+use crate::{
+    MicrosandboxResult,
+    config::{Microsandbox, ReferenceOrPath},
+    management::db,
+    oci::Reference,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The marker that identifies alias scripts, followed by the name of the alias.
+const ALIAS_MARKER: &str = "# MSB-ALIAS:";
+
+/// Core toolchain scripts that carry the alias marker but aren't user-installed aliases.
+const PROTECTED_EXECUTABLES: [&str; 3] = ["msi", "msx", "msr"];
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// A sandbox alias installed with `msb install`.
+#[derive(Debug, Clone, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct InstalledAlias {
+    /// The name of the alias, which is also the name of its script.
+    name: String,
+
+    /// The path of the alias script.
+    path: PathBuf,
+
+    /// The sandbox script the alias runs, or `None` if it runs the sandbox's default command.
+    script: Option<String>,
+
+    /// The image of the installed sandbox behind the alias, or `None` if that sandbox is no
+    /// longer installed.
+    image: Option<ReferenceOrPath>,
+}
+
+/// Why an installed alias no longer works.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AliasProblem {
+    /// The sandbox behind the alias is no longer installed.
+    SandboxNotInstalled,
+
+    /// The image of the sandbox behind the alias is no longer cached.
+    ImageNotCached(Reference),
+
+    /// The rootfs directory of the sandbox behind the alias no longer exists.
+    RootfsNotFound(PathBuf),
+}
 
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
 
+/// List the sandbox aliases installed with `msb install`
+///
+/// This finds the scripts in ~/.local/bin that contain the MSB-ALIAS marker, except for the
+/// core toolchain scripts (msi, msx, msr), and looks up the installed sandbox behind each one.
+///
+/// ## Returns
+/// The installed aliases, sorted by name
+///
+/// ## Example
+/// ```no_run
+/// use microsandbox_core::management::toolchain;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// for alias in toolchain::list().await? {
+///     println!("{}", alias.get_name());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn list() -> MicrosandboxResult<Vec<InstalledAlias>> {
+    let bin_dir = XDG_HOME_DIR.join(XDG_BIN_DIR);
+    let installs_path = env::get_microsandbox_home_path().join(INSTALLS_SUBDIR);
+
+    list_aliases(&bin_dir, &installs_path).await
+}
+
+/// Find installed sandbox aliases that no longer work
+///
+/// An alias is broken when its sandbox is no longer installed, or when the image of its sandbox
+/// is no longer cached, e.g. because it was cleaned up. Broken aliases can be reinstalled with
+/// `msb install` or removed with `msb uninstall`.
+///
+/// ## Returns
+/// The broken aliases, sorted by name, each with what is wrong with it
+///
+/// ## Example
+/// ```no_run
+/// use microsandbox_core::management::toolchain;
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// for (alias, problem) in toolchain::doctor().await? {
+///     println!("{}: {}", alias.get_name(), problem);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub async fn doctor() -> MicrosandboxResult<Vec<(InstalledAlias, AliasProblem)>> {
+    let home_path = env::get_microsandbox_home_path();
+    let aliases = list().await?;
+
+    // Without an OCI database no image is cached
+    let db_path = home_path.join(OCI_DB_FILENAME);
+    let oci_db = if db_path.exists() {
+        Some(db::get_pool(&db_path).await?)
+    } else {
+        None
+    };
+
+    diagnose_aliases(aliases, &home_path.join(INSTALLS_SUBDIR), oci_db.as_ref()).await
+}
+
 /// Clean up user-installed microsandbox scripts
 ///
 /// This removes all scripts in ~/.local/bin that contain the MSB-ALIAS marker,
@@ -145,6 +264,122 @@ async fn uninstall_versioned_libraries(lib_dir: &Path, lib_prefix: &str) -> Micr
     Ok(())
 }
 
+/// List the alias scripts in `bin_dir`, along with the sandboxes installed in `installs_path`.
+async fn list_aliases(
+    bin_dir: &Path,
+    installs_path: &Path,
+) -> MicrosandboxResult<Vec<InstalledAlias>> {
+    if !bin_dir.exists() {
+        tracing::info!("bin directory not found: {}", bin_dir.display());
+        return Ok(Vec::new());
+    }
+
+    // Aliases whose sandbox can't be found are reported as such rather than failing the listing
+    let config_path = installs_path.join(MICROSANDBOX_CONFIG_FILENAME);
+    let installed = match fs::read_to_string(&config_path).await {
+        Ok(contents) => serde_yaml::from_str::<Microsandbox>(&contents)
+            .inspect_err(|e| tracing::warn!("failed to parse {}: {}", config_path.display(), e))
+            .unwrap_or_default(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Microsandbox::default(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut aliases = Vec::new();
+    let mut entries = fs::read_dir(bin_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let Some(name) = path
+            .file_name()
+            .and_then(|f| f.to_str())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        if PROTECTED_EXECUTABLES.contains(&name.as_str()) {
+            continue;
+        }
+
+        let Ok(content) = fs::read_to_string(&path).await else {
+            continue;
+        };
+        if !content.contains(ALIAS_MARKER) {
+            continue;
+        }
+
+        let image = installed
+            .get_sandboxes()
+            .get(&name)
+            .map(|sandbox| sandbox.get_image().clone());
+
+        aliases.push(InstalledAlias {
+            script: parse_alias_script_name(&content),
+            name,
+            path,
+            image,
+        });
+    }
+
+    aliases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(aliases)
+}
+
+/// Returns the sandbox script an alias script runs, from the `<alias>~<script>` it passes to
+/// `msb run`.
+fn parse_alias_script_name(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find(|line| line.trim_start().starts_with("exec "))
+        .and_then(|line| line.split_once(" run \""))
+        .and_then(|(_, rest)| rest.split_once('"'))
+        .and_then(|(target, _)| target.split_once('~'))
+        .map(|(_, script)| script.to_string())
+}
+
+/// Finds what is wrong with each alias, if anything.
+///
+/// Relative rootfs paths are resolved against `installs_path`, where the sandboxes are installed.
+async fn diagnose_aliases(
+    aliases: Vec<InstalledAlias>,
+    installs_path: &Path,
+    oci_db: Option<&Pool<Sqlite>>,
+) -> MicrosandboxResult<Vec<(InstalledAlias, AliasProblem)>> {
+    let mut broken = Vec::new();
+    for alias in aliases {
+        let problem = match &alias.image {
+            None => Some(AliasProblem::SandboxNotInstalled),
+            Some(ReferenceOrPath::Reference(reference)) => {
+                let cached = match oci_db {
+                    Some(pool) => db::image_exists(pool, &reference.as_db_key()).await?,
+                    None => false,
+                };
+                if cached {
+                    None
+                } else {
+                    Some(AliasProblem::ImageNotCached(reference.clone()))
+                }
+            }
+            Some(ReferenceOrPath::Path(path)) => {
+                if installs_path.join(path).exists() {
+                    None
+                } else {
+                    Some(AliasProblem::RootfsNotFound(path.clone()))
+                }
+            }
+        };
+
+        if let Some(problem) = problem {
+            tracing::debug!("alias {} is broken: {}", alias.name, problem);
+            broken.push((alias, problem));
+        }
+    }
+
+    Ok(broken)
+}
+
 /// Clean all user scripts with MSB-ALIAS markers from the specified bin directory
 async fn clean_user_scripts(bin_dir: &Path) -> MicrosandboxResult<()> {
     // Exit early if bin directory doesn't exist
@@ -153,9 +388,6 @@ async fn clean_user_scripts(bin_dir: &Path) -> MicrosandboxResult<()> {
         return Ok(());
     }
 
-    // Core executables that should not be removed by clean
-    let protected_executables = ["msi", "msx", "msr"];
-
     // Get all files in the bin directory
     let mut entries = fs::read_dir(bin_dir).await?;
     let mut removed_count = 0;
@@ -171,7 +403,7 @@ async fn clean_user_scripts(bin_dir: &Path) -> MicrosandboxResult<()> {
 
         // Skip protected executables
         if let Some(filename) = path.file_name().and_then(|f| f.to_str())
-            && protected_executables.contains(&filename)
+            && PROTECTED_EXECUTABLES.contains(&filename)
         {
             tracing::debug!("skipping protected executable: {}", filename);
             continue;
@@ -179,7 +411,7 @@ async fn clean_user_scripts(bin_dir: &Path) -> MicrosandboxResult<()> {
 
         // Read file content and check for MSB-ALIAS marker
         if let Ok(content) = fs::read_to_string(&path).await
-            && content.contains("# MSB-ALIAS:")
+            && content.contains(ALIAS_MARKER)
         {
             // This is a microsandbox alias script, remove it
             fs::remove_file(&path).await?;
@@ -195,3 +427,129 @@ async fn clean_user_scripts(bin_dir: &Path) -> MicrosandboxResult<()> {
 
     Ok(())
 }
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl fmt::Display for AliasProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AliasProblem::SandboxNotInstalled => write!(f, "its sandbox is no longer installed"),
+            AliasProblem::ImageNotCached(reference) => {
+                write!(f, "image {} is no longer cached", reference)
+            }
+            AliasProblem::RootfsNotFound(path) => {
+                write!(f, "rootfs {} no longer exists", path.display())
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{config::Sandbox, management::home::generate_alias_script};
+
+    #[tokio::test]
+    async fn test_toolchain_list_and_diagnose_aliases() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let bin_dir = temp_dir.path().join("bin");
+        let installs_path = temp_dir.path().join("installs");
+        fs::create_dir_all(&bin_dir).await?;
+        fs::create_dir_all(&installs_path).await?;
+
+        // Only the python image is still cached
+        let python = Reference::from_str("python:3.11")?;
+        let node = Reference::from_str("node:20")?;
+        let oci_db =
+            db::get_or_create_pool(&temp_dir.path().join("oci.db"), &db::OCI_DB_MIGRATOR).await?;
+        db::save_or_update_image(&oci_db, &python.as_db_key(), 0).await?;
+
+        let sandbox = |reference: &Reference| {
+            Sandbox::builder()
+                .image(ReferenceOrPath::Reference(reference.clone()))
+                .build()
+        };
+        let config = Microsandbox::builder()
+            .sandboxes([
+                ("py".to_string(), sandbox(&python)),
+                ("node".to_string(), sandbox(&node)),
+            ])
+            .build_unchecked();
+        fs::write(
+            installs_path.join(MICROSANDBOX_CONFIG_FILENAME),
+            serde_yaml::to_string(&config)?,
+        )
+        .await?;
+
+        fs::write(
+            bin_dir.join("py"),
+            generate_alias_script("py", Some("repl")),
+        )
+        .await?;
+        fs::write(bin_dir.join("node"), generate_alias_script("node", None)).await?;
+
+        // Core toolchain scripts and unrelated files are not aliases
+        fs::write(bin_dir.join("msr"), generate_alias_script("msr", None)).await?;
+        fs::write(bin_dir.join("other"), "#!/bin/sh\necho other\n").await?;
+
+        let aliases = list_aliases(&bin_dir, &installs_path).await?;
+        assert_eq!(
+            aliases,
+            [
+                InstalledAlias {
+                    name: "node".to_string(),
+                    path: bin_dir.join("node"),
+                    script: None,
+                    image: Some(ReferenceOrPath::Reference(node.clone())),
+                },
+                InstalledAlias {
+                    name: "py".to_string(),
+                    path: bin_dir.join("py"),
+                    script: Some("repl".to_string()),
+                    image: Some(ReferenceOrPath::Reference(python.clone())),
+                },
+            ]
+        );
+
+        let broken = diagnose_aliases(aliases.clone(), &installs_path, Some(&oci_db)).await?;
+        assert_eq!(
+            broken,
+            [(
+                aliases[0].clone(),
+                AliasProblem::ImageNotCached(node.clone())
+            )]
+        );
+        assert_eq!(
+            broken[0].1.to_string(),
+            format!("image {} is no longer cached", node)
+        );
+
+        // An alias whose sandbox was removed from the installs config is broken too
+        fs::write(bin_dir.join("gone"), generate_alias_script("gone", None)).await?;
+        let aliases = list_aliases(&bin_dir, &installs_path).await?;
+        let broken = diagnose_aliases(aliases, &installs_path, Some(&oci_db)).await?;
+        let problems = broken
+            .iter()
+            .map(|(alias, problem)| (alias.get_name().as_str(), problem.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                ("gone", AliasProblem::SandboxNotInstalled),
+                ("node", AliasProblem::ImageNotCached(node)),
+            ]
+        );
+
+        Ok(())
+    }
+}
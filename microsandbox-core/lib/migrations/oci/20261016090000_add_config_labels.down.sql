@@ -0,0 +1,2 @@
+-- Drop labels column from configs table
+ALTER TABLE configs DROP COLUMN config_labels_json;
@@ -0,0 +1,2 @@
+-- Add labels column to configs table
+ALTER TABLE configs ADD COLUMN config_labels_json TEXT;
@@ -170,6 +170,9 @@ pub struct Config {
     /// User to run as
     pub config_user: Option<String>,
 
+    /// JSON string containing labels
+    pub config_labels_json: Option<String>,
+
     /// Type of root filesystem
     pub rootfs_type: String,
 
@@ -0,0 +1,581 @@
+//! Storing the registry credentials saved by `msb login`.
+//!
+//! Credentials are stored by registry host in a JSON file that only its owner can read. The file
+//! carries the version of its format, so the format can change as [`MsbRegistryAuth`] gains
+//! variants without orphaning credentials saved by an older msb:
+//! - Older versions are upgraded in place when the store is loaded, one version at a time
+//! - Newer versions are rejected, since there is no telling what a downgrade would lose
+//!
+//! Pulls look credentials up by the exact registry host of the image reference, so credentials
+//! are never sent to a host other than the one they were stored for. Registries with a host per
+//! account or region, like ECR, can opt into a wildcard host such as
+//! `*.dkr.ecr.us-east-1.amazonaws.com`, see [`RegistryCredentials`].
+
+use std::{collections::BTreeMap, path::PathBuf};
+
+use async_trait::async_trait;
+use microsandbox_utils::{CREDENTIALS_FILE, DEFAULT_OCI_REGISTRY, env};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::fs;
+
+use crate::{
+    MicrosandboxError, MicrosandboxResult,
+    oci::{Reference, docker_config::write_private_file, reference::LEGACY_DOCKER_HUB_REGISTRY},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The version of the credential store format written by this build.
+pub const CREDENTIAL_STORE_VERSION: u64 = 1;
+
+/// The migrations between credential store versions, where the migration at index `i` upgrades a
+/// store from version `i + 1` to version `i + 2`.
+///
+/// A migration is added here whenever [`CREDENTIAL_STORE_VERSION`] is bumped.
+const MIGRATIONS: &[fn(&mut Map<String, Value>) -> MicrosandboxResult<()>] = &[];
+
+/// The prefix of registry hosts that match every host one label below them.
+const WILDCARD_HOST_PREFIX: &str = "*.";
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Credentials for authenticating with a registry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MsbRegistryAuth {
+    /// A username and password.
+    Basic {
+        /// The username.
+        username: String,
+
+        /// The password.
+        password: String,
+    },
+
+    /// An identity token issued by the registry.
+    Token(String),
+}
+
+/// A place registry credentials are kept, keyed by registry host.
+#[async_trait]
+pub trait CredentialStore: Send + Sync {
+    /// Returns the credentials stored for a registry, if any.
+    async fn get(&self, registry: &str) -> MicrosandboxResult<Option<MsbRegistryAuth>>;
+
+    /// Stores the credentials for a registry, replacing any stored before.
+    async fn store(&self, registry: &str, auth: MsbRegistryAuth) -> MicrosandboxResult<()>;
+
+    /// Removes the credentials stored for a registry.
+    ///
+    /// Returns whether there were any to remove.
+    async fn remove(&self, registry: &str) -> MicrosandboxResult<bool>;
+
+    /// Returns the hosts of the registries with stored credentials, in sorted order.
+    ///
+    /// Only the hosts are returned, never the credentials themselves.
+    async fn list_registries(&self) -> MicrosandboxResult<Vec<String>>;
+}
+
+/// A credential store kept in a JSON file.
+#[derive(Debug, Clone)]
+pub struct FileCredentialStore {
+    /// The path of the store file.
+    path: PathBuf,
+}
+
+/// The contents of a credential store file at [`CREDENTIAL_STORE_VERSION`].
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredCredentials {
+    /// The version of the store format.
+    version: u64,
+
+    /// The credentials of each registry, keyed by registry host.
+    #[serde(default)]
+    registries: BTreeMap<String, MsbRegistryAuth>,
+}
+
+/// Registry credentials looked up by the registry host of an image reference.
+///
+/// Credentials are only ever used for the host they were stored for: those for `a.example.com`
+/// are not used for `b.example.com`, nor for `example.com`. Hosts are compared case-insensitively,
+/// with the port, and `index.docker.io` is the same host as `docker.io`.
+///
+/// A host stored as a wildcard, e.g. `*.dkr.ecr.us-east-1.amazonaws.com`, opts into matching
+/// every host exactly one label below it, such as `123456789012.dkr.ecr.us-east-1.amazonaws.com`.
+/// Credentials stored for the exact host take precedence over a wildcard.
+#[derive(Debug, Clone, Default)]
+pub struct RegistryCredentials {
+    /// The credentials of each registry, keyed by normalized host or wildcard host.
+    hosts: BTreeMap<String, MsbRegistryAuth>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl FileCredentialStore {
+    /// Creates a store kept in the file at `path`, which is created when credentials are first
+    /// stored.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Creates a store kept in the credentials file of the microsandbox home directory.
+    pub fn from_env() -> Self {
+        Self::new(env::get_microsandbox_home_path().join(CREDENTIALS_FILE))
+    }
+
+    /// Returns the path of the store file.
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Loads the store, upgrading it in place if it was written in an older format.
+    async fn load(&self) -> MicrosandboxResult<StoredCredentials> {
+        let contents = match fs::read_to_string(&self.path).await {
+            Ok(contents) if contents.trim().is_empty() => return Ok(StoredCredentials::new()),
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(StoredCredentials::new());
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let Value::Object(mut store) = serde_json::from_str(&contents)? else {
+            return Err(self.invalid("not a JSON object"));
+        };
+
+        let version = store
+            .get("version")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| self.invalid("missing a `version`"))?;
+        let migrated = migrate(&mut store, version).map_err(|e| match e {
+            MicrosandboxError::InvalidCredentialStore(reason) => self.invalid(&reason),
+            e => e,
+        })?;
+
+        let credentials = serde_json::from_value(Value::Object(store))?;
+        if migrated {
+            tracing::info!(
+                "upgraded credential store {} from version {version} to {CREDENTIAL_STORE_VERSION}",
+                self.path.display()
+            );
+            self.save(&credentials).await?;
+        }
+
+        Ok(credentials)
+    }
+
+    /// Saves the store, creating its parent directory if needed.
+    async fn save(&self, credentials: &StoredCredentials) -> MicrosandboxResult<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        write_private_file(&self.path, &serde_json::to_string_pretty(credentials)?).await
+    }
+
+    /// Returns the error for a store file that can't be read.
+    fn invalid(&self, reason: &str) -> MicrosandboxError {
+        MicrosandboxError::InvalidCredentialStore(format!("{} is {reason}", self.path.display()))
+    }
+}
+
+impl StoredCredentials {
+    /// Creates an empty store at the current version.
+    fn new() -> Self {
+        Self {
+            version: CREDENTIAL_STORE_VERSION,
+            registries: BTreeMap::new(),
+        }
+    }
+}
+
+impl RegistryCredentials {
+    /// Creates an empty set of credentials, with which every registry is pulled from anonymously.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads every credential kept in a store.
+    ///
+    /// Credentials stored for an invalid host are skipped with a warning rather than failing
+    /// every pull.
+    pub async fn load(store: &dyn CredentialStore) -> MicrosandboxResult<Self> {
+        let mut credentials = Self::new();
+        for registry in store.list_registries().await? {
+            let Some(auth) = store.get(&registry).await? else {
+                continue;
+            };
+
+            if let Err(e) = credentials.insert(&registry, auth) {
+                tracing::warn!("ignoring stored credentials: {}", e);
+            }
+        }
+
+        Ok(credentials)
+    }
+
+    /// Adds the credentials for a registry host or wildcard host, replacing any added before.
+    ///
+    /// ## Returns
+    ///
+    /// `MicrosandboxError::InvalidRegistryHost` if the host is not valid, see
+    /// [`validate_registry_host`]
+    pub fn insert(&mut self, registry: &str, auth: MsbRegistryAuth) -> MicrosandboxResult<()> {
+        validate_registry_host(registry)?;
+        self.hosts.insert(normalize_host(registry), auth);
+        Ok(())
+    }
+
+    /// Returns the credentials for a registry host, if any.
+    ///
+    /// The credentials stored for the exact host are returned if there are any, otherwise those
+    /// of a wildcard host one label above it. No other host's credentials are ever returned.
+    pub fn for_host(&self, host: &str) -> Option<&MsbRegistryAuth> {
+        let host = normalize_host(host);
+        if let Some(auth) = self.hosts.get(&host) {
+            return Some(auth);
+        }
+
+        let (_, parent) = host.split_once('.')?;
+        self.hosts.get(&format!("{WILDCARD_HOST_PREFIX}{parent}"))
+    }
+
+    /// Returns the credentials for the registry an image is pulled from, if any.
+    ///
+    /// Images in an OCI image layout on disk have no registry, and so no credentials.
+    pub fn for_reference(&self, reference: &Reference) -> Option<&MsbRegistryAuth> {
+        if reference.layout().is_some() {
+            return None;
+        }
+
+        self.for_host(reference.registry())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+#[async_trait]
+impl CredentialStore for FileCredentialStore {
+    async fn get(&self, registry: &str) -> MicrosandboxResult<Option<MsbRegistryAuth>> {
+        Ok(self.load().await?.registries.remove(registry))
+    }
+
+    async fn store(&self, registry: &str, auth: MsbRegistryAuth) -> MicrosandboxResult<()> {
+        validate_registry_host(registry)?;
+        let mut credentials = self.load().await?;
+        credentials.registries.insert(registry.to_string(), auth);
+        self.save(&credentials).await
+    }
+
+    async fn remove(&self, registry: &str) -> MicrosandboxResult<bool> {
+        let mut credentials = self.load().await?;
+        if credentials.registries.remove(registry).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&credentials).await?;
+        Ok(true)
+    }
+
+    async fn list_registries(&self) -> MicrosandboxResult<Vec<String>> {
+        Ok(self.load().await?.registries.into_keys().collect())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Checks that credentials can be stored for a registry host.
+///
+/// A host is a hostname with an optional port, e.g. `registry.internal:5000`. A wildcard host is
+/// `*.` followed by a host of at least two labels, e.g. `*.example.com`, so that a wildcard can't
+/// cover a whole top-level domain. `*` can't appear anywhere else.
+pub fn validate_registry_host(registry: &str) -> MicrosandboxResult<()> {
+    let invalid =
+        |reason: &str| MicrosandboxError::InvalidRegistryHost(format!("{registry}: {reason}"));
+
+    let host = registry
+        .strip_prefix(WILDCARD_HOST_PREFIX)
+        .unwrap_or(registry);
+    if host.is_empty() {
+        return Err(invalid("the host is empty"));
+    }
+
+    if host.contains('*') {
+        return Err(invalid(
+            "a wildcard is only allowed as the first label, e.g. *.example.com",
+        ));
+    }
+
+    if host.contains(['/', '@']) || host.chars().any(char::is_whitespace) {
+        return Err(invalid(
+            "expected a host with an optional port, e.g. registry.internal:5000",
+        ));
+    }
+
+    let name = host.split_once(':').map_or(host, |(name, _)| name);
+    if name.split('.').any(str::is_empty) {
+        return Err(invalid("the host has an empty label"));
+    }
+
+    if host.len() < registry.len() && !name.contains('.') {
+        return Err(invalid(
+            "a wildcard host needs at least two labels, e.g. *.example.com",
+        ));
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Normalizes a registry host for comparison, lowercasing it and treating `index.docker.io` as
+/// `docker.io`.
+fn normalize_host(host: &str) -> String {
+    let host = host.to_ascii_lowercase();
+    match host.as_str() {
+        LEGACY_DOCKER_HUB_REGISTRY => DEFAULT_OCI_REGISTRY.to_string(),
+        _ => host,
+    }
+}
+
+/// Upgrades a store from `version` to [`CREDENTIAL_STORE_VERSION`].
+///
+/// Returns whether the store was changed.
+fn migrate(store: &mut Map<String, Value>, version: u64) -> MicrosandboxResult<bool> {
+    if version > CREDENTIAL_STORE_VERSION {
+        return Err(MicrosandboxError::UnsupportedCredentialStoreVersion {
+            version,
+            supported: CREDENTIAL_STORE_VERSION,
+        });
+    }
+
+    if version == 0 {
+        return Err(MicrosandboxError::InvalidCredentialStore(
+            "at version 0, which was never written".to_string(),
+        ));
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize - 1) {
+        migration(store)?;
+        store.insert("version".to_string(), Value::from(from as u64 + 2));
+    }
+
+    Ok(version < CREDENTIAL_STORE_VERSION)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_credential_store_loads_v1_store() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(CREDENTIALS_FILE);
+        let fixture = json!({
+            "version": 1,
+            "registries": {
+                "docker.io": { "basic": { "username": "alice", "password": "s3cret" } },
+                "ghcr.io": { "token": "token-123" }
+            }
+        });
+        fs::write(&path, fixture.to_string()).await?;
+
+        let store = FileCredentialStore::new(&path);
+        assert_eq!(
+            store.get("docker.io").await?,
+            Some(MsbRegistryAuth::Basic {
+                username: "alice".to_string(),
+                password: "s3cret".to_string(),
+            })
+        );
+        assert_eq!(
+            store.get("ghcr.io").await?,
+            Some(MsbRegistryAuth::Token("token-123".to_string()))
+        );
+        assert_eq!(store.get("quay.io").await?, None);
+
+        // Storing more credentials keeps the ones already there, at the current version
+        store
+            .store("quay.io", MsbRegistryAuth::Token("token-456".to_string()))
+            .await?;
+        let saved: Value = serde_json::from_str(&fs::read_to_string(&path).await?)?;
+        assert_eq!(saved["version"], json!(CREDENTIAL_STORE_VERSION));
+        assert_eq!(
+            saved["registries"]["ghcr.io"],
+            fixture["registries"]["ghcr.io"]
+        );
+        assert_eq!(
+            saved["registries"]["quay.io"],
+            json!({ "token": "token-456" })
+        );
+
+        assert!(store.remove("quay.io").await?);
+        assert!(!store.remove("quay.io").await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_credential_store_rejects_newer_version() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(CREDENTIALS_FILE);
+        let contents = json!({
+            "version": CREDENTIAL_STORE_VERSION + 1,
+            "registries": { "docker.io": { "oauth": { "refresh_token": "r" } } }
+        })
+        .to_string();
+        fs::write(&path, &contents).await?;
+
+        let store = FileCredentialStore::new(&path);
+        let result = store.get("docker.io").await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::UnsupportedCredentialStoreVersion { version, .. })
+                if version == CREDENTIAL_STORE_VERSION + 1
+        ));
+        assert!(result.unwrap_err().to_string().contains("downgrading"));
+
+        // The newer store is left untouched
+        let result = store
+            .store("docker.io", MsbRegistryAuth::Token("t".to_string()))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).await?, contents);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_credential_store_lists_registries_without_secrets() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = FileCredentialStore::new(dir.path().join(CREDENTIALS_FILE));
+        assert!(store.list_registries().await?.is_empty());
+
+        let basic = MsbRegistryAuth::Basic {
+            username: "alice".to_string(),
+            password: "s3cret".to_string(),
+        };
+        store.store("ghcr.io", basic).await?;
+        store
+            .store("docker.io", MsbRegistryAuth::Token("token-123".to_string()))
+            .await?;
+
+        let registries = store.list_registries().await?;
+        assert_eq!(registries, ["docker.io", "ghcr.io"]);
+
+        let listed = format!("{registries:?}");
+        assert!(!listed.contains("s3cret"));
+        assert!(!listed.contains("token-123"));
+
+        Ok(())
+    }
+
+    fn token(token: &str) -> MsbRegistryAuth {
+        MsbRegistryAuth::Token(token.to_string())
+    }
+
+    #[test]
+    fn test_registry_credentials_never_cross_hosts() -> anyhow::Result<()> {
+        let mut credentials = RegistryCredentials::new();
+        credentials.insert("a.example.com", token("a"))?;
+        credentials.insert("registry.internal:5000", token("internal"))?;
+
+        assert_eq!(credentials.for_host("a.example.com"), Some(&token("a")));
+        assert_eq!(credentials.for_host("A.Example.com"), Some(&token("a")));
+        assert_eq!(credentials.for_host("b.example.com"), None);
+        assert_eq!(credentials.for_host("example.com"), None);
+        assert_eq!(credentials.for_host("x.a.example.com"), None);
+
+        // The port is part of the host
+        assert_eq!(credentials.for_host("registry.internal"), None);
+        assert_eq!(credentials.for_host("registry.internal:5001"), None);
+
+        let reference: Reference = "b.example.com/team/app:1.0".parse()?;
+        assert_eq!(credentials.for_reference(&reference), None);
+        let reference: Reference = "a.example.com/team/app:1.0".parse()?;
+        assert_eq!(credentials.for_reference(&reference), Some(&token("a")));
+
+        // Docker Hub goes by either name
+        credentials.insert("docker.io", token("hub"))?;
+        let reference: Reference = "alpine".parse()?;
+        assert_eq!(credentials.for_reference(&reference), Some(&token("hub")));
+        assert_eq!(credentials.for_host("index.docker.io"), Some(&token("hub")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry_credentials_wildcard_hosts_are_opt_in() -> anyhow::Result<()> {
+        let ecr = "dkr.ecr.us-east-1.amazonaws.com";
+        let mut credentials = RegistryCredentials::new();
+        credentials.insert(&format!("*.{ecr}"), token("ecr"))?;
+        credentials.insert(&format!("111111111111.{ecr}"), token("pinned"))?;
+
+        assert_eq!(
+            credentials.for_host(&format!("123456789012.{ecr}")),
+            Some(&token("ecr"))
+        );
+
+        // The exact host takes precedence over the wildcard
+        assert_eq!(
+            credentials.for_host(&format!("111111111111.{ecr}")),
+            Some(&token("pinned"))
+        );
+
+        // A wildcard covers exactly one label, and not the host it is under
+        assert_eq!(credentials.for_host(&format!("a.b.{ecr}")), None);
+        assert_eq!(credentials.for_host(ecr), None);
+        assert_eq!(
+            credentials.for_host("123456789012.dkr.ecr.eu-west-1.amazonaws.com"),
+            None
+        );
+
+        for registry in [
+            "*.com",
+            "*",
+            "*.",
+            "a.*.example.com",
+            "example.com/path",
+            "a..com",
+        ] {
+            assert!(
+                matches!(
+                    validate_registry_host(registry),
+                    Err(MicrosandboxError::InvalidRegistryHost(_))
+                ),
+                "{registry} should be rejected"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_rejects_unversioned_store() {
+        let mut store = Map::new();
+        assert!(matches!(
+            migrate(&mut store, 0),
+            Err(MicrosandboxError::InvalidCredentialStore(_))
+        ));
+        assert!(!migrate(&mut store, CREDENTIAL_STORE_VERSION).unwrap());
+    }
+}
@@ -0,0 +1,261 @@
+//! Exporting registry credentials to Docker's `config.json`.
+//!
+//! Some tools only read registry credentials from Docker's config file, so credentials used with
+//! microsandbox can be written there too. Entries are written in Docker's format:
+//! - Username and password credentials are stored base64-encoded as `username:password` under `auth`
+//! - Token credentials are stored under `identitytoken`
+//!
+//! The rest of the file, including the entries of other registries, is left as it is.
+
+use std::path::{Path, PathBuf};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use serde_json::{Map, Value};
+use tokio::fs;
+
+use crate::{MicrosandboxError, MicrosandboxResult, oci::credential_store::MsbRegistryAuth};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The environment variable Docker reads its configuration directory from.
+const DOCKER_CONFIG_ENV_VAR: &str = "DOCKER_CONFIG";
+
+/// The name of Docker's configuration file.
+const DOCKER_CONFIG_FILENAME: &str = "config.json";
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Returns the path of Docker's configuration file.
+///
+/// This is `config.json` in the directory set by `DOCKER_CONFIG`, or in `~/.docker` otherwise.
+pub fn docker_config_path() -> Option<PathBuf> {
+    match std::env::var_os(DOCKER_CONFIG_ENV_VAR) {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join(DOCKER_CONFIG_FILENAME)),
+        _ => dirs::home_dir().map(|home| home.join(".docker").join(DOCKER_CONFIG_FILENAME)),
+    }
+}
+
+/// Writes the credentials for a registry into Docker's configuration file.
+///
+/// ## Arguments
+///
+/// * `registry` - The registry the credentials are for, e.g. `docker.io`
+/// * `auth` - The credentials to write
+///
+/// ## Returns
+///
+/// The path of the configuration file that was written
+pub async fn export_credentials(
+    registry: &str,
+    auth: &MsbRegistryAuth,
+) -> MicrosandboxResult<PathBuf> {
+    let config_path = docker_config_path().ok_or_else(|| {
+        MicrosandboxError::InvalidDockerConfig(
+            "cannot determine the home directory to find the Docker config in".to_string(),
+        )
+    })?;
+
+    export_credentials_to(&config_path, registry, auth).await?;
+    Ok(config_path)
+}
+
+/// Writes the credentials for a registry into the Docker configuration file at `config_path`.
+///
+/// The file and its parent directory are created if they don't exist. An existing entry for the
+/// registry has its credentials replaced, while everything else in the file is preserved.
+pub async fn export_credentials_to(
+    config_path: &Path,
+    registry: &str,
+    auth: &MsbRegistryAuth,
+) -> MicrosandboxResult<()> {
+    let mut config = match fs::read_to_string(config_path).await {
+        Ok(contents) if contents.trim().is_empty() => Map::new(),
+        Ok(contents) => match serde_json::from_str(&contents)? {
+            Value::Object(config) => config,
+            _ => {
+                return Err(MicrosandboxError::InvalidDockerConfig(format!(
+                    "{} is not a JSON object",
+                    config_path.display()
+                )));
+            }
+        },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Map::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    set_registry_auth(&mut config, registry, auth, config_path)?;
+
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    write_private_file(config_path, &serde_json::to_string_pretty(&config)?).await
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Sets the entry of a registry under `auths` in a Docker configuration.
+fn set_registry_auth(
+    config: &mut Map<String, Value>,
+    registry: &str,
+    auth: &MsbRegistryAuth,
+    config_path: &Path,
+) -> MicrosandboxResult<()> {
+    let not_an_object = |key: &str| {
+        MicrosandboxError::InvalidDockerConfig(format!(
+            "`{}` in {} is not a JSON object",
+            key,
+            config_path.display()
+        ))
+    };
+
+    let auths = config
+        .entry("auths")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| not_an_object("auths"))?;
+
+    let entry = auths
+        .entry(registry)
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| not_an_object(registry))?;
+
+    // Drop the previous credentials, so a token doesn't linger next to a new password
+    for key in ["auth", "identitytoken", "username", "password"] {
+        entry.remove(key);
+    }
+
+    match auth {
+        MsbRegistryAuth::Basic { username, password } => {
+            let encoded = BASE64_STANDARD.encode(format!("{username}:{password}"));
+            entry.insert("auth".to_string(), Value::String(encoded));
+        }
+        MsbRegistryAuth::Token(token) => {
+            entry.insert("identitytoken".to_string(), Value::String(token.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a file only its owner can read, replacing it in one step.
+pub(crate) async fn write_private_file(path: &Path, contents: &str) -> MicrosandboxResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    fs::write(&temp_path, contents).await?;
+    fs::set_permissions(&temp_path, std::fs::Permissions::from_mode(0o600)).await?;
+    fs::rename(&temp_path, path).await?;
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    async fn read_config(path: &Path) -> anyhow::Result<Value> {
+        Ok(serde_json::from_str(&fs::read_to_string(path).await?)?)
+    }
+
+    #[tokio::test]
+    async fn test_export_credentials_creates_config() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join(".docker").join(DOCKER_CONFIG_FILENAME);
+
+        let auth = MsbRegistryAuth::Basic {
+            username: "alice".to_string(),
+            password: "s3cret".to_string(),
+        };
+        export_credentials_to(&config_path, "docker.io", &auth).await?;
+
+        let config = read_config(&config_path).await?;
+        assert_eq!(
+            config,
+            json!({ "auths": { "docker.io": { "auth": "YWxpY2U6czNjcmV0" } } })
+        );
+
+        let mode = std::fs::metadata(&config_path)?.permissions();
+        assert_eq!(
+            std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+            0o600
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_credentials_preserves_other_entries() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join(DOCKER_CONFIG_FILENAME);
+        fs::write(
+            &config_path,
+            json!({
+                "auths": {
+                    "ghcr.io": { "auth": "Ym9iOnB3" },
+                    "docker.io": { "auth": "b2xkOm9sZA==", "email": "alice@example.com" }
+                },
+                "credsStore": "desktop",
+                "psFormat": "table {{.ID}}"
+            })
+            .to_string(),
+        )
+        .await?;
+
+        let auth = MsbRegistryAuth::Token("token-123".to_string());
+        export_credentials_to(&config_path, "docker.io", &auth).await?;
+
+        let config = read_config(&config_path).await?;
+        assert_eq!(
+            config,
+            json!({
+                "auths": {
+                    "ghcr.io": { "auth": "Ym9iOnB3" },
+                    "docker.io": { "identitytoken": "token-123", "email": "alice@example.com" }
+                },
+                "credsStore": "desktop",
+                "psFormat": "table {{.ID}}"
+            })
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_export_credentials_rejects_invalid_config() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config_path = dir.path().join(DOCKER_CONFIG_FILENAME);
+        fs::write(&config_path, r#"{ "auths": [] }"#).await?;
+
+        let auth = MsbRegistryAuth::Token("token-123".to_string());
+        let result = export_credentials_to(&config_path, "docker.io", &auth).await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::InvalidDockerConfig(_))
+        ));
+
+        // The file is left untouched
+        assert_eq!(
+            fs::read_to_string(&config_path).await?,
+            r#"{ "auths": [] }"#
+        );
+
+        Ok(())
+    }
+}
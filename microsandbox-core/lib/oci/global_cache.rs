@@ -1,6 +1,11 @@
 use std::{path::PathBuf, str::FromStr, sync::Arc};
 
 use async_trait::async_trait;
+use microsandbox_utils::LAYER_LOCKS_SUBDIR;
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+};
 use oci_spec::image::Digest;
 use sqlx::{Pool, Sqlite};
 use tokio::fs;
@@ -23,6 +28,42 @@ pub trait GlobalCacheOps: Send + Sync {
     /// Returns the directory where extracted layers are stored.
     fn extracted_layers_dir(&self) -> &PathBuf;
 
+    /// Returns the directory where completed layer tar files are shared between pulls, if any.
+    ///
+    /// Layers are content-addressed by digest here, so an image sharing a layer with one that was
+    /// pulled before doesn't download it again.
+    fn blob_cache_dir(&self) -> Option<&PathBuf> {
+        None
+    }
+
+    /// Returns the directory the lock files of layers are kept in.
+    ///
+    /// This sits next to the extracted layers directory rather than inside it, so lock files never
+    /// show up among the layers.
+    fn layer_locks_dir(&self) -> PathBuf {
+        self.extracted_layers_dir()
+            .with_file_name(LAYER_LOCKS_SUBDIR)
+    }
+
+    /// Locks a layer against other processes using the same layers directory, waiting until the
+    /// lock is free.
+    ///
+    /// The layer directories are shared between `msb` processes, so downloading a layer into the
+    /// blob cache and extracting it take this lock to work on the layer one process at a time.
+    ///
+    /// ## Arguments
+    ///
+    /// * `digest` - The digest of the layer to lock
+    ///
+    /// ## Returns
+    ///
+    /// The lock, released when it is dropped
+    async fn lock_layer(&self, digest: &Digest) -> MicrosandboxResult<LayerLock> {
+        let locks_dir = self.layer_locks_dir();
+        fs::create_dir_all(&locks_dir).await?;
+        LayerLock::acquire(locks_dir.join(format!("{digest}.lock"))).await
+    }
+
     /// Get a layer ops by digest.
     ///
     /// # Arguments
@@ -68,6 +109,15 @@ pub trait GlobalCacheOps: Send + Sync {
     async fn all_layers_extracted(&self, image: &Reference) -> MicrosandboxResult<bool>;
 }
 
+/// An exclusive lock on a layer, held with `flock` on a lock file named by the layer's digest.
+///
+/// The lock is released when this is dropped, or when the process holding it exits, so a crashed
+/// process never leaves a layer locked.
+pub(crate) struct LayerLock {
+    /// The locked lock file
+    _file: Flock<std::fs::File>,
+}
+
 /// Abstraction around the global storage destinations. This includes:
 /// - The directory where layers are downloaded to
 /// - The directory where extracted layers are stored
@@ -80,6 +130,9 @@ pub(crate) struct GlobalCache {
     /// Directory where extracted layers are stored
     extracted_layers_dir: PathBuf,
 
+    /// Directory where completed layer tar files are shared between pulls
+    blob_cache_dir: Option<PathBuf>,
+
     /// Database pool for layer metadata
     db: Pool<Sqlite>,
 }
@@ -104,12 +157,23 @@ impl GlobalCache {
         let this = Self {
             tar_download_dir,
             extracted_layers_dir,
+            blob_cache_dir: None,
             db,
         };
         this.ensure_layers_dir().await?;
         Ok(this)
     }
 
+    /// Shares completed layer tar files between pulls through the given directory
+    ///
+    /// ## Arguments
+    ///
+    /// * `blob_cache_dir` - The directory where completed layer tar files are kept, by digest
+    pub fn with_blob_cache_dir(mut self, blob_cache_dir: PathBuf) -> Self {
+        self.blob_cache_dir = Some(blob_cache_dir);
+        self
+    }
+
     /// Create layers directory if it doesn't exist
     async fn ensure_layers_dir(&self) -> MicrosandboxResult<()> {
         fs::create_dir_all(&self.extracted_layers_dir).await?;
@@ -117,6 +181,34 @@ impl GlobalCache {
     }
 }
 
+impl LayerLock {
+    /// Takes the lock on the lock file at `path`, creating the file if it doesn't exist.
+    async fn acquire(path: PathBuf) -> MicrosandboxResult<Self> {
+        tokio::task::spawn_blocking(move || -> MicrosandboxResult<Self> {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)?;
+
+            let file = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+                Ok(file) => file,
+                Err((file, Errno::EWOULDBLOCK)) => {
+                    tracing::info!(
+                        lock_path = %path.display(),
+                        "waiting for another process to finish with the layer"
+                    );
+                    Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, errno)| errno)?
+                }
+                Err((_, errno)) => return Err(errno.into()),
+            };
+
+            Ok(Self { _file: file })
+        })
+        .await?
+    }
+}
+
 #[async_trait]
 impl GlobalCacheOps for GlobalCache {
     fn tar_download_dir(&self) -> &PathBuf {
@@ -127,13 +219,17 @@ impl GlobalCacheOps for GlobalCache {
         &self.extracted_layers_dir
     }
 
+    fn blob_cache_dir(&self) -> Option<&PathBuf> {
+        self.blob_cache_dir.as_ref()
+    }
+
     async fn build_layer(&self, digest: &Digest) -> Arc<dyn LayerOps> {
         Arc::new(Layer::new(Arc::new(self.clone()), digest.clone()))
     }
 
     async fn all_layers_extracted(&self, image: &Reference) -> MicrosandboxResult<bool> {
         // Check if the image exists in the database
-        match db::image_exists(&self.db, &image.to_string()).await {
+        match db::image_exists(&self.db, &image.as_db_key()).await {
             Ok(true) => {}
             Ok(false) => {
                 tracing::warn!(?image, "Image does not exist in db");
@@ -146,7 +242,7 @@ impl GlobalCacheOps for GlobalCache {
         }
 
         // Image exists, get all layer digests for this image
-        let layer_digests = match db::get_image_layer_digests(&self.db, &image.to_string()).await {
+        let layer_digests = match db::get_image_layer_digests(&self.db, &image.as_db_key()).await {
             Ok(layer_digests) => layer_digests,
             Err(err) => {
                 tracing::warn!(?err, ?image, "Error checking layer digests");
@@ -178,7 +274,7 @@ impl GlobalCacheOps for GlobalCache {
         }
 
         // Get the OCI config from database to verify database records exist for all digests
-        let Some(config) = db::get_image_config(&self.db, &image.to_string()).await? else {
+        let Some(config) = db::get_image_config(&self.db, &image.as_db_key()).await? else {
             tracing::warn!(?image, "Image config does not exist in db");
             return Ok(false);
         };
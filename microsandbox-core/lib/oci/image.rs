@@ -15,33 +15,57 @@
 //!     let layer_output_dir = Some(PathBuf::from("/custom/path"));
 //!
 //!     // Pull a single image from Docker registry
-//!     Image::pull("docker.io/library/ubuntu:latest".parse().unwrap(), layer_output_dir.clone()).await?;
+//!     Image::pull("docker.io/library/ubuntu:latest".parse().unwrap(), layer_output_dir.clone(), false, false).await?;
 //!
 //!     // Pull an image from the default registry (when no registry is specified in the reference)
-//!     Image::pull("nginx:latest".parse().unwrap(), layer_output_dir.clone()).await?;
+//!     Image::pull("nginx:latest".parse().unwrap(), layer_output_dir.clone(), false, false).await?;
 //!
 //!     // You can set the OCI_REGISTRY_DOMAIN environment variable to specify your default registry
 //!     unsafe { std::env::set_var("OCI_REGISTRY_DOMAIN", "docker.io") };
-//!     Image::pull("alpine:latest".parse().unwrap(), layer_output_dir.clone()).await?;
+//!     Image::pull("alpine:latest".parse().unwrap(), layer_output_dir.clone(), false, false).await?;
+//!
+//!     // Pull an image from a private registry; a first component with a `.`, a `:` or
+//!     // `localhost` is treated as the registry host
+//!     Image::pull("registry.internal:5000/team/app:1.0".parse().unwrap(), layer_output_dir.clone(), false, false).await?;
+//!
+//!     // Pull an image again, ignoring any layers that are already downloaded or extracted
+//!     Image::pull("alpine:latest".parse().unwrap(), layer_output_dir.clone(), true, false).await?;
+//!
+//!     // Pull an image and keep the temporary download directory around for debugging
+//!     Image::pull("alpine:latest".parse().unwrap(), layer_output_dir.clone(), false, true).await?;
 //!
 //!     // Pull an image from Docker registry and store the layers in a custom directory
-//!     Image::pull("docker.io/library/ubuntu:latest".parse().unwrap(), layer_output_dir).await?;
+//!     Image::pull("docker.io/library/ubuntu:latest".parse().unwrap(), layer_output_dir, false, false).await?;
 //!
 //!     Ok(())
 //! }
 //! ```
 use crate::{
     MicrosandboxResult,
-    management::db::{self},
-    oci::{GlobalCache, LayerDependencies, LayerOps, Reference, Registry},
+    management::{
+        db::{self},
+        image::write_layer_index,
+    },
+    oci::{
+        GlobalCache, ImageRewrites, LayerDependencies, LayerOps, PullSummary, Reference, Registry,
+        credential_store::{FileCredentialStore, RegistryCredentials},
+        timing,
+    },
 };
 use futures::future;
-#[cfg(feature = "cli")]
-use microsandbox_utils::term::{self};
-use microsandbox_utils::{LAYERS_SUBDIR, OCI_DB_FILENAME, env};
+use microsandbox_utils::{
+    BLOBS_SUBDIR, LAYERS_SUBDIR, OCI_DB_FILENAME, env, term::MultiItemProgress,
+};
 use oci_spec::image::{Digest, Os, Platform};
-use std::{path::PathBuf, sync::Arc};
-use tempfile::tempdir;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tempfile::TempDir;
+
+/// The prefix of temporary image download directories.
+const DOWNLOAD_DIR_PREFIX: &str = "msb-download-";
 
 /// A bundle of layers that are related (e.g., parent layers for a given layer)
 #[derive(Clone)]
@@ -51,6 +75,18 @@ pub struct Image {
     layers: Vec<Arc<dyn LayerOps>>,
 }
 
+/// A temporary directory image layers are downloaded to.
+///
+/// The directory and everything in it is removed when the guard is dropped, whether the pull
+/// succeeded or not, unless it was created to be kept for debugging.
+pub(crate) struct DownloadDir {
+    /// The directory, taken out when it is kept on drop.
+    dir: Option<TempDir>,
+
+    /// Whether to leave the directory in place when dropped.
+    keep: bool,
+}
+
 impl Image {
     /// Creates a new image bundle.
     ///
@@ -84,39 +120,34 @@ impl Image {
 
     /// Extracts all layers in the image.
     pub(crate) async fn extract_all(&self) -> MicrosandboxResult<()> {
-        #[cfg(feature = "cli")]
-        let extract_layers_sp = term::create_spinner(
-            "Extracting layers".to_string(),
-            None,
-            Some(self.layers.len() as u64),
-        );
+        let progress = MultiItemProgress::new("Extracting layers", cfg!(feature = "cli"));
 
         let extraction_futures = self.layers.iter().map(|layer| {
-            #[cfg(feature = "cli")]
-            let pb = extract_layers_sp.clone();
+            let progress = progress.clone();
 
             async move {
-                let parent_layers = self.get_layer_parent(layer.digest());
-                let result = layer.extract(parent_layers).await;
+                let parent_layers = self
+                    .get_layer_parent(layer.digest())
+                    .with_progress(progress);
+                let digest = layer.digest().to_string();
+                let span = timing::phase_span("extract", Some(&digest));
+                let result = timing::measure(span, layer.extract(parent_layers)).await;
                 if let Err(err) = &result {
                     tracing::error!(?err, "Extracting failed. Cleaning up extracted artifacts");
                     layer.cleanup_extracted().await?;
                 }
 
-                #[cfg(feature = "cli")]
-                pb.inc(1);
                 result
             }
         });
 
         // Wait for all extractions to complete
-        for result in future::join_all(extraction_futures).await {
+        let results = future::join_all(extraction_futures).await;
+        progress.finish();
+        for result in results {
             result?;
         }
 
-        #[cfg(feature = "cli")]
-        extract_layers_sp.finish();
-
         Ok(())
     }
 
@@ -126,34 +157,145 @@ impl Image {
     ///
     /// * `image` - The reference to the image to pull
     /// * `layer_extraction_dir` - The path to store the layer files.
-    ///   If None, the default layer output directory is used.
+    ///   If None, the default layer output directory is used. Otherwise a `microsandbox-index.json`
+    ///   mapping the image to its layers and configuration is written there too.
+    /// * `no_cache` - Whether to download and extract every layer again, even if it is cached
+    /// * `keep_download` - Whether to keep the temporary download directory instead of removing it
+    ///   once the pull is done. Download directories are created in the directory set by
+    ///   `OCI_DOWNLOAD_DIR`, or in the system temporary directory.
+    ///
+    /// The registry contacted can be changed with image rewrite rules, see [`ImageRewrites`].
     ///
+    /// ## Returns
+    ///
+    /// A summary of the pull, with the layers that were downloaded or found in the cache
     pub async fn pull(
         image: Reference,
         layer_extraction_dir: Option<PathBuf>,
-    ) -> MicrosandboxResult<()> {
-        let temp_download_dir = tempdir()?;
-        let temp_download_dir = temp_download_dir.path().to_path_buf();
-        tracing::info!(?temp_download_dir, "temporary download directory");
-
-        let microsandbox_home_path = env::get_microsandbox_home_path();
-        let db_path = microsandbox_home_path.join(OCI_DB_FILENAME);
-        let db = db::get_or_create_pool(&db_path, &db::OCI_DB_MIGRATOR).await?;
-        let layer_output_dir = layer_extraction_dir
-            .unwrap_or_else(|| env::get_microsandbox_home_path().join(LAYERS_SUBDIR));
-        let layer_cache = GlobalCache::new(temp_download_dir, layer_output_dir, db.clone()).await?;
-
-        // libkrun is based solely on Linux, so explicitly set the platform to Linux
-        let mut platform = Platform::default();
-        platform.set_os(Os::Linux);
-
-        Registry::new(db.clone(), platform, layer_cache)
-            .await?
-            .pull_image(&image)
-            .await
+        no_cache: bool,
+        keep_download: bool,
+    ) -> MicrosandboxResult<PullSummary> {
+        let image_rewrites = ImageRewrites::from_env()?;
+        let credentials = RegistryCredentials::load(&FileCredentialStore::from_env()).await?;
+        let download_dir = DownloadDir::new(env::get_oci_download_dir().as_deref(), keep_download)?;
+
+        pull_in_download_dir(download_dir, |temp_download_dir| async move {
+            let microsandbox_home_path = env::get_microsandbox_home_path();
+            let db_path = microsandbox_home_path.join(OCI_DB_FILENAME);
+            let db = db::get_or_create_pool(&db_path, &db::OCI_DB_MIGRATOR).await?;
+            let layer_output_dir = layer_extraction_dir
+                .clone()
+                .unwrap_or_else(|| env::get_microsandbox_home_path().join(LAYERS_SUBDIR));
+            let mut layer_cache =
+                GlobalCache::new(temp_download_dir, layer_output_dir, db.clone()).await?;
+            if env::is_oci_blob_cache_enabled() {
+                layer_cache =
+                    layer_cache.with_blob_cache_dir(microsandbox_home_path.join(BLOBS_SUBDIR));
+            }
+
+            // libkrun is based solely on Linux, so explicitly set the platform to Linux
+            let mut platform = Platform::default();
+            platform.set_os(Os::Linux);
+
+            let summary = Registry::new(db.clone(), platform, layer_cache)
+                .await?
+                .with_image_rewrites(image_rewrites)
+                .with_credentials(credentials)
+                .with_stream_layers(env::is_oci_stream_layers_enabled())
+                .pull_image(&image, no_cache)
+                .await?;
+
+            // A custom layer directory gets an index so it can be used without the database
+            if let Some(layer_extraction_dir) = &layer_extraction_dir {
+                write_layer_index(&db, layer_extraction_dir, &image).await?;
+            }
+
+            Ok(summary)
+        })
+        .await
+    }
+}
+
+impl DownloadDir {
+    /// Creates a new download directory.
+    ///
+    /// ## Arguments
+    ///
+    /// * `parent` - The directory to create the download directory in, created if it doesn't
+    ///   exist. If None, the system temporary directory is used.
+    /// * `keep` - Whether to leave the directory in place when the guard is dropped
+    pub(crate) fn new(parent: Option<&Path>, keep: bool) -> MicrosandboxResult<Self> {
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(DOWNLOAD_DIR_PREFIX);
+
+        let dir = match parent {
+            Some(parent) => {
+                std::fs::create_dir_all(parent)?;
+                builder.tempdir_in(parent)?
+            }
+            None => builder.tempdir()?,
+        };
+
+        tracing::info!(path = ?dir.path(), "temporary download directory");
+
+        Ok(Self {
+            dir: Some(dir),
+            keep,
+        })
+    }
+
+    /// Returns the path of the download directory.
+    pub(crate) fn path(&self) -> &Path {
+        self.dir
+            .as_ref()
+            .expect("download directory is only taken out on drop")
+            .path()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Drop for DownloadDir {
+    fn drop(&mut self) {
+        let Some(dir) = self.dir.take() else {
+            return;
+        };
+
+        if self.keep {
+            let path = dir.keep();
+            tracing::info!(?path, "keeping temporary download directory");
+        } else {
+            let path = dir.path().to_path_buf();
+            if let Err(e) = dir.close() {
+                tracing::warn!(?path, error = %e, "failed to remove temporary download directory");
+            }
+        }
     }
 }
 
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Runs a pull that downloads into `download_dir`, then removes the directory unless it is kept.
+///
+/// The directory is removed before returning, whether the pull succeeded or failed. A cancelled
+/// pull drops the guard as well, so the directory doesn't outlive it either way.
+async fn pull_in_download_dir<F, Fut, T>(
+    download_dir: DownloadDir,
+    pull: F,
+) -> MicrosandboxResult<T>
+where
+    F: FnOnce(PathBuf) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<T>>,
+{
+    let result = pull(download_dir.path().to_path_buf()).await;
+    drop(download_dir);
+    result
+}
+
 //--------------------------------------------------------------------------------------------------
 // Tests
 //--------------------------------------------------------------------------------------------------
@@ -192,8 +334,8 @@ mod tests {
         let extracted_dir = layers_dir.path().join("extracted");
 
         // Verify image exists in database after pulling
-        registry.pull_image(&image_ref).await?;
-        let image_exists = db::image_exists(&db, &image_ref.to_string()).await?;
+        registry.pull_image(&image_ref, false).await?;
+        let image_exists = db::image_exists(&db, &image_ref.as_db_key()).await?;
         assert!(image_exists, "Image should exist in database");
 
         // Verify layers directory exists and contains extracted layers
@@ -227,4 +369,53 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_download_dir_is_removed_after_pull() -> anyhow::Result<()> {
+        let parent = tempfile::tempdir()?;
+        let parent_path = parent.path().join("downloads");
+
+        // A mocked pull that downloads a layer and succeeds
+        let download_dir = DownloadDir::new(Some(&parent_path), false)?;
+        let download_path = download_dir.path().to_path_buf();
+        assert!(download_path.starts_with(&parent_path));
+
+        pull_in_download_dir(download_dir, |dir| async move {
+            fs::write(dir.join("layer.tar.gz"), b"layer").await?;
+            Ok(())
+        })
+        .await?;
+        assert!(!download_path.exists());
+
+        // A mocked pull that fails halfway through
+        let download_dir = DownloadDir::new(Some(&parent_path), false)?;
+        let download_path = download_dir.path().to_path_buf();
+
+        let result: MicrosandboxResult<()> = pull_in_download_dir(download_dir, |dir| async move {
+            fs::write(dir.join("layer.tar.gz"), b"lay").await?;
+            Err(crate::MicrosandboxError::ImageNotFound("app".to_string()))
+        })
+        .await;
+        assert!(result.is_err());
+        assert!(!download_path.exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_download_dir_is_kept_when_requested() -> anyhow::Result<()> {
+        let parent = tempfile::tempdir()?;
+
+        let download_dir = DownloadDir::new(Some(parent.path()), true)?;
+        let download_path = download_dir.path().to_path_buf();
+
+        pull_in_download_dir(download_dir, |dir| async move {
+            fs::write(dir.join("layer.tar.gz"), b"layer").await?;
+            Ok(())
+        })
+        .await?;
+        assert!(download_path.join("layer.tar.gz").exists());
+
+        Ok(())
+    }
 }
@@ -38,7 +38,7 @@ fn get_full_mode(entry_type: &tokio_tar::EntryType, permission_bits: u32) -> u32
 }
 
 /// Helper function to set xattr with stat information
-fn set_stat_xattr(
+pub(crate) fn set_stat_xattr(
     path: &Path,
     xattr_name: &CStr,
     uid: u64,
@@ -48,8 +48,12 @@ fn set_stat_xattr(
     use std::ffi::CString;
 
     let stat_data = format!("{}:{}:0{:o}", uid, gid, mode);
-    let path_cstring = CString::new(path.as_os_str().as_encoded_bytes())
-        .map_err(|e| MicrosandboxError::LayerExtraction(format!("Invalid path: {:?}", e)))?;
+    let path_cstring = CString::new(path.as_os_str().as_encoded_bytes()).map_err(|e| {
+        MicrosandboxError::LayerExtraction {
+            message: format!("Invalid path: {:?}", e),
+            source: Some(Box::new(e)),
+        }
+    })?;
 
     let result = unsafe {
         #[cfg(target_os = "macos")]
@@ -83,11 +87,10 @@ fn set_stat_xattr(
                 path.display()
             );
         } else {
-            return Err(MicrosandboxError::LayerExtraction(format!(
-                "Failed to set xattr on {}: {}",
-                path.display(),
-                errno
-            )));
+            return Err(MicrosandboxError::LayerExtraction {
+                message: format!("Failed to set xattr on {}: {}", path.display(), errno),
+                source: Some(Box::new(errno)),
+            });
         }
     }
     Ok(())
@@ -111,8 +114,19 @@ pub(crate) async fn extract_tar_with_ownership_override<R: AsyncRead + Unpin>(
 
     while let Some(entry) = entries.next().await {
         let entry = entry?;
-        let entry_path = entry.path()?.to_path_buf();
+        let raw_path = entry.path()?.to_path_buf();
+
+        // Skip entries that would be written outside the extraction directory, either directly
+        // or through a symlink extracted earlier
+        let Some(entry_path) = contained_path(&raw_path) else {
+            tracing::warn!(path = %raw_path.display(), "Skipping entry escaping the layer");
+            continue;
+        };
         let dst_path = extract_dir.join(&entry_path);
+        if has_symlink_ancestor(extract_dir, &dst_path) {
+            tracing::warn!(path = %raw_path.display(), "Skipping entry below a symlink");
+            continue;
+        }
 
         // Get the original metadata from the tar entry
         let original_uid = entry.header().uid()?;
@@ -130,9 +144,18 @@ pub(crate) async fn extract_tar_with_ownership_override<R: AsyncRead + Unpin>(
         // Handle hard links separately - collect them for processing after all files are extracted
         if is_hard_link {
             if let Ok(Some(link_name)) = entry.link_name() {
+                let Some(target_path) = contained_path(&link_name) else {
+                    tracing::warn!(
+                        path = %raw_path.display(),
+                        target = %link_name.display(),
+                        "Skipping hard link to a target outside the layer"
+                    );
+                    continue;
+                };
+
                 hard_links.push(HardLink {
                     link_path: dst_path.clone(),
-                    target_path: extract_dir.join(link_name.as_ref()),
+                    target_path: extract_dir.join(target_path),
                     uid: original_uid,
                     gid: original_gid,
                     mode: original_mode,
@@ -206,10 +229,53 @@ pub(crate) async fn extract_tar_with_ownership_override<R: AsyncRead + Unpin>(
         );
     }
 
-    hard_links.extract(&xattr_name).await?;
+    hard_links.extract(extract_dir, &xattr_name).await?;
     Ok(())
 }
 
+/// Normalizes the path of a tar entry relative to the extraction directory.
+///
+/// `.` components are dropped and `..` components are resolved lexically. A leading `/` is
+/// stripped like `tar` does, so absolute paths end up inside the extraction directory too.
+///
+/// ## Returns
+///
+/// The normalized path, or `None` if a `..` component climbs out of the extraction directory
+fn contained_path(path: &Path) -> Option<PathBuf> {
+    let mut contained = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => contained.push(part),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => {
+                if !contained.pop() {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(contained)
+}
+
+/// Returns whether writing to `dst_path` would go through a symlink below `extract_dir`.
+///
+/// A layer can contain a symlink pointing anywhere on the host followed by an entry below it, so
+/// entries are only written when every directory between `extract_dir` and them is a real one.
+fn has_symlink_ancestor(extract_dir: &Path, dst_path: &Path) -> bool {
+    let Ok(relative_path) = dst_path.strip_prefix(extract_dir) else {
+        return true;
+    };
+
+    let mut ancestor = extract_dir.to_path_buf();
+    relative_path.parent().is_some_and(|parent| {
+        parent.components().any(|component| {
+            ancestor.push(component);
+            std::fs::symlink_metadata(&ancestor).is_ok_and(|metadata| metadata.is_symlink())
+        })
+    })
+}
+
 /// Unpacks a tar entry into a destination path, copying ancestor directories from parent layers if needed
 ///
 /// ## Arguments
@@ -267,9 +333,10 @@ async fn unpack<R: AsyncRead + Unpin>(
 
     // Try to unpack the entry again after creating the ancestor directories
     if let Err(err) = entry.unpack(&dst_path).await {
-        return Err(MicrosandboxError::LayerExtraction(format!(
-            "layer extraction failed after retry: {err}",
-        )));
+        return Err(MicrosandboxError::LayerExtraction {
+            message: format!("layer extraction failed after retry: {err}"),
+            source: Some(Box::new(err)),
+        });
     }
 
     Ok(())
@@ -288,10 +355,13 @@ async fn create_and_copy_dir_attr(template_dir: &Path, dest_dir: &Path) -> Micro
     }
 
     if !template_dir.is_dir() {
-        return Err(MicrosandboxError::LayerExtraction(format!(
-            "Source directory is not a directory or does not exist: {}",
-            template_dir.display()
-        )));
+        return Err(MicrosandboxError::LayerExtraction {
+            message: format!(
+                "Source directory is not a directory or does not exist: {}",
+                template_dir.display()
+            ),
+            source: None,
+        });
     }
 
     // Create new directory, and copy over permissions and xattrs from template directory
@@ -335,9 +405,21 @@ impl HardLinkVec {
         self.hard_links.push(link);
     }
 
-    async fn extract(&self, xattr_name: &CStr) -> MicrosandboxResult<()> {
+    async fn extract(&self, extract_dir: &Path, xattr_name: &CStr) -> MicrosandboxResult<()> {
         // Second pass: process hard links after all regular files are extracted
         for link_info in &self.hard_links {
+            // Symlinks extracted after the link was collected could redirect either end of it
+            if has_symlink_ancestor(extract_dir, &link_info.link_path)
+                || has_symlink_ancestor(extract_dir, &link_info.target_path)
+            {
+                tracing::warn!(
+                    "Skipping hard link {} -> {} through a symlink",
+                    link_info.link_path.display(),
+                    link_info.target_path.display()
+                );
+                continue;
+            }
+
             // Create the hard link
             match std::fs::hard_link(&link_info.target_path, &link_info.link_path) {
                 Ok(_) => {
@@ -423,6 +505,8 @@ mod tests {
     use super::*;
     use crate::oci::{Image, LayerDependencies, LayerOps, global_cache::GlobalCacheOps};
     use async_trait::async_trait;
+    use bytes::Bytes;
+    use futures::stream::BoxStream;
     use oci_spec::image::Digest;
     use std::{io::Cursor, os::unix::fs::PermissionsExt, str::FromStr, sync::Arc};
     use tempfile::TempDir;
@@ -503,6 +587,15 @@ mod tests {
             Ok(())
         }
 
+        async fn extract_from_stream(
+            &self,
+            _stream: BoxStream<'static, crate::MicrosandboxResult<Bytes>>,
+            _total_bytes: u64,
+            _parent: LayerDependencies,
+        ) -> crate::MicrosandboxResult<()> {
+            Ok(())
+        }
+
         async fn find_dir(&self, path_in_tar: &Path) -> Option<PathBuf> {
             let canonical_path = self.extracted_dir.join(path_in_tar);
             if canonical_path.exists() && canonical_path.is_dir() {
@@ -639,4 +732,203 @@ mod tests {
             assert_eq!(attr, dir.as_bytes(), "xattr value mismatch on '{dir}'");
         }
     }
+
+    /// Appends an entry to a tar archive with its path and link name written as they are, since
+    /// `tar::Header::set_path` refuses the escaping paths these tests need.
+    fn append_raw_entry(
+        builder: &mut tar::Builder<Vec<u8>>,
+        entry_type: tar::EntryType,
+        path: &str,
+        link_name: Option<&str>,
+        contents: &[u8],
+    ) {
+        let mut header = tar::Header::new_gnu();
+        let gnu = header.as_gnu_mut().unwrap();
+        gnu.name[..path.len()].copy_from_slice(path.as_bytes());
+        if let Some(link_name) = link_name {
+            gnu.linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+        }
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o755);
+        header.set_entry_type(entry_type);
+        header.set_cksum();
+        builder.append(&header, contents).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_extract_keeps_escaping_entries_inside_extract_dir() {
+        let temp = TempDir::new().unwrap();
+        let extract_dir = temp.path().join("layers/extracted");
+        let outside_dir = temp.path().join("outside");
+        std::fs::create_dir_all(&extract_dir).unwrap();
+        std::fs::create_dir_all(&outside_dir).unwrap();
+        std::fs::write(outside_dir.join("secret"), "host secret").unwrap();
+
+        let absolute_path = format!("{}/absolute.txt", outside_dir.display());
+        let outside_secret = format!("{}/secret", outside_dir.display());
+
+        let mut builder = tar::Builder::new(Vec::new());
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Regular,
+            "../escape.txt",
+            None,
+            b"escaped",
+        );
+        for ancestor in outside_dir
+            .ancestors()
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+        {
+            append_raw_entry(
+                &mut builder,
+                tar::EntryType::Directory,
+                &format!("{}/", ancestor.display()),
+                None,
+                b"",
+            );
+        }
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Regular,
+            &absolute_path,
+            None,
+            b"absolute",
+        );
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Symlink,
+            "link",
+            Some(outside_dir.to_str().unwrap()),
+            b"",
+        );
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Regular,
+            "link/through-symlink.txt",
+            None,
+            b"through symlink",
+        );
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Link,
+            "hardlink",
+            Some("../../outside/secret"),
+            b"",
+        );
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Link,
+            "hardlink-via-symlink",
+            Some("link/secret"),
+            b"",
+        );
+        append_raw_entry(
+            &mut builder,
+            tar::EntryType::Regular,
+            "kept.txt",
+            None,
+            b"kept",
+        );
+        let mut archive = Archive::new(Cursor::new(builder.into_inner().unwrap()));
+
+        let parent_layers = LayerDependencies::new(
+            Digest::from_str(
+                "sha256:eeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeeee",
+            )
+            .unwrap(),
+            Image::new(vec![]),
+        );
+        extract_tar_with_ownership_override(&mut archive, &extract_dir, parent_layers)
+            .await
+            .expect("escaping entries should be skipped, not fail the extraction");
+
+        // Nothing was written outside the extraction directory
+        assert!(!temp.path().join("layers/escape.txt").exists());
+        assert!(!outside_dir.join("absolute.txt").exists());
+        assert!(!outside_dir.join("through-symlink.txt").exists());
+        assert_eq!(
+            std::fs::read_dir(&outside_dir).unwrap().count(),
+            1,
+            "only the original file should be in the outside directory"
+        );
+
+        // The absolute entry was kept relative to the extraction directory instead
+        let contained_absolute = extract_dir.join(absolute_path.trim_start_matches('/'));
+        assert_eq!(
+            std::fs::read_to_string(contained_absolute).unwrap(),
+            "absolute"
+        );
+        assert!(
+            !extract_dir
+                .join(outside_secret.trim_start_matches('/'))
+                .exists()
+        );
+
+        // Hard links to files outside the extraction directory were not created
+        assert!(!extract_dir.join("hardlink").exists());
+        assert!(!extract_dir.join("hardlink-via-symlink").exists());
+        assert_eq!(
+            std::fs::read_to_string(extract_dir.join("kept.txt")).unwrap(),
+            "kept"
+        );
+    }
+
+    #[test]
+    fn test_contained_path_normalizes_entry_paths() {
+        assert_eq!(
+            contained_path(Path::new("./usr/../etc/hosts")),
+            Some(PathBuf::from("etc/hosts"))
+        );
+        assert_eq!(
+            contained_path(Path::new("/etc/passwd")),
+            Some(PathBuf::from("etc/passwd"))
+        );
+        assert_eq!(contained_path(Path::new("../escape")), None);
+        assert_eq!(contained_path(Path::new("usr/../../escape")), None);
+    }
+
+    #[tokio::test]
+    async fn test_layer_extraction_failure_keeps_root_cause() {
+        let temp = TempDir::new().unwrap();
+        let digest = Digest::from_str(
+            "sha256:dddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddd",
+        )
+        .unwrap();
+        let global_ops: Arc<dyn GlobalCacheOps> = Arc::new(MockGlobalCacheOps {
+            tar_dir: temp.path().join("tar"),
+            extracted_dir: temp.path().join("extracted"),
+        });
+        let layer = crate::oci::Layer::new(global_ops, digest.clone());
+
+        // A layer blob that is not gzip-compressed fails as soon as it is read
+        std::fs::create_dir_all(temp.path().join("tar")).unwrap();
+        std::fs::write(layer.tar_path(), b"definitely not a gzip stream").unwrap();
+
+        let error = layer
+            .extract(LayerDependencies::new(digest.clone(), Image::new(vec![])))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, MicrosandboxError::LayerExtraction { .. }));
+        assert!(error.to_string().starts_with("layer extraction error: "));
+
+        // The layer is named by the first source rather than in the message
+        let context = std::error::Error::source(&error).unwrap();
+        assert_eq!(
+            context.to_string(),
+            format!("failed to extract layer {digest}")
+        );
+        assert!(!error.to_string().contains("failed to extract layer"));
+
+        // Walk the chain down to the error that caused the failure
+        let mut root: &dyn std::error::Error = &error;
+        while let Some(source) = root.source() {
+            root = source;
+        }
+        assert!(
+            root.downcast_ref::<std::io::Error>().is_some(),
+            "root cause should be the io error from decoding the blob, got: {root}"
+        );
+    }
 }
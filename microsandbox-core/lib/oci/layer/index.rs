@@ -0,0 +1,487 @@
+//! Seekable index of the entries in an uncompressed layer tar.
+//!
+//! Gzip streams can't be seeked, so only uncompressed layer tars can be indexed. The index is
+//! built once, cached next to the layer tar, and lets a single entry be extracted by reading just
+//! its data instead of scanning the whole archive.
+
+use std::{
+    ffi::CString,
+    io::{ErrorKind, SeekFrom},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use microsandbox_utils::SeekableReader;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+
+use crate::{MicrosandboxError, MicrosandboxResult, oci::extraction::set_stat_xattr};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The extension of cached tar indexes, replacing the `tar` extension of the layer tar.
+const TAR_INDEX_EXTENSION: &str = "tar.index";
+
+/// The offset of the `ustar` magic in a tar header.
+const USTAR_MAGIC_OFFSET: usize = 257;
+
+/// The magic that marks a POSIX or GNU tar header.
+const USTAR_MAGIC: &[u8] = b"ustar";
+
+/// The xattr the original ownership and mode of extracted entries are stored in.
+const STAT_OVERRIDE_XATTR: &str = "user.containers.override_stat";
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// The kind of an indexed tar entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum TarEntryKind {
+    /// A regular file.
+    File,
+
+    /// A directory.
+    Directory,
+
+    /// A symbolic link.
+    Symlink,
+
+    /// A hard link to an earlier entry.
+    HardLink,
+
+    /// Any other entry, e.g. a device node or fifo.
+    Other,
+}
+
+/// The location and metadata of a single entry in a tar archive.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TarIndexEntry {
+    /// The path of the entry, without leading `./` or `/`.
+    pub(crate) path: PathBuf,
+
+    /// The kind of the entry.
+    pub(crate) kind: TarEntryKind,
+
+    /// The offset of the entry's data from the start of the archive.
+    pub(crate) data_offset: u64,
+
+    /// The size of the entry's data in bytes.
+    pub(crate) size: u64,
+
+    /// The permission bits of the entry.
+    pub(crate) mode: u32,
+
+    /// The owner user ID of the entry.
+    pub(crate) uid: u64,
+
+    /// The owner group ID of the entry.
+    pub(crate) gid: u64,
+
+    /// The target of a symbolic or hard link.
+    pub(crate) link_name: Option<PathBuf>,
+}
+
+/// An index of the entries in an uncompressed tar archive.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TarIndex {
+    /// The entries in archive order.
+    entries: Vec<TarIndexEntry>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl TarIndex {
+    /// Builds the index of an uncompressed tar, seeking past the data of each entry.
+    pub(crate) fn build(tar_path: &Path) -> MicrosandboxResult<Self> {
+        let mut archive = tar::Archive::new(std::fs::File::open(tar_path)?);
+
+        let mut entries = Vec::new();
+        for entry in archive.entries_with_seek()? {
+            let entry = entry?;
+            let header = entry.header();
+            let entry_type = header.entry_type();
+            let kind = if entry_type.is_file() {
+                TarEntryKind::File
+            } else if entry_type.is_dir() {
+                TarEntryKind::Directory
+            } else if entry_type.is_symlink() {
+                TarEntryKind::Symlink
+            } else if entry_type.is_hard_link() {
+                TarEntryKind::HardLink
+            } else {
+                TarEntryKind::Other
+            };
+
+            entries.push(TarIndexEntry {
+                path: normalize_path(&entry.path()?),
+                kind,
+                data_offset: entry.raw_file_position(),
+                size: entry.size(),
+                mode: header.mode()?,
+                uid: header.uid()?,
+                gid: header.gid()?,
+                link_name: entry.link_name()?.map(|name| normalize_path(&name)),
+            });
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Loads the cached index of a layer tar, building and caching it first if needed.
+    ///
+    /// An unreadable cached index is rebuilt rather than treated as an error.
+    pub(crate) async fn load_or_build(tar_path: &Path) -> MicrosandboxResult<Self> {
+        let index_path = get_index_path(tar_path);
+        match fs::read(&index_path).await {
+            Ok(bytes) => match serde_json::from_slice(&bytes) {
+                Ok(index) => return Ok(index),
+                Err(e) => {
+                    tracing::warn!(index_path = %index_path.display(), "rebuilding unreadable tar index: {e}");
+                }
+            },
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let build_path = tar_path.to_path_buf();
+        let index = tokio::task::spawn_blocking(move || Self::build(&build_path)).await??;
+
+        // Write under a temporary name first so a partial index is never loaded
+        let partial_path = index_path.with_extension("index.partial");
+        fs::write(&partial_path, serde_json::to_vec(&index)?).await?;
+        fs::rename(&partial_path, &index_path).await?;
+
+        tracing::debug!(index_path = %index_path.display(), entries = index.entries.len(), "built tar index");
+        Ok(index)
+    }
+
+    /// Finds the entry at `path`.
+    ///
+    /// When the archive holds the same path more than once the last entry wins, as it would when
+    /// extracting the whole archive.
+    pub(crate) fn find(&self, path: &Path) -> Option<&TarIndexEntry> {
+        let path = normalize_path(path);
+        self.entries.iter().rev().find(|entry| entry.path == path)
+    }
+
+    /// Extracts the entry at `path` into `dest_dir`, reading only that entry's data.
+    ///
+    /// Directories are created without their contents, and hard links are extracted as a copy of
+    /// the file they link to. Like a full layer extraction, the owner keeps read and write access
+    /// and the original ownership and mode are stored in the stat override xattr.
+    ///
+    /// ## Arguments
+    ///
+    /// * `reader` - A seekable reader over the uncompressed tar the index was built from
+    /// * `path` - The path of the entry in the archive
+    /// * `dest_dir` - The directory to extract the entry under
+    ///
+    /// ## Returns
+    ///
+    /// The path of the extracted entry, or None if the archive has no entry at `path`
+    pub(crate) async fn extract_path<R>(
+        &self,
+        reader: &mut R,
+        path: &Path,
+        dest_dir: &Path,
+    ) -> MicrosandboxResult<Option<PathBuf>>
+    where
+        R: SeekableReader + Unpin,
+    {
+        let Some(entry) = self.find(path) else {
+            return Ok(None);
+        };
+
+        if entry
+            .path
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_)))
+        {
+            return Err(MicrosandboxError::LayerExtraction {
+                message: format!(
+                    "refusing to extract {} outside the destination",
+                    entry.path.display()
+                ),
+                source: None,
+            });
+        }
+
+        let dst_path = dest_dir.join(&entry.path);
+        if let Some(parent) = dst_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let stat_entry =
+            match entry.kind {
+                TarEntryKind::Directory => {
+                    fs::create_dir_all(&dst_path).await?;
+                    entry
+                }
+                TarEntryKind::File | TarEntryKind::HardLink => {
+                    let data_entry = if entry.kind == TarEntryKind::HardLink {
+                        entry
+                            .link_name
+                            .as_deref()
+                            .and_then(|target| self.find(target))
+                            .ok_or_else(|| MicrosandboxError::LayerExtraction {
+                                message: format!(
+                                    "hard link target of {} not found in archive",
+                                    entry.path.display()
+                                ),
+                                source: None,
+                            })?
+                    } else {
+                        entry
+                    };
+
+                    reader.seek(SeekFrom::Start(data_entry.data_offset)).await?;
+                    let mut file = fs::File::create(&dst_path).await?;
+                    tokio::io::copy(&mut (&mut *reader).take(data_entry.size), &mut file).await?;
+                    data_entry
+                }
+                TarEntryKind::Symlink => {
+                    let target = entry.link_name.as_ref().ok_or_else(|| {
+                        MicrosandboxError::LayerExtraction {
+                            message: format!("symlink {} has no target", entry.path.display()),
+                            source: None,
+                        }
+                    })?;
+
+                    if fs::symlink_metadata(&dst_path).await.is_ok() {
+                        fs::remove_file(&dst_path).await?;
+                    }
+                    fs::symlink(target, &dst_path).await?;
+                    return Ok(Some(dst_path));
+                }
+                TarEntryKind::Other => {
+                    return Err(MicrosandboxError::LayerExtraction {
+                        message: format!(
+                            "cannot extract special file {} individually",
+                            entry.path.display()
+                        ),
+                        source: None,
+                    });
+                }
+            };
+
+        let (file_type_bits, owner_bits) = if stat_entry.kind == TarEntryKind::Directory {
+            (libc::S_IFDIR as u32, 0o700)
+        } else {
+            (libc::S_IFREG as u32, 0o600)
+        };
+
+        let permission_bits = stat_entry.mode & 0o7777;
+        fs::set_permissions(
+            &dst_path,
+            std::fs::Permissions::from_mode(permission_bits | owner_bits),
+        )
+        .await?;
+
+        let xattr_name = CString::new(STAT_OVERRIDE_XATTR)
+            .map_err(|e| anyhow::anyhow!("Invalid attr name: {e:?}"))?;
+        set_stat_xattr(
+            &dst_path,
+            &xattr_name,
+            stat_entry.uid,
+            stat_entry.gid,
+            file_type_bits | permission_bits,
+        )?;
+
+        Ok(Some(dst_path))
+    }
+}
+
+impl TarIndexEntry {
+    /// Whether the entry is a directory.
+    pub(crate) fn is_dir(&self) -> bool {
+        self.kind == TarEntryKind::Directory
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Returns the path the index of a layer tar is cached at.
+pub(crate) fn get_index_path(tar_path: &Path) -> PathBuf {
+    tar_path.with_extension(TAR_INDEX_EXTENSION)
+}
+
+/// Checks whether a layer tar is an uncompressed tar archive, and so can be indexed.
+///
+/// Missing files and anything without a `ustar` header, such as gzip compressed layers, can't be
+/// indexed.
+pub(crate) async fn is_indexable_tar(tar_path: &Path) -> MicrosandboxResult<bool> {
+    let mut file = match fs::File::open(tar_path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut header = [0u8; USTAR_MAGIC_OFFSET + USTAR_MAGIC.len()];
+    match file.read_exact(&mut header).await {
+        Ok(_) => Ok(&header[USTAR_MAGIC_OFFSET..] == USTAR_MAGIC),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Strips the `./` and `/` prefixes and trailing slashes tar entry paths may carry.
+fn normalize_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir | Component::RootDir))
+        .collect()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn append(
+        builder: &mut tar::Builder<std::fs::File>,
+        path: &str,
+        entry_type: tar::EntryType,
+        mode: u32,
+        data: &[u8],
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_uid(1000);
+        header.set_gid(1000);
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append(&header, data).unwrap();
+    }
+
+    fn build_test_tar(tar_path: &Path) {
+        let mut builder = tar::Builder::new(std::fs::File::create(tar_path).unwrap());
+        append(
+            &mut builder,
+            "./etc/",
+            tar::EntryType::Directory,
+            0o750,
+            b"",
+        );
+        append(
+            &mut builder,
+            "./etc/app/",
+            tar::EntryType::Directory,
+            0o755,
+            b"",
+        );
+        append(
+            &mut builder,
+            "./etc/app/config",
+            tar::EntryType::Regular,
+            0o640,
+            b"key=value\n",
+        );
+        append(
+            &mut builder,
+            "./var/log/big",
+            tar::EntryType::Regular,
+            0o644,
+            &vec![b'x'; 64 * 1024],
+        );
+        builder.into_inner().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tar_index_extract_single_entry() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let tar_path = temp_dir.path().join("layer.tar");
+        build_test_tar(&tar_path);
+
+        assert!(is_indexable_tar(&tar_path).await?);
+        let index = TarIndex::load_or_build(&tar_path).await?;
+
+        // The index is cached next to the layer tar and reused
+        assert!(get_index_path(&tar_path).exists());
+        assert_eq!(TarIndex::load_or_build(&tar_path).await?, index);
+
+        let config = index.find(Path::new("etc/app/config")).unwrap();
+        assert_eq!(config.kind, TarEntryKind::File);
+        assert_eq!(config.size, 10);
+        assert!(index.find(Path::new("/etc/")).unwrap().is_dir());
+
+        // Only the requested entry is extracted
+        let dest_dir = temp_dir.path().join("dest");
+        let mut reader = fs::File::open(&tar_path).await?;
+        let extracted = index
+            .extract_path(&mut reader, Path::new("etc/app/config"), &dest_dir)
+            .await?
+            .unwrap();
+
+        assert_eq!(extracted, dest_dir.join("etc/app/config"));
+        assert_eq!(fs::read_to_string(&extracted).await?, "key=value\n");
+        assert_eq!(
+            fs::metadata(&extracted).await?.permissions().mode() & 0o7777,
+            0o640
+        );
+        assert!(!dest_dir.join("var").exists());
+
+        // Directories are extracted without their contents
+        let dir_dest = temp_dir.path().join("dir_dest");
+        let extracted = index
+            .extract_path(&mut reader, Path::new("./etc"), &dir_dest)
+            .await?
+            .unwrap();
+        assert!(extracted.is_dir());
+        assert_eq!(
+            fs::metadata(&extracted).await?.permissions().mode() & 0o7777,
+            0o750
+        );
+        assert!(!extracted.join("app").exists());
+
+        assert!(
+            index
+                .extract_path(&mut reader, Path::new("missing"), &dest_dir)
+                .await?
+                .is_none()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tar_index_gzip_layer_not_indexable() -> anyhow::Result<()> {
+        use std::io::Write;
+
+        let temp_dir = tempdir()?;
+        let tar_path = temp_dir.path().join("layer.tar");
+        build_test_tar(&tar_path);
+
+        let gz_path = temp_dir.path().join("layer.tar.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&gz_path)?,
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&std::fs::read(&tar_path)?)?;
+        encoder.finish()?;
+
+        assert!(!is_indexable_tar(&gz_path).await?);
+        assert!(!is_indexable_tar(&temp_dir.path().join("missing.tar")).await?);
+
+        Ok(())
+    }
+}
@@ -1,28 +1,42 @@
 pub(crate) mod extraction;
+pub(crate) mod index;
 mod progress;
 
 use std::{
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
     sync::Arc,
 };
 
 use async_compression::tokio::bufread::GzipDecoder;
 use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt, stream::BoxStream};
 
-use microsandbox_utils::EXTRACTED_LAYER_SUFFIX;
+use microsandbox_utils::{
+    EXTRACTED_LAYER_SUFFIX, EXTRACTION_COMPLETE_SUFFIX, INDEXED_LAYER_SUFFIX,
+    term::MultiItemProgress,
+};
 use oci_spec::image::Digest;
+use tempfile::TempDir;
 use tokio::{
     fs,
-    io::BufReader,
+    io::{AsyncRead, BufReader},
     sync::{Mutex, OwnedMutexGuard},
 };
 use tokio_tar::Archive;
+use tokio_util::io::StreamReader;
 
+use self::progress::ProgressReader;
 use crate::{
     MicrosandboxError, MicrosandboxResult,
     oci::{
-        extraction::extract_tar_with_ownership_override, global_cache::GlobalCacheOps, image::Image,
+        extraction::extract_tar_with_ownership_override,
+        global_cache::{GlobalCacheOps, LayerLock},
+        image::Image,
+        index::{TarIndex, is_indexable_tar},
     },
+    utils::{self, StreamHasher},
 };
 
 #[async_trait]
@@ -32,14 +46,54 @@ pub(crate) trait LayerOps: Send + Sync {
     /// Get the digest of the layer.
     fn digest(&self) -> &Digest;
 
-    /// Get the path to the layer tar file.
-    fn tar_path(&self) -> PathBuf {
+    /// Get the path the layer tar file is downloaded to.
+    fn download_path(&self) -> PathBuf {
         self.global_layer_ops()
             .tar_download_dir()
             .join(self.digest().to_string())
             .with_extension("tar")
     }
 
+    /// Get the path to the layer tar file.
+    ///
+    /// When a blob cache is configured this is the shared copy in the cache, otherwise it is the
+    /// download path.
+    fn tar_path(&self) -> PathBuf {
+        match self.global_layer_ops().blob_cache_dir() {
+            Some(blob_cache_dir) => blob_cache_dir
+                .join(self.digest().to_string())
+                .with_extension("tar"),
+            None => self.download_path(),
+        }
+    }
+
+    /// Moves a completed download into the blob cache, if one is configured.
+    ///
+    /// The file is renamed when possible, and copied when the download directory and the blob
+    /// cache are on different filesystems.
+    async fn store_download(&self) -> MicrosandboxResult<()> {
+        let download_path = self.download_path();
+        let tar_path = self.tar_path();
+        if download_path == tar_path {
+            return Ok(());
+        }
+
+        if let Some(parent) = tar_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // Write under a temporary name first so the cache never holds a partial blob
+        let partial_path = tar_path.with_extension("tar.partial");
+        if fs::rename(&download_path, &partial_path).await.is_err() {
+            fs::copy(&download_path, &partial_path).await?;
+            fs::remove_file(&download_path).await?;
+        }
+        fs::rename(&partial_path, &tar_path).await?;
+
+        tracing::debug!(digest = %self.digest(), tar_path = %tar_path.display(), "stored layer in blob cache");
+        Ok(())
+    }
+
     /// Gets the size of the layer tar file.
     ///
     /// ## Returns
@@ -60,6 +114,32 @@ pub(crate) trait LayerOps: Send + Sync {
         Some(len)
     }
 
+    /// Checks whether the layer tar file is complete, i.e. it has the expected size and its
+    /// content matches the layer's digest.
+    ///
+    /// ## Arguments
+    ///
+    /// * `expected_size` - The size of the layer tar file in bytes
+    ///
+    /// ## Returns
+    ///
+    /// Whether the layer tar file can be used as is, which is false if it does not exist.
+    async fn tar_verified(&self, expected_size: u64) -> MicrosandboxResult<bool> {
+        if self.get_tar_size() != Some(expected_size) {
+            return Ok(false);
+        }
+
+        let digest = self.digest();
+        let actual_hash =
+            hex::encode(utils::get_file_hash(&self.tar_path(), digest.algorithm()).await?);
+        if actual_hash != digest.digest() {
+            tracing::warn!(%digest, actual_hash, "layer tar does not match its digest");
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
     /// The directory the layer will be extracted to.
     ///
     /// This follows after the format of `<layer-name>.extracted`.
@@ -70,7 +150,58 @@ pub(crate) trait LayerOps: Send + Sync {
             .join(format!("{}.{}", file_name, EXTRACTED_LAYER_SUFFIX))
     }
 
-    /// Checks if the layer has been extracted.
+    /// The marker file written once the layer has been fully extracted.
+    ///
+    /// This follows after the format of `<layer-name>.extracted.complete`, next to the extracted
+    /// layer directory so it never shows up in the layer's filesystem.
+    fn extraction_marker_path(&self) -> PathBuf {
+        let file_name = self.digest().to_string();
+        self.global_layer_ops().extracted_layers_dir().join(format!(
+            "{}.{}.{}",
+            file_name, EXTRACTED_LAYER_SUFFIX, EXTRACTION_COMPLETE_SUFFIX
+        ))
+    }
+
+    /// Checks whether the completion marker for the layer was written.
+    ///
+    /// The marker holds the digest of the layer, so a marker that is empty or names another
+    /// layer, e.g. because it was cut short, does not count.
+    async fn extraction_complete(&self) -> MicrosandboxResult<bool> {
+        match fs::read_to_string(self.extraction_marker_path()).await {
+            Ok(contents) => Ok(contents.trim() == self.digest().to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The directory entries extracted individually through the layer's tar index are placed in.
+    ///
+    /// This follows after the format of `<layer-name>.indexed`.
+    fn indexed_layer_dir(&self) -> PathBuf {
+        let file_name = self.digest().to_string();
+        self.global_layer_ops()
+            .extracted_layers_dir()
+            .join(format!("{}.{}", file_name, INDEXED_LAYER_SUFFIX))
+    }
+
+    /// Loads the seekable index of the layer tar, building it on first use.
+    ///
+    /// ## Returns
+    ///
+    /// The index, or None if the layer tar is compressed or missing and so can't be indexed.
+    async fn tar_index(&self) -> MicrosandboxResult<Option<TarIndex>> {
+        let tar_path = self.tar_path();
+        if !is_indexable_tar(&tar_path).await? {
+            return Ok(None);
+        }
+
+        Ok(Some(TarIndex::load_or_build(&tar_path).await?))
+    }
+
+    /// Checks if the layer has been fully extracted.
+    ///
+    /// A layer directory without a completion marker is left over from an interrupted extraction
+    /// and does not count as extracted.
     async fn extracted(&self) -> MicrosandboxResult<(bool, OwnedMutexGuard<()>)>;
 
     /// Cleans up the extracted layer directory if it exists.
@@ -79,6 +210,24 @@ pub(crate) trait LayerOps: Send + Sync {
     /// Extracts the layer.
     async fn extract(&self, parent: LayerDependencies) -> MicrosandboxResult<()>;
 
+    /// Extracts the layer straight from the stream of its compressed tar, without writing the tar
+    /// to disk first.
+    ///
+    /// The stream is hashed as it is extracted, and the extracted layer is only moved into place
+    /// if the hash matches the layer's digest.
+    ///
+    /// ## Arguments
+    ///
+    /// * `stream` - The compressed tar of the layer, e.g. the body of a registry response
+    /// * `total_bytes` - The size of the compressed tar, for reporting progress
+    /// * `parent` - The layers below this one
+    async fn extract_from_stream(
+        &self,
+        stream: BoxStream<'static, MicrosandboxResult<Bytes>>,
+        total_bytes: u64,
+        parent: LayerDependencies,
+    ) -> MicrosandboxResult<()>;
+
     /// Search for directory in the current layer
     ///
     /// ## Arguments
@@ -107,6 +256,119 @@ impl Layer {
             lock: Arc::new(Mutex::new(())),
         }
     }
+
+    /// Takes the locks that keep other extractions of the layer out, in this process and in
+    /// others.
+    ///
+    /// ## Returns
+    ///
+    /// The locks, or None if the layer is already extracted, possibly by another process that
+    /// held the lock first.
+    async fn lock_for_extraction(
+        &self,
+    ) -> MicrosandboxResult<Option<(OwnedMutexGuard<()>, LayerLock)>> {
+        let (false, guard) = self.extracted().await? else {
+            return Ok(None);
+        };
+
+        // Wait for any other process working on the layer, which may have extracted it meanwhile
+        let layer_lock = self.global_layer_ops().lock_layer(self.digest()).await?;
+        if self.extraction_complete().await? && self.extracted_layer_dir().exists() {
+            tracing::info!("Layer was extracted by another process");
+            return Ok(None);
+        }
+
+        Ok(Some((guard, layer_lock)))
+    }
+
+    /// Creates a staging directory unique to this extraction, so a failed pull never leaves a
+    /// partial layer at the final path.
+    ///
+    /// The staging directory is removed when it is dropped on failure.
+    async fn create_staging_dir(&self) -> MicrosandboxResult<TempDir> {
+        let digest = self.digest();
+        let layers_dir = self.global_layer_ops().extracted_layers_dir();
+        fs::create_dir_all(layers_dir).await?;
+
+        let staging_dir = tempfile::Builder::new()
+            .prefix(&format!(".{digest}."))
+            .suffix(".partial")
+            .tempdir_in(layers_dir)
+            .map_err(|source| MicrosandboxError::LayerHandling {
+                layer: digest.to_string(),
+                source,
+            })?;
+        fs::set_permissions(staging_dir.path(), std::fs::Permissions::from_mode(0o755)).await?;
+
+        Ok(staging_dir)
+    }
+
+    /// Extracts the compressed tar read from `reader` into `extract_dir`, reporting the bytes read
+    /// to the progress of `parent`.
+    ///
+    /// The layers directory must have at least `total_bytes` free, as a layer hardly ever takes
+    /// less space extracted than compressed. Running out of space is reported as
+    /// `MicrosandboxError::DiskFull`.
+    async fn extract_archive<R: AsyncRead + Unpin + Send>(
+        &self,
+        reader: R,
+        total_bytes: u64,
+        extract_dir: &Path,
+        parent: LayerDependencies,
+    ) -> MicrosandboxResult<()> {
+        let digest = self.digest();
+        let layers_dir = self.global_layer_ops().extracted_layers_dir();
+        utils::ensure_disk_space(layers_dir, total_bytes)?;
+
+        let item = Arc::new(
+            parent
+                .progress()
+                .start_item(digest.digest().get(..8).unwrap_or(""), total_bytes),
+        );
+        let reader = ProgressReader {
+            inner: reader,
+            item: Arc::clone(&item),
+        };
+
+        let mut archive = Archive::new(GzipDecoder::new(BufReader::new(reader)));
+        extract_tar_with_ownership_override(&mut archive, extract_dir, parent)
+            .await
+            .map_err(|e| {
+                // The message stays as it was, and the layer is named in the source chain
+                let error = MicrosandboxError::LayerExtraction {
+                    message: format!("{e:?}"),
+                    source: Some(
+                        anyhow::Error::new(e)
+                            .context(format!("failed to extract layer {digest}"))
+                            .into(),
+                    ),
+                };
+                utils::map_disk_full(error, layers_dir, None)
+            })?;
+        item.finish();
+
+        Ok(())
+    }
+
+    /// Moves a completed extraction from its staging directory to the layer's directory, and
+    /// marks the layer as extracted.
+    async fn finish_extraction(&self, staging_dir: TempDir) -> MicrosandboxResult<()> {
+        let layer_dir = self.extracted_layer_dir();
+
+        // A directory without a marker at the final path is left over from an interrupted
+        // extraction, so replace it
+        if layer_dir.exists() {
+            tracing::warn!(layer_dir = %layer_dir.display(), "replacing incomplete layer directory");
+            fs::remove_dir_all(&layer_dir).await?;
+        }
+
+        // The staging directory is gone once renamed, so dropping it afterwards is a no-op
+        fs::rename(staging_dir.path(), &layer_dir).await?;
+        fs::write(self.extraction_marker_path(), self.digest().to_string()).await?;
+        drop(staging_dir);
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -126,6 +388,11 @@ impl LayerOps for Layer {
             return Ok((false, guard));
         }
 
+        if !self.extraction_complete().await? {
+            tracing::warn!(digest = %self.digest(), "layer directory exists but extraction did not complete");
+            return Ok((false, guard));
+        }
+
         // Check that the layer directory actually has content
         let mut read_dir = fs::read_dir(&dir).await?;
         let next = read_dir.next_entry().await;
@@ -140,15 +407,25 @@ impl LayerOps for Layer {
 
     async fn cleanup_extracted(&self) -> MicrosandboxResult<()> {
         let _guard = self.lock.lock().await;
-        let layer_path = self.extracted_layer_dir();
-        if layer_path.exists() {
-            tracing::debug!(layer_path = %layer_path.display(), "Cleaning up extracted layer");
-
-            tokio::fs::remove_dir_all(&layer_path)
-                .await
-                .inspect_err(|err| {
-                    tracing::error!(?err, "Failed to clean extracted layer");
-                })?;
+        let _layer_lock = self.global_layer_ops().lock_layer(self.digest()).await?;
+
+        // Remove the marker first so the layer is never seen as extracted while it is removed
+        match fs::remove_file(self.extraction_marker_path()).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        for layer_path in [self.extracted_layer_dir(), self.indexed_layer_dir()] {
+            if layer_path.exists() {
+                tracing::debug!(layer_path = %layer_path.display(), "Cleaning up extracted layer");
+
+                tokio::fs::remove_dir_all(&layer_path)
+                    .await
+                    .inspect_err(|err| {
+                        tracing::error!(?err, "Failed to clean extracted layer");
+                    })?;
+            }
         }
 
         Ok(())
@@ -160,42 +437,71 @@ impl LayerOps for Layer {
     ))]
     async fn extract(&self, parent: LayerDependencies) -> MicrosandboxResult<()> {
         assert_eq!(self.digest(), parent.digest());
-        let (false, _guard) = self.extracted().await? else {
+        let Some(_locks) = self.lock_for_extraction().await? else {
             return Ok(());
         };
 
         let layer_path = self.tar_path();
-        let digest = self.digest().clone();
-        let extract_dir = self.extracted_layer_dir();
-        fs::create_dir_all(&extract_dir).await.map_err(|source| {
-            MicrosandboxError::LayerHandling {
-                layer: digest.to_string(),
-                source,
-            }
-        })?;
+        let staging_dir = self.create_staging_dir().await?;
 
         tracing::info!("Extracting layer");
 
         let file = tokio::fs::File::open(&layer_path).await?;
-        #[cfg(feature = "cli")]
-        let (file, pb) = {
-            use crate::oci::layer::progress::{ProgressReader, build_progress_bar};
-
-            let total_bytes = fs::metadata(&layer_path).await?.len();
-            let bar = build_progress_bar(total_bytes, &digest.digest()[..8]);
-            let bar_clone = bar.clone();
-            (ProgressReader { inner: file, bar }, bar_clone)
+        let total_bytes = fs::metadata(&layer_path).await?.len();
+        self.extract_archive(file, total_bytes, staging_dir.path(), parent)
+            .await?;
+
+        self.finish_extraction(staging_dir).await?;
+
+        tracing::info!("Successfully extracted layer");
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all, fields(
+        extract_dir = %self.extracted_layer_dir().display(),
+        digest = %self.digest(),
+    ))]
+    async fn extract_from_stream(
+        &self,
+        stream: BoxStream<'static, MicrosandboxResult<Bytes>>,
+        total_bytes: u64,
+        parent: LayerDependencies,
+    ) -> MicrosandboxResult<()> {
+        assert_eq!(self.digest(), parent.digest());
+        let Some(_locks) = self.lock_for_extraction().await? else {
+            return Ok(());
         };
 
-        let mut archive = Archive::new(GzipDecoder::new(BufReader::new(file)));
-        extract_tar_with_ownership_override(&mut archive, &extract_dir, parent)
-            .await
-            .map_err(|e| MicrosandboxError::LayerExtraction(format!("{e:?}")))?;
+        let digest = self.digest().clone();
+        let mut hasher = StreamHasher::new(digest.algorithm())?;
+        let staging_dir = self.create_staging_dir().await?;
 
-        #[cfg(feature = "cli")]
-        pb.finish_and_clear();
+        tracing::info!("Extracting layer from stream");
 
-        tracing::info!("Successfully extracted layer");
+        let mut stream = stream.inspect_ok(|bytes| hasher.update(bytes));
+        let reader = StreamReader::new((&mut stream).map_err(std::io::Error::other));
+        self.extract_archive(reader, total_bytes, staging_dir.path(), parent)
+            .await?;
+
+        // The tar can end before the compressed stream does, and all of it has to be hashed
+        while let Some(bytes) = stream.next().await {
+            bytes?;
+        }
+        drop(stream);
+
+        // The staging directory is removed when it is dropped, so a layer that doesn't match its
+        // digest is never used
+        let actual_hash = hex::encode(hasher.finalize());
+        if actual_hash != digest.digest() {
+            return Err(MicrosandboxError::ImageLayerDownloadFailed(format!(
+                "({digest}) stream hash {actual_hash} does not match expected hash {}",
+                digest.digest()
+            )));
+        }
+
+        self.finish_extraction(staging_dir).await?;
+
+        tracing::info!("Successfully extracted layer from stream");
         Ok(())
     }
 
@@ -217,6 +523,8 @@ pub(crate) struct LayerDependencies {
     layer: Digest,
     /// The image this layer belongs to.
     image: Image,
+    /// The progress extractions of these layers are reported to.
+    progress: MultiItemProgress,
 }
 
 impl LayerDependencies {
@@ -227,7 +535,22 @@ impl LayerDependencies {
     /// * `layer` - The layer digest in focus.
     /// * `image` - The image this layer belongs to.
     pub(crate) fn new(layer: Digest, image: Image) -> Self {
-        Self { layer, image }
+        Self {
+            layer,
+            image,
+            progress: MultiItemProgress::hidden("Extracting layers"),
+        }
+    }
+
+    /// Reports the extraction of the layer, and of any parent layers it needs, to `progress`.
+    pub(crate) fn with_progress(mut self, progress: MultiItemProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Gets the progress extractions of these layers are reported to.
+    pub(crate) fn progress(&self) -> &MultiItemProgress {
+        &self.progress
     }
 
     /// Gets the digest of the layer in focus.
@@ -247,11 +570,32 @@ impl LayerDependencies {
         let path = path.as_ref().to_path_buf();
 
         // iterate over layers in reverse order
-        // if the layer hasn't been extracted yet, extract it
+        // if the layer hasn't been extracted yet, extract it, or just the directory when the
+        // layer tar is indexed
         // if the file is found in the layer, return the layer digest and the path to the file
         for layer in self.image.layers().iter() {
+            if !layer.extracted_layer_dir().exists()
+                && let Some(index) = layer.tar_index().await?
+            {
+                if !index.find(&path).is_some_and(|entry| entry.is_dir()) {
+                    continue;
+                }
+
+                let mut reader = fs::File::open(layer.tar_path()).await?;
+                if let Some(dir) = index
+                    .extract_path(&mut reader, &path, &layer.indexed_layer_dir())
+                    .await?
+                {
+                    return Ok(Some((layer.digest().clone(), dir)));
+                }
+            }
+
             layer
-                .extract(self.image.get_layer_parent(layer.digest()))
+                .extract(
+                    self.image
+                        .get_layer_parent(layer.digest())
+                        .with_progress(self.progress.clone()),
+                )
                 .await?;
 
             if let Some(path) = layer.find_dir(&path).await {
@@ -262,3 +606,373 @@ impl LayerDependencies {
         Ok(None)
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Write, str::FromStr};
+
+    use sha2::{Digest as _, Sha256};
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::{
+        management::db::{self, OCI_DB_MIGRATOR},
+        oci::GlobalCache,
+    };
+
+    fn gzip_tar(path: &str, contents: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path(path).unwrap();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, contents).unwrap();
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Builds a gzipped tar with a directory, a regular and an executable file, and a symlink.
+    fn gzip_tree_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut append = |path: &str, entry_type, mode, contents: &[u8], link: Option<&str>| {
+            let mut header = tar::Header::new_gnu();
+            header.set_path(path).unwrap();
+            header.set_size(contents.len() as u64);
+            header.set_mode(mode);
+            header.set_entry_type(entry_type);
+            if let Some(link) = link {
+                header.set_link_name(link).unwrap();
+            }
+            header.set_cksum();
+            builder.append(&header, contents).unwrap();
+        };
+
+        let app = (0..64 << 10).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        append("etc/", tar::EntryType::Directory, 0o755, b"", None);
+        append(
+            "etc/hostname",
+            tar::EntryType::Regular,
+            0o644,
+            b"sandbox\n",
+            None,
+        );
+        append("bin/", tar::EntryType::Directory, 0o755, b"", None);
+        append("bin/app", tar::EntryType::Regular, 0o755, &app, None);
+        append("bin/sh", tar::EntryType::Symlink, 0o777, b"", Some("app"));
+
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&builder.into_inner().unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Returns the sha256 digest of `data`.
+    fn sha256_digest(data: &[u8]) -> Digest {
+        Digest::from_str(&format!("sha256:{}", hex::encode(Sha256::digest(data)))).unwrap()
+    }
+
+    /// Returns every entry under `dir` with its mode and its contents or link target.
+    fn read_tree(dir: &Path) -> anyhow::Result<BTreeMap<PathBuf, (u32, Vec<u8>)>> {
+        let mut tree = BTreeMap::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for entry in std::fs::read_dir(&current)? {
+                let path = entry?.path();
+                let metadata = std::fs::symlink_metadata(&path)?;
+                let contents = if metadata.is_symlink() {
+                    std::fs::read_link(&path)?
+                        .into_os_string()
+                        .into_encoded_bytes()
+                } else if metadata.is_dir() {
+                    pending.push(path.clone());
+                    Vec::new()
+                } else {
+                    std::fs::read(&path)?
+                };
+                let relative = path.strip_prefix(dir)?.to_path_buf();
+                tree.insert(relative, (metadata.permissions().mode(), contents));
+            }
+        }
+
+        Ok(tree)
+    }
+
+    async fn test_layer(root: &Path) -> anyhow::Result<Layer> {
+        let digest = Digest::from_str(&format!("sha256:{}", "b".repeat(64)))?;
+        test_layer_with_digest(root, digest).await
+    }
+
+    async fn test_layer_with_digest(root: &Path, digest: Digest) -> anyhow::Result<Layer> {
+        let db = db::get_or_create_pool(&root.join("db"), &OCI_DB_MIGRATOR).await?;
+        let cache = GlobalCache::new(root.join("download"), root.join("extracted"), db).await?;
+        Ok(Layer::new(Arc::new(cache), digest))
+    }
+
+    /// Returns a stream of `data` in small chunks, like the body of a registry response.
+    fn response_stream(data: Vec<u8>) -> BoxStream<'static, MicrosandboxResult<Bytes>> {
+        let chunks = data
+            .chunks(1000)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        futures::stream::iter(chunks).boxed()
+    }
+
+    #[tokio::test]
+    async fn test_layer_extracted_requires_completion_marker() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let layer = test_layer(temp_dir.path()).await?;
+
+        // A directory with content but no marker is not extracted
+        fs::create_dir_all(layer.extracted_layer_dir()).await?;
+        fs::write(layer.extracted_layer_dir().join("file"), b"contents").await?;
+        assert!(!layer.extracted().await?.0);
+
+        // Nor is one whose marker doesn't name the layer
+        fs::write(layer.extraction_marker_path(), b"").await?;
+        assert!(!layer.extracted().await?.0);
+
+        fs::write(layer.extraction_marker_path(), layer.digest().to_string()).await?;
+        assert!(layer.extracted().await?.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_layer_failed_extraction_is_not_extracted() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let layer = test_layer(temp_dir.path()).await?;
+        let digest = layer.digest().clone();
+        let parent = || LayerDependencies::new(digest.clone(), Image::new(Vec::new()));
+
+        // A valid gzip stream whose tar is cut off midway fails partway through extraction
+        let mut truncated = gzip_tar("file", &[b'x'; 4096]);
+        truncated.truncate(truncated.len() / 2);
+        fs::create_dir_all(layer.tar_path().parent().unwrap()).await?;
+        fs::write(layer.tar_path(), &truncated).await?;
+
+        assert!(layer.extract(parent()).await.is_err());
+        assert!(!layer.extracted().await?.0);
+        assert!(!layer.extracted_layer_dir().exists());
+        assert_eq!(
+            std::fs::read_dir(layer.global_layer_ops().extracted_layers_dir())?.count(),
+            0,
+            "no staging directory should be left behind"
+        );
+
+        // A non-empty directory left by an interrupted extraction doesn't count as extracted
+        fs::create_dir_all(layer.extracted_layer_dir()).await?;
+        fs::write(layer.extracted_layer_dir().join("partial"), b"").await?;
+        assert!(!layer.extracted().await?.0);
+
+        // A later extraction replaces the leftover directory and marks the layer complete
+        fs::write(layer.tar_path(), gzip_tar("file", b"contents")).await?;
+        layer.extract(parent()).await?;
+        assert!(layer.extracted().await?.0);
+        assert_eq!(
+            fs::read_to_string(layer.extraction_marker_path()).await?,
+            digest.to_string()
+        );
+        assert!(!layer.extracted_layer_dir().join("partial").exists());
+        assert_eq!(
+            fs::read(layer.extracted_layer_dir().join("file")).await?,
+            b"contents"
+        );
+
+        layer.cleanup_extracted().await?;
+        assert!(!layer.extracted().await?.0);
+        assert!(!layer.extraction_marker_path().exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_layer_extract_from_stream_matches_file() -> anyhow::Result<()> {
+        let tar = gzip_tree_tar();
+        let digest = sha256_digest(&tar);
+        let parent = || LayerDependencies::new(digest.clone(), Image::new(Vec::new()));
+
+        // Extract one layer from its downloaded tar
+        let file_dir = tempdir()?;
+        let file_layer = test_layer_with_digest(file_dir.path(), digest.clone()).await?;
+        fs::create_dir_all(file_layer.tar_path().parent().unwrap()).await?;
+        fs::write(file_layer.tar_path(), &tar).await?;
+        file_layer.extract(parent()).await?;
+
+        // And the other straight from the response, without writing the tar anywhere
+        let stream_dir = tempdir()?;
+        let stream_layer = test_layer_with_digest(stream_dir.path(), digest.clone()).await?;
+        stream_layer
+            .extract_from_stream(response_stream(tar.clone()), tar.len() as u64, parent())
+            .await?;
+
+        assert!(stream_layer.extracted().await?.0);
+        assert!(!stream_layer.tar_path().exists());
+        let stream_tree = read_tree(&stream_layer.extracted_layer_dir())?;
+        assert_eq!(stream_tree, read_tree(&file_layer.extracted_layer_dir())?);
+        assert_eq!(stream_tree.len(), 5);
+
+        // A layer that is already extracted doesn't read the stream at all
+        let unread = futures::stream::iter([Err(MicrosandboxError::ImageLayerDownloadFailed(
+            "stream should not be read".to_string(),
+        ))])
+        .boxed();
+        stream_layer
+            .extract_from_stream(unread, tar.len() as u64, parent())
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_layer_extract_from_stream_rejects_mismatched_digest() -> anyhow::Result<()> {
+        let tar = gzip_tree_tar();
+        let digest = sha256_digest(b"another layer");
+        let temp_dir = tempdir()?;
+        let layer = test_layer_with_digest(temp_dir.path(), digest.clone()).await?;
+
+        let parent = LayerDependencies::new(digest, Image::new(Vec::new()));
+        let result = layer
+            .extract_from_stream(response_stream(tar.clone()), tar.len() as u64, parent)
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::ImageLayerDownloadFailed(_))
+        ));
+        assert!(!layer.extracted().await?.0);
+        assert!(!layer.extracted_layer_dir().exists());
+        assert_eq!(
+            std::fs::read_dir(layer.global_layer_ops().extracted_layers_dir())?.count(),
+            0,
+            "no staging directory should be left behind"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_layer_extract_reports_full_disk() -> anyhow::Result<()> {
+        let tar = gzip_tree_tar();
+        let digest = sha256_digest(&tar);
+        let temp_dir = tempdir()?;
+        let layer = test_layer_with_digest(temp_dir.path(), digest.clone()).await?;
+        let layers_dir = layer.global_layer_ops().extracted_layers_dir().clone();
+
+        // A layer bigger than the free space is refused before any of it is read
+        let parent = LayerDependencies::new(digest.clone(), Image::new(Vec::new()));
+        let result = layer
+            .extract_from_stream(response_stream(tar.clone()), u64::MAX, parent)
+            .await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::DiskFull {
+                needed: Some(u64::MAX),
+                available: Some(_),
+                ..
+            })
+        ));
+
+        // The disk filling up halfway through the layer is reported the same way
+        let chunks = vec![
+            Ok(Bytes::copy_from_slice(&tar[..tar.len() / 2])),
+            Err(MicrosandboxError::Io(std::io::Error::from(
+                std::io::ErrorKind::StorageFull,
+            ))),
+        ];
+        let parent = LayerDependencies::new(digest, Image::new(Vec::new()));
+        let result = layer
+            .extract_from_stream(
+                futures::stream::iter(chunks).boxed(),
+                tar.len() as u64,
+                parent,
+            )
+            .await;
+        let Err(MicrosandboxError::DiskFull {
+            path,
+            needed: None,
+            available,
+        }) = result
+        else {
+            panic!("expected a disk full error");
+        };
+        assert_eq!(path, layers_dir);
+        assert!(available.is_some());
+        assert!(!layer.extracted().await?.0);
+        assert!(!layer.extracted_layer_dir().exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_layer_concurrent_extractions_extract_once() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let root = temp_dir.path();
+        let runtime = || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+        };
+
+        let layer = runtime()?.block_on(test_layer(root))?;
+        let contents = (0..4 << 20).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::create_dir_all(layer.tar_path().parent().unwrap())?;
+        std::fs::write(layer.tar_path(), gzip_tar("file", &contents))?;
+
+        // Each runtime stands in for a separate `msb` process, with its own cache and layer, so
+        // only the cross-process lock keeps them from extracting the layer at the same time
+        let progress = MultiItemProgress::hidden("Extracting layers");
+        let barrier = std::sync::Barrier::new(2);
+        let extract = || -> anyhow::Result<()> {
+            runtime()?.block_on(async {
+                let layer = test_layer(root).await?;
+                let parent = LayerDependencies::new(layer.digest().clone(), Image::new(Vec::new()))
+                    .with_progress(progress.clone());
+                barrier.wait();
+                layer.extract(parent).await?;
+                Ok(())
+            })
+        };
+        std::thread::scope(|scope| {
+            let first = scope.spawn(extract);
+            let second = scope.spawn(extract);
+            first.join().expect("extraction thread panicked")?;
+            second.join().expect("extraction thread panicked")
+        })?;
+
+        // One of them extracted the layer and the other found it complete
+        assert!(
+            progress
+                .summary()
+                .starts_with("Extracting layers: 1 of 1 done"),
+            "{}",
+            progress.summary()
+        );
+        assert_eq!(
+            std::fs::read(layer.extracted_layer_dir().join("file"))?,
+            contents
+        );
+        assert_eq!(
+            std::fs::read_to_string(layer.extraction_marker_path())?,
+            layer.digest().to_string()
+        );
+
+        // No staging directory is left behind
+        let mut entries = std::fs::read_dir(layer.global_layer_ops().extracted_layers_dir())?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        assert_eq!(
+            entries,
+            [layer.extracted_layer_dir(), layer.extraction_marker_path()]
+        );
+
+        Ok(())
+    }
+}
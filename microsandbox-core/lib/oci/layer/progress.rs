@@ -1,38 +1,16 @@
-#[cfg(feature = "cli")]
-use indicatif::{ProgressBar, ProgressStyle};
-#[cfg(feature = "cli")]
-use microsandbox_utils::MULTI_PROGRESS;
-#[cfg(feature = "cli")]
+use microsandbox_utils::term::ItemProgress;
 use pin_project_lite::pin_project;
-#[cfg(feature = "cli")]
-use std::task::Poll;
-#[cfg(feature = "cli")]
+use std::{sync::Arc, task::Poll};
 use tokio::io::{AsyncRead, ReadBuf};
 
-#[cfg(feature = "cli")]
-pub(super) fn build_progress_bar(total_bytes: u64, prefix: &str) -> ProgressBar {
-    let pb = MULTI_PROGRESS.add(ProgressBar::new(total_bytes));
-    pb.set_style(
-        ProgressStyle::with_template(
-            "{prefix:.bold.dim} {bar:40.green/green.dim} {bytes:.bold}/{total_bytes:.dim}",
-        )
-        .unwrap()
-        .progress_chars("=+-"),
-    );
-    pb.set_prefix(prefix.to_string());
-    pb
-}
-
-#[cfg(feature = "cli")]
 pin_project! {
     pub(super) struct ProgressReader<R> {
         #[pin]
         pub(super) inner: R,
-        pub(super) bar: ProgressBar,
+        pub(super) item: Arc<ItemProgress>,
     }
 }
 
-#[cfg(feature = "cli")]
 impl<R: AsyncRead> AsyncRead for ProgressReader<R> {
     fn poll_read(
         self: std::pin::Pin<&mut Self>,
@@ -40,11 +18,12 @@ impl<R: AsyncRead> AsyncRead for ProgressReader<R> {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
         let p = self.project();
+        let filled = buf.filled().len();
         match p.inner.poll_read(cx, buf)? {
             Poll::Ready(()) => {
-                let n = buf.filled().len();
+                let n = buf.filled().len() - filled;
                 if n > 0 {
-                    p.bar.inc(n as u64);
+                    p.item.inc(n as u64);
                 }
                 Poll::Ready(Ok(()))
             }
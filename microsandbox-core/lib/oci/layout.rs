@@ -0,0 +1,473 @@
+//! Pulling images from OCI image layouts on disk.
+//!
+//! An [OCI image layout] is a directory with an `oci-layout` marker file, an `index.json` that
+//! lists the images in it, and the blobs they are made of under `blobs/<algorithm>/<hex>`, as
+//! written by e.g. `skopeo copy docker://alpine:3.20 oci:alpine:3.20`. Images in a layout are
+//! imported into the local database and layer cache the same way images are pulled from a
+//! registry, without any network access, which makes air-gapped workflows possible.
+//!
+//! [OCI image layout]: https://github.com/opencontainers/image-spec/blob/main/image-layout.md
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Instant,
+};
+
+use futures::future::try_join_all;
+use microsandbox_utils::DEFAULT_OCI_REFERENCE_TAG;
+use oci_client::{
+    config::ConfigFile as OciConfigFile,
+    manifest::{
+        IMAGE_MANIFEST_LIST_MEDIA_TYPE, ImageIndexEntry, OCI_IMAGE_INDEX_MEDIA_TYPE, OciDescriptor,
+        OciImageIndex, OciImageManifest,
+    },
+};
+use oci_spec::image::{Digest, Platform};
+use serde::{Deserialize, de::DeserializeOwned};
+use sqlx::{Pool, Sqlite};
+use tokio::fs;
+
+use crate::{
+    MicrosandboxError, MicrosandboxResult,
+    oci::{
+        PullSummary, Reference, global_cache::GlobalCacheOps, image::Image, layer::LayerOps,
+        resolve_digest_for_platform, save_image_records, timing,
+    },
+    utils,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The name of the file that marks a directory as an OCI image layout.
+const OCI_LAYOUT_FILE: &str = "oci-layout";
+
+/// The name of the file that lists the images in a layout.
+const INDEX_FILE: &str = "index.json";
+
+/// The directory of a layout the blobs are stored in.
+const BLOBS_DIR: &str = "blobs";
+
+/// The version of the layout format that can be read.
+const SUPPORTED_LAYOUT_VERSION: &str = "1.0.0";
+
+/// The annotation of an index entry that holds the tag of the image.
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// An OCI image layout on disk.
+struct OciLayout {
+    /// The directory of the layout.
+    dir: PathBuf,
+}
+
+/// The contents of the `oci-layout` marker file.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OciLayoutMarker {
+    /// The version of the layout format.
+    image_layout_version: String,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl OciLayout {
+    /// Opens the layout in `dir`, checking that it is a layout in a format that can be read.
+    async fn open(dir: &Path) -> MicrosandboxResult<Self> {
+        let marker_path = dir.join(OCI_LAYOUT_FILE);
+        let marker = match fs::read(&marker_path).await {
+            Ok(marker) => marker,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(MicrosandboxError::InvalidOciLayout(format!(
+                    "{} is not an OCI image layout, it has no {OCI_LAYOUT_FILE} file",
+                    dir.display()
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let marker: OciLayoutMarker = serde_json::from_slice(&marker).map_err(|e| {
+            MicrosandboxError::InvalidOciLayout(format!("{}: {e}", marker_path.display()))
+        })?;
+        if marker.image_layout_version != SUPPORTED_LAYOUT_VERSION {
+            return Err(MicrosandboxError::InvalidOciLayout(format!(
+                "{} has unsupported layout version {}, expected {SUPPORTED_LAYOUT_VERSION}",
+                dir.display(),
+                marker.image_layout_version
+            )));
+        }
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Returns the path of the blob with the given digest.
+    fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.dir
+            .join(BLOBS_DIR)
+            .join(digest.algorithm().to_string())
+            .join(digest.digest())
+    }
+
+    /// Reads a blob, checking that its content matches its digest.
+    async fn read_blob(&self, digest: &str) -> MicrosandboxResult<Vec<u8>> {
+        let digest = Digest::from_str(digest)?;
+        let path = self.blob_path(&digest);
+        let blob = match fs::read(&path).await {
+            Ok(blob) => blob,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(MicrosandboxError::InvalidOciLayout(format!(
+                    "blob {digest} is missing from {}",
+                    self.dir.display()
+                )));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        self.verify_blob(&digest, &path).await?;
+        Ok(blob)
+    }
+
+    /// Checks that the content of the file at `path` matches `digest`.
+    async fn verify_blob(&self, digest: &Digest, path: &Path) -> MicrosandboxResult<()> {
+        let actual_hash = hex::encode(utils::get_file_hash(path, digest.algorithm()).await?);
+        if actual_hash != digest.digest() {
+            return Err(MicrosandboxError::InvalidOciLayout(format!(
+                "blob {digest} in {} has hash {actual_hash}",
+                self.dir.display()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Finds the image manifest for the given tag and platform.
+    ///
+    /// Index entries are matched by their `org.opencontainers.image.ref.name` annotation. A
+    /// layout that holds a single untagged image, as written by `skopeo copy` without a tag,
+    /// matches the `latest` tag. Multi-platform images select the manifest for `platform`.
+    ///
+    /// ## Returns
+    ///
+    /// The size of the image, the manifest and its digest
+    async fn resolve_manifest(
+        &self,
+        reference: &Reference,
+        platform: &Platform,
+    ) -> MicrosandboxResult<(i64, OciImageManifest, String)> {
+        let index_path = self.dir.join(INDEX_FILE);
+        let index: OciImageIndex =
+            serde_json::from_slice(&fs::read(&index_path).await?).map_err(|e| {
+                MicrosandboxError::InvalidOciLayout(format!("{}: {e}", index_path.display()))
+            })?;
+
+        let tag = reference.tag().unwrap_or(DEFAULT_OCI_REFERENCE_TAG);
+        let entry = find_tagged_entry(&index.manifests, tag)
+            .ok_or_else(|| MicrosandboxError::ImageNotFound(reference.to_string()))?;
+
+        if ![OCI_IMAGE_INDEX_MEDIA_TYPE, IMAGE_MANIFEST_LIST_MEDIA_TYPE]
+            .contains(&entry.media_type.as_str())
+        {
+            let manifest: OciImageManifest = self.read_json_blob(&entry.digest).await?;
+            return Ok((manifest.config.size, manifest, entry.digest.clone()));
+        }
+
+        // A multi-platform image, whose entries are manifests for each platform
+        let platforms: OciImageIndex = self.read_json_blob(&entry.digest).await?;
+        let digest = resolve_digest_for_platform(platform.clone(), &platforms.manifests)
+            .ok_or_else(|| {
+                MicrosandboxError::ImageNotFound(format!(
+                    "{reference} for {}/{}",
+                    platform.os(),
+                    platform.architecture()
+                ))
+            })?;
+
+        let manifest = self.read_json_blob(&digest).await?;
+        let size = platforms.manifests.iter().map(|m| m.size).sum();
+        Ok((size, manifest, digest))
+    }
+
+    /// Reads a blob that holds a JSON document.
+    async fn read_json_blob<T: DeserializeOwned>(&self, digest: &str) -> MicrosandboxResult<T> {
+        let blob = self.read_blob(digest).await?;
+        serde_json::from_slice(&blob).map_err(|e| {
+            MicrosandboxError::InvalidOciLayout(format!("blob {digest} is not valid: {e}"))
+        })
+    }
+
+    /// Copies a layer from the layout into the layer cache, unless it is cached already.
+    ///
+    /// The layer is locked while it is copied, as when it is downloaded from a registry, and a
+    /// cached copy is only reused if it matches the layer's digest.
+    ///
+    /// ## Returns
+    ///
+    /// The layer along with the number of bytes copied for it, which is zero when the layer was
+    /// already cached.
+    async fn import_layer<O: GlobalCacheOps>(
+        &self,
+        global_cache: &O,
+        descriptor: &OciDescriptor,
+    ) -> MicrosandboxResult<(Arc<dyn LayerOps>, u64)> {
+        let digest = Digest::from_str(&descriptor.digest)?;
+        let layer = global_cache.build_layer(&digest).await;
+        let _layer_lock = global_cache.lock_layer(&digest).await?;
+        if layer.tar_verified(descriptor.size as u64).await? {
+            tracing::info!(?digest, "Layer already exists. Skipping import");
+            return Ok((layer, 0));
+        }
+
+        let download_path = layer.download_path();
+        if let Some(parent) = download_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let blob_path = self.blob_path(&digest);
+        if !blob_path.exists() {
+            return Err(MicrosandboxError::InvalidOciLayout(format!(
+                "blob {digest} is missing from {}",
+                self.dir.display()
+            )));
+        }
+
+        let copied = fs::copy(&blob_path, &download_path).await?;
+        if let Err(e) = self.verify_blob(&digest, &download_path).await {
+            fs::remove_file(&download_path).await?;
+            return Err(e);
+        }
+
+        layer.store_download().await?;
+        tracing::info!(?digest, "layer imported and cached successfully");
+        Ok((layer, copied))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Imports an image from an OCI image layout on disk into the database and layer cache, and
+/// extracts its layers.
+///
+/// ## Arguments
+///
+/// * `db` - The database where image configurations, and manifests are stored
+/// * `platform` - The platform to select the manifest of a multi-platform image for
+/// * `global_cache` - The global layer cache
+/// * `reference` - The reference to the image, which is saved under it
+/// * `layout_dir` - The directory of the layout
+/// * `started` - When the pull started, to report its duration
+///
+/// ## Returns
+///
+/// A summary of the pull, where the bytes copied from the layout count as downloaded
+pub(crate) async fn pull_layout_image<O: GlobalCacheOps>(
+    db: &Pool<Sqlite>,
+    platform: &Platform,
+    global_cache: &O,
+    reference: &Reference,
+    layout_dir: &Path,
+    started: Instant,
+) -> MicrosandboxResult<PullSummary> {
+    let layout = OciLayout::open(layout_dir).await?;
+
+    let (size, manifest, manifest_digest, config) =
+        timing::measure(timing::phase_span("manifest", None), async {
+            let (size, manifest, digest) = layout.resolve_manifest(reference, platform).await?;
+            let config: OciConfigFile = layout.read_json_blob(&manifest.config.digest).await?;
+            Ok::<_, MicrosandboxError>((size, manifest, digest, config))
+        })
+        .await?;
+
+    timing::measure(
+        timing::phase_span("db", None),
+        save_image_records(db, reference, size, &manifest, &config),
+    )
+    .await?;
+
+    let imports = manifest.layers.iter().map(|descriptor| {
+        let span = timing::phase_span("import", Some(descriptor.digest.as_str()));
+        timing::measure(span, layout.import_layer(global_cache, descriptor))
+    });
+    let (layers, layer_imports): (Vec<_>, Vec<_>) =
+        try_join_all(imports).await?.into_iter().unzip();
+
+    Image::new(layers).extract_all().await?;
+
+    Ok(PullSummary::new(
+        reference,
+        Some(manifest_digest),
+        &layer_imports,
+        started.elapsed(),
+    ))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Finds the index entry of the image with the given tag.
+fn find_tagged_entry<'a>(entries: &'a [ImageIndexEntry], tag: &str) -> Option<&'a ImageIndexEntry> {
+    let ref_name = |entry: &ImageIndexEntry| {
+        entry
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(REF_NAME_ANNOTATION))
+            .cloned()
+    };
+
+    entries
+        .iter()
+        .find(|entry| ref_name(entry).as_deref() == Some(tag))
+        .or_else(|| match entries {
+            [entry] if tag == DEFAULT_OCI_REFERENCE_TAG => {
+                ref_name(entry).is_none().then_some(entry)
+            }
+            _ => None,
+        })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        management::db,
+        oci::mocks::{LayoutFixture, mock_registry_and_db},
+    };
+
+    #[tokio::test]
+    async fn test_pull_layout_image_imports_image_and_layers() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(Some("1.0"))?;
+        let (registry, db, temp_dir) = mock_registry_and_db().await;
+
+        let reference = fixture.reference("1.0")?;
+        let summary = registry.pull_image(&reference, false).await?;
+        assert_eq!(summary.get_layers_pulled(), &1);
+
+        // The image and its layer are recorded under the layout reference
+        assert!(db::image_exists(&db, &reference.as_db_key()).await?);
+        assert_eq!(
+            db::get_image_layer_digests(&db, &reference.as_db_key()).await?,
+            vec![fixture.layer_digest.clone()]
+        );
+
+        // The layer is extracted into the layers directory
+        let layer = registry
+            .global_cache()
+            .build_layer(&Digest::from_str(&fixture.layer_digest)?)
+            .await;
+        assert!(
+            layer
+                .extracted_layer_dir()
+                .starts_with(temp_dir.path().join("extracted"))
+        );
+        assert_eq!(
+            std::fs::read_to_string(layer.extracted_layer_dir().join("hello.txt"))?,
+            "hello"
+        );
+
+        // Pulling again finds the image in the cache
+        let summary = registry.pull_image(&reference, false).await?;
+        assert_eq!(summary.get_digest(), &None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pull_layout_image_replaces_corrupted_cached_layer() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(Some("1.0"))?;
+        let (registry, _db, _temp_dir) = mock_registry_and_db().await;
+
+        // A cached copy of the layer with the right size but the wrong content
+        let layer_digest = Digest::from_str(&fixture.layer_digest)?;
+        let blob = std::fs::read(
+            fixture
+                .dir
+                .path()
+                .join(BLOBS_DIR)
+                .join("sha256")
+                .join(layer_digest.digest()),
+        )?;
+        let layer = registry.global_cache().build_layer(&layer_digest).await;
+        std::fs::create_dir_all(layer.tar_path().parent().unwrap())?;
+        std::fs::write(layer.tar_path(), vec![0; blob.len()])?;
+
+        registry
+            .pull_image(&fixture.reference("1.0")?, false)
+            .await?;
+        assert_eq!(std::fs::read(layer.tar_path())?, blob);
+        assert_eq!(
+            std::fs::read_to_string(layer.extracted_layer_dir().join("hello.txt"))?,
+            "hello"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pull_layout_image_selects_untagged_image_as_latest() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(None)?;
+        let (registry, db, _temp_dir) = mock_registry_and_db().await;
+
+        let reference: Reference = format!("oci:{}", fixture.dir.path().display()).parse()?;
+        registry.pull_image(&reference, false).await?;
+        assert!(db::image_exists(&db, &reference.as_db_key()).await?);
+
+        // Other tags don't match the untagged image
+        let result = registry.pull_image(&fixture.reference("1.0")?, false).await;
+        assert!(matches!(result, Err(MicrosandboxError::ImageNotFound(_))));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pull_layout_image_rejects_invalid_layouts() -> anyhow::Result<()> {
+        let fixture = LayoutFixture::new(Some("1.0"))?;
+        let (registry, _db, _temp_dir) = mock_registry_and_db().await;
+
+        // A missing tag
+        let result = registry.pull_image(&fixture.reference("2.0")?, false).await;
+        assert!(matches!(result, Err(MicrosandboxError::ImageNotFound(_))));
+
+        // A corrupted layer
+        let layer_digest = Digest::from_str(&fixture.layer_digest)?;
+        std::fs::write(
+            fixture
+                .dir
+                .path()
+                .join(BLOBS_DIR)
+                .join("sha256")
+                .join(layer_digest.digest()),
+            b"corrupted",
+        )?;
+        let result = registry.pull_image(&fixture.reference("1.0")?, false).await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::InvalidOciLayout(_))
+        ));
+
+        // A directory that isn't a layout
+        std::fs::remove_file(fixture.dir.path().join(OCI_LAYOUT_FILE))?;
+        let result = registry.pull_image(&fixture.reference("1.0")?, false).await;
+        assert!(matches!(
+            result,
+            Err(MicrosandboxError::InvalidOciLayout(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
+use std::io::Write;
+
 use oci_spec::image::Platform;
+use serde_json::json;
+use sha2::{Digest as _, Sha256};
 use sqlx::{Pool, Sqlite};
 
 use crate::{
     management::db::{self, OCI_DB_MIGRATOR},
-    oci::{Registry, global_cache::GlobalCache},
+    oci::{Reference, Registry, global_cache::GlobalCache},
 };
 use tempfile::TempDir;
 
@@ -28,3 +32,85 @@ pub(crate) async fn mock_registry_and_db() -> (Registry<GlobalCache>, Pool<Sqlit
         .unwrap();
     (registry, db, temp_dir)
 }
+
+/// A layout with a single image, whose only layer holds `hello.txt`.
+pub(crate) struct LayoutFixture {
+    /// The directory of the layout.
+    pub(crate) dir: tempfile::TempDir,
+
+    /// The digest of the layer.
+    pub(crate) layer_digest: String,
+}
+
+impl LayoutFixture {
+    /// Writes the layout, tagging the image with `tag` unless it is None.
+    pub(crate) fn new(tag: Option<&str>) -> anyhow::Result<Self> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("blobs").join("sha256"))?;
+        let write_blob = |blob: &[u8]| -> anyhow::Result<String> {
+            let hex = hex::encode(Sha256::digest(blob));
+            std::fs::write(dir.path().join("blobs").join("sha256").join(&hex), blob)?;
+            Ok(format!("sha256:{hex}"))
+        };
+
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_path("hello.txt")?;
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_cksum();
+        builder.append(&header, &b"hello"[..])?;
+        let layer_tar = builder.into_inner()?;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&layer_tar)?;
+        let layer = encoder.finish()?;
+
+        let diff_id = format!("sha256:{}", hex::encode(Sha256::digest(&layer_tar)));
+        let config = serde_json::to_vec(&json!({
+            "architecture": Platform::default().architecture().to_string(),
+            "os": "linux",
+            "rootfs": { "type": "layers", "diff_ids": [diff_id] }
+        }))?;
+        let layer_digest = write_blob(&layer)?;
+        let config_digest = write_blob(&config)?;
+        let manifest = serde_json::to_vec(&json!({
+            "schemaVersion": 2,
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "config": {
+                "mediaType": "application/vnd.oci.image.config.v1+json",
+                "digest": config_digest,
+                "size": config.len()
+            },
+            "layers": [{
+                "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+                "digest": layer_digest,
+                "size": layer.len()
+            }]
+        }))?;
+        let manifest_digest = write_blob(&manifest)?;
+
+        let mut entry = json!({
+            "mediaType": "application/vnd.oci.image.manifest.v1+json",
+            "digest": manifest_digest,
+            "size": manifest.len()
+        });
+        if let Some(tag) = tag {
+            entry["annotations"] = json!({});
+            entry["annotations"]["org.opencontainers.image.ref.name"] = json!(tag);
+        }
+        let index = json!({ "schemaVersion": 2, "manifests": [entry] });
+        std::fs::write(dir.path().join("index.json"), index.to_string())?;
+        std::fs::write(
+            dir.path().join("oci-layout"),
+            json!({ "imageLayoutVersion": "1.0.0" }).to_string(),
+        )?;
+
+        Ok(Self { dir, layer_digest })
+    }
+
+    /// Returns a reference to the image in the layout with the given tag.
+    pub(crate) fn reference(&self, tag: &str) -> anyhow::Result<Reference> {
+        Ok(format!("oci:{}:{tag}", self.dir.path().display()).parse()?)
+    }
+}
@@ -3,17 +3,24 @@
 //! This module provides functionality for:
 //! - Pulling container images from OCI-compliant registries
 //! - Parsing and validating image references (tags and digests)
+//! - Rewriting image references to pull them through mirrors
 //! - Managing image manifests, configurations, and layers
 
+pub mod credential_store;
+pub mod docker_config;
 mod global_cache;
 mod image;
 mod layer;
+mod layout;
 #[cfg(test)]
 pub(crate) mod mocks;
+mod pull_summary;
 mod reference;
 mod registry;
+mod rewrite;
 #[cfg(test)]
 mod tests;
+mod timing;
 
 //--------------------------------------------------------------------------------------------------
 // Exports
@@ -22,5 +29,8 @@ mod tests;
 pub(crate) use global_cache::*;
 pub use image::*;
 pub(crate) use layer::*;
+pub use pull_summary::*;
 pub use reference::*;
 pub(crate) use registry::*;
+pub use rewrite::*;
+pub use timing::*;
@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use getset::Getters;
+use serde::Serialize;
+
+use crate::oci::Reference;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// What happened during an image pull.
+///
+/// Serializes to the JSON summary `msb pull --output json` prints, e.g.
+///
+/// ```json
+/// {
+///   "reference": "docker.io/library/alpine:latest",
+///   "digest": "sha256:...",
+///   "layers_pulled": 1,
+///   "layers_cached": 1,
+///   "bytes_downloaded": 3623807,
+///   "duration_ms": 1532
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct PullSummary {
+    /// The reference of the pulled image.
+    reference: String,
+
+    /// The digest of the manifest the reference resolved to.
+    ///
+    /// This is `None` when the image was already pulled and the registry wasn't contacted.
+    digest: Option<String>,
+
+    /// The number of layers that had to be downloaded, in full or in part.
+    layers_pulled: usize,
+
+    /// The number of layers that were already cached and not downloaded again.
+    layers_cached: usize,
+
+    /// The number of bytes downloaded across all layers.
+    bytes_downloaded: u64,
+
+    /// How long the pull took, in milliseconds.
+    duration_ms: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl PullSummary {
+    /// Creates a summary of a pull from the number of bytes downloaded for each layer.
+    ///
+    /// Layers nothing had to be downloaded for count as cached.
+    ///
+    /// ## Arguments
+    ///
+    /// * `reference` - The reference of the pulled image
+    /// * `digest` - The digest of the manifest the reference resolved to
+    /// * `layer_downloads` - The number of bytes downloaded for each layer of the image
+    /// * `duration` - How long the pull took
+    pub(crate) fn new(
+        reference: &Reference,
+        digest: Option<String>,
+        layer_downloads: &[u64],
+        duration: Duration,
+    ) -> Self {
+        let layers_pulled = layer_downloads.iter().filter(|bytes| **bytes > 0).count();
+
+        Self {
+            reference: reference.to_string(),
+            digest,
+            layers_pulled,
+            layers_cached: layer_downloads.len() - layers_pulled,
+            bytes_downloaded: layer_downloads.iter().sum(),
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+
+    /// Creates a summary of a pull of an image whose layers were all cached already.
+    pub(crate) fn cached(reference: &Reference, layers: usize, duration: Duration) -> Self {
+        Self::new(reference, None, &vec![0; layers], duration)
+    }
+
+    /// Returns whether the image was already cached, so that no layer had to be downloaded.
+    pub fn is_cached(&self) -> bool {
+        self.layers_pulled == 0
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_pull_summary_with_new_and_cached_layer() -> anyhow::Result<()> {
+        let reference = Reference::from_str("docker.io/library/alpine:3.20")?;
+        let digest = format!("sha256:{}", "a".repeat(64));
+
+        // One layer is downloaded and the other is already in the cache
+        let summary = PullSummary::new(
+            &reference,
+            Some(digest.clone()),
+            &[3_623_807, 0],
+            Duration::from_millis(1532),
+        );
+
+        assert_eq!(
+            serde_json::to_value(&summary)?,
+            json!({
+                "reference": reference.to_string(),
+                "digest": digest,
+                "layers_pulled": 1,
+                "layers_cached": 1,
+                "bytes_downloaded": 3_623_807,
+                "duration_ms": 1532,
+            })
+        );
+
+        let summary = PullSummary::cached(&reference, 2, Duration::from_millis(4));
+        assert_eq!(summary.get_digest(), &None);
+        assert_eq!(summary.get_layers_pulled(), &0);
+        assert_eq!(summary.get_layers_cached(), &2);
+        assert_eq!(summary.get_bytes_downloaded(), &0);
+
+        Ok(())
+    }
+}
@@ -1,33 +1,249 @@
 use core::fmt;
-use std::{ops::Deref, str::FromStr};
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
+use microsandbox_utils::{
+    DEFAULT_OCI_REFERENCE_REPO_NAMESPACE, DEFAULT_OCI_REFERENCE_TAG, DEFAULT_OCI_REGISTRY, env,
+};
 use serde;
 
 use crate::MicrosandboxError;
 
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// Legacy hostname of the Docker Hub registry, equivalent to [`DEFAULT_OCI_REGISTRY`].
+pub(crate) const LEGACY_DOCKER_HUB_REGISTRY: &str = "index.docker.io";
+
+/// The scheme of references to images in an OCI image layout on disk, e.g. `oci:/images/app:1.0`.
+pub const OCI_LAYOUT_SCHEME: &str = "oci:";
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
 
 /// Represents an OCI-compliant image reference.
+///
+/// Besides images in registries, a reference can point to an image in an OCI image layout on
+/// disk with the `oci:` scheme, e.g. `oci:/images/app:1.0`, see [`Reference::layout`].
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 #[serde(try_from = "String")]
 #[serde(into = "String")]
 pub struct Reference {
     reference: oci_client::Reference,
+
+    /// The absolute path of the OCI image layout the image is in, if it isn't in a registry.
+    layout: Option<PathBuf>,
 }
 
+/// What a reference selects within its repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceSelector<'a> {
+    /// A tag, e.g. `latest`.
+    Tag(&'a str),
+
+    /// A content digest, e.g. `sha256:...`.
+    Digest(&'a str),
+}
+
+/// Builds a [`Reference`] from its parts.
+///
+/// ## Examples
+///
+/// ```
+/// use microsandbox_core::oci::Reference;
+///
+/// let reference = Reference::builder()
+///     .registry("registry.internal:5000")
+///     .namespace("team")
+///     .name("app")
+///     .tag("1.0")
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(reference.to_string(), "registry.internal:5000/team/app:1.0");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ReferenceBuilder {
+    registry: Option<String>,
+    namespace: Option<String>,
+    name: Option<String>,
+    tag: Option<String>,
+    digest: Option<String>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
 impl Reference {
+    /// Creates a new builder for a [`Reference`].
+    pub fn builder() -> ReferenceBuilder {
+        ReferenceBuilder::default()
+    }
+
     /// Create an [`oci_client::Reference`] from [`Reference`].
     pub fn as_oci_reference(&self) -> oci_client::Reference {
         self.reference.clone()
     }
 
+    /// Returns the registry host, including the port if there is one, e.g. `docker.io` or
+    /// `registry.internal:5000`.
+    pub fn registry(&self) -> &str {
+        self.reference.registry()
+    }
+
+    /// Returns the repository path within the registry, e.g. `library/nginx` or `team/app`.
+    pub fn repository(&self) -> &str {
+        self.reference.repository()
+    }
+
+    /// Returns the part of the repository path before its name, e.g. `library` for
+    /// `docker.io/library/nginx`, or None if the repository has no namespace.
+    pub fn namespace(&self) -> Option<&str> {
+        self.repository()
+            .rsplit_once('/')
+            .map(|(namespace, _)| namespace)
+    }
+
+    /// Returns the last component of the repository path, e.g. `nginx` for
+    /// `docker.io/library/nginx:latest`.
+    pub fn name(&self) -> &str {
+        let repository = self.repository();
+        repository
+            .rsplit_once('/')
+            .map_or(repository, |(_, name)| name)
+    }
+
+    /// Returns the directory of the OCI image layout the image is in, or None if the image is in
+    /// a registry.
+    ///
+    /// A layout reference has no registry, its repository is the name of the layout directory,
+    /// and it selects the image tagged with its tag, `latest` if it has none.
+    pub fn layout(&self) -> Option<&Path> {
+        self.layout.as_deref()
+    }
+
+    /// Returns what the reference selects within the repository.
+    ///
+    /// A digest takes precedence over a tag since it pins the exact image, and a reference with
+    /// neither selects the default `latest` tag.
+    pub fn selector(&self) -> ReferenceSelector<'_> {
+        match (self.reference.digest(), self.reference.tag()) {
+            (Some(digest), _) => ReferenceSelector::Digest(digest),
+            (None, Some(tag)) => ReferenceSelector::Tag(tag),
+            (None, None) => ReferenceSelector::Tag(DEFAULT_OCI_REFERENCE_TAG),
+        }
+    }
+
+    /// Returns the fully-qualified canonical form of the reference.
+    ///
+    /// Short forms such as `nginx`, `library/nginx`, `docker.io/nginx` and
+    /// `docker.io/library/nginx:latest` all normalize to `docker.io/library/nginx:latest`: the
+    /// registry is always present, Docker Hub repositories get the implicit `library/` namespace,
+    /// and the `latest` tag is made explicit unless the reference is pinned by digest alone.
+    pub fn normalize(&self) -> String {
+        if let Some(layout) = &self.layout {
+            let tag = self.reference.tag().unwrap_or(DEFAULT_OCI_REFERENCE_TAG);
+            return format!("{OCI_LAYOUT_SCHEME}{}:{tag}", layout.display());
+        }
+
+        let registry = match self.registry() {
+            LEGACY_DOCKER_HUB_REGISTRY => DEFAULT_OCI_REGISTRY,
+            registry => registry,
+        };
+
+        let repository = self.repository();
+        let mut normalized = if registry == DEFAULT_OCI_REGISTRY && !repository.contains('/') {
+            format!("{registry}/{DEFAULT_OCI_REFERENCE_REPO_NAMESPACE}/{repository}")
+        } else {
+            format!("{registry}/{repository}")
+        };
+
+        match (self.reference.tag(), self.reference.digest()) {
+            (Some(tag), _) => normalized.push_str(&format!(":{tag}")),
+            (None, None) => normalized.push_str(&format!(":{DEFAULT_OCI_REFERENCE_TAG}")),
+            (None, Some(_)) => {}
+        }
+
+        if let Some(digest) = self.reference.digest() {
+            normalized.push_str(&format!("@{digest}"));
+        }
+
+        normalized
+    }
+
+    /// Returns the key under which the image is stored in the database.
     pub(crate) fn as_db_key(&self) -> String {
-        self.reference.to_string()
+        self.normalize()
     }
 }
 
+impl ReferenceBuilder {
+    /// Sets the registry host. If not set, the default registry is used, see [`env::get_oci_registry`].
+    pub fn registry(mut self, registry: impl Into<String>) -> Self {
+        self.registry = Some(registry.into());
+        self
+    }
+
+    /// Sets the namespace, e.g. `team` or `org/team`.
+    pub fn namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.namespace = Some(namespace.into());
+        self
+    }
+
+    /// Sets the name of the repository, e.g. `app`.
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the tag.
+    pub fn tag(mut self, tag: impl Into<String>) -> Self {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Sets the digest, e.g. `sha256:...`.
+    pub fn digest(mut self, digest: impl Into<String>) -> Self {
+        self.digest = Some(digest.into());
+        self
+    }
+
+    /// Builds the reference, validating it the same way a parsed reference is.
+    pub fn build(self) -> Result<Reference, MicrosandboxError> {
+        let name = self
+            .name
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| MicrosandboxError::ImageReferenceError("missing name".to_string()))?;
+
+        // Without a registry, the default one is filled in like it is for a parsed short form
+        let mut reference = [self.registry, self.namespace, Some(name)]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if let Some(tag) = self.tag {
+            reference.push_str(&format!(":{tag}"));
+        }
+
+        if let Some(digest) = self.digest {
+            reference.push_str(&format!("@{digest}"));
+        }
+
+        reference.parse()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
 impl Deref for Reference {
     type Target = oci_client::Reference;
 
@@ -40,15 +256,23 @@ impl FromStr for Reference {
     type Err = MicrosandboxError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(layout) = s.strip_prefix(OCI_LAYOUT_SCHEME) {
+            return parse_layout_reference(layout);
+        }
+
         Ok(Reference {
-            reference: oci_client::Reference::from_str(s)?,
+            reference: oci_client::Reference::from_str(&qualify_reference(
+                s,
+                &env::get_oci_registry(),
+            ))?,
+            layout: None,
         })
     }
 }
 
 impl From<Reference> for String {
     fn from(reference: Reference) -> Self {
-        reference.reference.to_string()
+        reference.to_string()
     }
 }
 
@@ -56,14 +280,304 @@ impl TryFrom<String> for Reference {
     type Error = MicrosandboxError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        Ok(Reference {
-            reference: oci_client::Reference::try_from(value)?,
-        })
+        value.parse()
     }
 }
 
 impl fmt::Display for Reference {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.reference)
+        match self.layout {
+            Some(_) => write!(f, "{}", self.normalize()),
+            None => write!(f, "{}", self.reference),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Parses the part of an `oci:` reference after the scheme, a layout path with an optional tag.
+///
+/// Like skopeo's `oci:` transport, the tag is whatever follows the last `:`, unless it contains a
+/// `/` and so is part of the path. Relative paths are resolved against the current directory, so
+/// the reference keeps pointing to the same layout wherever it is used.
+fn parse_layout_reference(layout: &str) -> Result<Reference, MicrosandboxError> {
+    let (path, tag) = match layout.rsplit_once(':') {
+        Some((path, tag)) if !tag.is_empty() && !tag.contains('/') => (path, tag),
+        _ => (layout, DEFAULT_OCI_REFERENCE_TAG),
+    };
+
+    if path.is_empty() {
+        return Err(MicrosandboxError::ImageReferenceError(format!(
+            "missing OCI layout path in {OCI_LAYOUT_SCHEME}{layout}"
+        )));
+    }
+
+    let path = std::path::absolute(path).map_err(|e| {
+        MicrosandboxError::ImageReferenceError(format!("invalid OCI layout path {path}: {e}"))
+    })?;
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    Ok(Reference {
+        reference: oci_client::Reference::with_tag(String::new(), name, tag.to_string()),
+        layout: Some(path),
+    })
+}
+
+/// Prepends the default registry to a reference that doesn't name one.
+///
+/// Follows Docker's rules: the first path component is treated as a registry host only if it
+/// contains a `.` or a `:`, or is exactly `localhost`. Otherwise the reference belongs to the
+/// default registry, and single-component names on Docker Hub get the implicit `library/`
+/// namespace.
+///
+/// The default registry comes from `msb --registry` if it is given, and from the
+/// `OCI_REGISTRY_DOMAIN` environment variable otherwise, see [`env::get_oci_registry`].
+fn qualify_reference(reference: &str, registry: &str) -> String {
+    if let Some((first, _)) = reference.split_once('/')
+        && (first.contains('.') || first.contains(':') || first == "localhost")
+    {
+        return reference.to_string();
+    }
+
+    if !reference.contains('/') && registry == DEFAULT_OCI_REGISTRY {
+        return format!("{registry}/{DEFAULT_OCI_REFERENCE_REPO_NAMESPACE}/{reference}");
+    }
+
+    format!("{registry}/{reference}")
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_with_localhost_registry() {
+        let reference: Reference = "localhost:5000/x".parse().unwrap();
+        assert_eq!(reference.registry(), "localhost:5000");
+        assert_eq!(reference.repository(), "x");
+    }
+
+    #[test]
+    fn test_reference_with_custom_registry_and_path() {
+        let reference: Reference = "reg.io/team/app".parse().unwrap();
+        assert_eq!(reference.registry(), "reg.io");
+        assert_eq!(reference.repository(), "team/app");
+
+        let reference: Reference = "registry.internal:5000/team/app:tag".parse().unwrap();
+        assert_eq!(reference.registry(), "registry.internal:5000");
+        assert_eq!(reference.repository(), "team/app");
+        assert_eq!(reference.tag(), Some("tag"));
+    }
+
+    #[test]
+    fn test_reference_with_implicit_library_namespace() {
+        let reference: Reference = "busybox".parse().unwrap();
+        assert_eq!(reference.registry(), DEFAULT_OCI_REGISTRY);
+        assert_eq!(reference.repository(), "library/busybox");
+    }
+
+    #[test]
+    fn test_reference_normalize_short_forms() {
+        let forms = [
+            "nginx",
+            "nginx:latest",
+            "library/nginx",
+            "docker.io/nginx",
+            "docker.io/library/nginx",
+            "docker.io/library/nginx:latest",
+            "index.docker.io/library/nginx",
+        ];
+
+        for form in forms {
+            let reference: Reference = form.parse().unwrap();
+            assert_eq!(
+                reference.normalize(),
+                "docker.io/library/nginx:latest",
+                "{form}"
+            );
+            assert_eq!(reference.as_db_key(), reference.normalize());
+        }
+    }
+
+    #[test]
+    fn test_reference_normalize_keeps_tag_and_digest() {
+        let reference: Reference = "reg.io/team/app:1.0".parse().unwrap();
+        assert_eq!(reference.normalize(), "reg.io/team/app:1.0");
+
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let reference: Reference = format!("alpine@{digest}").parse().unwrap();
+        assert_eq!(
+            reference.normalize(),
+            format!("docker.io/library/alpine@{digest}")
+        );
+    }
+
+    #[test]
+    fn test_reference_with_namespace_on_default_registry() {
+        let reference: Reference = "team/app:1.0".parse().unwrap();
+        assert_eq!(reference.registry(), DEFAULT_OCI_REGISTRY);
+        assert_eq!(reference.repository(), "team/app");
+        assert_eq!(reference.tag(), Some("1.0"));
+    }
+
+    #[test]
+    fn test_reference_accessors_fully_qualified() {
+        let reference: Reference = "registry.internal:5000/org/team/app:1.0".parse().unwrap();
+        assert_eq!(reference.registry(), "registry.internal:5000");
+        assert_eq!(reference.repository(), "org/team/app");
+        assert_eq!(reference.namespace(), Some("org/team"));
+        assert_eq!(reference.name(), "app");
+        assert_eq!(reference.selector(), ReferenceSelector::Tag("1.0"));
+
+        let reference: Reference = "localhost:5000/app".parse().unwrap();
+        assert_eq!(reference.registry(), "localhost:5000");
+        assert_eq!(reference.namespace(), None);
+        assert_eq!(reference.name(), "app");
+    }
+
+    #[test]
+    fn test_reference_accessors_short_form() {
+        let reference: Reference = "nginx".parse().unwrap();
+        assert_eq!(reference.registry(), DEFAULT_OCI_REGISTRY);
+        assert_eq!(reference.repository(), "library/nginx");
+        assert_eq!(reference.namespace(), Some("library"));
+        assert_eq!(reference.name(), "nginx");
+        assert_eq!(
+            reference.selector(),
+            ReferenceSelector::Tag(DEFAULT_OCI_REFERENCE_TAG)
+        );
+
+        let reference: Reference = "team/app:2".parse().unwrap();
+        assert_eq!(reference.namespace(), Some("team"));
+        assert_eq!(reference.name(), "app");
+        assert_eq!(reference.selector(), ReferenceSelector::Tag("2"));
+    }
+
+    #[test]
+    fn test_reference_accessors_digest_pinned() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+
+        let reference: Reference = format!("alpine@{digest}").parse().unwrap();
+        assert_eq!(reference.name(), "alpine");
+        assert_eq!(reference.selector(), ReferenceSelector::Digest(&digest));
+
+        // The digest pins the image even when a tag is given too
+        let reference: Reference = format!("reg.io/team/app:1.0@{digest}").parse().unwrap();
+        assert_eq!(reference.registry(), "reg.io");
+        assert_eq!(reference.namespace(), Some("team"));
+        assert_eq!(reference.selector(), ReferenceSelector::Digest(&digest));
+    }
+
+    #[test]
+    fn test_reference_builder() {
+        let reference = Reference::builder()
+            .registry("registry.internal:5000")
+            .namespace("team")
+            .name("app")
+            .tag("1.0")
+            .build()
+            .unwrap();
+        assert_eq!(
+            reference,
+            "registry.internal:5000/team/app:1.0".parse().unwrap()
+        );
+
+        // Without a registry the default one is used, like for a parsed short form
+        let reference = Reference::builder().name("nginx").build().unwrap();
+        assert_eq!(reference, "nginx".parse().unwrap());
+
+        let digest = format!("sha256:{}", "b".repeat(64));
+        let reference = Reference::builder()
+            .registry("reg.io")
+            .name("app")
+            .digest(&digest)
+            .build()
+            .unwrap();
+        assert_eq!(reference.selector(), ReferenceSelector::Digest(&digest));
+
+        assert!(Reference::builder().tag("1.0").build().is_err());
+        assert!(Reference::builder().name("App").build().is_err());
+    }
+
+    #[test]
+    fn test_reference_qualify_with_default_registry() {
+        // A registry named in the reference wins over the default registry
+        assert_eq!(
+            qualify_reference("reg.io/team/app", "mirror.internal"),
+            "reg.io/team/app"
+        );
+        assert_eq!(
+            qualify_reference("localhost/app", "mirror.internal"),
+            "localhost/app"
+        );
+
+        // Otherwise the default registry is prepended, without Docker Hub's `library/` namespace
+        assert_eq!(
+            qualify_reference("team/app", "mirror.internal"),
+            "mirror.internal/team/app"
+        );
+        assert_eq!(
+            qualify_reference("alpine:3.20", "mirror.internal"),
+            "mirror.internal/alpine:3.20"
+        );
+        assert_eq!(
+            qualify_reference("alpine", DEFAULT_OCI_REGISTRY),
+            format!("{DEFAULT_OCI_REGISTRY}/library/alpine")
+        );
+    }
+
+    #[test]
+    fn test_reference_oci_layout() {
+        let reference: Reference = "oci:/images/app:1.0".parse().unwrap();
+        assert_eq!(reference.layout(), Some(Path::new("/images/app")));
+        assert_eq!(reference.registry(), "");
+        assert_eq!(reference.name(), "app");
+        assert_eq!(reference.selector(), ReferenceSelector::Tag("1.0"));
+        assert_eq!(reference.to_string(), "oci:/images/app:1.0");
+        assert_eq!(reference.as_db_key(), "oci:/images/app:1.0");
+
+        // Without a tag the `latest` tag is selected
+        let reference: Reference = "oci:/images/app".parse().unwrap();
+        assert_eq!(reference.selector(), ReferenceSelector::Tag("latest"));
+        assert_eq!(reference.to_string(), "oci:/images/app:latest");
+
+        // A `:` followed by a path component is part of the path
+        let reference: Reference = "oci:/mnt/c:/images/app".parse().unwrap();
+        assert_eq!(reference.layout(), Some(Path::new("/mnt/c:/images/app")));
+
+        // Relative paths are made absolute
+        let reference: Reference = "oci:app:1.0".parse().unwrap();
+        assert_eq!(
+            reference.layout(),
+            Some(std::env::current_dir().unwrap().join("app").as_path())
+        );
+
+        // Layout references survive a round trip through their string form
+        let reference: Reference = "oci:/images/app:1.0".parse().unwrap();
+        assert_eq!(
+            String::from(reference.clone())
+                .parse::<Reference>()
+                .unwrap(),
+            reference
+        );
+
+        assert!("oci:".parse::<Reference>().is_err());
+        assert!("oci::1.0".parse::<Reference>().is_err());
+        assert!(
+            "docker.io/library/alpine"
+                .parse::<Reference>()
+                .unwrap()
+                .layout()
+                .is_none()
+        );
     }
 }
@@ -1,15 +1,32 @@
-use std::{str::FromStr, sync::Arc};
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use futures::{
     StreamExt,
     future::{self, try_join_all},
     stream::BoxStream,
 };
+use getset::Getters;
+use microsandbox_utils::{
+    DEFAULT_OCI_REGISTRY_CONNECT_TIMEOUT_SECS, DEFAULT_OCI_REGISTRY_READ_TIMEOUT_SECS, env,
+    term::MultiItemProgress,
+};
 use oci_client::{
     Client as OciClient,
-    client::{BlobResponse, ClientConfig as OciClientConfig, Config as OciConfig, LayerDescriptor},
+    client::{
+        BlobResponse, Certificate, CertificateEncoding, ClientConfig as OciClientConfig,
+        ClientProtocol, Config as OciConfig, LayerDescriptor,
+    },
     config::ConfigFile as OciConfigFile,
+    errors::{OciDistributionError, OciErrorCode},
     manifest::{ImageIndexEntry, OciImageManifest, OciManifest},
     secrets::RegistryAuth,
 };
@@ -19,18 +36,24 @@ use tokio::{
     fs::{self, OpenOptions},
     io::AsyncWriteExt,
 };
+use tracing::Span;
 
 use crate::{
     MicrosandboxError, MicrosandboxResult,
     management::db,
-    oci::{Reference, global_cache::GlobalCacheOps, image::Image, layer::LayerOps},
+    oci::{
+        ImageRewrites, LayerDependencies, PullSummary, Reference,
+        credential_store::{MsbRegistryAuth, RegistryCredentials},
+        global_cache::GlobalCacheOps,
+        image::Image,
+        layer::LayerOps,
+        layout, timing,
+    },
     utils,
 };
 
 #[cfg(feature = "cli")]
-use indicatif::{ProgressBar, ProgressStyle};
-#[cfg(feature = "cli")]
-use microsandbox_utils::term::{self, MULTI_PROGRESS};
+use microsandbox_utils::term;
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -40,12 +63,37 @@ use microsandbox_utils::term::{self, MULTI_PROGRESS};
 /// Spinner message used for fetching image details.
 const FETCH_IMAGE_DETAILS_MSG: &str = "Fetch image details";
 
-#[cfg(feature = "cli")]
-/// Spinner message used for downloading layers.
+/// Progress label used for downloading layers.
 const DOWNLOAD_LAYER_MSG: &str = "Download layers";
 
 pub(crate) const DOCKER_REFERENCE_TYPE_ANNOTATION: &str = "vnd.docker.reference.type";
 
+/// The maximum number of characters of a registry response body kept in errors.
+const REGISTRY_BODY_SNIPPET_LEN: usize = 256;
+
+/// The hint given when a registry returns a manifest list where a single manifest was expected.
+const MANIFEST_LIST_HINT: &str = "manifest list returned; platform selection needed";
+
+/// The hint given when a registry answers a manifest request with a body that isn't a manifest.
+const NOT_A_MANIFEST_HINT: &str = "the response is not a manifest, such as an HTML page or a registry error; check the registry address and any proxy in between";
+
+/// What serde reports when a body matches none of the manifest types.
+const NOT_A_MANIFEST_ERROR: &str = "did not match any variant of untagged enum";
+
+/// The most times a rate limited registry request is retried.
+const RATE_LIMIT_MAX_RETRIES: u32 = 3;
+
+/// The wait before the first retry of a rate limited request when the registry doesn't say how
+/// long to wait, which doubles with every retry.
+const RATE_LIMIT_INITIAL_WAIT: Duration = Duration::from_secs(5);
+
+/// The longest a rate limited registry request waits before it is retried, whatever the registry
+/// asks for.
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// The longest to wait for the registry to say how long to wait before retrying.
+const RATE_LIMIT_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
 /// Registry is an abstraction over the logic for fetching images from a registry,
 /// and storing them in a local cache.
 ///
@@ -55,14 +103,57 @@ pub(crate) const DOCKER_REFERENCE_TYPE_ANNOTATION: &str = "vnd.docker.reference.
 pub struct Registry<C: GlobalCacheOps> {
     client: OciClient,
 
-    /// TODO (333): Support varying auth methods.
-    auth: RegistryAuth,
+    /// Clients for registry hosts that trust extra CA certificates, keyed by host.
+    host_clients: HashMap<String, OciClient>,
+
+    /// The credentials used for each registry host, which is pulled from anonymously without any.
+    credentials: RegistryCredentials,
 
     /// The database where image configurations, and manifests are stored.
     db: Pool<Sqlite>,
 
+    /// The platform images are pulled for.
+    platform: Platform,
+
     /// Abstraction for interacting with the global microsandbox cache.
     global_cache: C,
+
+    /// The rules that rewrite the references images are pulled from, e.g. to go through a mirror.
+    image_rewrites: ImageRewrites,
+
+    /// Whether layers are extracted straight from the registry response instead of from a
+    /// downloaded file.
+    stream_layers: bool,
+}
+
+/// TLS options for pulling from self-hosted registries.
+///
+/// Both options are keyed by registry host as it appears in the image reference, including the
+/// port, e.g. `registry.internal:5000`.
+#[derive(Debug, Clone, Default, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct RegistryTlsConfig {
+    /// Hosts that are pulled from over plain HTTP, which skips TLS verification entirely.
+    pub(crate) insecure_hosts: Vec<String>,
+
+    /// Paths to extra CA certificates (PEM or DER) trusted when talking to a given host.
+    pub(crate) ca_certs: HashMap<String, PathBuf>,
+}
+
+/// Timeouts applied to every request to a registry, so a registry that stops answering fails the
+/// pull instead of stalling it.
+///
+/// There is no limit on how long a whole request may take, as large layers can take a long time
+/// to download over a slow but working connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct RegistryTimeouts {
+    /// How long a registry has to accept a connection within.
+    pub(crate) connect: Duration,
+
+    /// How long a registry may go without sending any data, including before it starts
+    /// answering a request.
+    pub(crate) read: Duration,
 }
 
 impl<O> Registry<O>
@@ -76,26 +167,178 @@ where
     /// * `db` - The database where image configurations, and manifests are stored
     /// * `platform` - The platform for which the image is being downloaded
     /// * `global_cache` - The global layer cache
+    ///
+    /// TLS options and timeouts are read from the environment, see [`RegistryTlsConfig::from_env`]
+    /// and [`RegistryTimeouts::from_env`].
     pub async fn new(
         db: Pool<Sqlite>,
         platform: Platform,
         global_cache: O,
     ) -> MicrosandboxResult<Self> {
-        let config = OciClientConfig {
-            platform_resolver: Some(Box::new(move |manifests| {
-                Self::resolve_digest_for_platform(platform.clone(), manifests)
-            })),
-            ..Default::default()
-        };
+        Self::with_tls_config(db, platform, global_cache, RegistryTlsConfig::from_env()).await
+    }
+
+    /// Creates a new Docker Registry client with explicit TLS options for self-hosted registries.
+    ///
+    /// ## Arguments
+    ///
+    /// * `db` - The database where image configurations, and manifests are stored
+    /// * `platform` - The platform for which the image is being downloaded
+    /// * `global_cache` - The global layer cache
+    /// * `tls` - The per-host TLS options
+    ///
+    /// Timeouts are read from the environment, see [`RegistryTimeouts::from_env`].
+    pub async fn with_tls_config(
+        db: Pool<Sqlite>,
+        platform: Platform,
+        global_cache: O,
+        tls: RegistryTlsConfig,
+    ) -> MicrosandboxResult<Self> {
+        Self::with_connection_config(
+            db,
+            platform,
+            global_cache,
+            tls,
+            RegistryTimeouts::from_env(),
+        )
+        .await
+    }
+
+    /// Creates a new Docker Registry client with explicit TLS options and timeouts.
+    ///
+    /// ## Arguments
+    ///
+    /// * `db` - The database where image configurations, and manifests are stored
+    /// * `platform` - The platform for which the image is being downloaded
+    /// * `global_cache` - The global layer cache
+    /// * `tls` - The per-host TLS options
+    /// * `timeouts` - The timeouts applied to every request
+    pub async fn with_connection_config(
+        db: Pool<Sqlite>,
+        platform: Platform,
+        global_cache: O,
+        tls: RegistryTlsConfig,
+        timeouts: RegistryTimeouts,
+    ) -> MicrosandboxResult<Self> {
+        for host in &tls.insecure_hosts {
+            tracing::warn!(
+                "TLS verification is DISABLED for registry {host}; it will be pulled from over plain HTTP"
+            );
+        }
+
+        let client = OciClient::new(
+            Self::build_client_config(platform.clone(), &tls, &timeouts, None).await?,
+        );
+        let mut host_clients = HashMap::new();
+        for host in tls.ca_certs.keys() {
+            let config =
+                Self::build_client_config(platform.clone(), &tls, &timeouts, Some(host)).await?;
+            host_clients.insert(host.clone(), OciClient::new(config));
+        }
 
         Ok(Self {
-            client: OciClient::new(config),
-            auth: RegistryAuth::Anonymous,
+            client,
+            host_clients,
+            credentials: RegistryCredentials::default(),
             db,
+            platform,
             global_cache,
+            image_rewrites: ImageRewrites::default(),
+            stream_layers: false,
+        })
+    }
+
+    /// Sets the rules that rewrite the references images are pulled from.
+    ///
+    /// Images are still recorded under the reference they were pulled with, so only the
+    /// registry that is contacted changes.
+    pub fn with_image_rewrites(mut self, image_rewrites: ImageRewrites) -> Self {
+        self.image_rewrites = image_rewrites;
+        self
+    }
+
+    /// Sets whether layers are extracted straight from the registry response, without writing
+    /// their tar to disk first.
+    ///
+    /// This halves the disk IO of a pull, but layers are downloaded one after the other since
+    /// each is extracted on top of the ones below it. Layers are still downloaded to files when a
+    /// blob cache is configured, so they can be shared with later pulls.
+    pub fn with_stream_layers(mut self, stream_layers: bool) -> Self {
+        self.stream_layers = stream_layers;
+        self
+    }
+
+    /// Sets the credentials used to pull from registries.
+    ///
+    /// Credentials are looked up by the exact registry host an image is pulled from, after image
+    /// rewrites, so they are never sent to another host.
+    pub fn with_credentials(mut self, credentials: RegistryCredentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Builds the OCI client configuration for the given registry host.
+    ///
+    /// ## Arguments
+    ///
+    /// * `platform` - The platform for which the image is being downloaded
+    /// * `tls` - The per-host TLS options
+    /// * `timeouts` - The timeouts applied to every request
+    /// * `host` - The registry host whose extra CA certificate should be trusted, if any
+    pub(crate) async fn build_client_config(
+        platform: Platform,
+        tls: &RegistryTlsConfig,
+        timeouts: &RegistryTimeouts,
+        host: Option<&str>,
+    ) -> MicrosandboxResult<OciClientConfig> {
+        let protocol = if tls.insecure_hosts.is_empty() {
+            ClientProtocol::Https
+        } else {
+            ClientProtocol::HttpsExcept(tls.insecure_hosts.clone())
+        };
+
+        let mut extra_root_certificates = Vec::new();
+        if let Some(path) = host.and_then(|host| tls.ca_certs.get(host)) {
+            let data = fs::read(path).await?;
+            let encoding = if data.starts_with(b"-----BEGIN") {
+                CertificateEncoding::Pem
+            } else {
+                CertificateEncoding::Der
+            };
+
+            extra_root_certificates.push(Certificate { encoding, data });
+        }
+
+        Ok(OciClientConfig {
+            protocol,
+            extra_root_certificates,
+            connect_timeout: Some(timeouts.connect),
+            read_timeout: Some(timeouts.read),
+            platform_resolver: Some(Box::new(move |manifests| {
+                resolve_digest_for_platform(platform.clone(), manifests)
+            })),
+            ..Default::default()
         })
     }
 
+    /// Returns the client to use for the registry host of the given reference.
+    fn client_for(&self, reference: &Reference) -> &OciClient {
+        self.host_clients
+            .get(reference.registry())
+            .unwrap_or(&self.client)
+    }
+
+    /// Returns the credentials to use for the registry host of the given reference.
+    fn auth_for(&self, reference: &Reference) -> RegistryAuth {
+        match self.credentials.for_reference(reference) {
+            Some(MsbRegistryAuth::Basic { username, password }) => {
+                RegistryAuth::Basic(username.clone(), password.clone())
+            }
+            Some(MsbRegistryAuth::Token(token)) => RegistryAuth::Bearer(token.clone()),
+            None => RegistryAuth::Anonymous,
+        }
+    }
+
     /// Returns the global layer cache.
     pub fn global_cache(&self) -> &O {
         &self.global_cache
@@ -118,48 +361,69 @@ where
         digest: &Digest,
         expected_size: u64,
     ) -> MicrosandboxResult<Arc<dyn LayerOps>> {
-        #[cfg(feature = "cli")]
-        let progress_bar = {
-            let pb = MULTI_PROGRESS.add(ProgressBar::new(expected_size));
-            let style = ProgressStyle::with_template(
-                "{prefix:.bold.dim} {bar:40.green/green.dim} {bytes:.bold} / {total_bytes:.dim}",
-            )
-            .unwrap()
-            .progress_chars("=+-");
-
-            pb.set_style(style);
-            // first 8 chars of sha part
-            let digest_short = digest.digest().get(..8).unwrap_or("");
-            pb.set_prefix(digest_short.to_string());
-            pb.clone()
-        };
+        let progress = MultiItemProgress::new(DOWNLOAD_LAYER_MSG, cfg!(feature = "cli"));
+        self.download_image_blob_with_progress(reference, digest, expected_size, &progress)
+            .await
+            .map(|(layer, _)| layer)
+    }
+
+    /// Downloads a blob from the registry like [`Registry::download_image_blob`], reporting the
+    /// download as an item of `progress`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the layer along with the number of bytes downloaded for it, which is zero when the
+    /// layer was already cached.
+    async fn download_image_blob_with_progress(
+        &self,
+        reference: &Reference,
+        digest: &Digest,
+        expected_size: u64,
+        progress: &MultiItemProgress,
+    ) -> MicrosandboxResult<(Arc<dyn LayerOps>, u64)> {
+        // first 8 chars of sha part
+        let digest_short = digest.digest().get(..8).unwrap_or("");
+        let item = progress.start_item(digest_short, expected_size);
 
         let layer = self.global_cache.build_layer(digest).await;
-        #[cfg(feature = "cli")]
-        {
-            // If we already have some bytes downloaded, reflect that on the progress bar.
-            let downloaded_so_far = layer.get_tar_size().unwrap_or(0);
-            progress_bar.set_position(downloaded_so_far);
+
+        // The download path of a layer is shared with other pulls of it, in this process or
+        // another, so wait for any of them that is downloading it, after which it is usually
+        // downloaded already
+        let _layer_lock = self.global_cache.lock_layer(digest).await?;
+
+        // Skip the download entirely if the layer is already in the cache, checking its digest so a
+        // corrupted or different blob of the same size is downloaded again
+        if layer.tar_verified(expected_size).await? {
+            tracing::info!(?digest, "Layer already exists. Skipping download");
+            item.set_position(expected_size);
+            item.finish();
+            return Ok((layer, 0));
         }
 
         // Ensure the destination directory exists
-        let download_path = layer.tar_path();
+        let download_path = layer.download_path();
         if let Some(parent) = download_path.parent() {
             fs::create_dir_all(parent).await?;
         }
 
-        let (mut file, mut existing_size) = (OpenOptions::new(), 0);
-        match layer.get_tar_size() {
-            // If the layer was completely downloaded, skip re-download
+        let downloaded_size = fs::metadata(&download_path).await.ok().map(|m| m.len());
+
+        // If we already have some bytes downloaded, reflect that on the progress
+        item.set_position(downloaded_size.unwrap_or(0));
+
+        let (mut file, mut existing_size, mut downloaded_bytes) = (OpenOptions::new(), 0, 0);
+        match downloaded_size {
+            // If the layer was completely downloaded, only verify and store it
             Some(size) if size == expected_size => {
-                tracing::info!(?digest, "Layer already exists. Skipping download");
-                return Ok(layer);
+                tracing::info!(?digest, "Layer already downloaded. Skipping download");
+                existing_size = size;
             }
 
             // Open the file for writing, create if it doesn't exist
             None | Some(0) => {
                 tracing::info!(?digest, ?download_path, "Layer doesn't exist. Downloading");
-                file.create(true).truncate(true).write(true)
+                file.create(true).truncate(true).write(true);
             }
 
             Some(current_size) => {
@@ -171,26 +435,25 @@ where
                     "Layer exists but is incomplete. Resuming download"
                 );
                 existing_size = current_size;
-                file.append(true)
+                file.append(true);
             }
         };
 
-        let mut file = file.open(&download_path).await?;
-        let mut stream = self
-            .fetch_digest_blob(reference, digest, existing_size, None)
-            .await?;
+        if existing_size < expected_size {
+            let mut file = file.open(&download_path).await?;
+            let mut stream = self
+                .fetch_digest_blob(reference, digest, existing_size, None)
+                .await?;
 
-        // Write the stream to the file
-        while let Some(chunk) = stream.next().await {
-            let bytes = chunk?;
-            file.write_all(&bytes).await?;
-            #[cfg(feature = "cli")]
-            progress_bar.inc(bytes.len() as u64);
+            // Write the stream to the file
+            while let Some(chunk) = stream.next().await {
+                let bytes = chunk?;
+                file.write_all(&bytes).await?;
+                item.inc(bytes.len() as u64);
+                downloaded_bytes += bytes.len() as u64;
+            }
         }
 
-        #[cfg(feature = "cli")]
-        progress_bar.finish_and_clear();
-
         // Verify the hash of the downloaded file
         let algorithm = digest.algorithm();
         let expected_hash = digest.digest();
@@ -204,59 +467,69 @@ where
             )));
         }
 
+        // Share the verified layer with later pulls
+        layer.store_download().await?;
+
         let layer = self
             .global_cache
             .get_downloaded_layer(digest)
             .await
             .expect("layer should be present in cache after download");
 
+        item.finish();
         tracing::info!(?digest, "layer downloaded and cached successfully");
-        Ok(layer)
-    }
-
-    /// Filters through all image index manifests and returns the digest of the
-    /// manifest that matches the platform specified.
-    ///
-    /// ## Arguments
-    ///
-    /// * `platform` - The platform for which the image is being downloaded
-    /// * `manifests` - The list of manifests for the image index
-    fn resolve_digest_for_platform(
-        platform: Platform,
-        manifests: &[ImageIndexEntry],
-    ) -> Option<String> {
-        manifests
-            .iter()
-            // First priority: match both OS and architecture
-            .find(|m| {
-                m.platform.as_ref().is_some_and(|p| {
-                    p.os == *platform.os()    &&
-                    p.architecture == *platform.architecture() &&
-                    // Skip attestation manifests
-                    !m.annotations.as_ref().is_some_and(|a| a.contains_key(DOCKER_REFERENCE_TYPE_ANNOTATION))
-                })
-            })
-            // Second priority: match architecture only, if no Linux match found
-            .or_else(|| {
-                manifests.iter().find(|m| {
-                    m.platform.as_ref().is_some_and(|p| {
-                        p.architecture == *platform.architecture() &&
-                        !m.annotations.as_ref().is_some_and(|a| a.contains_key(DOCKER_REFERENCE_TYPE_ANNOTATION))
-                    })
-                })
-            })
-            .map(|m| m.digest.clone())
+        Ok((layer, downloaded_bytes))
     }
 
     /// Pulls an OCI image from the specified repository, and This includes downloading
     /// the image manifest, fetching the image configuration, and downloading the image layers.
     ///
     /// The image can be selected either by tag or digest using the [`ReferenceSelector`] enum.
-    pub(crate) async fn pull_image(&self, reference: &Reference) -> MicrosandboxResult<()> {
-        // Check if all layers are extracted before proceeding to fetch and extract
-        if self.global_cache().all_layers_extracted(reference).await? {
+    ///
+    /// With `no_cache`, the check for already extracted layers is skipped and the image's cached
+    /// layers are cleared first, so every layer is downloaded and extracted again.
+    ///
+    /// A reference to an OCI image layout on disk is imported from the layout without contacting
+    /// any registry, see [`Reference::layout`].
+    ///
+    /// Otherwise, the image is pulled from the reference the image rewrite rules map it to, see
+    /// [`Registry::with_image_rewrites`], and recorded under `reference`.
+    ///
+    /// Returns a summary of what was pulled.
+    pub(crate) async fn pull_image(
+        &self,
+        reference: &Reference,
+        no_cache: bool,
+    ) -> MicrosandboxResult<PullSummary> {
+        let started = Instant::now();
+        if no_cache {
+            self.clear_cached_layers(reference).await?;
+        } else if self.global_cache().all_layers_extracted(reference).await? {
+            // Check if all layers are extracted before proceeding to fetch and extract
             tracing::info!(?reference, "Image was already extracted");
-            return Ok(());
+            let layers = db::get_image_layer_digests(&self.db, &reference.as_db_key())
+                .await?
+                .len();
+            return Ok(PullSummary::cached(reference, layers, started.elapsed()));
+        }
+
+        // Images in a layout on disk are imported from it instead of the network
+        if let Some(layout_dir) = reference.layout() {
+            return layout::pull_layout_image(
+                &self.db,
+                &self.platform,
+                &self.global_cache,
+                reference,
+                layout_dir,
+                started,
+            )
+            .await;
+        }
+
+        // Pull through a mirror if a rewrite rule says so
+        let source = self.image_rewrites.rewrite(reference)?;
+        if source != *reference {
+            tracing::info!(%reference, %source, "rewrote image reference");
         }
 
         // Calculate total size and save image record
@@ -264,64 +537,203 @@ where
         let fetch_details_sp =
             term::create_spinner(FETCH_IMAGE_DETAILS_MSG.to_string(), None, None);
 
-        let index = self.fetch_index(reference).await?;
-        let size = match index {
-            OciManifest::Image(m) => m.config.size,
-            OciManifest::ImageIndex(m) => m.manifests.iter().map(|m| m.size).sum(),
-        };
-        let image_id = db::save_or_update_image(&self.db, &reference.as_db_key(), size).await?;
-
-        // Fetch and save manifest
-        let (manifest, config) = self.fetch_manifest_and_config(reference).await?;
-        let manifest_id = db::save_manifest(&self.db, image_id, &manifest).await?;
-        db::save_config(&self.db, manifest_id, &config).await?;
-
-        // First, write the layer info to disk. This guarantees that we
-        let diffs = config.rootfs.diff_ids.iter();
-        let layer_to_zip = manifest.layers.iter().zip(diffs);
-        let db_ops = layer_to_zip
-            .clone()
-            .map(|(layer, diff_id)| {
-                db::create_or_update_manifest_layer(&self.db, layer, diff_id, manifest_id)
+        // Fetch the index, manifest and config
+        let (size, (manifest, manifest_digest, config)) =
+            timing::measure(timing::phase_span("manifest", None), async {
+                let size = match self.fetch_index(&source).await? {
+                    OciManifest::Image(m) => m.config.size,
+                    OciManifest::ImageIndex(m) => m.manifests.iter().map(|m| m.size).sum(),
+                };
+                let manifest = self.fetch_manifest_and_config(&source).await?;
+                Ok::<_, MicrosandboxError>((size, manifest))
             })
-            .collect::<Vec<_>>();
-        try_join_all(db_ops).await?;
+            .await?;
+
+        // Save the image, manifest and config, and write the layer info to the database before
+        // downloading the layers
+        timing::measure(
+            timing::phase_span("db", None),
+            save_image_records(&self.db, reference, size, &manifest, &config),
+        )
+        .await?;
 
         #[cfg(feature = "cli")]
         fetch_details_sp.finish();
 
-        #[cfg(feature = "cli")]
-        let download_layers_sp = term::create_spinner(
-            DOWNLOAD_LAYER_MSG.to_string(),
-            None,
-            Some(manifest.layers.len() as u64),
-        );
+        let progress = MultiItemProgress::new(DOWNLOAD_LAYER_MSG, cfg!(feature = "cli"));
+
+        // Extract layers while they are downloaded if there is no blob cache to keep them in
+        if self.stream_layers && self.global_cache.blob_cache_dir().is_none() {
+            let layer_downloads = self
+                .stream_image_layers(&source, &manifest, &progress)
+                .await;
+            progress.finish();
+
+            return Ok(PullSummary::new(
+                reference,
+                Some(manifest_digest),
+                &layer_downloads?,
+                started.elapsed(),
+            ));
+        }
 
         // Download layers concurrently and save to database
-        let layer_futures: Vec<_> = layer_to_zip
-            .into_iter()
-            .map(|(layer, _diff_id)| async {
-                #[cfg(feature = "cli")]
-                download_layers_sp.inc(1);
-                let digest = Digest::from_str(&layer.digest)?;
-                let blob = self
-                    .download_image_blob(reference, &digest, layer.size as u64)
-                    .await?;
-
-                Ok::<_, MicrosandboxError>(blob)
+        let layer_futures: Vec<_> = manifest
+            .layers
+            .iter()
+            .map(|layer| {
+                let progress = &progress;
+                let source = &source;
+                let span = timing::phase_span("download", Some(layer.digest.as_str()));
+                timing::measure(span, async move {
+                    let digest = Digest::from_str(&layer.digest)?;
+                    let (blob, downloaded) = self
+                        .download_image_blob_with_progress(
+                            source,
+                            &digest,
+                            layer.size as u64,
+                            progress,
+                        )
+                        .await?;
+                    Span::current().record("bytes", downloaded);
+
+                    Ok::<_, MicrosandboxError>((blob, downloaded))
+                })
             })
             .collect();
 
         // Wait for all layers to be downloaded
-        let layers = future::join_all(layer_futures)
-            .await
+        let results = future::join_all(layer_futures).await;
+        progress.finish();
+        let (layers, layer_downloads): (Vec<_>, Vec<_>) = results
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
             .into_iter()
-            .collect::<Result<Vec<_>, _>>()?;
+            .unzip();
 
-        #[cfg(feature = "cli")]
-        download_layers_sp.finish();
+        Image::new(layers).extract_all().await?;
+
+        Ok(PullSummary::new(
+            reference,
+            Some(manifest_digest),
+            &layer_downloads,
+            started.elapsed(),
+        ))
+    }
+
+    /// Downloads and extracts the layers of an image, extracting each layer straight from the
+    /// registry response.
+    ///
+    /// Layers are handled in order, since extracting a layer looks up directories in the layers
+    /// below it. A layer whose tar was already downloaded, fully or partially, is extracted from
+    /// the file instead.
+    ///
+    /// ## Arguments
+    ///
+    /// * `reference` - The reference the layers are pulled from
+    /// * `manifest` - The manifest listing the layers, from the base layer up
+    /// * `progress` - The progress downloads are reported to
+    ///
+    /// ## Returns
+    ///
+    /// The number of bytes downloaded for each layer, which is zero for layers that were already
+    /// extracted.
+    async fn stream_image_layers(
+        &self,
+        reference: &Reference,
+        manifest: &OciImageManifest,
+        progress: &MultiItemProgress,
+    ) -> MicrosandboxResult<Vec<u64>> {
+        let mut layers: Vec<Arc<dyn LayerOps>> = Vec::with_capacity(manifest.layers.len());
+        let mut layer_downloads = Vec::with_capacity(manifest.layers.len());
+        for descriptor in &manifest.layers {
+            let digest = Digest::from_str(&descriptor.digest)?;
+            let expected_size = descriptor.size as u64;
+            let parent = LayerDependencies::new(digest.clone(), Image::new(layers.clone()))
+                .with_progress(progress.clone());
+            let layer = self.global_cache.build_layer(&digest).await;
+            let span = timing::phase_span("download", Some(descriptor.digest.as_str()));
+
+            let result = timing::measure(span, async {
+                let extracted = layer.extracted().await?.0;
+                let downloaded = if extracted {
+                    tracing::info!(?digest, "Layer already extracted. Skipping download");
+                    0
+                } else if layer.get_tar_size().is_some() || layer.download_path().exists() {
+                    let (blob, downloaded) = self
+                        .download_image_blob_with_progress(
+                            reference,
+                            &digest,
+                            expected_size,
+                            progress,
+                        )
+                        .await?;
+                    blob.extract(parent).await?;
+                    downloaded
+                } else {
+                    let stream = self.fetch_digest_blob(reference, &digest, 0, None).await?;
+                    layer
+                        .extract_from_stream(stream, expected_size, parent)
+                        .await?;
+                    expected_size
+                };
+                Span::current().record("bytes", downloaded);
+
+                Ok::<_, MicrosandboxError>(downloaded)
+            })
+            .await;
+
+            let downloaded = match result {
+                Ok(downloaded) => downloaded,
+                Err(err) => {
+                    tracing::error!(?err, "Extracting failed. Cleaning up extracted artifacts");
+                    layer.cleanup_extracted().await?;
+                    return Err(err);
+                }
+            };
+
+            layers.push(layer);
+            layer_downloads.push(downloaded);
+        }
+
+        Ok(layer_downloads)
+    }
+
+    /// Removes the extracted directories and tar files of the layers recorded for an image.
+    ///
+    /// Layers that are not recorded in the database yet have nothing cached to clear. Layers that
+    /// other images use as well are kept, and each layer is cleared under its layer lock, so a
+    /// concurrent pull never sees a layer half removed.
+    ///
+    /// ## Arguments
+    ///
+    /// * `reference` - The reference to the image whose layers should be cleared
+    async fn clear_cached_layers(&self, reference: &Reference) -> MicrosandboxResult<()> {
+        let layer_digests =
+            db::get_exclusive_image_layer_digests(&self.db, &reference.as_db_key()).await?;
+        for digest in &layer_digests {
+            let digest = Digest::from_str(digest)?;
+            let layer = self.global_cache.build_layer(&digest).await;
+
+            // Takes the layer lock itself
+            layer.cleanup_extracted().await?;
+
+            let _layer_lock = self.global_cache.lock_layer(&digest).await?;
+            for tar_path in [layer.tar_path(), layer.download_path()] {
+                match fs::remove_file(&tar_path).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
 
-        Image::new(layers).extract_all().await
+        tracing::info!(
+            ?reference,
+            layers = layer_digests.len(),
+            "cleared cached layers"
+        );
+        Ok(())
     }
 
     /// Fetches all available multi-platform manifests for the given reference.
@@ -337,7 +749,9 @@ where
         &self,
         reference: &Reference,
     ) -> MicrosandboxResult<OciManifest> {
-        let (index, _) = self.client.pull_manifest(reference, &self.auth).await?;
+        let client = self.client_for(reference);
+        let auth = self.auth_for(reference);
+        let (index, _) = with_rate_limit_retry(|| client.pull_manifest(reference, &auth)).await?;
         Ok(index)
     }
 
@@ -346,18 +760,21 @@ where
     /// ## Argumebts
     ///
     /// * `reference` - The reference to the repository and tag
+    ///
+    /// ## Returns
+    /// Returns the manifest, the digest of the manifest and the image configuration.
     pub(crate) async fn fetch_manifest_and_config(
         &self,
         reference: &Reference,
-    ) -> MicrosandboxResult<(OciImageManifest, OciConfigFile)> {
-        let (manifest, _, config) = self
-            .client
-            .pull_manifest_and_config(reference, &self.auth)
-            .await?;
+    ) -> MicrosandboxResult<(OciImageManifest, String, OciConfigFile)> {
+        let client = self.client_for(reference);
+        let auth = self.auth_for(reference);
+        let (manifest, digest, config) =
+            with_rate_limit_retry(|| client.pull_manifest_and_config(reference, &auth)).await?;
 
         let config = OciConfig::oci_v1(config.as_bytes().to_vec(), manifest.annotations.clone());
         let config = OciConfigFile::try_from(config)?;
-        Ok((manifest, config))
+        Ok((manifest, digest, config))
     }
 
     /// Fetches a image blob from the registry by its digest.
@@ -390,10 +807,11 @@ where
             urls: &None,
         };
 
-        let stream = self
-            .client
-            .pull_blob_stream_partial(reference, &layer, offset, length)
-            .await?;
+        let client = self.client_for(reference);
+        let stream = with_rate_limit_retry(|| {
+            client.pull_blob_stream_partial(reference, &layer, offset, length)
+        })
+        .await?;
 
         let stream = match stream {
             BlobResponse::Full(s) => s,
@@ -403,3 +821,329 @@ where
         Ok(stream.stream.map(|r| r.map_err(Into::into)).boxed())
     }
 }
+
+impl RegistryTlsConfig {
+    /// Reads the TLS options from the `OCI_INSECURE_REGISTRIES` and `OCI_REGISTRY_CA_CERTS`
+    /// environment variables.
+    pub fn from_env() -> Self {
+        Self {
+            insecure_hosts: env::get_oci_insecure_registries(),
+            ca_certs: env::get_oci_registry_ca_certs().into_iter().collect(),
+        }
+    }
+}
+
+impl RegistryTimeouts {
+    /// Reads the timeouts from the `OCI_REGISTRY_CONNECT_TIMEOUT_SECS` and
+    /// `OCI_REGISTRY_READ_TIMEOUT_SECS` environment variables.
+    pub fn from_env() -> Self {
+        Self {
+            connect: env::get_oci_registry_connect_timeout(),
+            read: env::get_oci_registry_read_timeout(),
+        }
+    }
+}
+
+impl Default for RegistryTimeouts {
+    fn default() -> Self {
+        Self {
+            connect: Duration::from_secs(DEFAULT_OCI_REGISTRY_CONNECT_TIMEOUT_SECS),
+            read: Duration::from_secs(DEFAULT_OCI_REGISTRY_READ_TIMEOUT_SECS),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Describes a registry response that could not be used, with a hint at what it means.
+///
+/// Registries answer with a manifest list where a single manifest was expected, or with an
+/// `{"errors": [...]}` envelope, and neither deserializes into what the client asked for. This
+/// looks at the status and body to tell the common cases apart.
+///
+/// ## Arguments
+///
+/// * `status` - The HTTP status of the response
+/// * `body` - The response body
+pub(crate) fn registry_response_error(status: u16, body: &str) -> MicrosandboxError {
+    MicrosandboxError::RegistryResponse {
+        status,
+        body_snippet: body_snippet(body),
+        hint: registry_response_hint(status, body).to_string(),
+    }
+}
+
+/// Maps registry client errors that carry a status and body to [`MicrosandboxError::RegistryResponse`],
+/// and requests that timed out to [`MicrosandboxError::RegistryTimeout`]. A successful response
+/// whose body isn't a manifest also becomes [`MicrosandboxError::RegistryResponse`], with a hint.
+///
+/// Other errors are passed through unchanged.
+pub(crate) fn registry_error(error: OciDistributionError) -> MicrosandboxError {
+    match error {
+        OciDistributionError::ServerError { code, message, .. } => {
+            registry_response_error(code, &message)
+        }
+        OciDistributionError::UnauthorizedError { .. } => registry_response_error(401, ""),
+        OciDistributionError::AuthenticationFailure(message) => {
+            registry_response_error(401, &message)
+        }
+        OciDistributionError::RequestError(error) if error.is_timeout() => {
+            MicrosandboxError::RegistryTimeout {
+                url: error.url().map(ToString::to_string).unwrap_or_default(),
+            }
+        }
+        OciDistributionError::ImageIndexParsingNoPlatformResolverError => {
+            MicrosandboxError::RegistryResponse {
+                status: 200,
+                body_snippet: String::new(),
+                hint: MANIFEST_LIST_HINT.to_string(),
+            }
+        }
+        error if error.to_string().contains(NOT_A_MANIFEST_ERROR) => {
+            MicrosandboxError::RegistryResponse {
+                status: 200,
+                body_snippet: String::new(),
+                hint: NOT_A_MANIFEST_HINT.to_string(),
+            }
+        }
+        error => error.into(),
+    }
+}
+
+/// Parses the value of a `Retry-After` header into how long to wait from `now`.
+///
+/// The value is either a number of seconds or an HTTP date. A date in the past means there is no
+/// need to wait.
+///
+/// ## Arguments
+///
+/// * `value` - The value of the header
+/// * `now` - The time to measure a date from
+pub(crate) fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
+/// Runs a registry request, waiting and retrying it while the registry rate limits it, with
+/// `fetch_retry_after` asking the registry how long to wait before retrying the request to a URL.
+///
+/// The wait follows what the registry asks for, capped at [`RATE_LIMIT_MAX_WAIT`]. When it doesn't
+/// say, the wait starts at [`RATE_LIMIT_INITIAL_WAIT`] and doubles with every retry. The request
+/// fails with [`MicrosandboxError::RateLimited`] once it has been retried
+/// [`RATE_LIMIT_MAX_RETRIES`] times. Other errors are mapped with [`registry_error`].
+pub(crate) async fn with_rate_limit_retry_with<T, F, Fut, R, RFut>(
+    mut request: F,
+    mut fetch_retry_after: R,
+) -> MicrosandboxResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OciDistributionError>>,
+    R: FnMut(String) -> RFut,
+    RFut: Future<Output = Option<Duration>>,
+{
+    let mut retries = 0;
+    loop {
+        let error = match request().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        let Some(url) = rate_limited_url(&error).map(ToString::to_string) else {
+            return Err(registry_error(error));
+        };
+
+        let retry_after = fetch_retry_after(url.clone()).await;
+        if retries >= RATE_LIMIT_MAX_RETRIES {
+            return Err(MicrosandboxError::RateLimited { retry_after });
+        }
+
+        let wait = retry_after
+            .unwrap_or(RATE_LIMIT_INITIAL_WAIT * 2u32.pow(retries))
+            .min(RATE_LIMIT_MAX_WAIT);
+        tracing::warn!("rate limited by the registry, retrying {url} in {wait:?}");
+        tokio::time::sleep(wait).await;
+        retries += 1;
+    }
+}
+
+/// Filters through all image index manifests and returns the digest of the
+/// manifest that matches the platform specified.
+///
+/// ## Arguments
+///
+/// * `platform` - The platform for which the image is being downloaded
+/// * `manifests` - The list of manifests for the image index
+pub(crate) fn resolve_digest_for_platform(
+    platform: Platform,
+    manifests: &[ImageIndexEntry],
+) -> Option<String> {
+    manifests
+        .iter()
+        // First priority: match both OS and architecture
+        .find(|m| {
+            m.platform.as_ref().is_some_and(|p| {
+                p.os == *platform.os()    &&
+                p.architecture == *platform.architecture() &&
+                // Skip attestation manifests
+                !m.annotations.as_ref().is_some_and(|a| a.contains_key(DOCKER_REFERENCE_TYPE_ANNOTATION))
+            })
+        })
+        // Second priority: match architecture only, if no Linux match found
+        .or_else(|| {
+            manifests.iter().find(|m| {
+                m.platform.as_ref().is_some_and(|p| {
+                    p.architecture == *platform.architecture() &&
+                    !m.annotations.as_ref().is_some_and(|a| a.contains_key(DOCKER_REFERENCE_TYPE_ANNOTATION))
+                })
+            })
+        })
+        .map(|m| m.digest.clone())
+}
+
+/// Saves the records of an image to the database: the image itself, its manifest and config, and
+/// its layers.
+///
+/// ## Arguments
+///
+/// * `db` - The database where image configurations, and manifests are stored
+/// * `reference` - The reference the image is saved under
+/// * `size` - The size of the image in bytes
+/// * `manifest` - The manifest of the image
+/// * `config` - The configuration of the image
+pub(crate) async fn save_image_records(
+    db: &Pool<Sqlite>,
+    reference: &Reference,
+    size: i64,
+    manifest: &OciImageManifest,
+    config: &OciConfigFile,
+) -> MicrosandboxResult<()> {
+    let image_id = db::save_or_update_image(db, &reference.as_db_key(), size).await?;
+    let manifest_id = db::save_manifest(db, image_id, manifest).await?;
+    db::save_config(db, manifest_id, config).await?;
+
+    let diffs = config.rootfs.diff_ids.iter();
+    let db_ops = manifest
+        .layers
+        .iter()
+        .zip(diffs)
+        .map(|(layer, diff_id)| {
+            db::create_or_update_manifest_layer(db, layer, diff_id, manifest_id)
+        })
+        .collect::<Vec<_>>();
+    try_join_all(db_ops).await?;
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Runs a registry request, waiting and retrying it while the registry rate limits it, see
+/// [`with_rate_limit_retry_with`].
+async fn with_rate_limit_retry<T, F, Fut>(request: F) -> MicrosandboxResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, OciDistributionError>>,
+{
+    with_rate_limit_retry_with(request, fetch_retry_after).await
+}
+
+/// Returns the URL of the request that failed if it failed because the registry rate limited it.
+fn rate_limited_url(error: &OciDistributionError) -> Option<&str> {
+    match error {
+        OciDistributionError::ServerError { code: 429, url, .. } => Some(url),
+        OciDistributionError::RegistryError { envelope, url }
+            if envelope
+                .errors
+                .iter()
+                .any(|e| matches!(e.code, OciErrorCode::Toomanyrequests)) =>
+        {
+            Some(url)
+        }
+        _ => None,
+    }
+}
+
+/// Asks the registry how long to wait before retrying a rate limited request to `url`.
+///
+/// The registry client drops the headers of failed responses, so the `Retry-After` of the rate
+/// limited response is read by repeating the request as a `HEAD` request, which registries don't
+/// count against pull limits. Nothing is returned when the registry answers it with anything but a
+/// rate limited response with a `Retry-After`.
+async fn fetch_retry_after(url: String) -> Option<Duration> {
+    let client = reqwest::Client::builder()
+        .timeout(RATE_LIMIT_PROBE_TIMEOUT)
+        .build()
+        .ok()?;
+    let response = client.head(&url).send().await.ok()?;
+    if response.status() != reqwest::StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?;
+    parse_retry_after(value.to_str().ok()?, Utc::now())
+}
+
+/// Picks the hint for a registry response from its status and body.
+fn registry_response_hint(status: u16, body: &str) -> &'static str {
+    let json: Option<serde_json::Value> = serde_json::from_str(body).ok();
+
+    // Registries report failures as `{"errors": [{"code": "...", ...}]}`
+    let error_codes: Vec<&str> = json
+        .as_ref()
+        .and_then(|v| v.get("errors"))
+        .and_then(|v| v.as_array())
+        .map(|errors| {
+            errors
+                .iter()
+                .filter_map(|e| e.get("code").and_then(|c| c.as_str()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let has_code = |codes: &[&str]| error_codes.iter().any(|c| codes.contains(c));
+
+    if status == 401 || status == 403 || has_code(&["UNAUTHORIZED", "DENIED"]) {
+        return "authentication required; run `msb login` or check the registry credentials";
+    }
+
+    if status == 429 || has_code(&["TOOMANYREQUESTS"]) {
+        return "rate limited by the registry; wait and retry, or log in for a higher limit";
+    }
+
+    if status == 404 || has_code(&["MANIFEST_UNKNOWN", "NAME_UNKNOWN"]) {
+        return "image or tag not found; check the image reference";
+    }
+
+    let is_manifest_list = json.as_ref().is_some_and(|v| {
+        v.get("manifests").is_some_and(|m| m.is_array())
+            || v.get("mediaType")
+                .and_then(|m| m.as_str())
+                .is_some_and(|m| m.contains("manifest.list") || m.contains("image.index"))
+    });
+    if is_manifest_list {
+        return MANIFEST_LIST_HINT;
+    }
+
+    "unexpected response from the registry"
+}
+
+/// Truncates a response body to [`REGISTRY_BODY_SNIPPET_LEN`] characters.
+fn body_snippet(body: &str) -> String {
+    let body = body.trim();
+    match body.char_indices().nth(REGISTRY_BODY_SNIPPET_LEN) {
+        Some((end, _)) => format!("{}...", &body[..end]),
+        None => body.to_string(),
+    }
+}
@@ -0,0 +1,336 @@
+//! Rewriting image references to pull them through mirrors.
+//!
+//! Organizations often route every pull through an internal mirror, e.g. pulling
+//! `docker.io/library/alpine` from `mirror.corp/dockerhub/library/alpine`. Rewrite rules map the
+//! fully qualified reference of an image to the reference it is actually pulled from, while the
+//! image is still recorded under its original reference.
+//!
+//! A rule is written as `pattern=replacement`:
+//! - `docker.io/=mirror.corp/dockerhub/` replaces a prefix. A trailing `*` on both sides is
+//!   allowed and means the same, e.g. `docker.io/*=mirror.corp/dockerhub/*`.
+//! - `regex:^ghcr\.io/(.+)$=mirror.corp/ghcr/$1` replaces a regular expression match, with `$1`
+//!   and `${name}` expanding to the capture groups.
+//!
+//! Rules come from the `OCI_IMAGE_REWRITES` environment variable, separated by whitespace,
+//! followed by the lines of the file set by `OCI_IMAGE_REWRITES_FILE`. The first rule that
+//! matches wins. There are no rules unless either is set.
+
+use std::{path::Path, str::FromStr};
+
+use microsandbox_utils::env;
+use regex::Regex;
+
+use crate::{MicrosandboxError, MicrosandboxResult, oci::Reference};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The prefix of rules whose pattern is a regular expression.
+const REGEX_RULE_PREFIX: &str = "regex:";
+
+/// The prefix of comment lines in a rewrite rules file.
+const COMMENT_PREFIX: char = '#';
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// A rule that rewrites image references.
+#[derive(Debug, Clone)]
+pub enum RewriteRule {
+    /// Replaces the start of references that begin with `from` with `to`.
+    Prefix {
+        /// The prefix the rule matches.
+        from: String,
+
+        /// What the prefix is replaced with.
+        to: String,
+    },
+
+    /// Replaces the first match of a regular expression with `replacement`.
+    Regex {
+        /// The regular expression the rule matches.
+        pattern: Regex,
+
+        /// What the match is replaced with, where `$1` and `${name}` expand to capture groups.
+        replacement: String,
+    },
+}
+
+/// An ordered list of rules that rewrite image references, where the first matching rule wins.
+///
+/// ## Examples
+///
+/// ```
+/// use microsandbox_core::oci::{ImageRewrites, Reference};
+///
+/// let rule = "docker.io/*=mirror.corp/dockerhub/*".parse().unwrap();
+/// let rewrites = ImageRewrites::new(vec![rule]);
+/// let reference: Reference = "alpine:3.20".parse().unwrap();
+///
+/// assert_eq!(
+///     rewrites.rewrite(&reference).unwrap().to_string(),
+///     "mirror.corp/dockerhub/library/alpine:3.20"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ImageRewrites {
+    /// The rules, in the order they are tried.
+    rules: Vec<RewriteRule>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl RewriteRule {
+    /// Returns the reference rewritten by this rule, or None if the rule doesn't match it.
+    pub fn apply(&self, reference: &str) -> Option<String> {
+        match self {
+            RewriteRule::Prefix { from, to } => reference
+                .strip_prefix(from.as_str())
+                .map(|rest| format!("{to}{rest}")),
+            RewriteRule::Regex {
+                pattern,
+                replacement,
+            } => pattern.is_match(reference).then(|| {
+                pattern
+                    .replace(reference, replacement.as_str())
+                    .into_owned()
+            }),
+        }
+    }
+}
+
+impl ImageRewrites {
+    /// Creates rewrites from rules, in the order they are tried.
+    pub fn new(rules: Vec<RewriteRule>) -> Self {
+        Self { rules }
+    }
+
+    /// Reads the rules from the `OCI_IMAGE_REWRITES` environment variable and the file set by
+    /// `OCI_IMAGE_REWRITES_FILE`, in that order.
+    ///
+    /// Without either, there are no rules and references are pulled as they are.
+    pub fn from_env() -> MicrosandboxResult<Self> {
+        let mut rules = env::get_oci_image_rewrites()
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<MicrosandboxResult<Vec<_>>>()?;
+
+        if let Some(path) = env::get_oci_image_rewrites_file() {
+            rules.extend(Self::load(&path)?.rules);
+        }
+
+        Ok(Self::new(rules))
+    }
+
+    /// Reads the rules from a file with one `pattern=replacement` rule per line.
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn load(path: &Path) -> MicrosandboxResult<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        contents.parse()
+    }
+
+    /// Returns whether there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns the reference an image is pulled from, rewritten by the first rule that matches
+    /// its fully qualified form.
+    ///
+    /// References that no rule matches, and references to OCI image layouts on disk, are
+    /// returned unchanged.
+    ///
+    /// ## Returns
+    ///
+    /// The rewritten reference, or `MicrosandboxError::InvalidImageRewrite` if the rewritten
+    /// reference isn't a valid reference
+    pub fn rewrite(&self, reference: &Reference) -> MicrosandboxResult<Reference> {
+        if reference.layout().is_some() {
+            return Ok(reference.clone());
+        }
+
+        let original = reference.normalize();
+        let Some(rewritten) = self.rules.iter().find_map(|rule| rule.apply(&original)) else {
+            return Ok(reference.clone());
+        };
+
+        rewritten.parse().map_err(|e| {
+            MicrosandboxError::InvalidImageRewrite(format!(
+                "{original} was rewritten to {rewritten}, which is not a valid reference: {e}"
+            ))
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl FromStr for RewriteRule {
+    type Err = MicrosandboxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason: &str| MicrosandboxError::InvalidImageRewrite(format!("{s}: {reason}"));
+
+        let (pattern, replacement) = s
+            .trim()
+            .split_once('=')
+            .ok_or_else(|| invalid("expected pattern=replacement"))?;
+        let (pattern, replacement) = (pattern.trim(), replacement.trim());
+
+        if let Some(pattern) = pattern.strip_prefix(REGEX_RULE_PREFIX) {
+            let pattern = Regex::new(pattern).map_err(|e| invalid(&e.to_string()))?;
+            return Ok(RewriteRule::Regex {
+                pattern,
+                replacement: replacement.to_string(),
+            });
+        }
+
+        let from = pattern.strip_suffix('*').unwrap_or(pattern);
+        if from.is_empty() {
+            return Err(invalid("the pattern is empty"));
+        }
+
+        Ok(RewriteRule::Prefix {
+            from: from.to_string(),
+            to: replacement
+                .strip_suffix('*')
+                .unwrap_or(replacement)
+                .to_string(),
+        })
+    }
+}
+
+impl FromStr for ImageRewrites {
+    type Err = MicrosandboxError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rules = s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with(COMMENT_PREFIX))
+            .map(str::parse)
+            .collect::<MicrosandboxResult<Vec<_>>>()?;
+
+        Ok(Self::new(rules))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_rewrites(rules: &str) -> ImageRewrites {
+        rules.parse().unwrap()
+    }
+
+    fn rewrite(rewrites: &ImageRewrites, reference: &str) -> String {
+        rewrites
+            .rewrite(&reference.parse().unwrap())
+            .unwrap()
+            .normalize()
+    }
+
+    #[test]
+    fn test_image_rewrites_prefix_rule() {
+        let rewrites = parse_rewrites("docker.io/*=mirror.corp/dockerhub/*");
+        assert_eq!(
+            rewrite(&rewrites, "alpine:3.20"),
+            "mirror.corp/dockerhub/library/alpine:3.20"
+        );
+
+        let digest = format!("sha256:{}", "a".repeat(64));
+        assert_eq!(
+            rewrite(&rewrites, &format!("index.docker.io/team/app@{digest}")),
+            format!("mirror.corp/dockerhub/team/app@{digest}")
+        );
+
+        // The trailing `*` is optional
+        let rewrites = parse_rewrites("docker.io/ = mirror.corp/dockerhub/");
+        assert_eq!(
+            rewrite(&rewrites, "team/app:1.0"),
+            "mirror.corp/dockerhub/team/app:1.0"
+        );
+
+        // Regular expression rules expand capture groups
+        let rewrites = parse_rewrites(r"regex:^ghcr\.io/([^/]+)/(.+)$=mirror.corp/ghcr-$1/$2");
+        assert_eq!(
+            rewrite(&rewrites, "ghcr.io/acme/tool:2"),
+            "mirror.corp/ghcr-acme/tool:2"
+        );
+    }
+
+    #[test]
+    fn test_image_rewrites_passes_through_unmatched_references() {
+        let rewrites = parse_rewrites(
+            "
+            # Only Docker Hub goes through the mirror
+            docker.io/=mirror.corp/dockerhub/
+            ",
+        );
+        assert_eq!(
+            rewrite(&rewrites, "quay.io/coreos/etcd:v3"),
+            "quay.io/coreos/etcd:v3"
+        );
+
+        // OCI image layouts on disk are never rewritten
+        let rewrites = parse_rewrites("regex:.*=mirror.corp/everything");
+        let layout: Reference = "oci:/images/app:1.0".parse().unwrap();
+        assert_eq!(rewrites.rewrite(&layout).unwrap(), layout);
+
+        assert!(ImageRewrites::default().is_empty());
+        assert_eq!(
+            rewrite(&ImageRewrites::default(), "alpine"),
+            "docker.io/library/alpine:latest"
+        );
+    }
+
+    #[test]
+    fn test_image_rewrites_first_matching_rule_wins() {
+        let rewrites = parse_rewrites(
+            "
+            docker.io/library/=mirror.corp/official/
+            docker.io/=mirror.corp/dockerhub/
+            regex:^docker\\.io/library/(.+)$=never.used/$1
+            ",
+        );
+        assert_eq!(
+            rewrite(&rewrites, "alpine:3.20"),
+            "mirror.corp/official/alpine:3.20"
+        );
+        assert_eq!(
+            rewrite(&rewrites, "team/app:1.0"),
+            "mirror.corp/dockerhub/team/app:1.0"
+        );
+    }
+
+    #[test]
+    fn test_image_rewrites_rejects_invalid_rules() {
+        for rule in ["docker.io/", "=mirror.corp/", "*=mirror.corp/", "regex:(=x"] {
+            assert!(
+                matches!(
+                    rule.parse::<RewriteRule>(),
+                    Err(MicrosandboxError::InvalidImageRewrite(_))
+                ),
+                "{rule} should be rejected"
+            );
+        }
+
+        // A rewrite that doesn't produce a valid reference fails instead of pulling something else
+        let rewrites = parse_rewrites("docker.io/=Mirror Corp/");
+        assert!(matches!(
+            rewrites.rewrite(&"alpine".parse().unwrap()),
+            Err(MicrosandboxError::InvalidImageRewrite(_))
+        ));
+    }
+}
@@ -1,25 +1,50 @@
-use std::str::FromStr;
+use std::{
+    collections::HashMap,
+    path::Path,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use crate::{
+    MicrosandboxError,
+    management::db::{self, OCI_DB_MIGRATOR},
     oci::{
-        DOCKER_REFERENCE_TYPE_ANNOTATION, Reference, global_cache::GlobalCacheOps,
+        DOCKER_REFERENCE_TYPE_ANNOTATION, LayerOps, PullTimingLayer, Reference, Registry,
+        RegistryTimeouts, RegistryTlsConfig,
+        global_cache::{GlobalCache, GlobalCacheOps},
         mocks::mock_registry_and_db,
+        parse_retry_after, registry_error, registry_response_error, with_rate_limit_retry_with,
     },
     utils,
 };
 
+use chrono::{TimeZone, Utc};
 use futures::StreamExt;
-use oci_client::manifest::OciManifest;
-use oci_spec::image::{Digest, DigestAlgorithm, Os};
-use sqlx::Row;
-use tokio::{fs, io::AsyncWriteExt, test};
+use oci_client::{
+    client::{CertificateEncoding, ClientProtocol},
+    errors::OciDistributionError,
+    manifest::{OciDescriptor, OciImageManifest, OciManifest},
+};
+use oci_spec::image::{Digest, DigestAlgorithm, Os, Platform};
+use sha2::{Digest as _, Sha256};
+use sqlx::{Pool, Row, Sqlite};
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing_subscriber::layer::SubscriberExt;
 
-#[test]
+#[tokio::test]
 #[ignore = "makes network requests to Docker registry to pull an image"]
 async fn test_docker_pull_image() -> anyhow::Result<()> {
     let (registry, db, _dir) = mock_registry_and_db().await;
     let reference = Reference::from_str("alpine:latest").unwrap();
-    let result = registry.pull_image(&reference).await;
+    let result = registry.pull_image(&reference, false).await;
     assert!(result.is_ok(), "{:?}", result.err());
 
     // Verify image record in database
@@ -84,7 +109,7 @@ async fn test_docker_pull_image() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[test]
+#[tokio::test]
 #[ignore = "makes network requests to Docker registry to fetch image index"]
 async fn test_docker_fetch_index() -> anyhow::Result<()> {
     let (registry, _, _) = mock_registry_and_db().await;
@@ -115,16 +140,19 @@ async fn test_docker_fetch_index() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[test]
+#[tokio::test]
 #[ignore = "makes network requests to Docker registry to fetch image manifest"]
 async fn test_docker_fetch_manifest_and_config() -> anyhow::Result<()> {
     let (registry, _, _) = mock_registry_and_db().await;
     let reference = Reference::from_str("alpine:latest").unwrap();
-    let (manifest, config) = registry
+    let (manifest, digest, config) = registry
         .fetch_manifest_and_config(&reference)
         .await
         .unwrap();
 
+    // Verify the manifest digest
+    assert!(digest.starts_with("sha256:"));
+
     // Verify manifest has required fields
     assert_eq!(manifest.schema_version, 2);
     assert!(manifest.config.size > 0);
@@ -160,14 +188,14 @@ async fn test_docker_fetch_manifest_and_config() -> anyhow::Result<()> {
     Ok(())
 }
 
-#[test]
+#[tokio::test]
 #[ignore = "makes network requests to Docker registry to fetch image blob"]
 async fn test_docker_fetch_image_blob() -> anyhow::Result<()> {
     let (registry, _, _) = mock_registry_and_db().await;
     let reference = Reference::from_str("alpine:latest").unwrap();
 
     // Get a layer digest from manifest
-    let (manifest, _) = registry.fetch_manifest_and_config(&reference).await?;
+    let (manifest, _, _) = registry.fetch_manifest_and_config(&reference).await?;
     let layer = manifest.layers.first().unwrap();
     let digest = Digest::try_from(layer.digest.clone()).unwrap();
     let mut stream = registry
@@ -195,3 +223,750 @@ async fn test_docker_fetch_image_blob() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_registry_client_config_with_tls_options() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let ca_path = temp_dir.path().join("ca.pem");
+    fs::write(
+        &ca_path,
+        "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n",
+    )
+    .await?;
+
+    let tls = RegistryTlsConfig {
+        insecure_hosts: vec!["localhost:5000".to_string()],
+        ca_certs: HashMap::from([("registry.internal:5000".to_string(), ca_path)]),
+    };
+
+    let timeouts = RegistryTimeouts::default();
+
+    // The insecure host is pulled from over plain HTTP, every other host uses HTTPS
+    let config =
+        Registry::<GlobalCache>::build_client_config(Platform::default(), &tls, &timeouts, None)
+            .await?;
+    assert!(matches!(
+        &config.protocol,
+        ClientProtocol::HttpsExcept(hosts) if hosts == &["localhost:5000"]
+    ));
+    assert!(config.extra_root_certificates.is_empty());
+
+    // The custom CA is only trusted for the host it was configured for
+    let config = Registry::<GlobalCache>::build_client_config(
+        Platform::default(),
+        &tls,
+        &timeouts,
+        Some("registry.internal:5000"),
+    )
+    .await?;
+    assert_eq!(config.extra_root_certificates.len(), 1);
+    assert!(matches!(
+        config.extra_root_certificates[0].encoding,
+        CertificateEncoding::Pem
+    ));
+
+    let config = Registry::<GlobalCache>::build_client_config(
+        Platform::default(),
+        &tls,
+        &timeouts,
+        Some("reg.io"),
+    )
+    .await?;
+    assert!(config.extra_root_certificates.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registry_client_config_with_timeouts() -> anyhow::Result<()> {
+    let timeouts = RegistryTimeouts {
+        connect: Duration::from_secs(3),
+        read: Duration::from_secs(7),
+    };
+
+    // Every client gets the timeouts, including those with an extra CA
+    for host in [None, Some("registry.internal:5000")] {
+        let config = Registry::<GlobalCache>::build_client_config(
+            Platform::default(),
+            &RegistryTlsConfig::default(),
+            &timeouts,
+            host,
+        )
+        .await?;
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(3)));
+        assert_eq!(config.read_timeout, Some(Duration::from_secs(7)));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stalled_registry_times_out() -> anyhow::Result<()> {
+    // A registry that accepts connections but never answers
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let host = listener.local_addr()?.to_string();
+    tokio::spawn(async move {
+        let mut connections = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            connections.push(stream);
+        }
+    });
+
+    let (_, db, temp_dir) = mock_registry_and_db().await;
+    let cache = GlobalCache::new(
+        temp_dir.path().join("download"),
+        temp_dir.path().join("extracted"),
+        db.clone(),
+    )
+    .await?;
+    let tls = RegistryTlsConfig {
+        insecure_hosts: vec![host.clone()],
+        ..Default::default()
+    };
+    let timeouts = RegistryTimeouts {
+        connect: Duration::from_secs(1),
+        read: Duration::from_secs(1),
+    };
+    let registry =
+        Registry::with_connection_config(db, Platform::default(), cache, tls, timeouts).await?;
+
+    let reference = Reference::from_str(&format!("{host}/team/app:latest"))?;
+    let started = Instant::now();
+    let result = tokio::time::timeout(Duration::from_secs(10), registry.fetch_index(&reference))
+        .await
+        .expect("the pull should fail on its own instead of hanging");
+
+    match result {
+        Err(MicrosandboxError::RegistryTimeout { url }) => assert!(url.contains(&host)),
+        result => panic!("unexpected result: {:?}", result.map(|_| ())),
+    }
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shared_layer_is_not_downloaded_twice() -> anyhow::Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let db = db::get_or_create_pool(&temp_dir.path().join("db"), &OCI_DB_MIGRATOR).await?;
+    let blob_cache_dir = temp_dir.path().join("blobs");
+
+    // Nothing listens on this port, so any attempt to hit the network fails
+    let first = Reference::from_str("localhost:1/first:latest")?;
+    let second = Reference::from_str("localhost:1/second:latest")?;
+
+    // Simulate the first image's pull having fetched the shared layer
+    let content = b"shared layer contents";
+    let content_path = temp_dir.path().join("content");
+    fs::write(&content_path, content).await?;
+    let hash = hex::encode(utils::get_file_hash(&content_path, &DigestAlgorithm::Sha256).await?);
+    let digest = Digest::from_str(&format!("sha256:{hash}"))?;
+    let size = content.len() as u64;
+
+    let first_registry =
+        registry_with_blob_cache(temp_dir.path(), "first", &db, &blob_cache_dir).await;
+    let download_path = first_registry
+        .global_cache()
+        .build_layer(&digest)
+        .await
+        .download_path();
+    fs::create_dir_all(download_path.parent().unwrap()).await?;
+    fs::write(&download_path, content).await?;
+
+    let layer = first_registry
+        .download_image_blob(&first, &digest, size)
+        .await?;
+    assert!(layer.tar_path().starts_with(&blob_cache_dir));
+    assert_eq!(layer.get_tar_size(), Some(size));
+    assert!(!download_path.exists());
+
+    // A second pull with its own download directory reuses the cached layer without fetching it
+    let second_registry =
+        registry_with_blob_cache(temp_dir.path(), "second", &db, &blob_cache_dir).await;
+    let layer = second_registry
+        .download_image_blob(&second, &digest, size)
+        .await?;
+    assert!(layer.tar_path().starts_with(&blob_cache_dir));
+    assert!(!layer.download_path().exists());
+
+    // Without the blob cache the layer has to be fetched again, which fails here
+    let uncached_cache = GlobalCache::new(
+        temp_dir.path().join("uncached").join("download"),
+        temp_dir.path().join("extracted"),
+        db.clone(),
+    )
+    .await?;
+    let uncached_registry = Registry::new(db.clone(), Platform::default(), uncached_cache).await?;
+    assert!(
+        uncached_registry
+            .download_image_blob(&second, &digest, size)
+            .await
+            .is_err()
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_cache_pull_skips_existence_check() -> anyhow::Result<()> {
+    let (registry, db, _dir) = mock_registry_and_db().await;
+
+    // Nothing listens on this port, so any attempt to hit the network fails
+    let reference = Reference::from_str("localhost:1/cached:latest")?;
+    let digest = Digest::from_str(&format!("sha256:{}", "a".repeat(64)))?;
+
+    // Record an image whose only layer is already downloaded and extracted
+    let image_id = db::save_or_update_image(&db, &reference.as_db_key(), 0).await?;
+    let manifest_id = db::save_manifest(&db, image_id, &OciImageManifest::default()).await?;
+    let descriptor = OciDescriptor {
+        digest: digest.to_string(),
+        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+        ..Default::default()
+    };
+    db::create_or_update_manifest_layer(&db, &descriptor, "diff", manifest_id).await?;
+    let config = serde_json::from_value(serde_json::json!({
+        "architecture": "amd64",
+        "os": "linux",
+        "rootfs": { "type": "layers", "diff_ids": ["diff"] },
+    }))?;
+    db::save_config(&db, manifest_id, &config).await?;
+
+    let layer = registry.global_cache().build_layer(&digest).await;
+    fs::create_dir_all(layer.extracted_layer_dir()).await?;
+    fs::write(layer.extracted_layer_dir().join("file"), b"stale").await?;
+    fs::write(layer.extraction_marker_path(), digest.to_string()).await?;
+    fs::create_dir_all(layer.tar_path().parent().unwrap()).await?;
+    fs::write(layer.tar_path(), b"stale").await?;
+
+    // A cached pull returns early without touching the network
+    registry.pull_image(&reference, false).await?;
+    assert!(layer.extracted_layer_dir().exists());
+
+    // Without the cache the layers are cleared, so they are downloaded and extracted again,
+    // and the registry is contacted, which fails here
+    assert!(registry.pull_image(&reference, true).await.is_err());
+    assert!(!layer.extracted_layer_dir().exists());
+    assert!(!layer.extraction_marker_path().exists());
+    assert!(!layer.tar_path().exists());
+    assert!(
+        !registry
+            .global_cache()
+            .all_layers_extracted(&reference)
+            .await?
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_cache_pull_keeps_shared_layers() -> anyhow::Result<()> {
+    let (registry, db, _dir) = mock_registry_and_db().await;
+
+    // Nothing listens on this port, so any attempt to hit the network fails
+    let reference = Reference::from_str("localhost:1/cached:latest")?;
+    let other = Reference::from_str("localhost:1/other:latest")?;
+    let digest = Digest::from_str(&format!("sha256:{}", "b".repeat(64)))?;
+
+    // Record two images that share their only layer
+    let descriptor = OciDescriptor {
+        digest: digest.to_string(),
+        media_type: "application/vnd.oci.image.layer.v1.tar+gzip".to_string(),
+        ..Default::default()
+    };
+    for image in [&reference, &other] {
+        let image_id = db::save_or_update_image(&db, &image.as_db_key(), 0).await?;
+        let manifest_id = db::save_manifest(&db, image_id, &OciImageManifest::default()).await?;
+        db::create_or_update_manifest_layer(&db, &descriptor, "diff", manifest_id).await?;
+    }
+
+    let layer = registry.global_cache().build_layer(&digest).await;
+    fs::create_dir_all(layer.extracted_layer_dir()).await?;
+    fs::write(layer.extracted_layer_dir().join("file"), b"shared").await?;
+    fs::write(layer.extraction_marker_path(), digest.to_string()).await?;
+    fs::create_dir_all(layer.tar_path().parent().unwrap()).await?;
+    fs::write(layer.tar_path(), b"shared").await?;
+
+    // The pull fails at the registry, but the layer the other image uses is left alone
+    assert!(registry.pull_image(&reference, true).await.is_err());
+    assert!(layer.extracted_layer_dir().exists());
+    assert!(layer.extraction_marker_path().exists());
+    assert!(layer.tar_path().exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_registry_response_error_manifest_list() {
+    let body = r#"{
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.docker.distribution.manifest.list.v2+json",
+        "manifests": [
+            {
+                "mediaType": "application/vnd.docker.distribution.manifest.v2+json",
+                "digest": "sha256:1e43a0bc0e0f4d4f8e6b0b1e1b0e0f4d4f8e6b0b1e1b0e0f4d4f8e6b0b1e1b0e",
+                "size": 528,
+                "platform": { "architecture": "amd64", "os": "linux" }
+            }
+        ]
+    }"#;
+
+    match registry_response_error(200, body) {
+        MicrosandboxError::RegistryResponse {
+            status,
+            body_snippet,
+            hint,
+        } => {
+            assert_eq!(status, 200);
+            assert_eq!(hint, "manifest list returned; platform selection needed");
+            assert!(body_snippet.starts_with("{"));
+            assert!(body_snippet.ends_with("..."));
+            assert!(body_snippet.len() <= 256 + 3);
+        }
+        error => panic!("unexpected error: {error}"),
+    }
+}
+
+#[test]
+fn test_registry_error_not_a_manifest() {
+    // A registry error envelope served with a successful status
+    let body = r#"{"errors":[{"code":"UNKNOWN","message":"unknown error"}]}"#;
+    let error = serde_json::from_str::<OciManifest>(body).unwrap_err();
+
+    match registry_error(OciDistributionError::JsonError(error)) {
+        MicrosandboxError::RegistryResponse { status, hint, .. } => {
+            assert_eq!(status, 200);
+            assert!(hint.starts_with("the response is not a manifest"));
+        }
+        error => panic!("unexpected error: {error}"),
+    }
+}
+
+#[test]
+fn test_registry_response_error_authentication() {
+    let body =
+        r#"{"errors":[{"code":"UNAUTHORIZED","message":"authentication required","detail":null}]}"#;
+
+    match registry_response_error(401, body) {
+        MicrosandboxError::RegistryResponse {
+            status,
+            body_snippet,
+            hint,
+        } => {
+            assert_eq!(status, 401);
+            assert_eq!(body_snippet, body);
+            assert!(hint.starts_with("authentication required"));
+        }
+        error => panic!("unexpected error: {error}"),
+    }
+
+    // The error envelope is recognized even when the status does not say so
+    match registry_response_error(200, body) {
+        MicrosandboxError::RegistryResponse { hint, .. } => {
+            assert!(hint.starts_with("authentication required"))
+        }
+        error => panic!("unexpected error: {error}"),
+    }
+}
+
+#[tokio::test]
+async fn test_rate_limited_pull_waits_for_retry_after() -> anyhow::Result<()> {
+    let manifest = serde_json::to_vec(&serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": format!("sha256:{}", "a".repeat(64)),
+            "size": 2
+        },
+        "layers": []
+    }))?;
+    let (host, manifest_requests) = serve_rate_limited_registry(manifest).await?;
+
+    let (_, db, temp_dir) = mock_registry_and_db().await;
+    let cache = GlobalCache::new(
+        temp_dir.path().join("download"),
+        temp_dir.path().join("extracted"),
+        db.clone(),
+    )
+    .await?;
+    let tls = RegistryTlsConfig {
+        insecure_hosts: vec![host.clone()],
+        ..Default::default()
+    };
+    let registry = Registry::with_tls_config(db, Platform::default(), cache, tls).await?;
+
+    // The first request is rate limited with `Retry-After: 0`, so the retry goes through right
+    // away instead of after the 5s backoff used when the registry doesn't say
+    let reference = Reference::from_str(&format!("{host}/team/app:latest"))?;
+    let started = Instant::now();
+    let index = registry.fetch_index(&reference).await?;
+
+    assert!(matches!(index, OciManifest::Image(_)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(manifest_requests.load(Ordering::SeqCst), 2);
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_rate_limit_retry_follows_retry_after_up_to_cap() -> anyhow::Result<()> {
+    let attempts = AtomicUsize::new(0);
+    let request = || {
+        let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+        async move {
+            match attempt {
+                0 | 1 => Err(rate_limited_error()),
+                _ => Ok(attempt),
+            }
+        }
+    };
+
+    // The registry asks for 2s, then for an hour, which is capped at a minute
+    let waits = AtomicUsize::new(0);
+    let fetch_retry_after = |_| {
+        let wait = match waits.fetch_add(1, Ordering::SeqCst) {
+            0 => Duration::from_secs(2),
+            _ => Duration::from_secs(3600),
+        };
+        async move { Some(wait) }
+    };
+
+    let started = tokio::time::Instant::now();
+    let attempt = with_rate_limit_retry_with(request, fetch_retry_after).await?;
+    assert_eq!(attempt, 2);
+    assert_eq!(started.elapsed(), Duration::from_secs(62));
+
+    Ok(())
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_rate_limit_retry_backs_off_without_retry_after() {
+    let attempts = AtomicUsize::new(0);
+    let request = || {
+        attempts.fetch_add(1, Ordering::SeqCst);
+        async { Err::<(), _>(rate_limited_error()) }
+    };
+
+    // Without a Retry-After the waits double from 5s, and the request gives up after 3 retries
+    let started = tokio::time::Instant::now();
+    let result = with_rate_limit_retry_with(request, |_| async { None }).await;
+    assert!(matches!(
+        result,
+        Err(MicrosandboxError::RateLimited { retry_after: None })
+    ));
+    assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    assert_eq!(started.elapsed(), Duration::from_secs(5 + 10 + 20));
+}
+
+#[test]
+fn test_parse_retry_after() {
+    let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap();
+
+    assert_eq!(
+        parse_retry_after("120", now),
+        Some(Duration::from_secs(120))
+    );
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:28:30 GMT", now),
+        Some(Duration::from_secs(30))
+    );
+
+    // A date that has already passed needs no wait
+    assert_eq!(
+        parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+        Some(Duration::ZERO)
+    );
+    assert_eq!(parse_retry_after("soon", now), None);
+}
+
+#[tokio::test]
+async fn test_pull_records_phase_timings() -> anyhow::Result<()> {
+    // An image with a single layer holding one file
+    let mut builder = tar::Builder::new(Vec::new());
+    let contents = b"hello";
+    let mut header = tar::Header::new_gnu();
+    header.set_path("hello.txt")?;
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_cksum();
+    builder.append(&header, &contents[..])?;
+    let layer_tar = builder.into_inner()?;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    std::io::Write::write_all(&mut encoder, &layer_tar)?;
+    let layer = encoder.finish()?;
+
+    let sha256 = |bytes: &[u8]| format!("sha256:{}", hex::encode(Sha256::digest(bytes)));
+    let config = serde_json::to_vec(&serde_json::json!({
+        "architecture": Platform::default().architecture().to_string(),
+        "os": "linux",
+        "rootfs": { "type": "layers", "diff_ids": [sha256(&layer_tar)] }
+    }))?;
+    let manifest = serde_json::to_vec(&serde_json::json!({
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.manifest.v1+json",
+        "config": {
+            "mediaType": "application/vnd.oci.image.config.v1+json",
+            "digest": sha256(&config),
+            "size": config.len()
+        },
+        "layers": [{
+            "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip",
+            "digest": sha256(&layer),
+            "size": layer.len()
+        }]
+    }))?;
+    let layer_digest = sha256(&layer);
+    let blobs = HashMap::from([
+        (sha256(&config), config),
+        (layer_digest.clone(), layer.clone()),
+    ]);
+    let host = serve_static_registry(manifest, blobs).await?;
+
+    let (_, db, temp_dir) = mock_registry_and_db().await;
+    let cache = GlobalCache::new(
+        temp_dir.path().join("download"),
+        temp_dir.path().join("extracted"),
+        db.clone(),
+    )
+    .await?;
+    let tls = RegistryTlsConfig {
+        insecure_hosts: vec![host.clone()],
+        ..Default::default()
+    };
+    let registry = Registry::with_tls_config(db, Platform::default(), cache, tls).await?;
+
+    let timing = PullTimingLayer::new();
+    let _guard =
+        tracing::subscriber::set_default(tracing_subscriber::registry().with(timing.clone()));
+
+    let reference = Reference::from_str(&format!("{host}/team/app:latest"))?;
+    registry.pull_image(&reference, false).await?;
+
+    // Every phase finished with a duration, and the layer phases name the layer
+    let timings = timing.timings();
+    let find = |phase: &str| {
+        timings
+            .iter()
+            .find(|timing| timing.get_phase() == phase)
+            .unwrap_or_else(|| panic!("no timing for the {phase} phase"))
+    };
+    for phase in ["manifest", "db", "download", "extract"] {
+        assert!(find(phase).get_duration_ms().is_some());
+    }
+    assert_eq!(find("manifest").get_layer(), &None);
+    assert_eq!(find("db").get_layer(), &None);
+    assert_eq!(find("download").get_layer(), &Some(layer_digest.clone()));
+    assert_eq!(find("download").get_bytes(), &Some(layer.len() as u64));
+    assert_eq!(find("extract").get_layer(), &Some(layer_digest));
+    assert_eq!(timings.len(), 4);
+
+    Ok(())
+}
+
+/// Builds a registry with its own download directory that shares the given blob cache.
+async fn registry_with_blob_cache(
+    root: &Path,
+    name: &str,
+    db: &Pool<Sqlite>,
+    blob_cache_dir: &Path,
+) -> Registry<GlobalCache> {
+    let cache = GlobalCache::new(
+        root.join(name).join("download"),
+        root.join("extracted"),
+        db.clone(),
+    )
+    .await
+    .unwrap()
+    .with_blob_cache_dir(blob_cache_dir.to_path_buf());
+
+    Registry::new(db.clone(), Platform::default(), cache)
+        .await
+        .unwrap()
+}
+
+/// A registry response rate limiting a manifest request.
+fn rate_limited_error() -> OciDistributionError {
+    OciDistributionError::ServerError {
+        code: 429,
+        url: "https://registry.test/v2/team/app/manifests/latest".to_string(),
+        message: String::new(),
+    }
+}
+
+/// Serves a registry over plain HTTP that rate limits the first manifest request, and serves
+/// `manifest` afterwards. Asking it how long to wait with a `HEAD` request always gets a rate
+/// limited response with `Retry-After: 0`.
+///
+/// Returns the host of the registry and the number of manifest requests it received.
+async fn serve_rate_limited_registry(
+    manifest: Vec<u8>,
+) -> anyhow::Result<(String, Arc<AtomicUsize>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let host = listener.local_addr()?.to_string();
+    let manifest_requests = Arc::new(AtomicUsize::new(0));
+    let rate_limited = Arc::new(AtomicBool::new(true));
+    let manifest = Arc::new(manifest);
+
+    let requests = manifest_requests.clone();
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_registry_connection(
+                stream,
+                manifest.clone(),
+                requests.clone(),
+                rate_limited.clone(),
+            ));
+        }
+    });
+
+    Ok((host, manifest_requests))
+}
+
+/// Answers the requests of a single connection to the registry served by
+/// [`serve_rate_limited_registry`].
+async fn serve_registry_connection(
+    mut stream: TcpStream,
+    manifest: Arc<Vec<u8>>,
+    manifest_requests: Arc<AtomicUsize>,
+    rate_limited: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        // Requests have no body, so a request ends with its headers
+        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            let mut chunk = [0; 4096];
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            continue;
+        };
+
+        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+        buf.drain(..end + 4);
+        let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+        let method = request_line.next().unwrap_or_default();
+        let path = request_line.next().unwrap_or_default();
+
+        let rate_limited_response = || {
+            let body = br#"{"errors":[{"code":"TOOMANYREQUESTS","message":"too many requests"}]}"#;
+            (
+                "429 Too Many Requests",
+                "Retry-After: 0\r\nContent-Type: application/json\r\n".to_string(),
+                body.to_vec(),
+            )
+        };
+
+        let (status, headers, body) = if !path.contains("/manifests/") {
+            ("200 OK", String::new(), b"{}".to_vec())
+        } else if method == "HEAD" {
+            rate_limited_response()
+        } else if rate_limited.swap(false, Ordering::SeqCst) {
+            manifest_requests.fetch_add(1, Ordering::SeqCst);
+            rate_limited_response()
+        } else {
+            manifest_requests.fetch_add(1, Ordering::SeqCst);
+
+            let digest = hex::encode(Sha256::digest(manifest.as_slice()));
+            let headers = format!(
+                "Content-Type: application/vnd.oci.image.manifest.v1+json\r\nDocker-Content-Digest: sha256:{digest}\r\n"
+            );
+            ("200 OK", headers, manifest.to_vec())
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        if method != "HEAD" {
+            response.extend_from_slice(&body);
+        }
+        stream.write_all(&response).await?;
+    }
+}
+
+/// Serves a registry over plain HTTP that answers manifest requests with `manifest` and blob
+/// requests with the blob of the requested digest.
+///
+/// Returns the host of the registry.
+async fn serve_static_registry(
+    manifest: Vec<u8>,
+    blobs: HashMap<String, Vec<u8>>,
+) -> anyhow::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let host = listener.local_addr()?.to_string();
+    let manifest = Arc::new(manifest);
+    let blobs = Arc::new(blobs);
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            tokio::spawn(serve_static_registry_connection(
+                stream,
+                manifest.clone(),
+                blobs.clone(),
+            ));
+        }
+    });
+
+    Ok(host)
+}
+
+/// Answers the requests of a single connection to the registry served by
+/// [`serve_static_registry`].
+async fn serve_static_registry_connection(
+    mut stream: TcpStream,
+    manifest: Arc<Vec<u8>>,
+    blobs: Arc<HashMap<String, Vec<u8>>>,
+) -> anyhow::Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        // Requests have no body, so a request ends with its headers
+        let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+            let mut chunk = [0; 4096];
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            buf.extend_from_slice(&chunk[..n]);
+            continue;
+        };
+
+        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+        buf.drain(..end + 4);
+        let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+        let method = request_line.next().unwrap_or_default();
+        let path = request_line.next().unwrap_or_default();
+
+        let blob = path
+            .split_once("/blobs/")
+            .and_then(|(_, digest)| blobs.get(digest));
+        let (status, headers, body) = if path.contains("/manifests/") {
+            let digest = hex::encode(Sha256::digest(manifest.as_slice()));
+            let headers = format!(
+                "Content-Type: application/vnd.oci.image.manifest.v1+json\r\nDocker-Content-Digest: sha256:{digest}\r\n"
+            );
+            ("200 OK", headers, manifest.to_vec())
+        } else if let Some(blob) = blob {
+            let headers = "Content-Type: application/octet-stream\r\n".to_string();
+            ("200 OK", headers, blob.clone())
+        } else if path.contains("/blobs/") {
+            ("404 Not Found", String::new(), Vec::new())
+        } else {
+            ("200 OK", String::new(), b"{}".to_vec())
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        if method != "HEAD" {
+            response.extend_from_slice(&body);
+        }
+        stream.write_all(&response).await?;
+    }
+}
@@ -0,0 +1,280 @@
+//! Timing the phases of an image pull.
+//!
+//! Each phase of a pull runs in a `pull_phase` span, which records the phase's name, the layer it
+//! works on if any, the number of bytes it downloaded, and how long it took in `duration_ms`:
+//! - `manifest` fetches the image index, manifest and configuration
+//! - `db` writes the image, manifest, configuration and layer records
+//! - `download` downloads one layer
+//! - `import` copies one layer from an OCI image layout on disk, in place of `download`
+//! - `extract` extracts one layer
+//!
+//! [`PullTimingLayer`] collects these spans so they can be printed as a table after the pull.
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use getset::Getters;
+use tracing::{
+    Instrument, Span, Subscriber,
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+};
+use tracing_subscriber::{Layer, layer::Context, registry::LookupSpan};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The name of the spans the phases of a pull run in.
+pub const PULL_PHASE_SPAN: &str = "pull_phase";
+
+/// The number of digest characters shown for a layer in the timing table.
+const SHORT_DIGEST_LEN: usize = 12;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// How long a phase of a pull took.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct PhaseTiming {
+    /// The name of the phase, e.g. `download`.
+    phase: String,
+
+    /// The digest of the layer the phase worked on, if it worked on a single layer.
+    layer: Option<String>,
+
+    /// The number of bytes the phase downloaded, if it downloaded anything.
+    bytes: Option<u64>,
+
+    /// How long the phase took, in milliseconds.
+    duration_ms: Option<u64>,
+}
+
+/// A tracing layer that collects the timings of the phases of image pulls.
+///
+/// Phases are collected as their spans close, so [`PullTimingLayer::timings`] lists them in the
+/// order they finished.
+#[derive(Debug, Clone, Default)]
+pub struct PullTimingLayer {
+    /// The timings of the phases that have finished.
+    timings: Arc<Mutex<Vec<PhaseTiming>>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl PullTimingLayer {
+    /// Creates a layer that hasn't collected any timings yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the timings of the phases that have finished so far.
+    pub fn timings(&self) -> Vec<PhaseTiming> {
+        self.timings.lock().unwrap().clone()
+    }
+
+    /// Returns the timings collected so far as a table, with the total duration of each phase at
+    /// the end.
+    pub fn summary(&self) -> String {
+        let timings = self.timings();
+        let mut summary = table_row("PHASE", "LAYER", "BYTES", "DURATION");
+        for timing in &timings {
+            let bytes = timing.bytes.map(|bytes| bytes.to_string());
+            summary.push_str(&table_row(
+                &timing.phase,
+                timing.layer.as_deref().map(short_digest).unwrap_or("-"),
+                bytes.as_deref().unwrap_or("-"),
+                &format!("{} ms", timing.duration_ms.unwrap_or(0)),
+            ));
+        }
+
+        // Phases that run per layer overlap, so their totals add up the time spent across layers
+        let mut phases: Vec<&str> = Vec::new();
+        for timing in &timings {
+            if !phases.contains(&timing.phase.as_str()) {
+                phases.push(&timing.phase);
+            }
+        }
+        for phase in phases {
+            let total: u64 = timings
+                .iter()
+                .filter(|timing| timing.phase == phase)
+                .filter_map(|timing| timing.duration_ms)
+                .sum();
+            summary.push_str(&table_row(phase, "total", "", &format!("{} ms", total)));
+        }
+
+        summary
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Visit for PhaseTiming {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "phase" => self.phase = value.to_string(),
+            "layer" => self.layer = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "bytes" => self.bytes = Some(value),
+            "duration_ms" => self.duration_ms = Some(value),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl<S> Layer<S> for PullTimingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != PULL_PHASE_SPAN {
+            return;
+        }
+
+        let mut timing = PhaseTiming::default();
+        attrs.record(&mut timing);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(timing);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id)
+            && let Some(timing) = span.extensions_mut().get_mut::<PhaseTiming>()
+        {
+            values.record(timing);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        // A phase without a duration never finished, so there is nothing to report for it
+        let timing = span.extensions_mut().remove::<PhaseTiming>();
+        if let Some(timing) = timing.filter(|timing| timing.duration_ms.is_some()) {
+            self.timings.lock().unwrap().push(timing);
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Creates the span a phase of a pull runs in.
+///
+/// ## Arguments
+///
+/// * `phase` - The name of the phase
+/// * `layer` - The digest of the layer the phase works on, if it works on a single layer
+pub(crate) fn phase_span(phase: &str, layer: Option<&str>) -> Span {
+    let span = tracing::info_span!(
+        PULL_PHASE_SPAN,
+        phase,
+        layer = tracing::field::Empty,
+        bytes = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    if let Some(layer) = layer {
+        span.record("layer", layer);
+    }
+
+    span
+}
+
+/// Runs a phase of a pull in its span and records how long it took in `duration_ms`.
+///
+/// The duration is recorded whether the phase succeeds or fails. The phase can record the bytes
+/// it downloaded on [`Span::current`].
+///
+/// ## Arguments
+///
+/// * `span` - The span of the phase, created with [`phase_span`]
+/// * `fut` - The work of the phase
+///
+/// ## Returns
+///
+/// The output of `fut`
+pub(crate) async fn measure<F: Future>(span: Span, fut: F) -> F::Output {
+    let started = Instant::now();
+    let output = fut.instrument(span.clone()).await;
+    span.record("duration_ms", started.elapsed().as_millis() as u64);
+    output
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Formats a row of the timing table.
+fn table_row(phase: &str, layer: &str, bytes: &str, duration: &str) -> String {
+    format!(
+        "{:<10} {:<14} {:>12} {:>10}\n",
+        phase, layer, bytes, duration
+    )
+}
+
+/// Shortens a digest such as `sha256:abc...` to the start of its hex part.
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.split_once(':').map_or(digest, |(_, hex)| hex);
+    hex.get(..SHORT_DIGEST_LEN).unwrap_or(hex)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pull_timing_layer_collects_finished_phases() {
+        let layer = PullTimingLayer::new();
+        let subscriber = tracing_subscriber::registry().with(layer.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let digest = format!("sha256:{}", "a".repeat(64));
+        measure(phase_span("download", Some(&digest)), async {
+            Span::current().record("bytes", 1024u64);
+        })
+        .await;
+
+        // A span that is never measured has no duration and isn't reported
+        drop(phase_span("extract", Some(&digest)));
+
+        let timings = layer.timings();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].get_phase(), "download");
+        assert_eq!(timings[0].get_layer(), &Some(digest));
+        assert_eq!(timings[0].get_bytes(), &Some(1024));
+        assert!(timings[0].get_duration_ms().is_some());
+
+        let summary = layer.summary();
+        assert!(summary.starts_with("PHASE"));
+        assert!(summary.contains("aaaaaaaaaaaa "));
+        assert!(summary.contains("1024"));
+    }
+}
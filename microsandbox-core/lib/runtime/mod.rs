@@ -1,9 +1,15 @@
 //! Runtime components for the Microsandbox runtime.
 
 mod monitor;
+mod orphans;
+mod probe;
+mod retry;
 
 //--------------------------------------------------------------------------------------------------
 // Exports
 //--------------------------------------------------------------------------------------------------
 
 pub use monitor::*;
+pub use orphans::*;
+pub use probe::*;
+pub use retry::*;
@@ -137,17 +137,7 @@ impl ProcessMonitor for MicroVmMonitor {
         self.log_path = Some(log_path);
 
         // Get rootfs paths
-        let rootfs_paths = match &self.rootfs {
-            Rootfs::Native(path) => format!("native:{}", path.to_string_lossy().into_owned()),
-            Rootfs::Overlayfs(paths) => format!(
-                "overlayfs:{}",
-                paths
-                    .iter()
-                    .map(|p| p.to_string_lossy().into_owned())
-                    .collect::<Vec<String>>()
-                    .join(":")
-            ),
-        };
+        let rootfs_paths = self.rootfs.to_string();
 
         // Insert sandbox entry into database
         db::save_or_update_sandbox(
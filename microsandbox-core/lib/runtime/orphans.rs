@@ -0,0 +1,351 @@
+//! Discovery and cleanup of supervisor and microVM processes that outlived their sandbox records.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+
+use crate::{MicrosandboxResult, management::db};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The name of the binary that runs both the supervisor and the microVM.
+const MSBRUN_BINARY_NAME: &str = "msbrun";
+
+/// The argument the supervisor receives its sandbox database path through.
+const SANDBOX_DB_PATH_ARG: &str = "--sandbox-db-path";
+
+/// How long a runtime process may run without a sandbox record before it counts as orphaned.
+///
+/// A sandbox that is still starting has its supervisor and microVM running before the supervisor
+/// records them, so they are left alone for this long.
+const ORPHAN_GRACE_PERIOD: Duration = Duration::from_secs(60);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// The role of a microsandbox runtime process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeProcessKind {
+    /// A `msbrun supervisor` process.
+    Supervisor,
+
+    /// A `msbrun microvm` process.
+    MicroVm,
+}
+
+/// A microsandbox runtime process found on the host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeProcess {
+    /// The process ID.
+    pub pid: u32,
+
+    /// Whether the process is a supervisor or a microVM.
+    pub kind: RuntimeProcessKind,
+
+    /// The sandbox database a supervisor reports to, if it was passed one.
+    pub sandbox_db_path: Option<PathBuf>,
+
+    /// How long the process has been running, if it could be determined.
+    pub age: Option<Duration>,
+}
+
+/// The PIDs recorded for running sandboxes in the sandbox databases.
+#[derive(Debug, Clone, Default)]
+pub struct RunningPids {
+    /// PIDs of supervisors with a running sandbox record.
+    pub supervisors: HashSet<u32>,
+
+    /// PIDs of microVMs with a running sandbox record.
+    pub microvms: HashSet<u32>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Lists the supervisor and microVM processes currently running on the host.
+///
+/// Processes are recognized by their command line: the executable is `msbrun` and its first
+/// argument is `supervisor` or `microvm`. Processes whose command line can't be read, e.g.
+/// because they belong to another user, are skipped.
+pub fn find_runtime_processes() -> MicrosandboxResult<Vec<RuntimeProcess>> {
+    let own_pid = std::process::id();
+    let processes = psutil::process::processes()
+        .map_err(|e| anyhow::anyhow!("failed to list processes: {e}"))?;
+
+    Ok(processes
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|process| process.pid() != own_pid)
+        .filter_map(|process| {
+            let cmdline = process.cmdline_vec().ok()??;
+            parse_runtime_process(process.pid(), process_age(&process), &cmdline)
+        })
+        .collect())
+}
+
+/// Collects the PIDs of running sandboxes from the databases the given supervisors report to.
+///
+/// Databases that no longer exist are skipped, since they can't vouch for any process.
+pub async fn running_pids(processes: &[RuntimeProcess]) -> MicrosandboxResult<RunningPids> {
+    let db_paths: BTreeSet<&Path> = processes
+        .iter()
+        .filter_map(|p| p.sandbox_db_path.as_deref())
+        .collect();
+
+    let mut running = RunningPids::default();
+    for db_path in db_paths {
+        if !db_path.exists() {
+            continue;
+        }
+
+        let pool = db::get_pool(db_path).await?;
+        for sandbox in db::get_running_sandboxes(&pool).await? {
+            running.supervisors.insert(sandbox.supervisor_pid);
+            running.microvms.insert(sandbox.microvm_pid);
+        }
+    }
+
+    Ok(running)
+}
+
+/// Selects the processes that have no running sandbox record.
+///
+/// A supervisor is orphaned when no database lists it as the supervisor of a running sandbox, and
+/// a microVM when none lists it as the microVM of one. Processes younger than the grace period,
+/// or whose age is unknown, may belong to a sandbox that is still starting and are never selected.
+pub fn select_orphans(processes: &[RuntimeProcess], running: &RunningPids) -> Vec<RuntimeProcess> {
+    processes
+        .iter()
+        .filter(|process| process.age.is_some_and(|age| age >= ORPHAN_GRACE_PERIOD))
+        .filter(|process| match process.kind {
+            RuntimeProcessKind::Supervisor => !running.supervisors.contains(&process.pid),
+            RuntimeProcessKind::MicroVm => !running.microvms.contains(&process.pid),
+        })
+        .cloned()
+        .collect()
+}
+
+/// Finds the supervisor and microVM processes that outlived their sandbox records.
+pub async fn find_orphans() -> MicrosandboxResult<Vec<RuntimeProcess>> {
+    let processes = find_runtime_processes()?;
+    let running = running_pids(&processes).await?;
+    Ok(select_orphans(&processes, &running))
+}
+
+/// Stops every orphaned supervisor and microVM process.
+///
+/// Orphans are sent `SIGTERM`, or `SIGKILL` when `force` is set. Processes that exit before they
+/// are signalled are not treated as errors.
+///
+/// ## Arguments
+///
+/// * `force` - Whether to kill the processes instead of asking them to terminate
+///
+/// ## Returns
+///
+/// The orphaned processes that were signalled
+pub async fn reap_orphans(force: bool) -> MicrosandboxResult<Vec<RuntimeProcess>> {
+    let signal = if force {
+        Signal::SIGKILL
+    } else {
+        Signal::SIGTERM
+    };
+
+    let mut reaped = Vec::new();
+    for orphan in find_orphans().await? {
+        match signal::kill(Pid::from_raw(orphan.pid as i32), signal) {
+            Ok(()) => {
+                tracing::info!(
+                    "sent {:?} to orphaned {:?} {}",
+                    signal,
+                    orphan.kind,
+                    orphan.pid
+                );
+                reaped.push(orphan);
+            }
+            Err(nix::Error::ESRCH) => {
+                tracing::debug!("orphaned process {} already exited", orphan.pid);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(reaped)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Works out how long a process has been running.
+///
+/// On Linux the creation time of a process is measured from boot, elsewhere from the Unix epoch.
+fn process_age(process: &psutil::process::Process) -> Option<Duration> {
+    #[cfg(target_os = "linux")]
+    let now = psutil::host::uptime().ok()?;
+    #[cfg(not(target_os = "linux"))]
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?;
+
+    now.checked_sub(process.create_time())
+}
+
+/// Recognizes a runtime process from its command line.
+fn parse_runtime_process(
+    pid: u32,
+    age: Option<Duration>,
+    cmdline: &[String],
+) -> Option<RuntimeProcess> {
+    let (exe, args) = cmdline.split_first()?;
+    if Path::new(exe).file_name()? != OsStr::new(MSBRUN_BINARY_NAME) {
+        return None;
+    }
+
+    let kind = match args.first()?.as_str() {
+        "supervisor" => RuntimeProcessKind::Supervisor,
+        "microvm" => RuntimeProcessKind::MicroVm,
+        _ => return None,
+    };
+
+    // The path is passed either as `--sandbox-db-path <path>` or `--sandbox-db-path=<path>`
+    let sandbox_db_path = args.iter().enumerate().find_map(|(i, arg)| {
+        if arg == SANDBOX_DB_PATH_ARG {
+            args.get(i + 1).map(PathBuf::from)
+        } else {
+            arg.strip_prefix(SANDBOX_DB_PATH_ARG)
+                .and_then(|rest| rest.strip_prefix('='))
+                .map(PathBuf::from)
+        }
+    });
+
+    Some(RuntimeProcess {
+        pid,
+        kind,
+        sandbox_db_path,
+        age,
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmdline(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_orphans_parse_runtime_process() {
+        let supervisor = parse_runtime_process(
+            10,
+            None,
+            &cmdline(&[
+                "/usr/local/bin/msbrun",
+                "supervisor",
+                "--sandbox-name",
+                "app",
+                "--sandbox-db-path",
+                "/project/.menv/sandbox.db",
+            ]),
+        )
+        .unwrap();
+        assert_eq!(supervisor.kind, RuntimeProcessKind::Supervisor);
+        assert_eq!(
+            supervisor.sandbox_db_path,
+            Some(PathBuf::from("/project/.menv/sandbox.db"))
+        );
+
+        let microvm = parse_runtime_process(
+            11,
+            None,
+            &cmdline(&["msbrun", "microvm", "--exec-path=/bin/sh"]),
+        )
+        .unwrap();
+        assert_eq!(microvm.kind, RuntimeProcessKind::MicroVm);
+        assert_eq!(microvm.sandbox_db_path, None);
+
+        assert!(parse_runtime_process(12, None, &cmdline(&["msb", "supervisor"])).is_none());
+        assert!(parse_runtime_process(13, None, &cmdline(&["msbrun", "--help"])).is_none());
+        assert!(parse_runtime_process(14, None, &[]).is_none());
+    }
+
+    #[test]
+    fn test_orphans_only_unmatched_pids_selected() {
+        let process = |pid, kind| RuntimeProcess {
+            pid,
+            kind,
+            sandbox_db_path: None,
+            age: Some(ORPHAN_GRACE_PERIOD),
+        };
+        let processes = vec![
+            process(100, RuntimeProcessKind::Supervisor),
+            process(101, RuntimeProcessKind::MicroVm),
+            process(200, RuntimeProcessKind::Supervisor),
+            process(201, RuntimeProcessKind::MicroVm),
+            process(300, RuntimeProcessKind::MicroVm),
+        ];
+
+        // Sandbox 100/101 is running, 200 lost its record, 201 and 300 lost their supervisors
+        let running = RunningPids {
+            supervisors: HashSet::from([100]),
+            microvms: HashSet::from([101]),
+        };
+
+        let orphans: Vec<u32> = select_orphans(&processes, &running)
+            .iter()
+            .map(|p| p.pid)
+            .collect();
+        assert_eq!(orphans, vec![200, 201, 300]);
+
+        // A PID recorded as a supervisor does not vouch for a microVM with the same PID
+        let running = RunningPids {
+            supervisors: HashSet::from([100, 101]),
+            microvms: HashSet::new(),
+        };
+        let orphans: Vec<u32> = select_orphans(&processes[..2], &running)
+            .iter()
+            .map(|p| p.pid)
+            .collect();
+        assert_eq!(orphans, vec![101]);
+    }
+
+    #[test]
+    fn test_orphans_young_processes_not_selected() {
+        let process = |pid, age| RuntimeProcess {
+            pid,
+            kind: RuntimeProcessKind::Supervisor,
+            sandbox_db_path: None,
+            age,
+        };
+
+        // A supervisor that just started has not recorded its sandbox yet
+        let processes = vec![
+            process(100, Some(Duration::from_secs(1))),
+            process(200, None),
+            process(300, Some(ORPHAN_GRACE_PERIOD * 2)),
+        ];
+
+        let orphans: Vec<u32> = select_orphans(&processes, &RunningPids::default())
+            .iter()
+            .map(|p| p.pid)
+            .collect();
+        assert_eq!(orphans, vec![300]);
+    }
+}
@@ -0,0 +1,428 @@
+//! Shallow checks for whether the virtualization backend is usable on this host.
+
+use std::{
+    ffi::CString,
+    path::{Path, PathBuf},
+};
+
+use crate::{MicrosandboxError, MicrosandboxResult};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The KVM device libkrun uses on Linux.
+#[cfg(target_os = "linux")]
+const KVM_DEVICE_PATH: &str = "/dev/kvm";
+
+/// The libkrun library microsandbox is built against.
+///
+/// The ABI version has to be kept in sync with `LIBKRUN_ABI_VERSION` in `scripts/build_libkrun.sh`.
+pub const LIBKRUN: BackendLibrary = BackendLibrary::new("libkrun", 1);
+
+/// The libkrunfw library libkrun loads the guest kernel from.
+///
+/// The ABI version has to be kept in sync with `LIBKRUNFW_ABI_VERSION` in
+/// `scripts/build_libkrun.sh`.
+pub const LIBKRUNFW: BackendLibrary = BackendLibrary::new("libkrunfw", 4);
+
+/// How to install the backend libraries when they are missing or don't match.
+const INSTALL_REMEDIATION: &str = "Reinstall microsandbox with `curl -sSL https://get.microsandbox.dev | sh`, or build the libraries from source with `make build_libkrun && make install`";
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// A shared library the virtualization backend needs at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BackendLibrary {
+    /// The name of the library, e.g. `libkrun`.
+    name: &'static str,
+
+    /// The ABI version the library has to have.
+    abi_version: u32,
+}
+
+/// Where a backend library was found, if anywhere.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LibraryLookup {
+    /// The library with the expected ABI version.
+    Found(PathBuf),
+
+    /// Only a version of the library with a different ABI version.
+    Mismatched {
+        /// The library that was found.
+        path: PathBuf,
+
+        /// Its ABI version.
+        abi_version: u32,
+    },
+
+    /// No version of the library at all.
+    Missing,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl BackendLibrary {
+    /// Creates a new `BackendLibrary`.
+    const fn new(name: &'static str, abi_version: u32) -> Self {
+        Self { name, abi_version }
+    }
+
+    /// The file name the dynamic loader looks the library up by, e.g. `libkrun.so.1`.
+    #[cfg(not(target_os = "macos"))]
+    pub fn file_name(&self) -> String {
+        format!("{}.so.{}", self.name, self.abi_version)
+    }
+
+    /// The file name the dynamic loader looks the library up by, e.g. `libkrun.1.dylib`.
+    #[cfg(target_os = "macos")]
+    pub fn file_name(&self) -> String {
+        format!("{}.{}.dylib", self.name, self.abi_version)
+    }
+
+    /// Returns the ABI version of a versioned file of this library, like `libkrun.so.1.14.0`.
+    #[cfg(not(target_os = "macos"))]
+    fn abi_version_of(&self, file_name: &str) -> Option<u32> {
+        let version = file_name.strip_prefix(self.name)?.strip_prefix(".so.")?;
+        version.split('.').next()?.parse().ok()
+    }
+
+    /// Returns the ABI version of a versioned file of this library, like `libkrun.1.dylib`.
+    #[cfg(target_os = "macos")]
+    fn abi_version_of(&self, file_name: &str) -> Option<u32> {
+        let version = file_name
+            .strip_prefix(self.name)?
+            .strip_prefix('.')?
+            .strip_suffix(".dylib")?;
+        version.split('.').next()?.parse().ok()
+    }
+
+    /// Looks the library up in the given directories.
+    ///
+    /// A file with the expected ABI version in any of the directories wins over files with other
+    /// ABI versions, which are only reported when nothing matches.
+    fn lookup(&self, search_dirs: &[PathBuf]) -> LibraryLookup {
+        let file_name = self.file_name();
+        let mut mismatched = None;
+
+        for dir in search_dirs {
+            let path = dir.join(&file_name);
+            if path.exists() {
+                return LibraryLookup::Found(path);
+            }
+
+            if mismatched.is_some() {
+                continue;
+            }
+
+            let Ok(entries) = std::fs::read_dir(dir) else {
+                continue;
+            };
+
+            mismatched = entries.flatten().find_map(|entry| {
+                let abi_version = self.abi_version_of(entry.file_name().to_str()?)?;
+                (abi_version != self.abi_version).then(|| LibraryLookup::Mismatched {
+                    path: entry.path(),
+                    abi_version,
+                })
+            });
+        }
+
+        mismatched.unwrap_or(LibraryLookup::Missing)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Checks that the virtualization backend can be used to start microVMs.
+///
+/// This is a shallow check that doesn't start a VM. It verifies that the backend libraries can be
+/// found, see [`check_backend_libraries`], and on Linux that `/dev/kvm` can be opened for reading
+/// and writing.
+///
+/// ## Returns
+///
+/// Returns [`MicrosandboxError::BackendUnavailable`] describing the problem if the backend is not
+/// usable.
+#[cfg(target_os = "linux")]
+pub fn probe_backend() -> MicrosandboxResult<()> {
+    check_backend_libraries()?;
+
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(KVM_DEVICE_PATH)
+        .map(|_| ())
+        .map_err(|e| MicrosandboxError::BackendUnavailable {
+            reason: format!("cannot open {KVM_DEVICE_PATH}: {e}"),
+            remediation: format!(
+                "Enable KVM on this host and give your user read and write access to {KVM_DEVICE_PATH}, e.g. with `sudo usermod -aG kvm $USER`"
+            ),
+        })
+}
+
+/// Checks that the virtualization backend can be used to start microVMs.
+///
+/// This is a shallow check that doesn't start a VM. It verifies that the backend libraries can be
+/// found, see [`check_backend_libraries`], and that the Hypervisor framework is supported.
+///
+/// ## Returns
+///
+/// Returns [`MicrosandboxError::BackendUnavailable`] describing the problem if the backend is not
+/// usable.
+#[cfg(target_os = "macos")]
+pub fn probe_backend() -> MicrosandboxResult<()> {
+    check_backend_libraries()?;
+
+    let mut supported: libc::c_int = 0;
+    let mut size = std::mem::size_of::<libc::c_int>();
+    let ret = unsafe {
+        libc::sysctlbyname(
+            c"kern.hv_support".as_ptr(),
+            &mut supported as *mut _ as *mut libc::c_void,
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+
+    if ret != 0 || supported != 1 {
+        return Err(MicrosandboxError::BackendUnavailable {
+            reason: "the Hypervisor framework is not supported on this host".to_string(),
+            remediation: "Run microsandbox on a Mac with Apple silicon".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that the virtualization backend can be used to start microVMs.
+///
+/// ## Returns
+///
+/// Always returns [`MicrosandboxError::BackendUnavailable`] as no backend is supported on this
+/// platform.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn probe_backend() -> MicrosandboxResult<()> {
+    Err(MicrosandboxError::BackendUnavailable {
+        reason: "unsupported platform".to_string(),
+        remediation: "Run microsandbox on Linux with KVM or on macOS".to_string(),
+    })
+}
+
+/// Checks that libkrun and libkrunfw are installed with the ABI versions microsandbox is built
+/// against.
+///
+/// The libraries are looked up in the directories they are usually installed to, see
+/// [`library_search_dirs`]. A library that isn't in any of them is still accepted if the dynamic
+/// loader can load it, since the loader may be configured with other directories.
+///
+/// ## Returns
+///
+/// Returns [`MicrosandboxError::BackendUnavailable`] naming the missing or mismatched library and
+/// how to install it.
+pub fn check_backend_libraries() -> MicrosandboxResult<()> {
+    check_libraries(&[LIBKRUN, LIBKRUNFW], &library_search_dirs(), is_loadable)
+}
+
+/// Returns the directories the backend libraries are looked up in, in order.
+///
+/// These are the dynamic loader's path variables, the directory `msb` installs the libraries to
+/// and the system library directories.
+pub fn library_search_dirs() -> Vec<PathBuf> {
+    #[cfg(target_os = "macos")]
+    const PATH_VARS: &[&str] = &["DYLD_LIBRARY_PATH", "DYLD_FALLBACK_LIBRARY_PATH"];
+    #[cfg(not(target_os = "macos"))]
+    const PATH_VARS: &[&str] = &["LD_LIBRARY_PATH"];
+
+    let mut dirs: Vec<PathBuf> = PATH_VARS
+        .iter()
+        .filter_map(std::env::var_os)
+        .flat_map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .collect();
+
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".local").join("lib"));
+    }
+
+    dirs.push(PathBuf::from("/usr/local/lib"));
+
+    #[cfg(target_os = "macos")]
+    dirs.push(PathBuf::from("/opt/homebrew/lib"));
+
+    #[cfg(not(target_os = "macos"))]
+    dirs.extend(
+        [
+            format!("/usr/lib/{}-linux-gnu", std::env::consts::ARCH),
+            "/usr/lib64".to_string(),
+            "/usr/lib".to_string(),
+            "/lib64".to_string(),
+            "/lib".to_string(),
+        ]
+        .map(PathBuf::from),
+    );
+
+    dirs
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Checks each library, falling back to `is_loadable` for libraries not in `search_dirs`.
+fn check_libraries(
+    libraries: &[BackendLibrary],
+    search_dirs: &[PathBuf],
+    is_loadable: impl Fn(&str) -> bool,
+) -> MicrosandboxResult<()> {
+    for library in libraries {
+        let file_name = library.file_name();
+        match library.lookup(search_dirs) {
+            LibraryLookup::Found(path) => {
+                tracing::debug!("found {} at {}", file_name, path.display());
+            }
+            _ if is_loadable(&file_name) => {
+                tracing::debug!("{} is loadable by the dynamic loader", file_name);
+            }
+            LibraryLookup::Mismatched { path, abi_version } => {
+                return Err(MicrosandboxError::BackendUnavailable {
+                    reason: format!(
+                        "{} has ABI version {} but microsandbox needs {} (ABI version {})",
+                        path.display(),
+                        abi_version,
+                        file_name,
+                        library.abi_version
+                    ),
+                    remediation: INSTALL_REMEDIATION.to_string(),
+                });
+            }
+            LibraryLookup::Missing => {
+                let searched = search_dirs
+                    .iter()
+                    .map(|dir| dir.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                return Err(MicrosandboxError::BackendUnavailable {
+                    reason: format!("{} was not found (searched {})", file_name, searched),
+                    remediation: INSTALL_REMEDIATION.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether the dynamic loader can load the library with the given file name.
+fn is_loadable(file_name: &str) -> bool {
+    let Ok(file_name) = CString::new(file_name) else {
+        return false;
+    };
+
+    unsafe {
+        let handle = libc::dlopen(file_name.as_ptr(), libc::RTLD_LAZY | libc::RTLD_LOCAL);
+        if handle.is_null() {
+            return false;
+        }
+
+        libc::dlclose(handle);
+    }
+
+    true
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    /// Returns the file name of `library` with another ABI version.
+    fn file_name_with_abi(library: &BackendLibrary, abi_version: u32) -> String {
+        BackendLibrary::new(library.name, abi_version).file_name()
+    }
+
+    #[test]
+    fn test_check_libraries_accepts_installed_libraries() -> anyhow::Result<()> {
+        let lib_dir = tempdir()?;
+        std::fs::write(lib_dir.path().join(LIBKRUN.file_name()), "")?;
+        std::fs::write(lib_dir.path().join(LIBKRUNFW.file_name()), "")?;
+
+        let search_dirs = [PathBuf::from("/nonexistent"), lib_dir.path().to_path_buf()];
+        check_libraries(&[LIBKRUN, LIBKRUNFW], &search_dirs, |_| false)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_libraries_reports_missing_library() -> anyhow::Result<()> {
+        let lib_dir = tempdir()?;
+        std::fs::write(lib_dir.path().join(LIBKRUN.file_name()), "")?;
+
+        let search_dirs = [lib_dir.path().to_path_buf()];
+        match check_libraries(&[LIBKRUN, LIBKRUNFW], &search_dirs, |_| false) {
+            Err(MicrosandboxError::BackendUnavailable {
+                reason,
+                remediation,
+            }) => {
+                assert!(reason.contains(&LIBKRUNFW.file_name()));
+                assert!(reason.contains(&lib_dir.path().display().to_string()));
+                assert!(remediation.contains("get.microsandbox.dev"));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // A library the dynamic loader can load is accepted even if it isn't in the directories
+        check_libraries(&[LIBKRUN, LIBKRUNFW], &search_dirs, |name| {
+            name == LIBKRUNFW.file_name()
+        })?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_libraries_reports_mismatched_library() -> anyhow::Result<()> {
+        let lib_dir = tempdir()?;
+        let mismatched = file_name_with_abi(&LIBKRUN, LIBKRUN.abi_version + 1);
+        std::fs::write(lib_dir.path().join(&mismatched), "")?;
+
+        // libkrunfw has a similar name, but must not be taken for a libkrun version
+        std::fs::write(lib_dir.path().join(LIBKRUNFW.file_name()), "")?;
+
+        let search_dirs = [lib_dir.path().to_path_buf()];
+        match check_libraries(&[LIBKRUN], &search_dirs, |_| false) {
+            Err(MicrosandboxError::BackendUnavailable {
+                reason,
+                remediation,
+            }) => {
+                assert!(reason.contains(&mismatched));
+                assert!(reason.contains(&LIBKRUN.file_name()));
+                assert!(remediation.contains("make build_libkrun"));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+
+        // The expected version in a later directory wins over the mismatched one
+        let other_lib_dir = tempdir()?;
+        std::fs::write(other_lib_dir.path().join(LIBKRUN.file_name()), "")?;
+        let search_dirs = [
+            lib_dir.path().to_path_buf(),
+            other_lib_dir.path().to_path_buf(),
+        ];
+        check_libraries(&[LIBKRUN], &search_dirs, |_| false)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+//! Retrying fallible operations with exponential backoff.
+//!
+//! An operation is retried until it succeeds, fails with an error that isn't retryable, or the
+//! policy's attempt limit or deadline is reached, sleeping between attempts with an exponentially
+//! growing, optionally jittered delay.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use getset::Getters;
+use rand::Rng;
+use typed_builder::TypedBuilder;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// How often and how long to retry an operation.
+///
+/// With neither `max_attempts` nor `deadline` set, the operation is retried until it succeeds or
+/// fails with an error that isn't retryable.
+///
+/// ## Examples
+///
+/// ```
+/// use microsandbox_core::runtime::RetryPolicy;
+/// use std::time::Duration;
+///
+/// let policy = RetryPolicy::builder()
+///     .max_attempts(5)
+///     .deadline(Duration::from_secs(10))
+///     .initial_backoff(Duration::from_millis(50))
+///     .build();
+///
+/// assert_eq!(policy.get_max_attempts(), &Some(5));
+/// assert_eq!(policy.get_multiplier(), &2.0);
+/// ```
+#[derive(Debug, Clone, PartialEq, TypedBuilder, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct RetryPolicy {
+    /// The most attempts to make, including the first one.
+    #[builder(default, setter(strip_option))]
+    max_attempts: Option<u32>,
+
+    /// The longest to keep retrying for, measured from the first attempt.
+    ///
+    /// The delay before an attempt is cut short so that it doesn't overshoot the deadline, and no
+    /// attempt is started once the deadline has passed.
+    #[builder(default, setter(strip_option))]
+    deadline: Option<Duration>,
+
+    /// The delay before the second attempt.
+    #[builder(default = Duration::from_millis(100))]
+    initial_backoff: Duration,
+
+    /// The upper bound on the delay between attempts.
+    #[builder(default = Duration::from_secs(5))]
+    max_backoff: Duration,
+
+    /// The factor the delay grows by after each attempt, where `1.0` keeps it constant.
+    #[builder(default = 2.0)]
+    multiplier: f64,
+
+    /// The fraction of each delay that is randomized, between `0.0` for none and `1.0` for all of
+    /// it.
+    ///
+    /// Jitter keeps several clients retrying against the same service from doing so in lockstep.
+    #[builder(default = 0.2)]
+    jitter: f64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl RetryPolicy {
+    /// Returns the delay to sleep for given the current backoff, with jitter applied.
+    fn jittered(&self, backoff: Duration) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+
+        backoff.mul_f64(1.0 - rand::rng().random_range(0.0..=jitter))
+    }
+
+    /// Returns the backoff to use after `backoff`.
+    fn next_backoff(&self, backoff: Duration) -> Duration {
+        backoff
+            .mul_f64(self.multiplier.max(1.0))
+            .min(self.max_backoff)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Runs `op` until it succeeds or the retry policy is exhausted.
+///
+/// Every error is retried. See [`retry_if`] to only retry some of them.
+///
+/// ## Arguments
+///
+/// * `policy` - How often and how long to retry
+/// * `op` - The operation to run, called once per attempt
+///
+/// ## Returns
+///
+/// The result of the first successful attempt, or the error of the last attempt
+pub async fn retry<T, E, F, Fut>(policy: &RetryPolicy, op: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_if(policy, |_| true, op).await
+}
+
+/// Runs `op` until it succeeds, fails with an error `is_retryable` rejects, or the retry policy
+/// is exhausted.
+///
+/// ## Arguments
+///
+/// * `policy` - How often and how long to retry
+/// * `is_retryable` - Whether an attempt failing with the given error should be retried
+/// * `op` - The operation to run, called once per attempt
+///
+/// ## Returns
+///
+/// The result of the first successful attempt, or the error of the last attempt
+pub async fn retry_if<T, E, F, Fut, R>(
+    policy: &RetryPolicy,
+    mut is_retryable: R,
+    mut op: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    R: FnMut(&E) -> bool,
+{
+    let deadline = policy.deadline.map(|deadline| Instant::now() + deadline);
+    let mut backoff = policy.initial_backoff;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        let error = match op().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        if !is_retryable(&error) {
+            return Err(error);
+        }
+
+        if policy.max_attempts.is_some_and(|max| attempts >= max) {
+            tracing::debug!("giving up after {} attempt(s)", attempts);
+            return Err(error);
+        }
+
+        // Sleep before the next attempt, without overshooting the deadline
+        let mut delay = policy.jittered(backoff);
+        if let Some(deadline) = deadline {
+            let now = Instant::now();
+            if now >= deadline {
+                tracing::debug!("giving up after {} attempt(s), deadline passed", attempts);
+                return Err(error);
+            }
+
+            delay = delay.min(deadline - now);
+        }
+
+        tracing::trace!("attempt {} failed, retrying in {:?}", attempts, delay);
+        tokio::time::sleep(delay).await;
+        backoff = policy.next_backoff(backoff);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy::builder()
+            .initial_backoff(Duration::from_millis(5))
+            .max_backoff(Duration::from_millis(20))
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_retry_succeeds_on_third_attempt() {
+        let attempts = &AtomicU32::new(0);
+        let policy = RetryPolicy {
+            max_attempts: Some(5),
+            ..fast_policy()
+        };
+
+        let result: Result<u32, String> = retry(&policy, || async move {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            if attempt < 3 {
+                Err(format!("attempt {attempt} failed"))
+            } else {
+                Ok(attempt)
+            }
+        })
+        .await;
+
+        assert_eq!(result, Ok(3));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retry_returns_last_error_after_deadline() {
+        let attempts = &AtomicU32::new(0);
+        let deadline = Duration::from_millis(100);
+        let policy = RetryPolicy {
+            deadline: Some(deadline),
+            ..fast_policy()
+        };
+
+        let started = Instant::now();
+        let result: Result<(), String> = retry(&policy, || async move {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            Err(format!("attempt {attempt} failed"))
+        })
+        .await;
+
+        let attempts = attempts.load(Ordering::SeqCst);
+        assert_eq!(result, Err(format!("attempt {attempts} failed")));
+        assert!(attempts > 1);
+        assert!(started.elapsed() >= deadline);
+        assert!(started.elapsed() < deadline * 5);
+    }
+
+    #[tokio::test]
+    async fn test_retry_if_stops_on_non_retryable_error() {
+        let attempts = &AtomicU32::new(0);
+        let policy = RetryPolicy {
+            max_attempts: Some(5),
+            ..fast_policy()
+        };
+
+        let result: Result<(), &str> = retry_if(
+            &policy,
+            |error| *error != "fatal",
+            || async move {
+                if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                    Err("transient")
+                } else {
+                    Err("fatal")
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(result, Err("fatal"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_retry_policy_backoff_grows_up_to_max() {
+        let policy = RetryPolicy::builder()
+            .initial_backoff(Duration::from_millis(100))
+            .max_backoff(Duration::from_millis(300))
+            .jitter(0.0)
+            .build();
+
+        let backoff = policy.next_backoff(*policy.get_initial_backoff());
+        assert_eq!(backoff, Duration::from_millis(200));
+        assert_eq!(policy.next_backoff(backoff), Duration::from_millis(300));
+        assert_eq!(policy.jittered(backoff), backoff);
+    }
+}
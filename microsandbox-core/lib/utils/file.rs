@@ -1,6 +1,6 @@
 //! Utility functions for working with files.
 
-use std::path::Path;
+use std::{error::Error, ffi::CString, io, path::Path};
 
 use oci_spec::image::DigestAlgorithm;
 use sha2::{Digest, Sha256, Sha384, Sha512};
@@ -8,6 +8,59 @@ use tokio::{fs::File, io::AsyncReadExt};
 
 use crate::{MicrosandboxError, MicrosandboxResult};
 
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Computes the hash of data that arrives in chunks, e.g. a blob streamed from a registry.
+pub enum StreamHasher {
+    /// A SHA-256 hash.
+    Sha256(Sha256),
+
+    /// A SHA-384 hash.
+    Sha384(Sha384),
+
+    /// A SHA-512 hash.
+    Sha512(Sha512),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl StreamHasher {
+    /// Creates a hasher for the given digest algorithm.
+    pub fn new(algorithm: &DigestAlgorithm) -> MicrosandboxResult<Self> {
+        match algorithm {
+            DigestAlgorithm::Sha256 => Ok(Self::Sha256(Sha256::new())),
+            DigestAlgorithm::Sha384 => Ok(Self::Sha384(Sha384::new())),
+            DigestAlgorithm::Sha512 => Ok(Self::Sha512(Sha512::new())),
+            _ => Err(MicrosandboxError::UnsupportedImageHashAlgorithm(format!(
+                "Unsupported algorithm: {}",
+                algorithm
+            ))),
+        }
+    }
+
+    /// Adds the next chunk of data to the hash.
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(hasher) => hasher.update(data),
+            Self::Sha384(hasher) => hasher.update(data),
+            Self::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Returns the hash of all data added.
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Sha256(hasher) => hasher.finalize().to_vec(),
+            Self::Sha384(hasher) => hasher.finalize().to_vec(),
+            Self::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
@@ -35,3 +88,169 @@ pub async fn get_file_hash(
 
     Ok(hash)
 }
+
+/// Returns the free bytes on the file system of a path, or of its closest existing ancestor.
+pub fn available_disk_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|path| path.exists())?;
+    let path = CString::new(existing.as_os_str().as_encoded_bytes()).ok()?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Checks that the file system of a path has enough free space for a write.
+///
+/// The check is skipped if the free space can't be determined.
+///
+/// ## Arguments
+///
+/// * `path` - The path that is about to be written to
+/// * `needed` - The number of bytes that will be written
+///
+/// ## Returns
+///
+/// `MicrosandboxError::DiskFull` if less than `needed` bytes are free
+pub fn ensure_disk_space(path: &Path, needed: u64) -> MicrosandboxResult<()> {
+    match available_disk_space(path) {
+        Some(available) if available < needed => Err(MicrosandboxError::DiskFull {
+            path: path.to_path_buf(),
+            needed: Some(needed),
+            available: Some(available),
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Turns an error caused by a full disk into `MicrosandboxError::DiskFull`, and returns any other
+/// error as it is.
+///
+/// The disk is full when any error in the chain of sources is an IO error of kind
+/// [`io::ErrorKind::StorageFull`], i.e. `ENOSPC`.
+///
+/// ## Arguments
+///
+/// * `error` - The error to check
+/// * `path` - The path that was being written to
+/// * `needed` - The number of bytes that were needed, if known
+pub fn map_disk_full(
+    error: MicrosandboxError,
+    path: &Path,
+    needed: Option<u64>,
+) -> MicrosandboxError {
+    if matches!(error, MicrosandboxError::DiskFull { .. }) {
+        return error;
+    }
+
+    if !is_disk_full(&error) {
+        return error;
+    }
+
+    MicrosandboxError::DiskFull {
+        path: path.to_path_buf(),
+        needed,
+        available: available_disk_space(path),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Returns whether an error or any of its sources is an IO error caused by a full disk.
+fn is_disk_full(error: &(dyn Error + 'static)) -> bool {
+    let mut next = Some(error);
+    while let Some(error) = next {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            if io_error.kind() == io::ErrorKind::StorageFull {
+                return true;
+            }
+
+            // The source of an IO error is that of the error it wraps, skipping the wrapped error
+            if let Some(inner) = io_error.get_ref() {
+                next = Some(inner);
+                continue;
+            }
+        }
+
+        next = error.source();
+    }
+
+    false
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_ensure_disk_space() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("not-created-yet");
+
+        ensure_disk_space(&path, 0)?;
+        let Err(MicrosandboxError::DiskFull {
+            path: full_path,
+            needed,
+            available,
+        }) = ensure_disk_space(&path, u64::MAX)
+        else {
+            panic!("expected a disk full error");
+        };
+        assert_eq!(full_path, path);
+        assert_eq!(needed, Some(u64::MAX));
+        assert!(available.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_map_disk_full() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+
+        // The cause is found however deep it is wrapped
+        let error = MicrosandboxError::LayerExtraction {
+            message: "failed to extract layer".to_string(),
+            source: Some(Box::new(MicrosandboxError::Io(io::Error::from(
+                io::ErrorKind::StorageFull,
+            )))),
+        };
+        let error = map_disk_full(error, temp_dir.path(), Some(42));
+        assert!(matches!(
+            error,
+            MicrosandboxError::DiskFull {
+                needed: Some(42),
+                available: Some(_),
+                ..
+            }
+        ));
+        assert!(error.to_string().contains("42 bytes needed"));
+
+        // Other errors are left alone
+        let error = MicrosandboxError::Io(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(matches!(
+            map_disk_full(error, temp_dir.path(), None),
+            MicrosandboxError::Io(_)
+        ));
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_map_disk_full_from_enospc() {
+        // Every write to /dev/full fails with ENOSPC
+        let error = std::fs::write("/dev/full", b"layer").unwrap_err();
+        let error = map_disk_full(error.into(), Path::new("/dev/full"), None);
+        assert!(matches!(error, MicrosandboxError::DiskFull { .. }));
+    }
+}
@@ -0,0 +1,148 @@
+use getset::Getters;
+
+use crate::{MicrosandboxError, MicrosandboxResult};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The smallest amount of memory, in MiB, a running microVM can be shrunk to.
+pub const MIN_BALLOON_MEMORY_MIB: u32 = 128;
+
+/// Whether the virtualization backend can change the memory of a running microVM.
+///
+/// libkrun doesn't expose a virtio-balloon device or any other way to adjust guest memory once
+/// `krun_start_enter` has been called, so memory is fixed for the lifetime of the microVM.
+const BACKEND_SUPPORTS_MEMORY_RESIZE: bool = false;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// The range of memory sizes, in MiB, a running microVM can be resized within.
+///
+/// A balloon can only give memory back to the host and reclaim it again, so the upper bound is
+/// the memory the microVM booted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct MemoryResizeRange {
+    /// The smallest allowed memory size in MiB.
+    min_mib: u32,
+
+    /// The largest allowed memory size in MiB.
+    max_mib: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl MemoryResizeRange {
+    /// Creates a range from explicit bounds.
+    pub fn new(min_mib: u32, max_mib: u32) -> Self {
+        Self { min_mib, max_mib }
+    }
+
+    /// Creates the range for a microVM that booted with `boot_memory_mib` of memory.
+    pub fn for_boot_memory(boot_memory_mib: u32) -> Self {
+        Self::new(MIN_BALLOON_MEMORY_MIB.min(boot_memory_mib), boot_memory_mib)
+    }
+
+    /// Checks that `requested_mib` lies within the range.
+    pub fn validate(&self, requested_mib: u32) -> MicrosandboxResult<()> {
+        if requested_mib < self.min_mib || requested_mib > self.max_mib {
+            return Err(MicrosandboxError::InvalidMemoryResize {
+                requested: requested_mib,
+                min: self.min_mib,
+                max: self.max_mib,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Requests that a running microVM's memory be resized to `requested_mib`.
+///
+/// ## Arguments
+///
+/// * `range` - The sizes the microVM can be resized within
+/// * `requested_mib` - The memory size to resize to in MiB
+///
+/// ## Returns
+///
+/// Returns [`MicrosandboxError::InvalidMemoryResize`] if the size is outside `range`, and
+/// [`MicrosandboxError::MemoryResizeNotSupported`] if the backend can't resize a running microVM.
+pub fn resize_memory(range: MemoryResizeRange, requested_mib: u32) -> MicrosandboxResult<()> {
+    range.validate(requested_mib)?;
+
+    if !BACKEND_SUPPORTS_MEMORY_RESIZE {
+        return Err(MicrosandboxError::MemoryResizeNotSupported(
+            "libkrun cannot change the memory of a running microVM; update the sandbox's memory and restart it"
+                .to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_resize_range_validation() {
+        let range = MemoryResizeRange::new(256, 1024);
+
+        assert!(range.validate(256).is_ok());
+        assert!(range.validate(512).is_ok());
+        assert!(range.validate(1024).is_ok());
+
+        for requested in [0, 255, 1025] {
+            match range.validate(requested) {
+                Err(MicrosandboxError::InvalidMemoryResize {
+                    requested: r,
+                    min,
+                    max,
+                }) => {
+                    assert_eq!((r, min, max), (requested, 256, 1024));
+                }
+                result => panic!("unexpected result for {requested}: {result:?}"),
+            }
+        }
+
+        // The range never goes above boot memory, even when that is below the usual minimum
+        assert_eq!(
+            MemoryResizeRange::for_boot_memory(64),
+            MemoryResizeRange::new(64, 64)
+        );
+        assert_eq!(
+            MemoryResizeRange::for_boot_memory(2048),
+            MemoryResizeRange::new(MIN_BALLOON_MEMORY_MIB, 2048)
+        );
+    }
+
+    #[test]
+    fn test_memory_resize_not_supported() {
+        let range = MemoryResizeRange::for_boot_memory(1024);
+
+        // Validation runs first so an out of range request reports the range
+        assert!(matches!(
+            resize_memory(range, 4096),
+            Err(MicrosandboxError::InvalidMemoryResize { .. })
+        ));
+
+        assert!(matches!(
+            resize_memory(range, 512),
+            Err(MicrosandboxError::MemoryResizeNotSupported(_))
+        ));
+    }
+}
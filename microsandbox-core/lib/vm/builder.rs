@@ -6,7 +6,7 @@ use typed_path::Utf8UnixPathBuf;
 
 use crate::{
     MicrosandboxResult,
-    config::{EnvPair, NetworkScope, PathPair, PortPair},
+    config::{EnvPair, NetworkMode, NetworkScope, PathPair, PortPair},
 };
 
 use super::{
@@ -29,10 +29,12 @@ use super::{
 /// - `memory_mib`: The amount of memory in MiB to use for the MicroVm.
 /// - `mapped_dirs`: The directories to mount in the MicroVm.
 /// - `port_map`: The ports to map in the MicroVm.
+/// - `network`: How the MicroVm is connected to the network.
 /// - `rlimits`: The resource limits to use for the MicroVm.
 /// - `workdir_path`: The working directory to use for the MicroVm.
 /// - `args`: The arguments to pass to the executable.
 /// - `env`: The environment variables to use for the MicroVm.
+/// - `kernel_args`: The extra arguments to append to the guest kernel command line.
 /// - `console_output`: The path to the file to write the console output to.
 #[derive(Debug)]
 pub struct MicroVmConfigBuilder<R, E> {
@@ -42,6 +44,7 @@ pub struct MicroVmConfigBuilder<R, E> {
     memory_mib: u32,
     mapped_dirs: Vec<PathPair>,
     port_map: Vec<PortPair>,
+    network: NetworkMode,
     scope: NetworkScope,
     ip: Option<Ipv4Addr>,
     subnet: Option<Ipv4Network>,
@@ -50,6 +53,7 @@ pub struct MicroVmConfigBuilder<R, E> {
     exec_path: E,
     args: Vec<String>,
     env: Vec<EnvPair>,
+    kernel_args: Vec<String>,
     console_output: Option<Utf8UnixPathBuf>,
 }
 
@@ -69,6 +73,7 @@ pub struct MicroVmConfigBuilder<R, E> {
 /// - `memory_mib`: The amount of memory in MiB to use for the MicroVm.
 /// - `mapped_dirs`: The directories to mount in the MicroVm.
 /// - `port_map`: The ports to map in the MicroVm.
+/// - `network`: How the MicroVm is connected to the network.
 /// - `scope`: The network scope to use for the MicroVm.
 /// - `ip`: The IP address to use for the MicroVm.
 /// - `subnet`: The subnet to use for the MicroVm.
@@ -76,6 +81,7 @@ pub struct MicroVmConfigBuilder<R, E> {
 /// - `workdir_path`: The working directory to use for the MicroVm.
 /// - `args`: The arguments to pass to the executable.
 /// - `env`: The environment variables to use for the MicroVm.
+/// - `kernel_args`: The extra arguments to append to the guest kernel command line.
 /// - `console_output`: The path to the file to write the console output to.
 ///
 /// ## Examples
@@ -177,6 +183,7 @@ impl<R, M> MicroVmConfigBuilder<R, M> {
             memory_mib: self.memory_mib,
             mapped_dirs: self.mapped_dirs,
             port_map: self.port_map,
+            network: self.network,
             scope: self.scope,
             ip: self.ip,
             subnet: self.subnet,
@@ -185,6 +192,7 @@ impl<R, M> MicroVmConfigBuilder<R, M> {
             exec_path: self.exec_path,
             args: self.args,
             env: self.env,
+            kernel_args: self.kernel_args,
             console_output: self.console_output,
         }
     }
@@ -302,6 +310,31 @@ impl<R, M> MicroVmConfigBuilder<R, M> {
         self
     }
 
+    /// Sets how the MicroVm is connected to the network.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use microsandbox_core::vm::MicroVmConfigBuilder;
+    /// use microsandbox_core::config::NetworkMode;
+    ///
+    /// let config = MicroVmConfigBuilder::default()
+    ///     .network(NetworkMode::None);  // No network interface at all
+    /// ```
+    ///
+    /// ## Network Mode Options
+    /// - `None` - No network interface, and the guest can't open any connection
+    /// - `Host` - Guest sockets are carried over the host's network, limited by the scope (default)
+    /// - `Nat` - A virtual network interface backed by a `passt` process on the host
+    ///
+    /// ## Notes
+    /// - Port mappings are kept in every mode, so published ports stay reachable from the host
+    /// - The scope is ignored with `Nat`
+    pub fn network(mut self, network: NetworkMode) -> Self {
+        self.network = network;
+        self
+    }
+
     /// Sets the network scope for the MicroVm.
     ///
     /// The network scope controls the MicroVm's level of network isolation and connectivity.
@@ -478,6 +511,7 @@ impl<R, M> MicroVmConfigBuilder<R, M> {
             memory_mib: self.memory_mib,
             mapped_dirs: self.mapped_dirs,
             port_map: self.port_map,
+            network: self.network,
             scope: self.scope,
             ip: self.ip,
             subnet: self.subnet,
@@ -486,6 +520,7 @@ impl<R, M> MicroVmConfigBuilder<R, M> {
             exec_path: exec_path.into(),
             args: self.args,
             env: self.env,
+            kernel_args: self.kernel_args,
             console_output: self.console_output,
         }
     }
@@ -553,6 +588,31 @@ impl<R, M> MicroVmConfigBuilder<R, M> {
         self
     }
 
+    /// Sets extra arguments for the guest kernel command line.
+    ///
+    /// The arguments are appended after the defaults, in the order they appear in the iterator.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use microsandbox_core::vm::MicroVmConfigBuilder;
+    ///
+    /// let config = MicroVmConfigBuilder::default()
+    ///     .kernel_args([
+    ///         "console=hvc0",  // Write kernel messages to the virtio console
+    ///         "loglevel=7",    // Print debug messages while booting
+    ///     ]);
+    /// ```
+    ///
+    /// ## Notes
+    /// - Each argument must be a single word, such as `key=value` or `flag`
+    /// - Parameters set by microsandbox, such as `init`, can't be overridden
+    /// - An argument can't set the same parameter as an environment variable
+    pub fn kernel_args<'a>(mut self, kernel_args: impl IntoIterator<Item = &'a str>) -> Self {
+        self.kernel_args = kernel_args.into_iter().map(|s| s.to_string()).collect();
+        self
+    }
+
     /// Sets the path for capturing console output from the MicroVm.
     ///
     /// This allows redirecting and saving all console output (stdout/stderr) from
@@ -776,6 +836,33 @@ impl<R, M> MicroVmBuilder<R, M> {
         self
     }
 
+    /// Sets how the MicroVm is connected to the network.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use microsandbox_core::vm::{MicroVmBuilder, Rootfs};
+    /// use microsandbox_core::config::NetworkMode;
+    /// use std::path::PathBuf;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let vm = MicroVmBuilder::default()
+    ///     .network(NetworkMode::None)  // No network interface at all
+    ///     .rootfs(Rootfs::Native(PathBuf::from("/path/to/rootfs")))
+    ///     .exec_path("/bin/echo");
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// ## Network Mode Options
+    /// - `None` - No network interface, and the guest can't open any connection
+    /// - `Host` - Guest sockets are carried over the host's network, limited by the scope (default)
+    /// - `Nat` - A virtual network interface backed by a `passt` process on the host
+    pub fn network(mut self, network: NetworkMode) -> Self {
+        self.inner = self.inner.network(network);
+        self
+    }
+
     /// Sets the network scope for the MicroVm.
     ///
     /// The network scope controls the MicroVm's level of network isolation and connectivity.
@@ -976,6 +1063,31 @@ impl<R, M> MicroVmBuilder<R, M> {
         self
     }
 
+    /// Sets extra arguments for the guest kernel command line.
+    ///
+    /// The arguments are appended after the defaults, in the order they appear in the iterator.
+    ///
+    /// ## Examples
+    ///
+    /// ```rust
+    /// use microsandbox_core::vm::MicroVmBuilder;
+    ///
+    /// let vm = MicroVmBuilder::default()
+    ///     .kernel_args([
+    ///         "console=hvc0",  // Write kernel messages to the virtio console
+    ///         "loglevel=7",    // Print debug messages while booting
+    ///     ]);
+    /// ```
+    ///
+    /// ## Notes
+    /// - Each argument must be a single word, such as `key=value` or `flag`
+    /// - Parameters set by microsandbox, such as `init`, can't be overridden
+    /// - An argument can't set the same parameter as an environment variable
+    pub fn kernel_args<'a>(mut self, kernel_args: impl IntoIterator<Item = &'a str>) -> Self {
+        self.inner = self.inner.kernel_args(kernel_args);
+        self
+    }
+
     /// Sets the path for capturing console output from the MicroVm.
     ///
     /// This allows redirecting and saving all console output (stdout/stderr) from
@@ -1015,6 +1127,7 @@ impl MicroVmConfigBuilder<Rootfs, Utf8UnixPathBuf> {
             memory_mib: self.memory_mib,
             mapped_dirs: self.mapped_dirs,
             port_map: self.port_map,
+            network: self.network,
             scope: self.scope,
             ip: self.ip,
             subnet: self.subnet,
@@ -1023,6 +1136,7 @@ impl MicroVmConfigBuilder<Rootfs, Utf8UnixPathBuf> {
             exec_path: self.exec_path,
             args: self.args,
             env: self.env,
+            kernel_args: self.kernel_args,
             console_output: self.console_output,
         }
     }
@@ -1068,6 +1182,7 @@ impl MicroVmBuilder<Rootfs, Utf8UnixPathBuf> {
             memory_mib: self.inner.memory_mib,
             mapped_dirs: self.inner.mapped_dirs,
             port_map: self.inner.port_map,
+            network: self.inner.network,
             scope: self.inner.scope,
             ip: self.inner.ip,
             subnet: self.inner.subnet,
@@ -1076,6 +1191,7 @@ impl MicroVmBuilder<Rootfs, Utf8UnixPathBuf> {
             exec_path: self.inner.exec_path,
             args: self.inner.args,
             env: self.inner.env,
+            kernel_args: self.inner.kernel_args,
             console_output: self.inner.console_output,
         })
     }
@@ -1094,6 +1210,7 @@ impl Default for MicroVmConfigBuilder<(), ()> {
             memory_mib: DEFAULT_MEMORY_MIB,
             mapped_dirs: vec![],
             port_map: vec![],
+            network: NetworkMode::default(),
             scope: NetworkScope::default(),
             ip: None,
             subnet: None,
@@ -1102,6 +1219,7 @@ impl Default for MicroVmConfigBuilder<(), ()> {
             exec_path: (),
             args: vec![],
             env: vec![],
+            kernel_args: vec![],
             console_output: None,
         }
     }
@@ -1195,7 +1313,32 @@ mod tests {
         assert_eq!(builder.inner.exec_path, Utf8UnixPathBuf::from("/bin/echo"));
         assert!(builder.inner.args.is_empty());
         assert!(builder.inner.env.is_empty());
+        assert!(builder.inner.kernel_args.is_empty());
         assert_eq!(builder.inner.console_output, None);
         Ok(())
     }
+
+    #[test]
+    fn test_microvm_config_builder_appends_kernel_args() -> anyhow::Result<()> {
+        let config = MicroVmConfigBuilder::default()
+            .rootfs(Rootfs::Native(PathBuf::from("/tmp")))
+            .exec_path("/bin/echo")
+            .env(["KEY1=VALUE1".parse()?, "KEY2=VALUE2".parse()?])
+            .kernel_args(["console=hvc0", "loglevel=7", "debug"])
+            .build();
+
+        // The extra arguments come after the environment, in the order they were given
+        assert_eq!(
+            config.kernel_cmdline(),
+            [
+                "KEY1=VALUE1",
+                "KEY2=VALUE2",
+                "console=hvc0",
+                "loglevel=7",
+                "debug"
+            ]
+        );
+        assert!(config.validate().is_ok());
+        Ok(())
+    }
 }
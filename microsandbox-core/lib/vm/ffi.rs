@@ -146,7 +146,6 @@ unsafe extern "C" {
     ///
     /// * `ctx_id` - The configuration context ID.
     /// * `fd` - A file descriptor to communicate with passt.
-    #[allow(dead_code)]
     pub(crate) fn krun_set_passt_fd(ctx_id: u32, fd: i32) -> i32;
 
     /// Configures the networking to use gvproxy in vfkit mode.
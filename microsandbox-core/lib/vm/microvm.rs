@@ -1,4 +1,15 @@
-use std::{ffi::CString, net::Ipv4Addr, path::PathBuf, ptr};
+use std::{
+    ffi::CString,
+    fmt::{self, Display},
+    net::Ipv4Addr,
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::{net::UnixStream, process::CommandExt},
+    },
+    path::PathBuf,
+    process::Command,
+    ptr,
+};
 
 use getset::Getters;
 use ipnetwork::Ipv4Network;
@@ -7,7 +18,7 @@ use typed_path::Utf8UnixPathBuf;
 
 use crate::{
     InvalidMicroVMConfigError, MicrosandboxError, MicrosandboxResult,
-    config::{EnvPair, NetworkScope, PathPair, PortPair},
+    config::{EnvPair, NetworkMode, NetworkScope, PathPair, PortPair},
     utils,
 };
 
@@ -20,6 +31,22 @@ use super::{LinuxRlimit, MicroVmBuilder, MicroVmConfigBuilder, ffi};
 /// The prefix used for virtio-fs tags when mounting shared directories
 pub const VIRTIOFS_TAG_PREFIX: &str = "virtiofs";
 
+/// The program that provides the guest's network interface in the `nat` network mode
+pub const PASST_EXE: &str = "passt";
+
+/// The kernel command line parameters libkrun sets itself, which extra kernel arguments can't
+/// override
+pub const RESERVED_KERNEL_PARAMS: &[&str] = &[
+    "init",
+    "root",
+    "rootfstype",
+    "reboot",
+    "panic",
+    "KRUN_INIT",
+    "KRUN_WORKDIR",
+    "KRUN_RLIMITS",
+];
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
@@ -57,6 +84,11 @@ pub struct MicroVm {
     /// The configuration for the MicroVm.
     #[get = "pub with_prefix"]
     config: MicroVmConfig,
+
+    /// The socket libkrun talks to passt over, which is only held to keep it open for as long as
+    /// the MicroVm exists.
+    #[allow(dead_code)]
+    passt_socket: Option<OwnedFd>,
 }
 
 /// The type of rootfs to use for the MicroVm.
@@ -134,6 +166,9 @@ pub struct MicroVmConfig {
     /// The port map to use for the MicroVm.
     pub port_map: Vec<PortPair>,
 
+    /// How the MicroVm is connected to the network.
+    pub network: NetworkMode,
+
     /// The network scope to use for the MicroVm.
     pub scope: NetworkScope,
 
@@ -158,6 +193,9 @@ pub struct MicroVmConfig {
     /// The environment variables to set for the executable.
     pub env: Vec<EnvPair>,
 
+    /// The extra arguments to append to the guest kernel command line.
+    pub kernel_args: Vec<String>,
+
     /// The console output path to use for the MicroVm.
     pub console_output: Option<Utf8UnixPathBuf>,
 }
@@ -206,9 +244,23 @@ impl MicroVm {
 
         config.validate()?;
 
-        Self::apply_config(ctx_id, &config);
-
-        Ok(Self { ctx_id, config })
+        // The translated network needs passt running before its interface can be attached
+        let passt_socket = match config.network {
+            NetworkMode::Nat => Some(Self::start_passt(&config)?),
+            NetworkMode::None | NetworkMode::Host => None,
+        };
+
+        Self::apply_config(
+            ctx_id,
+            &config,
+            passt_socket.as_ref().map(|fd| fd.as_raw_fd()),
+        );
+
+        Ok(Self {
+            ctx_id,
+            config,
+            passt_socket,
+        })
     }
 
     /// Creates a builder for configuring a new MicroVm instance.
@@ -283,6 +335,40 @@ impl MicroVm {
         ctx_id as u32
     }
 
+    /// Starts the passt process that provides the guest's network interface.
+    ///
+    /// passt gets one end of a socket pair and exits once the other end, which libkrun talks to
+    /// passt over, is closed.
+    ///
+    /// ## Returns
+    /// The end of the socket pair to hand to libkrun.
+    fn start_passt(config: &MicroVmConfig) -> MicrosandboxResult<OwnedFd> {
+        let (vm_socket, passt_socket) = UnixStream::pair()?;
+        let passt_fd = passt_socket.as_raw_fd();
+
+        let mut command = Command::new(PASST_EXE);
+        command.args(config.passt_args(passt_fd).unwrap_or_default());
+
+        // The socket is opened close-on-exec, but passt has to inherit it
+        unsafe {
+            command.pre_exec(move || {
+                let flags = libc::fcntl(passt_fd, libc::F_GETFD);
+                if flags < 0 || libc::fcntl(passt_fd, libc::F_SETFD, flags & !libc::FD_CLOEXEC) < 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command
+            .spawn()
+            .map_err(MicrosandboxError::PasstStartFailed)?;
+        tracing::info!("started passt with PID: {}", child.id());
+
+        Ok(vm_socket.into())
+    }
+
     /// Applies the configuration to the MicroVm context.
     ///
     /// This method configures all aspects of the MicroVm including:
@@ -293,19 +379,20 @@ impl MicroVm {
     /// - Resource limits
     /// - Working directory
     /// - Executable and arguments
-    /// - Environment variables
+    /// - Environment variables and extra kernel arguments
     /// - Console output
     /// - Network settings
     ///
     /// ## Arguments
     /// * `ctx_id` - The MicroVm context ID to configure
     /// * `config` - The configuration to apply
+    /// * `passt_socket` - The socket to talk to passt over, for the `nat` network mode
     ///
     /// ## Panics
     /// Panics if:
     /// - Any libkrun API call fails
     /// - Cannot update the rootfs fstab file
-    fn apply_config(ctx_id: u32, config: &MicroVmConfig) {
+    fn apply_config(ctx_id: u32, config: &MicroVmConfig, passt_socket: Option<RawFd>) {
         // Set log level
         unsafe {
             let status = ffi::krun_set_log_level(config.log_level as u32);
@@ -368,24 +455,33 @@ impl MicroVm {
             }
         }
 
-        // Set port map
-        let c_port_map: Vec<_> = config
-            .port_map
-            .iter()
-            .map(|p| CString::new(p.to_string()).unwrap())
-            .collect();
-        let c_port_map_ptrs = utils::to_null_terminated_c_array(&c_port_map);
+        // Set up the network. The translated network attaches an interface backed by passt, which
+        // forwards the mapped ports itself, while the other modes carry guest sockets over the host
+        if let Some(passt_socket) = passt_socket {
+            unsafe {
+                let status = ffi::krun_set_passt_fd(ctx_id, passt_socket);
+                assert!(status >= 0, "failed to set passt socket: {}", status);
+            }
+        } else {
+            let c_port_map: Vec<_> = config
+                .port_map
+                .iter()
+                .map(|p| CString::new(p.to_string()).unwrap())
+                .collect();
+            let c_port_map_ptrs = utils::to_null_terminated_c_array(&c_port_map);
 
-        unsafe {
-            let status = ffi::krun_set_port_map(ctx_id, c_port_map_ptrs.as_ptr());
-            assert!(status >= 0, "failed to set port map: {}", status);
+            unsafe {
+                let status = ffi::krun_set_port_map(ctx_id, c_port_map_ptrs.as_ptr());
+                assert!(status >= 0, "failed to set port map: {}", status);
+            }
         }
 
         // Set network scope
-        unsafe {
-            let status =
-                ffi::krun_set_tsi_scope(ctx_id, ptr::null(), ptr::null(), config.scope as u8);
-            assert!(status >= 0, "failed to set network scope: {}", status);
+        if let Some(scope) = config.tsi_scope() {
+            unsafe {
+                let status = ffi::krun_set_tsi_scope(ctx_id, ptr::null(), ptr::null(), scope as u8);
+                assert!(status >= 0, "failed to set network scope: {}", status);
+            }
         }
 
         // Set resource limits
@@ -421,10 +517,12 @@ impl MicroVm {
             .collect();
         let c_argv_ptrs = utils::to_null_terminated_c_array(&c_argv);
 
+        // libkrun puts the environment on the kernel command line, which is how the extra kernel
+        // arguments get there too
         let c_env: Vec<_> = config
-            .env
-            .iter()
-            .map(|s| CString::new(s.to_string()).unwrap())
+            .kernel_cmdline()
+            .into_iter()
+            .map(|s| CString::new(s).unwrap())
             .collect();
         let c_env_ptrs = utils::to_null_terminated_c_array(&c_env);
 
@@ -541,6 +639,7 @@ impl MicroVmConfig {
     /// - Ensures memory allocation is non-zero
     /// - Validates executable path and arguments contain only printable ASCII characters
     /// - Validates guest paths don't overlap or conflict with each other
+    /// - Validates extra kernel arguments don't override or repeat a parameter
     ///
     /// ## Returns
     /// - `Ok(())` if the configuration is valid
@@ -622,6 +721,127 @@ impl MicroVmConfig {
         // Validate guest paths are not subsets of each other
         Self::validate_guest_paths(&self.mapped_dirs)?;
 
+        self.validate_kernel_args()?;
+
+        Ok(())
+    }
+
+    /// Returns the arguments microsandbox adds to the guest kernel command line, in order.
+    ///
+    /// libkrun appends these after its own defaults: the environment variables of the executable,
+    /// which the guest init picks up, followed by the extra kernel arguments.
+    ///
+    /// ## Examples
+    /// ```rust
+    /// use microsandbox_core::vm::{MicroVmConfig, Rootfs};
+    /// use std::path::PathBuf;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let config = MicroVmConfig::builder()
+    ///     .rootfs(Rootfs::Native(PathBuf::from("/tmp")))
+    ///     .exec_path("/bin/echo")
+    ///     .env(["TZ=UTC".parse()?])
+    ///     .kernel_args(["loglevel=7"])
+    ///     .build();
+    ///
+    /// assert_eq!(config.kernel_cmdline(), ["TZ=UTC", "loglevel=7"]);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn kernel_cmdline(&self) -> Vec<String> {
+        self.env
+            .iter()
+            .map(|env| env.to_string())
+            .chain(self.kernel_args.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the arguments passt is started with to provide the guest's network interface.
+    ///
+    /// Only the `nat` network mode gives the guest a network interface, so this is `None` for the
+    /// other modes. passt talks to libkrun over `fd` and forwards the mapped TCP ports.
+    ///
+    /// ## Examples
+    /// ```rust
+    /// use microsandbox_core::{config::NetworkMode, vm::{MicroVmConfig, Rootfs}};
+    /// use std::path::PathBuf;
+    ///
+    /// # fn main() -> anyhow::Result<()> {
+    /// let config = MicroVmConfig::builder()
+    ///     .rootfs(Rootfs::Native(PathBuf::from("/tmp")))
+    ///     .exec_path("/bin/echo")
+    ///     .network(NetworkMode::Nat)
+    ///     .port_map(["8080:80".parse()?])
+    ///     .build();
+    ///
+    /// assert_eq!(
+    ///     config.passt_args(3).unwrap(),
+    ///     ["--foreground", "--fd", "3", "--tcp-ports", "8080:80"]
+    /// );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn passt_args(&self, fd: RawFd) -> Option<Vec<String>> {
+        if self.network != NetworkMode::Nat {
+            return None;
+        }
+
+        let mut args = vec![
+            "--foreground".to_string(),
+            "--fd".to_string(),
+            fd.to_string(),
+        ];
+        for port in &self.port_map {
+            args.push("--tcp-ports".to_string());
+            args.push(port.to_string());
+        }
+
+        Some(args)
+    }
+
+    /// Returns the scope that limits the guest sockets carried over the host's network.
+    ///
+    /// Without a network the guest can't reach anything, whatever the configured scope. With the
+    /// translated network guest sockets aren't carried over the host, so there is no scope.
+    pub fn tsi_scope(&self) -> Option<NetworkScope> {
+        match self.network {
+            NetworkMode::None => Some(NetworkScope::None),
+            NetworkMode::Host => Some(self.scope),
+            NetworkMode::Nat => None,
+        }
+    }
+
+    /// Validates the extra kernel arguments.
+    ///
+    /// Each argument must be a single printable ASCII word that isn't `--`, must not set one of
+    /// the [`RESERVED_KERNEL_PARAMS`], and must not set the same parameter as another argument or
+    /// an environment variable.
+    fn validate_kernel_args(&self) -> MicrosandboxResult<()> {
+        let mut params: Vec<&str> = self.env.iter().map(|env| env.get_name().as_str()).collect();
+
+        for arg in &self.kernel_args {
+            Self::validate_command_line(arg)?;
+            if arg.is_empty() || arg == "--" || arg.contains(' ') {
+                return Err(MicrosandboxError::InvalidMicroVMConfig(
+                    InvalidMicroVMConfigError::InvalidKernelArg(arg.clone()),
+                ));
+            }
+
+            let param = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+            if RESERVED_KERNEL_PARAMS.contains(&param) {
+                return Err(MicrosandboxError::InvalidMicroVMConfig(
+                    InvalidMicroVMConfigError::ReservedKernelArg(arg.clone()),
+                ));
+            }
+
+            if params.contains(&param) {
+                return Err(MicrosandboxError::InvalidMicroVMConfig(
+                    InvalidMicroVMConfigError::DuplicateKernelArg(arg.clone()),
+                ));
+            }
+            params.push(param);
+        }
+
         Ok(())
     }
 
@@ -677,6 +897,25 @@ impl Drop for MicroVm {
     }
 }
 
+/// Formats the rootfs the way it is recorded in the sandbox database, as `native:<path>` or
+/// `overlayfs:<lower>:...:<upper>`.
+impl Display for Rootfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rootfs::Native(path) => write!(f, "native:{}", path.to_string_lossy()),
+            Rootfs::Overlayfs(paths) => write!(
+                f,
+                "overlayfs:{}",
+                paths
+                    .iter()
+                    .map(|p| p.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(":")
+            ),
+        }
+    }
+}
+
 impl TryFrom<u8> for LogLevel {
     type Error = MicrosandboxError;
 
@@ -932,4 +1171,94 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_microvm_config_validation_kernel_args() -> anyhow::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let config_with = |kernel_args: &[&str]| -> anyhow::Result<MicroVmConfig> {
+            Ok(MicroVmConfig::builder()
+                .rootfs(Rootfs::Native(temp_dir.path().to_path_buf()))
+                .exec_path("/bin/echo")
+                .env(["TZ=UTC".parse()?])
+                .kernel_args(kernel_args.iter().copied())
+                .build())
+        };
+
+        assert!(
+            config_with(&["console=hvc0", "loglevel=7", "debug"])?
+                .validate()
+                .is_ok()
+        );
+
+        for arg in ["init=/bin/sh", "KRUN_INIT=/bin/sh", "root=/dev/vda"] {
+            assert!(matches!(
+                config_with(&[arg])?.validate(),
+                Err(MicrosandboxError::InvalidMicroVMConfig(
+                    InvalidMicroVMConfigError::ReservedKernelArg(_)
+                ))
+            ));
+        }
+
+        for args in [&["TZ=CET"][..], &["loglevel=7", "loglevel=3"][..]] {
+            assert!(matches!(
+                config_with(args)?.validate(),
+                Err(MicrosandboxError::InvalidMicroVMConfig(
+                    InvalidMicroVMConfigError::DuplicateKernelArg(_)
+                ))
+            ));
+        }
+
+        for arg in ["", "--", "a b"] {
+            assert!(matches!(
+                config_with(&[arg])?.validate(),
+                Err(MicrosandboxError::InvalidMicroVMConfig(
+                    InvalidMicroVMConfigError::InvalidKernelArg(_)
+                ))
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_microvm_config_network_setup() -> anyhow::Result<()> {
+        let config_with = |network| -> anyhow::Result<MicroVmConfig> {
+            Ok(MicroVmConfig::builder()
+                .rootfs(Rootfs::Native(PathBuf::from("/tmp")))
+                .exec_path("/bin/echo")
+                .port_map(["8080:80".parse()?, "3000".parse()?])
+                .scope(NetworkScope::Any)
+                .network(network)
+                .build())
+        };
+
+        // The default keeps carrying guest sockets over the host within the configured scope
+        let host = config_with(NetworkMode::default())?;
+        assert_eq!(host.network, NetworkMode::Host);
+        assert_eq!(host.passt_args(3), None);
+        assert_eq!(host.tsi_scope(), Some(NetworkScope::Any));
+
+        // No network interface is set up, and the guest can't open any connection
+        let none = config_with(NetworkMode::None)?;
+        assert_eq!(none.passt_args(3), None);
+        assert_eq!(none.tsi_scope(), Some(NetworkScope::None));
+
+        // The translated network gets an interface that forwards the mapped ports
+        let nat = config_with(NetworkMode::Nat)?;
+        assert_eq!(
+            nat.passt_args(3).unwrap(),
+            [
+                "--foreground",
+                "--fd",
+                "3",
+                "--tcp-ports",
+                "8080:80",
+                "--tcp-ports",
+                "3000"
+            ]
+        );
+        assert_eq!(nat.tsi_scope(), None);
+
+        Ok(())
+    }
 }
@@ -1,5 +1,6 @@
 //! Runtime management and configuration.
 
+mod balloon;
 mod builder;
 mod ffi;
 mod microvm;
@@ -9,6 +10,7 @@ mod rlimit;
 // Exports
 //--------------------------------------------------------------------------------------------------
 
+pub use balloon::*;
 pub use builder::*;
 #[allow(unused)]
 pub use ffi::*;
@@ -13,7 +13,11 @@ use std::{
 use tokio::{net::TcpListener, signal};
 
 use microsandbox_portal::{
-    portal::repl::{EngineHandle, start_engines},
+    portal::{
+        fs::FileSystem,
+        output::DEFAULT_MAX_OUTPUT_BYTES,
+        repl::{EngineHandle, start_engines_with},
+    },
     route::create_router,
     state::SharedState,
 };
@@ -36,6 +40,10 @@ struct PortalArgs {
     /// Port number to listen on
     #[arg(short, long)]
     port: Option<u16>,
+
+    /// Most output bytes kept for a single evaluation or command, across stdout and stderr
+    #[arg(long, default_value_t = DEFAULT_MAX_OUTPUT_BYTES)]
+    max_output_bytes: usize,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -43,7 +51,7 @@ struct PortalArgs {
 //--------------------------------------------------------------------------------------------------
 
 /// Shutdown signal handler
-async fn shutdown_signal(engine_handle: Option<EngineHandle>) {
+async fn shutdown_signal(engine_handle: Option<EngineHandle>, file_system: FileSystem) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -77,6 +85,12 @@ async fn shutdown_signal(engine_handle: Option<EngineHandle>) {
         }
     }
 
+    // No session outlives the portal, so neither do their temporary directories
+    let removed = file_system.cleanup_all_temp().await;
+    if removed > 0 {
+        tracing::info!("Removed {} temporary directories", removed);
+    }
+
     tracing::info!("Server shutdown complete");
 }
 
@@ -95,11 +109,14 @@ async fn main() -> Result<()> {
         .unwrap();
 
     // Initialize the engine handle
-    let state = SharedState::default();
+    let state = SharedState {
+        max_output_bytes: args.max_output_bytes,
+        ..Default::default()
+    };
     let engine_handle_for_shutdown = Arc::clone(&state.engine_handle);
 
     // Try to start the REPL engines
-    match start_engines().await {
+    match start_engines_with(state.max_output_bytes).await {
         Ok(engine_handle) => {
             tracing::info!("REPL engines started successfully");
             *engine_handle_for_shutdown.lock().await = Some(engine_handle.clone());
@@ -115,6 +132,7 @@ async fn main() -> Result<()> {
     tracing::info!("Starting microsandbox portal server on {}", addr);
 
     // Create the router
+    let file_system = state.file_system.clone();
     let app = create_router(state);
 
     // Clone for shutdown
@@ -123,7 +141,7 @@ async fn main() -> Result<()> {
     // Start the server with graceful shutdown
     let listener = TcpListener::bind(addr).await?;
     axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(engine_handle_clone))
+        .with_graceful_shutdown(shutdown_signal(engine_handle_clone, file_system))
         .await?;
 
     Ok(())
@@ -94,7 +94,7 @@ for i, fruit in enumerate(fruits):
             .await?;
 
         // Print the output
-        for line in result {
+        for line in result.lines {
             println!("[{:?}] {}", line.stream, line.text);
         }
     }
@@ -152,7 +152,7 @@ console.log("Waiting for data...");
             .await?;
 
         // Print the output
-        for line in result {
+        for line in result.lines {
             println!("[{:?}] {}", line.stream, line.text);
         }
     }
@@ -167,7 +167,7 @@ console.log("Waiting for data...");
         let result1 = _engine_handle
             .eval(python_step1, Language::Python, "123", None)
             .await?;
-        for line in result1 {
+        for line in result1.lines {
             println!("[{:?}] {}", line.stream, line.text);
         }
 
@@ -176,7 +176,7 @@ console.log("Waiting for data...");
         let result2 = _engine_handle
             .eval(python_step2, Language::Python, "123", None)
             .await?;
-        for line in result2 {
+        for line in result2.lines {
             println!("[{:?}] {}", line.stream, line.text);
         }
     }
@@ -191,7 +191,7 @@ console.log("Waiting for data...");
         let result1 = _engine_handle
             .eval(nodejs_step1, Language::Node, "123", None)
             .await?;
-        for line in result1 {
+        for line in result1.lines {
             println!("[{:?}] {}", line.stream, line.text);
         }
 
@@ -200,7 +200,7 @@ console.log("Waiting for data...");
         let result2 = _engine_handle
             .eval(nodejs_step2, Language::Node, "123", None)
             .await?;
-        for line in result2 {
+        for line in result2.lines {
             println!("[{:?}] {}", line.stream, line.text);
         }
     }
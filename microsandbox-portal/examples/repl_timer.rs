@@ -104,7 +104,7 @@ print("Python counting complete!")
             )
             .await?;
 
-        print_output("Python normal", &result);
+        print_output("Python normal", &result.lines);
     }
 
     // Example 2: Python code that runs in an infinite loop (should timeout)
@@ -131,7 +131,7 @@ while True:
             )
             .await?;
 
-        print_output("Python infinite loop", &result);
+        print_output("Python infinite loop", &result.lines);
     }
 
     // Example 3: Node.js code that completes normally
@@ -164,7 +164,7 @@ console.log("Node.js counting complete!");
             )
             .await?;
 
-        print_output("Node.js normal", &result);
+        print_output("Node.js normal", &result.lines);
     }
 
     // Example 4: Node.js code that runs in an infinite loop (should timeout)
@@ -197,7 +197,7 @@ while (true) {
             )
             .await?;
 
-        print_output("Node.js infinite loop", &result);
+        print_output("Node.js infinite loop", &result.lines);
     }
 
     println!("✅ All examples completed!");
@@ -62,6 +62,7 @@
 use anyhow::Result;
 use reqwest::Client;
 use serde_json::{Value, json};
+use std::collections::HashMap;
 
 // Import the parameter types from the microsandbox-portal crate
 use microsandbox_portal::payload::{JSONRPC_VERSION, JsonRpcRequest, SandboxCommandRunParams};
@@ -142,6 +143,9 @@ async fn main() -> Result<()> {
         command: "ls".to_string(),
         args: vec!["-la".to_string()],
         timeout: Some(30), // Add a 30 second timeout
+        session: None,
+        cwd: None,
+        env: HashMap::new(),
     };
 
     let result = send_rpc_request(&client, "sandbox.command.run", ls_params).await?;
@@ -187,6 +191,9 @@ async fn main() -> Result<()> {
         command: "echo".to_string(),
         args: vec!["Hello from the sandbox!".to_string()],
         timeout: None, // No timeout needed for simple echo command
+        session: None,
+        cwd: None,
+        env: HashMap::new(),
     };
 
     let result = send_rpc_request(&client, "sandbox.command.run", echo_params).await?;
@@ -232,6 +239,9 @@ async fn main() -> Result<()> {
         command: "nonexistent_command".to_string(),
         args: vec![],
         timeout: Some(5), // Short timeout
+        session: None,
+        cwd: None,
+        env: HashMap::new(),
     };
 
     // This will likely fail, so handle the error case
@@ -7,7 +7,9 @@ use axum::{
 };
 use thiserror::Error;
 
-use crate::payload::JsonRpcError;
+use microsandbox_utils::PORTAL_NOT_FOUND_ERROR_CODE;
+
+use crate::{payload::JsonRpcError, portal::fs::FsError};
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -31,47 +33,64 @@ pub enum PortalError {
     /// Error during parsing
     #[error("Parse error: {0}")]
     Parse(String),
+
+    /// A path or resource that does not exist
+    #[error("{0}")]
+    NotFound(String),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl PortalError {
+    /// Returns the JSON-RPC error code of the error
+    pub fn code(&self) -> i32 {
+        match self {
+            PortalError::JsonRpc(_) => -32600,
+            PortalError::MethodNotFound(_) => -32601,
+            PortalError::Parse(_) => -32700,
+            PortalError::Internal(_) => -32603,
+            PortalError::NotFound(_) => PORTAL_NOT_FOUND_ERROR_CODE,
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
 // Trait Implementations
 //--------------------------------------------------------------------------------------------------
 
+impl From<FsError> for PortalError {
+    fn from(error: FsError) -> Self {
+        match error {
+            FsError::NotFound(_) => PortalError::NotFound(error.to_string()),
+            FsError::OutsideRoot(_) | FsError::InvalidPrefix(_) | FsError::Symlink(_) => {
+                PortalError::JsonRpc(error.to_string())
+            }
+            FsError::Io(_) => PortalError::Internal(error.to_string()),
+        }
+    }
+}
+
 impl IntoResponse for PortalError {
     fn into_response(self) -> Response {
-        let (status, error_response) = match self {
-            PortalError::JsonRpc(message) => {
-                let error = JsonRpcError {
-                    code: -32600,
-                    message,
-                    data: None,
-                };
-                (StatusCode::BAD_REQUEST, error)
-            }
-            PortalError::MethodNotFound(message) => {
-                let error = JsonRpcError {
-                    code: -32601,
-                    message,
-                    data: None,
-                };
-                (StatusCode::NOT_FOUND, error)
-            }
-            PortalError::Parse(message) => {
-                let error = JsonRpcError {
-                    code: -32700,
-                    message,
-                    data: None,
-                };
-                (StatusCode::BAD_REQUEST, error)
-            }
-            PortalError::Internal(message) => {
-                let error = JsonRpcError {
-                    code: -32603,
-                    message,
-                    data: None,
-                };
-                (StatusCode::INTERNAL_SERVER_ERROR, error)
-            }
+        let status = match self {
+            PortalError::JsonRpc(_) | PortalError::Parse(_) => StatusCode::BAD_REQUEST,
+            PortalError::MethodNotFound(_) | PortalError::NotFound(_) => StatusCode::NOT_FOUND,
+            PortalError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let code = self.code();
+        let message = match self {
+            PortalError::JsonRpc(message)
+            | PortalError::MethodNotFound(message)
+            | PortalError::Parse(message)
+            | PortalError::Internal(message)
+            | PortalError::NotFound(message) => message,
+        };
+        let error_response = JsonRpcError {
+            code,
+            message,
+            data: None,
         };
 
         (status, Json(error_response)).into_response()
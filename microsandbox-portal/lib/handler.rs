@@ -1,23 +1,42 @@
 //! Request handlers for the microsandbox portal JSON-RPC server.
 
-use std::sync::atomic::Ordering;
+use std::{path::PathBuf, sync::atomic::Ordering, time::Duration};
 
 use axum::{Json, extract::State, http::StatusCode, response::IntoResponse};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::de::DeserializeOwned;
 use serde_json::{Value, json};
 use tracing::debug;
 
 use crate::{
     error::PortalError,
     payload::{
-        JSONRPC_VERSION, JsonRpcError, JsonRpcRequest, JsonRpcResponse, SandboxCommandRunParams,
+        FsReadEncoding, JSONRPC_VERSION, JsonRpcError, JsonRpcRequest, JsonRpcResponse,
+        SandboxCommandResetParams, SandboxCommandRunParams, SandboxFsCleanupTempParams,
+        SandboxFsListParams, SandboxFsMkdirParams, SandboxFsMktempParams, SandboxFsReadParams,
+        SandboxFsStatParams, SandboxFsWriteParams, SandboxPtyCloseParams, SandboxPtyOpenParams,
+        SandboxPtyReadParams, SandboxPtyResizeParams, SandboxPtyWriteParams,
+        SandboxReplInstallParams, SandboxReplInterruptParams, SandboxReplResetParams,
         SandboxReplRunParams,
     },
-    portal::command::create_command_executor,
+    portal::command::{
+        CommandContext, CommandError, CommandHandle, PtySize, create_command_executor,
+    },
     state::SharedState,
 };
 
 #[cfg(any(feature = "python", feature = "nodejs"))]
-use crate::portal::repl::{Language, start_engines};
+use crate::portal::repl::{EngineHandle, InterruptOutcome, Language, start_engines_with};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How long a pseudo-terminal read waits for output by default, in milliseconds
+const DEFAULT_PTY_READ_TIMEOUT_MS: u64 = 1000;
+
+/// The longest a pseudo-terminal read may wait for output, in milliseconds
+const MAX_PTY_READ_TIMEOUT_MS: u64 = 30_000;
 
 //--------------------------------------------------------------------------------------------------
 // Functions
@@ -72,6 +91,20 @@ pub async fn json_rpc_handler(
                 }
             }
         }
+        "sandbox.repl.reset" => match sandbox_repl_reset_impl(state, request.params).await {
+            Ok(result) => Ok((StatusCode::OK, Json(JsonRpcResponse::success(result, id)))),
+            Err(e) => Ok(create_error_response(e, id)),
+        },
+        "sandbox.repl.install" => match sandbox_repl_install_impl(state, request.params).await {
+            Ok(result) => Ok((StatusCode::OK, Json(JsonRpcResponse::success(result, id)))),
+            Err(e) => Ok(create_error_response(e, id)),
+        },
+        "sandbox.repl.interrupt" => {
+            match sandbox_repl_interrupt_impl(state, request.params).await {
+                Ok(result) => Ok((StatusCode::OK, Json(JsonRpcResponse::success(result, id)))),
+                Err(e) => Ok(create_error_response(e, id)),
+            }
+        }
         "sandbox.command.run" => {
             // Call the sandbox_command_run_impl function
             match sandbox_command_run_impl(state, request.params).await {
@@ -85,6 +118,43 @@ pub async fn json_rpc_handler(
                 }
             }
         }
+        "sandbox.command.reset" => match sandbox_command_reset_impl(state, request.params).await {
+            Ok(result) => Ok((StatusCode::OK, Json(JsonRpcResponse::success(result, id)))),
+            Err(e) => Ok(create_error_response(e, id)),
+        },
+        "sandbox.fs.stat"
+        | "sandbox.fs.list"
+        | "sandbox.fs.read"
+        | "sandbox.fs.write"
+        | "sandbox.fs.mkdir"
+        | "sandbox.fs.mktemp"
+        | "sandbox.fs.cleanup_temp" => {
+            // Call the sandbox_fs_impl function
+            match sandbox_fs_impl(state, method, request.params).await {
+                Ok(result) => {
+                    // Create JSON-RPC response with success
+                    Ok((StatusCode::OK, Json(JsonRpcResponse::success(result, id))))
+                }
+                Err(e) => {
+                    // Use our helper function to create the error response
+                    Ok(create_error_response(e, id))
+                }
+            }
+        }
+        "sandbox.pty.open" | "sandbox.pty.write" | "sandbox.pty.read" | "sandbox.pty.resize"
+        | "sandbox.pty.close" => {
+            // Call the sandbox_pty_impl function
+            match sandbox_pty_impl(state, method, request.params).await {
+                Ok(result) => {
+                    // Create JSON-RPC response with success
+                    Ok((StatusCode::OK, Json(JsonRpcResponse::success(result, id))))
+                }
+                Err(e) => {
+                    // Use our helper function to create the error response
+                    Ok(create_error_response(e, id))
+                }
+            }
+        }
         _ => {
             let error = PortalError::MethodNotFound(format!("Method not found: {}", method));
             Ok(create_error_response(error, id))
@@ -115,45 +185,14 @@ async fn sandbox_run_impl(_state: SharedState, params: Value) -> Result<Value, P
         #[cfg(feature = "nodejs")]
         "node" | "nodejs" | "javascript" => language = Language::Node,
         _ => {
-            // Check if we're being asked for a language that is supported but not enabled via features
-            let error_msg = match params.language.to_lowercase().as_str() {
-                "python" => {
-                    "Python language support is not enabled. Recompile with --features python"
-                        .to_string()
-                }
-                "node" | "nodejs" | "javascript" => {
-                    "Node.js language support is not enabled. Recompile with --features nodejs"
-                        .to_string()
-                }
-                _ => format!("Unsupported language: {}", params.language),
-            };
-
             #[allow(clippy::needless_return)]
-            return Err(PortalError::JsonRpc(error_msg));
+            return Err(unsupported_language(&params.language));
         }
     };
 
     // Get or initialize engine handle
-    // With tokio::sync::Mutex, we can safely .await while holding the lock
     #[cfg(any(feature = "python", feature = "nodejs"))]
-    let engine_handle = {
-        // Get the current engine handle if it exists
-        let mut lock = _state.engine_handle.lock().await;
-
-        if let Some(ref handle) = *lock {
-            handle.clone()
-        } else {
-            // Otherwise initialize a new engine
-            let handle = start_engines()
-                .await
-                .map_err(|e| PortalError::Internal(format!("Failed to start engines: {}", e)))?;
-
-            // Store the new handle in the shared state
-            *lock = Some(handle.clone());
-
-            handle
-        }
-    };
+    let engine_handle = engine_handle(&_state).await?;
 
     #[cfg(any(feature = "python", feature = "nodejs"))]
     debug!("Language: {}", params.language);
@@ -164,17 +203,21 @@ async fn sandbox_run_impl(_state: SharedState, params: Value) -> Result<Value, P
 
     // Execute the code in REPL
     #[cfg(any(feature = "python", feature = "nodejs"))]
-    let lines = engine_handle
+    let output = engine_handle
         .eval(&params.code, language, &temp_id, params.timeout)
         .await
         .map_err(|e| PortalError::Internal(format!("REPL execution failed: {}", e)))?;
 
     #[cfg(any(feature = "python", feature = "nodejs"))]
-    debug!("REPL execution produced {} output lines", lines.len());
+    debug!(
+        "REPL execution produced {} output lines",
+        output.lines.len()
+    );
 
     // Convert the lines to a format suitable for JSON
     #[cfg(any(feature = "python", feature = "nodejs"))]
-    let output_lines: Vec<Value> = lines
+    let output_lines: Vec<Value> = output
+        .lines
         .iter()
         .map(|line| {
             json!({
@@ -193,6 +236,8 @@ async fn sandbox_run_impl(_state: SharedState, params: Value) -> Result<Value, P
         "status": "success".to_string(),
         "language": params.language.to_string(),
         "output": output_lines,
+        "truncated": output.truncated,
+        "total_bytes": output.total_bytes,
     });
 
     #[cfg(any(feature = "python", feature = "nodejs"))]
@@ -202,6 +247,112 @@ async fn sandbox_run_impl(_state: SharedState, params: Value) -> Result<Value, P
     Ok(result)
 }
 
+/// Implementation for the sandbox REPL reset method, which clears a language's session
+async fn sandbox_repl_reset_impl(_state: SharedState, params: Value) -> Result<Value, PortalError> {
+    debug!(?params, "Sandbox REPL reset method called");
+
+    let params: SandboxReplResetParams = parse_params(params)?;
+
+    #[cfg(any(feature = "python", feature = "nodejs"))]
+    {
+        let language = match params.language.to_lowercase().as_str() {
+            #[cfg(feature = "python")]
+            "python" => Language::Python,
+            #[cfg(feature = "nodejs")]
+            "node" | "nodejs" | "javascript" => Language::Node,
+            _ => return Err(unsupported_language(&params.language)),
+        };
+
+        // Engines that haven't been started yet have no session to clear
+        let engine_handle = _state.engine_handle.lock().await.clone();
+        if let Some(handle) = engine_handle {
+            handle
+                .reset_session(language)
+                .await
+                .map_err(|e| PortalError::Internal(format!("REPL reset failed: {}", e)))?;
+        }
+
+        Ok(json!({}))
+    }
+
+    #[cfg(not(any(feature = "python", feature = "nodejs")))]
+    Err(unsupported_language(&params.language))
+}
+
+/// Implementation for the sandbox REPL install method, which installs packages for a language
+async fn sandbox_repl_install_impl(
+    _state: SharedState,
+    params: Value,
+) -> Result<Value, PortalError> {
+    debug!(?params, "Sandbox REPL install method called");
+
+    let params: SandboxReplInstallParams = parse_params(params)?;
+
+    #[cfg(any(feature = "python", feature = "nodejs"))]
+    {
+        let language = match params.language.to_lowercase().as_str() {
+            #[cfg(feature = "python")]
+            "python" => Language::Python,
+            #[cfg(feature = "nodejs")]
+            "node" | "nodejs" | "javascript" => Language::Node,
+            _ => return Err(unsupported_language(&params.language)),
+        };
+
+        // The packages are for the session, so the engines are started if they haven't been
+        let installs = engine_handle(&_state)
+            .await?
+            .install_packages(language, &params.packages)
+            .await
+            .map_err(|e| PortalError::Internal(format!("Package installation failed: {}", e)))?;
+
+        Ok(json!({
+            "language": params.language,
+            "success": installs.iter().all(|install| install.success),
+            "packages": installs,
+        }))
+    }
+
+    #[cfg(not(any(feature = "python", feature = "nodejs")))]
+    Err(unsupported_language(&params.language))
+}
+
+/// Implementation for the sandbox REPL interrupt method, which stops a language's running
+/// evaluation
+async fn sandbox_repl_interrupt_impl(
+    _state: SharedState,
+    params: Value,
+) -> Result<Value, PortalError> {
+    debug!(?params, "Sandbox REPL interrupt method called");
+
+    let params: SandboxReplInterruptParams = parse_params(params)?;
+
+    #[cfg(any(feature = "python", feature = "nodejs"))]
+    {
+        let language = match params.language.to_lowercase().as_str() {
+            #[cfg(feature = "python")]
+            "python" => Language::Python,
+            #[cfg(feature = "nodejs")]
+            "node" | "nodejs" | "javascript" => Language::Node,
+            _ => return Err(unsupported_language(&params.language)),
+        };
+
+        // Engines that haven't been started yet have nothing running
+        let engine_handle = _state.engine_handle.lock().await.clone();
+        let outcome = match engine_handle {
+            Some(handle) => handle
+                .interrupt(language)
+                .await
+                .map_err(|e| PortalError::Internal(format!("REPL interrupt failed: {}", e)))?,
+            None => InterruptOutcome::Idle,
+        };
+
+        Ok(json!({ "outcome": outcome }))
+    }
+
+    #[cfg(not(any(feature = "python", feature = "nodejs")))]
+    Err(unsupported_language(&params.language))
+}
+
 /// Implementation for sandbox command run method
 async fn sandbox_command_run_impl(state: SharedState, params: Value) -> Result<Value, PortalError> {
     debug!(?params, "Sandbox command run method called");
@@ -211,31 +362,44 @@ async fn sandbox_command_run_impl(state: SharedState, params: Value) -> Result<V
         .map_err(|e| PortalError::JsonRpc(format!("Invalid parameters: {}", e)))?;
 
     // Get or initialize command executor handle
-    let cmd_handle = {
-        // Get the current command handle if it exists
-        let mut lock = state.command_handle.lock().await;
+    let cmd_handle = command_handle(&state).await;
 
-        if let Some(ref handle) = *lock {
-            handle.clone()
-        } else {
-            // Otherwise initialize a new command executor
-            let handle = create_command_executor();
-
-            // Store the new handle in the shared state
-            *lock = Some(handle.clone());
-
-            handle
+    // Run in the session's working directory and environment, if the command is part of one
+    let context = CommandContext {
+        cwd: params.cwd.as_ref().map(PathBuf::from),
+        env: params.env.clone(),
+    };
+    let result = match &params.session {
+        Some(session) => {
+            cmd_handle
+                .execute_in_session(
+                    session,
+                    &params.command,
+                    params.args.clone(),
+                    context,
+                    params.stdin.clone(),
+                    params.timeout,
+                )
+                .await
+        }
+        None => {
+            cmd_handle
+                .execute_with_input(
+                    &params.command,
+                    params.args.clone(),
+                    context,
+                    params.stdin.clone(),
+                    params.timeout,
+                )
+                .await
         }
     };
-
-    // Execute the command
-    let (exit_code, output_lines) = cmd_handle
-        .execute(&params.command, params.args.clone(), params.timeout)
-        .await
-        .map_err(|e| PortalError::Internal(format!("Command execution failed: {}", e)))?;
+    let (exit_code, output) =
+        result.map_err(|e| PortalError::Internal(format!("Command execution failed: {}", e)))?;
 
     // Convert the output lines
-    let formatted_lines = output_lines
+    let formatted_lines = output
+        .lines
         .iter()
         .map(|line| {
             json!({
@@ -255,6 +419,8 @@ async fn sandbox_command_run_impl(state: SharedState, params: Value) -> Result<V
         "exit_code": exit_code,
         "success": exit_code == 0,
         "output": formatted_lines,
+        "truncated": output.truncated,
+        "total_bytes": output.total_bytes,
     });
 
     debug!("Returning command result with output: {}", result);
@@ -262,25 +428,255 @@ async fn sandbox_command_run_impl(state: SharedState, params: Value) -> Result<V
     Ok(result)
 }
 
+/// Implementation for the sandbox command reset method, which clears a command session
+async fn sandbox_command_reset_impl(
+    state: SharedState,
+    params: Value,
+) -> Result<Value, PortalError> {
+    debug!(?params, "Sandbox command reset method called");
+
+    let params: SandboxCommandResetParams = parse_params(params)?;
+    let cmd_handle = command_handle(&state).await;
+    let existed = cmd_handle.reset_session(&params.session);
+
+    // The session's temporary directories end with it
+    state.file_system.cleanup_temp(&params.session).await;
+
+    Ok(json!({ "existed": existed }))
+}
+
+/// Implementation for the sandbox file system methods used to copy files in and out
+async fn sandbox_fs_impl(
+    state: SharedState,
+    method: &str,
+    params: Value,
+) -> Result<Value, PortalError> {
+    debug!(?method, "Sandbox file system method called");
+
+    let fs = &state.file_system;
+    match method {
+        "sandbox.fs.stat" => {
+            let params: SandboxFsStatParams = parse_params(params)?;
+            Ok(json!(fs.stat(&params.path).await?))
+        }
+        "sandbox.fs.list" => {
+            let params: SandboxFsListParams = parse_params(params)?;
+            Ok(json!({ "entries": fs.list(&params.path).await? }))
+        }
+        "sandbox.fs.read" => {
+            let params: SandboxFsReadParams = parse_params(params)?;
+            let chunk = fs
+                .read_chunk(&params.path, params.offset, params.length)
+                .await?;
+
+            // Text is only sent as is when asked for and the chunk doesn't split a character
+            let text = (params.encoding == FsReadEncoding::Auto && !chunk.is_binary)
+                .then(|| std::str::from_utf8(&chunk.data).ok())
+                .flatten();
+            let (encoding, data) = match text {
+                Some(text) => ("utf-8", text.to_string()),
+                None => ("base64", BASE64.encode(&chunk.data)),
+            };
+
+            Ok(json!({
+                "data": data,
+                "encoding": encoding,
+                "eof": chunk.eof,
+                "size": chunk.size,
+                "mode": chunk.mode,
+                "content_type": chunk.content_type,
+                "is_binary": chunk.is_binary,
+            }))
+        }
+        "sandbox.fs.write" => {
+            let params: SandboxFsWriteParams = parse_params(params)?;
+            let data = BASE64
+                .decode(&params.data)
+                .map_err(|e| PortalError::JsonRpc(format!("Invalid base64 data: {}", e)))?;
+            fs.write_chunk(&params.path, params.offset, &data, params.mode)
+                .await?;
+            Ok(json!({ "written": data.len() }))
+        }
+        "sandbox.fs.mkdir" => {
+            let params: SandboxFsMkdirParams = parse_params(params)?;
+            fs.create_dir(&params.path, params.mode).await?;
+            Ok(json!({}))
+        }
+        "sandbox.fs.mktemp" => {
+            let params: SandboxFsMktempParams = parse_params(params)?;
+            let path = fs.make_temp_dir(&params.session, &params.prefix).await?;
+            Ok(json!({ "path": path }))
+        }
+        "sandbox.fs.cleanup_temp" => {
+            let params: SandboxFsCleanupTempParams = parse_params(params)?;
+            Ok(json!({ "removed": fs.cleanup_temp(&params.session).await }))
+        }
+        _ => Err(PortalError::MethodNotFound(format!(
+            "Method not found: {}",
+            method
+        ))),
+    }
+}
+
+/// Implementation for the sandbox pseudo-terminal methods used by interactive commands
+async fn sandbox_pty_impl(
+    state: SharedState,
+    method: &str,
+    params: Value,
+) -> Result<Value, PortalError> {
+    debug!(?method, "Sandbox pseudo-terminal method called");
+
+    let sessions = &state.pty_sessions;
+    match method {
+        "sandbox.pty.open" => {
+            let params: SandboxPtyOpenParams = parse_params(params)?;
+            let size = PtySize {
+                rows: params.rows,
+                cols: params.cols,
+            };
+            let cmd_handle = command_handle(&state).await;
+            let session = sessions
+                .open(&cmd_handle, params.command, params.args, size)
+                .await
+                .map_err(pty_error)?;
+            Ok(json!({ "session": session }))
+        }
+        "sandbox.pty.write" => {
+            let params: SandboxPtyWriteParams = parse_params(params)?;
+            let data = BASE64
+                .decode(&params.data)
+                .map_err(|e| PortalError::JsonRpc(format!("Invalid base64 data: {}", e)))?;
+            sessions
+                .write(&params.session, &data)
+                .await
+                .map_err(pty_error)?;
+            Ok(json!({ "written": data.len() }))
+        }
+        "sandbox.pty.read" => {
+            let params: SandboxPtyReadParams = parse_params(params)?;
+            let wait = Duration::from_millis(
+                params
+                    .timeout_ms
+                    .unwrap_or(DEFAULT_PTY_READ_TIMEOUT_MS)
+                    .min(MAX_PTY_READ_TIMEOUT_MS),
+            );
+            let output = sessions
+                .read(&params.session, wait)
+                .await
+                .map_err(pty_error)?;
+            Ok(json!({
+                "data": BASE64.encode(output.data),
+                "exit_code": output.exit_code,
+            }))
+        }
+        "sandbox.pty.resize" => {
+            let params: SandboxPtyResizeParams = parse_params(params)?;
+            let size = PtySize {
+                rows: params.rows,
+                cols: params.cols,
+            };
+            sessions
+                .resize(&params.session, size)
+                .await
+                .map_err(pty_error)?;
+            Ok(json!({}))
+        }
+        "sandbox.pty.close" => {
+            let params: SandboxPtyCloseParams = parse_params(params)?;
+            sessions.close(&params.session).await.map_err(pty_error)?;
+
+            // The session's temporary directories end with it
+            state.file_system.cleanup_temp(&params.session).await;
+            Ok(json!({}))
+        }
+        _ => Err(PortalError::MethodNotFound(format!(
+            "Method not found: {}",
+            method
+        ))),
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Helpers
 //--------------------------------------------------------------------------------------------------
 
+/// Get the shared REPL engine handle, starting the engines on first use
+#[cfg(any(feature = "python", feature = "nodejs"))]
+async fn engine_handle(state: &SharedState) -> Result<EngineHandle, PortalError> {
+    // With tokio::sync::Mutex, we can safely .await while holding the lock
+    let mut lock = state.engine_handle.lock().await;
+
+    if let Some(ref handle) = *lock {
+        return Ok(handle.clone());
+    }
+
+    // Otherwise initialize a new engine
+    let handle = start_engines_with(state.max_output_bytes)
+        .await
+        .map_err(|e| PortalError::Internal(format!("Failed to start engines: {}", e)))?;
+
+    // Store the new handle in the shared state
+    *lock = Some(handle.clone());
+
+    Ok(handle)
+}
+
+/// Get the shared command executor, starting it on first use
+async fn command_handle(state: &SharedState) -> CommandHandle {
+    // Get the current command handle if it exists
+    let mut lock = state.command_handle.lock().await;
+
+    if let Some(ref handle) = *lock {
+        handle.clone()
+    } else {
+        // Otherwise initialize a new command executor
+        let handle = create_command_executor().with_max_output_bytes(state.max_output_bytes);
+
+        // Store the new handle in the shared state
+        *lock = Some(handle.clone());
+
+        handle
+    }
+}
+
+/// Build the error for a language that is unsupported or not enabled via features
+fn unsupported_language(language: &str) -> PortalError {
+    let error_msg = match language.to_lowercase().as_str() {
+        "python" => {
+            "Python language support is not enabled. Recompile with --features python".to_string()
+        }
+        "node" | "nodejs" | "javascript" => {
+            "Node.js language support is not enabled. Recompile with --features nodejs".to_string()
+        }
+        _ => format!("Unsupported language: {}", language),
+    };
+
+    PortalError::JsonRpc(error_msg)
+}
+
+/// Convert a pseudo-terminal error into a portal error
+fn pty_error(error: CommandError) -> PortalError {
+    match error {
+        CommandError::Pty(message) if message.starts_with("Session not found") => {
+            PortalError::JsonRpc(message)
+        }
+        error => PortalError::Internal(format!("Pseudo-terminal error: {}", error)),
+    }
+}
+
+/// Deserialize method parameters into their structured type
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, PortalError> {
+    serde_json::from_value(params)
+        .map_err(|e| PortalError::JsonRpc(format!("Invalid parameters: {}", e)))
+}
+
 /// Helper function to create a JSON-RPC error response from a PortalError
 fn create_error_response(
     error: PortalError,
     id: Option<Value>,
 ) -> (StatusCode, Json<JsonRpcResponse>) {
-    // Determine appropriate JSON-RPC error code
-    let code = match &error {
-        PortalError::JsonRpc(_) => -32600,        // Invalid Request
-        PortalError::MethodNotFound(_) => -32601, // Method not found
-        PortalError::Parse(_) => -32700,          // Parse error
-        PortalError::Internal(_) => -32603,       // Internal error
-    };
-
     let json_rpc_error = JsonRpcError {
-        code,
+        code: error.code(),
         message: error.to_string(),
         data: None,
     };
@@ -291,3 +687,300 @@ fn create_error_response(
         Json(JsonRpcResponse::error(json_rpc_error, id)),
     )
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::TempDir;
+
+    use microsandbox_utils::PORTAL_NOT_FOUND_ERROR_CODE;
+
+    use super::*;
+    use crate::portal::fs::{EntryKind, FileSystem};
+
+    fn state_with_root() -> (SharedState, TempDir) {
+        let root = TempDir::new().unwrap();
+        let state = SharedState {
+            file_system: FileSystem::new(root.path()),
+            ..Default::default()
+        };
+        (state, root)
+    }
+
+    #[tokio::test]
+    async fn test_fs_copy_into_sandbox() {
+        let (state, root) = state_with_root();
+
+        // The file is written in two chunks, the way the CLI streams large files
+        let first = json!({ "path": "/data/script.sh", "data": BASE64.encode("#!/bin/sh\n"), "mode": 0o755 });
+        sandbox_fs_impl(state.clone(), "sandbox.fs.write", first)
+            .await
+            .unwrap();
+        let second = json!({ "path": "/data/script.sh", "offset": 10, "data": BASE64.encode("echo hi\n"), "mode": 0o755 });
+        sandbox_fs_impl(state.clone(), "sandbox.fs.write", second)
+            .await
+            .unwrap();
+
+        let path = root.path().join("data/script.sh");
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "#!/bin/sh\necho hi\n"
+        );
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fs_copy_out_of_sandbox() {
+        let (state, root) = state_with_root();
+        std::fs::create_dir_all(root.path().join("out/nested")).unwrap();
+        std::fs::write(root.path().join("out/nested/result.txt"), "done").unwrap();
+        std::fs::set_permissions(
+            root.path().join("out/nested/result.txt"),
+            std::fs::Permissions::from_mode(0o640),
+        )
+        .unwrap();
+
+        let listing = sandbox_fs_impl(state.clone(), "sandbox.fs.list", json!({ "path": "/out" }))
+            .await
+            .unwrap();
+        let entries: Vec<crate::portal::fs::FsEntry> =
+            serde_json::from_value(listing["entries"].clone()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].path, "nested");
+        assert_eq!(entries[0].kind, EntryKind::Directory);
+        assert_eq!(entries[1].path, "nested/result.txt");
+        assert_eq!(entries[1].kind, EntryKind::File);
+        assert_eq!(entries[1].mode, 0o640);
+
+        let chunk = sandbox_fs_impl(
+            state,
+            "sandbox.fs.read",
+            json!({ "path": "/out/nested/result.txt", "length": 2 }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            BASE64.decode(chunk["data"].as_str().unwrap()).unwrap(),
+            b"do"
+        );
+        assert_eq!(chunk["eof"], false);
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_reports_content_type_of_text_file() {
+        let (state, root) = state_with_root();
+        std::fs::write(root.path().join("notes.md"), "# héllo\n").unwrap();
+
+        let params = json!({ "path": "/notes.md", "encoding": "auto" });
+        let chunk = sandbox_fs_impl(state.clone(), "sandbox.fs.read", params)
+            .await
+            .unwrap();
+        assert_eq!(chunk["content_type"], "text/plain; charset=utf-8");
+        assert_eq!(chunk["is_binary"], false);
+        assert_eq!(chunk["encoding"], "utf-8");
+        assert_eq!(chunk["data"], "# héllo\n");
+        assert_eq!(chunk["size"], 9);
+        assert_eq!(chunk["eof"], true);
+
+        // Contents stay base64-encoded unless asked otherwise
+        let params = json!({ "path": "/notes.md" });
+        let chunk = sandbox_fs_impl(state, "sandbox.fs.read", params)
+            .await
+            .unwrap();
+        assert_eq!(chunk["encoding"], "base64");
+        assert_eq!(chunk["data"], BASE64.encode("# héllo\n"));
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_reports_content_type_of_binary_file() {
+        let (state, root) = state_with_root();
+        let png = [
+            0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, b'I', b'H',
+            b'D', b'R',
+        ];
+        std::fs::write(root.path().join("pixel.png"), png).unwrap();
+        std::fs::set_permissions(
+            root.path().join("pixel.png"),
+            std::fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+
+        // Binary data is base64-encoded even when text is allowed, for every chunk of the file
+        let params = json!({ "path": "/pixel.png", "offset": 12, "encoding": "auto" });
+        let chunk = sandbox_fs_impl(state, "sandbox.fs.read", params)
+            .await
+            .unwrap();
+        assert_eq!(chunk["content_type"], "image/png");
+        assert_eq!(chunk["is_binary"], true);
+        assert_eq!(chunk["encoding"], "base64");
+        assert_eq!(chunk["data"], BASE64.encode("IHDR"));
+        assert_eq!(chunk["size"], png.len());
+        assert_eq!(chunk["mode"], 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_fs_read_reports_content_type_of_empty_file() {
+        let (state, root) = state_with_root();
+        std::fs::write(root.path().join("empty"), "").unwrap();
+
+        let params = json!({ "path": "/empty", "encoding": "auto" });
+        let chunk = sandbox_fs_impl(state, "sandbox.fs.read", params)
+            .await
+            .unwrap();
+        assert_eq!(chunk["content_type"], "text/plain; charset=utf-8");
+        assert_eq!(chunk["is_binary"], false);
+        assert_eq!(chunk["data"], "");
+        assert_eq!(chunk["size"], 0);
+        assert_eq!(chunk["eof"], true);
+    }
+
+    #[tokio::test]
+    async fn test_fs_rejects_path_traversal() {
+        let (state, root) = state_with_root();
+        let outside = root.path().parent().unwrap().join("escaped.txt");
+
+        let params = json!({ "path": "../escaped.txt", "data": BASE64.encode("x") });
+        let result = sandbox_fs_impl(state.clone(), "sandbox.fs.write", params).await;
+        assert!(matches!(result, Err(PortalError::JsonRpc(_))));
+        assert!(!outside.exists());
+
+        let params = json!({ "path": "/a/../../etc/passwd" });
+        let result = sandbox_fs_impl(state.clone(), "sandbox.fs.read", params).await;
+        assert!(matches!(result, Err(PortalError::JsonRpc(_))));
+
+        // Symlinks can't be used to reach outside of the root either
+        std::os::unix::fs::symlink(root.path().parent().unwrap(), root.path().join("link"))
+            .unwrap();
+        let params = json!({ "path": "/link/escaped.txt", "data": BASE64.encode("x") });
+        let result = sandbox_fs_impl(state.clone(), "sandbox.fs.write", params).await;
+        assert!(matches!(result, Err(PortalError::JsonRpc(_))));
+        assert!(!outside.exists());
+
+        // A dangling symlink as the last component isn't followed either
+        std::os::unix::fs::symlink(&outside, root.path().join("dangling")).unwrap();
+        let params = json!({ "path": "/dangling", "data": BASE64.encode("x") });
+        let result = sandbox_fs_impl(state.clone(), "sandbox.fs.write", params).await;
+        assert!(matches!(result, Err(PortalError::JsonRpc(_))));
+        assert!(!outside.exists());
+
+        // A missing path is reported with its own error code
+        let params = json!({ "path": "/missing.txt" });
+        let result = sandbox_fs_impl(state, "sandbox.fs.stat", params).await;
+        assert!(matches!(
+            &result,
+            Err(error @ PortalError::NotFound(_)) if error.code() == PORTAL_NOT_FOUND_ERROR_CODE
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fs_temp_dir_removed_when_session_ends() {
+        let (state, root) = state_with_root();
+        let host_path = |made: &Value| {
+            let path = made["path"].as_str().unwrap();
+            root.path().join(path.trim_start_matches('/'))
+        };
+
+        let params = json!({ "session": "agent", "prefix": "scratch-" });
+        let made = sandbox_fs_impl(state.clone(), "sandbox.fs.mktemp", params.clone())
+            .await
+            .unwrap();
+        let other = sandbox_fs_impl(state.clone(), "sandbox.fs.mktemp", params)
+            .await
+            .unwrap();
+        assert_ne!(other["path"], made["path"]);
+
+        // The directory is confined to the root, private and can be written to
+        let path = made["path"].as_str().unwrap();
+        assert!(path.starts_with("/tmp/scratch-"));
+        let dir = host_path(&made);
+        assert!(dir.is_dir());
+        assert_eq!(
+            std::fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        let params = json!({ "path": format!("{}/notes.txt", path), "data": BASE64.encode("x") });
+        sandbox_fs_impl(state.clone(), "sandbox.fs.write", params)
+            .await
+            .unwrap();
+        assert!(dir.join("notes.txt").is_file());
+
+        // A prefix can't lead out of the temporary directory
+        let params = json!({ "session": "agent", "prefix": "../../escaped" });
+        let result = sandbox_fs_impl(state.clone(), "sandbox.fs.mktemp", params).await;
+        assert!(matches!(result, Err(PortalError::JsonRpc(_))));
+
+        // Ending a session removes its directories, but not another session's
+        let params = json!({ "session": "other" });
+        let kept = sandbox_fs_impl(state.clone(), "sandbox.fs.mktemp", params)
+            .await
+            .unwrap();
+
+        sandbox_command_reset_impl(state.clone(), json!({ "session": "agent" }))
+            .await
+            .unwrap();
+        assert!(!host_path(&made).exists());
+        assert!(!host_path(&other).exists());
+        assert!(host_path(&kept).is_dir());
+
+        // Directories can also be removed without ending the session
+        let params = json!({ "session": "other" });
+        let removed = sandbox_fs_impl(state, "sandbox.fs.cleanup_temp", params)
+            .await
+            .unwrap();
+        assert_eq!(removed["removed"], 1);
+        assert!(!host_path(&kept).exists());
+    }
+
+    #[tokio::test]
+    async fn test_pty_session_round_trip() {
+        let state = SharedState::default();
+
+        let params = json!({
+            "command": "sh",
+            "args": ["-c", "read line; echo got:$line; exit 3"],
+            "rows": 24,
+            "cols": 80,
+        });
+        let opened = sandbox_pty_impl(state.clone(), "sandbox.pty.open", params)
+            .await
+            .unwrap();
+        let session = opened["session"].as_str().unwrap().to_string();
+
+        let params = json!({ "session": session, "data": BASE64.encode("hi\n") });
+        sandbox_pty_impl(state.clone(), "sandbox.pty.write", params)
+            .await
+            .unwrap();
+
+        // Keep reading until the command has exited and all of its output is drained
+        let mut output = Vec::new();
+        let exit_code = loop {
+            let params = json!({ "session": session, "timeout_ms": 5000 });
+            let result = sandbox_pty_impl(state.clone(), "sandbox.pty.read", params)
+                .await
+                .unwrap();
+            output.extend(BASE64.decode(result["data"].as_str().unwrap()).unwrap());
+            if let Some(code) = result["exit_code"].as_i64() {
+                break code;
+            }
+        };
+
+        assert_eq!(exit_code, 3);
+        assert!(String::from_utf8_lossy(&output).contains("got:hi"));
+
+        let params = json!({ "session": session });
+        sandbox_pty_impl(state.clone(), "sandbox.pty.close", params)
+            .await
+            .unwrap();
+        let params = json!({ "session": session });
+        let result = sandbox_pty_impl(state, "sandbox.pty.close", params).await;
+        assert!(matches!(result, Err(PortalError::JsonRpc(_))));
+    }
+}
@@ -2,6 +2,7 @@
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -94,6 +95,199 @@ pub struct SandboxCommandRunParams {
 
     /// Optional timeout in seconds after which execution will be cancelled
     pub timeout: Option<u64>,
+
+    /// Optional session whose working directory and environment the command runs with
+    #[serde(default)]
+    pub session: Option<String>,
+
+    /// Optional working directory, relative to the session's when running in a session
+    #[serde(default)]
+    pub cwd: Option<String>,
+
+    /// Optional environment variables, kept for later commands when running in a session
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Optional input written to the command's stdin, which is closed afterwards
+    #[serde(default)]
+    pub stdin: Option<String>,
+}
+
+/// Request parameters for resetting the session of a REPL language
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxReplResetParams {
+    /// Programming language whose session is reset
+    pub language: String,
+}
+
+/// Request parameters for installing packages for a REPL language
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxReplInstallParams {
+    /// Programming language whose package manager installs the packages
+    pub language: String,
+
+    /// Packages to install, as the language's package manager takes them
+    pub packages: Vec<String>,
+}
+
+/// Request parameters for interrupting a REPL evaluation
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxReplInterruptParams {
+    /// Programming language whose running evaluation is interrupted
+    pub language: String,
+}
+
+/// Request parameters for resetting a command session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxCommandResetParams {
+    /// Name of the session to reset
+    pub session: String,
+}
+
+/// Request parameters for getting information about a path in the sandbox
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsStatParams {
+    /// Path in the sandbox
+    pub path: String,
+}
+
+/// Request parameters for listing a directory tree in the sandbox
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsListParams {
+    /// Path of the directory in the sandbox
+    pub path: String,
+}
+
+/// Request parameters for reading a chunk of a file in the sandbox
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsReadParams {
+    /// Path of the file in the sandbox
+    pub path: String,
+
+    /// Byte offset to start reading from
+    #[serde(default)]
+    pub offset: u64,
+
+    /// Maximum number of bytes to read
+    pub length: Option<u64>,
+
+    /// How the chunk contents are encoded in the response
+    #[serde(default)]
+    pub encoding: FsReadEncoding,
+}
+
+/// How the contents of a file chunk are encoded in a read response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsReadEncoding {
+    /// Always base64-encode the contents
+    #[default]
+    Base64,
+
+    /// Return text as is and base64-encode binary data
+    Auto,
+}
+
+/// Request parameters for writing a chunk of a file in the sandbox
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsWriteParams {
+    /// Path of the file in the sandbox
+    pub path: String,
+
+    /// Byte offset to write the chunk at. A chunk at offset zero truncates the file.
+    #[serde(default)]
+    pub offset: u64,
+
+    /// Base64-encoded chunk contents
+    pub data: String,
+
+    /// Permission bits to apply to the file
+    pub mode: Option<u32>,
+}
+
+/// Request parameters for creating a directory in the sandbox
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsMkdirParams {
+    /// Path of the directory in the sandbox
+    pub path: String,
+
+    /// Permission bits to apply to the directory
+    pub mode: Option<u32>,
+}
+
+/// Request parameters for making a temporary directory for a session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsMktempParams {
+    /// Session the directory belongs to, which removes it when it ends
+    pub session: String,
+
+    /// Prefix of the directory's name
+    #[serde(default)]
+    pub prefix: String,
+}
+
+/// Request parameters for removing the temporary directories of a session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxFsCleanupTempParams {
+    /// Session whose temporary directories are removed
+    pub session: String,
+}
+
+/// Request parameters for starting a command in a pseudo-terminal
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxPtyOpenParams {
+    /// The command to execute
+    pub command: String,
+
+    /// Optional arguments for the command
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Number of rows in the terminal
+    pub rows: u16,
+
+    /// Number of columns in the terminal
+    pub cols: u16,
+}
+
+/// Request parameters for writing input to a pseudo-terminal session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxPtyWriteParams {
+    /// ID of the session
+    pub session: String,
+
+    /// Base64-encoded input
+    pub data: String,
+}
+
+/// Request parameters for reading output from a pseudo-terminal session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxPtyReadParams {
+    /// ID of the session
+    pub session: String,
+
+    /// How long to wait for output, in milliseconds
+    pub timeout_ms: Option<u64>,
+}
+
+/// Request parameters for resizing a pseudo-terminal session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxPtyResizeParams {
+    /// ID of the session
+    pub session: String,
+
+    /// Number of rows in the terminal
+    pub rows: u16,
+
+    /// Number of columns in the terminal
+    pub cols: u16,
+}
+
+/// Request parameters for closing a pseudo-terminal session
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SandboxPtyCloseParams {
+    /// ID of the session
+    pub session: String,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -5,6 +5,9 @@
 //! - Spawning and managing command processes using tokio::process::Command
 //! - Streaming stdout and stderr output in real-time
 //! - Managing command lifecycle and termination
+//! - Running interactive commands attached to a pseudo-terminal
+//! - Carrying the working directory and environment between the commands of a session,
+//!   including directory changes made with `cd`
 //! - Providing a secure execution environment for system commands
 //!
 //! # Architecture
@@ -21,13 +24,21 @@
 //! variables to maintain system security. Command execution is isolated to prevent
 //! damage to the host system.
 
+use nix::pty::{Winsize, openpty};
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
     fmt,
+    os::fd::{AsRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    process::Stdio,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
+    fs::File,
+    io::{AsyncWriteExt, BufReader},
+    process::{Child, Command},
     sync::{
         mpsc::{self, Sender},
         oneshot,
@@ -36,7 +47,23 @@ use tokio::{
 };
 use uuid::Uuid;
 
-use crate::portal::repl::types::Stream;
+use crate::portal::{
+    output::{DEFAULT_MAX_OUTPUT_BYTES, LineReader, Output, OutputCap},
+    repl::types::Stream,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The most command sessions kept at once; the least recently used one is dropped beyond this
+pub const MAX_COMMAND_SESSIONS: usize = 256;
+
+/// How long a command session is kept without being used
+pub const COMMAND_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// Shells whose `-c` scripts report the directory they end in back to their session
+const SESSION_SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ash", "ksh"];
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -60,6 +87,67 @@ pub enum CommandError {
     /// Command environment unavailable
     #[error("Command environment unavailable: {0}")]
     Unavailable(String),
+
+    /// Error setting up or using a pseudo-terminal
+    #[error("Pseudo-terminal error: {0}")]
+    Pty(String),
+}
+
+/// Size of a pseudo-terminal in character cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PtySize {
+    /// Number of rows
+    pub rows: u16,
+
+    /// Number of columns
+    pub cols: u16,
+}
+
+/// A command running attached to a pseudo-terminal
+///
+/// Output from the command, including what it writes to stderr, is read from `reader`, and
+/// anything written to `writer` is fed to the command as terminal input.
+#[derive(Debug)]
+pub struct PtySession {
+    /// Read half of the terminal
+    pub reader: File,
+
+    /// Write half of the terminal
+    pub writer: File,
+
+    /// Resizes the terminal
+    pub resizer: PtyResizer,
+
+    /// The running command
+    pub child: Child,
+}
+
+/// Handle used to resize a pseudo-terminal
+#[derive(Debug)]
+pub struct PtyResizer {
+    /// The master side of the terminal
+    master: OwnedFd,
+}
+
+/// The working directory and environment a command runs with
+///
+/// Within a session, these are carried from one command to the next: each command adds its
+/// environment variables to the session's, and changes the session's working directory, resolving
+/// a relative one against the current one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandContext {
+    /// Working directory, or the portal's own if not set
+    pub cwd: Option<PathBuf>,
+
+    /// Environment variables added to the portal's own
+    pub env: HashMap<String, String>,
+}
+
+/// A command session's working directory and environment, with when it was last used
+#[derive(Debug)]
+struct CommandSession {
+    context: CommandContext,
+    last_used: Instant,
 }
 
 /// A single line of output from command execution
@@ -113,6 +201,8 @@ pub enum CommandResp {
 #[derive(Clone)]
 pub struct CommandHandle {
     cmd_sender: Sender<CommandRequest>,
+    sessions: Arc<Mutex<HashMap<String, CommandSession>>>,
+    max_output_bytes: usize,
 }
 
 // Implement Debug for CommandHandle
@@ -120,6 +210,8 @@ impl fmt::Debug for CommandHandle {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("CommandHandle")
             .field("cmd_sender", &"<SENDER>")
+            .field("sessions", &self.sessions)
+            .field("max_output_bytes", &self.max_output_bytes)
             .finish()
     }
 }
@@ -129,9 +221,12 @@ struct CommandRequest {
     id: String,
     command: String,
     args: Vec<String>,
+    context: CommandContext,
+    stdin: Option<String>,
     resp_tx: Sender<CommandResp>,
     done_tx: oneshot::Sender<Result<i32, CommandError>>,
     timeout: Option<u64>,
+    max_output_bytes: usize,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -156,20 +251,45 @@ impl CommandHandle {
                     id,
                     command,
                     args,
+                    context,
+                    stdin,
                     resp_tx,
                     done_tx,
                     timeout,
+                    max_output_bytes,
                 } = req;
 
                 // Execute the command in a separate task
                 tokio::spawn(async move {
-                    let result = execute_command(id, command, args, resp_tx.clone(), timeout).await;
+                    let result = execute_command(
+                        id,
+                        command,
+                        args,
+                        context,
+                        stdin,
+                        resp_tx.clone(),
+                        timeout,
+                        max_output_bytes,
+                    )
+                    .await;
                     let _ = done_tx.send(result);
                 });
             }
         });
 
-        Self { cmd_sender }
+        Self {
+            cmd_sender,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+
+    /// Sets the most output bytes kept for a single command
+    ///
+    /// Output beyond this is dropped, and the command's output is marked as truncated.
+    pub fn with_max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
     }
 
     /// Executes a command and streams the output
@@ -182,13 +302,65 @@ impl CommandHandle {
     ///
     /// # Returns
     ///
-    /// A tuple containing the exit code and a vector of output lines
+    /// A tuple containing the exit code and the output lines, capped at the handle's output limit
+    /// across stdout and stderr
     pub async fn execute<S: Into<String>>(
         &self,
         command: S,
         args: Vec<String>,
         timeout: Option<u64>,
-    ) -> Result<(i32, Vec<CommandLine>), CommandError> {
+    ) -> Result<(i32, Output<CommandLine>), CommandError> {
+        self.execute_with_context(command, args, CommandContext::default(), timeout)
+            .await
+    }
+
+    /// Executes a command with the given working directory and environment
+    ///
+    /// # Parameters
+    ///
+    /// * `command` - The command to execute
+    /// * `args` - Arguments to pass to the command
+    /// * `context` - Working directory and environment to run the command with
+    /// * `timeout` - Optional timeout in seconds after which execution will be cancelled
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing the exit code and the output lines, capped at the handle's output limit
+    /// across stdout and stderr
+    pub async fn execute_with_context<S: Into<String>>(
+        &self,
+        command: S,
+        args: Vec<String>,
+        context: CommandContext,
+        timeout: Option<u64>,
+    ) -> Result<(i32, Output<CommandLine>), CommandError> {
+        self.execute_with_input(command, args, context, None, timeout)
+            .await
+    }
+
+    /// Executes a command with the given working directory and environment, feeding it input
+    ///
+    /// # Parameters
+    ///
+    /// * `command` - The command to execute
+    /// * `args` - Arguments to pass to the command
+    /// * `context` - Working directory and environment to run the command with
+    /// * `stdin` - Optional input written to the command's stdin, which is closed afterwards.
+    ///   Without it, the command's stdin is empty
+    /// * `timeout` - Optional timeout in seconds after which execution will be cancelled
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing the exit code and the output lines, capped at the handle's output limit
+    /// across stdout and stderr
+    pub async fn execute_with_input<S: Into<String>>(
+        &self,
+        command: S,
+        args: Vec<String>,
+        context: CommandContext,
+        stdin: Option<String>,
+        timeout: Option<u64>,
+    ) -> Result<(i32, Output<CommandLine>), CommandError> {
         let command = command.into();
 
         // Generate a unique execution ID
@@ -205,9 +377,12 @@ impl CommandHandle {
                 id: execution_id,
                 command,
                 args,
+                context,
+                stdin,
                 resp_tx,
                 done_tx,
                 timeout,
+                max_output_bytes: self.max_output_bytes,
             })
             .await
             .map_err(|_| CommandError::Unavailable("Command executor not available".to_string()))?;
@@ -247,10 +422,16 @@ impl CommandHandle {
             exit_code
         });
 
-        // Collect all output lines
+        // Collect output lines up to the output limit, draining and counting the rest
+        let mut cap = OutputCap::new(self.max_output_bytes);
         let mut lines = Vec::new();
         while let Some(line) = line_rx.recv().await {
-            lines.push(line);
+            if let Some(text) = cap.admit(line.text) {
+                lines.push(CommandLine {
+                    stream: line.stream,
+                    text,
+                });
+            }
         }
 
         // Wait for processing to complete
@@ -261,7 +442,308 @@ impl CommandHandle {
             .await
             .map_err(|_| CommandError::ExecutionError("Command execution failed".to_string()))??;
 
-        Ok((result, lines))
+        Ok((result, cap.finish(lines)))
+    }
+
+    /// Executes a command as part of a session
+    ///
+    /// The command runs with the session's working directory and environment, updated with its
+    /// own as in [`update_session`](Self::update_session). A directory change made by the command
+    /// is kept for the session's later commands: a plain `cd [dir]` changes the session's working
+    /// directory without starting a process, and a shell script run with `-c` updates it to the
+    /// directory the script ends in.
+    ///
+    /// # Parameters
+    ///
+    /// * `session` - The name of the session
+    /// * `command` - The command to execute
+    /// * `args` - Arguments to pass to the command
+    /// * `context` - Working directory and environment given for the command
+    /// * `stdin` - Optional input written to the command's stdin, which is closed afterwards
+    /// * `timeout` - Optional timeout in seconds after which execution will be cancelled
+    ///
+    /// # Returns
+    ///
+    /// A tuple containing the exit code and the output lines, capped at the handle's output limit
+    /// across stdout and stderr
+    pub async fn execute_in_session<S: Into<String>>(
+        &self,
+        session: &str,
+        command: S,
+        mut args: Vec<String>,
+        context: CommandContext,
+        stdin: Option<String>,
+        timeout: Option<u64>,
+    ) -> Result<(i32, Output<CommandLine>), CommandError> {
+        let command = command.into();
+        let context = self.update_session(session, context);
+
+        if command == "cd" && args.len() <= 1 {
+            return Ok(self
+                .change_session_dir(session, &context, args.first())
+                .await);
+        }
+
+        // Have a shell script write the directory it ends in, so the next command starts there
+        let is_shell = Path::new(&command)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| SESSION_SHELLS.contains(&name));
+        let cwd_file = match args.as_mut_slice() {
+            [flag, script, ..] if is_shell && flag == "-c" => {
+                let path = std::env::temp_dir().join(format!("msb-cwd-{}", Uuid::new_v4()));
+                *script = format!("trap 'pwd -P > \"{}\"' EXIT\n{}", path.display(), script);
+                Some(path)
+            }
+            _ => None,
+        };
+
+        let result = self
+            .execute_with_input(command, args, context, stdin, timeout)
+            .await;
+
+        if let Some(path) = cwd_file {
+            if let Ok(cwd) = tokio::fs::read_to_string(&path).await {
+                let cwd = cwd.trim_end_matches('\n');
+                if !cwd.is_empty() {
+                    self.set_session_cwd(session, PathBuf::from(cwd));
+                }
+            }
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+
+        result
+    }
+
+    /// Applies a command's working directory and environment to a session
+    ///
+    /// The session is created on first use. The command's environment variables are added to the
+    /// session's, and its working directory replaces the session's, with a relative one resolved
+    /// against the session's current working directory.
+    ///
+    /// Sessions unused for [`COMMAND_SESSION_IDLE_TIMEOUT`] are dropped, and when a new session
+    /// would exceed [`MAX_COMMAND_SESSIONS`], the least recently used one is dropped to make room.
+    ///
+    /// # Parameters
+    ///
+    /// * `session` - The name of the session
+    /// * `context` - The working directory and environment given for the command
+    ///
+    /// # Returns
+    ///
+    /// The session's working directory and environment to run the command with
+    pub fn update_session(&self, session: &str, context: CommandContext) -> CommandContext {
+        self.update_session_at(session, context, Instant::now())
+    }
+
+    /// Applies a command's working directory and environment to a session, as of `now`
+    fn update_session_at(
+        &self,
+        session: &str,
+        context: CommandContext,
+        now: Instant,
+    ) -> CommandContext {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        // Drop idle sessions, then the least recently used one if a new session needs room
+        sessions.retain(|_, entry| {
+            now.saturating_duration_since(entry.last_used) < COMMAND_SESSION_IDLE_TIMEOUT
+        });
+        if !sessions.contains_key(session) && sessions.len() >= MAX_COMMAND_SESSIONS {
+            let oldest = sessions
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                sessions.remove(&oldest);
+            }
+        }
+
+        let entry = sessions
+            .entry(session.to_string())
+            .or_insert_with(|| CommandSession {
+                context: CommandContext::default(),
+                last_used: now,
+            });
+        entry.last_used = now;
+
+        let current = &mut entry.context;
+        if let Some(cwd) = context.cwd {
+            current.cwd = Some(match &current.cwd {
+                Some(base) if cwd.is_relative() => base.join(cwd),
+                _ => cwd,
+            });
+        }
+        current.env.extend(context.env);
+
+        current.clone()
+    }
+
+    /// Clears the working directory and environment of a session
+    ///
+    /// # Returns
+    ///
+    /// Whether the session existed
+    pub fn reset_session(&self, session: &str) -> bool {
+        self.sessions.lock().unwrap().remove(session).is_some()
+    }
+
+    /// Sets the working directory of a session, if the session is still kept
+    fn set_session_cwd(&self, session: &str, cwd: PathBuf) {
+        if let Some(entry) = self.sessions.lock().unwrap().get_mut(session) {
+            entry.context.cwd = Some(cwd);
+        }
+    }
+
+    /// Changes a session's working directory the way a shell's `cd` would
+    ///
+    /// Without a directory, this changes to the session's `HOME`. A relative directory is
+    /// resolved against the session's working directory.
+    async fn change_session_dir(
+        &self,
+        session: &str,
+        context: &CommandContext,
+        dir: Option<&String>,
+    ) -> (i32, Output<CommandLine>) {
+        let target = match dir {
+            Some(dir) => PathBuf::from(dir),
+            None => context
+                .env
+                .get("HOME")
+                .cloned()
+                .or_else(|| std::env::var("HOME").ok())
+                .map_or_else(|| PathBuf::from("/"), PathBuf::from),
+        };
+        let base = match &context.cwd {
+            Some(cwd) => Some(cwd.clone()),
+            None => std::env::current_dir().ok(),
+        };
+        let target = match base {
+            Some(base) if target.is_relative() => base.join(target),
+            _ => target,
+        };
+
+        let mut cap = OutputCap::new(self.max_output_bytes);
+        match tokio::fs::canonicalize(&target).await {
+            Ok(path) if path.is_dir() => {
+                self.set_session_cwd(session, path);
+                (0, cap.finish(Vec::new()))
+            }
+            result => {
+                let reason = match result {
+                    Ok(_) => "Not a directory".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                let text = format!("cd: {}: {}", target.display(), reason);
+                let lines = cap
+                    .admit(text)
+                    .map(|text| CommandLine {
+                        stream: Stream::Stderr,
+                        text,
+                    })
+                    .into_iter()
+                    .collect();
+                (1, cap.finish(lines))
+            }
+        }
+    }
+}
+
+impl CommandHandle {
+    /// Executes a command attached to a new pseudo-terminal of the given size
+    ///
+    /// The command runs in its own session with the terminal as its controlling terminal, so
+    /// interactive programs such as shells and editors behave as they would locally.
+    ///
+    /// # Parameters
+    ///
+    /// * `command` - The command to execute
+    /// * `args` - Arguments to pass to the command
+    /// * `size` - Initial size of the terminal
+    pub fn execute_pty<S: Into<String>>(
+        &self,
+        command: S,
+        args: Vec<String>,
+        size: PtySize,
+    ) -> Result<PtySession, CommandError> {
+        let winsize: Winsize = size.into();
+        let pty = openpty(&winsize, None)
+            .map_err(|e| CommandError::Pty(format!("Failed to open pseudo-terminal: {}", e)))?;
+
+        // Keep both ends out of other commands; the child gets the slave end as its stdio only
+        set_cloexec(&pty.master)?;
+        set_cloexec(&pty.slave)?;
+
+        let stdin = pty.slave.try_clone().map_err(pty_error)?;
+        let stdout = pty.slave.try_clone().map_err(pty_error)?;
+        let stderr = pty.slave;
+
+        let mut command = Command::new(command.into());
+        command
+            .args(&args)
+            .stdin(Stdio::from(stdin))
+            .stdout(Stdio::from(stdout))
+            .stderr(Stdio::from(stderr))
+            .kill_on_drop(true);
+
+        // Make the terminal the controlling terminal of a new session
+        unsafe {
+            command.pre_exec(|| {
+                libc::setsid();
+                if libc::ioctl(libc::STDIN_FILENO, libc::TIOCSCTTY as _, 0 as libc::c_long) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command
+            .spawn()
+            .map_err(|e| CommandError::SpawnError(format!("Failed to spawn command: {}", e)))?;
+
+        // The slave ends were moved into the command, so only the child holds them now
+        drop(command);
+
+        let reader = pty.master.try_clone().map_err(pty_error)?;
+        let writer = pty.master.try_clone().map_err(pty_error)?;
+
+        Ok(PtySession {
+            reader: File::from_std(std::fs::File::from(reader)),
+            writer: File::from_std(std::fs::File::from(writer)),
+            resizer: PtyResizer { master: pty.master },
+            child,
+        })
+    }
+}
+
+impl PtySession {
+    /// Resizes the terminal, which sends SIGWINCH to the command
+    pub fn resize(&self, size: PtySize) -> Result<(), CommandError> {
+        self.resizer.resize(size)
+    }
+}
+
+impl PtyResizer {
+    /// Resizes the terminal, which sends SIGWINCH to the command
+    pub fn resize(&self, size: PtySize) -> Result<(), CommandError> {
+        let winsize: Winsize = size.into();
+        let result = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) };
+        if result < 0 {
+            return Err(pty_error(std::io::Error::last_os_error()));
+        }
+
+        Ok(())
+    }
+}
+
+impl From<PtySize> for Winsize {
+    fn from(size: PtySize) -> Self {
+        Winsize {
+            ws_row: size.rows,
+            ws_col: size.cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        }
     }
 }
 
@@ -275,22 +757,46 @@ pub fn create_command_executor() -> CommandHandle {
 }
 
 /// Executes a system command and streams the output
+///
+/// Each line is read holding at most `max_output_bytes` bytes of it, so a command printing
+/// without a newline cannot exhaust the portal's memory.
+#[allow(clippy::too_many_arguments)]
 async fn execute_command(
     id: String,
     command: String,
     args: Vec<String>,
+    context: CommandContext,
+    stdin: Option<String>,
     resp_tx: Sender<CommandResp>,
     timeout: Option<u64>,
+    max_output_bytes: usize,
 ) -> Result<i32, CommandError> {
     // Spawn the command process
-    let mut process = Command::new(&command)
+    let mut cmd = Command::new(&command);
+    if let Some(cwd) = &context.cwd {
+        cmd.current_dir(cwd);
+    }
+
+    let mut process = cmd
         .args(&args)
-        .stdin(std::process::Stdio::null())
+        .envs(&context.env)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
         .stdout(std::process::Stdio::piped())
         .stderr(std::process::Stdio::piped())
         .spawn()
         .map_err(|e| CommandError::SpawnError(format!("Failed to spawn command: {}", e)))?;
 
+    // Feed the input in the background and close stdin afterwards, so the command sees its end
+    if let (Some(input), Some(mut pipe)) = (stdin, process.stdin.take()) {
+        tokio::spawn(async move {
+            let _ = pipe.write_all(input.as_bytes()).await;
+        });
+    }
+
     // Get stdout and stderr handles
     let stdout = process
         .stdout
@@ -312,7 +818,7 @@ async fn execute_command(
     let stdout_processing = Arc::clone(&processing);
 
     let stdout_handle = tokio::spawn(async move {
-        let mut lines = stdout_reader.lines();
+        let mut lines = LineReader::new(stdout_reader, max_output_bytes);
 
         while let Ok(Some(line)) = lines.next_line().await {
             if *stdout_processing.lock().unwrap() {
@@ -336,7 +842,7 @@ async fn execute_command(
     let stderr_processing = Arc::clone(&processing);
 
     let stderr_handle = tokio::spawn(async move {
-        let mut lines = stderr_reader.lines();
+        let mut lines = LineReader::new(stderr_reader, max_output_bytes);
 
         while let Ok(Some(line)) = lines.next_line().await {
             if *stderr_processing.lock().unwrap() {
@@ -418,3 +924,306 @@ async fn execute_command(
 
     result
 }
+
+/// Converts an I/O error on the pseudo-terminal into a command error
+fn pty_error(error: std::io::Error) -> CommandError {
+    CommandError::Pty(error.to_string())
+}
+
+/// Marks a descriptor close-on-exec, so it isn't inherited by the commands the portal spawns
+fn set_cloexec(fd: &OwnedFd) -> Result<(), CommandError> {
+    let fd = fd.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+        return Err(pty_error(std::io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    /// Reads everything the command writes until the terminal is closed
+    async fn read_all(session: &mut PtySession) -> String {
+        let mut output = Vec::new();
+        let mut buffer = [0; 1024];
+
+        // Reading the master fails with EIO once the command has exited and closed the terminal
+        while let Ok(read) = session.reader.read(&mut buffer).await {
+            if read == 0 {
+                break;
+            }
+            output.extend_from_slice(&buffer[..read]);
+        }
+
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_session_carries_cwd_and_env_until_reset() {
+        let handle = create_command_executor();
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        std::fs::create_dir(root_path.join("work")).unwrap();
+
+        let env = HashMap::from([("MSB_SESSION_VAR".to_string(), "kept".to_string())]);
+        handle.update_session(
+            "s1",
+            CommandContext {
+                cwd: Some(root_path.clone()),
+                env,
+            },
+        );
+
+        // A relative working directory is resolved against the session's current one
+        let context = handle.update_session(
+            "s1",
+            CommandContext {
+                cwd: Some(PathBuf::from("work")),
+                env: HashMap::new(),
+            },
+        );
+        let script = "pwd; echo $MSB_SESSION_VAR".to_string();
+        let (exit_code, output) = handle
+            .execute_with_context("sh", vec!["-c".to_string(), script], context, None)
+            .await
+            .unwrap();
+
+        assert_eq!(exit_code, 0);
+        let output: Vec<_> = output.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(
+            output,
+            vec![root_path.join("work").to_str().unwrap(), "kept"]
+        );
+
+        // Other sessions don't see it, and resetting clears it
+        assert_eq!(
+            handle.update_session("s2", CommandContext::default()),
+            CommandContext::default()
+        );
+        assert!(handle.reset_session("s1"));
+        assert!(!handle.reset_session("s1"));
+        assert_eq!(
+            handle.update_session("s1", CommandContext::default()),
+            CommandContext::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_keeps_directory_changes() {
+        let handle = create_command_executor();
+        let root = tempfile::tempdir().unwrap();
+        let root_path = root.path().canonicalize().unwrap();
+        std::fs::create_dir_all(root_path.join("a/b")).unwrap();
+        let start = CommandContext {
+            cwd: Some(root_path.clone()),
+            env: HashMap::new(),
+        };
+
+        // A plain `cd` changes the session's directory
+        let (exit_code, _) = handle
+            .execute_in_session("s1", "cd", vec!["a".to_string()], start, None, None)
+            .await
+            .unwrap();
+        assert_eq!(exit_code, 0);
+
+        // A shell script's `cd` is kept too
+        let script = "cd b".to_string();
+        let (exit_code, _) = handle
+            .execute_in_session(
+                "s1",
+                "sh",
+                vec!["-c".to_string(), script],
+                CommandContext::default(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(exit_code, 0);
+
+        let (_, output) = handle
+            .execute_in_session("s1", "pwd", vec![], CommandContext::default(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            output.lines[0].text,
+            root_path.join("a/b").to_str().unwrap()
+        );
+
+        // Changing to a missing directory fails and keeps the current one
+        let (exit_code, output) = handle
+            .execute_in_session(
+                "s1",
+                "cd",
+                vec!["missing".to_string()],
+                CommandContext::default(),
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(exit_code, 1);
+        assert!(matches!(output.lines[0].stream, Stream::Stderr));
+        assert_eq!(
+            handle.update_session("s1", CommandContext::default()).cwd,
+            Some(root_path.join("a/b"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_capped_and_expire() {
+        let handle = create_command_executor();
+        let now = Instant::now();
+        let context = || CommandContext {
+            cwd: Some(PathBuf::from("/tmp")),
+            env: HashMap::new(),
+        };
+
+        // Beyond the cap, the least recently used session is dropped
+        for i in 0..MAX_COMMAND_SESSIONS {
+            let at = now + Duration::from_secs(i as u64);
+            handle.update_session_at(&format!("s{}", i), context(), at);
+        }
+        let at = now + Duration::from_secs(MAX_COMMAND_SESSIONS as u64);
+        handle.update_session_at("new", context(), at);
+        assert_eq!(handle.sessions.lock().unwrap().len(), MAX_COMMAND_SESSIONS);
+        assert!(!handle.reset_session("s0"));
+        assert!(handle.reset_session("s1"));
+
+        // Sessions unused for the idle timeout are dropped
+        let later = at + COMMAND_SESSION_IDLE_TIMEOUT;
+        handle.update_session_at("other", CommandContext::default(), later);
+        let sessions = handle.sessions.lock().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert!(sessions.contains_key("other"));
+    }
+
+    #[tokio::test]
+    async fn test_execute_with_input_feeds_stdin() {
+        let handle = create_command_executor();
+
+        let (exit_code, output) = handle
+            .execute_with_input(
+                "cat",
+                vec![],
+                CommandContext::default(),
+                Some("hello\nworld\n".to_string()),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(exit_code, 0);
+        let output: Vec<_> = output.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(output, vec!["hello", "world"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_caps_output() {
+        let handle = create_command_executor().with_max_output_bytes(10);
+
+        // Below the cap, stdout and stderr are kept whole
+        let script = "echo hello; echo oops >&2".to_string();
+        let (_, output) = handle
+            .execute("sh", vec!["-c".to_string(), script], None)
+            .await
+            .unwrap();
+        assert!(!output.truncated);
+        assert_eq!(output.total_bytes, 9);
+        assert_eq!(output.lines.len(), 2);
+
+        // Above the cap, the kept output is a prefix of what was produced
+        let script = "for i in 1 2 3 4 5; do echo line$i; done".to_string();
+        let (exit_code, output) = handle
+            .execute("sh", vec!["-c".to_string(), script], None)
+            .await
+            .unwrap();
+        assert_eq!(exit_code, 0);
+        assert!(output.truncated);
+        assert_eq!(output.total_bytes, 25);
+        let kept: Vec<_> = output.lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(kept, vec!["line1", "line"]);
+    }
+
+    #[tokio::test]
+    async fn test_execute_pty_reports_terminal_size() {
+        let handle = create_command_executor();
+        let mut session = handle
+            .execute_pty(
+                "stty",
+                vec!["size".to_string()],
+                PtySize {
+                    rows: 33,
+                    cols: 101,
+                },
+            )
+            .unwrap();
+
+        let output = read_all(&mut session).await;
+        let status = session.child.wait().await.unwrap();
+
+        assert!(status.success());
+        assert_eq!(output.trim(), "33 101");
+    }
+
+    #[tokio::test]
+    async fn test_execute_pty_keeps_master_from_command() {
+        let handle = create_command_executor();
+        let open = handle
+            .execute_pty("cat", vec![], PtySize { rows: 24, cols: 80 })
+            .unwrap();
+        let master = open.resizer.master.as_raw_fd();
+
+        // The terminal of a session still open in the portal would show up in a new command if
+        // it were inherited
+        let script = format!(
+            "[ -e /proc/$$/fd/{} ] && echo leaked || echo closed",
+            master
+        );
+        let mut session = handle
+            .execute_pty(
+                "sh",
+                vec!["-c".to_string(), script],
+                PtySize { rows: 24, cols: 80 },
+            )
+            .unwrap();
+        let output = read_all(&mut session).await;
+
+        assert_eq!(output.trim(), "closed");
+    }
+
+    #[tokio::test]
+    async fn test_execute_pty_resize() {
+        let handle = create_command_executor();
+        let mut session = handle
+            .execute_pty(
+                "sh",
+                vec!["-c".to_string(), "read _; stty size".to_string()],
+                PtySize { rows: 24, cols: 80 },
+            )
+            .unwrap();
+
+        // Resize before letting the command query the size
+        session
+            .resize(PtySize {
+                rows: 50,
+                cols: 132,
+            })
+            .unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut session.writer, b"\n")
+            .await
+            .unwrap();
+
+        let output = read_all(&mut session).await;
+        assert!(output.contains("50 132"), "unexpected output: {output:?}");
+    }
+}
@@ -1 +1,536 @@
-//! TODO: Implement file system operations for the microsandbox portal.
+//! File system operations for the microsandbox portal.
+//!
+//! This module provides the file operations used to copy files between the host and a running
+//! sandbox. It handles:
+//! - Confining every path to the sandbox root, rejecting `..` traversal and symlink escapes
+//! - Reading and writing files in chunks so large files can be streamed
+//! - Detecting the content type of a file from its leading bytes when it is read
+//! - Listing directory trees with their permission bits so they can be recreated elsewhere
+//! - Making scratch directories for sessions and removing them when the session ends
+//!
+//! # Security Considerations
+//!
+//! Paths are resolved lexically against the root first, and the nearest existing ancestor is then
+//! canonicalized to make sure no symlink along the way points outside of the root.
+
+use std::{
+    collections::HashMap,
+    io::SeekFrom,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rand::{Rng, distr::Alphanumeric};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, DirBuilder, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The root that guest paths are confined to by default
+pub const DEFAULT_FS_ROOT: &str = "/";
+
+/// The largest chunk that can be read or written in a single request
+pub const MAX_FS_CHUNK_SIZE: u64 = 4 * 1024 * 1024;
+
+/// The directory, relative to the root, that temporary directories are made in
+pub const TEMP_DIR: &str = "/tmp";
+
+/// The longest prefix a temporary directory name can have
+const MAX_TEMP_PREFIX_LEN: usize = 64;
+
+/// The number of random characters that follow the prefix of a temporary directory name
+const TEMP_SUFFIX_LEN: usize = 12;
+
+/// How many names are tried for a temporary directory before giving up
+const MAX_TEMP_ATTEMPTS: usize = 16;
+
+/// How many leading bytes of a file are inspected to detect its content type
+const CONTENT_SNIFF_LEN: usize = 8 * 1024;
+
+/// The content type reported for text that has no more specific type
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// The content type reported for binary data that has no more specific type
+const BINARY_CONTENT_TYPE: &str = "application/octet-stream";
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Error types that can occur during file system operations
+#[derive(Debug, thiserror::Error)]
+pub enum FsError {
+    /// The path resolves to somewhere outside of the sandbox root
+    #[error("Path escapes the sandbox root: {0}")]
+    OutsideRoot(String),
+
+    /// The path does not exist
+    #[error("Path not found: {0}")]
+    NotFound(String),
+
+    /// The last component of a path to write is a symlink
+    #[error("Refusing to write through a symlink: {0}")]
+    Symlink(String),
+
+    /// The prefix of a temporary directory is not a plain file name
+    #[error("Invalid temporary directory prefix: {0}")]
+    InvalidPrefix(String),
+
+    /// An I/O error occurred
+    #[error("File system error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// The kind of a file system entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    /// A regular file
+    File,
+
+    /// A directory
+    Directory,
+
+    /// A symbolic link
+    Symlink,
+}
+
+/// A single file system entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FsEntry {
+    /// Path of the entry, relative to the listed directory for listings
+    pub path: String,
+
+    /// Kind of the entry
+    pub kind: EntryKind,
+
+    /// Permission bits of the entry
+    pub mode: u32,
+
+    /// Size of the entry in bytes
+    pub size: u64,
+}
+
+/// A chunk of a file along with what is known about the whole file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChunk {
+    /// The bytes read
+    pub data: Vec<u8>,
+
+    /// Whether the end of the file was reached
+    pub eof: bool,
+
+    /// Size of the whole file in bytes
+    pub size: u64,
+
+    /// Permission bits of the file
+    pub mode: u32,
+
+    /// MIME type detected from the leading bytes of the file
+    pub content_type: String,
+
+    /// Whether the file holds binary rather than text data
+    pub is_binary: bool,
+}
+
+/// File system access confined to a root directory
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+    /// The directory all paths are resolved against
+    root: PathBuf,
+
+    /// Guest paths of the temporary directories made for each session
+    temp_dirs: Arc<Mutex<HashMap<String, Vec<String>>>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl FileSystem {
+    /// Create a new file system confined to the given root
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            temp_dirs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Get the root all paths are confined to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolve a guest path to a path under the root
+    ///
+    /// Absolute and relative paths are both treated as relative to the root.
+    pub async fn resolve(&self, path: &str) -> Result<PathBuf, FsError> {
+        let mut relative = PathBuf::new();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::ParentDir => {
+                    if !relative.pop() {
+                        return Err(FsError::OutsideRoot(path.to_string()));
+                    }
+                }
+                Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+            }
+        }
+
+        let resolved = self.root.join(&relative);
+
+        // Make sure no symlink along the way leads outside of the root
+        let root = fs::canonicalize(&self.root).await?;
+        let mut existing = resolved.as_path();
+        loop {
+            match fs::canonicalize(existing).await {
+                Ok(canonical) if canonical.starts_with(&root) => break,
+                Ok(_) => return Err(FsError::OutsideRoot(path.to_string())),
+                Err(_) => match existing.parent() {
+                    Some(parent) => existing = parent,
+                    None => break,
+                },
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    /// Get information about a single path
+    pub async fn stat(&self, path: &str) -> Result<FsEntry, FsError> {
+        let resolved = self.resolve(path).await?;
+        let metadata = symlink_metadata(&resolved, path).await?;
+        Ok(entry_from_metadata(path.to_string(), &metadata))
+    }
+
+    /// List a directory tree
+    ///
+    /// Entries are relative to the listed directory and every directory comes before its
+    /// contents, so the tree can be recreated in order.
+    pub async fn list(&self, path: &str) -> Result<Vec<FsEntry>, FsError> {
+        let resolved = self.resolve(path).await?;
+        let mut entries = Vec::new();
+        let mut pending = vec![PathBuf::new()];
+
+        while let Some(relative) = pending.pop() {
+            let mut read_dir = fs::read_dir(resolved.join(&relative)).await?;
+            let mut children = Vec::new();
+            while let Some(child) = read_dir.next_entry().await? {
+                children.push((relative.join(child.file_name()), child.metadata().await?));
+            }
+            children.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            // Visit subdirectories in order after the current directory's entries
+            for (child, metadata) in children.iter().rev() {
+                if metadata.is_dir() {
+                    pending.push(child.clone());
+                }
+            }
+
+            for (child, metadata) in children {
+                entries.push(entry_from_metadata(
+                    child.to_string_lossy().into_owned(),
+                    &metadata,
+                ));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Read a chunk of a file
+    ///
+    /// The content type is detected from the leading bytes of the file rather than the chunk, so
+    /// every chunk of a file reports the same type.
+    ///
+    /// ## Returns
+    ///
+    /// The bytes read, at most [`MAX_FS_CHUNK_SIZE`], whether the end of the file was reached and
+    /// the size, mode and content type of the file.
+    pub async fn read_chunk(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<FileChunk, FsError> {
+        let resolved = self.resolve(path).await?;
+        let metadata = symlink_metadata(&resolved, path).await?;
+        let size = metadata.len();
+        let length = length
+            .unwrap_or(MAX_FS_CHUNK_SIZE)
+            .min(MAX_FS_CHUNK_SIZE)
+            .min(size.saturating_sub(offset));
+
+        let mut file = fs::File::open(&resolved).await?;
+
+        let mut head = vec![0; size.min(CONTENT_SNIFF_LEN as u64) as usize];
+        file.read_exact(&mut head).await?;
+        let (content_type, is_binary) = detect_content_type(&head);
+
+        file.seek(SeekFrom::Start(offset)).await?;
+        let mut data = vec![0; length as usize];
+        file.read_exact(&mut data).await?;
+
+        Ok(FileChunk {
+            data,
+            eof: offset + length >= size,
+            size,
+            mode: metadata.permissions().mode() & 0o7777,
+            content_type,
+            is_binary,
+        })
+    }
+
+    /// Write a chunk of a file
+    ///
+    /// A chunk at offset zero creates or truncates the file. The mode, if given, is applied to
+    /// the file after the chunk is written.
+    pub async fn write_chunk(
+        &self,
+        path: &str,
+        offset: u64,
+        data: &[u8],
+        mode: Option<u32>,
+    ) -> Result<(), FsError> {
+        let resolved = self.resolve(path).await?;
+        if let Some(parent) = resolved.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // A symlink in the last component could point anywhere, even if it dangles, so it is
+        // never followed
+        let mut options = OpenOptions::new();
+        options
+            .write(true)
+            .create(true)
+            .custom_flags(libc::O_NOFOLLOW);
+        if offset == 0 {
+            options.truncate(true);
+        }
+
+        let mut file = match options.open(&resolved).await {
+            Ok(file) => file,
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                return Err(FsError::Symlink(path.to_string()));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.write_all(data).await?;
+        file.flush().await?;
+
+        if let Some(mode) = mode {
+            file.set_permissions(std::fs::Permissions::from_mode(mode))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Create a directory and any missing parents
+    pub async fn create_dir(&self, path: &str, mode: Option<u32>) -> Result<(), FsError> {
+        let resolved = self.resolve(path).await?;
+        fs::create_dir_all(&resolved).await?;
+
+        if let Some(mode) = mode {
+            fs::set_permissions(&resolved, std::fs::Permissions::from_mode(mode)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Make a temporary directory for a session
+    ///
+    /// The directory is made under [`TEMP_DIR`] with a name of the prefix followed by random
+    /// characters, readable only by its owner. It is removed with the session's other temporary
+    /// directories by [`cleanup_temp`](Self::cleanup_temp).
+    ///
+    /// ## Returns
+    ///
+    /// The path of the directory in the sandbox.
+    pub async fn make_temp_dir(&self, session: &str, prefix: &str) -> Result<String, FsError> {
+        if prefix.len() > MAX_TEMP_PREFIX_LEN
+            || prefix.starts_with('.')
+            || !prefix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            return Err(FsError::InvalidPrefix(prefix.to_string()));
+        }
+
+        let parent = self.resolve(TEMP_DIR).await?;
+        fs::create_dir_all(&parent).await?;
+
+        let mut attempts = 0;
+        let name = loop {
+            let suffix: String = rand::rng()
+                .sample_iter(&Alphanumeric)
+                .take(TEMP_SUFFIX_LEN)
+                .map(char::from)
+                .collect();
+            let name = format!("{}{}", prefix, suffix);
+
+            // Creating the directory itself rather than its parents fails instead of reusing a
+            // directory that already exists
+            match DirBuilder::new()
+                .mode(0o700)
+                .create(parent.join(&name))
+                .await
+            {
+                Ok(()) => break name,
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::AlreadyExists
+                        && attempts + 1 < MAX_TEMP_ATTEMPTS =>
+                {
+                    attempts += 1;
+                }
+                Err(e) => return Err(FsError::Io(e)),
+            }
+        };
+
+        let path = format!("{}/{}", TEMP_DIR, name);
+        self.temp_dirs
+            .lock()
+            .unwrap()
+            .entry(session.to_string())
+            .or_default()
+            .push(path.clone());
+
+        Ok(path)
+    }
+
+    /// Remove the temporary directories made for a session
+    ///
+    /// A directory that has since been moved outside of the root, or replaced with a symlink
+    /// leading there, is left alone.
+    ///
+    /// ## Returns
+    ///
+    /// The number of directories removed.
+    pub async fn cleanup_temp(&self, session: &str) -> usize {
+        let paths = self
+            .temp_dirs
+            .lock()
+            .unwrap()
+            .remove(session)
+            .unwrap_or_default();
+
+        let mut removed = 0;
+        for path in paths {
+            match self.remove_temp_dir(&path).await {
+                Ok(()) => removed += 1,
+                Err(FsError::NotFound(_)) => {}
+                Err(e) => tracing::warn!("failed to remove temporary directory {}: {}", path, e),
+            }
+        }
+
+        removed
+    }
+
+    /// Remove the temporary directories made for every session
+    pub async fn cleanup_all_temp(&self) -> usize {
+        let sessions: Vec<String> = self.temp_dirs.lock().unwrap().keys().cloned().collect();
+
+        let mut removed = 0;
+        for session in sessions {
+            removed += self.cleanup_temp(&session).await;
+        }
+
+        removed
+    }
+
+    /// Remove a single temporary directory and everything in it
+    async fn remove_temp_dir(&self, path: &str) -> Result<(), FsError> {
+        let resolved = self.resolve(path).await?;
+
+        // A symlink put in place of the directory is removed rather than followed
+        fs::remove_dir_all(&resolved).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                FsError::NotFound(path.to_string())
+            } else {
+                FsError::Io(e)
+            }
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Default for FileSystem {
+    fn default() -> Self {
+        Self::new(DEFAULT_FS_ROOT)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Get the metadata of a path without following a final symlink
+async fn symlink_metadata(resolved: &Path, path: &str) -> Result<std::fs::Metadata, FsError> {
+    fs::symlink_metadata(resolved).await.map_err(|e| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            FsError::NotFound(path.to_string())
+        } else {
+            FsError::Io(e)
+        }
+    })
+}
+
+/// Build an entry from file metadata
+fn entry_from_metadata(path: String, metadata: &std::fs::Metadata) -> FsEntry {
+    let kind = if metadata.is_symlink() {
+        EntryKind::Symlink
+    } else if metadata.is_dir() {
+        EntryKind::Directory
+    } else {
+        EntryKind::File
+    };
+
+    FsEntry {
+        path,
+        kind,
+        mode: metadata.permissions().mode() & 0o7777,
+        size: metadata.len(),
+    }
+}
+
+/// Detect the content type of a file from its leading bytes
+///
+/// Known file signatures are matched first. Anything else is text if it is valid UTF-8 without
+/// NUL bytes, allowing for a character cut off at the end of the inspected bytes.
+///
+/// ## Returns
+///
+/// The MIME type and whether the content is binary.
+fn detect_content_type(head: &[u8]) -> (String, bool) {
+    if head.is_empty() {
+        return (TEXT_CONTENT_TYPE.to_string(), false);
+    }
+
+    if let Some(kind) = infer::get(head) {
+        let is_binary = kind.matcher_type() != infer::MatcherType::Text;
+        return (kind.mime_type().to_string(), is_binary);
+    }
+
+    let is_text = !head.contains(&0)
+        && match std::str::from_utf8(head) {
+            Ok(_) => true,
+            Err(e) => e.error_len().is_none() && head.len() == CONTENT_SNIFF_LEN,
+        };
+
+    if is_text {
+        (TEXT_CONTENT_TYPE.to_string(), false)
+    } else {
+        (BINARY_CONTENT_TYPE.to_string(), true)
+    }
+}
@@ -10,6 +10,8 @@
 //!
 //! - `repl`: Provides multi-language REPL engines for interactive code execution
 //! - `command`: Handles sandboxed execution of system commands
+//! - `output`: Caps the output kept for a single evaluation or command
+//! - `pty`: Tracks interactive commands running in pseudo-terminals
 //! - `fs`: Manages secure file system operations
 //!
 //! # Architecture
@@ -60,7 +62,7 @@
 //!     let (exit_code, output) = cmd_handle.execute("ls", vec!["-la".to_string()], None).await?;
 //!
 //!     // Process the output
-//!     for line in output {
+//!     for line in output.lines {
 //!         println!("[{}] {}",
 //!                  if line.stream == microsandbox_portal::repl::Stream::Stdout { "stdout" } else { "stderr" },
 //!                  line.text);
@@ -76,4 +78,6 @@
 
 pub mod command;
 pub mod fs;
+pub mod output;
+pub mod pty;
 pub mod repl;
@@ -0,0 +1,294 @@
+//! Output limits for the microsandbox portal.
+//!
+//! REPL evaluations and commands collect their output in memory before it is returned, so a
+//! program printing without end could exhaust the portal's memory. This module caps the output
+//! kept for a single invocation across stdout and stderr:
+//! - Lines are kept in full until the cap is reached
+//! - The line that crosses the cap is cut short, so the kept output is an exact prefix
+//! - Anything after that is dropped, but still counted so callers know how much was produced
+//!
+//! Output is read with [`LineReader`], which never holds more than the cap of a single line in
+//! memory, so a program printing without a newline is bounded too.
+
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The most output bytes kept for a single evaluation or command by default
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 10 * 1024 * 1024;
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Output collected from a single evaluation or command
+#[derive(Debug, Clone)]
+pub struct Output<L> {
+    /// The lines that were kept, the last of which may have been cut short
+    pub lines: Vec<L>,
+
+    /// Whether output was dropped because it went over the cap
+    pub truncated: bool,
+
+    /// The number of output bytes produced, including any that were dropped
+    pub total_bytes: u64,
+}
+
+/// Tracks the output of a single invocation against a byte cap
+///
+/// Bytes are counted over the text of each line, without line terminators.
+#[derive(Debug, Clone)]
+pub struct OutputCap {
+    /// The most bytes to keep
+    max_bytes: usize,
+
+    /// The bytes kept so far
+    kept_bytes: usize,
+
+    /// The bytes produced so far
+    total_bytes: u64,
+}
+
+/// Reads lines of output while holding at most `max_line_bytes` bytes of a line in memory
+///
+/// A line longer than that is returned in pieces of `max_line_bytes` bytes, cut at character
+/// boundaries. Fed through an [`OutputCap`] of the same size, the pieces are kept and counted
+/// exactly as the whole line would have been.
+#[derive(Debug)]
+pub struct LineReader<R> {
+    /// The output being read
+    reader: R,
+
+    /// The most bytes of a line held at once
+    max_line_bytes: usize,
+
+    /// The part of the current line read so far
+    buf: Vec<u8>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl OutputCap {
+    /// Creates a cap that keeps at most `max_bytes` bytes of output
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            kept_bytes: 0,
+            total_bytes: 0,
+        }
+    }
+
+    /// Accounts for a line of output and returns the part of it that is kept
+    ///
+    /// The line is returned whole while it fits under the cap. The line crossing the cap is cut
+    /// short at the last character boundary that fits, and `None` is returned once nothing more
+    /// can be kept.
+    pub fn admit(&mut self, mut text: String) -> Option<String> {
+        self.total_bytes += text.len() as u64;
+
+        let remaining = self.max_bytes - self.kept_bytes;
+        if text.len() > remaining {
+            let mut end = remaining;
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            text.truncate(end);
+
+            // Nothing after the line crossing the cap is kept, even if it would fit
+            self.kept_bytes = self.max_bytes;
+            return (!text.is_empty()).then_some(text);
+        }
+
+        self.kept_bytes += text.len();
+        Some(text)
+    }
+
+    /// Returns whether any output has been dropped
+    pub fn truncated(&self) -> bool {
+        self.total_bytes > self.kept_bytes as u64
+    }
+
+    /// Returns the number of output bytes produced so far
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    /// Wraps the kept lines into the output of the invocation
+    pub fn finish<L>(&self, lines: Vec<L>) -> Output<L> {
+        Output {
+            lines,
+            truncated: self.truncated(),
+            total_bytes: self.total_bytes,
+        }
+    }
+}
+
+impl<R: AsyncBufRead + Unpin> LineReader<R> {
+    /// Creates a reader that holds at most `max_line_bytes` bytes of a line in memory
+    pub fn new(reader: R, max_line_bytes: usize) -> Self {
+        Self {
+            reader,
+            max_line_bytes: max_line_bytes.max(4),
+            buf: Vec::new(),
+        }
+    }
+
+    /// Reads the next line, or the next piece of a line longer than the limit
+    ///
+    /// Line terminators are stripped and invalid UTF-8 is replaced. Returns `None` at the end of
+    /// the output.
+    pub async fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+
+                return Ok(Some(self.take(self.buf.len())));
+            }
+
+            let newline = available.iter().position(|&b| b == b'\n');
+            let end = newline.unwrap_or(available.len());
+            let room = self.max_line_bytes - self.buf.len();
+
+            if end > room {
+                // Hold no more than the limit, and hand out what fits as a piece of the line
+                self.buf.extend_from_slice(&available[..room]);
+                self.reader.consume(room);
+                return Ok(Some(self.take_piece()));
+            }
+
+            self.buf.extend_from_slice(&available[..end]);
+            match newline {
+                Some(_) => {
+                    self.reader.consume(end + 1);
+                    if self.buf.last() == Some(&b'\r') {
+                        self.buf.pop();
+                    }
+
+                    return Ok(Some(self.take(self.buf.len())));
+                }
+                None => self.reader.consume(end),
+            }
+        }
+    }
+
+    /// Takes a full piece of a long line, leaving a character cut at its end for the next piece
+    fn take_piece(&mut self) -> String {
+        let end = match std::str::from_utf8(&self.buf) {
+            Err(e) if e.error_len().is_none() && e.valid_up_to() > 0 => e.valid_up_to(),
+            _ => self.buf.len(),
+        };
+
+        self.take(end)
+    }
+
+    /// Takes the first `end` bytes of the buffer as text
+    fn take(&mut self, end: usize) -> String {
+        let rest = self.buf.split_off(end);
+        let bytes = std::mem::replace(&mut self.buf, rest);
+        match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::BufReader;
+
+    use super::*;
+
+    fn collect(cap: &mut OutputCap, lines: &[&str]) -> Vec<String> {
+        lines
+            .iter()
+            .filter_map(|line| cap.admit(line.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_output_cap_keeps_output_below_cap() {
+        let mut cap = OutputCap::new(10);
+        let kept = collect(&mut cap, &["hello", "world"]);
+
+        assert_eq!(kept, vec!["hello", "world"]);
+        assert!(!cap.truncated());
+        assert_eq!(cap.total_bytes(), 10);
+    }
+
+    #[test]
+    fn test_output_cap_truncates_output_above_cap() {
+        let mut cap = OutputCap::new(8);
+        let kept = collect(&mut cap, &["hello", "world", "!", "more"]);
+
+        assert_eq!(kept, vec!["hello", "wor"]);
+        assert!(cap.truncated());
+        assert_eq!(cap.total_bytes(), 15);
+
+        let output = cap.finish(kept);
+        assert!(output.truncated);
+        assert_eq!(output.total_bytes, 15);
+    }
+
+    #[test]
+    fn test_output_cap_cuts_at_char_boundary() {
+        // "é" is two bytes, so the second one doesn't fit in the four bytes kept
+        let mut cap = OutputCap::new(4);
+        let kept = collect(&mut cap, &["aéé"]);
+
+        assert_eq!(kept, vec!["aé"]);
+        assert!(cap.truncated());
+        assert_eq!(cap.total_bytes(), 5);
+    }
+
+    async fn read_lines(output: &[u8], max_line_bytes: usize) -> Vec<String> {
+        // A small buffer makes lines span several reads
+        let mut reader = LineReader::new(BufReader::with_capacity(3, output), max_line_bytes);
+        let mut lines = Vec::new();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_line_reader_reads_lines() {
+        let lines = read_lines(b"hello\r\nworld\n\nlast", 16).await;
+
+        assert_eq!(lines, vec!["hello", "world", "", "last"]);
+    }
+
+    #[tokio::test]
+    async fn test_line_reader_splits_long_line_without_newline() {
+        let output = "a".repeat(25);
+        let lines = read_lines(output.as_bytes(), 10).await;
+
+        assert_eq!(lines, vec!["a".repeat(10), "a".repeat(10), "a".repeat(5)]);
+
+        // The pieces are capped and counted as the whole line would have been
+        let mut cap = OutputCap::new(10);
+        let kept: Vec<String> = lines.into_iter().filter_map(|l| cap.admit(l)).collect();
+        assert_eq!(kept, vec!["a".repeat(10)]);
+        assert!(cap.truncated());
+        assert_eq!(cap.total_bytes(), 25);
+    }
+
+    #[tokio::test]
+    async fn test_line_reader_splits_at_char_boundary() {
+        // "é" is two bytes, so a piece of five bytes would cut the third one in half
+        let lines = read_lines("ééééé\n".as_bytes(), 5).await;
+
+        assert_eq!(lines, vec!["éé", "éé", "é"]);
+    }
+}
@@ -0,0 +1,286 @@
+//! Interactive pseudo-terminal sessions for the microsandbox portal.
+//!
+//! This module keeps track of commands started with [`CommandHandle::execute_pty`] so that
+//! clients can drive them over JSON-RPC. It handles:
+//! - Pumping terminal output into a buffer that clients long-poll
+//! - Feeding client input to the terminal
+//! - Resizing the terminal and tracking the command's exit code
+//!
+//! Output is only reported as finished once it has all been read, so clients never miss the
+//! last bytes a command writes before exiting. Closing a session hangs up the command's process
+//! group, killing the command if it outlives the hangup, and every command is reaped once it exits.
+
+use std::{collections::HashMap, io, process::ExitStatus, sync::Arc, time::Duration};
+
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Child,
+    sync::{Mutex, mpsc, oneshot, watch},
+    time::timeout,
+};
+use uuid::Uuid;
+
+use crate::portal::command::{CommandError, CommandHandle, PtyResizer, PtySize};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// Size of the buffer terminal output is read into
+const READ_BUFFER_SIZE: usize = 4096;
+
+/// Number of output chunks buffered before the command is paused
+const OUTPUT_CHANNEL_CAPACITY: usize = 256;
+
+/// How long a command may keep running after its session is closed before it is killed
+const HANGUP_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Registry of the pseudo-terminal sessions running in the portal
+#[derive(Debug, Clone, Default)]
+pub struct PtySessions {
+    /// Sessions by ID
+    sessions: Arc<Mutex<HashMap<String, Arc<PtySessionEntry>>>>,
+}
+
+/// The result of reading from a session
+#[derive(Debug, Default)]
+pub struct PtyOutput {
+    /// Bytes written by the command since the last read
+    pub data: Vec<u8>,
+
+    /// The command's exit code, once it has exited and all output has been read
+    pub exit_code: Option<i32>,
+}
+
+/// A single running session
+#[derive(Debug)]
+struct PtySessionEntry {
+    /// Write half of the terminal
+    writer: Mutex<File>,
+
+    /// Output read from the terminal, closed once the terminal is
+    output: Mutex<mpsc::Receiver<Vec<u8>>>,
+
+    /// Resizes the terminal
+    resizer: PtyResizer,
+
+    /// The command's exit code, set once it exits
+    exit_code: watch::Receiver<Option<i32>>,
+
+    /// Tells the task waiting on the command to hang it up; dropping it does the same
+    hangup: Mutex<Option<oneshot::Sender<()>>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl PtySessions {
+    /// Starts a command in a new pseudo-terminal session and returns the session ID
+    pub async fn open(
+        &self,
+        handle: &CommandHandle,
+        command: String,
+        args: Vec<String>,
+        size: PtySize,
+    ) -> Result<String, CommandError> {
+        let session = handle.execute_pty(command, args, size)?;
+        let mut reader = session.reader;
+        let mut child = session.child;
+
+        // Pump output until the terminal is closed
+        let (output_tx, output_rx) = mpsc::channel(OUTPUT_CHANNEL_CAPACITY);
+        tokio::spawn(async move {
+            let mut buffer = vec![0; READ_BUFFER_SIZE];
+            while let Ok(read) = reader.read(&mut buffer).await {
+                if read == 0 || output_tx.send(buffer[..read].to_vec()).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Reap the command and record its exit code once it exits, or hang it up once the
+        // session is closed
+        let (exit_tx, exit_rx) = watch::channel(None);
+        let (hangup_tx, hangup_rx) = oneshot::channel();
+        tokio::spawn(async move {
+            let status = tokio::select! {
+                status = child.wait() => status,
+                _ = hangup_rx => hang_up(&mut child).await,
+            };
+            let code = match status {
+                Ok(status) => status.code().unwrap_or(1),
+                Err(_) => 1,
+            };
+            let _ = exit_tx.send(Some(code));
+        });
+
+        let id = Uuid::new_v4().to_string();
+        let entry = PtySessionEntry {
+            writer: Mutex::new(session.writer),
+            output: Mutex::new(output_rx),
+            resizer: session.resizer,
+            exit_code: exit_rx,
+            hangup: Mutex::new(Some(hangup_tx)),
+        };
+
+        self.sessions
+            .lock()
+            .await
+            .insert(id.clone(), Arc::new(entry));
+
+        Ok(id)
+    }
+
+    /// Writes input to a session's terminal
+    pub async fn write(&self, id: &str, data: &[u8]) -> Result<(), CommandError> {
+        let entry = self.get(id).await?;
+        let mut writer = entry.writer.lock().await;
+        writer
+            .write_all(data)
+            .await
+            .map_err(|e| CommandError::Pty(e.to_string()))?;
+        writer
+            .flush()
+            .await
+            .map_err(|e| CommandError::Pty(e.to_string()))
+    }
+
+    /// Reads the output a session produced since the last read
+    ///
+    /// Waits up to `wait` for output if none is buffered yet.
+    pub async fn read(&self, id: &str, wait: Duration) -> Result<PtyOutput, CommandError> {
+        let entry = self.get(id).await?;
+        let mut output = entry.output.lock().await;
+
+        let first = match timeout(wait, output.recv()).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => {
+                // All output has been read, so report the exit code once it is known
+                let mut exit_code = entry.exit_code.clone();
+                let code = match timeout(wait, exit_code.wait_for(Option::is_some)).await {
+                    Ok(Ok(code)) => *code,
+                    _ => None,
+                };
+                return Ok(PtyOutput {
+                    data: Vec::new(),
+                    exit_code: code,
+                });
+            }
+            Err(_) => return Ok(PtyOutput::default()),
+        };
+
+        // Drain whatever else is already buffered
+        let mut data = first;
+        while let Ok(chunk) = output.try_recv() {
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(PtyOutput {
+            data,
+            exit_code: None,
+        })
+    }
+
+    /// Resizes a session's terminal
+    pub async fn resize(&self, id: &str, size: PtySize) -> Result<(), CommandError> {
+        self.get(id).await?.resizer.resize(size)
+    }
+
+    /// Closes a session, hanging up the command if it is still running
+    pub async fn close(&self, id: &str) -> Result<(), CommandError> {
+        let entry = self
+            .sessions
+            .lock()
+            .await
+            .remove(id)
+            .ok_or_else(|| CommandError::Pty(format!("Session not found: {}", id)))?;
+
+        if let Some(hangup) = entry.hangup.lock().await.take() {
+            let _ = hangup.send(());
+        }
+
+        Ok(())
+    }
+
+    /// Gets a session by ID
+    async fn get(&self, id: &str) -> Result<Arc<PtySessionEntry>, CommandError> {
+        self.sessions
+            .lock()
+            .await
+            .get(id)
+            .cloned()
+            .ok_or_else(|| CommandError::Pty(format!("Session not found: {}", id)))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Hangs up a command's terminal and reaps it, killing it if it is still running after
+/// [`HANGUP_GRACE_PERIOD`]
+async fn hang_up(child: &mut Child) -> io::Result<ExitStatus> {
+    // The command leads its own session, so hang up its whole process group
+    if let Some(pid) = child.id() {
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGHUP) };
+    }
+
+    match timeout(HANGUP_GRACE_PERIOD, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            child.kill().await?;
+            child.wait().await
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portal::command::create_command_executor;
+
+    #[tokio::test]
+    async fn test_close_reaps_command() {
+        let handle = create_command_executor();
+        let sessions = PtySessions::default();
+        let script = "echo $$; exec sleep 30".to_string();
+        let id = sessions
+            .open(
+                &handle,
+                "sh".to_string(),
+                vec!["-c".to_string(), script],
+                PtySize { rows: 24, cols: 80 },
+            )
+            .await
+            .unwrap();
+
+        let output = sessions.read(&id, Duration::from_secs(5)).await.unwrap();
+        let pid: libc::pid_t = String::from_utf8_lossy(&output.data)
+            .trim()
+            .parse()
+            .unwrap();
+        sessions.close(&id).await.unwrap();
+
+        // Once reaped, not even a zombie is left for the process ID
+        for _ in 0..50 {
+            if unsafe { libc::kill(pid, 0) } < 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!(
+            "command {} was not reaped after its session was closed",
+            pid
+        );
+    }
+}
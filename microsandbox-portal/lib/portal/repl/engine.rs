@@ -48,17 +48,39 @@
 //! }
 //! ```
 
-use tokio::sync::mpsc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{mpsc, oneshot};
+#[cfg(any(feature = "python", feature = "nodejs"))]
+use tokio::time::{Duration, timeout};
 
 #[cfg(feature = "nodejs")]
 use super::nodejs;
 #[cfg(feature = "python")]
 use super::python;
 
-use super::types::{Cmd, EngineError, EngineHandle, Language, Line, Resp, Stream};
+use super::types::{
+    Cmd, EngineError, EngineHandle, InterruptOutcome, Language, Line, PackageInstall, Resp, Stream,
+};
+use crate::portal::output::{DEFAULT_MAX_OUTPUT_BYTES, Output, OutputCap};
+
+#[cfg(any(feature = "python", feature = "nodejs"))]
+use super::types::{Engine, InterruptReceiver};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How long an interrupted evaluation is given to stop before its interpreter is restarted
+#[cfg(any(feature = "python", feature = "nodejs"))]
+const INTERRUPT_GRACE_PERIOD: Duration = Duration::from_secs(2);
 
+/// How long installing a single package may take before the package manager is killed
 #[cfg(any(feature = "python", feature = "nodejs"))]
-use super::types::Engine;
+const PACKAGE_INSTALL_TIMEOUT: Duration = Duration::from_secs(300);
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -81,6 +103,14 @@ struct Engines {
 //--------------------------------------------------------------------------------------------------
 
 impl EngineHandle {
+    /// Sets the most output bytes kept for a single evaluation
+    ///
+    /// Output beyond this is dropped, and the evaluation's output is marked as truncated.
+    pub fn with_max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
     /// Evaluates code in the specified language
     ///
     /// This method sends a command to the reactor thread to evaluate the
@@ -96,7 +126,8 @@ impl EngineHandle {
     ///
     /// # Returns
     ///
-    /// A vector of output lines from the evaluation.
+    /// The output lines from the evaluation, capped at the handle's output limit
+    /// across stdout and stderr.
     ///
     /// # Errors
     ///
@@ -108,7 +139,7 @@ impl EngineHandle {
         language: Language,
         execution_id: S,
         timeout: Option<u64>,
-    ) -> Result<Vec<Line>, EngineError> {
+    ) -> Result<Output<Line>, EngineError> {
         let code = code.into();
         let execution_id = execution_id.into();
         // Create channel for receiving results
@@ -154,16 +185,131 @@ impl EngineHandle {
             }
         });
 
-        // Collect all lines
+        // Collect lines up to the output limit, draining and counting the rest
+        let mut cap = OutputCap::new(self.max_output_bytes);
         let mut lines = Vec::new();
         while let Some(line) = line_rx.recv().await {
-            lines.push(line);
+            if let Some(text) = cap.admit(line.text) {
+                lines.push(Line {
+                    stream: line.stream,
+                    text,
+                });
+            }
         }
 
         // Wait for processing to complete
         let _ = process_handle.await;
 
-        Ok(lines)
+        Ok(cap.finish(lines))
+    }
+
+    /// Resets the session of the engine for the specified language
+    ///
+    /// Evaluations in a language share a session, so variables, the working
+    /// directory and environment changes persist from one evaluation to the
+    /// next. Resetting clears them, and later evaluations start from a fresh
+    /// interpreter.
+    ///
+    /// # Parameters
+    ///
+    /// * `language` - The language whose session is reset
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EngineError` if the engine fails to restart or if the
+    /// reactor thread is not available.
+    pub async fn reset_session(&self, language: Language) -> Result<(), EngineError> {
+        let (done_tx, done_rx) = oneshot::channel();
+
+        self.cmd_sender
+            .send(Cmd::Reset {
+                _language: language,
+                _done_tx: done_tx,
+            })
+            .await
+            .map_err(|_| EngineError::Unavailable("Reactor thread not available".to_string()))?;
+
+        done_rx
+            .await
+            .map_err(|_| EngineError::Unavailable("Reactor thread not available".to_string()))?
+    }
+
+    /// Installs packages for the engine of the specified language
+    ///
+    /// The language's package manager installs each package in turn, and the
+    /// packages that are installed can be imported by later evaluations in the
+    /// language's session.
+    ///
+    /// # Parameters
+    ///
+    /// * `language` - The language whose package manager is used
+    /// * `packages` - The packages to install
+    ///
+    /// # Returns
+    ///
+    /// How installing each package went, in the order given.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EngineError` if the engine fails to pick up the installed
+    /// packages or if the reactor thread is not available.
+    pub async fn install_packages(
+        &self,
+        language: Language,
+        packages: &[String],
+    ) -> Result<Vec<PackageInstall>, EngineError> {
+        let (done_tx, done_rx) = oneshot::channel();
+
+        self.cmd_sender
+            .send(Cmd::Install {
+                _language: language,
+                _packages: packages.to_vec(),
+                _done_tx: done_tx,
+            })
+            .await
+            .map_err(|_| EngineError::Unavailable("Reactor thread not available".to_string()))?;
+
+        done_rx
+            .await
+            .map_err(|_| EngineError::Unavailable("Reactor thread not available".to_string()))?
+    }
+
+    /// Interrupts the running evaluation of the engine for the specified language
+    ///
+    /// The interpreter is sent SIGINT, like pressing Ctrl+C, which stops the
+    /// evaluation and keeps the session. An evaluation that doesn't stop within
+    /// a grace period has its interpreter restarted instead, which loses the
+    /// session. Either way, later evaluations in the language keep working.
+    ///
+    /// # Parameters
+    ///
+    /// * `language` - The language whose evaluation is interrupted
+    ///
+    /// # Returns
+    ///
+    /// What interrupting did, which is `InterruptOutcome::Idle` when nothing
+    /// was being evaluated.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `EngineError` if the engine is not available.
+    pub async fn interrupt(&self, language: Language) -> Result<InterruptOutcome, EngineError> {
+        let interrupter = self.interrupters.lock().unwrap().get(&language).cloned();
+
+        // An engine that is still starting has nothing running to interrupt
+        let Some(interrupter) = interrupter else {
+            return Ok(InterruptOutcome::Idle);
+        };
+
+        let (outcome_tx, outcome_rx) = oneshot::channel();
+        interrupter
+            .send(outcome_tx)
+            .await
+            .map_err(|_| EngineError::Unavailable("Engine not available".to_string()))?;
+
+        outcome_rx
+            .await
+            .map_err(|_| EngineError::Unavailable("Engine not available".to_string()))
     }
 
     /// Shuts down all engines and the reactor
@@ -201,16 +347,44 @@ impl EngineHandle {
 ///
 /// Returns an `EngineError` if any of the engines fail to initialize.
 pub async fn start_engines() -> Result<EngineHandle, EngineError> {
+    start_engines_with(DEFAULT_MAX_OUTPUT_BYTES).await
+}
+
+/// Start all supported REPL engines with the given output limit and return a handle
+///
+/// Like [`start_engines`], but keeps at most `max_output_bytes` bytes of output for a single
+/// evaluation. The engines hold no more than that of an output line in memory while reading it.
+///
+/// # Returns
+///
+/// An `EngineHandle` that can be used to evaluate code and shut down the engines.
+///
+/// # Errors
+///
+/// Returns an `EngineError` if any of the engines fail to initialize.
+pub async fn start_engines_with(max_output_bytes: usize) -> Result<EngineHandle, EngineError> {
     let (cmd_tx, mut _cmd_rx) = mpsc::channel::<Cmd>(100);
+    let interrupters = Arc::new(Mutex::new(HashMap::new()));
 
     // Spawn reactor task
     #[cfg(any(feature = "python", feature = "nodejs"))]
+    let reactor_interrupters = Arc::clone(&interrupters);
+    #[cfg(any(feature = "python", feature = "nodejs"))]
     tokio::spawn(async move {
         // Initialize engines asynchronously
-        let mut engines = initialize_engines()
+        let mut engines = initialize_engines(max_output_bytes)
             .await
             .expect("Failed to initialize engines");
 
+        // Interrupts go straight to the engines, as the reactor waits out each evaluation
+        {
+            let mut interrupters = reactor_interrupters.lock().unwrap();
+            #[cfg(feature = "python")]
+            interrupters.insert(Language::Python, engines.python.interrupter());
+            #[cfg(feature = "nodejs")]
+            interrupters.insert(Language::Node, engines.nodejs.interrupter());
+        }
+
         // Process commands until shutdown
         while let Some(cmd) = _cmd_rx.recv().await {
             match cmd {
@@ -252,6 +426,31 @@ pub async fn start_engines() -> Result<EngineHandle, EngineError> {
                         }
                     }
                 },
+                Cmd::Reset {
+                    _language,
+                    _done_tx,
+                } => {
+                    let result = match _language {
+                        #[cfg(feature = "python")]
+                        Language::Python => engines.python.reset().await,
+                        #[cfg(feature = "nodejs")]
+                        Language::Node => engines.nodejs.reset().await,
+                    };
+                    let _ = _done_tx.send(result);
+                }
+                Cmd::Install {
+                    _language,
+                    _packages,
+                    _done_tx,
+                } => {
+                    let result = match _language {
+                        #[cfg(feature = "python")]
+                        Language::Python => engines.python.install_packages(&_packages).await,
+                        #[cfg(feature = "nodejs")]
+                        Language::Node => engines.nodejs.install_packages(&_packages).await,
+                    };
+                    let _ = _done_tx.send(result);
+                }
                 Cmd::Shutdown => {
                     // Shutdown all engines
                     #[cfg(feature = "python")]
@@ -264,7 +463,55 @@ pub async fn start_engines() -> Result<EngineHandle, EngineError> {
         }
     });
 
-    Ok(EngineHandle { cmd_sender: cmd_tx })
+    Ok(EngineHandle {
+        cmd_sender: cmd_tx,
+        max_output_bytes,
+        interrupters,
+    })
+}
+
+/// Waits for an evaluation to complete, interrupting it if asked to while waiting
+///
+/// An interrupt sends SIGINT to the interpreter and waits up to `INTERRUPT_GRACE_PERIOD` for the
+/// evaluation to complete. The requester is told whether it did, or whether the interpreter has
+/// to be restarted, which is left to the engine.
+///
+/// # Parameters
+///
+/// * `completion` - Completes when the evaluation does
+/// * `interrupt_rx` - Where the engine takes interrupt requests
+/// * `pid` - The process ID of the interpreter
+///
+/// # Errors
+///
+/// Returns `EngineError::Interrupted` if the evaluation was interrupted.
+#[cfg(any(feature = "python", feature = "nodejs"))]
+pub(crate) async fn wait_or_interrupt(
+    completion: impl std::future::Future<Output = ()>,
+    interrupt_rx: &mut InterruptReceiver,
+    pid: Option<u32>,
+) -> Result<(), EngineError> {
+    tokio::pin!(completion);
+
+    let outcome_tx = tokio::select! {
+        _ = &mut completion => return Ok(()),
+        Some(outcome_tx) = interrupt_rx.recv() => outcome_tx,
+    };
+
+    if let Some(pid) = pid {
+        unsafe { libc::kill(pid as libc::pid_t, libc::SIGINT) };
+    }
+
+    let stopped = timeout(INTERRUPT_GRACE_PERIOD, &mut completion)
+        .await
+        .is_ok();
+    let _ = outcome_tx.send(if stopped {
+        InterruptOutcome::Interrupted
+    } else {
+        InterruptOutcome::Restarted
+    });
+
+    Err(EngineError::Interrupted { restart: !stopped })
 }
 
 /// Initialize all engines
@@ -272,6 +519,10 @@ pub async fn start_engines() -> Result<EngineHandle, EngineError> {
 /// This function creates and initializes instances of each language engine
 /// that has been enabled through feature flags.
 ///
+/// # Parameters
+///
+/// * `max_output_bytes` - The most bytes of an output line each engine holds in memory
+///
 /// # Returns
 ///
 /// An `Engines` struct containing the initialized engines.
@@ -280,11 +531,11 @@ pub async fn start_engines() -> Result<EngineHandle, EngineError> {
 ///
 /// Returns an `EngineError` if any of the engines fail to initialize.
 #[cfg(any(feature = "python", feature = "nodejs"))]
-async fn initialize_engines() -> Result<Engines, EngineError> {
+async fn initialize_engines(max_output_bytes: usize) -> Result<Engines, EngineError> {
     #[cfg(feature = "python")]
-    let mut python_engine = python::create_engine()?;
+    let mut python_engine = python::create_engine(max_output_bytes)?;
     #[cfg(feature = "nodejs")]
-    let mut nodejs_engine = nodejs::create_engine()?;
+    let mut nodejs_engine = nodejs::create_engine(max_output_bytes)?;
 
     // Initialize each engine asynchronously
     #[cfg(feature = "python")]
@@ -299,3 +550,74 @@ async fn initialize_engines() -> Result<Engines, EngineError> {
         nodejs: nodejs_engine,
     })
 }
+
+/// Installs packages one at a time with a package manager, reporting how each went
+///
+/// # Parameters
+///
+/// * `command` - The package manager, with the arguments that install the package given after them
+/// * `envs` - Environment variables the package manager is run with
+/// * `packages` - The packages to install
+#[cfg(any(feature = "python", feature = "nodejs"))]
+pub(crate) async fn install_each(
+    command: &[&str],
+    envs: &[(&str, &str)],
+    packages: &[String],
+) -> Vec<PackageInstall> {
+    let mut installs = Vec::with_capacity(packages.len());
+    for package in packages {
+        let error = install_package(command, envs, package).await.err();
+        installs.push(PackageInstall {
+            package: package.clone(),
+            success: error.is_none(),
+            error,
+        });
+    }
+
+    installs
+}
+
+/// Installs a single package, returning why it failed if it did
+#[cfg(any(feature = "python", feature = "nodejs"))]
+async fn install_package(
+    command: &[&str],
+    envs: &[(&str, &str)],
+    package: &str,
+) -> Result<(), String> {
+    // The package manager would take anything looking like an option as one
+    if package.trim().is_empty() || package.starts_with('-') {
+        return Err(format!("Invalid package: {:?}", package));
+    }
+
+    let (program, args) = command
+        .split_first()
+        .expect("package manager command is empty");
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .arg(package)
+        .envs(envs.iter().copied())
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output();
+
+    let output = timeout(PACKAGE_INSTALL_TIMEOUT, output)
+        .await
+        .map_err(|_| {
+            format!(
+                "Installation timed out after {} seconds",
+                PACKAGE_INSTALL_TIMEOUT.as_secs()
+            )
+        })?
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+
+    if output.status.success() {
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    Err(if stderr.is_empty() {
+        format!("{} exited with {}", program, output.status)
+    } else {
+        stderr
+    })
+}
@@ -45,8 +45,8 @@
 //! }
 //! ```
 //!
-//! Each evaluation returns a vector of output lines, with information about whether
-//! they were sent to stdout or stderr.
+//! Each evaluation returns its output lines, with information about whether they were
+//! sent to stdout or stderr, capped at the handle's output limit.
 
 //--------------------------------------------------------------------------------------------------
 // Exports
@@ -13,7 +13,7 @@ use async_trait::async_trait;
 use rand::{Rng, distr::Alphanumeric};
 use std::sync::{Arc, Mutex};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncWriteExt, BufReader},
     process::Command,
     sync::{
         mpsc::{self, Sender},
@@ -22,7 +22,25 @@ use tokio::{
     time::{Duration, sleep, timeout as tokio_timeout},
 };
 
-use super::types::{Engine, EngineError, Resp, Stream};
+use crate::portal::output::LineReader;
+
+use super::{
+    engine::{install_each, wait_or_interrupt},
+    types::{
+        Engine, EngineError, InterruptOutcome, InterruptReceiver, InterruptSender, PackageInstall,
+        Resp, Stream,
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The command npm installs a package with
+///
+/// It is run in the directory the REPL is started in, whose `node_modules` the REPL resolves
+/// `require` calls from.
+const NPM_INSTALL_COMMAND: &[&str] = &["npm", "install", "--no-audit", "--no-fund"];
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -32,6 +50,11 @@ use super::types::{Engine, EngineError, Resp, Stream};
 pub struct NodeEngine {
     process_control_tx: Option<Sender<ProcessControl>>,
     eval_tx: Option<Sender<EvalRequest>>,
+    interrupt_tx: InterruptSender,
+    interrupt_rx: Arc<tokio::sync::Mutex<InterruptReceiver>>,
+
+    /// The most bytes of an output line held in memory at once
+    max_line_bytes: usize,
 }
 
 /// Commands for controlling the Node.js process
@@ -61,10 +84,14 @@ struct ExecutionStatus {
 //--------------------------------------------------------------------------------------------------
 
 impl NodeEngine {
-    fn new() -> Self {
+    fn new(max_line_bytes: usize) -> Self {
+        let (interrupt_tx, interrupt_rx) = mpsc::channel(10);
         NodeEngine {
             process_control_tx: None,
             eval_tx: None,
+            interrupt_tx,
+            interrupt_rx: Arc::new(tokio::sync::Mutex::new(interrupt_rx)),
+            max_line_bytes,
         }
     }
 }
@@ -84,13 +111,18 @@ impl Engine for NodeEngine {
         self.eval_tx = Some(eval_tx);
 
         // Start the Node.js process manager in a separate task
+        let interrupt_rx = Arc::clone(&self.interrupt_rx);
+        let max_line_bytes = self.max_line_bytes;
         tokio::spawn(async move {
+            // The interpreter replacing this one after a reset waits for this one to be gone
+            let mut interrupt_rx = interrupt_rx.lock_owned().await;
+
             // Start Node.js process with custom REPL
             // Custom REPL starts with no prompt, no terminal features, and ignores undefined
             let mut process = match Command::new("node")
                 .args([
                     "-e",
-                    "const r=require('repl').start({prompt:'',terminal:false,ignoreUndefined:true,useGlobal:true});r._prompt='';r.displayPrompt=()=>{}",
+                    "const r=require('repl').start({prompt:'',terminal:false,ignoreUndefined:true,useGlobal:true,breakEvalOnSigint:true});r._prompt='';r.displayPrompt=()=>{}",
                 ])
                 .stdin(std::process::Stdio::piped())
                 .stdout(std::process::Stdio::piped())
@@ -104,6 +136,8 @@ impl Engine for NodeEngine {
                 }
             };
 
+            let pid = process.id();
+
             // Get stdin handle
             let mut stdin = match process.stdin.take() {
                 Some(s) => s,
@@ -139,7 +173,7 @@ impl Engine for NodeEngine {
             let stdout_exec_status = Arc::clone(&execution_status);
 
             tokio::task::spawn_blocking(move || {
-                let mut lines_future = stdout_reader.lines();
+                let mut lines_future = LineReader::new(stdout_reader, max_line_bytes);
                 let runtime = tokio::runtime::Handle::current();
 
                 loop {
@@ -203,7 +237,7 @@ impl Engine for NodeEngine {
             let stderr_exec_status = Arc::clone(&execution_status);
 
             tokio::task::spawn_blocking(move || {
-                let mut lines_future = stderr_reader.lines();
+                let mut lines_future = LineReader::new(stderr_reader, max_line_bytes);
                 let runtime = tokio::runtime::Handle::current();
 
                 loop {
@@ -240,6 +274,10 @@ impl Engine for NodeEngine {
                             }
                         }
                     }
+                    Some(outcome_tx) = interrupt_rx.recv() => {
+                        // Nothing is being evaluated
+                        let _ = outcome_tx.send(InterruptOutcome::Idle);
+                    }
                     Some(eval_req) = eval_rx.recv() => {
                         let EvalRequest { id, code, resp_tx, done_tx, timeout } = eval_req;
 
@@ -302,22 +340,29 @@ impl Engine for NodeEngine {
                                 }
                             };
 
+                            // Stop waiting early if the evaluation is interrupted
+                            let wait_future =
+                                wait_or_interrupt(wait_future, &mut interrupt_rx, pid);
+
                             // Apply timeout only if specified
                             match timeout {
                                 Some(timeout_secs) => {
                                     let timeout_duration = Duration::from_secs(timeout_secs);
-                                    if tokio_timeout(timeout_duration, wait_future).await.is_err() {
+                                    let Ok(result) =
+                                        tokio_timeout(timeout_duration, wait_future).await
+                                    else {
                                         // Timeout occurred
                                         let _ = resp_tx.send(Resp::Error {
                                             id: id.clone(),
                                             message: format!("Execution timed out after {} seconds", timeout_secs),
                                         }).await;
                                         return Err(EngineError::Timeout(timeout_secs));
-                                    }
+                                    };
+                                    result?;
                                 },
                                 None => {
                                     // No timeout, just wait for completion
-                                    wait_future.await;
+                                    wait_future.await?;
                                 }
                             }
 
@@ -330,8 +375,17 @@ impl Engine for NodeEngine {
                             *status_guard = None;
                         }
 
+                        // An evaluation that didn't stop when interrupted is stopped with the
+                        // interpreter, which the engine then replaces
+                        let restart =
+                            matches!(result, Err(EngineError::Interrupted { restart: true }));
+
                         // Signal completion to caller
                         let _ = done_tx.send(result);
+
+                        if restart {
+                            break;
+                        }
                     }
                     _ = stdout_done_rx.recv() => {
                         eprintln!("Node.js stdout handler exited");
@@ -382,9 +436,27 @@ impl Engine for NodeEngine {
             .map_err(|_| EngineError::Unavailable("Node.js process channel closed".to_string()))?;
 
         // Wait for completion
-        done_rx
+        let result = done_rx
             .await
-            .map_err(|_| EngineError::Unavailable("Node.js evaluation cancelled".to_string()))?
+            .map_err(|_| EngineError::Unavailable("Node.js evaluation cancelled".to_string()))?;
+
+        // The interpreter was stopped along with an evaluation that didn't stop when interrupted
+        if matches!(result, Err(EngineError::Interrupted { restart: true })) {
+            self.reset().await?;
+        }
+
+        result
+    }
+
+    fn interrupter(&self) -> InterruptSender {
+        self.interrupt_tx.clone()
+    }
+
+    async fn install_packages(
+        &mut self,
+        packages: &[String],
+    ) -> Result<Vec<PackageInstall>, EngineError> {
+        Ok(install_each(NPM_INSTALL_COMMAND, &[], packages).await)
     }
 
     async fn shutdown(&mut self) {
@@ -406,6 +478,8 @@ impl Engine for NodeEngine {
 //--------------------------------------------------------------------------------------------------
 
 /// Create a new Node.js engine instance
-pub fn create_engine() -> Result<Box<dyn Engine>, EngineError> {
-    Ok(Box::new(NodeEngine::new()))
+///
+/// Output lines are read holding at most `max_output_bytes` bytes of each in memory.
+pub fn create_engine(max_output_bytes: usize) -> Result<Box<dyn Engine>, EngineError> {
+    Ok(Box::new(NodeEngine::new(max_output_bytes)))
 }
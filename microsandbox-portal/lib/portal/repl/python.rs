@@ -13,7 +13,7 @@ use async_trait::async_trait;
 use rand::{Rng, distr::Alphanumeric};
 use std::sync::{Arc, Mutex};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncWriteExt, BufReader},
     process::Command,
     sync::{
         mpsc::{self, Sender},
@@ -22,7 +22,40 @@ use tokio::{
     time::{Duration, sleep, timeout as tokio_timeout},
 };
 
-use super::types::{Engine, EngineError, Resp, Stream};
+use crate::portal::output::LineReader;
+
+use super::{
+    engine::{install_each, wait_or_interrupt},
+    types::{
+        Engine, EngineError, InterruptOutcome, InterruptReceiver, InterruptSender, PackageInstall,
+        Resp, Stream,
+    },
+};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The interpreter the engine runs
+const PYTHON: &str = "python3";
+
+/// The arguments the interpreter runs pip to install a package with
+const PIP_INSTALL_ARGS: &[&str] = &["-m", "pip", "install", "--no-input"];
+
+/// The environment pip is run with, which lets it install into a distribution's Python
+const PIP_INSTALL_ENV: &[(&str, &str)] = &[
+    ("PIP_BREAK_SYSTEM_PACKAGES", "1"),
+    ("PIP_DISABLE_PIP_VERSION_CHECK", "1"),
+];
+
+/// Has the interpreter pick up newly installed packages
+///
+/// pip falls back to the user site directory, which is only on the path if it existed when the
+/// interpreter started, and the interpreter caches the directories it imports from. Both lines
+/// evaluate to `None`, so nothing is echoed and no names are left in the session.
+const REFRESH_IMPORTS_CODE: &str = r#"(__import__('site').ENABLE_USER_SITE
+    and __import__('site').addsitedir(__import__('site').getusersitepackages())) or None
+__import__('importlib').invalidate_caches()"#;
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -32,6 +65,14 @@ use super::types::{Engine, EngineError, Resp, Stream};
 pub struct PythonEngine {
     process_control_tx: Option<Sender<ProcessControl>>,
     eval_tx: Option<Sender<EvalRequest>>,
+    interrupt_tx: InterruptSender,
+    interrupt_rx: Arc<tokio::sync::Mutex<InterruptReceiver>>,
+
+    /// The most bytes of an output line held in memory at once
+    max_line_bytes: usize,
+
+    /// The interpreter that runs the code and installs packages
+    python: String,
 }
 
 /// Commands for controlling the Python process
@@ -61,10 +102,20 @@ struct ExecutionStatus {
 //--------------------------------------------------------------------------------------------------
 
 impl PythonEngine {
-    fn new() -> Self {
+    fn new(max_line_bytes: usize) -> Self {
+        Self::with_python(max_line_bytes, PYTHON)
+    }
+
+    /// Creates an engine that runs the given interpreter instead of the one on the path
+    fn with_python(max_line_bytes: usize, python: impl Into<String>) -> Self {
+        let (interrupt_tx, interrupt_rx) = mpsc::channel(10);
         PythonEngine {
             process_control_tx: None,
             eval_tx: None,
+            interrupt_tx,
+            interrupt_rx: Arc::new(tokio::sync::Mutex::new(interrupt_rx)),
+            max_line_bytes,
+            python: python.into(),
         }
     }
 }
@@ -84,10 +135,16 @@ impl Engine for PythonEngine {
         self.eval_tx = Some(eval_tx);
 
         // Start the Python process manager in a separate task
+        let interrupt_rx = Arc::clone(&self.interrupt_rx);
+        let max_line_bytes = self.max_line_bytes;
+        let python = self.python.clone();
         tokio::spawn(async move {
+            // The interpreter replacing this one after a reset waits for this one to be gone
+            let mut interrupt_rx = interrupt_rx.lock_owned().await;
+
             // Start Python process with interactive mode
             // -q: hide banner, -u: unbuffered, -i: interactive, clear prompts
-            let mut process = match Command::new("python3")
+            let mut process = match Command::new(&python)
                 .args(["-q", "-u", "-i", "-c", "import sys; sys.ps1=sys.ps2=''"])
                 .stdin(std::process::Stdio::piped())
                 .stdout(std::process::Stdio::piped())
@@ -101,6 +158,8 @@ impl Engine for PythonEngine {
                 }
             };
 
+            let pid = process.id();
+
             // Get stdin handle
             let mut stdin = match process.stdin.take() {
                 Some(s) => s,
@@ -136,7 +195,7 @@ impl Engine for PythonEngine {
             let stdout_exec_status = Arc::clone(&execution_status);
 
             tokio::task::spawn_blocking(move || {
-                let mut lines_future = stdout_reader.lines();
+                let mut lines_future = LineReader::new(stdout_reader, max_line_bytes);
                 let runtime = tokio::runtime::Handle::current();
 
                 loop {
@@ -193,7 +252,7 @@ impl Engine for PythonEngine {
             let stderr_exec_status = Arc::clone(&execution_status);
 
             tokio::task::spawn_blocking(move || {
-                let mut lines_future = stderr_reader.lines();
+                let mut lines_future = LineReader::new(stderr_reader, max_line_bytes);
                 let runtime = tokio::runtime::Handle::current();
 
                 loop {
@@ -230,6 +289,10 @@ impl Engine for PythonEngine {
                             }
                         }
                     }
+                    Some(outcome_tx) = interrupt_rx.recv() => {
+                        // Nothing is being evaluated
+                        let _ = outcome_tx.send(InterruptOutcome::Idle);
+                    }
                     Some(eval_req) = eval_rx.recv() => {
                         let EvalRequest { id, code, resp_tx, done_tx, timeout } = eval_req;
 
@@ -287,22 +350,29 @@ impl Engine for PythonEngine {
                                 }
                             };
 
+                            // Stop waiting early if the evaluation is interrupted
+                            let wait_future =
+                                wait_or_interrupt(wait_future, &mut interrupt_rx, pid);
+
                             // Apply timeout only if specified
                             match timeout {
                                 Some(timeout_secs) => {
                                     let timeout_duration = Duration::from_secs(timeout_secs);
-                                    if tokio_timeout(timeout_duration, wait_future).await.is_err() {
+                                    let Ok(result) =
+                                        tokio_timeout(timeout_duration, wait_future).await
+                                    else {
                                         // Timeout occurred
                                         let _ = resp_tx.send(Resp::Error {
                                             id: id.clone(),
                                             message: format!("Execution timed out after {} seconds", timeout_secs),
                                         }).await;
                                         return Err(EngineError::Timeout(timeout_secs));
-                                    }
+                                    };
+                                    result?;
                                 },
                                 None => {
                                     // No timeout, just wait for completion
-                                    wait_future.await;
+                                    wait_future.await?;
                                 }
                             }
 
@@ -315,8 +385,17 @@ impl Engine for PythonEngine {
                             *status_guard = None;
                         }
 
+                        // An evaluation that didn't stop when interrupted is stopped with the
+                        // interpreter, which the engine then replaces
+                        let restart =
+                            matches!(result, Err(EngineError::Interrupted { restart: true }));
+
                         // Signal completion to caller
                         let _ = done_tx.send(result);
+
+                        if restart {
+                            break;
+                        }
                     }
                     _ = stdout_done_rx.recv() => {
                         eprintln!("Python stdout handler exited");
@@ -368,9 +447,43 @@ impl Engine for PythonEngine {
             .map_err(|_| EngineError::Unavailable("Python process channel closed".to_string()))?;
 
         // Wait for completion
-        done_rx
+        let result = done_rx
             .await
-            .map_err(|_| EngineError::Unavailable("Python evaluation cancelled".to_string()))?
+            .map_err(|_| EngineError::Unavailable("Python evaluation cancelled".to_string()))?;
+
+        // The interpreter was stopped along with an evaluation that didn't stop when interrupted
+        if matches!(result, Err(EngineError::Interrupted { restart: true })) {
+            self.reset().await?;
+        }
+
+        result
+    }
+
+    fn interrupter(&self) -> InterruptSender {
+        self.interrupt_tx.clone()
+    }
+
+    async fn install_packages(
+        &mut self,
+        packages: &[String],
+    ) -> Result<Vec<PackageInstall>, EngineError> {
+        let command: Vec<&str> = std::iter::once(self.python.as_str())
+            .chain(PIP_INSTALL_ARGS.iter().copied())
+            .collect();
+        let installs = install_each(&command, PIP_INSTALL_ENV, packages).await;
+
+        if installs.iter().any(|install| install.success) {
+            let (resp_tx, _resp_rx) = mpsc::channel(100);
+            self.eval(
+                "install".to_string(),
+                REFRESH_IMPORTS_CODE.to_string(),
+                &resp_tx,
+                Some(10),
+            )
+            .await?;
+        }
+
+        Ok(installs)
     }
 
     async fn shutdown(&mut self) {
@@ -392,6 +505,216 @@ impl Engine for PythonEngine {
 //--------------------------------------------------------------------------------------------------
 
 /// Create a new Python engine instance
-pub fn create_engine() -> Result<Box<dyn Engine>, EngineError> {
-    Ok(Box::new(PythonEngine::new()))
+///
+/// Output lines are read holding at most `max_output_bytes` bytes of each in memory.
+pub fn create_engine(max_output_bytes: usize) -> Result<Box<dyn Engine>, EngineError> {
+    Ok(Box::new(PythonEngine::new(max_output_bytes)))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::portal::output::DEFAULT_MAX_OUTPUT_BYTES;
+
+    use super::*;
+
+    /// Evaluates code and returns what it printed to stdout
+    async fn eval_stdout(engine: &mut dyn Engine, code: &str) -> Vec<String> {
+        let (resp_tx, mut resp_rx) = mpsc::channel(100);
+        engine
+            .eval("test".to_string(), code.to_string(), &resp_tx, Some(10))
+            .await
+            .unwrap();
+
+        let mut lines = Vec::new();
+        while let Ok(resp) = resp_rx.try_recv() {
+            if let Resp::Line {
+                stream: Stream::Stdout,
+                text,
+                ..
+            } = resp
+            {
+                lines.push(text);
+            }
+        }
+
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_session_persists_until_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir_path = dir.path().canonicalize().unwrap();
+
+        let mut engine = PythonEngine::new(DEFAULT_MAX_OUTPUT_BYTES);
+        engine.initialize().await.unwrap();
+        let dir_str = dir_path.to_str().unwrap();
+
+        let code = format!(
+            "import os\nos.chdir({dir_str:?})\nos.environ['MSB_SESSION_VAR'] = 'kept'\nanswer = 42"
+        );
+        eval_stdout(&mut engine, &code).await;
+
+        let code = "print(answer, os.getcwd(), os.environ['MSB_SESSION_VAR'])";
+        assert_eq!(
+            eval_stdout(&mut engine, code).await,
+            vec![format!("42 {dir_str} kept")]
+        );
+
+        engine.reset().await.unwrap();
+
+        // Variables, the working directory and the environment are all gone after a reset
+        let code = format!(
+            "import os\nprint('answer' in globals(), os.getcwd() == {dir_str:?}, os.environ.get('MSB_SESSION_VAR'))"
+        );
+        assert_eq!(
+            eval_stdout(&mut engine, &code).await,
+            vec!["False False None".to_string()]
+        );
+
+        engine.shutdown().await;
+    }
+
+    /// Evaluates `code`, interrupting it once it has had time to get going
+    async fn eval_and_interrupt(
+        engine: &mut dyn Engine,
+        code: &str,
+    ) -> (Result<(), EngineError>, InterruptOutcome) {
+        let interrupter = engine.interrupter();
+        let interrupt = tokio::spawn(async move {
+            sleep(Duration::from_secs(1)).await;
+            let (outcome_tx, outcome_rx) = oneshot::channel();
+            interrupter.send(outcome_tx).await.unwrap();
+            outcome_rx.await.unwrap()
+        });
+
+        let (resp_tx, _resp_rx) = mpsc::channel(100);
+        let result = engine
+            .eval("test".to_string(), code.to_string(), &resp_tx, Some(30))
+            .await;
+
+        (result, interrupt.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_stops_evaluation_and_keeps_session() {
+        let mut engine = PythonEngine::new(DEFAULT_MAX_OUTPUT_BYTES);
+        engine.initialize().await.unwrap();
+
+        eval_stdout(&mut engine, "answer = 42").await;
+
+        let (result, outcome) =
+            eval_and_interrupt(&mut engine, "import time\ntime.sleep(30)").await;
+        assert!(matches!(
+            result,
+            Err(EngineError::Interrupted { restart: false })
+        ));
+        assert_eq!(outcome, InterruptOutcome::Interrupted);
+
+        // The engine recovers, and the session survives the interrupt
+        assert_eq!(
+            eval_stdout(&mut engine, "print(answer)").await,
+            vec!["42".to_string()]
+        );
+
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_restarts_evaluation_that_ignores_sigint() {
+        let mut engine = PythonEngine::new(DEFAULT_MAX_OUTPUT_BYTES);
+        engine.initialize().await.unwrap();
+
+        let code =
+            "import signal, time\nsignal.signal(signal.SIGINT, signal.SIG_IGN)\ntime.sleep(30)";
+        let (result, outcome) = eval_and_interrupt(&mut engine, code).await;
+        assert!(matches!(
+            result,
+            Err(EngineError::Interrupted { restart: true })
+        ));
+        assert_eq!(outcome, InterruptOutcome::Restarted);
+
+        // A fresh interpreter takes the next evaluation
+        assert_eq!(
+            eval_stdout(&mut engine, "print(1 + 1)").await,
+            vec!["2".to_string()]
+        );
+
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_interrupt_without_evaluation_is_idle() {
+        let mut engine = PythonEngine::new(DEFAULT_MAX_OUTPUT_BYTES);
+        engine.initialize().await.unwrap();
+
+        let (outcome_tx, outcome_rx) = oneshot::channel();
+        engine.interrupter().send(outcome_tx).await.unwrap();
+        assert_eq!(outcome_rx.await.unwrap(), InterruptOutcome::Idle);
+
+        assert_eq!(
+            eval_stdout(&mut engine, "print('still here')").await,
+            vec!["still here".to_string()]
+        );
+
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_installed_package_can_be_imported() {
+        // A wheel installs without reaching a package index
+        const BUILD_WHEEL: &str = r#"import sys, zipfile
+info = 'msb_trivial-0.1.dist-info/'
+files = {
+    'msb_trivial/__init__.py': 'VALUE = 42\n',
+    info + 'METADATA': 'Metadata-Version: 2.1\nName: msb-trivial\nVersion: 0.1\n',
+    info + 'WHEEL': 'Wheel-Version: 1.0\nRoot-Is-Purelib: true\nTag: py3-none-any\n',
+}
+files[info + 'RECORD'] = ''.join(name + ',,\n' for name in [*files, info + 'RECORD'])
+with zipfile.ZipFile(sys.argv[1], 'w') as wheel:
+    for name, content in files.items():
+        wheel.writestr(name, content)
+"#;
+
+        let dir = tempfile::tempdir().unwrap();
+        let wheel = dir.path().join("msb_trivial-0.1-py3-none-any.whl");
+        let status = std::process::Command::new(PYTHON)
+            .arg("-c")
+            .arg(BUILD_WHEEL)
+            .arg(&wheel)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Install into a throwaway environment rather than the Python running the tests. It
+        // borrows pip from the system packages, so it doesn't need ensurepip.
+        let venv = dir.path().join("venv");
+        let status = std::process::Command::new(PYTHON)
+            .args(["-m", "venv", "--without-pip", "--system-site-packages"])
+            .arg(&venv)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let python = venv.join("bin").join("python3");
+        let mut engine =
+            PythonEngine::with_python(DEFAULT_MAX_OUTPUT_BYTES, python.to_str().unwrap());
+        engine.initialize().await.unwrap();
+
+        let packages = vec![
+            wheel.to_str().unwrap().to_string(),
+            "--index-url=https://example.com".to_string(),
+        ];
+        let installs = engine.install_packages(&packages).await.unwrap();
+        assert!(installs[0].success, "{:?}", installs[0].error);
+        assert!(!installs[1].success);
+
+        let code = "import msb_trivial\nprint(msb_trivial.VALUE)";
+        assert_eq!(eval_stdout(&mut engine, code).await, vec!["42".to_string()]);
+
+        engine.shutdown().await;
+    }
 }
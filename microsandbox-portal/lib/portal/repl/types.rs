@@ -27,15 +27,23 @@
 //! The design accounts for concurrent use by leveraging thread-safe primitives and
 //! message passing through channels to communicate between components.
 
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
 use thiserror::Error;
-use tokio::sync::mpsc::Sender;
+#[cfg(any(feature = "python", feature = "nodejs"))]
+use tokio::sync::mpsc::Receiver;
+use tokio::sync::{mpsc::Sender, oneshot};
 
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
 
 /// Supported programming languages for evaluation
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Language {
     /// Python language support
     #[cfg(feature = "python")]
@@ -74,8 +82,49 @@ pub struct Line {
 #[derive(Clone)]
 pub struct EngineHandle {
     pub(crate) cmd_sender: Sender<Cmd>,
+
+    /// The most output bytes kept for a single evaluation
+    pub(crate) max_output_bytes: usize,
+
+    /// Where each engine takes requests to interrupt its running evaluation, once started
+    pub(crate) interrupters: Arc<Mutex<HashMap<Language, InterruptSender>>>,
 }
 
+/// What interrupting an engine's running evaluation did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterruptOutcome {
+    /// Nothing was being evaluated
+    Idle,
+
+    /// The evaluation stopped on SIGINT, and the session was kept
+    Interrupted,
+
+    /// The evaluation didn't stop on SIGINT, so the interpreter was restarted and the session lost
+    Restarted,
+}
+
+/// How installing a package went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageInstall {
+    /// The package, as given to the package manager
+    pub package: String,
+
+    /// Whether the package was installed
+    pub success: bool,
+
+    /// Why the package wasn't installed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Sends requests to interrupt an engine's running evaluation, each answered with the outcome
+pub(crate) type InterruptSender = Sender<oneshot::Sender<InterruptOutcome>>;
+
+/// Receives requests to interrupt an engine's running evaluation
+#[cfg(any(feature = "python", feature = "nodejs"))]
+pub(crate) type InterruptReceiver = Receiver<oneshot::Sender<InterruptOutcome>>;
+
 /// Error types that can occur during engine operations
 ///
 /// This enum encapsulates the various error conditions that can occur
@@ -97,6 +146,13 @@ pub enum EngineError {
     /// Engine unavailable (shutdown or crashed)
     #[error("Engine unavailable: {0}")]
     Unavailable(String),
+
+    /// Evaluation interrupted on request
+    #[error("Evaluation interrupted")]
+    Interrupted {
+        /// Whether the evaluation didn't stop, leaving the interpreter to be restarted
+        restart: bool,
+    },
 }
 
 /// Command sent to the reactor thread
@@ -114,6 +170,19 @@ pub(crate) enum Cmd {
         _timeout: Option<u64>,
     },
 
+    /// Reset the session of a language engine
+    Reset {
+        _language: Language,
+        _done_tx: oneshot::Sender<Result<(), EngineError>>,
+    },
+
+    /// Install packages for a language engine
+    Install {
+        _language: Language,
+        _packages: Vec<String>,
+        _done_tx: oneshot::Sender<Result<Vec<PackageInstall>, EngineError>>,
+    },
+
     /// Shutdown the reactor and all engines
     Shutdown,
 }
@@ -192,6 +261,35 @@ pub trait Engine: Send + 'static {
     /// This method is called when the engine is being shut down to clean up
     /// resources, terminate processes, etc.
     async fn shutdown(&mut self);
+
+    /// Returns where the engine takes requests to interrupt its running evaluation
+    ///
+    /// Interrupts bypass the reactor, which is busy waiting for the evaluation to finish, so the
+    /// sender stays the same for the lifetime of the engine, across resets.
+    fn interrupter(&self) -> InterruptSender;
+
+    /// Install packages with the language's package manager
+    ///
+    /// Each package is installed on its own, so one that fails doesn't stop the rest, and can be
+    /// imported by later evaluations once installed.
+    ///
+    /// # Parameters
+    ///
+    /// * `packages` - The packages to install, as the package manager takes them
+    async fn install_packages(
+        &mut self,
+        packages: &[String],
+    ) -> Result<Vec<PackageInstall>, EngineError>;
+
+    /// Reset the engine's session
+    ///
+    /// Evaluations share one interpreter, so variables, the working directory and environment
+    /// changes made by one evaluation are visible to the next. Resetting discards them by
+    /// replacing the interpreter with a fresh one.
+    async fn reset(&mut self) -> Result<(), EngineError> {
+        self.shutdown().await;
+        self.initialize().await
+    }
 }
 
 // -------------------------------------------------------------------------------------------------
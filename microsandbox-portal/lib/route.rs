@@ -7,12 +7,21 @@
 
 use axum::{
     Router,
+    body::Body,
+    http::Request,
     routing::{get, post},
 };
 use tower_http::trace::TraceLayer;
 
 use crate::{handler, state::SharedState};
 
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The header carrying the ID the server assigned to the request being forwarded
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
@@ -23,10 +32,25 @@ pub fn create_router(state: SharedState) -> Router {
     // Using an adapter function to properly handle the state parameter
     let rpc_api = Router::new().route("/", post(handler::json_rpc_handler));
 
-    // Combine all routes with tracing middleware
+    // Combine all routes with tracing middleware, tagging each span with the server's request ID
     Router::new()
         .route("/health", get(handler::health_check_handler))
         .nest("/api/v1/rpc", rpc_api)
-        .layer(TraceLayer::new_for_http())
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|req: &Request<Body>| {
+                let request_id = req
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or("-");
+
+                tracing::info_span!(
+                    "request",
+                    method = %req.method(),
+                    uri = %req.uri(),
+                    request_id = %request_id,
+                )
+            }),
+        )
         .with_state(state)
 }
@@ -3,7 +3,10 @@
 use std::sync::{Arc, atomic::AtomicBool};
 use tokio::sync::Mutex;
 
-use crate::portal::{command::CommandHandle, repl::EngineHandle};
+use crate::portal::{
+    command::CommandHandle, fs::FileSystem, output::DEFAULT_MAX_OUTPUT_BYTES, pty::PtySessions,
+    repl::EngineHandle,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -20,6 +23,15 @@ pub struct SharedState {
 
     /// Command handle for command execution
     pub command_handle: Arc<Mutex<Option<CommandHandle>>>,
+
+    /// File system access for copying files in and out of the sandbox
+    pub file_system: FileSystem,
+
+    /// Interactive commands running in pseudo-terminals
+    pub pty_sessions: PtySessions,
+
+    /// The most output bytes kept for a single evaluation or command
+    pub max_output_bytes: usize,
 }
 
 impl Default for SharedState {
@@ -28,6 +40,9 @@ impl Default for SharedState {
             ready: Arc::new(AtomicBool::new(false)),
             engine_handle: Arc::new(Mutex::new(None)),
             command_handle: Arc::new(Mutex::new(None)),
+            file_system: FileSystem::default(),
+            pty_sessions: PtySessions::default(),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
         }
     }
 }
@@ -18,10 +18,16 @@ use std::{
 };
 
 use getset::Getters;
-use microsandbox_utils::{PROJECTS_SUBDIR, env};
+use microsandbox_utils::{
+    CORS_ALLOWED_HEADERS_ENV_VAR, CORS_ALLOWED_METHODS_ENV_VAR, CORS_ALLOWED_ORIGINS_ENV_VAR,
+    DEFAULT_MAX_REQUEST_BODY_BYTES, MAX_REQUEST_BODY_ENV_VAR, PROJECTS_SUBDIR, env,
+};
 use serde::Deserialize;
 
-use crate::{MicrosandboxServerError, MicrosandboxServerResult};
+use crate::{
+    MicrosandboxServerError, MicrosandboxServerResult, metrics::MetricsHistoryConfig,
+    rate_limit::RateLimitConfig,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -30,6 +36,9 @@ use crate::{MicrosandboxServerError, MicrosandboxServerResult};
 /// The header name for the proxy authorization
 pub const PROXY_AUTH_HEADER: &str = "Proxy-Authorization";
 
+/// The header name carrying the ID used to correlate a request across the server and portal
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
@@ -56,6 +65,38 @@ pub struct Config {
 
     /// Address to listen on
     addr: SocketAddr,
+
+    /// Whether to stop the sandboxes started by the server when it shuts down
+    stop_sandboxes_on_exit: bool,
+
+    /// Rate limits applied to each client
+    rate_limit: RateLimitConfig,
+
+    /// Cross-origin request settings
+    cors: CorsConfig,
+
+    /// How often sandbox metrics are sampled into the history, and how much of it is kept
+    metrics_history: MetricsHistoryConfig,
+
+    /// Maximum size, in bytes, of a JSON-RPC or MCP request body
+    max_request_body: usize,
+}
+
+/// Cross-origin resource sharing settings for browser-based clients
+///
+/// Empty lists mean "not configured": origins are then allowed only in dev mode, and
+/// methods and headers fall back to the ones the API needs.
+#[derive(Debug, Clone, Default, Deserialize, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct CorsConfig {
+    /// Origins allowed to make cross-origin requests, `*` allows any origin
+    allowed_origins: Vec<String>,
+
+    /// Methods allowed in cross-origin requests
+    allowed_methods: Vec<String>,
+
+    /// Headers allowed in cross-origin requests
+    allowed_headers: Vec<String>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -64,12 +105,21 @@ pub struct Config {
 
 impl Config {
     /// Create a new configuration
+    ///
+    /// Without a maximum request body size, it is read from `MSB_MAX_REQUEST_BODY`, falling back
+    /// to [`DEFAULT_MAX_REQUEST_BODY_BYTES`].
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         key: Option<String>,
         host: String,
         port: u16,
         project_dir: Option<PathBuf>,
         dev_mode: bool,
+        stop_sandboxes_on_exit: bool,
+        rate_limit: RateLimitConfig,
+        cors: CorsConfig,
+        metrics_history: MetricsHistoryConfig,
+        max_request_body: Option<usize>,
     ) -> MicrosandboxServerResult<Self> {
         // Check key requirement based on dev mode
         let key = match key {
@@ -90,6 +140,13 @@ impl Config {
         let addr = SocketAddr::new(host_ip, port);
         let project_dir =
             project_dir.unwrap_or_else(|| env::get_microsandbox_home_path().join(PROJECTS_SUBDIR));
+        let max_request_body = max_request_body
+            .or_else(|| {
+                std::env::var(MAX_REQUEST_BODY_ENV_VAR)
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+            })
+            .unwrap_or(DEFAULT_MAX_REQUEST_BODY_BYTES);
 
         Ok(Self {
             key,
@@ -98,6 +155,49 @@ impl Config {
             host: host_ip,
             port,
             addr,
+            stop_sandboxes_on_exit,
+            rate_limit,
+            cors,
+            metrics_history,
+            max_request_body,
         })
     }
 }
+
+impl CorsConfig {
+    /// Create a new CORS configuration
+    ///
+    /// Each list that is empty is read from its comma-separated environment variable instead.
+    pub fn new(
+        allowed_origins: Vec<String>,
+        allowed_methods: Vec<String>,
+        allowed_headers: Vec<String>,
+    ) -> Self {
+        Self {
+            allowed_origins: or_env_list(allowed_origins, CORS_ALLOWED_ORIGINS_ENV_VAR),
+            allowed_methods: or_env_list(allowed_methods, CORS_ALLOWED_METHODS_ENV_VAR),
+            allowed_headers: or_env_list(allowed_headers, CORS_ALLOWED_HEADERS_ENV_VAR),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Returns `values` if not empty, otherwise the comma-separated values of an environment variable
+fn or_env_list(values: Vec<String>, var: &str) -> Vec<String> {
+    if !values.is_empty() {
+        return values;
+    }
+
+    std::env::var(var)
+        .map(|list| {
+            list.split(',')
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
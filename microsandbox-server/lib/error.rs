@@ -12,16 +12,22 @@
 //! - Serializable error responses for API clients
 //! - Structured error codes for frontend handling
 
+use std::time::Duration;
+
 use axum::{
     Json,
-    http::StatusCode,
+    http::{HeaderValue, StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
+use microsandbox_core::MicrosandboxError;
 use microsandbox_utils::MicrosandboxUtilsError;
 use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use thiserror::Error;
 use tracing::error;
 
+use crate::middleware::current_request_id;
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
@@ -61,7 +67,7 @@ pub enum MicrosandboxServerError {
 }
 
 /// Represents all possible errors that can occur in the application
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ServerError {
     /// Error returned when authentication fails
     #[error("Authentication failed: {0}")]
@@ -86,6 +92,30 @@ pub enum ServerError {
     /// Error returned when an unexpected internal error occurs
     #[error("Internal server error: {0}")]
     InternalError(String),
+
+    /// Error returned when a client exceeds its request rate limit
+    #[error("Rate limit exceeded, retry after {0:?}")]
+    RateLimited(Duration),
+
+    /// Error returned when a request body is larger than the limit, in bytes
+    #[error("Request body exceeds the limit of {0} bytes")]
+    PayloadTooLarge(usize),
+
+    /// Error returned when a sandbox does not start within its start timeout
+    #[error("Sandbox start timed out: {0}")]
+    SandboxStartTimeout(String),
+
+    /// Error returned when the requested operation is not supported by the sandbox backend
+    #[error("Not supported: {0}")]
+    NotSupported(String),
+
+    /// Error returned when the host doesn't have the resources to start the requested sandboxes
+    #[error("Insufficient host resources: {0}")]
+    InsufficientResources(String),
+
+    /// Error returned when a sandbox a request is proxied to can't be reached
+    #[error("Bad gateway: {0}")]
+    BadGateway(String),
 }
 
 /// Error code structure to be sent to frontend
@@ -124,6 +154,14 @@ pub enum ErrorCode {
     EmailInvalid = 2003,
     /// Error returned when a confirmation token is invalid or has expired
     InvalidOrExpiredConfirmationToken = 2004,
+    /// Error returned when a sandbox configuration is invalid
+    InvalidConfig = 2005,
+    /// Error returned when a sandbox is not defined in the configuration
+    SandboxNotInConfig = 2006,
+    /// Error returned when the image of a sandbox cannot be found
+    ImageNotFound = 2007,
+    /// Error returned when a request body is larger than the server accepts
+    PayloadTooLarge = 2008,
 
     // Authorization error codes
     /// Error returned when a user is denied access to a resource
@@ -140,10 +178,22 @@ pub enum ErrorCode {
     DatabaseError = 5001,
     /// Error returned when an unexpected server error occurs
     InternalServerError = 5002,
+    /// Error returned when a sandbox does not start in time
+    SandboxStartTimeout = 5003,
+    /// Error returned when an operation is not supported by the sandbox backend
+    NotSupported = 5004,
+    /// Error returned when the host lacks the resources to start sandboxes
+    InsufficientHostResources = 5005,
+    /// Error returned when a sandbox a request is proxied to can't be reached
+    SandboxUnreachable = 5006,
+
+    // Rate limit error codes
+    /// Error returned when a client sends too many requests
+    RateLimited = 6001,
 }
 
 /// Represents different types of authentication failures
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum AuthenticationError {
     /// Security-sensitive authentication failures that shouldn't reveal details
     #[error("Invalid credentials")]
@@ -187,7 +237,7 @@ pub enum AuthenticationError {
 }
 
 /// Represents validation errors
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum ValidationError {
     /// Generic validation error
     #[error("{0}")]
@@ -204,10 +254,22 @@ pub enum ValidationError {
     /// Invalid or expired confirmation token
     #[error("Invalid or expired confirmation token")]
     InvalidConfirmationToken,
+
+    /// The sandbox configuration is invalid
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// The sandbox is not defined in the configuration
+    #[error("Sandbox '{0}' not found in configuration")]
+    SandboxNotInConfig(String),
+
+    /// The image of a sandbox cannot be found
+    #[error("Image '{0}' not found")]
+    ImageNotFound(String),
 }
 
 /// Represents authorization errors
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum AuthorizationError {
     /// Access denied
     #[error("Access denied")]
@@ -223,6 +285,63 @@ pub enum AuthorizationError {
 struct ErrorResponse {
     error: String,
     code: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl ServerError {
+    /// Maps an error from the microsandbox core to the server error reported to the client.
+    ///
+    /// Failures caused by the request or the configuration it refers to become validation
+    /// errors, so the client gets a 4xx with a distinct code. This includes a registry that
+    /// doesn't know the image or tag. A host without the resources to start the sandboxes is
+    /// reported as unavailable, since the same request can succeed once resources are freed.
+    /// Anything else is reported as an internal error.
+    ///
+    /// ## Arguments
+    ///
+    /// * `context` - What the server was doing, prefixed to the message of internal errors
+    /// * `e` - The error from the core
+    pub fn from_core(context: impl std::fmt::Display, e: MicrosandboxError) -> Self {
+        match e {
+            MicrosandboxError::SandboxNotFoundInConfig(sandbox, _) => {
+                ServerError::ValidationError(ValidationError::SandboxNotInConfig(sandbox))
+            }
+            e if e.is_image_not_found() => {
+                ServerError::ValidationError(ValidationError::ImageNotFound(
+                    e.missing_image().unwrap_or_else(|| "unknown".to_string()),
+                ))
+            }
+            MicrosandboxError::ConfigValidation(_)
+            | MicrosandboxError::ConfigValidationErrors(_)
+            | MicrosandboxError::ConfigParseError(_)
+            | MicrosandboxError::SerdeYaml(_)
+            | MicrosandboxError::NoSandboxesInConfig(_)
+            | MicrosandboxError::MissingStartOrExecOrShell
+            | MicrosandboxError::InvalidPathPair(_)
+            | MicrosandboxError::InvalidPortPair(_)
+            | MicrosandboxError::InvalidEnvPair(_)
+            | MicrosandboxError::MissingHostEnv(_)
+            | MicrosandboxError::SecretNotFound(_, _)
+            | MicrosandboxError::ImageReferenceError(_)
+            | MicrosandboxError::ParseError(_) => {
+                ServerError::ValidationError(ValidationError::InvalidConfig(e.to_string()))
+            }
+            MicrosandboxError::InsufficientHostResources(details) => {
+                ServerError::InsufficientResources(details)
+            }
+            MicrosandboxError::MicrosandboxConfigNotFound(_) => {
+                ServerError::NotFound(e.to_string())
+            }
+            e => ServerError::InternalError(format!("{}: {}", context, e)),
+        }
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -239,10 +358,31 @@ impl IntoResponse for ServerError {
     /// - Appropriate HTTP status code based on the error type
     /// - JSON body with an "error" field containing the error message
     /// - And an optional "code" field with a numeric error code for the frontend
+    /// - And the "request_id" of the request that failed, if it has one
     fn into_response(self) -> Response {
         // Log the actual error with details
         error!(error = ?self, "API error occurred");
 
+        // Whole seconds a rate-limited client should wait, rounded up
+        let retry_after = match &self {
+            ServerError::RateLimited(wait) => Some((wait.as_secs_f64().ceil() as u64).max(1)),
+            _ => None,
+        };
+
+        // Machine-readable details of the failure, for errors that have any
+        let details = match &self {
+            ServerError::ValidationError(ValidationError::InvalidConfig(reason)) => {
+                Some(json!({ "reason": reason }))
+            }
+            ServerError::ValidationError(ValidationError::SandboxNotInConfig(sandbox)) => {
+                Some(json!({ "sandbox": sandbox }))
+            }
+            ServerError::ValidationError(ValidationError::ImageNotFound(image)) => {
+                Some(json!({ "image": image }))
+            }
+            _ => None,
+        };
+
         let (status, error_message, error_code) = match self {
             ServerError::Authentication(auth_error) => {
                 match auth_error {
@@ -335,6 +475,21 @@ impl IntoResponse for ServerError {
                     "Invalid or expired confirmation token".to_string(),
                     Some(ErrorCode::InvalidOrExpiredConfirmationToken as u32),
                 ),
+                ValidationError::InvalidConfig(details) => (
+                    StatusCode::BAD_REQUEST,
+                    format!("Invalid configuration: {}", details),
+                    Some(ErrorCode::InvalidConfig as u32),
+                ),
+                e @ ValidationError::SandboxNotInConfig(_) => (
+                    StatusCode::NOT_FOUND,
+                    e.to_string(),
+                    Some(ErrorCode::SandboxNotInConfig as u32),
+                ),
+                e @ ValidationError::ImageNotFound(_) => (
+                    StatusCode::NOT_FOUND,
+                    e.to_string(),
+                    Some(ErrorCode::ImageNotFound as u32),
+                ),
             },
             ServerError::InternalError(details) => {
                 error!(details = ?details, "Internal error");
@@ -344,13 +499,193 @@ impl IntoResponse for ServerError {
                     Some(ErrorCode::InternalServerError as u32),
                 )
             }
+            ServerError::RateLimited(_) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, please try again later".to_string(),
+                Some(ErrorCode::RateLimited as u32),
+            ),
+            e @ ServerError::PayloadTooLarge(_) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                e.to_string(),
+                Some(ErrorCode::PayloadTooLarge as u32),
+            ),
+            ServerError::SandboxStartTimeout(details) => (
+                StatusCode::GATEWAY_TIMEOUT,
+                details,
+                Some(ErrorCode::SandboxStartTimeout as u32),
+            ),
+            ServerError::NotSupported(details) => (
+                StatusCode::NOT_IMPLEMENTED,
+                details,
+                Some(ErrorCode::NotSupported as u32),
+            ),
+            e @ ServerError::InsufficientResources(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                e.to_string(),
+                Some(ErrorCode::InsufficientHostResources as u32),
+            ),
+            ServerError::BadGateway(details) => (
+                StatusCode::BAD_GATEWAY,
+                details,
+                Some(ErrorCode::SandboxUnreachable as u32),
+            ),
         };
 
         let body = Json(ErrorResponse {
             error: error_message,
             code: error_code,
+            details,
+            request_id: current_request_id(),
         });
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use axum::body;
+    use oci_client::errors::OciDistributionError;
+
+    use super::*;
+
+    async fn into_parts(error: ServerError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_server_error_from_core_sandbox_not_in_config() {
+        let error = ServerError::from_core(
+            "Failed to start sandbox web",
+            MicrosandboxError::SandboxNotFoundInConfig(
+                "web".to_string(),
+                PathBuf::from("/projects/Sandboxfile"),
+            ),
+        );
+
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], ErrorCode::SandboxNotInConfig as u32);
+        assert_eq!(body["details"], json!({ "sandbox": "web" }));
+    }
+
+    #[tokio::test]
+    async fn test_server_error_from_core_image_not_found() {
+        let error = ServerError::from_core(
+            "Failed to start sandbox web",
+            MicrosandboxError::ImageNotFound("docker.io/library/nope:latest".to_string()),
+        );
+
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], ErrorCode::ImageNotFound as u32);
+        assert_eq!(
+            body["details"],
+            json!({ "image": "docker.io/library/nope:latest" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_error_from_core_registry_image_not_found() {
+        let error = ServerError::from_core(
+            "Failed to pull image",
+            MicrosandboxError::RegistryResponse {
+                status: 404,
+                body_snippet: String::new(),
+                hint: "the image or tag does not exist".to_string(),
+            },
+        );
+
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], ErrorCode::ImageNotFound as u32);
+        assert_eq!(body["details"], json!({ "image": "unknown" }));
+
+        // A registry client error that wasn't mapped names the image through its URL
+        let error = ServerError::from_core(
+            "Failed to pull image",
+            MicrosandboxError::OciDistribution(OciDistributionError::ServerError {
+                code: 404,
+                url: "https://registry-1.docker.io/v2/library/nginx/manifests/nope".to_string(),
+                message: "manifest unknown".to_string(),
+            }),
+        );
+
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], ErrorCode::ImageNotFound as u32);
+        assert_eq!(body["details"], json!({ "image": "library/nginx:nope" }));
+    }
+
+    #[tokio::test]
+    async fn test_server_error_from_core_invalid_config() {
+        let error = ServerError::from_core(
+            "Failed to write config file",
+            MicrosandboxError::InvalidPortPair("80:http".to_string()),
+        );
+
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], ErrorCode::InvalidConfig as u32);
+        assert_eq!(
+            body["details"],
+            json!({ "reason": "invalid port pair: 80:http" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_error_from_core_insufficient_host_resources() {
+        let error = ServerError::from_core(
+            "Failed to start sandbox web",
+            MicrosandboxError::InsufficientHostResources(
+                "needs 8192 MiB of memory, 2048 MiB available".to_string(),
+            ),
+        );
+
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["code"], ErrorCode::InsufficientHostResources as u32);
+        assert_eq!(
+            body["error"],
+            "Insufficient host resources: needs 8192 MiB of memory, 2048 MiB available"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_server_error_from_core_internal_error() {
+        let error = ServerError::from_core(
+            "Failed to start sandbox web",
+            MicrosandboxError::SupervisorError("sandbox exited early".to_string()),
+        );
+        assert!(matches!(
+            &error,
+            ServerError::InternalError(details)
+                if details == "Failed to start sandbox web: supervisor error: sandbox exited early"
+        ));
+
+        // Internal errors don't leak their details to the client
+        let (status, body) = into_parts(error).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], ErrorCode::InternalServerError as u32);
+        assert_eq!(body["error"], "Internal server error");
+        assert!(body.get("details").is_none());
     }
 }
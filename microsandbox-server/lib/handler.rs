@@ -14,48 +14,293 @@ use axum::{
     Json,
     body::Body,
     debug_handler,
-    extract::{Path, State},
-    http::{Request, StatusCode},
+    extract::{Path, Query, State},
+    http::{
+        HeaderMap, HeaderName, Request, StatusCode, Uri,
+        header::{AUTHORIZATION, CONNECTION, HOST},
+    },
     response::{IntoResponse, Response},
 };
-use microsandbox_core::management::{menv, orchestra};
-use microsandbox_utils::{DEFAULT_CONFIG, DEFAULT_PORTAL_GUEST_PORT, MICROSANDBOX_CONFIG_FILENAME};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use futures::StreamExt;
+use microsandbox_core::{
+    MicrosandboxError, MicrosandboxResult,
+    config::EnvPair,
+    management::{config, db, menv, orchestra},
+    runtime::{self, RetryPolicy},
+};
+use microsandbox_utils::{DEFAULT_PORTAL_GUEST_PORT, MICROSANDBOX_CONFIG_FILENAME};
+use once_cell::sync::Lazy;
 use reqwest;
 use serde_json::{self, json};
 use serde_yaml;
-use std::path::{Path as StdPath, PathBuf};
+use sqlx::{Pool, Sqlite};
+use std::{
+    collections::{BTreeMap, HashSet},
+    future::Future,
+    path::{Path as StdPath, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
 use tokio::{
     fs as tokio_fs,
-    time::{Duration, sleep, timeout},
+    sync::Semaphore,
+    time::{Duration, timeout},
 };
 use tracing::{debug, trace, warn};
 
 use crate::{
-    SandboxStatus, SandboxStatusResponse, ServerResult,
+    SandboxStatusResponse, ServerResult,
+    config::{PROXY_AUTH_HEADER, REQUEST_ID_HEADER},
     error::ServerError,
-    mcp, middleware,
+    idle::IdlePolicy,
+    mcp, metrics, middleware,
     payload::{
-        JSONRPC_VERSION, JsonRpcError, JsonRpcRequest, JsonRpcResponse,
-        JsonRpcResponseOrNotification, RegularMessageResponse, SandboxMetricsGetParams,
-        SandboxStartParams, SandboxStopParams,
+        ComponentHealth, HealthResponse, JSONRPC_VERSION, JsonRpcError, JsonRpcRequest,
+        JsonRpcResponse, JsonRpcResponseOrNotification, RegularMessageResponse,
+        SandboxCommandRunResult, SandboxExecParams, SandboxExecResult, SandboxFileUploadQuery,
+        SandboxFileUploadResult, SandboxMetricsGetParams, SandboxResizeParams,
+        SandboxStartBatchParams, SandboxStartBatchResult, SandboxStartParams, SandboxStopParams,
     },
     state::AppState,
 };
 
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// Interval before the second check when polling for a sandbox to start running.
+const POLL_INITIAL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Upper bound on the interval between checks when polling for a sandbox to start running.
+const POLL_MAX_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Overall time budget for a sandbox to start running, which can include a first-time image pull.
+const POLL_TIMEOUT: Duration = Duration::from_secs(50);
+
+/// Size of the chunks a streamed upload is written to the sandbox in. Once base64-encoded, each
+/// chunk stays well under the request body limit of the portal.
+const UPLOAD_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Headers that only apply to a single connection, which a proxy doesn't forward
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Client proxied requests are forwarded with. Redirects are passed back to the client rather
+/// than followed.
+static PROXY_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .expect("proxy client should build with default settings")
+});
+
+/// Caps how many sandboxes the server starts at once, across every request, at
+/// `MSB_MAX_CONCURRENT_SANDBOXES`, or the number of CPUs on the host if it isn't set
+static START_PERMITS: Lazy<Semaphore> =
+    Lazy::new(|| Semaphore::new(orchestra::default_max_concurrent_sandboxes()));
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// A sandbox whose configuration has been written and whose portal port has been assigned
+struct PreparedStart {
+    /// The name of the sandbox
+    sandbox: String,
+
+    /// The key the sandbox's port and idle timer are tracked under, see [`crate::sandbox_key`]
+    key: String,
+
+    /// The image of the sandbox, if its configuration names one
+    image: Option<String>,
+
+    /// The project directory the sandbox belongs to
+    project_dir: PathBuf,
+
+    /// How long to wait for the sandbox to start running
+    poll_timeout: Duration,
+
+    /// The project named in the start request, if any
+    project: Option<String>,
+
+    /// How the sandbox is scaled down when idle and back up on demand
+    idle_policy: IdlePolicy,
+
+    /// Releases the portal port assigned for this start unless the sandbox starts
+    port_guard: PortAssignmentGuard,
+}
+
+/// The sandbox and file a streamed upload is written to
+struct UploadTarget<'a> {
+    /// The name of the sandbox
+    sandbox: &'a str,
+
+    /// The key of the sandbox, see [`crate::sandbox_key`]
+    sandbox_key: &'a str,
+
+    /// The file to write and its permissions
+    query: &'a SandboxFileUploadQuery,
+}
+
+/// Releases a portal port assigned for a sandbox start, unless the start succeeds
+///
+/// The guard is only armed when the start assigned a new port; a sandbox that was already running
+/// keeps its port even if starting it again fails. Dropping an armed guard releases the port on a
+/// background task, which covers starts abandoned midway, e.g. because the client disconnected.
+struct PortAssignmentGuard {
+    /// The server state holding the port manager
+    state: AppState,
+
+    /// The key of the sandbox whose port is released, or `None` once the guard is disarmed
+    sandbox_key: Option<String>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl PreparedStart {
+    /// Keeps the portal port assigned and starts the idle timer, as the sandbox has started
+    fn started(self, state: &AppState) {
+        self.port_guard.disarm();
+        state.get_idle_tracker().started(
+            &self.key,
+            self.project.as_deref(),
+            &self.project_dir,
+            self.idle_policy,
+            Instant::now(),
+        );
+    }
+}
+
+impl PortAssignmentGuard {
+    /// Creates a guard that releases the port assigned to the sandbox with the given key
+    fn new(state: &AppState, sandbox_key: &str) -> Self {
+        Self {
+            state: state.clone(),
+            sandbox_key: Some(sandbox_key.to_string()),
+        }
+    }
+
+    /// Creates a guard that leaves the port assignment alone
+    fn disarmed(state: &AppState) -> Self {
+        Self {
+            state: state.clone(),
+            sandbox_key: None,
+        }
+    }
+
+    /// Keeps the port assigned, as the sandbox has started
+    fn disarm(mut self) {
+        self.sandbox_key = None;
+    }
+
+    /// Releases the port right away, as the sandbox failed to start
+    async fn release(mut self) {
+        if let Some(sandbox_key) = self.sandbox_key.take() {
+            release_assigned_port(&self.state, &sandbox_key).await;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Drop for PortAssignmentGuard {
+    fn drop(&mut self) {
+        if let Some(sandbox_key) = self.sandbox_key.take() {
+            let state = self.state.clone();
+            tokio::spawn(async move { release_assigned_port(&state, &sandbox_key).await });
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: REST API Handlers
 //--------------------------------------------------------------------------------------------------
 
-/// Handler for health check
-pub async fn health() -> ServerResult<impl IntoResponse> {
+/// Handler for readiness check
+///
+/// Returns 200 only when the virtualization backend is usable and the sandbox database is
+/// reachable, and 503 with the failing components otherwise.
+pub async fn health(State(state): State<AppState>) -> ServerResult<impl IntoResponse> {
+    Ok(check_health(state.sandbox_db_pool().await, runtime::probe_backend).await)
+}
+
+/// Handler for liveness check
+pub async fn livez() -> ServerResult<impl IntoResponse> {
     Ok((
         StatusCode::OK,
         Json(RegularMessageResponse {
-            message: "Service is healthy".to_string(),
+            message: "Service is alive".to_string(),
         }),
     ))
 }
 
+/// Handler for streaming a file into a sandbox
+///
+/// The request body is the raw contents of the file. It is never buffered whole, but written to
+/// the sandbox through `sandbox.fs.write` in chunks of [`UPLOAD_CHUNK_SIZE`] as it arrives, so
+/// uploads aren't bound by the request body limit of JSON-RPC calls.
+pub async fn upload_file(
+    State(state): State<AppState>,
+    Path(sandbox): Path<String>,
+    Query(query): Query<SandboxFileUploadQuery>,
+    body: Body,
+) -> ServerResult<Response> {
+    validate_sandbox_name(&sandbox)?;
+    let sandbox_key = resolve_sandbox_key(&state, &sandbox, query.project.as_deref()).await?;
+    state.get_idle_tracker().touch(&sandbox_key, Instant::now());
+    let target = UploadTarget {
+        sandbox: &sandbox,
+        sandbox_key: &sandbox_key,
+        query: &query,
+    };
+
+    let mut stream = body.into_data_stream();
+    let mut buffer = Vec::with_capacity(UPLOAD_CHUNK_SIZE);
+    let mut offset = 0;
+    while let Some(data) = stream.next().await {
+        let data = data.map_err(|e| {
+            ServerError::InternalError(format!("Failed to read upload body: {}", e))
+        })?;
+        buffer.extend_from_slice(&data);
+
+        while buffer.len() >= UPLOAD_CHUNK_SIZE {
+            let rest = buffer.split_off(UPLOAD_CHUNK_SIZE);
+            if let Some(error) = write_upload_chunk(&state, &target, offset, &buffer).await? {
+                return Ok(error);
+            }
+            offset += buffer.len() as u64;
+            buffer = rest;
+        }
+    }
+
+    // Write what is left, or an empty chunk so that an empty body still creates the file
+    if (!buffer.is_empty() || offset == 0)
+        && let Some(error) = write_upload_chunk(&state, &target, offset, &buffer).await?
+    {
+        return Ok(error);
+    }
+    offset += buffer.len() as u64;
+
+    Ok((
+        StatusCode::OK,
+        Json(SandboxFileUploadResult { written: offset }),
+    )
+        .into_response())
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: JSON-RPC Handlers
 //--------------------------------------------------------------------------------------------------
@@ -144,6 +389,23 @@ pub async fn json_rpc_handler(
                 Json(JsonRpcResponse::success(json!(result), id)),
             ))
         }
+        "sandbox.start.batch" => {
+            // Parse the params into a SandboxStartBatchParams
+            let batch_params: SandboxStartBatchParams =
+                serde_json::from_value(request.params.clone()).map_err(|e| {
+                    ServerError::ValidationError(crate::error::ValidationError::InvalidInput(
+                        format!("Invalid params for sandbox.start.batch: {}", e),
+                    ))
+                })?;
+
+            let result = sandbox_start_batch_impl(state, batch_params).await?;
+
+            // Create JSON-RPC response with success
+            Ok((
+                StatusCode::OK,
+                Json(JsonRpcResponse::success(json!(result), id)),
+            ))
+        }
         "sandbox.stop" => {
             // Parse the params into a SandboxStopRequest
             let stop_params: SandboxStopParams = serde_json::from_value(request.params.clone())
@@ -162,6 +424,23 @@ pub async fn json_rpc_handler(
                 Json(JsonRpcResponse::success(json!(result), id)),
             ))
         }
+        "sandbox.resize" => {
+            // Parse the params into a SandboxResizeParams
+            let resize_params: SandboxResizeParams = serde_json::from_value(request.params.clone())
+                .map_err(|e| {
+                    ServerError::ValidationError(crate::error::ValidationError::InvalidInput(
+                        format!("Invalid params for sandbox.resize: {}", e),
+                    ))
+                })?;
+
+            let result = sandbox_resize_impl(state, resize_params).await?;
+
+            // Create JSON-RPC response with success
+            Ok((
+                StatusCode::OK,
+                Json(JsonRpcResponse::success(json!(result), id)),
+            ))
+        }
         "sandbox.metrics.get" => {
             // Parse the params into a SandboxMetricsGetRequest
             let metrics_params: SandboxMetricsGetParams =
@@ -181,8 +460,61 @@ pub async fn json_rpc_handler(
             ))
         }
 
+        "sandbox.metrics.history" => {
+            // Parse the params into a SandboxMetricsHistoryParams
+            let history_params: SandboxMetricsHistoryParams =
+                serde_json::from_value(request.params.clone()).map_err(|e| {
+                    ServerError::ValidationError(crate::error::ValidationError::InvalidInput(
+                        format!("Invalid params for sandbox.metrics.history: {}", e),
+                    ))
+                })?;
+
+            let result = sandbox_metrics_history_impl(&state, history_params).await?;
+
+            // Create JSON-RPC response with success
+            Ok((
+                StatusCode::OK,
+                Json(JsonRpcResponse::success(json!(result), id)),
+            ))
+        }
+
+        "sandbox.exec" => {
+            // Parse the params into a SandboxExecParams
+            let exec_params: SandboxExecParams = serde_json::from_value(request.params.clone())
+                .map_err(|e| {
+                    ServerError::ValidationError(crate::error::ValidationError::InvalidInput(
+                        format!("Invalid params for sandbox.exec: {}", e),
+                    ))
+                })?;
+
+            let result = sandbox_exec_impl(state, exec_params).await?;
+
+            // Create JSON-RPC response with success
+            Ok((
+                StatusCode::OK,
+                Json(JsonRpcResponse::success(json!(result), id)),
+            ))
+        }
+
         // Portal-forwarded methods
-        "sandbox.repl.run" | "sandbox.command.run" => {
+        "sandbox.repl.run"
+        | "sandbox.repl.reset"
+        | "sandbox.repl.interrupt"
+        | "sandbox.repl.install"
+        | "sandbox.command.run"
+        | "sandbox.command.reset"
+        | "sandbox.fs.stat"
+        | "sandbox.fs.list"
+        | "sandbox.fs.read"
+        | "sandbox.fs.write"
+        | "sandbox.fs.mkdir"
+        | "sandbox.fs.mktemp"
+        | "sandbox.fs.cleanup_temp"
+        | "sandbox.pty.open"
+        | "sandbox.pty.write"
+        | "sandbox.pty.read"
+        | "sandbox.pty.resize"
+        | "sandbox.pty.close" => {
             // Forward these RPC methods to the portal
             match forward_rpc_to_portal(state, request).await {
                 Ok((status, json_response)) => Ok((status, json_response)),
@@ -213,16 +545,18 @@ pub async fn forward_rpc_to_portal(
     // The method will have the format "sandbox.repl.run" etc.
     // The method params will have a sandbox_name parameter
 
-    // Extract the sandbox name from the parameters
-    let sandbox_name = if let Some(params) = request.params.as_object() {
-        params
+    // Extract the sandbox name, and the project it belongs to if given, from the parameters
+    let (sandbox_name, project) = if let Some(params) = request.params.as_object() {
+        let sandbox_name = params
             .get("sandbox")
             .and_then(|v| v.as_str())
             .ok_or_else(|| {
                 ServerError::ValidationError(crate::error::ValidationError::InvalidInput(
                     "Missing required 'sandbox' parameter for portal request".to_string(),
                 ))
-            })?
+            })?;
+        let project = params.get("project").and_then(|v| v.as_str());
+        (sandbox_name, project)
     } else {
         return Err(ServerError::ValidationError(
             crate::error::ValidationError::InvalidInput(
@@ -231,8 +565,18 @@ pub async fn forward_rpc_to_portal(
         ));
     };
 
+    let sandbox_key = resolve_sandbox_key(&state, sandbox_name, project).await?;
+    forward_rpc_to_sandbox_portal(&state, &sandbox_key, request).await
+}
+
+/// Forwards the JSON-RPC request to the portal of the sandbox with the given key
+async fn forward_rpc_to_sandbox_portal(
+    state: &AppState,
+    sandbox_key: &str,
+    request: JsonRpcRequest,
+) -> ServerResult<(StatusCode, Json<JsonRpcResponse>)> {
     // Get the portal URL specifically for this sandbox
-    let portal_url = state.get_portal_url_for_sandbox(sandbox_name).await?;
+    let portal_url = state.get_portal_url_for_sandbox(sandbox_key).await?;
 
     // Create a full URL to the portal's JSON-RPC endpoint
     let portal_rpc_url = format!("{}/api/v1/rpc", portal_url);
@@ -250,78 +594,55 @@ pub async fn forward_rpc_to_portal(
     const TIMEOUT_MS: u64 = 50;
     const RETRY_DELAY_MS: u64 = 10;
 
-    // Try to establish a connection to the portal before sending the actual request
-    let mut retry_count = 0;
-    let mut last_error = None;
-
-    // Keep trying to connect until we succeed or hit max retries
-    while retry_count < MAX_RETRIES {
-        // Check if portal is available and ready using the health check endpoint
-        match client
-            .head(&portal_health_url)
+    let policy = RetryPolicy::builder()
+        .max_attempts(MAX_RETRIES)
+        .initial_backoff(Duration::from_millis(RETRY_DELAY_MS))
+        .multiplier(1.0)
+        .jitter(0.0)
+        .build();
+
+    // Check if portal is available and ready using the health check endpoint
+    let (health_client, health_url) = (&client, &portal_health_url);
+    runtime::retry(&policy, || async move {
+        let response = health_client
+            .head(health_url)
             .timeout(Duration::from_millis(TIMEOUT_MS))
             .send()
             .await
-        {
-            Ok(response) => {
-                let status = response.status();
-                if status == reqwest::StatusCode::OK {
-                    debug!(
-                        "Successfully connected to portal after {} retries (status: {})",
-                        retry_count, status
-                    );
-                    break;
-                } else if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
-                    last_error = Some(format!("Portal not ready yet (status: {})", status));
-                    trace!(
-                        "Portal not ready (attempt {}), retrying...",
-                        retry_count + 1
-                    );
-                } else {
-                    last_error = Some(format!("Portal returned error status: {}", status));
-                    trace!(
-                        "Portal connection attempt {} returned {}, retrying...",
-                        retry_count + 1,
-                        status
-                    );
-                }
+            .map_err(|e| e.to_string())?;
+
+        match response.status() {
+            reqwest::StatusCode::OK => Ok(()),
+            status @ reqwest::StatusCode::SERVICE_UNAVAILABLE => {
+                trace!("Portal not ready (status: {}), retrying...", status);
+                Err(format!("Portal not ready yet (status: {})", status))
             }
-            Err(e) => {
-                // Track the error for potential reporting but keep retrying
-                last_error = Some(e.to_string());
-                trace!("Connection attempt {} failed, retrying...", retry_count + 1);
+            status => {
+                trace!("Portal returned {}, retrying...", status);
+                Err(format!("Portal returned error status: {}", status))
             }
         }
+    })
+    .await
+    .map_err(|e| {
+        ServerError::InternalError(format!(
+            "Failed to connect to portal after {} retries: {}",
+            MAX_RETRIES, e
+        ))
+    })?;
 
-        // Increment retry counter
-        retry_count += 1;
-
-        // Wait before the next retry to give the portal time to start
-        sleep(Duration::from_millis(RETRY_DELAY_MS)).await;
-    }
+    debug!("Successfully connected to portal");
 
-    // If we've hit the max retries and still can't connect, report the error
-    if retry_count >= MAX_RETRIES {
-        let error_msg = if let Some(e) = last_error {
-            format!(
-                "Failed to connect to portal after {} retries: {}",
-                MAX_RETRIES, e
-            )
-        } else {
-            format!("Failed to connect to portal after {} retries", MAX_RETRIES)
-        };
-        return Err(ServerError::InternalError(error_msg));
+    // Forward the request to the portal now that we've verified connectivity, carrying the
+    // request ID so the portal's logs can be correlated with ours
+    let mut portal_request = client.post(&portal_rpc_url).json(&request);
+    if let Some(request_id) = middleware::current_request_id() {
+        portal_request = portal_request.header(REQUEST_ID_HEADER, request_id);
     }
 
-    // Forward the request to the portal now that we've verified connectivity
-    let response = client
-        .post(&portal_rpc_url)
-        .json(&request)
-        .send()
-        .await
-        .map_err(|e| {
-            ServerError::InternalError(format!("Failed to forward RPC to portal: {}", e))
-        })?;
+    let response = portal_request.send().await.map_err(|e| {
+        ServerError::InternalError(format!("Failed to forward RPC to portal: {}", e))
+    })?;
 
     // Check if the request was successful
     if !response.status().is_success() {
@@ -331,6 +652,13 @@ pub async fn forward_rpc_to_portal(
             .await
             .unwrap_or_else(|_| "Unknown error".to_string());
 
+        // Relay JSON-RPC errors from the portal so callers see what went wrong in the sandbox
+        if let Ok(portal_response) = serde_json::from_str::<JsonRpcResponse>(&error_text)
+            && portal_response.error.is_some()
+        {
+            return Ok((status, Json(portal_response)));
+        }
+
         return Err(ServerError::InternalError(format!(
             "Portal returned error status {}: {}",
             status, error_text
@@ -347,6 +675,9 @@ pub async fn forward_rpc_to_portal(
 }
 
 /// Implementation for starting a sandbox
+///
+/// Requests carrying an idempotency key are deduped: a retry while the first request is still in
+/// progress, or shortly after it succeeded, gets the same result without launching again.
 pub async fn sandbox_start_impl(
     state: AppState,
     params: SandboxStartParams,
@@ -354,7 +685,130 @@ pub async fn sandbox_start_impl(
     // Validate sandbox name
     validate_sandbox_name(&params.sandbox)?;
 
-    let project_dir = state.get_config().get_project_dir().clone();
+    match params.idempotency_key.clone() {
+        Some(key) => {
+            let sandbox_key =
+                resolve_sandbox_key(&state, &params.sandbox, params.project.as_deref()).await?;
+            let start_requests = state.get_start_requests().clone();
+            start_requests
+                .run(&sandbox_key, &key, || start_sandbox(state, params))
+                .await
+        }
+        None => start_sandbox(state, params).await,
+    }
+}
+
+/// Starts a sandbox, writing its configuration and assigning its portal port
+pub(crate) async fn start_sandbox(
+    state: AppState,
+    params: SandboxStartParams,
+) -> ServerResult<String> {
+    start_sandbox_with(&state, &params, up_sandboxes).await
+}
+
+/// Starts a sandbox, using `up` to start it once its configuration is written
+///
+/// The portal port assigned for the sandbox is released again if `up` fails.
+async fn start_sandbox_with<F, Fut>(
+    state: &AppState,
+    params: &SandboxStartParams,
+    up: F,
+) -> ServerResult<String>
+where
+    F: FnOnce(Vec<String>, PathBuf, Duration) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<()>>,
+{
+    let project_dir =
+        resolve_project_dir(state, &params.sandbox, params.project.as_deref()).await?;
+    let prepared = prepare_start(state, params, project_dir).await?;
+    let project_dir = prepared.project_dir.clone();
+    let poll_timeout = prepared.poll_timeout;
+    let config_file = MICROSANDBOX_CONFIG_FILENAME;
+    let sandbox = &params.sandbox;
+
+    // Start the sandbox, giving up if the supervisor does not report it running in time
+    match up(vec![sandbox.clone()], project_dir.clone(), poll_timeout).await {
+        Ok(()) => prepared.started(state),
+        Err(e) => {
+            prepared.port_guard.release().await;
+            return Err(start_error(sandbox, prepared.image.as_deref(), e));
+        }
+    }
+
+    // Wait for the sandbox to actually start running with a timeout
+    debug!("Waiting for sandbox {} to start...", sandbox);
+    match timeout(
+        poll_timeout,
+        poll_sandbox_until_running(&params.sandbox, &project_dir, config_file),
+    )
+    .await
+    {
+        Ok(result) => match result {
+            Ok(_) => {
+                debug!("Sandbox {} is now running", sandbox);
+                Ok(format!("Sandbox {} started successfully", params.sandbox))
+            }
+            Err(e) => {
+                // The sandbox was started but polling failed for some reason
+                warn!("Failed to verify sandbox {} is running: {}", sandbox, e);
+                Ok(format!(
+                    "Sandbox {} was started, but couldn't verify it's running: {}",
+                    params.sandbox, e
+                ))
+            }
+        },
+        Err(_) => {
+            // Timeout occurred, but we still return success since the sandbox might still be starting
+            warn!("Timeout waiting for sandbox {} to start", sandbox);
+            Ok(format!(
+                "Sandbox {} was started, but timed out waiting for it to be fully running. It may still be initializing.",
+                params.sandbox
+            ))
+        }
+    }
+}
+
+/// Implementation for starting several sandboxes at once
+///
+/// The configuration of every sandbox is written and its portal port assigned first, then the
+/// sandboxes of each project are started together with [`orchestra::up`], in dependency order.
+/// Each entry gets its own result, so a sandbox that fails validation or doesn't start doesn't fail
+/// the others. Idempotency keys are not supported for batch starts and are ignored.
+pub async fn sandbox_start_batch_impl(
+    state: AppState,
+    params: SandboxStartBatchParams,
+) -> ServerResult<Vec<SandboxStartBatchResult>> {
+    start_sandbox_batch(&state, params, up_sandboxes).await
+}
+
+/// Starts the given sandboxes of a project with [`orchestra::up_with_permits`], in dependency
+/// order.
+///
+/// How many sandboxes start at once is capped across every request by [`START_PERMITS`].
+async fn up_sandboxes(
+    sandboxes: Vec<String>,
+    project_dir: PathBuf,
+    start_timeout: Duration,
+) -> MicrosandboxResult<()> {
+    orchestra::up_with_permits(
+        sandboxes,
+        &[],
+        Some(&project_dir),
+        Some(MICROSANDBOX_CONFIG_FILENAME),
+        true,
+        Some(start_timeout),
+        &START_PERMITS,
+    )
+    .await
+}
+
+/// Prepares a sandbox in the project in `project_dir` for starting: writes its configuration and
+/// assigns its portal port
+async fn prepare_start(
+    state: &AppState,
+    params: &SandboxStartParams,
+    project_dir: PathBuf,
+) -> ServerResult<PreparedStart> {
     let config_file = MICROSANDBOX_CONFIG_FILENAME;
     let config_path = project_dir.join(config_file);
     let sandbox = &params.sandbox;
@@ -393,6 +847,10 @@ pub async fn sandbox_start_impl(
         ));
     }
 
+    if let Some(config) = &params.config {
+        validate_request_envs(&config.envs)?;
+    }
+
     // Load or create the config
     let mut config_yaml: serde_yaml::Value;
 
@@ -405,7 +863,10 @@ pub async fn sandbox_start_impl(
 
         // Parse the config as YAML
         config_yaml = serde_yaml::from_str(&config_content).map_err(|e| {
-            ServerError::InternalError(format!("Failed to parse config file: {}", e))
+            ServerError::ValidationError(crate::error::ValidationError::InvalidConfig(format!(
+                "failed to parse config file: {}",
+                e
+            )))
         })?;
 
         // If we're relying on existing config, verify that the sandbox exists in it
@@ -417,10 +878,7 @@ pub async fn sandbox_start_impl(
 
             if !has_sandbox_config {
                 return Err(ServerError::ValidationError(
-                    crate::error::ValidationError::InvalidInput(format!(
-                        "Sandbox '{}' not found in existing configuration",
-                        sandbox
-                    )),
+                    crate::error::ValidationError::SandboxNotInConfig(sandbox.clone()),
                 ));
             }
         }
@@ -434,15 +892,18 @@ pub async fn sandbox_start_impl(
             ));
         }
 
-        // Create default config
-        tokio_fs::write(&config_path, DEFAULT_CONFIG)
+        // Create default config, from the user's template if there is one
+        let default_config = menv::default_config().await.map_err(|e| {
+            ServerError::InternalError(format!("Failed to read default config: {}", e))
+        })?;
+        tokio_fs::write(&config_path, &default_config)
             .await
             .map_err(|e| {
                 ServerError::InternalError(format!("Failed to create config file: {}", e))
             })?;
 
         // Parse default config
-        config_yaml = serde_yaml::from_str(DEFAULT_CONFIG).map_err(|e| {
+        config_yaml = serde_yaml::from_str(&default_config).map_err(|e| {
             ServerError::InternalError(format!("Failed to parse default config: {}", e))
         })?;
     }
@@ -584,6 +1045,16 @@ pub async fn sandbox_start_impl(
             );
         }
 
+        if let Some(readiness) = &config.readiness {
+            let readiness = serde_yaml::to_value(readiness).map_err(|e| {
+                ServerError::InternalError(format!("Failed to serialize readiness probe: {}", e))
+            })?;
+            sandbox_map.insert(
+                serde_yaml::Value::String("readiness".to_string()),
+                readiness,
+            );
+        }
+
         // Replace or add the sandbox in the config
         sandboxes_map.insert(
             serde_yaml::Value::String(sandbox.clone()),
@@ -591,13 +1062,22 @@ pub async fn sandbox_start_impl(
         );
     }
 
-    // Assign a port for this sandbox
-    let sandbox_key = params.sandbox.clone();
-    let port = {
+    // Assign a port for this sandbox, releasing it again if the start doesn't get through
+    let sandbox_key = state.sandbox_key(&project_dir, sandbox);
+    let (port, port_guard) = {
         let mut port_manager = state.get_port_manager().write().await;
-        port_manager.assign_port(&sandbox_key).await.map_err(|e| {
+        let previous_port = port_manager.get_port(&sandbox_key);
+        let port = port_manager.assign_port(&sandbox_key).await.map_err(|e| {
             ServerError::InternalError(format!("Failed to assign portal port: {}", e))
-        })?
+        })?;
+
+        let port_guard = if previous_port == Some(port) {
+            PortAssignmentGuard::disarmed(state)
+        } else {
+            PortAssignmentGuard::new(state, &sandbox_key)
+        };
+
+        (port, port_guard)
     };
 
     debug!("Assigned portal port {} to sandbox {}", port, sandbox_key);
@@ -606,7 +1086,9 @@ pub async fn sandbox_start_impl(
     let sandbox_config = sandboxes_map
         .get_mut(serde_yaml::Value::String(sandbox.clone()))
         .ok_or_else(|| {
-            ServerError::InternalError(format!("Sandbox '{}' not found in configuration", sandbox))
+            ServerError::ValidationError(crate::error::ValidationError::SandboxNotInConfig(
+                sandbox.clone(),
+            ))
         })?
         .as_mapping_mut()
         .ok_or_else(|| {
@@ -640,25 +1122,28 @@ pub async fn sandbox_start_impl(
         sandbox_config.insert(ports_key, serde_yaml::Value::Sequence(ports_seq));
     }
 
+    let image = sandbox_config
+        .get("image")
+        .and_then(|image| image.as_str())
+        .map(String::from);
+
     // Write the updated config back to the file
     let updated_config = serde_yaml::to_string(&config_yaml)
         .map_err(|e| ServerError::InternalError(format!("Failed to serialize config: {}", e)))?;
 
-    tokio_fs::write(&config_path, updated_config)
+    // Replace the file in one step, so a failed write never leaves the config corrupted
+    config::write_config(&config_path, &updated_config)
         .await
-        .map_err(|e| ServerError::InternalError(format!("Failed to write config file: {}", e)))?;
+        .map_err(|e| ServerError::from_core("Failed to write config file", e))?;
 
-    // Start the sandbox
-    orchestra::up(
-        vec![sandbox.clone()],
-        Some(&project_dir),
-        Some(config_file),
-        true,
-    )
-    .await
-    .map_err(|e| {
-        ServerError::InternalError(format!("Failed to start sandbox {}: {}", params.sandbox, e))
-    })?;
+    // A sandbox restarted without a new configuration keeps its previous idle policy
+    let idle_policy = match &params.config {
+        Some(config) if config.image.is_some() => IdlePolicy {
+            timeout: config.idle_timeout.map(Duration::from_secs),
+            start_on_demand: config.start_on_demand,
+        },
+        _ => state.get_idle_tracker().policy(&sandbox_key),
+    };
 
     // Determine if this is a first-time image pull based on config
     let potentially_first_time_pull = if let Some(config) = &params.config {
@@ -675,36 +1160,166 @@ pub async fn sandbox_start_impl(
         Duration::from_secs(60) // 1 minute for regular starts
     };
 
-    // Wait for the sandbox to actually start running with a timeout
-    debug!("Waiting for sandbox {} to start...", sandbox);
-    match timeout(
+    Ok(PreparedStart {
+        sandbox: params.sandbox.clone(),
+        key: sandbox_key,
+        image,
+        project_dir,
         poll_timeout,
-        poll_sandbox_until_running(&params.sandbox, &project_dir, config_file),
-    )
-    .await
-    {
-        Ok(result) => match result {
-            Ok(_) => {
-                debug!("Sandbox {} is now running", sandbox);
-                Ok(format!("Sandbox {} started successfully", params.sandbox))
+        project: params.project.clone(),
+        idle_policy,
+        port_guard,
+    })
+}
+
+/// Starts the sandboxes of a batch, using `up` to start the sandboxes of each project together
+async fn start_sandbox_batch<F, Fut>(
+    state: &AppState,
+    params: SandboxStartBatchParams,
+    up: F,
+) -> ServerResult<Vec<SandboxStartBatchResult>>
+where
+    F: Fn(Vec<String>, PathBuf, Duration) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<()>>,
+{
+    let mut results: Vec<Option<ServerResult<String>>> =
+        params.sandboxes.iter().map(|_| None).collect();
+
+    // Write the configuration of each sandbox, grouping the prepared ones by project
+    let mut groups: BTreeMap<PathBuf, Vec<(usize, PreparedStart)>> = BTreeMap::new();
+    let mut seen = HashSet::new();
+    for (index, params) in params.sandboxes.iter().enumerate() {
+        let prepared = match validate_sandbox_name(&params.sandbox) {
+            Ok(()) => prepare_batch_entry(state, params, &mut seen).await,
+            Err(e) => Err(e),
+        };
+
+        match prepared {
+            Ok(prepared) => groups
+                .entry(prepared.project_dir.clone())
+                .or_default()
+                .push((index, prepared)),
+            Err(e) => results[index] = Some(Err(e)),
+        }
+    }
+
+    // Start each project's sandboxes together so dependencies are started first
+    for (project_dir, prepared) in groups {
+        let sandboxes: Vec<String> = prepared.iter().map(|(_, p)| p.sandbox.clone()).collect();
+        let start_timeout = prepared
+            .iter()
+            .map(|(_, p)| p.poll_timeout)
+            .max()
+            .unwrap_or_default();
+
+        match up(sandboxes.clone(), project_dir.clone(), start_timeout).await {
+            Ok(()) => {
+                for (index, prepared) in prepared {
+                    results[index] = Some(Ok(format!(
+                        "Sandbox {} started successfully",
+                        prepared.sandbox
+                    )));
+                    prepared.started(state);
+                }
             }
             Err(e) => {
-                // The sandbox was started but polling failed for some reason
-                warn!("Failed to verify sandbox {} is running: {}", sandbox, e);
-                Ok(format!(
-                    "Sandbox {} was started, but couldn't verify it's running: {}",
-                    params.sandbox, e
-                ))
+                // The group start stops at the first failure, so some sandboxes may be running
+                let message = e.to_string();
+                let running: HashSet<String> = orchestra::status(
+                    sandboxes,
+                    &[],
+                    Some(&project_dir),
+                    Some(MICROSANDBOX_CONFIG_FILENAME),
+                )
+                .await
+                .map(|statuses| {
+                    statuses
+                        .into_iter()
+                        .filter(|status| status.running)
+                        .map(|status| status.name)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+                for (index, prepared) in prepared {
+                    results[index] = Some(if running.contains(&prepared.sandbox) {
+                        let result =
+                            Ok(format!("Sandbox {} started successfully", prepared.sandbox));
+                        prepared.started(state);
+                        result
+                    } else {
+                        prepared.port_guard.release().await;
+                        Err(ServerError::InternalError(format!(
+                            "Failed to start sandbox {}: {}",
+                            prepared.sandbox, message
+                        )))
+                    });
+                }
             }
-        },
-        Err(_) => {
-            // Timeout occurred, but we still return success since the sandbox might still be starting
-            warn!("Timeout waiting for sandbox {} to start", sandbox);
-            Ok(format!(
-                "Sandbox {} was started, but timed out waiting for it to be fully running. It may still be initializing.",
+        }
+    }
+
+    Ok(params
+        .sandboxes
+        .iter()
+        .zip(results)
+        .map(|(params, result)| {
+            SandboxStartBatchResult::new(
+                &params.sandbox,
+                result.expect("every batch entry has a result"),
+            )
+        })
+        .collect())
+}
+
+/// Prepares an entry of a batch start, rejecting a sandbox whose key, see [`crate::sandbox_key`],
+/// is already in `seen`
+async fn prepare_batch_entry(
+    state: &AppState,
+    params: &SandboxStartParams,
+    seen: &mut HashSet<String>,
+) -> ServerResult<PreparedStart> {
+    let project_dir =
+        resolve_project_dir(state, &params.sandbox, params.project.as_deref()).await?;
+    if !seen.insert(state.sandbox_key(&project_dir, &params.sandbox)) {
+        return Err(ServerError::ValidationError(
+            crate::error::ValidationError::InvalidInput(format!(
+                "Sandbox '{}' appears more than once in the batch",
                 params.sandbox
+            )),
+        ));
+    }
+
+    prepare_start(state, params, project_dir).await
+}
+
+/// Maps an error from starting a sandbox to the server error reported to the client
+///
+/// A registry that doesn't know the image of the sandbox is reported as the image not being
+/// found, when the image is known.
+fn start_error(sandbox: &str, image: Option<&str>, e: MicrosandboxError) -> ServerError {
+    match e {
+        MicrosandboxError::StartTimeout { sandbox, elapsed } => {
+            ServerError::SandboxStartTimeout(format!(
+                "Sandbox {} did not start within {} seconds and was stopped",
+                sandbox,
+                elapsed.as_secs()
             ))
         }
+        MicrosandboxError::ReadinessTimeout { sandbox, elapsed } => {
+            ServerError::SandboxStartTimeout(format!(
+                "Sandbox {} did not pass its readiness probe within {} seconds",
+                sandbox,
+                elapsed.as_secs()
+            ))
+        }
+        e if e.is_image_not_found() => match image {
+            Some(image) => ServerError::ValidationError(
+                crate::error::ValidationError::ImageNotFound(image.to_string()),
+            ),
+            None => ServerError::from_core(format!("Failed to start sandbox {}", sandbox), e),
+        },
+        e => ServerError::from_core(format!("Failed to start sandbox {}", sandbox), e),
     }
 }
 
@@ -714,40 +1329,211 @@ async fn poll_sandbox_until_running(
     project_dir: &StdPath,
     config_file: &str,
 ) -> ServerResult<()> {
-    const POLL_INTERVAL: Duration = Duration::from_millis(20);
-    const MAX_ATTEMPTS: usize = 2500; // Increased to maintain similar overall timeout period with faster polling
-
-    for attempt in 1..=MAX_ATTEMPTS {
-        // Check if the sandbox is running
-        let statuses = orchestra::status(
-            vec![sandbox_name.to_string()],
-            Some(project_dir),
-            Some(config_file),
-        )
-        .await
-        .map_err(|e| ServerError::InternalError(format!("Failed to get sandbox status: {}", e)))?;
+    let result = poll_with_backoff(
+        || async move {
+            // Check if the sandbox is running
+            let statuses = orchestra::status(
+                vec![sandbox_name.to_string()],
+                &[],
+                Some(project_dir),
+                Some(config_file),
+            )
+            .await
+            .map_err(|e| {
+                ServerError::InternalError(format!("Failed to get sandbox status: {}", e))
+            })?;
 
-        // Find our sandbox in the results
-        if let Some(status) = statuses.iter().find(|s| s.name == sandbox_name)
-            && status.running
-        {
+            // Find our sandbox in the results
+            Ok(statuses
+                .iter()
+                .find(|s| s.name == sandbox_name)
+                .is_some_and(|s| s.running))
+        },
+        POLL_INITIAL_INTERVAL,
+        POLL_MAX_INTERVAL,
+        POLL_TIMEOUT,
+    )
+    .await?;
+
+    match result {
+        Some(attempts) => {
             // Sandbox is running, we're done
             debug!(
                 "Sandbox {} is running (verified on attempt {})",
-                sandbox_name, attempt
+                sandbox_name, attempts
             );
-            return Ok(());
+            Ok(())
         }
+        // If we reach here, we've exceeded our time budget
+        None => Err(ServerError::InternalError(format!(
+            "Exceeded maximum attempts to verify sandbox {} is running",
+            sandbox_name
+        ))),
+    }
+}
+
+/// Repeatedly runs `check` until it reports `true`, sleeping between attempts with an
+/// exponentially growing interval that starts at `initial_interval` and is capped at `max_interval`.
+///
+/// Returns the number of attempts made if the check succeeded within `budget`, or `None` if the
+/// budget ran out first.
+async fn poll_with_backoff<F, Fut>(
+    mut check: F,
+    initial_interval: Duration,
+    max_interval: Duration,
+    budget: Duration,
+) -> ServerResult<Option<usize>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ServerResult<bool>>,
+{
+    let policy = RetryPolicy::builder()
+        .deadline(budget)
+        .initial_backoff(initial_interval)
+        .max_backoff(max_interval)
+        .jitter(0.0)
+        .build();
+
+    // A check that isn't running yet is retried, while one that fails is not
+    let attempts = &AtomicUsize::new(0);
+    let result = runtime::retry_if(
+        &policy,
+        |error: &Option<ServerError>| error.is_none(),
+        || {
+            let running = check();
+            async move {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                match running.await {
+                    Ok(true) => Ok(attempt),
+                    Ok(false) => Err(None),
+                    Err(e) => Err(Some(e)),
+                }
+            }
+        },
+    )
+    .await;
 
-        // Sleep before the next attempt
-        sleep(POLL_INTERVAL).await;
+    match result {
+        Ok(attempts) => Ok(Some(attempts)),
+        Err(None) => Ok(None),
+        Err(Some(e)) => Err(e),
     }
+}
+
+/// Implementation for resizing the memory of a running sandbox
+pub async fn sandbox_resize_impl(
+    state: AppState,
+    params: SandboxResizeParams,
+) -> ServerResult<String> {
+    // Validate sandbox name
+    validate_sandbox_name(&params.sandbox)?;
+
+    let project_dir =
+        resolve_project_dir(&state, &params.sandbox, params.project.as_deref()).await?;
+    let config_file = MICROSANDBOX_CONFIG_FILENAME;
+
+    orchestra::resize(
+        &params.sandbox,
+        params.memory,
+        Some(&project_dir),
+        Some(config_file),
+    )
+    .await
+    .map_err(|e| match e {
+        MicrosandboxError::InvalidMemoryResize { .. } => {
+            ServerError::ValidationError(crate::error::ValidationError::InvalidInput(e.to_string()))
+        }
+        MicrosandboxError::SandboxNotRunning(_) => ServerError::NotFound(e.to_string()),
+        MicrosandboxError::MemoryResizeNotSupported(_) => ServerError::NotSupported(e.to_string()),
+        e => ServerError::from_core(format!("Failed to resize sandbox {}", params.sandbox), e),
+    })?;
 
-    // If we reach here, we've exceeded our attempt limit
-    Err(ServerError::InternalError(format!(
-        "Exceeded maximum attempts to verify sandbox {} is running",
-        sandbox_name
-    )))
+    Ok(format!(
+        "Sandbox {} resized to {} MiB",
+        params.sandbox, params.memory
+    ))
+}
+
+/// Implementation for getting the resource usage history of a sandbox
+///
+/// The history is only kept when the server samples metrics on an interval, so this fails with
+/// `NotSupported` when sampling is disabled.
+pub async fn sandbox_metrics_history_impl(
+    state: &AppState,
+    params: SandboxMetricsHistoryParams,
+) -> ServerResult<SandboxMetricsHistoryResponse> {
+    validate_sandbox_name(&params.sandbox)?;
+
+    let Some(interval) = *state.get_config().get_metrics_history().get_interval() else {
+        return Err(ServerError::NotSupported(
+            "Metrics history is disabled, start the server with --metrics-history-interval \
+             or MSB_METRICS_HISTORY_INTERVAL to enable it"
+                .to_string(),
+        ));
+    };
+
+    let sandbox_key =
+        resolve_sandbox_key(state, &params.sandbox, params.project.as_deref()).await?;
+    let samples = state.get_metrics_history().get(&sandbox_key);
+    Ok(SandboxMetricsHistoryResponse {
+        sandbox: params.sandbox,
+        interval_secs: interval.as_secs(),
+        samples,
+    })
+}
+
+/// Implementation for running a command in a sandbox and waiting for its result
+pub async fn sandbox_exec_impl(
+    state: AppState,
+    params: SandboxExecParams,
+) -> ServerResult<SandboxExecResult> {
+    sandbox_exec_with(params, |request| forward_rpc_to_portal(state, request)).await
+}
+
+/// Runs a command in a sandbox, sending it to the portal with `forward`
+///
+/// The command is run with the portal's `sandbox.command.run` method, whose output lines are
+/// split back into stdout and stderr.
+async fn sandbox_exec_with<F, Fut>(
+    params: SandboxExecParams,
+    forward: F,
+) -> ServerResult<SandboxExecResult>
+where
+    F: FnOnce(JsonRpcRequest) -> Fut,
+    Fut: Future<Output = ServerResult<(StatusCode, Json<JsonRpcResponse>)>>,
+{
+    // Validate sandbox name
+    validate_sandbox_name(&params.sandbox)?;
+
+    let request = JsonRpcRequest::new(
+        "sandbox.command.run".to_string(),
+        json!({
+            "sandbox": params.sandbox,
+            "project": params.project,
+            "command": params.command,
+            "args": params.args,
+            "env": params.env,
+            "cwd": params.cwd,
+            "stdin": params.stdin,
+            "timeout": params.timeout,
+        }),
+        json!(uuid::Uuid::new_v4().to_string()),
+    );
+
+    let (_, Json(response)) = forward(request).await?;
+    if let Some(error) = response.error {
+        return Err(ServerError::InternalError(format!(
+            "Failed to run command in sandbox {}: {}",
+            params.sandbox, error.message
+        )));
+    }
+
+    let result: SandboxCommandRunResult =
+        serde_json::from_value(response.result.unwrap_or_default()).map_err(|e| {
+            ServerError::InternalError(format!("Failed to parse portal command result: {}", e))
+        })?;
+
+    Ok(SandboxExecResult::from_portal(result))
 }
 
 /// Implementation for stopping a sandbox
@@ -755,10 +1541,11 @@ pub async fn sandbox_stop_impl(state: AppState, params: SandboxStopParams) -> Se
     // Validate sandbox name
     validate_sandbox_name(&params.sandbox)?;
 
-    let project_dir = state.get_config().get_project_dir().clone();
+    let project_dir =
+        resolve_project_dir(&state, &params.sandbox, params.project.as_deref()).await?;
     let config_file = MICROSANDBOX_CONFIG_FILENAME;
     let sandbox = &params.sandbox;
-    let sandbox_key = params.sandbox.clone();
+    let sandbox_key = state.sandbox_key(&project_dir, sandbox);
 
     // Verify that the project directory exists
     if !project_dir.exists() {
@@ -778,11 +1565,16 @@ pub async fn sandbox_stop_impl(state: AppState, params: SandboxStopParams) -> Se
     }
 
     // Stop the sandbox using orchestra::down
-    orchestra::down(vec![sandbox.clone()], Some(&project_dir), Some(config_file))
-        .await
-        .map_err(|e| {
-            ServerError::InternalError(format!("Failed to stop sandbox {}: {}", params.sandbox, e))
-        })?;
+    orchestra::down(
+        vec![sandbox.clone()],
+        &[],
+        Some(&project_dir),
+        Some(config_file),
+    )
+    .await
+    .map_err(|e| ServerError::from_core(format!("Failed to stop sandbox {}", params.sandbox), e))?;
+
+    state.get_idle_tracker().stopped(&sandbox_key);
 
     // Release the assigned port
     {
@@ -808,43 +1600,39 @@ pub async fn sandbox_get_metrics_impl(
         validate_sandbox_name(sandbox)?;
     }
 
-    let project_dir = state.get_config().get_project_dir().clone();
+    let projects_dir = state.get_config().get_project_dir().clone();
 
     // Check if the project directory exists
-    if !project_dir.exists() {
+    if !projects_dir.exists() {
         return Err(ServerError::InternalError(format!(
             "Project directory '{}' does not exist",
-            project_dir.display()
+            projects_dir.display()
         )));
     }
 
-    // Get metrics filtered by sandbox name if provided
-    let sandbox_names = if let Some(sandbox) = &params.sandbox {
-        vec![sandbox.clone()]
+    let metrics_error = |e| ServerError::from_core("Error getting metrics", e);
+    let all_statuses = if let Some(sandbox) = &params.sandbox {
+        // Get metrics of the sandbox from the project it belongs to
+        let project_dir = resolve_project_dir(&state, sandbox, params.project.as_deref()).await?;
+        let project = state.project_name(&project_dir);
+        metrics::project_statuses(&project, &project_dir, vec![sandbox.clone()])
+            .await
+            .map_err(metrics_error)?
     } else {
-        vec![]
-    };
-
-    let mut all_statuses = Vec::new();
-
-    match orchestra::status(sandbox_names, Some(&project_dir), None).await {
-        Ok(statuses) => {
-            for status in statuses {
-                all_statuses.push(SandboxStatus {
-                    name: status.name,
-                    running: status.running,
-                    cpu_usage: status.cpu_usage,
-                    memory_usage: status.memory_usage,
-                    disk_usage: status.disk_usage,
-                });
-            }
-        }
-        Err(e) => {
-            return Err(ServerError::InternalError(format!(
-                "Error getting metrics: {e}"
-            )));
+        // Get metrics of every sandbox of every project
+        let mut all_statuses = Vec::new();
+        for (project, project_dir) in orchestra::list_projects(&projects_dir)
+            .await
+            .map_err(metrics_error)?
+        {
+            all_statuses.extend(
+                metrics::project_statuses(&project, &project_dir, vec![])
+                    .await
+                    .map_err(metrics_error)?,
+            );
         }
-    }
+        all_statuses
+    };
 
     Ok(SandboxStatusResponse {
         sandboxes: all_statuses,
@@ -855,39 +1643,52 @@ pub async fn sandbox_get_metrics_impl(
 // Functions: Proxy Handlers
 //--------------------------------------------------------------------------------------------------
 
-/// Handler for proxy requests
-pub async fn proxy_request(
-    State(_state): State<AppState>,
-    Path((sandbox, path)): Path<(String, PathBuf)>,
+/// Handles a proxy request, using `start` to start the sandbox first if it is stopped and starts
+/// on demand
+///
+/// The request is forwarded to the first port the sandbox publishes on the host, see
+/// [`middleware::proxy_uri`]. Requests arriving during the start share it through the start
+/// request cache, the same way `sandbox.start` requests sharing an idempotency key do. The router
+/// mounts this with [`start_sandbox`], see [`crate::route::proxy_routes`].
+pub(crate) async fn proxy_request_with<F, Fut>(
+    state: AppState,
+    sandbox: String,
     req: Request<Body>,
-) -> ServerResult<impl IntoResponse> {
-    // In a real implementation, this would use the middleware::proxy_uri function
-    // to determine the target URI and then forward the request
-
-    let path_str = path.display().to_string();
-
-    // Calculate target URI using our middleware function
-    let original_uri = req.uri().clone();
-    let _target_uri = middleware::proxy_uri(original_uri, &sandbox);
-
-    // In a production system, this handler would forward the request to the target URI
-    // For now, we'll just return information about what would be proxied
+    start: F,
+) -> ServerResult<Response>
+where
+    F: FnOnce(AppState, SandboxStartParams) -> Fut,
+    Fut: Future<Output = ServerResult<String>>,
+{
+    validate_sandbox_name(&sandbox)?;
+    let project_dir = resolve_project_dir(&state, &sandbox, None).await?;
+    let sandbox_key = state.sandbox_key(&project_dir, &sandbox);
+
+    // Checked before the sandbox is started, so one that can't be proxied to isn't started
+    let port = proxy_port(&project_dir, &sandbox).await?;
+    let target_uri = middleware::proxy_uri(req.uri(), &sandbox, port)?;
+
+    if let Some(cold_start) = state.get_idle_tracker().cold_start(&sandbox_key) {
+        debug!("Starting sandbox {} on demand", sandbox);
+        let params = SandboxStartParams {
+            sandbox: sandbox.clone(),
+            config: None,
+            idempotency_key: Some(cold_start.idempotency_key.clone()),
+            project: cold_start.project,
+        };
 
-    let response = format!(
-        "Axum Proxy Request\n\nSandbox: {}\nPath: {}\nMethod: {}\nHeaders: {:?}",
-        sandbox,
-        path_str,
-        req.method(),
-        req.headers()
-    );
+        let start_requests = state.get_start_requests().clone();
+        start_requests
+            .run(&sandbox_key, &cold_start.idempotency_key, || {
+                start(state.clone(), params)
+            })
+            .await?;
+    }
 
-    let result = Response::builder()
-        .status(StatusCode::OK)
-        .header("Content-Type", "text/plain")
-        .body(Body::from(response))
-        .unwrap();
+    // Proxied requests count as activity on the sandbox
+    state.get_idle_tracker().touch(&sandbox_key, Instant::now());
 
-    Ok(result)
+    forward_proxy_request(target_uri, req).await
 }
 
 /// Fallback handler for proxy requests
@@ -899,6 +1700,218 @@ pub async fn proxy_fallback() -> ServerResult<impl IntoResponse> {
 // Functions: Helpers
 //--------------------------------------------------------------------------------------------------
 
+/// Returns the first port a sandbox publishes on the host, which proxied requests are sent to
+async fn proxy_port(project_dir: &StdPath, sandbox: &str) -> ServerResult<u16> {
+    let (config, _, _) = config::load_config(Some(project_dir), Some(MICROSANDBOX_CONFIG_FILENAME))
+        .await
+        .map_err(|e| ServerError::from_core("Failed to load config", e))?;
+
+    let sandbox_config = config.get_sandbox(sandbox).ok_or_else(|| {
+        ServerError::ValidationError(crate::error::ValidationError::SandboxNotInConfig(
+            sandbox.to_string(),
+        ))
+    })?;
+
+    sandbox_config
+        .get_ports()
+        .first()
+        .map(|port| port.get_host())
+        .ok_or_else(|| {
+            ServerError::NotSupported(format!(
+                "Sandbox {} publishes no ports to proxy requests to",
+                sandbox
+            ))
+        })
+}
+
+/// Forwards a proxied request to `target_uri` and returns the sandbox's response, streaming both
+/// bodies
+///
+/// Hop-by-hop headers aren't forwarded in either direction, and neither are the request's
+/// credentials for this server. A client that authenticates with `Proxy-Authorization` keeps
+/// `Authorization` for the sandbox.
+async fn forward_proxy_request(target_uri: Uri, req: Request<Body>) -> ServerResult<Response> {
+    let (parts, body) = req.into_parts();
+    let mut headers = parts.headers;
+    if !headers.contains_key(PROXY_AUTH_HEADER) {
+        headers.remove(AUTHORIZATION);
+    }
+    remove_hop_by_hop_headers(&mut headers);
+    headers.remove(HOST);
+
+    let response = PROXY_CLIENT
+        .request(parts.method, target_uri.to_string())
+        .headers(headers)
+        .body(reqwest::Body::wrap_stream(body.into_data_stream()))
+        .send()
+        .await
+        .map_err(|e| ServerError::BadGateway(format!("Failed to reach sandbox: {}", e)))?;
+
+    let status = response.status();
+    let mut headers = response.headers().clone();
+    remove_hop_by_hop_headers(&mut headers);
+
+    let mut proxied = Response::new(Body::from_stream(response.bytes_stream()));
+    *proxied.status_mut() = status;
+    *proxied.headers_mut() = headers;
+
+    Ok(proxied)
+}
+
+/// Removes the headers that only apply to a single connection, including those the `Connection`
+/// header names
+fn remove_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let named: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect();
+
+    for name in named {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+}
+
+/// Writes a chunk of a streamed upload to a file in the sandbox
+///
+/// ## Returns
+///
+/// The portal's JSON-RPC error response if it failed to write the chunk, which is relayed to the
+/// client as is
+async fn write_upload_chunk(
+    state: &AppState,
+    target: &UploadTarget<'_>,
+    offset: u64,
+    data: &[u8],
+) -> ServerResult<Option<Response>> {
+    let request = JsonRpcRequest {
+        jsonrpc: JSONRPC_VERSION.to_string(),
+        method: "sandbox.fs.write".to_string(),
+        params: json!({
+            "sandbox": target.sandbox,
+            "project": target.query.project,
+            "path": target.query.path,
+            "offset": offset,
+            "data": BASE64.encode(data),
+            "mode": target.query.mode,
+        }),
+        id: Some(json!(offset)),
+    };
+
+    let (status, Json(response)) =
+        forward_rpc_to_sandbox_portal(state, target.sandbox_key, request).await?;
+    if response.error.is_some() {
+        return Ok(Some((status, Json(response)).into_response()));
+    }
+
+    Ok(None)
+}
+
+/// Releases the portal port assigned to a sandbox whose start failed
+async fn release_assigned_port(state: &AppState, sandbox: &str) {
+    let mut port_manager = state.get_port_manager().write().await;
+    match port_manager.release_port(sandbox).await {
+        Ok(()) => debug!(
+            "Released portal port of sandbox {} after failed start",
+            sandbox
+        ),
+        Err(e) => warn!(
+            "Failed to release portal port of sandbox {}: {}",
+            sandbox, e
+        ),
+    }
+}
+
+/// Runs the shallow health checks for the virtualization backend and the sandbox database.
+///
+/// ## Arguments
+///
+/// * `pool` - The pool of the sandbox database, or the error it could not be created with
+/// * `probe_backend` - Checks whether the virtualization backend is usable
+async fn check_health(
+    pool: MicrosandboxResult<&Pool<Sqlite>>,
+    probe_backend: impl FnOnce() -> MicrosandboxResult<()>,
+) -> HealthResponse {
+    let backend = ComponentHealth::from_result("backend", probe_backend());
+
+    let database = match pool {
+        Ok(pool) => db::ping(pool).await,
+        Err(e) => Err(e),
+    };
+    let database = ComponentHealth::from_result("database", database);
+
+    if !backend.healthy || !database.healthy {
+        warn!(?backend, ?database, "health check failed");
+    }
+
+    HealthResponse::new(vec![backend, database])
+}
+
+/// Resolves the project directory a sandbox operation applies to
+///
+/// Names defined in more than one project under the server's project directory must be qualified
+/// with a project, otherwise the request is rejected with the candidate projects.
+async fn resolve_project_dir(
+    state: &AppState,
+    sandbox: &str,
+    project: Option<&str>,
+) -> ServerResult<PathBuf> {
+    let projects_dir = state.get_config().get_project_dir();
+
+    orchestra::resolve_sandbox_project(sandbox, projects_dir, project)
+        .await
+        .map_err(|e| match e {
+            MicrosandboxError::AmbiguousSandbox { .. }
+            | MicrosandboxError::InvalidPathComponent(_)
+            | MicrosandboxError::EmptyPathSegment => ServerError::ValidationError(
+                crate::error::ValidationError::InvalidInput(e.to_string()),
+            ),
+            e => ServerError::from_core(
+                format!("Failed to resolve project for sandbox {}", sandbox),
+                e,
+            ),
+        })
+}
+
+/// Resolves the key a sandbox's portal port, idle timer and start requests are tracked under,
+/// see [`crate::sandbox_key`]
+async fn resolve_sandbox_key(
+    state: &AppState,
+    sandbox: &str,
+    project: Option<&str>,
+) -> ServerResult<String> {
+    let project_dir = resolve_project_dir(state, sandbox, project).await?;
+    Ok(state.sandbox_key(&project_dir, sandbox))
+}
+
+/// Validates the environment variables of a start request
+///
+/// A variable without a value would inherit its value from the server's own environment, which
+/// would let any client read the server's secrets, so every variable must have a value.
+fn validate_request_envs(envs: &[String]) -> ServerResult<()> {
+    for env in envs {
+        let env = env.parse::<EnvPair>().map_err(|e| {
+            ServerError::ValidationError(crate::error::ValidationError::InvalidInput(e.to_string()))
+        })?;
+
+        if env.is_inherited() {
+            return Err(ServerError::ValidationError(
+                crate::error::ValidationError::InvalidInput(format!(
+                    "environment variable {} has no value; variables cannot be inherited from the server's environment",
+                    env.get_name()
+                )),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
 /// Validates a sandbox name
 fn validate_sandbox_name(name: &str) -> ServerResult<()> {
     // Check name length
@@ -941,3 +1954,374 @@ fn validate_sandbox_name(name: &str) -> ServerResult<()> {
 
     Ok(())
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use microsandbox_core::MicrosandboxError;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::mocks::test_state;
+
+    #[tokio::test]
+    async fn test_health_reports_healthy() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let pool = db::get_or_create_pool(
+            &project_dir.path().join("sandbox.db"),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await
+        .unwrap();
+
+        let report = check_health(Ok(&pool), || Ok(())).await;
+        assert!(report.is_healthy());
+        assert_eq!(report.status, "healthy");
+        assert_eq!(report.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_unreachable_db() {
+        // A closed pool can't reach the db
+        let project_dir = tempfile::tempdir().unwrap();
+        let pool = db::get_or_create_pool(
+            &project_dir.path().join("sandbox.db"),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await
+        .unwrap();
+        pool.close().await;
+
+        let report = check_health(Ok(&pool), || Ok(())).await;
+        assert!(!report.is_healthy());
+        assert_eq!(report.status, "unhealthy");
+
+        let database = report
+            .components
+            .iter()
+            .find(|c| c.name == "database")
+            .unwrap();
+        assert!(!database.healthy);
+        assert!(database.error.is_some());
+        assert!(
+            report
+                .components
+                .iter()
+                .any(|c| c.name == "backend" && c.healthy)
+        );
+
+        assert_eq!(
+            report.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_reports_unavailable_backend() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let pool = db::get_or_create_pool(
+            &project_dir.path().join("sandbox.db"),
+            &db::SANDBOX_DB_MIGRATOR,
+        )
+        .await
+        .unwrap();
+
+        let report = check_health(Ok(&pool), || {
+            Err(MicrosandboxError::BackendUnavailable {
+                reason: "no kvm".to_string(),
+                remediation: "enable kvm".to_string(),
+            })
+        })
+        .await;
+        assert!(!report.is_healthy());
+        assert_eq!(
+            report.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+    }
+
+    #[tokio::test]
+    async fn test_start_batch_reports_mixed_results() -> anyhow::Result<()> {
+        let project_dir = tempfile::tempdir()?;
+        let state = test_state(project_dir.path()).await;
+
+        let params: SandboxStartBatchParams = serde_json::from_value(json!({
+            "sandboxes": [
+                { "sandbox": "web", "config": { "image": "nginx:latest" } },
+                { "sandbox": "bad name!", "config": { "image": "nginx:latest" } },
+            ]
+        }))?;
+
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let results = start_sandbox_batch(&state, params, |sandboxes, _, _| {
+            let started = started.clone();
+            async move {
+                started.lock().unwrap().extend(sandboxes);
+                Ok(())
+            }
+        })
+        .await?;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].sandbox, "web");
+        assert!(results[0].started);
+        assert!(results[0].error.is_none());
+        assert_eq!(results[1].sandbox, "bad name!");
+        assert!(!results[1].started);
+        assert!(results[1].error.is_some());
+
+        // Only the valid sandbox is started and gets a portal port
+        assert_eq!(*started.lock().unwrap(), vec!["web"]);
+        let port_manager = state.get_port_manager().read().await;
+        assert_eq!(port_manager.sandbox_keys(), vec!["web"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_batch_keeps_projects_apart() -> anyhow::Result<()> {
+        let project_dir = tempfile::tempdir()?;
+        let state = test_state(project_dir.path()).await;
+
+        let params: SandboxStartBatchParams = serde_json::from_value(json!({
+            "sandboxes": [
+                { "sandbox": "web", "project": "shop", "config": { "image": "nginx:latest" } },
+                { "sandbox": "web", "project": "blog", "config": { "image": "nginx:latest" } },
+                { "sandbox": "web", "project": "shop", "config": { "image": "nginx:latest" } },
+            ]
+        }))?;
+
+        let results = start_sandbox_batch(&state, params, |_, _, _| async { Ok(()) }).await?;
+
+        // The same name in two projects is two sandboxes, but only once per project
+        assert!(results[0].started);
+        assert!(results[1].started);
+        assert!(!results[2].started);
+
+        // Each gets its own portal port
+        let port_manager = state.get_port_manager().read().await;
+        let mut keys = port_manager.sandbox_keys();
+        keys.sort();
+        assert_eq!(keys, vec!["blog/web", "shop/web"]);
+        assert_ne!(
+            port_manager.get_port("blog/web"),
+            port_manager.get_port("shop/web")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_start_releases_port_when_up_fails() -> anyhow::Result<()> {
+        let project_dir = tempfile::tempdir()?;
+        let state = test_state(project_dir.path()).await;
+
+        let params: SandboxStartParams = serde_json::from_value(json!({
+            "sandbox": "web",
+            "config": { "image": "nginx:latest" }
+        }))?;
+
+        let assigned = Arc::new(Mutex::new(None));
+        let result = start_sandbox_with(&state, &params, |_, _, _| {
+            let state = state.clone();
+            let assigned = assigned.clone();
+            async move {
+                let port_manager = state.get_port_manager().read().await;
+                *assigned.lock().unwrap() = port_manager.get_port("web");
+                Err(MicrosandboxError::SupervisorError(
+                    "sandbox exited early".to_string(),
+                ))
+            }
+        })
+        .await;
+
+        // The port was assigned while starting, and released once the start failed
+        assert!(result.is_err());
+        assert!(assigned.lock().unwrap().is_some());
+        let port_manager = state.get_port_manager().read().await;
+        assert!(port_manager.sandbox_keys().is_empty());
+        drop(port_manager);
+
+        // Failing to start a sandbox that already has a port leaves that port alone
+        let port = state
+            .get_port_manager()
+            .write()
+            .await
+            .assign_port("web")
+            .await?;
+        let result = start_sandbox_with(&state, &params, |_, _, _| async {
+            Err(MicrosandboxError::SupervisorError(
+                "sandbox exited early".to_string(),
+            ))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(
+            state.get_port_manager().read().await.get_port("web"),
+            Some(port)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_exec_forwards_command_to_portal() -> anyhow::Result<()> {
+        let params: SandboxExecParams = serde_json::from_value(json!({
+            "sandbox": "web",
+            "command": "sh",
+            "args": ["-c", "cat; echo oops >&2; exit 3"],
+            "env": { "GREETING": "hello" },
+            "cwd": "/app",
+            "stdin": "hello\n",
+            "timeout": 10
+        }))?;
+
+        // The mock portal records the request and answers like the real one does
+        let forwarded = Arc::new(Mutex::new(None));
+        let result = sandbox_exec_with(params, |request| {
+            let forwarded = forwarded.clone();
+            async move {
+                *forwarded.lock().unwrap() = Some(request);
+                let result = json!({
+                    "command": "sh",
+                    "exit_code": 3,
+                    "success": false,
+                    "output": [
+                        { "stream": "stdout", "text": "hello" },
+                        { "stream": "stderr", "text": "oops" }
+                    ],
+                    "truncated": false,
+                    "total_bytes": 9
+                });
+                Ok((
+                    StatusCode::OK,
+                    Json(JsonRpcResponse::success(result, Some(json!("1")))),
+                ))
+            }
+        })
+        .await?;
+
+        let request = forwarded.lock().unwrap().take().unwrap();
+        assert_eq!(request.method, "sandbox.command.run");
+        assert_eq!(
+            request.params,
+            json!({
+                "sandbox": "web",
+                "command": "sh",
+                "args": ["-c", "cat; echo oops >&2; exit 3"],
+                "env": { "GREETING": "hello" },
+                "cwd": "/app",
+                "stdin": "hello\n",
+                "timeout": 10
+            })
+        );
+
+        assert_eq!(
+            serde_json::to_value(&result)?,
+            json!({
+                "exit_code": 3,
+                "success": false,
+                "stdout": "hello\n",
+                "stderr": "oops\n",
+                "truncated": false,
+                "total_bytes": 9
+            })
+        );
+
+        // Invalid sandbox names never reach the portal
+        let params: SandboxExecParams =
+            serde_json::from_value(json!({ "sandbox": "-web", "command": "ls" }))?;
+        let result = sandbox_exec_with(params, |_| async {
+            Err(ServerError::InternalError(
+                "the portal was reached".to_string(),
+            ))
+        })
+        .await;
+        assert!(matches!(result, Err(ServerError::ValidationError(_))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_request_envs_rejects_inherited() {
+        assert!(
+            validate_request_envs(&["PATH=/usr/bin".to_string(), "EMPTY=".to_string()]).is_ok()
+        );
+        assert!(matches!(
+            validate_request_envs(&["AWS_SECRET_ACCESS_KEY".to_string()]),
+            Err(ServerError::ValidationError(
+                crate::error::ValidationError::InvalidInput(_)
+            ))
+        ));
+        assert!(validate_request_envs(&["=value".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_start_error_reports_missing_image() {
+        let registry_404 = || MicrosandboxError::RegistryResponse {
+            status: 404,
+            body_snippet: String::new(),
+            hint: "the image or tag does not exist".to_string(),
+        };
+
+        assert!(matches!(
+            start_error("web", Some("nginx:nope"), registry_404()),
+            ServerError::ValidationError(crate::error::ValidationError::ImageNotFound(image))
+                if image == "nginx:nope"
+        ));
+
+        // Without a known image, the image is still reported as missing
+        assert!(matches!(
+            start_error("web", None, registry_404()),
+            ServerError::ValidationError(crate::error::ValidationError::ImageNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_poll_with_backoff_detects_running_with_few_polls() {
+        let polls = &AtomicUsize::new(0);
+        let started = Instant::now();
+
+        // Fake status source that flips to running after 1.5s
+        let result = poll_with_backoff(
+            || async move {
+                polls.fetch_add(1, Ordering::SeqCst);
+                Ok(started.elapsed() >= Duration::from_millis(1500))
+            },
+            POLL_INITIAL_INTERVAL,
+            POLL_MAX_INTERVAL,
+            POLL_TIMEOUT,
+        )
+        .await
+        .unwrap();
+
+        let polls = polls.load(Ordering::SeqCst);
+        assert_eq!(result, Some(polls));
+        assert!(
+            polls < 15,
+            "expected far fewer than 2500 polls, got {polls}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_poll_with_backoff_gives_up_after_budget() {
+        let result = poll_with_backoff(
+            || async { Ok(false) },
+            Duration::from_millis(5),
+            Duration::from_millis(20),
+            Duration::from_millis(100),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result, None);
+    }
+}
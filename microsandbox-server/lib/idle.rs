@@ -0,0 +1,560 @@
+//! Idle timeouts and on-demand starts for sandboxes started through the microsandbox server.
+//!
+//! This module handles:
+//! - Tracking the last activity of each sandbox with an idle policy
+//! - Stopping sandboxes that have had no activity for longer than their timeout
+//! - Finding stopped sandboxes that should be started when a request arrives for them
+//! - Persisting idle policies to disk, so they survive a server restart
+//!
+//! The module provides:
+//! - A thread-safe tracker of sandbox activity
+//! - The background task that stops idle sandboxes and releases their ports
+//!
+//! Sandboxes are tracked by their key, see [`sandbox_key`], so sandboxes sharing a name in
+//! different projects have their own timers.
+//!
+//! A sandbox counts as active whenever an RPC or proxied request names it. Its idle policy is
+//! remembered after it is stopped, so it applies again when the sandbox is restarted. Policies
+//! loaded after a server restart start out stopped: the first request for a sandbox that starts
+//! on demand starts it, which leaves a sandbox that is still running as it is.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use microsandbox_core::{MicrosandboxResult, management::orchestra};
+use microsandbox_utils::{IDLE_POLICIES_FILE, MICROSANDBOX_CONFIG_FILENAME};
+use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+
+use crate::state::{AppState, sandbox_key, split_sandbox_key};
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How often sandboxes are checked for having gone idle
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// How a sandbox is scaled down when idle and back up on demand
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IdlePolicy {
+    /// How long the sandbox can go without activity before it is stopped
+    pub timeout: Option<Duration>,
+
+    /// Whether a proxied request for the stopped sandbox starts it again
+    pub start_on_demand: bool,
+}
+
+/// A stopped sandbox to start because a request arrived for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColdStart {
+    /// The project the sandbox was started in, if one was named
+    pub project: Option<String>,
+
+    /// The idempotency key shared by every request arriving while the sandbox is stopped
+    pub idempotency_key: String,
+}
+
+/// Tracks the last activity of the sandboxes that have an idle policy
+#[derive(Debug, Default)]
+pub struct IdleTracker {
+    /// The sandboxes with an idle policy, keyed by sandbox key
+    sandboxes: Mutex<HashMap<String, IdleSandbox>>,
+
+    /// The file the idle policies are persisted to, if any
+    file_path: Option<PathBuf>,
+}
+
+/// A sandbox with an idle policy
+#[derive(Debug)]
+struct IdleSandbox {
+    /// How the sandbox is scaled down and back up
+    policy: IdlePolicy,
+
+    /// The project the sandbox was started in, if one was named
+    project: Option<String>,
+
+    /// The project directory the sandbox belongs to
+    project_dir: PathBuf,
+
+    /// When the sandbox was last active, or `None` while it isn't running
+    last_activity: Option<Instant>,
+
+    /// How many times the sandbox has been stopped
+    stops: u64,
+}
+
+/// The idle policy of a sandbox as stored in the idle policies file
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredIdlePolicy {
+    /// Seconds the sandbox can go without activity before it is stopped
+    idle_timeout: Option<u64>,
+
+    /// Whether a proxied request for the stopped sandbox starts it again
+    start_on_demand: bool,
+
+    /// The project the sandbox was started in, if one was named
+    project: Option<String>,
+
+    /// The project directory the sandbox belongs to
+    project_dir: PathBuf,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl IdleTracker {
+    /// Create a tracker that persists idle policies in the given project directory, loading the
+    /// ones a previous server left there
+    ///
+    /// The loaded sandboxes count as stopped until they are started again. A file that can't be
+    /// read is logged and ignored.
+    pub fn load(project_dir: impl AsRef<Path>) -> Self {
+        let file_path = project_dir.as_ref().join(IDLE_POLICIES_FILE);
+        let stored = match std::fs::read_to_string(&file_path) {
+            Ok(contents) => serde_json::from_str::<HashMap<String, StoredIdlePolicy>>(&contents)
+                .inspect_err(|e| tracing::warn!("ignoring invalid idle policies file: {}", e))
+                .unwrap_or_default(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                tracing::warn!("failed to read idle policies file: {}", e);
+                HashMap::new()
+            }
+        };
+
+        let sandboxes = stored
+            .into_iter()
+            .map(|(sandbox, stored)| {
+                let idle = IdleSandbox {
+                    policy: IdlePolicy {
+                        timeout: stored.idle_timeout.map(Duration::from_secs),
+                        start_on_demand: stored.start_on_demand,
+                    },
+                    project: stored.project,
+                    project_dir: stored.project_dir,
+                    last_activity: None,
+                    stops: 0,
+                };
+                (sandbox, idle)
+            })
+            .collect();
+
+        Self {
+            sandboxes: Mutex::new(sandboxes),
+            file_path: Some(file_path),
+        }
+    }
+
+    /// Returns the idle policy of a sandbox, or the default policy if it has none
+    pub fn policy(&self, sandbox_key: &str) -> IdlePolicy {
+        self.sandboxes
+            .lock()
+            .unwrap()
+            .get(sandbox_key)
+            .map(|idle| idle.policy.clone())
+            .unwrap_or_default()
+    }
+
+    /// Start tracking a sandbox that started at `now`
+    ///
+    /// A sandbox with the default policy, which never stops or starts it, is no longer tracked.
+    pub fn started(
+        &self,
+        sandbox_key: &str,
+        project: Option<&str>,
+        project_dir: &Path,
+        policy: IdlePolicy,
+        now: Instant,
+    ) {
+        let mut sandboxes = self.sandboxes.lock().unwrap();
+        if policy == IdlePolicy::default() {
+            if sandboxes.remove(sandbox_key).is_some() {
+                self.save(&sandboxes);
+            }
+            return;
+        }
+
+        let stops = sandboxes.get(sandbox_key).map_or(0, |idle| idle.stops);
+        sandboxes.insert(
+            sandbox_key.to_string(),
+            IdleSandbox {
+                policy,
+                project: project.map(str::to_string),
+                project_dir: project_dir.to_path_buf(),
+                last_activity: Some(now),
+                stops,
+            },
+        );
+        self.save(&sandboxes);
+    }
+
+    /// Record activity on a sandbox at `now`, resetting its idle timer
+    ///
+    /// Sandboxes without an idle timeout, or that aren't running, are ignored.
+    pub fn touch(&self, sandbox_key: &str, now: Instant) {
+        if let Some(idle) = self.sandboxes.lock().unwrap().get_mut(sandbox_key)
+            && let Some(last_activity) = idle.last_activity.as_mut()
+        {
+            *last_activity = now.max(*last_activity);
+        }
+    }
+
+    /// Record activity at `now` on the sandbox a request names, resetting its idle timer
+    ///
+    /// Without a project, the sandbox is looked up by name among the tracked sandboxes, and
+    /// nothing is recorded when sandboxes of that name are tracked in more than one project, as
+    /// such a request is rejected.
+    pub fn touch_named(&self, sandbox: &str, project: Option<&str>, now: Instant) {
+        let key = match project {
+            Some(project) => sandbox_key(project, sandbox),
+            None => {
+                let sandboxes = self.sandboxes.lock().unwrap();
+                let mut keys = sandboxes
+                    .keys()
+                    .filter(|key| split_sandbox_key(key).1 == sandbox);
+                match (keys.next(), keys.next()) {
+                    (Some(key), None) => key.clone(),
+                    _ => return,
+                }
+            }
+        };
+
+        self.touch(&key, now);
+    }
+
+    /// Stop the idle timer of a sandbox that was stopped, keeping its policy for a restart
+    pub fn stopped(&self, sandbox_key: &str) {
+        if let Some(idle) = self.sandboxes.lock().unwrap().get_mut(sandbox_key) {
+            idle.stop();
+        }
+    }
+
+    /// Returns how to start a sandbox a request arrived for, if it is stopped and starts on
+    /// demand
+    ///
+    /// Requests arriving while the sandbox is stopped share an idempotency key, so that they
+    /// start it only once.
+    pub fn cold_start(&self, sandbox_key: &str) -> Option<ColdStart> {
+        let sandboxes = self.sandboxes.lock().unwrap();
+        let idle = sandboxes.get(sandbox_key)?;
+        if !idle.policy.start_on_demand || idle.last_activity.is_some() {
+            return None;
+        }
+
+        Some(ColdStart {
+            project: idle.project.clone(),
+            idempotency_key: format!("on-demand-{}", idle.stops),
+        })
+    }
+
+    /// Returns the keys of the sandboxes that have been idle for longer than their timeout at
+    /// `now`, along with their project directories
+    ///
+    /// The returned sandboxes are marked as stopped.
+    pub fn take_idle(&self, now: Instant) -> Vec<(String, PathBuf)> {
+        let mut sandboxes = self.sandboxes.lock().unwrap();
+        let mut idle_sandboxes = Vec::new();
+        for (sandbox, idle) in sandboxes.iter_mut() {
+            if let Some(last_activity) = idle.last_activity
+                && let Some(timeout) = idle.policy.timeout
+                && now.saturating_duration_since(last_activity) >= timeout
+            {
+                idle.stop();
+                idle_sandboxes.push((sandbox.clone(), idle.project_dir.clone()));
+            }
+        }
+
+        idle_sandboxes.sort();
+        idle_sandboxes
+    }
+
+    /// Write the idle policies to the idle policies file, if the tracker has one
+    ///
+    /// Failures are logged, as the policies still apply for as long as the server runs.
+    fn save(&self, sandboxes: &HashMap<String, IdleSandbox>) {
+        let Some(file_path) = &self.file_path else {
+            return;
+        };
+
+        let stored = sandboxes
+            .iter()
+            .map(|(sandbox, idle)| {
+                let stored = StoredIdlePolicy {
+                    idle_timeout: idle.policy.timeout.map(|timeout| timeout.as_secs()),
+                    start_on_demand: idle.policy.start_on_demand,
+                    project: idle.project.clone(),
+                    project_dir: idle.project_dir.clone(),
+                };
+                (sandbox, stored)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let result = serde_json::to_string_pretty(&stored)
+            .map_err(std::io::Error::other)
+            .and_then(|contents| {
+                if let Some(parent) = file_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(file_path, contents)
+            });
+        if let Err(e) = result {
+            tracing::warn!("failed to write idle policies file: {}", e);
+        }
+    }
+}
+
+impl IdleSandbox {
+    /// Marks the sandbox as stopped
+    fn stop(&mut self) {
+        if self.last_activity.take().is_some() {
+            self.stops += 1;
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Stop sandboxes that have gone idle, for as long as the server runs
+pub async fn stop_idle_sandboxes(state: AppState) {
+    let mut ticker = tokio::time::interval(IDLE_CHECK_INTERVAL);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+        stop_idle_sandboxes_with(&state, Instant::now(), |sandbox, project_dir| async move {
+            orchestra::down(
+                vec![sandbox],
+                &[],
+                Some(&project_dir),
+                Some(MICROSANDBOX_CONFIG_FILENAME),
+            )
+            .await
+        })
+        .await;
+    }
+}
+
+/// Stop the sandboxes that are idle at `now` using `down`, releasing their portal ports
+///
+/// Returns the keys of the sandboxes that were stopped.
+async fn stop_idle_sandboxes_with<F, Fut>(state: &AppState, now: Instant, down: F) -> Vec<String>
+where
+    F: Fn(String, PathBuf) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<()>>,
+{
+    let mut stopped = Vec::new();
+    for (key, project_dir) in state.get_idle_tracker().take_idle(now) {
+        tracing::info!("stopping sandbox {} after its idle timeout", key);
+        let (_, sandbox) = split_sandbox_key(&key);
+        if let Err(e) = down(sandbox.to_string(), project_dir).await {
+            tracing::warn!("failed to stop idle sandbox {}: {}", key, e);
+            continue;
+        }
+
+        let mut port_manager = state.get_port_manager().write().await;
+        if let Err(e) = port_manager.release_port(&key).await {
+            tracing::warn!("failed to release portal port of sandbox {}: {}", key, e);
+        }
+
+        stopped.push(key);
+    }
+
+    stopped
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::mocks::test_state;
+
+    const TIMEOUT: Duration = Duration::from_secs(60);
+
+    fn timeout_policy() -> IdlePolicy {
+        IdlePolicy {
+            timeout: Some(TIMEOUT),
+            start_on_demand: false,
+        }
+    }
+
+    #[test]
+    fn test_idle_tracker_activity_resets_timer() {
+        let tracker = IdleTracker::default();
+        let start = Instant::now();
+        tracker.started("web", None, Path::new("/project"), timeout_policy(), start);
+
+        // Activity just before the timeout pushes it back
+        tracker.touch("web", start + Duration::from_secs(50));
+        assert!(tracker.take_idle(start + TIMEOUT).is_empty());
+        assert!(
+            tracker
+                .take_idle(start + Duration::from_secs(109))
+                .is_empty()
+        );
+
+        // Without further activity the sandbox goes idle, and is only reported once
+        let idle = tracker.take_idle(start + Duration::from_secs(110));
+        assert_eq!(idle, vec![("web".to_string(), PathBuf::from("/project"))]);
+        assert!(tracker.take_idle(start + TIMEOUT * 10).is_empty());
+
+        // Activity on a stopped sandbox doesn't restart its timer, but the policy is kept
+        tracker.touch("web", start + TIMEOUT * 10);
+        assert!(tracker.take_idle(start + TIMEOUT * 20).is_empty());
+        assert_eq!(tracker.policy("web"), timeout_policy());
+    }
+
+    #[test]
+    fn test_idle_tracker_ignores_sandboxes_without_timeout() {
+        let tracker = IdleTracker::default();
+        let start = Instant::now();
+        let project_dir = Path::new("/project");
+        tracker.started("web", None, project_dir, timeout_policy(), start);
+        tracker.started("db", None, project_dir, IdlePolicy::default(), start);
+
+        // Restarting without a timeout stops tracking the sandbox
+        tracker.started("web", None, project_dir, IdlePolicy::default(), start);
+        assert!(tracker.take_idle(start + TIMEOUT * 2).is_empty());
+        assert_eq!(tracker.policy("web"), IdlePolicy::default());
+    }
+
+    #[test]
+    fn test_idle_tracker_cold_starts_stopped_on_demand_sandboxes() {
+        let tracker = IdleTracker::default();
+        let start = Instant::now();
+        let policy = IdlePolicy {
+            timeout: None,
+            start_on_demand: true,
+        };
+        tracker.started(
+            "web",
+            Some("shop"),
+            Path::new("/project"),
+            policy.clone(),
+            start,
+        );
+
+        // A running sandbox needs no start
+        assert_eq!(tracker.cold_start("web"), None);
+
+        // Each time the sandbox is stopped, requests share a new idempotency key
+        tracker.stopped("web");
+        let cold_start = ColdStart {
+            project: Some("shop".to_string()),
+            idempotency_key: "on-demand-1".to_string(),
+        };
+        assert_eq!(tracker.cold_start("web"), Some(cold_start));
+
+        tracker.started("web", Some("shop"), Path::new("/project"), policy, start);
+        tracker.stopped("web");
+        let cold_start = tracker.cold_start("web").unwrap();
+        assert_eq!(cold_start.idempotency_key, "on-demand-2");
+
+        // Sandboxes that don't start on demand are left alone
+        tracker.started("db", None, Path::new("/project"), timeout_policy(), start);
+        tracker.stopped("db");
+        assert_eq!(tracker.cold_start("db"), None);
+    }
+
+    #[test]
+    fn test_idle_tracker_keeps_projects_apart() {
+        let tracker = IdleTracker::default();
+        let start = Instant::now();
+        let policy = timeout_policy();
+        tracker.started(
+            "shop/web",
+            Some("shop"),
+            Path::new("/p/shop"),
+            policy.clone(),
+            start,
+        );
+        tracker.started(
+            "blog/web",
+            Some("blog"),
+            Path::new("/p/blog"),
+            policy,
+            start,
+        );
+
+        // A request naming the project only counts for that project's sandbox
+        tracker.touch_named("web", Some("shop"), start + Duration::from_secs(30));
+        let idle = tracker.take_idle(start + TIMEOUT);
+        assert_eq!(
+            idle,
+            vec![("blog/web".to_string(), PathBuf::from("/p/blog"))]
+        );
+
+        // Without a project, a name tracked in more than one project is left alone
+        tracker.started(
+            "blog/web",
+            Some("blog"),
+            Path::new("/p/blog"),
+            timeout_policy(),
+            start,
+        );
+        tracker.touch_named("web", None, start + TIMEOUT);
+        assert_eq!(tracker.take_idle(start + TIMEOUT).len(), 1);
+
+        // A name tracked in one project only is found without one
+        tracker.started(
+            "shop/api",
+            Some("shop"),
+            Path::new("/p/shop"),
+            timeout_policy(),
+            start,
+        );
+        tracker.touch_named("api", None, start + Duration::from_secs(30));
+        assert!(tracker.take_idle(start + TIMEOUT).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stop_idle_sandboxes_stops_inactive_sandboxes() -> anyhow::Result<()> {
+        let project_dir = tempfile::tempdir()?;
+        let state = test_state(project_dir.path()).await;
+        let mut port_manager = state.get_port_manager().write().await;
+        port_manager.assign_port("idle").await?;
+        port_manager.assign_port("busy").await?;
+        drop(port_manager);
+
+        let start = Instant::now();
+        let tracker = state.get_idle_tracker();
+        tracker.started("idle", None, project_dir.path(), timeout_policy(), start);
+        tracker.started("busy", None, project_dir.path(), timeout_policy(), start);
+        tracker.touch("busy", start + Duration::from_secs(30));
+
+        let downed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let stopped = stop_idle_sandboxes_with(&state, start + TIMEOUT, |sandbox, _| {
+            let downed = downed.clone();
+            async move {
+                downed.lock().unwrap().push(sandbox);
+                Ok(())
+            }
+        })
+        .await;
+
+        assert_eq!(stopped, vec!["idle"]);
+        assert_eq!(*downed.lock().unwrap(), vec!["idle"]);
+
+        let port_manager = state.get_port_manager().read().await;
+        assert!(port_manager.get_port("idle").is_none());
+        assert!(port_manager.get_port("busy").is_some());
+
+        Ok(())
+    }
+}
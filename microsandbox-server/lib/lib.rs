@@ -9,20 +9,28 @@
 pub mod config;
 pub mod error;
 pub mod handler;
+pub mod idle;
 pub mod management;
 pub mod mcp;
+pub mod metrics;
 pub mod middleware;
+#[cfg(test)]
+pub(crate) mod mocks;
 pub mod payload;
 pub mod port;
+pub mod rate_limit;
 pub mod route;
 pub mod state;
 
 pub use config::*;
 pub use error::*;
 pub use handler::*;
+pub use idle::*;
 pub use management::*;
 pub use mcp::*;
+pub use metrics::*;
 pub use middleware::*;
 pub use payload::*;
+pub use rate_limit::*;
 pub use route::*;
 pub use state::*;
@@ -8,25 +8,36 @@
 //!
 //! The module implements core server management features such as:
 //! - Secure server key generation and storage
+//! - Stable keys for development servers, derived from an identifier kept in `.menv`
 //! - PID file management for process tracking
 //! - Signal handling for graceful shutdown
 //! - JWT-based API key generation and formatting
 
-use std::{path::PathBuf, process::Stdio};
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
 
 use chrono::{Duration, Utc};
 use jsonwebtoken::{EncodingKey, Header};
+use microsandbox_core::{MicrosandboxResult, management::orchestra, runtime};
 #[cfg(feature = "cli")]
 use microsandbox_utils::term;
 use microsandbox_utils::{
-    DEFAULT_MSBSERVER_EXE_PATH, MSBSERVER_EXE_ENV_VAR, PROJECTS_SUBDIR, SERVER_KEY_FILE,
-    SERVER_PID_FILE, env,
+    DEFAULT_MSBSERVER_EXE_PATH, DEV_SERVER_ID_FILE, MICROSANDBOX_CONFIG_FILENAME,
+    MICROSANDBOX_ENV_DIR, MSBSERVER_EXE_ENV_VAR, OCI_REGISTRY_ENV_VAR, PROJECTS_SUBDIR,
+    SERVER_KEY_FILE, SERVER_PID_FILE, env,
 };
 use rand::{Rng, distr::Alphanumeric};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tokio::{fs, process::Command};
 
-use crate::{MicrosandboxServerError, MicrosandboxServerResult};
+use crate::{
+    MicrosandboxServerError, MicrosandboxServerResult,
+    state::{AppState, split_sandbox_key},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -38,6 +49,10 @@ pub const API_KEY_PREFIX: &str = "msb_";
 /// Length of the server key
 const SERVER_KEY_LENGTH: usize = 32;
 
+/// Mixed into the identifier a development server key is derived from, so the key can't be
+/// mistaken for a hash of the identifier made for some other purpose
+const DEV_SERVER_KEY_CONTEXT: &str = "microsandbox-dev-server-key";
+
 #[cfg(feature = "cli")]
 const START_SERVER_MSG: &str = "Start sandbox server";
 
@@ -66,14 +81,17 @@ pub struct Claims {
 //--------------------------------------------------------------------------------------------------
 
 /// Start the sandbox server
+#[allow(clippy::too_many_arguments)]
 pub async fn start(
     key: Option<String>,
     host: Option<String>,
     port: Option<u16>,
     project_dir: Option<PathBuf>,
     dev_mode: bool,
+    dev_key: bool,
     detach: bool,
     reset_key: bool,
+    stop_sandboxes_on_exit: bool,
 ) -> MicrosandboxServerResult<()> {
     // Ensure microsandbox home directory exists
     let microsandbox_home_path = env::get_microsandbox_home_path();
@@ -83,6 +101,9 @@ pub async fn start(
     let project_path = microsandbox_home_path.join(PROJECTS_SUBDIR);
     fs::create_dir_all(&project_path).await?;
 
+    // The server keeps its state in the given project directory, or in the default one
+    let server_project_dir = project_dir.clone().unwrap_or_else(|| project_path.clone());
+
     #[cfg(feature = "cli")]
     let start_server_sp = term::create_spinner(START_SERVER_MSG.to_string(), None, None);
 
@@ -124,6 +145,14 @@ pub async fn start(
         }
     }
 
+    // Check the backend libraries before starting a server that couldn't start any sandbox
+    runtime::check_backend_libraries().map_err(|e| {
+        #[cfg(feature = "cli")]
+        term::finish_with_error(&start_server_sp);
+
+        MicrosandboxServerError::StartError(e.to_string())
+    })?;
+
     // Get the path to the msbrun executable
     let msbserver_path = microsandbox_utils::path::resolve_env_path(
         MSBSERVER_EXE_ENV_VAR,
@@ -152,54 +181,33 @@ pub async fn start(
         command.arg("--path").arg(project_dir);
     }
 
-    // Handle secure non-dev mode
-    if !dev_mode {
-        // Create a key file with either the provided key or a generated one
-        let key_file_path = microsandbox_home_path.join(SERVER_KEY_FILE);
-
-        // Store if a key was provided before consuming the option
-        let key_provided = key.is_some();
-
-        let server_key = if let Some(key) = key {
-            // Use the provided key
-            command.arg("--key").arg(&key);
-            key
-        } else if key_file_path.exists() && !reset_key {
-            // Use existing key file if it exists and reset_key is not set
-            let existing_key = fs::read_to_string(&key_file_path).await.map_err(|e| {
-                #[cfg(feature = "cli")]
-                term::finish_with_error(&start_server_sp);
-
-                MicrosandboxServerError::StartError(format!(
-                    "failed to read existing key file {}: {}",
-                    key_file_path.display(),
-                    e
-                ))
-            })?;
-            command.arg("--key").arg(&existing_key);
-            existing_key
-        } else {
-            // Generate a new random key
-            let generated_key = generate_random_key();
-            command.arg("--key").arg(&generated_key);
-            generated_key
-        };
-
-        // Write the key to file (if it's a new key or we're resetting)
-        if !key_file_path.exists() || key_provided || reset_key {
-            fs::write(&key_file_path, &server_key).await.map_err(|e| {
-                #[cfg(feature = "cli")]
-                term::finish_with_error(&start_server_sp);
+    if stop_sandboxes_on_exit {
+        command.arg("--stop-sandboxes-on-exit");
+    }
 
-                MicrosandboxServerError::StartError(format!(
-                    "failed to write key file {}: {}",
-                    key_file_path.display(),
-                    e
-                ))
-            })?;
+    // The server pulls images from the registry this invocation was given, if any
+    if let Some(registry) = env::get_oci_registry_override() {
+        command.env(OCI_REGISTRY_ENV_VAR, registry);
+    }
 
-            tracing::info!("created server key file at {}", key_file_path.display());
-        }
+    // Development servers accept unauthenticated requests unless they opt into a stable key
+    // derived from their project, while other servers use the key kept in `server.key`
+    let key_file_path = microsandbox_home_path.join(SERVER_KEY_FILE);
+    let server_key = resolve_start_key(
+        key,
+        dev_mode,
+        dev_key,
+        reset_key,
+        &key_file_path,
+        &server_project_dir,
+    )
+    .await
+    .inspect_err(|_e| {
+        #[cfg(feature = "cli")]
+        term::finish_with_error(&start_server_sp);
+    })?;
+    if let Some(server_key) = server_key {
+        command.arg("--key").arg(server_key);
     }
 
     if detach {
@@ -304,10 +312,8 @@ pub async fn start(
         _ = sigterm.recv() => {
             tracing::info!("received SIGTERM signal");
 
-            // Send SIGTERM to child process
-            if let Err(e) = child.kill().await {
-                tracing::error!("failed to send SIGTERM to child process: {}", e);
-            }
+            // Send SIGTERM to child process so it can shut down gracefully
+            terminate_child(pid);
 
             // Wait for child to exit after sending signal
             if let Err(e) = child.wait().await {
@@ -323,10 +329,8 @@ pub async fn start(
         _ = sigint.recv() => {
             tracing::info!("received SIGINT signal");
 
-            // Send SIGTERM to child process
-            if let Err(e) = child.kill().await {
-                tracing::error!("failed to send SIGTERM to child process: {}", e);
-            }
+            // Send SIGTERM to child process so it can shut down gracefully
+            terminate_child(pid);
 
             // Wait for child to exit after sending signal
             if let Err(e) = child.wait().await {
@@ -406,35 +410,58 @@ pub async fn stop() -> MicrosandboxServerResult<()> {
 }
 
 /// Generate a new API key (JWT token)
-pub async fn keygen(expire: Option<Duration>) -> MicrosandboxServerResult<String> {
+///
+/// ## Arguments
+///
+/// * `expire` - How long the key is valid for. If None, defaults to 24 hours
+/// * `dev` - Whether to generate the key for a development server, signing it with the key
+///   derived for the server's project instead of the one in `server.key`, see [`dev_server_key`]
+/// * `project_dir` - The project directory of the development server. If None, defaults to the
+///   default project directory
+pub async fn keygen(
+    expire: Option<Duration>,
+    dev: bool,
+    project_dir: Option<PathBuf>,
+) -> MicrosandboxServerResult<String> {
     let microsandbox_home_path = env::get_microsandbox_home_path();
     let key_file_path = microsandbox_home_path.join(SERVER_KEY_FILE);
 
     #[cfg(feature = "cli")]
     let keygen_sp = term::create_spinner(KEYGEN_MSG.to_string(), None, None);
 
-    // Check if server key file exists
-    if !key_file_path.exists() {
-        #[cfg(feature = "cli")]
-        term::finish_with_error(&keygen_sp);
+    let server_key = if dev {
+        let project_dir =
+            project_dir.unwrap_or_else(|| microsandbox_home_path.join(PROJECTS_SUBDIR));
+        dev_server_key(&project_dir, false)
+            .await
+            .inspect_err(|_e| {
+                #[cfg(feature = "cli")]
+                term::finish_with_error(&keygen_sp);
+            })?
+    } else {
+        // Check if server key file exists
+        if !key_file_path.exists() {
+            #[cfg(feature = "cli")]
+            term::finish_with_error(&keygen_sp);
 
-        return Err(MicrosandboxServerError::KeyGenError(
-            "Server key file not found. Make sure the server is running in secure mode."
-                .to_string(),
-        ));
-    }
+            return Err(MicrosandboxServerError::KeyGenError(
+                "Server key file not found. Make sure the server is running in secure mode."
+                    .to_string(),
+            ));
+        }
 
-    // Read the server key
-    let server_key = fs::read_to_string(&key_file_path).await.map_err(|e| {
-        #[cfg(feature = "cli")]
-        term::finish_with_error(&keygen_sp);
+        // Read the server key
+        fs::read_to_string(&key_file_path).await.map_err(|e| {
+            #[cfg(feature = "cli")]
+            term::finish_with_error(&keygen_sp);
 
-        MicrosandboxServerError::KeyGenError(format!(
-            "Failed to read server key file {}: {}",
-            key_file_path.display(),
-            e
-        ))
-    })?;
+            MicrosandboxServerError::KeyGenError(format!(
+                "Failed to read server key file {}: {}",
+                key_file_path.display(),
+                e
+            ))
+        })?
+    };
 
     // Determine token expiration (default: 24 hours)
     let expire = expire.unwrap_or(Duration::hours(24));
@@ -482,6 +509,47 @@ pub async fn keygen(expire: Option<Duration>) -> MicrosandboxServerResult<String
     Ok(token_str)
 }
 
+/// Returns the key a development server is started with when no key is given.
+///
+/// The key is derived from an identifier that is generated once and kept in the `.menv` directory
+/// of the server's project directory, so it stays the same across restarts and API keys generated
+/// with `msb server keygen --dev` keep working. The key of a server not in development mode, kept
+/// in `server.key`, is unaffected.
+///
+/// ## Arguments
+///
+/// * `project_dir` - The project directory of the server
+/// * `reset` - Whether to generate a new identifier, which changes the key and invalidates the API
+///   keys generated for the previous one
+pub async fn dev_server_key(project_dir: &Path, reset: bool) -> MicrosandboxServerResult<String> {
+    let menv_path = project_dir.join(MICROSANDBOX_ENV_DIR);
+    let id_path = menv_path.join(DEV_SERVER_ID_FILE);
+
+    let existing_id = match fs::read_to_string(&id_path).await {
+        Ok(id) if !reset && !id.trim().is_empty() => Some(id.trim().to_string()),
+        Ok(_) => None,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+
+    let id = match existing_id {
+        Some(id) => id,
+        None => {
+            let id = uuid::Uuid::new_v4().to_string();
+            fs::create_dir_all(&menv_path).await?;
+            fs::write(&id_path, &id).await?;
+            tracing::info!(
+                "created development server identifier at {}",
+                id_path.display()
+            );
+            id
+        }
+    };
+
+    let digest = Sha256::digest(format!("{}:{}", DEV_SERVER_KEY_CONTEXT, id));
+    Ok(hex::encode(digest)[..SERVER_KEY_LENGTH].to_string())
+}
+
 /// Clean up the PID file
 pub async fn clean(pid_file_path: &PathBuf) -> MicrosandboxServerResult<()> {
     // Clean up PID file
@@ -493,10 +561,142 @@ pub async fn clean(pid_file_path: &PathBuf) -> MicrosandboxServerResult<()> {
     Ok(())
 }
 
+/// Waits until the server process receives SIGTERM or SIGINT.
+///
+/// Meant to be passed to `axum::serve(..).with_graceful_shutdown(..)` so that the server stops
+/// accepting new connections and drains in-flight requests before returning.
+pub async fn shutdown_signal() {
+    let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            tracing::error!("failed to set up SIGTERM handler: {}", e);
+            return std::future::pending().await;
+        }
+    };
+
+    tokio::select! {
+        _ = sigterm.recv() => tracing::info!("received SIGTERM signal, shutting down"),
+        _ = tokio::signal::ctrl_c() => tracing::info!("received SIGINT signal, shutting down"),
+    }
+}
+
+/// Stops every sandbox the server has started and releases its assigned port.
+///
+/// Failures are logged and don't prevent the remaining sandboxes from being stopped.
+pub async fn stop_managed_sandboxes(state: &AppState) {
+    stop_tracked_sandboxes(state, |sandbox, project_dir| async move {
+        orchestra::down(
+            vec![sandbox],
+            &[],
+            Some(&project_dir),
+            Some(MICROSANDBOX_CONFIG_FILENAME),
+        )
+        .await
+    })
+    .await;
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions: Helpers
 //--------------------------------------------------------------------------------------------------
 
+/// Calls `down` with the name and project directory of every sandbox tracked by the port
+/// manager, releasing each port as it goes.
+async fn stop_tracked_sandboxes<F, Fut>(state: &AppState, mut down: F)
+where
+    F: FnMut(String, PathBuf) -> Fut,
+    Fut: Future<Output = MicrosandboxResult<()>>,
+{
+    let sandbox_keys = state.get_port_manager().read().await.sandbox_keys();
+    for sandbox_key in sandbox_keys {
+        let (project, sandbox) = split_sandbox_key(&sandbox_key);
+        tracing::info!("stopping sandbox {} before shutdown", sandbox_key);
+        if let Err(e) = down(sandbox.to_string(), state.project_dir(project)).await {
+            tracing::error!("failed to stop sandbox {}: {}", sandbox_key, e);
+        }
+
+        let mut port_manager = state.get_port_manager().write().await;
+        if let Err(e) = port_manager.release_port(&sandbox_key).await {
+            tracing::error!("failed to release port for sandbox {}: {}", sandbox_key, e);
+        }
+    }
+}
+
+/// Returns the key a server is started with, or None for a development server that accepts
+/// unauthenticated requests.
+///
+/// A given key is always used. Otherwise, a development server only has a key if `dev_key` is
+/// set, in which case it uses its derived key, see [`dev_server_key`]. Any other server uses the
+/// key in `server.key`, or a newly generated one if there is none or it is reset. `server.key` is
+/// only ever written for servers not in development mode.
+async fn resolve_start_key(
+    key: Option<String>,
+    dev_mode: bool,
+    dev_key: bool,
+    reset_key: bool,
+    key_file_path: &Path,
+    project_dir: &Path,
+) -> MicrosandboxServerResult<Option<String>> {
+    if dev_mode {
+        return match key {
+            Some(key) => Ok(Some(key)),
+            None if dev_key => dev_server_key(project_dir, reset_key).await.map(Some),
+            None => Ok(None),
+        };
+    }
+
+    // Store if a key was provided before consuming the option
+    let key_provided = key.is_some();
+
+    let server_key = if let Some(key) = key {
+        // Use the provided key
+        key
+    } else if key_file_path.exists() && !reset_key {
+        // Use existing key file if it exists and reset_key is not set
+        fs::read_to_string(key_file_path).await.map_err(|e| {
+            MicrosandboxServerError::StartError(format!(
+                "failed to read existing key file {}: {}",
+                key_file_path.display(),
+                e
+            ))
+        })?
+    } else {
+        // Generate a new random key
+        generate_random_key()
+    };
+
+    // Write the key to file (if it's a new key or we're resetting)
+    if !key_file_path.exists() || key_provided || reset_key {
+        fs::write(key_file_path, &server_key).await.map_err(|e| {
+            MicrosandboxServerError::StartError(format!(
+                "failed to write key file {}: {}",
+                key_file_path.display(),
+                e
+            ))
+        })?;
+
+        tracing::info!("created server key file at {}", key_file_path.display());
+    }
+
+    Ok(Some(server_key))
+}
+
+/// Sends SIGTERM to the server child process.
+fn terminate_child(pid: u32) {
+    // A PID of 0 would signal our own process group
+    if pid == 0 {
+        return;
+    }
+
+    if unsafe { libc::kill(pid as i32, libc::SIGTERM) } != 0 {
+        tracing::error!(
+            "failed to send SIGTERM to child process: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
 /// Generate a random key for JWT token signing
 fn generate_random_key() -> String {
     rand::rng()
@@ -513,3 +713,180 @@ pub fn convert_jwt_to_api_key(jwt_token: &str) -> MicrosandboxServerResult<Strin
     // Create custom API key format: API_KEY_PREFIX + full JWT token
     Ok(format!("{}{}", API_KEY_PREFIX, jwt_token))
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use axum::{
+        Router,
+        body::Body,
+        http::{Request, StatusCode, header::AUTHORIZATION},
+        middleware,
+        routing::get,
+    };
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        middleware::auth_middleware,
+        mocks::{test_state, test_state_with},
+    };
+
+    /// Starts a development server for the project in `home`, with its derived key if `dev_key`
+    /// is set, and returns a router behind its auth
+    async fn start_dev_server(home: &Path, dev_key: bool) -> anyhow::Result<Router> {
+        let project_dir = home.join(PROJECTS_SUBDIR);
+        let key_file_path = home.join(SERVER_KEY_FILE);
+        let key =
+            resolve_start_key(None, true, dev_key, false, &key_file_path, &project_dir).await?;
+        let state = test_state_with(&project_dir, key, None).await;
+
+        Ok(Router::new()
+            .route("/", get(|| async { "ok" }))
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                auth_middleware,
+            ))
+            .with_state(state))
+    }
+
+    async fn status(router: Router, token: Option<&str>) -> anyhow::Result<StatusCode> {
+        let mut request = Request::builder().uri("/");
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+
+        Ok(router.oneshot(request.body(Body::empty())?).await?.status())
+    }
+
+    #[tokio::test]
+    async fn test_stop_tracked_sandboxes_downs_each_sandbox() -> anyhow::Result<()> {
+        let project_dir = tempfile::tempdir()?;
+        let state = test_state(project_dir.path()).await;
+        let mut port_manager = state.get_port_manager().write().await;
+        port_manager.assign_port("alpha").await?;
+        port_manager.assign_port("beta").await?;
+        port_manager.assign_port("blog/alpha").await?;
+        drop(port_manager);
+
+        let stopped = Arc::new(Mutex::new(Vec::new()));
+
+        stop_tracked_sandboxes(&state, |sandbox, sandbox_project_dir| {
+            let stopped = stopped.clone();
+            async move {
+                stopped.lock().unwrap().push((sandbox, sandbox_project_dir));
+                Ok(())
+            }
+        })
+        .await;
+
+        // Each sandbox is stopped in the project it was started in
+        let mut stopped = stopped.lock().unwrap().clone();
+        stopped.sort();
+        assert_eq!(
+            stopped,
+            vec![
+                ("alpha".to_string(), project_dir.path().to_path_buf()),
+                ("alpha".to_string(), project_dir.path().join("blog")),
+                ("beta".to_string(), project_dir.path().to_path_buf()),
+            ]
+        );
+        assert!(
+            state
+                .get_port_manager()
+                .read()
+                .await
+                .sandbox_keys()
+                .is_empty()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dev_server_key_is_stable_until_reset() -> anyhow::Result<()> {
+        let home = tempfile::tempdir()?;
+        let key_file_path = home.path().join(SERVER_KEY_FILE);
+        let project_dir = home.path().join(PROJECTS_SUBDIR);
+
+        // Without opting in, a development server has no key and derives none
+        let key = resolve_start_key(None, true, false, false, &key_file_path, &project_dir).await?;
+        assert_eq!(key, None);
+        assert!(!project_dir.join(MICROSANDBOX_ENV_DIR).exists());
+
+        // Two starts in development mode with the derived key get the same key
+        let first = resolve_start_key(None, true, true, false, &key_file_path, &project_dir)
+            .await?
+            .unwrap();
+        let second = resolve_start_key(None, true, true, false, &key_file_path, &project_dir)
+            .await?
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.len(), SERVER_KEY_LENGTH);
+        assert!(
+            project_dir
+                .join(MICROSANDBOX_ENV_DIR)
+                .join(DEV_SERVER_ID_FILE)
+                .exists()
+        );
+
+        // Resetting the key changes it, and the new key is stable again
+        let reset = resolve_start_key(None, true, true, true, &key_file_path, &project_dir)
+            .await?
+            .unwrap();
+        assert_ne!(reset, first);
+        assert_eq!(dev_server_key(&project_dir, false).await?, reset);
+
+        // Development mode never touches the key of a server not in development mode
+        assert!(!key_file_path.exists());
+        let secure = resolve_start_key(None, false, false, false, &key_file_path, &project_dir)
+            .await?
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&key_file_path)?, secure);
+        resolve_start_key(None, true, true, true, &key_file_path, &project_dir).await?;
+        assert_eq!(std::fs::read_to_string(&key_file_path)?, secure);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dev_server_token_authenticates_across_restarts() -> anyhow::Result<()> {
+        let home = tempfile::tempdir()?;
+        let project_dir = home.path().join(PROJECTS_SUBDIR);
+
+        // A development server that didn't opt into a key accepts unauthenticated requests
+        let open = start_dev_server(home.path(), false).await?;
+        assert_eq!(status(open, None).await?, StatusCode::OK);
+
+        let server = start_dev_server(home.path(), true).await?;
+        let token = keygen(Some(Duration::hours(1)), true, Some(project_dir.clone())).await?;
+        assert_eq!(status(server.clone(), Some(&token)).await?, StatusCode::OK);
+
+        // The dev server validates tokens now that it has a key
+        assert_eq!(
+            status(server.clone(), None).await?,
+            StatusCode::UNAUTHORIZED
+        );
+        let forged = convert_jwt_to_api_key("not.a.token")?;
+        assert_eq!(
+            status(server, Some(&forged)).await?,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // The token keeps working after the server restarts
+        let restarted = start_dev_server(home.path(), true).await?;
+        assert_eq!(status(restarted, Some(&token)).await?, StatusCode::OK);
+
+        // Resetting the key invalidates it
+        dev_server_key(&project_dir, true).await?;
+        let reset = start_dev_server(home.path(), true).await?;
+        assert_eq!(status(reset, Some(&token)).await?, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    }
+}
@@ -119,6 +119,10 @@ pub async fn handle_mcp_list_tools(
                                     "description": "Environment variables"
                                 }
                             }
+                        },
+                        "idempotency_key": {
+                            "type": "string",
+                            "description": "Optional key to safely retry a start request without launching the sandbox twice"
                         }
                     },
                     "required": ["sandbox"]
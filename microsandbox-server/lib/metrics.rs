@@ -0,0 +1,338 @@
+//! Resource usage history for the microsandbox server.
+//!
+//! This module handles:
+//! - Sampling the status of the server's sandboxes on an interval, across all of its projects
+//! - Keeping a short rolling history of the samples of each sandbox, keyed by its sandbox key
+//! - Metrics history configuration from flags and environment variables
+//!
+//! The module provides:
+//! - Metrics history configuration
+//! - A thread-safe history keyed by sandbox, bounded to a fixed number of samples each
+//! - The background task that samples the sandboxes
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use getset::Getters;
+use microsandbox_core::{
+    MicrosandboxError, MicrosandboxResult,
+    management::orchestra::{self, ROOT_PROJECT_NAME},
+};
+use microsandbox_utils::{
+    DEFAULT_METRICS_HISTORY_DEPTH, METRICS_HISTORY_DEPTH_ENV_VAR, METRICS_HISTORY_INTERVAL_ENV_VAR,
+};
+use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+
+use crate::{
+    payload::SandboxStatus,
+    state::{AppState, sandbox_key},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// How often the server samples its sandboxes and how many samples it keeps
+#[derive(Debug, Clone, Copy, Deserialize, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct MetricsHistoryConfig {
+    /// How often sandboxes are sampled, or `None` to keep no history
+    interval: Option<Duration>,
+
+    /// The most samples kept for each sandbox
+    depth: usize,
+}
+
+/// Rolling resource usage history of the server's sandboxes
+///
+/// Each sandbox keeps at most `depth` samples, the oldest being dropped to make room for new
+/// ones, so the history takes a bounded amount of memory however long the server runs.
+#[derive(Debug)]
+pub struct MetricsHistory {
+    /// The most samples kept for each sandbox
+    depth: usize,
+
+    /// The samples of each sandbox, oldest first
+    samples: Mutex<HashMap<String, VecDeque<MetricsSample>>>,
+}
+
+/// The resource usage of a sandbox at one point in time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MetricsSample {
+    /// When the sample was taken
+    pub timestamp: DateTime<Utc>,
+
+    /// Whether the sandbox was running
+    pub running: bool,
+
+    /// CPU usage percentage
+    pub cpu_usage: Option<f32>,
+
+    /// Memory usage in MiB
+    pub memory_usage: Option<u64>,
+
+    /// Disk usage of the RW layer in bytes
+    pub disk_usage: Option<u64>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl MetricsHistoryConfig {
+    /// Create a new metrics history configuration
+    ///
+    /// Each value that isn't provided is read from its environment variable. Without an interval,
+    /// or with an interval of zero seconds, no history is kept.
+    pub fn new(interval_secs: Option<u64>, depth: Option<usize>) -> Self {
+        let interval_secs = interval_secs.or_else(|| env_parse(METRICS_HISTORY_INTERVAL_ENV_VAR));
+        Self {
+            interval: interval_secs
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            depth: depth
+                .or_else(|| env_parse(METRICS_HISTORY_DEPTH_ENV_VAR))
+                .unwrap_or(DEFAULT_METRICS_HISTORY_DEPTH),
+        }
+    }
+}
+
+impl MetricsHistory {
+    /// Create an empty history keeping at most `depth` samples per sandbox
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a sample for the sandbox with the given key, see [`sandbox_key`], dropping its oldest
+    /// sample if it already has `depth` of them
+    pub fn push(&self, sandbox_key: &str, sample: MetricsSample) {
+        if self.depth == 0 {
+            return;
+        }
+
+        let mut samples = self.samples.lock().unwrap();
+        let sandbox_samples = samples.entry(sandbox_key.to_string()).or_default();
+        while sandbox_samples.len() >= self.depth {
+            sandbox_samples.pop_front();
+        }
+        sandbox_samples.push_back(sample);
+    }
+
+    /// Record the statuses of a sampling round taken at `timestamp`
+    ///
+    /// Sandboxes missing from the round no longer exist, so their history is dropped.
+    pub fn record(&self, timestamp: DateTime<Utc>, statuses: &[SandboxStatus]) {
+        let mut sampled = HashSet::new();
+        for status in statuses {
+            let project = status.project.as_deref().unwrap_or(ROOT_PROJECT_NAME);
+            let key = sandbox_key(project, &status.name);
+            self.push(&key, MetricsSample::from_status(timestamp, status));
+            sampled.insert(key);
+        }
+
+        self.samples
+            .lock()
+            .unwrap()
+            .retain(|sandbox_key, _| sampled.contains(sandbox_key));
+    }
+
+    /// Returns the samples of the sandbox with the given key, oldest first
+    pub fn get(&self, sandbox_key: &str) -> Vec<MetricsSample> {
+        self.samples
+            .lock()
+            .unwrap()
+            .get(sandbox_key)
+            .map(|samples| samples.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl MetricsSample {
+    /// Create a sample from the status of a sandbox taken at `timestamp`
+    pub fn from_status(timestamp: DateTime<Utc>, status: &SandboxStatus) -> Self {
+        Self {
+            timestamp,
+            running: status.running,
+            cpu_usage: status.cpu_usage,
+            memory_usage: status.memory_usage,
+            disk_usage: status.disk_usage,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Default for MetricsHistoryConfig {
+    fn default() -> Self {
+        Self {
+            interval: None,
+            depth: DEFAULT_METRICS_HISTORY_DEPTH,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Sample the status of the server's sandboxes into its metrics history, for as long as the
+/// server runs
+///
+/// Returns right away if the server keeps no metrics history.
+pub async fn sample_metrics(state: AppState) {
+    let Some(interval) = *state.get_config().get_metrics_history().get_interval() else {
+        return;
+    };
+
+    let projects_dir = state.get_config().get_project_dir().clone();
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        ticker.tick().await;
+        if !projects_dir.exists() {
+            continue;
+        }
+
+        let projects = match orchestra::list_projects(&projects_dir).await {
+            Ok(projects) => projects,
+            Err(e) => {
+                tracing::warn!("failed to list projects to sample: {}", e);
+                continue;
+            }
+        };
+
+        // A project that fails to report is skipped, so its history is dropped with this round
+        let mut statuses = Vec::new();
+        for (project, project_dir) in projects {
+            match project_statuses(&project, &project_dir, vec![]).await {
+                Ok(project_statuses) => statuses.extend(project_statuses),
+                Err(e) => tracing::warn!(
+                    "failed to sample sandbox metrics of project {}: {}",
+                    project,
+                    e
+                ),
+            }
+        }
+        state.get_metrics_history().record(Utc::now(), &statuses);
+    }
+}
+
+/// Gets the status of the sandboxes with the given names in a project, or of all of its
+/// sandboxes if no names are given
+///
+/// ## Arguments
+///
+/// * `project` - The name of the project, see [`AppState::project_name`]
+/// * `project_dir` - The directory of the project
+/// * `names` - The names of the sandboxes to get the status of
+pub(crate) async fn project_statuses(
+    project: &str,
+    project_dir: &Path,
+    names: Vec<String>,
+) -> MicrosandboxResult<Vec<SandboxStatus>> {
+    let statuses = match orchestra::status(names, &[], Some(project_dir), None).await {
+        Ok(statuses) => statuses,
+        // A project without sandboxes has no metrics to report
+        Err(MicrosandboxError::NoSandboxesInConfig(_)) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let project = (project != ROOT_PROJECT_NAME).then(|| project.to_string());
+    Ok(statuses
+        .into_iter()
+        .map(|status| SandboxStatus {
+            name: status.name,
+            project: project.clone(),
+            running: status.running,
+            cpu_usage: status.cpu_usage,
+            memory_usage: status.memory_usage,
+            disk_usage: status.disk_usage,
+        })
+        .collect())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Read and parse an environment variable, if set and valid
+fn env_parse<T: std::str::FromStr>(var: &str) -> Option<T> {
+    std::env::var(var).ok().and_then(|value| value.parse().ok())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn status(name: &str, memory_usage: u64) -> SandboxStatus {
+        SandboxStatus {
+            name: name.to_string(),
+            project: None,
+            running: true,
+            cpu_usage: Some(1.5),
+            memory_usage: Some(memory_usage),
+            disk_usage: None,
+        }
+    }
+
+    fn at(secs: i64) -> DateTime<Utc> {
+        Utc.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn test_metrics_history_keeps_samples_in_order_up_to_depth() {
+        let history = MetricsHistory::new(3);
+        for round in 0..5 {
+            history.record(at(round), &[status("web", round as u64 * 10)]);
+        }
+
+        // Only the three most recent samples are kept, oldest first
+        let samples = history.get("web");
+        let memory: Vec<_> = samples.iter().map(|s| s.memory_usage).collect();
+        assert_eq!(memory, vec![Some(20), Some(30), Some(40)]);
+        let timestamps: Vec<_> = samples.iter().map(|s| s.timestamp).collect();
+        assert_eq!(timestamps, vec![at(2), at(3), at(4)]);
+
+        assert!(history.get("db").is_empty());
+    }
+
+    #[test]
+    fn test_metrics_history_forgets_removed_sandboxes() {
+        let history = MetricsHistory::new(3);
+        history.record(at(0), &[status("web", 10), status("db", 20)]);
+        history.record(at(1), &[status("web", 11)]);
+
+        assert_eq!(history.get("web").len(), 2);
+        assert!(history.get("db").is_empty());
+
+        // Sandboxes sharing a name in different projects keep their own history
+        let mut other = status("web", 99);
+        other.project = Some("blog".to_string());
+        history.record(at(2), &[status("web", 12), other]);
+        assert_eq!(history.get("web").len(), 3);
+        assert_eq!(history.get("blog/web").len(), 1);
+
+        // A zero depth keeps nothing
+        let history = MetricsHistory::new(0);
+        history.record(at(0), &[status("web", 10)]);
+        assert!(history.get("web").is_empty());
+    }
+}
@@ -10,28 +10,79 @@
 //! - Middleware components for common operations
 //! - Authentication middleware for API security
 //! - Logging and tracing middleware
+//! - Request IDs for correlating a request across the server and portal
+//! - Activity tracking for sandbox idle timeouts
+//! - Request body size limits
+
+use std::{net::SocketAddr, time::Instant};
 
 use axum::{
-    body::Body,
-    extract::State,
-    http::{HeaderMap, Request, StatusCode, Uri},
+    body::{Body, Bytes},
+    extract::{ConnectInfo, State},
+    http::{
+        HeaderMap, HeaderValue, Request, StatusCode, Uri,
+        header::{CONTENT_LENGTH, CONTENT_TYPE},
+    },
     middleware::Next,
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
+use futures::StreamExt;
 use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::Instrument;
+use uuid::Uuid;
 
 use crate::{
     Claims,
-    config::PROXY_AUTH_HEADER,
+    config::{PROXY_AUTH_HEADER, REQUEST_ID_HEADER},
     error::{AuthenticationError, ServerError},
     management::API_KEY_PREFIX,
+    rate_limit::RequestClass,
     state::AppState,
 };
 
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// The maximum length of a client-supplied request ID
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// The ID of the request currently being handled
+    static REQUEST_ID: String;
+}
+
 //--------------------------------------------------------------------------------------------------
 // Middleware Functions
 //--------------------------------------------------------------------------------------------------
 
+/// Assign an ID to each request, reusing the client's `X-Request-Id` if it sent a valid one
+///
+/// The ID is attached to the tracing span for the request, forwarded to the portal and echoed
+/// back in the response headers.
+pub async fn request_id_middleware(req: Request<Body>, next: Next) -> Response {
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(req).instrument(span))
+        .await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    response
+}
+
 /// Proxy middleware for forwarding requests to a target service
 pub async fn proxy_middleware(
     State(_state): State<AppState>,
@@ -43,28 +94,32 @@ pub async fn proxy_middleware(
     next.run(req).await
 }
 
-/// Convert a URI to a proxied URI targeting a sandbox
-pub fn proxy_uri(original_uri: Uri, sandbox_name: &str) -> Uri {
-    // In a real implementation, you would:
-    // 1. Look up the sandbox's address from a registry or state
-    // 2. Construct a new URI that points to the sandbox
-    // 3. Return the new URI for proxying
-
-    // For demonstration purposes, we'll construct a simple URI
-    // In production, you would get this from a sandbox registry
-    let target_host = format!("sandbox-{}.internal", sandbox_name);
-
-    let uri_string = if let Some(path_and_query) = original_uri.path_and_query() {
-        format!("http://{}:{}{}", target_host, 8080, path_and_query)
-    } else {
-        format!("http://{}:{}/", target_host, 8080)
-    };
-
-    // Try to parse the string into a URI
-    // In case of errors, fallback to a default URI
-    uri_string
-        .parse()
-        .unwrap_or_else(|_| "http://localhost:8080/".parse().unwrap())
+/// Converts the URI of a proxied request to the URI it is forwarded to
+///
+/// The path after the sandbox name and the query are kept, and sent to the sandbox's published
+/// `port` on the host.
+///
+/// ## Arguments
+///
+/// * `original_uri` - The URI of the request, relative to the proxy routes
+/// * `sandbox_name` - The name of the sandbox the request is for
+/// * `port` - The host port the sandbox publishes
+pub fn proxy_uri(original_uri: &Uri, sandbox_name: &str, port: u16) -> Result<Uri, ServerError> {
+    let path_and_query = original_uri
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str());
+    let forwarded = path_and_query
+        .strip_prefix('/')
+        .and_then(|path| path.strip_prefix(sandbox_name))
+        .filter(|path| path.starts_with('/'))
+        .unwrap_or(path_and_query);
+
+    Uri::builder()
+        .scheme("http")
+        .authority(format!("127.0.0.1:{}", port))
+        .path_and_query(forwarded)
+        .build()
+        .map_err(|e| ServerError::InternalError(format!("Failed to build proxy URI: {}", e)))
 }
 
 /// Log incoming requests
@@ -88,13 +143,14 @@ pub async fn logging_middleware(
 }
 
 /// Authentication middleware for verifying API keys
+///
+/// A server in dev mode only skips authentication when it was started without a key.
 pub async fn auth_middleware(
     State(state): State<AppState>,
     req: Request<Body>,
     next: Next,
 ) -> Result<impl IntoResponse, ServerError> {
-    // Skip auth in dev mode if configured
-    if *state.get_config().get_dev_mode() {
+    if !requires_auth(&state) {
         return Ok(next.run(req).await);
     }
 
@@ -108,15 +164,97 @@ pub async fn auth_middleware(
     Ok(next.run(req).await)
 }
 
+/// Body limit middleware that rejects requests whose body is larger than the configured maximum
+///
+/// Requests announcing a larger `Content-Length` are rejected with 413 before their body is read.
+/// Bodies without one are checked as they are buffered by the middleware further in.
+pub async fn body_limit_middleware(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, ServerError> {
+    let limit = *state.get_config().get_max_request_body();
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    if content_length.is_some_and(|length| length > limit as u64) {
+        tracing::warn!(?content_length, limit, "request body too large");
+        return Err(ServerError::PayloadTooLarge(limit));
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Rate limiting middleware keyed by API key
+///
+/// Requests with a valid API key are counted against the bucket of that key, so clients sharing
+/// an address don't share a bucket. Requests without a valid key, and all requests to a dev
+/// server without a key, are counted against the bucket of their peer address: rotating made-up
+/// keys doesn't get a client a fresh bucket. Requests that start or stop sandboxes are counted
+/// against a separate, stricter bucket. Only JSON bodies are read to find out which method is
+/// called, so streamed uploads stay streamed.
+pub async fn rate_limit_middleware(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, ServerError> {
+    let client = rate_limit_client_key(&req, &state);
+
+    // Buffer the body to find out which method is being called, then put it back
+    let (class, req) = if is_json_request(&req) {
+        let (parts, body) = req.into_parts();
+        let bytes = read_body(body, *state.get_config().get_max_request_body()).await?;
+        let class = classify_request(&bytes);
+        (class, Request::from_parts(parts, Body::from(bytes)))
+    } else {
+        (RequestClass::General, req)
+    };
+
+    if let Err(retry_after) = state.get_rate_limiter().check(&client, class) {
+        tracing::warn!(?class, ?retry_after, "rate limit exceeded");
+        return Err(ServerError::RateLimited(retry_after));
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Activity middleware that resets the idle timer of the sandbox a request names
+///
+/// JSON-RPC requests name the sandbox in `params.sandbox`, and MCP tool calls in
+/// `params.arguments.sandbox`, each along with its optional `project`.
+pub async fn activity_middleware(
+    State(state): State<AppState>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<impl IntoResponse, ServerError> {
+    if !is_json_request(&req) {
+        return Ok(next.run(req).await);
+    }
+
+    // Buffer the body to find out which sandbox is being called, then put it back
+    let (parts, body) = req.into_parts();
+    let bytes = read_body(body, *state.get_config().get_max_request_body()).await?;
+    if let Some((sandbox, project)) = requested_sandbox(&bytes) {
+        state
+            .get_idle_tracker()
+            .touch_named(&sandbox, project.as_deref(), Instant::now());
+    }
+    let req = Request::from_parts(parts, Body::from(bytes));
+
+    Ok(next.run(req).await)
+}
+
 /// Smart authentication middleware for MCP requests
-/// All methods require valid token authentication
+/// All methods require valid token authentication, unless the server is in dev mode without a key
 pub async fn mcp_smart_auth_middleware(
     State(state): State<AppState>,
     req: Request<Body>,
     next: Next,
 ) -> Result<impl IntoResponse, ServerError> {
-    // Skip auth in dev mode if configured
-    if *state.get_config().get_dev_mode() {
+    if !requires_auth(&state) {
         return Ok(next.run(req).await);
     }
 
@@ -130,10 +268,32 @@ pub async fn mcp_smart_auth_middleware(
     Ok(next.run(req).await)
 }
 
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Get the ID of the request currently being handled, if any
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
 //--------------------------------------------------------------------------------------------------
 // Helper Functions
 //--------------------------------------------------------------------------------------------------
 
+/// Check that a client-supplied request ID is short and made of visible ASCII characters
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Check whether requests must carry a valid API key
+///
+/// Servers not in dev mode always have a key. A dev server started with one, such as the stable
+/// key `msb server start --dev` derives, validates tokens like any other server.
+fn requires_auth(state: &AppState) -> bool {
+    !*state.get_config().get_dev_mode() || state.get_config().get_key().is_some()
+}
+
 /// Extract API key from request headers
 fn extract_api_key_from_headers(headers: &HeaderMap) -> Result<String, ServerError> {
     // First check the Proxy-Authorization header
@@ -175,6 +335,89 @@ fn extract_api_key_from_headers(headers: &HeaderMap) -> Result<String, ServerErr
     ))
 }
 
+/// Check whether a request carries a JSON body, such as a JSON-RPC or MCP call
+fn is_json_request(req: &Request<Body>) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"))
+}
+
+/// Buffer a request body, failing with `PayloadTooLarge` once it grows past `limit` bytes
+async fn read_body(body: Body, limit: usize) -> Result<Bytes, ServerError> {
+    let mut stream = body.into_data_stream();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| {
+            ServerError::InternalError(format!("Failed to read request body: {}", e))
+        })?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(ServerError::PayloadTooLarge(limit));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(Bytes::from(bytes))
+}
+
+/// Get the key used to identify a client for rate limiting
+///
+/// A validated API key is identified by its hash, so the limiter doesn't keep keys around. Any
+/// other request is identified by its peer address.
+fn rate_limit_client_key(req: &Request<Body>, state: &AppState) -> String {
+    if requires_auth(state) {
+        let api_key = extract_api_key_from_headers(req.headers())
+            .ok()
+            .filter(|api_key| validate_token(api_key, state).is_ok());
+        if let Some(api_key) = api_key {
+            return format!("key:{}", hex::encode(Sha256::digest(api_key.as_bytes())));
+        }
+    }
+
+    match req.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(addr)) => format!("ip:{}", addr.ip()),
+        None => "ip:unknown".to_string(),
+    }
+}
+
+/// Classify a JSON-RPC or MCP request body by the method it calls
+fn classify_request(body: &[u8]) -> RequestClass {
+    let Ok(request) = serde_json::from_slice::<Value>(body) else {
+        return RequestClass::General;
+    };
+
+    let method = request.get("method").and_then(Value::as_str);
+    let tool = request
+        .get("params")
+        .and_then(|params| params.get("name"))
+        .and_then(Value::as_str);
+
+    match (method, tool) {
+        (Some("sandbox.start" | "sandbox.start.batch" | "sandbox.stop"), _) => {
+            RequestClass::Lifecycle
+        }
+        (Some("tools/call"), Some("sandbox_start" | "sandbox_stop")) => RequestClass::Lifecycle,
+        _ => RequestClass::General,
+    }
+}
+
+/// Get the sandbox a JSON-RPC or MCP request body names, if any, along with its project
+fn requested_sandbox(body: &[u8]) -> Option<(String, Option<String>)> {
+    let request = serde_json::from_slice::<Value>(body).ok()?;
+    let params = request.get("params")?;
+    let params = match params.get("arguments") {
+        Some(arguments) if params.get("sandbox").is_none() => arguments,
+        _ => params,
+    };
+
+    let sandbox = params.get("sandbox")?.as_str()?.to_string();
+    let project = params
+        .get("project")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    Some((sandbox, project))
+}
+
 /// Convert a custom API key back to a standard JWT format
 fn convert_api_key_to_jwt(api_key: &str) -> Result<String, ServerError> {
     // Check if the API key has the expected prefix
@@ -192,8 +435,7 @@ fn convert_api_key_to_jwt(api_key: &str) -> Result<String, ServerError> {
 
 /// Get the server key from the AppState config
 fn get_server_key(state: &AppState) -> Result<String, ServerError> {
-    // Get the key from the config - we already know we're not in dev mode
-    // by the time this function is called
+    // Get the key from the config - only servers with a key validate tokens
     match state.get_config().get_key() {
         Some(key) => Ok(key.clone()),
         None => Err(ServerError::Authentication(
@@ -231,3 +473,184 @@ fn validate_token(api_key: &str, state: &AppState) -> Result<Claims, ServerError
 
     Ok(token_data.claims)
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use axum::{Router, body, middleware, routing::get};
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{management::convert_jwt_to_api_key, mocks};
+
+    fn router() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|| async { current_request_id().unwrap_or_default() }),
+            )
+            .route(
+                "/error",
+                get(|| async { ServerError::NotFound("missing".to_string()) }),
+            )
+            .layer(middleware::from_fn(request_id_middleware))
+    }
+
+    async fn send(uri: &str, request_id: Option<&str>) -> Response {
+        let mut request = Request::builder().uri(uri);
+        if let Some(id) = request_id {
+            request = request.header(REQUEST_ID_HEADER, id);
+        }
+
+        router()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_string(response: Response) -> String {
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_request_id_generated_when_absent() {
+        let response = send("/", None).await;
+        let header = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        assert!(Uuid::parse_str(&header).is_ok());
+        assert_eq!(body_string(response).await, header);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_preserved_when_supplied() {
+        let response = send("/", Some("client-id-123")).await;
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "client-id-123");
+        assert_eq!(body_string(response).await, "client-id-123");
+
+        // Invalid IDs are replaced rather than echoed back
+        let response = send("/", Some(&"x".repeat(MAX_REQUEST_ID_LEN + 1))).await;
+        let header = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(Uuid::parse_str(header).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_id_included_in_error_body() {
+        let response = send("/error", Some("client-id-123")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "client-id-123");
+
+        let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["request_id"], "client-id-123");
+    }
+
+    #[test]
+    fn test_requested_sandbox_from_rpc_and_mcp_bodies() {
+        let rpc = br#"{"jsonrpc":"2.0","method":"sandbox.repl.run","params":{"sandbox":"web"}}"#;
+        assert_eq!(requested_sandbox(rpc), Some(("web".to_string(), None)));
+
+        let rpc = br#"{"method":"sandbox.repl.run","params":{"sandbox":"web","project":"shop"}}"#;
+        assert_eq!(
+            requested_sandbox(rpc),
+            Some(("web".to_string(), Some("shop".to_string())))
+        );
+
+        let mcp = br#"{"method":"tools/call","params":{"name":"sandbox_run_code","arguments":{"sandbox":"db"}}}"#;
+        assert_eq!(requested_sandbox(mcp), Some(("db".to_string(), None)));
+
+        assert_eq!(
+            requested_sandbox(br#"{"method":"sandbox.metrics.get"}"#),
+            None
+        );
+        assert_eq!(requested_sandbox(b"not json"), None);
+    }
+
+    fn request_from(ip: [u8; 4], api_key: Option<&str>) -> Request<Body> {
+        let mut request = Request::builder().uri("/");
+        if let Some(api_key) = api_key {
+            request = request.header("Authorization", format!("Bearer {}", api_key));
+        }
+
+        let mut request = request.body(Body::empty()).unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::from((ip, 5555))));
+        request
+    }
+
+    fn api_key(server_key: &str) -> String {
+        let now = chrono::Utc::now().timestamp() as u64;
+        let claims = Claims {
+            exp: now + 3600,
+            iat: now,
+        };
+        let jwt = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(server_key.as_bytes()),
+        )
+        .unwrap();
+        convert_jwt_to_api_key(&jwt).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_client_key_follows_api_key() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let state =
+            mocks::test_state_with(project_dir.path(), Some("server-key".to_string()), None).await;
+        let api_key = api_key("server-key");
+
+        // A valid key has the same bucket from any address, and clients sharing an address
+        // without one don't share it
+        let keyed = rate_limit_client_key(&request_from([10, 0, 0, 1], Some(&api_key)), &state);
+        assert!(keyed.starts_with("key:"));
+        assert!(!keyed.contains(&api_key));
+        assert_eq!(
+            rate_limit_client_key(&request_from([10, 0, 0, 2], Some(&api_key)), &state),
+            keyed
+        );
+
+        // Made-up keys are counted against the address they come from
+        let forged = api_key.replace("msb_", "msb_x");
+        assert_eq!(
+            rate_limit_client_key(&request_from([10, 0, 0, 1], Some(&forged)), &state),
+            "ip:10.0.0.1"
+        );
+        assert_eq!(
+            rate_limit_client_key(&request_from([10, 0, 0, 1], None), &state),
+            "ip:10.0.0.1"
+        );
+
+        // A dev server without a key has no keys to tell clients apart by
+        let state = mocks::test_state(project_dir.path()).await;
+        assert_eq!(
+            rate_limit_client_key(&request_from([10, 0, 0, 1], Some(&api_key)), &state),
+            "ip:10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn test_proxy_uri_targets_published_port() {
+        let uri: Uri = "/web/api/items?page=2".parse().unwrap();
+        assert_eq!(
+            proxy_uri(&uri, "web", 8080).unwrap(),
+            "http://127.0.0.1:8080/api/items?page=2"
+        );
+
+        // Only a whole path segment is taken as the sandbox name
+        let uri: Uri = "/webapp/index.html".parse().unwrap();
+        assert_eq!(
+            proxy_uri(&uri, "web", 8080).unwrap(),
+            "http://127.0.0.1:8080/webapp/index.html"
+        );
+    }
+}
@@ -0,0 +1,48 @@
+//! Fixtures shared by the tests of the server modules.
+
+use std::{path::Path, sync::Arc};
+
+use tokio::sync::RwLock;
+
+use crate::{config::Config, port::PortManager, state::AppState};
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Creates the state of a development server for the project in `project_dir`, without a key.
+pub(crate) async fn test_state(project_dir: &Path) -> AppState {
+    test_state_with(project_dir, None, None).await
+}
+
+/// Creates the state of a development server for the project in `project_dir`.
+///
+/// ## Arguments
+///
+/// * `project_dir` - The project directory the server manages
+/// * `key` - The key API tokens are signed with, or None to accept requests without a token
+/// * `max_request_body` - The most bytes a request body may have, or None for the default
+pub(crate) async fn test_state_with(
+    project_dir: &Path,
+    key: Option<String>,
+    max_request_body: Option<usize>,
+) -> AppState {
+    let config = Config::new(
+        key,
+        "127.0.0.1".to_string(),
+        0,
+        Some(project_dir.to_path_buf()),
+        true,
+        true,
+        Default::default(),
+        Default::default(),
+        Default::default(),
+        max_request_body,
+    )
+    .expect("the test config should be valid");
+    let port_manager = PortManager::new(project_dir)
+        .await
+        .expect("the port manager should load");
+
+    AppState::new(Arc::new(config), Arc::new(RwLock::new(port_manager)))
+}
@@ -12,8 +12,12 @@
 //! - Success message formatting for sandbox operations
 //! - Detailed error information handling
 
+use microsandbox_core::config::ReadinessProbe;
 use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::metrics::MetricsSample;
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -124,6 +128,22 @@ pub struct SandboxStartParams {
 
     /// Optional sandbox configuration
     pub config: Option<SandboxConfig>,
+
+    /// Optional key that dedupes retried start requests for the same sandbox
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// Request payload for starting several sandboxes at once
+#[derive(Debug, Deserialize)]
+pub struct SandboxStartBatchParams {
+    /// The sandboxes to start, each with the same parameters as `sandbox.start`
+    pub sandboxes: Vec<SandboxStartParams>,
 }
 
 /// Request payload for stopping a sandbox
@@ -131,6 +151,26 @@ pub struct SandboxStartParams {
 pub struct SandboxStopParams {
     /// Sandbox name
     pub sandbox: String,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// Request payload for resizing a running sandbox
+#[derive(Debug, Deserialize)]
+pub struct SandboxResizeParams {
+    /// Sandbox name
+    pub sandbox: String,
+
+    /// The memory size to resize to in MiB
+    pub memory: u32,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
 }
 
 /// Request payload for getting sandbox metrics
@@ -138,6 +178,73 @@ pub struct SandboxStopParams {
 pub struct SandboxMetricsGetParams {
     /// Optional sandbox name - if not provided, all sandboxes will be included
     pub sandbox: Option<String>,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// Query parameters for streaming a file into a sandbox
+#[derive(Debug, Deserialize)]
+pub struct SandboxFileUploadQuery {
+    /// Path of the file in the sandbox
+    pub path: String,
+
+    /// Permission bits to apply to the file
+    pub mode: Option<u32>,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// Request payload for getting the resource usage history of a sandbox
+#[derive(Debug, Deserialize)]
+pub struct SandboxMetricsHistoryParams {
+    /// Sandbox name
+    pub sandbox: String,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// Request payload for running a command in a sandbox and waiting for its result
+#[derive(Debug, Deserialize)]
+pub struct SandboxExecParams {
+    /// Sandbox name
+    pub sandbox: String,
+
+    /// Command to execute
+    pub command: String,
+
+    /// Optional arguments for the command
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Optional environment variables for the command
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// Optional working directory for the command
+    #[serde(default)]
+    pub cwd: Option<String>,
+
+    /// Optional input written to the command's stdin
+    #[serde(default)]
+    pub stdin: Option<String>,
+
+    /// Optional timeout in seconds after which the command is killed
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    /// Optional project the sandbox belongs to, required when the name is defined in more than
+    /// one project
+    #[serde(default)]
+    pub project: Option<String>,
 }
 
 /// Configuration for a sandbox
@@ -181,6 +288,19 @@ pub struct SandboxConfig {
 
     /// The exec command to run
     pub exec: Option<String>,
+
+    /// The probe that has to succeed before the sandbox is reported as started
+    #[serde(default)]
+    pub readiness: Option<ReadinessProbe>,
+
+    /// Seconds the sandbox can go without an RPC or proxied request naming it before the server
+    /// stops it
+    #[serde(default)]
+    pub idle_timeout: Option<u64>,
+
+    /// Whether a proxied request for the sandbox starts it again once it has been stopped
+    #[serde(default)]
+    pub start_on_demand: bool,
     // SECURITY: Needs networking namespacing to be implemented
     // /// The network scope for the sandbox
     // pub scope: Option<String>,
@@ -225,6 +345,18 @@ pub struct SandboxCommandRunParams {
 
     /// Optional timeout in seconds
     pub timeout: Option<i32>,
+
+    /// Optional session whose working directory and environment the command runs with
+    #[serde(default)]
+    pub session: Option<String>,
+
+    /// Optional working directory, relative to the session's when running in a session
+    #[serde(default)]
+    pub cwd: Option<String>,
+
+    /// Optional environment variables, kept for later commands when running in a session
+    #[serde(default)]
+    pub env: HashMap<String, String>,
 }
 
 /// Request parameters for retrieving output from a previous command execution
@@ -234,6 +366,35 @@ pub struct SandboxCommandGetOutputParams {
     pub execution_id: String,
 }
 
+/// Result of a shell command as returned by the portal
+#[derive(Debug, Deserialize)]
+pub struct SandboxCommandRunResult {
+    /// Exit code of the command
+    pub exit_code: i32,
+
+    /// Output lines of the command, in the order they were written
+    #[serde(default)]
+    pub output: Vec<SandboxCommandOutputLine>,
+
+    /// Whether output was dropped for going over the portal's output limit
+    #[serde(default)]
+    pub truncated: bool,
+
+    /// Output bytes produced, including any that were dropped
+    #[serde(default)]
+    pub total_bytes: u64,
+}
+
+/// A line of output of a shell command as returned by the portal
+#[derive(Debug, Deserialize)]
+pub struct SandboxCommandOutputLine {
+    /// The stream the line was written to, `stdout` or `stderr`
+    pub stream: String,
+
+    /// The text of the line
+    pub text: String,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Methods
 //--------------------------------------------------------------------------------------------------
@@ -332,6 +493,77 @@ pub struct RegularMessageResponse {
     pub message: String,
 }
 
+/// Health check response describing each checked component
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    /// Overall status, either "healthy" or "unhealthy"
+    pub status: String,
+
+    /// Results of the individual component checks
+    pub components: Vec<ComponentHealth>,
+}
+
+/// Health of a single component checked by the health endpoint
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    /// The name of the component
+    pub name: String,
+
+    /// Whether the component is healthy
+    pub healthy: bool,
+
+    /// Why the component is unhealthy, if it is
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of starting one sandbox of a batch
+#[derive(Debug, Serialize)]
+pub struct SandboxStartBatchResult {
+    /// The name of the sandbox
+    pub sandbox: String,
+
+    /// Whether the sandbox was started
+    pub started: bool,
+
+    /// The outcome message, if the sandbox was started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+
+    /// Why the sandbox was not started, if it wasn't
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Result of streaming a file into a sandbox
+#[derive(Debug, Serialize)]
+pub struct SandboxFileUploadResult {
+    /// Number of bytes written to the file
+    pub written: u64,
+}
+
+/// Result of running a command in a sandbox
+#[derive(Debug, Serialize)]
+pub struct SandboxExecResult {
+    /// Exit code of the command
+    pub exit_code: i32,
+
+    /// Whether the command exited with code 0
+    pub success: bool,
+
+    /// What the command wrote to stdout
+    pub stdout: String,
+
+    /// What the command wrote to stderr
+    pub stderr: String,
+
+    /// Whether output was dropped for going over the portal's output limit
+    pub truncated: bool,
+
+    /// Output bytes produced, including any that were dropped
+    pub total_bytes: u64,
+}
+
 /// System status response
 #[derive(Debug, Serialize)]
 pub struct SystemStatusResponse {}
@@ -343,6 +575,19 @@ pub struct SandboxStatusResponse {
     pub sandboxes: Vec<SandboxStatus>,
 }
 
+/// Sandbox resource usage history response
+#[derive(Debug, Serialize)]
+pub struct SandboxMetricsHistoryResponse {
+    /// The name of the sandbox
+    pub sandbox: String,
+
+    /// Seconds between samples
+    pub interval_secs: u64,
+
+    /// The samples kept for the sandbox, oldest first
+    pub samples: Vec<MetricsSample>,
+}
+
 /// Sandbox configuration response
 #[derive(Debug, Serialize)]
 pub struct SandboxConfigResponse {}
@@ -353,6 +598,10 @@ pub struct SandboxStatus {
     /// The name of the sandbox
     pub name: String,
 
+    /// The project the sandbox belongs to, unless it is in the server's own project directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+
     /// Whether the sandbox is running
     pub running: bool,
 
@@ -366,10 +615,104 @@ pub struct SandboxStatus {
     pub disk_usage: Option<u64>,
 }
 
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl HealthResponse {
+    /// Creates a health response from the individual component checks
+    pub fn new(components: Vec<ComponentHealth>) -> Self {
+        let status = if components.iter().all(|c| c.healthy) {
+            "healthy"
+        } else {
+            "unhealthy"
+        };
+
+        Self {
+            status: status.to_string(),
+            components,
+        }
+    }
+
+    /// Whether all components are healthy
+    pub fn is_healthy(&self) -> bool {
+        self.components.iter().all(|c| c.healthy)
+    }
+}
+
+impl ComponentHealth {
+    /// Creates a component health entry from the result of its check
+    pub fn from_result<E: std::fmt::Display>(name: &str, result: Result<(), E>) -> Self {
+        Self {
+            name: name.to_string(),
+            healthy: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+impl SandboxExecResult {
+    /// Creates the result of a command from the portal's result, splitting its output lines back
+    /// into stdout and stderr
+    pub fn from_portal(result: SandboxCommandRunResult) -> Self {
+        let (mut stdout, mut stderr) = (String::new(), String::new());
+        for line in result.output {
+            let stream = if line.stream == "stderr" {
+                &mut stderr
+            } else {
+                &mut stdout
+            };
+            stream.push_str(&line.text);
+            stream.push('\n');
+        }
+
+        Self {
+            exit_code: result.exit_code,
+            success: result.exit_code == 0,
+            stdout,
+            stderr,
+            truncated: result.truncated,
+            total_bytes: result.total_bytes,
+        }
+    }
+}
+
+impl SandboxStartBatchResult {
+    /// Creates a batch entry from the result of starting the sandbox
+    pub fn new<E: std::fmt::Display>(sandbox: &str, result: Result<String, E>) -> Self {
+        match result {
+            Ok(message) => Self {
+                sandbox: sandbox.to_string(),
+                started: true,
+                message: Some(message),
+                error: None,
+            },
+            Err(e) => Self {
+                sandbox: sandbox.to_string(),
+                started: false,
+                message: None,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Trait Implementations
 //--------------------------------------------------------------------------------------------------
 
+impl axum::response::IntoResponse for HealthResponse {
+    fn into_response(self) -> axum::response::Response {
+        let status = if self.is_healthy() {
+            axum::http::StatusCode::OK
+        } else {
+            axum::http::StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        (status, axum::Json(self)).into_response()
+    }
+}
+
 impl axum::response::IntoResponse for JsonRpcResponseOrNotification {
     fn into_response(self) -> axum::response::Response {
         match self {
@@ -266,6 +266,11 @@ impl PortManager {
         self.mappings.get_port(key)
     }
 
+    /// Get the keys of all sandboxes that currently have a port assigned
+    pub fn sandbox_keys(&self) -> Vec<String> {
+        self.mappings.sandbox_to_port.keys().cloned().collect()
+    }
+
     /// Verify that a port is still available (not bound by something else)
     fn verify_port_availability(&self, port: u16) -> bool {
         let addr = SocketAddr::new(LOCALHOST_IP, port);
@@ -0,0 +1,355 @@
+//! Request rate limiting for the microsandbox server.
+//!
+//! This module handles:
+//! - Token-bucket rate limiting per client
+//! - Evicting the buckets of idle clients
+//! - Separate, stricter limits for sandbox start/stop requests
+//! - Rate limit configuration from flags and environment variables
+//!
+//! The module provides:
+//! - Rate limit configuration
+//! - A thread-safe rate limiter keyed by client and request class
+
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use getset::Getters;
+use microsandbox_utils::{
+    DEFAULT_LIFECYCLE_RATE_LIMIT_BURST, DEFAULT_LIFECYCLE_RATE_LIMIT_RPS, DEFAULT_RATE_LIMIT_BURST,
+    DEFAULT_RATE_LIMIT_RPS, LIFECYCLE_RATE_LIMIT_BURST_ENV_VAR, LIFECYCLE_RATE_LIMIT_RPS_ENV_VAR,
+    RATE_LIMIT_BURST_ENV_VAR, RATE_LIMIT_RPS_ENV_VAR,
+};
+use serde::Deserialize;
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How often buckets that have refilled completely are evicted
+const BUCKET_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Rate limits applied to each client of the server
+#[derive(Debug, Clone, Copy, Deserialize, Getters)]
+#[getset(get = "pub with_prefix")]
+pub struct RateLimitConfig {
+    /// Requests per second allowed for general requests
+    requests_per_second: f64,
+
+    /// Number of general requests that can be made in a burst
+    burst: u32,
+
+    /// Requests per second allowed for sandbox start/stop requests
+    lifecycle_requests_per_second: f64,
+
+    /// Number of sandbox start/stop requests that can be made in a burst
+    lifecycle_burst: u32,
+}
+
+/// The class of a request, each of which has its own bucket per client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    /// Read-only and other general requests
+    General,
+
+    /// Requests that start or stop sandboxes
+    Lifecycle,
+}
+
+/// Token-bucket rate limiter keyed by client and request class
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// The configured limits
+    config: RateLimitConfig,
+
+    /// The bucket for each client and request class
+    buckets: Mutex<Buckets>,
+}
+
+/// The token buckets of all clients
+#[derive(Debug)]
+struct Buckets {
+    /// The bucket for each client and request class
+    by_client: HashMap<(String, RequestClass), TokenBucket>,
+
+    /// When full buckets were last evicted
+    last_sweep: Instant,
+}
+
+/// A single token bucket
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens currently available
+    tokens: f64,
+
+    /// When the bucket was last refilled
+    last_refill: Instant,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl RateLimitConfig {
+    /// Create a new rate limit configuration
+    ///
+    /// Each value that isn't provided is read from its environment variable, falling back to
+    /// the default.
+    pub fn new(
+        requests_per_second: Option<f64>,
+        burst: Option<u32>,
+        lifecycle_requests_per_second: Option<f64>,
+        lifecycle_burst: Option<u32>,
+    ) -> Self {
+        Self {
+            requests_per_second: requests_per_second
+                .unwrap_or_else(|| env_or(RATE_LIMIT_RPS_ENV_VAR, DEFAULT_RATE_LIMIT_RPS)),
+            burst: burst
+                .unwrap_or_else(|| env_or(RATE_LIMIT_BURST_ENV_VAR, DEFAULT_RATE_LIMIT_BURST)),
+            lifecycle_requests_per_second: lifecycle_requests_per_second.unwrap_or_else(|| {
+                env_or(
+                    LIFECYCLE_RATE_LIMIT_RPS_ENV_VAR,
+                    DEFAULT_LIFECYCLE_RATE_LIMIT_RPS,
+                )
+            }),
+            lifecycle_burst: lifecycle_burst.unwrap_or_else(|| {
+                env_or(
+                    LIFECYCLE_RATE_LIMIT_BURST_ENV_VAR,
+                    DEFAULT_LIFECYCLE_RATE_LIMIT_BURST,
+                )
+            }),
+        }
+    }
+
+    /// Returns the requests per second and burst for a request class
+    fn limits_for(&self, class: RequestClass) -> (f64, u32) {
+        match class {
+            RequestClass::General => (self.requests_per_second, self.burst),
+            RequestClass::Lifecycle => (self.lifecycle_requests_per_second, self.lifecycle_burst),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Create a new rate limiter
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token for the given client and request class
+    ///
+    /// Returns how long the client should wait before retrying if its bucket is empty
+    pub fn check(&self, client: &str, class: RequestClass) -> Result<(), Duration> {
+        self.check_at(client, class, Instant::now())
+    }
+
+    /// Take a token for the given client and request class as of `now`
+    fn check_at(&self, client: &str, class: RequestClass, now: Instant) -> Result<(), Duration> {
+        let (rate, burst) = self.config.limits_for(class);
+        let capacity = burst.max(1) as f64;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.last_sweep) >= BUCKET_SWEEP_INTERVAL {
+            self.sweep(&mut buckets.by_client, now);
+            buckets.last_sweep = now;
+        }
+
+        let bucket = buckets
+            .by_client
+            .entry((client.to_string(), class))
+            .or_insert_with(|| TokenBucket {
+                tokens: capacity,
+                last_refill: now,
+            });
+
+        // Refill the bucket for the time elapsed since the last request
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if rate <= 0.0 {
+            return Err(Duration::MAX);
+        }
+
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+    }
+
+    /// Evict the buckets that have refilled completely as of `now`
+    ///
+    /// A full bucket behaves exactly like a new one, so clients that went idle lose nothing and
+    /// the map only holds clients that were recently active.
+    fn sweep(&self, buckets: &mut HashMap<(String, RequestClass), TokenBucket>, now: Instant) {
+        buckets.retain(|(_, class), bucket| {
+            let (rate, burst) = self.config.limits_for(*class);
+            let elapsed = now.saturating_duration_since(bucket.last_refill);
+            bucket.tokens + elapsed.as_secs_f64() * rate < burst.max(1) as f64
+        });
+    }
+
+    /// Returns the number of buckets currently held
+    #[cfg(test)]
+    fn bucket_count(&self) -> usize {
+        self.buckets.lock().unwrap().by_client.len()
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: DEFAULT_RATE_LIMIT_RPS,
+            burst: DEFAULT_RATE_LIMIT_BURST,
+            lifecycle_requests_per_second: DEFAULT_LIFECYCLE_RATE_LIMIT_RPS,
+            lifecycle_burst: DEFAULT_LIFECYCLE_RATE_LIMIT_BURST,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions: Helpers
+//--------------------------------------------------------------------------------------------------
+
+/// Read and parse an environment variable, falling back to a default if unset or invalid
+fn env_or<T: FromStr>(var: &str, default: T) -> T {
+    std::env::var(var)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::{StatusCode, header::RETRY_AFTER},
+        response::IntoResponse,
+    };
+
+    use super::*;
+    use crate::error::ServerError;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            requests_per_second: 10.0,
+            burst: 3,
+            lifecycle_requests_per_second: 1.0,
+            lifecycle_burst: 1,
+        })
+    }
+
+    #[test]
+    fn test_rate_limiter_exhausts_and_recovers() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at("key", RequestClass::General, now).is_ok());
+        }
+
+        let retry_after = limiter
+            .check_at("key", RequestClass::General, now)
+            .unwrap_err();
+        assert!(retry_after <= Duration::from_millis(100));
+
+        // Other clients have their own bucket
+        assert!(
+            limiter
+                .check_at("other", RequestClass::General, now)
+                .is_ok()
+        );
+
+        // One token is refilled after the window
+        let later = now + Duration::from_millis(100);
+        assert!(
+            limiter
+                .check_at("key", RequestClass::General, later)
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check_at("key", RequestClass::General, later)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_lifecycle_bucket_is_stricter() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(
+            limiter
+                .check_at("key", RequestClass::Lifecycle, now)
+                .is_ok()
+        );
+        let retry_after = limiter
+            .check_at("key", RequestClass::Lifecycle, now)
+            .unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+
+        // General requests are unaffected by the lifecycle bucket
+        assert!(limiter.check_at("key", RequestClass::General, now).is_ok());
+
+        let later = now + Duration::from_secs(1);
+        assert!(
+            limiter
+                .check_at("key", RequestClass::Lifecycle, later)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_evicts_idle_buckets() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        for client in 0..100 {
+            assert!(
+                limiter
+                    .check_at(&client.to_string(), RequestClass::General, now)
+                    .is_ok()
+            );
+        }
+        assert_eq!(limiter.bucket_count(), 100);
+
+        // Once the sweep interval has passed, every idle bucket has refilled and is evicted
+        let later = now + BUCKET_SWEEP_INTERVAL;
+        assert!(
+            limiter
+                .check_at("active", RequestClass::General, later)
+                .is_ok()
+        );
+        assert_eq!(limiter.bucket_count(), 1);
+    }
+
+    #[test]
+    fn test_rate_limited_response_has_retry_after() {
+        let response = ServerError::RateLimited(Duration::from_millis(1500)).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
+}
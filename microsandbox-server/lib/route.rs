@@ -10,46 +10,518 @@
 //! - Route handlers and middleware integration
 //! - State management for routes
 
+use std::{future::Future, path::PathBuf};
+
 use axum::{
-    Router, middleware,
-    routing::{get, post},
+    Router,
+    body::Body,
+    extract::{DefaultBodyLimit, Path, State},
+    http::{
+        HeaderName, HeaderValue, Method, Request,
+        header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE, PROXY_AUTHORIZATION},
+    },
+    middleware,
+    routing::{any, get, post, put},
 };
+use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, Any, CorsLayer};
 
-use crate::{handler, middleware as app_middleware, state::AppState};
+use crate::{
+    ServerResult, config::CorsConfig, handler, middleware as app_middleware,
+    payload::SandboxStartParams, state::AppState,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
 
 /// Create a new router with the given state
+///
+/// JSON-RPC and MCP requests are limited to the configured maximum body size, while file uploads
+/// are streamed to the sandbox and aren't.
 pub fn create_router(state: AppState) -> Router {
-    // Create REST API routes - only health endpoint remains here
-    let rest_api = Router::new().route("/health", get(handler::health));
+    let max_request_body = *state.get_config().get_max_request_body();
+
+    // Create REST API routes - readiness and liveness endpoints remain here
+    let rest_api = Router::new()
+        .route("/health", get(handler::health))
+        .route("/livez", get(handler::livez));
 
     // Create JSON-RPC routes with authentication - a single endpoint that handles all RPC methods
     // This now mirrors the structure used in microsandbox-portal
     let rpc_api = Router::new()
         .route("/", post(handler::json_rpc_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::activity_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::auth_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::rate_limit_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::body_limit_middleware,
+        ))
+        .layer(DefaultBodyLimit::max(max_request_body));
+
+    // Create file upload routes - the raw body is streamed to the sandbox in chunks
+    let upload_api = Router::new()
+        .route("/{sandbox}/files", put(handler::upload_file))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             app_middleware::auth_middleware,
-        ));
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::rate_limit_middleware,
+        ))
+        .layer(DefaultBodyLimit::disable());
+
+    // Create proxy routes - sandboxes that start on demand are started by the first request
+    let proxy_api = proxy_routes(&state, handler::start_sandbox);
 
     // Create MCP routes - separate endpoint for Model Context Protocol
     // Uses smart auth middleware that handles protocol vs tool methods differently
-    let mcp_api =
-        Router::new()
-            .route("/", post(handler::mcp_handler))
-            .layer(middleware::from_fn_with_state(
-                state.clone(),
-                app_middleware::mcp_smart_auth_middleware,
-            ));
+    let mcp_api = Router::new()
+        .route("/", post(handler::mcp_handler))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::activity_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::mcp_smart_auth_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::rate_limit_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::body_limit_middleware,
+        ))
+        .layer(DefaultBodyLimit::max(max_request_body));
 
-    // Combine all routes with logging middleware
+    // CORS is the outermost layer so preflight requests are answered without reaching handlers
+    let cors = cors_layer(
+        state.get_config().get_cors(),
+        *state.get_config().get_dev_mode(),
+    );
+
+    // Combine all routes with logging middleware, inside the request ID span
     Router::new()
         .nest("/api/v1", rest_api)
         .nest("/api/v1/rpc", rpc_api)
+        .nest("/api/v1/sandboxes", upload_api)
+        .nest("/proxy", proxy_api)
         .nest("/mcp", mcp_api)
         .layer(middleware::from_fn(app_middleware::logging_middleware))
+        .layer(middleware::from_fn(app_middleware::request_id_middleware))
+        .layer(cors)
         .with_state(state)
 }
+
+/// Create the routes that proxy requests to sandboxes, using `start` to start a stopped sandbox
+/// that starts on demand
+///
+/// A request for `/{sandbox}/{path}` is forwarded to `path` on the first port the sandbox
+/// publishes. Like file uploads, proxied bodies are streamed and not bound by the request body
+/// limit.
+pub(crate) fn proxy_routes<F, Fut>(state: &AppState, start: F) -> Router<AppState>
+where
+    F: FnOnce(AppState, SandboxStartParams) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = ServerResult<String>> + Send + 'static,
+{
+    Router::new()
+        .route(
+            "/{sandbox}/{*path}",
+            any(
+                move |State(state): State<AppState>,
+                      Path((sandbox, _)): Path<(String, PathBuf)>,
+                      req: Request<Body>| {
+                    handler::proxy_request_with(state, sandbox, req, start)
+                },
+            ),
+        )
+        .fallback(handler::proxy_fallback)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::auth_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_middleware::rate_limit_middleware,
+        ))
+        .layer(DefaultBodyLimit::disable())
+}
+
+/// Create the CORS layer from the configuration
+///
+/// Without configured origins, any origin is allowed in dev mode and none otherwise.
+pub fn cors_layer(config: &CorsConfig, dev_mode: bool) -> CorsLayer {
+    let origins = config.get_allowed_origins();
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::from(Any)
+    } else if !origins.is_empty() {
+        AllowOrigin::list(origins.iter().filter_map(|origin| {
+            HeaderValue::from_str(origin)
+                .inspect_err(|_| tracing::warn!("ignoring invalid CORS origin: {}", origin))
+                .ok()
+        }))
+    } else if dev_mode {
+        AllowOrigin::from(Any)
+    } else {
+        return CorsLayer::new();
+    };
+
+    let methods = config.get_allowed_methods();
+    let allow_methods = if methods.is_empty() {
+        // PUT uploads files, and requests proxied to a sandbox can use any of these
+        AllowMethods::list([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::OPTIONS,
+        ])
+    } else {
+        AllowMethods::list(methods.iter().filter_map(|method| {
+            Method::from_bytes(method.to_uppercase().as_bytes())
+                .inspect_err(|_| tracing::warn!("ignoring invalid CORS method: {}", method))
+                .ok()
+        }))
+    };
+
+    let headers = config.get_allowed_headers();
+    let allow_headers = if headers.is_empty() {
+        AllowHeaders::list([AUTHORIZATION, ACCEPT, CONTENT_TYPE, PROXY_AUTHORIZATION])
+    } else {
+        AllowHeaders::list(headers.iter().filter_map(|header| {
+            HeaderName::try_from(header.as_str())
+                .inspect_err(|_| tracing::warn!("ignoring invalid CORS header: {}", header))
+                .ok()
+        }))
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(allow_methods)
+        .allow_headers(allow_headers)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::{Duration, Instant},
+    };
+
+    use axum::http::{
+        StatusCode,
+        header::{ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN},
+    };
+    use microsandbox_utils::MICROSANDBOX_CONFIG_FILENAME;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        idle::IdlePolicy,
+        mocks::{test_state, test_state_with},
+    };
+
+    fn router(config: CorsConfig, dev_mode: bool) -> Router {
+        Router::new()
+            .route("/", post(|| async { "ok" }))
+            .layer(cors_layer(&config, dev_mode))
+    }
+
+    async fn allow_origin(router: Router, method: Method, origin: &str) -> Option<HeaderValue> {
+        let request = Request::builder()
+            .method(method)
+            .uri("/")
+            .header("Origin", origin)
+            .header("Access-Control-Request-Method", "POST")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).cloned()
+    }
+
+    #[tokio::test]
+    async fn test_cors_allows_listed_origin() {
+        let config = CorsConfig::new(vec!["https://app.example.com".to_string()], vec![], vec![]);
+
+        let header = allow_origin(
+            router(config.clone(), false),
+            Method::POST,
+            "https://app.example.com",
+        )
+        .await;
+        assert_eq!(header.unwrap(), "https://app.example.com");
+
+        // Preflight requests are answered by the layer
+        let header = allow_origin(
+            router(config, false),
+            Method::OPTIONS,
+            "https://app.example.com",
+        )
+        .await;
+        assert_eq!(header.unwrap(), "https://app.example.com");
+    }
+
+    #[tokio::test]
+    async fn test_cors_default_methods_allow_uploads() {
+        let config = CorsConfig::new(vec!["https://app.example.com".to_string()], vec![], vec![]);
+
+        // The preflight of a file upload lists PUT among the allowed methods
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header("Origin", "https://app.example.com")
+            .header("Access-Control-Request-Method", "PUT")
+            .body(Body::empty())
+            .unwrap();
+        let response = router(config, false).oneshot(request).await.unwrap();
+        let methods = response
+            .headers()
+            .get(ACCESS_CONTROL_ALLOW_METHODS)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("PUT"));
+        assert!(methods.contains("DELETE"));
+    }
+
+    #[tokio::test]
+    async fn test_cors_rejects_unlisted_origin() {
+        let config = CorsConfig::new(vec!["https://app.example.com".to_string()], vec![], vec![]);
+
+        let header =
+            allow_origin(router(config, false), Method::POST, "https://evil.example").await;
+        assert!(header.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cors_defaults_depend_on_dev_mode() {
+        let header = allow_origin(
+            router(CorsConfig::default(), true),
+            Method::POST,
+            "https://anything.example",
+        )
+        .await;
+        assert_eq!(header.unwrap(), "*");
+
+        let header = allow_origin(
+            router(CorsConfig::default(), false),
+            Method::POST,
+            "https://anything.example",
+        )
+        .await;
+        assert!(header.is_none());
+    }
+
+    async fn app(project_dir: &std::path::Path, max_request_body: Option<usize>) -> Router {
+        create_router(test_state_with(project_dir, None, max_request_body).await)
+    }
+
+    /// A router with the proxy routes, whose sandbox starts are counted instead of run
+    fn proxy_app(state: &AppState, starts: Arc<AtomicUsize>, policy: IdlePolicy) -> Router {
+        let start = move |state: AppState, params: SandboxStartParams| async move {
+            starts.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let project_dir = state.get_config().get_project_dir().clone();
+            state.get_idle_tracker().started(
+                &params.sandbox,
+                None,
+                &project_dir,
+                policy,
+                Instant::now(),
+            );
+            Ok(format!("Sandbox {} started successfully", params.sandbox))
+        };
+
+        Router::new()
+            .nest("/proxy", proxy_routes(state, start))
+            .with_state(state.clone())
+    }
+
+    async fn proxy_get(router: Router, uri: &str) -> (StatusCode, String) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        response_text(router.oneshot(request).await.unwrap()).await
+    }
+
+    async fn response_text(response: axum::response::Response) -> (StatusCode, String) {
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Serves an app on a local port that answers with the URI, whether the request was
+    /// authorized and the body of each request, returning the port
+    async fn serve_upstream() -> u16 {
+        let app = Router::new().fallback(|req: Request<Body>| async move {
+            let uri = req.uri().to_string();
+            let authorized = req.headers().contains_key(AUTHORIZATION);
+            let body = axum::body::to_bytes(req.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            format!("{} {} {}", uri, authorized, String::from_utf8_lossy(&body))
+        });
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        port
+    }
+
+    /// Writes a project config whose `web` sandbox publishes `port`, and whose `worker` sandbox
+    /// publishes none
+    fn write_proxy_config(project_dir: &std::path::Path, port: u16) {
+        let config = format!(
+            "sandboxes:\n  web:\n    image: alpine\n    ports:\n      - \"{}:80\"\n  worker:\n    image: alpine\n",
+            port
+        );
+        std::fs::write(project_dir.join(MICROSANDBOX_CONFIG_FILENAME), config).unwrap();
+    }
+
+    /// An MCP `tools/list` request padded to at least `size` bytes
+    fn padded_mcp_request(size: usize) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "tools/list",
+            "params": { "padding": "x".repeat(size) },
+            "id": 1,
+        })
+        .to_string()
+    }
+
+    async fn post_mcp(router: Router, body: String, content_length: bool) -> StatusCode {
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri("/mcp")
+            .header(CONTENT_TYPE, "application/json");
+        if content_length {
+            request = request.header("Content-Length", body.len());
+        }
+
+        let request = request.body(Body::from(body)).unwrap();
+        router.oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_request_body_limit_rejects_oversized_bodies() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let router = app(project_dir.path(), Some(1024)).await;
+
+        let status = post_mcp(router.clone(), padded_mcp_request(100), true).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Rejected from the announced length, before the body is read
+        let status = post_mcp(router.clone(), padded_mcp_request(2048), true).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Rejected while the body is read when no length is announced
+        let status = post_mcp(router, padded_mcp_request(2048), false).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_request_body_limit_default_is_generous() {
+        let project_dir = tempfile::tempdir().unwrap();
+        let router = app(project_dir.path(), None).await;
+
+        // Well over the 2 MiB axum allows by default
+        let status = post_mcp(router, padded_mcp_request(4 * 1024 * 1024), true).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_route_starts_stopped_on_demand_sandbox_once() {
+        let project_dir = tempfile::tempdir().unwrap();
+        write_proxy_config(project_dir.path(), serve_upstream().await);
+        let state = test_state(project_dir.path()).await;
+
+        // The sandbox starts on demand and has been stopped
+        let policy = IdlePolicy {
+            timeout: Some(Duration::from_secs(60)),
+            start_on_demand: true,
+        };
+        let tracker = state.get_idle_tracker();
+        tracker.started(
+            "web",
+            None,
+            project_dir.path(),
+            policy.clone(),
+            Instant::now(),
+        );
+        tracker.stopped("web");
+
+        let starts = Arc::new(AtomicUsize::new(0));
+        let router = proxy_app(&state, starts.clone(), policy.clone());
+
+        // Requests arriving during the cold start share a single start, then are forwarded
+        let (first, second) = tokio::join!(
+            proxy_get(router.clone(), "/proxy/web/index.html"),
+            proxy_get(router.clone(), "/proxy/web/app.js"),
+        );
+        assert_eq!(first, (StatusCode::OK, "/index.html false ".to_string()));
+        assert_eq!(second, (StatusCode::OK, "/app.js false ".to_string()));
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+
+        // Once running, the sandbox is served without starting it again
+        let (status, _) = proxy_get(router, "/proxy/web/index.html").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+
+        // After a server restart, the policy is loaded from disk and the sandbox is started again
+        let restarted = test_state(project_dir.path()).await;
+        let router = proxy_app(&restarted, starts.clone(), policy);
+        let (status, _) = proxy_get(router, "/proxy/web/index.html").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(starts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_route_forwards_requests_to_published_port() {
+        let project_dir = tempfile::tempdir().unwrap();
+        write_proxy_config(project_dir.path(), serve_upstream().await);
+        let state = test_state(project_dir.path()).await;
+        let starts = Arc::new(AtomicUsize::new(0));
+        let router = proxy_app(&state, starts.clone(), IdlePolicy::default());
+
+        // The path after the sandbox name, the query and the body are forwarded, but not the
+        // server's credentials
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/proxy/web/api/items?page=2")
+            .header(AUTHORIZATION, "Bearer msb_server_key")
+            .body(Body::from("hello"))
+            .unwrap();
+        let response = response_text(router.clone().oneshot(request).await.unwrap()).await;
+        assert_eq!(
+            response,
+            (StatusCode::OK, "/api/items?page=2 false hello".to_string())
+        );
+
+        // A sandbox publishing no ports can't be proxied to, and isn't started for it
+        let (status, _) = proxy_get(router, "/proxy/worker/index.html").await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(starts.load(Ordering::SeqCst), 0);
+    }
+}
@@ -10,17 +10,38 @@
 //! - State initialization and access methods
 //! - Configuration state management
 
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OnceCell, RwLock};
 
 use getset::Getters;
+use microsandbox_core::{
+    MicrosandboxResult,
+    management::{db, orchestra::ROOT_PROJECT_NAME},
+};
+use microsandbox_utils::{MICROSANDBOX_ENV_DIR, SANDBOX_DB_FILENAME};
+use sqlx::{Pool, Sqlite};
 
 use crate::{
     ServerError, ServerResult,
     config::Config,
+    idle::IdleTracker,
+    metrics::MetricsHistory,
     port::{LOCALHOST_IP, PortManager},
+    rate_limit::RateLimiter,
 };
 
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How long the result of a start request is remembered for its idempotency key
+const IDEMPOTENCY_KEY_TTL: Duration = Duration::from_secs(300);
+
 //--------------------------------------------------------------------------------------------------
 // Types
 //--------------------------------------------------------------------------------------------------
@@ -34,6 +55,38 @@ pub struct AppState {
 
     /// The port manager for handling sandbox port assignments
     port_manager: Arc<RwLock<PortManager>>,
+
+    /// The rate limiter for client requests
+    rate_limiter: Arc<RateLimiter>,
+
+    /// In-progress and recent start requests, keyed by sandbox key and idempotency key
+    start_requests: Arc<StartRequestCache>,
+
+    /// The rolling resource usage history of the sandboxes
+    metrics_history: Arc<MetricsHistory>,
+
+    /// The last activity of the sandboxes that have an idle timeout
+    idle_tracker: Arc<IdleTracker>,
+
+    /// The pool of the sandbox database in the project directory, created on first use
+    sandbox_db: Arc<OnceCell<Pool<Sqlite>>>,
+}
+
+/// Short-lived cache that dedupes sandbox start requests sharing an idempotency key
+#[derive(Debug, Default)]
+pub struct StartRequestCache {
+    /// The start requests, keyed by sandbox key and idempotency key
+    entries: Mutex<HashMap<(String, String), StartRequestEntry>>,
+}
+
+/// A start request tracked by the [`StartRequestCache`]
+#[derive(Debug)]
+struct StartRequestEntry {
+    /// When the first request with this key arrived
+    created_at: Instant,
+
+    /// The result of the start, shared by every request with this key
+    result: Arc<OnceCell<ServerResult<String>>>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -43,25 +96,225 @@ pub struct AppState {
 impl AppState {
     /// Create a new application state instance
     pub fn new(config: Arc<Config>, port_manager: Arc<RwLock<PortManager>>) -> Self {
+        let rate_limiter = Arc::new(RateLimiter::new(*config.get_rate_limit()));
+        let metrics_history = Arc::new(MetricsHistory::new(
+            *config.get_metrics_history().get_depth(),
+        ));
+        let idle_tracker = Arc::new(IdleTracker::load(config.get_project_dir()));
+
         Self {
             config,
             port_manager,
+            rate_limiter,
+            start_requests: Arc::new(StartRequestCache::default()),
+            metrics_history,
+            idle_tracker,
+            sandbox_db: Arc::new(OnceCell::new()),
         }
     }
 
+    /// Get the pool of the sandbox database in the project directory
+    ///
+    /// The pool is created, and the database migrated, on first use and reused afterwards. A
+    /// failed attempt is not remembered, so the next call tries again.
+    pub async fn sandbox_db_pool(&self) -> MicrosandboxResult<&Pool<Sqlite>> {
+        self.sandbox_db
+            .get_or_try_init(|| async {
+                let db_path = self
+                    .config
+                    .get_project_dir()
+                    .join(MICROSANDBOX_ENV_DIR)
+                    .join(SANDBOX_DB_FILENAME);
+                db::get_or_create_pool(&db_path, &db::SANDBOX_DB_MIGRATOR).await
+            })
+            .await
+    }
+
     /// Get a sandbox's portal URL
     ///
-    /// Returns an error if no port is assigned for the given sandbox
-    pub async fn get_portal_url_for_sandbox(&self, sandbox_name: &str) -> ServerResult<String> {
+    /// Returns an error if no port is assigned for the sandbox with the given key, see
+    /// [`sandbox_key`]
+    pub async fn get_portal_url_for_sandbox(&self, sandbox_key: &str) -> ServerResult<String> {
         let port_manager = self.port_manager.read().await;
 
-        if let Some(port) = port_manager.get_port(sandbox_name) {
+        if let Some(port) = port_manager.get_port(sandbox_key) {
             Ok(format!("http://{}:{}", LOCALHOST_IP, port))
         } else {
             Err(ServerError::InternalError(format!(
                 "No portal port assigned for sandbox {}",
-                sandbox_name
+                sandbox_key
             )))
         }
     }
+
+    /// Returns the name of the project in `project_dir`, relative to the server's project
+    /// directory, which is itself the project named [`ROOT_PROJECT_NAME`]
+    pub fn project_name(&self, project_dir: &Path) -> String {
+        match project_dir.strip_prefix(self.config.get_project_dir()) {
+            Ok(project) if !project.as_os_str().is_empty() => project.display().to_string(),
+            Ok(_) => ROOT_PROJECT_NAME.to_string(),
+            Err(_) => project_dir.display().to_string(),
+        }
+    }
+
+    /// Returns the directory of a project named as by [`AppState::project_name`]
+    pub fn project_dir(&self, project: &str) -> PathBuf {
+        let projects_dir = self.config.get_project_dir();
+        if project == ROOT_PROJECT_NAME {
+            projects_dir.clone()
+        } else {
+            projects_dir.join(project)
+        }
+    }
+
+    /// Returns the key of a sandbox in the project in `project_dir`, see [`sandbox_key`]
+    pub fn sandbox_key(&self, project_dir: &Path, sandbox: &str) -> String {
+        sandbox_key(&self.project_name(project_dir), sandbox)
+    }
+}
+
+impl StartRequestCache {
+    /// Run `start` for the given sandbox unless a request with the same idempotency key is in
+    /// progress or recently succeeded, in which case its result is returned instead.
+    ///
+    /// Failed starts are forgotten so that a retry with the same key tries again.
+    pub async fn run<F, Fut>(&self, sandbox: &str, key: &str, start: F) -> ServerResult<String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = ServerResult<String>>,
+    {
+        let entry_key = (sandbox.to_string(), key.to_string());
+        let cell = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.retain(|_, entry| entry.created_at.elapsed() < IDEMPOTENCY_KEY_TTL);
+            entries
+                .entry(entry_key.clone())
+                .or_insert_with(|| StartRequestEntry {
+                    created_at: Instant::now(),
+                    result: Arc::new(OnceCell::new()),
+                })
+                .result
+                .clone()
+        };
+
+        let result = cell.get_or_init(start).await.clone();
+
+        if result.is_err() {
+            let mut entries = self.entries.lock().unwrap();
+            if entries
+                .get(&entry_key)
+                .is_some_and(|entry| Arc::ptr_eq(&entry.result, &cell))
+            {
+                entries.remove(&entry_key);
+            }
+        }
+
+        result
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Returns the key the server tracks the portal port, idle timer and start requests of a sandbox
+/// under
+///
+/// A sandbox in the root project is keyed by its name, and a sandbox in any other project by
+/// `<project>/<name>`, so sandboxes sharing a name in different projects never share state.
+pub fn sandbox_key(project: &str, sandbox: &str) -> String {
+    if project == ROOT_PROJECT_NAME {
+        sandbox.to_string()
+    } else {
+        format!("{}/{}", project, sandbox)
+    }
+}
+
+/// Splits a key made by [`sandbox_key`] into the project and name of its sandbox
+pub fn split_sandbox_key(key: &str) -> (&str, &str) {
+    key.rsplit_once('/').unwrap_or((ROOT_PROJECT_NAME, key))
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{http::StatusCode, response::IntoResponse};
+
+    use super::*;
+
+    #[test]
+    fn test_sandbox_key_separates_projects() {
+        assert_eq!(sandbox_key(ROOT_PROJECT_NAME, "web"), "web");
+        assert_eq!(sandbox_key("shop", "web"), "shop/web");
+        assert_ne!(sandbox_key("shop", "web"), sandbox_key("blog", "web"));
+
+        assert_eq!(split_sandbox_key("web"), (ROOT_PROJECT_NAME, "web"));
+        assert_eq!(split_sandbox_key("shop/web"), ("shop", "web"));
+    }
+
+    #[tokio::test]
+    async fn test_start_request_cache_dedupes_concurrent_starts() {
+        let cache = StartRequestCache::default();
+        let ups = &AtomicUsize::new(0);
+
+        let start = || async move {
+            ups.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok("Sandbox test started successfully".to_string())
+        };
+
+        let (first, second) = tokio::join!(
+            cache.run("test", "key-1", start),
+            cache.run("test", "key-1", start)
+        );
+
+        assert_eq!(first.unwrap(), "Sandbox test started successfully");
+        assert_eq!(second.unwrap(), "Sandbox test started successfully");
+        assert_eq!(ups.load(Ordering::SeqCst), 1);
+
+        // A different key starts again
+        cache.run("test", "key-2", start).await.unwrap();
+        assert_eq!(ups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_start_request_cache_forgets_failures() {
+        let cache = StartRequestCache::default();
+        let ups = &AtomicUsize::new(0);
+
+        let failing = || async move {
+            ups.fetch_add(1, Ordering::SeqCst);
+            Err(ServerError::InternalError("boom".to_string()))
+        };
+
+        assert!(cache.run("test", "key", failing).await.is_err());
+        assert!(cache.run("test", "key", failing).await.is_err());
+        assert_eq!(ups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_start_request_cache_replays_original_error() {
+        let cache = StartRequestCache::default();
+
+        let timing_out = || async {
+            Err(ServerError::SandboxStartTimeout(
+                "sandbox test did not start within 30s".to_string(),
+            ))
+        };
+
+        let (first, second) = tokio::join!(
+            cache.run("test", "key", timing_out),
+            cache.run("test", "key", timing_out)
+        );
+
+        for result in [first, second] {
+            let response = result.unwrap_err().into_response();
+            assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        }
+    }
 }
@@ -24,19 +24,52 @@ use crate::MICROSANDBOX_HOME_DIR;
 /// The default maximum log file size (10MB)
 pub const DEFAULT_LOG_MAX_SIZE: u64 = 10 * 1024 * 1024;
 
+/// The default number of rotated log files kept next to the active log file
+pub const DEFAULT_LOG_MAX_FILES: usize = 1;
+
 /// The default number of vCPUs to use for the MicroVm.
+///
+/// Sandboxes that don't set `cpus` get this unless `MSB_DEFAULT_CPUS` overrides it, see
+/// [`get_default_num_vcpus`](crate::env::get_default_num_vcpus).
 pub const DEFAULT_NUM_VCPUS: u8 = 1;
 
 /// The default amount of memory in MiB to use for the MicroVm.
+///
+/// Sandboxes that don't set `memory` get this unless `MSB_DEFAULT_MEMORY_MIB` overrides it, see
+/// [`get_default_memory_mib`](crate::env::get_default_memory_mib).
 pub const DEFAULT_MEMORY_MIB: u32 = 1024;
 
 /// The path where all microsandbox global data is stored.
 pub static DEFAULT_MICROSANDBOX_HOME: LazyLock<PathBuf> =
     LazyLock::new(|| dirs::home_dir().unwrap().join(MICROSANDBOX_HOME_DIR));
 
+/// The default number of requests per second a client can make to the server.
+pub const DEFAULT_RATE_LIMIT_RPS: f64 = 20.0;
+
+/// The default number of requests a client can burst to the server.
+pub const DEFAULT_RATE_LIMIT_BURST: u32 = 40;
+
+/// The default number of sandbox start/stop requests per second a client can make to the server.
+pub const DEFAULT_LIFECYCLE_RATE_LIMIT_RPS: f64 = 1.0;
+
+/// The default number of sandbox start/stop requests a client can burst to the server.
+pub const DEFAULT_LIFECYCLE_RATE_LIMIT_BURST: u32 = 5;
+
+/// The default number of metrics samples the server keeps per sandbox.
+pub const DEFAULT_METRICS_HISTORY_DEPTH: usize = 120;
+
+/// The default maximum size, in bytes, of a JSON-RPC or MCP request body the server accepts.
+pub const DEFAULT_MAX_REQUEST_BODY_BYTES: usize = 32 * 1024 * 1024;
+
 /// The default OCI registry domain.
 pub const DEFAULT_OCI_REGISTRY: &str = "docker.io";
 
+/// The default number of seconds a registry has to accept a connection within.
+pub const DEFAULT_OCI_REGISTRY_CONNECT_TIMEOUT_SECS: u64 = 30;
+
+/// The default number of seconds a registry may go without sending any data on a request.
+pub const DEFAULT_OCI_REGISTRY_READ_TIMEOUT_SECS: u64 = 60;
+
 /// The default OCI reference tag.
 pub const DEFAULT_OCI_REFERENCE_TAG: &str = "latest";
 
@@ -74,3 +107,12 @@ pub const DEFAULT_SERVER_PORT: u16 = 5555;
 
 /// The default microsandbox-portal port.
 pub const DEFAULT_PORTAL_GUEST_PORT: u16 = 4444;
+
+/// The JSON-RPC error code the microsandbox-portal returns for a path that does not exist.
+pub const PORTAL_NOT_FOUND_ERROR_CODE: i32 = -32004;
+
+/// The default number of seconds between readiness probe attempts.
+pub const DEFAULT_READINESS_INTERVAL_SECS: u64 = 1;
+
+/// The default number of seconds a readiness probe has to succeed within.
+pub const DEFAULT_READINESS_TIMEOUT_SECS: u64 = 60;
@@ -1,8 +1,12 @@
 //! Utility functions for working with environment variables.
 
-use std::path::PathBuf;
+use std::{path::PathBuf, sync::OnceLock, time::Duration};
 
-use crate::{DEFAULT_MICROSANDBOX_HOME, DEFAULT_OCI_REGISTRY};
+use crate::{
+    DEFAULT_LOG_MAX_FILES, DEFAULT_LOG_MAX_SIZE, DEFAULT_MEMORY_MIB, DEFAULT_MICROSANDBOX_HOME,
+    DEFAULT_NUM_VCPUS, DEFAULT_OCI_REGISTRY, DEFAULT_OCI_REGISTRY_CONNECT_TIMEOUT_SECS,
+    DEFAULT_OCI_REGISTRY_READ_TIMEOUT_SECS, SECRETS_FILE,
+};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -14,12 +18,122 @@ pub const MICROSANDBOX_HOME_ENV_VAR: &str = "MICROSANDBOX_HOME";
 /// Environment variable for the OCI registry domain
 pub const OCI_REGISTRY_ENV_VAR: &str = "OCI_REGISTRY_DOMAIN";
 
+/// Environment variable for a comma-separated list of OCI registry hosts that are pulled from
+/// over plain HTTP, e.g. `localhost:5000,registry.internal`
+pub const OCI_INSECURE_REGISTRIES_ENV_VAR: &str = "OCI_INSECURE_REGISTRIES";
+
+/// Environment variable for a comma-separated list of `host=path` pairs pointing at extra CA
+/// certificates to trust for a given OCI registry host
+pub const OCI_REGISTRY_CA_CERTS_ENV_VAR: &str = "OCI_REGISTRY_CA_CERTS";
+
+/// Environment variable for the seconds an OCI registry has to accept a connection within
+pub const OCI_REGISTRY_CONNECT_TIMEOUT_ENV_VAR: &str = "OCI_REGISTRY_CONNECT_TIMEOUT_SECS";
+
+/// Environment variable for the seconds an OCI registry may go without sending any data on a
+/// request
+pub const OCI_REGISTRY_READ_TIMEOUT_ENV_VAR: &str = "OCI_REGISTRY_READ_TIMEOUT_SECS";
+
+/// Environment variable for a whitespace-separated list of `pattern=replacement` rules that
+/// rewrite image references before they are pulled, e.g. `docker.io/=mirror.corp/dockerhub/`
+pub const OCI_IMAGE_REWRITES_ENV_VAR: &str = "OCI_IMAGE_REWRITES";
+
+/// Environment variable for the path to a file of image rewrite rules, one `pattern=replacement`
+/// rule per line
+pub const OCI_IMAGE_REWRITES_FILE_ENV_VAR: &str = "OCI_IMAGE_REWRITES_FILE";
+
+/// Environment variable that, when set to `1` or `true`, keeps downloaded layer blobs in a shared
+/// cache so images sharing a layer only download it once
+pub const OCI_BLOB_CACHE_ENV_VAR: &str = "OCI_BLOB_CACHE";
+
+/// Environment variable that, when set to `1` or `true`, extracts image layers straight from the
+/// registry response instead of downloading them to a file first
+pub const OCI_STREAM_LAYERS_ENV_VAR: &str = "OCI_STREAM_LAYERS";
+
+/// Environment variable for the directory temporary image download directories are created in
+pub const OCI_DOWNLOAD_DIR_ENV_VAR: &str = "OCI_DOWNLOAD_DIR";
+
+/// Environment variable for the file `secret://` environment references are resolved against
+pub const MICROSANDBOX_SECRETS_FILE_ENV_VAR: &str = "MICROSANDBOX_SECRETS_FILE";
+
+/// Environment variable for the requests per second a client can make to the server
+pub const RATE_LIMIT_RPS_ENV_VAR: &str = "MSB_RATE_LIMIT_RPS";
+
+/// Environment variable for the requests a client can burst to the server
+pub const RATE_LIMIT_BURST_ENV_VAR: &str = "MSB_RATE_LIMIT_BURST";
+
+/// Environment variable for the sandbox start/stop requests per second a client can make
+pub const LIFECYCLE_RATE_LIMIT_RPS_ENV_VAR: &str = "MSB_LIFECYCLE_RATE_LIMIT_RPS";
+
+/// Environment variable for the sandbox start/stop requests a client can burst
+pub const LIFECYCLE_RATE_LIMIT_BURST_ENV_VAR: &str = "MSB_LIFECYCLE_RATE_LIMIT_BURST";
+
+/// Environment variable for how often, in seconds, the server samples sandbox metrics into its
+/// history
+pub const METRICS_HISTORY_INTERVAL_ENV_VAR: &str = "MSB_METRICS_HISTORY_INTERVAL";
+
+/// Environment variable for how many metrics samples the server keeps per sandbox
+pub const METRICS_HISTORY_DEPTH_ENV_VAR: &str = "MSB_METRICS_HISTORY_DEPTH";
+
+/// Environment variable for the maximum size, in bytes, of a JSON-RPC or MCP request body
+pub const MAX_REQUEST_BODY_ENV_VAR: &str = "MSB_MAX_REQUEST_BODY";
+
+/// Environment variable for a comma-separated list of origins allowed to call the server
+pub const CORS_ALLOWED_ORIGINS_ENV_VAR: &str = "MSB_CORS_ALLOWED_ORIGINS";
+
+/// Environment variable for a comma-separated list of methods allowed in cross-origin requests
+pub const CORS_ALLOWED_METHODS_ENV_VAR: &str = "MSB_CORS_ALLOWED_METHODS";
+
+/// Environment variable for a comma-separated list of headers allowed in cross-origin requests
+pub const CORS_ALLOWED_HEADERS_ENV_VAR: &str = "MSB_CORS_ALLOWED_HEADERS";
+
+/// Environment variable for the URL of the sandbox server used by clients
+pub const MSB_SERVER_URL_ENV_VAR: &str = "MSB_SERVER_URL";
+
+/// Environment variable for the API key clients send to the sandbox server
+pub const MSB_API_KEY_ENV_VAR: &str = "MSB_API_KEY";
+
+/// Environment variable for the size in bytes a log file is rotated at
+pub const LOG_MAX_SIZE_ENV_VAR: &str = "MSB_LOG_MAX_SIZE";
+
+/// Environment variable for the number of rotated log files kept next to the active one
+pub const LOG_MAX_FILES_ENV_VAR: &str = "MSB_LOG_MAX_FILES";
+
+/// Environment variable for how sandboxes that exceed the host's resources are handled,
+/// either `warn` or `error`
+pub const RESOURCE_CHECK_ENV_VAR: &str = "MSB_RESOURCE_CHECK";
+
+/// Environment variable for how inherited environment variables that aren't set on the host are
+/// handled, either `warn` or `error`
+pub const INHERITED_ENV_CHECK_ENV_VAR: &str = "MSB_INHERITED_ENV_CHECK";
+
+/// Environment variable for where sandboxes that don't set `dns` get their DNS resolvers from,
+/// either `public` or `inherit`
+pub const DEFAULT_DNS_ENV_VAR: &str = "MSB_DEFAULT_DNS";
+
+/// Environment variable for the memory in MiB given to sandboxes that don't set `memory`
+pub const DEFAULT_MEMORY_MIB_ENV_VAR: &str = "MSB_DEFAULT_MEMORY_MIB";
+
+/// Environment variable for the number of vCPUs given to sandboxes that don't set `cpus`
+pub const DEFAULT_NUM_VCPUS_ENV_VAR: &str = "MSB_DEFAULT_CPUS";
+
+/// Environment variable for how many sandboxes are started at once when a project is brought up
+pub const MAX_CONCURRENT_SANDBOXES_ENV_VAR: &str = "MSB_MAX_CONCURRENT_SANDBOXES";
+
 /// Environment variable for the msbrun binary path
 pub const MSBRUN_EXE_ENV_VAR: &str = "MSBRUN_EXE";
 
+/// Environment variable msbrun takes a sandbox's environment variables from, as a JSON array of
+/// `KEY=VALUE` strings. Unlike its arguments, a process's environment can only be read by its
+/// own user, so resolved secrets are never put on msbrun's command line.
+pub const MSBRUN_ENVS_ENV_VAR: &str = "MSBRUN_ENVS";
+
 /// Environment variable for the msbserver binary path
 pub const MSBSERVER_EXE_ENV_VAR: &str = "MSBSERVER_EXE";
 
+/// The OCI registry domain set with [`set_oci_registry`], which takes precedence over the
+/// environment
+static OCI_REGISTRY_OVERRIDE: OnceLock<String> = OnceLock::new();
+
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
@@ -36,12 +150,290 @@ pub fn get_microsandbox_home_path() -> PathBuf {
 }
 
 /// Returns the domain for the OCI registry.
+/// If a domain was set with [`set_oci_registry`], returns that value.
 /// If the OCI_REGISTRY_DOMAIN environment variable is set, returns that value.
 /// Otherwise, returns the default OCI registry domain.
 pub fn get_oci_registry() -> String {
-    if let Ok(oci_registry_domain) = std::env::var(OCI_REGISTRY_ENV_VAR) {
+    if let Some(oci_registry_domain) = get_oci_registry_override() {
+        oci_registry_domain.to_string()
+    } else if let Ok(oci_registry_domain) = std::env::var(OCI_REGISTRY_ENV_VAR) {
         oci_registry_domain
     } else {
         DEFAULT_OCI_REGISTRY.to_string()
     }
 }
+
+/// Sets the domain for the OCI registry for the rest of this process, taking precedence over the
+/// OCI_REGISTRY_DOMAIN environment variable it was started with.
+///
+/// Only the first call takes effect. The environment is left untouched, so processes started
+/// afterwards have to be passed [`get_oci_registry_override`] themselves.
+pub fn set_oci_registry(registry: &str) {
+    let _ = OCI_REGISTRY_OVERRIDE.set(registry.to_string());
+}
+
+/// Returns the domain for the OCI registry set with [`set_oci_registry`], if any.
+pub fn get_oci_registry_override() -> Option<&'static str> {
+    OCI_REGISTRY_OVERRIDE.get().map(String::as_str)
+}
+
+/// Returns the OCI registry hosts that should be pulled from over plain HTTP.
+/// If the OCI_INSECURE_REGISTRIES environment variable is set, returns its comma-separated hosts.
+/// Otherwise, returns an empty list.
+pub fn get_oci_insecure_registries() -> Vec<String> {
+    std::env::var(OCI_INSECURE_REGISTRIES_ENV_VAR)
+        .map(|hosts| {
+            hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the extra CA certificates to trust for each OCI registry host.
+/// If the OCI_REGISTRY_CA_CERTS environment variable is set, returns its `host=path` pairs.
+/// Otherwise, returns an empty list.
+pub fn get_oci_registry_ca_certs() -> Vec<(String, PathBuf)> {
+    std::env::var(OCI_REGISTRY_CA_CERTS_ENV_VAR)
+        .map(|pairs| {
+            pairs
+                .split(',')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(host, path)| (host.trim().to_string(), PathBuf::from(path.trim())))
+                .filter(|(host, _)| !host.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns how long an OCI registry has to accept a connection within.
+/// If the OCI_REGISTRY_CONNECT_TIMEOUT_SECS environment variable is set to a positive number of
+/// seconds, returns that value. Otherwise, returns the default connect timeout.
+pub fn get_oci_registry_connect_timeout() -> Duration {
+    get_timeout_secs(
+        OCI_REGISTRY_CONNECT_TIMEOUT_ENV_VAR,
+        DEFAULT_OCI_REGISTRY_CONNECT_TIMEOUT_SECS,
+    )
+}
+
+/// Returns how long an OCI registry may go without sending any data on a request.
+/// If the OCI_REGISTRY_READ_TIMEOUT_SECS environment variable is set to a positive number of
+/// seconds, returns that value. Otherwise, returns the default read timeout.
+pub fn get_oci_registry_read_timeout() -> Duration {
+    get_timeout_secs(
+        OCI_REGISTRY_READ_TIMEOUT_ENV_VAR,
+        DEFAULT_OCI_REGISTRY_READ_TIMEOUT_SECS,
+    )
+}
+
+/// Returns the rules that rewrite image references before they are pulled.
+/// If the OCI_IMAGE_REWRITES environment variable is set, returns its whitespace-separated rules.
+/// Otherwise, returns an empty list.
+pub fn get_oci_image_rewrites() -> Vec<String> {
+    std::env::var(OCI_IMAGE_REWRITES_ENV_VAR)
+        .map(|rules| split_image_rewrites(&rules))
+        .unwrap_or_default()
+}
+
+/// Returns the path to the file of rules that rewrite image references before they are pulled.
+/// If the OCI_IMAGE_REWRITES_FILE environment variable is set, returns that path.
+/// Otherwise, returns None and only the rules in OCI_IMAGE_REWRITES apply.
+pub fn get_oci_image_rewrites_file() -> Option<PathBuf> {
+    std::env::var(OCI_IMAGE_REWRITES_FILE_ENV_VAR)
+        .ok()
+        .filter(|path| !path.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns whether downloaded layer blobs should be kept in the shared blob cache.
+/// If the OCI_BLOB_CACHE environment variable is set to `1` or `true`, returns true.
+/// Otherwise, returns false.
+pub fn is_oci_blob_cache_enabled() -> bool {
+    std::env::var(OCI_BLOB_CACHE_ENV_VAR)
+        .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Returns whether image layers should be extracted straight from the registry response.
+/// If the OCI_STREAM_LAYERS environment variable is set to `1` or `true`, returns true.
+/// Otherwise, returns false.
+pub fn is_oci_stream_layers_enabled() -> bool {
+    std::env::var(OCI_STREAM_LAYERS_ENV_VAR)
+        .map(|value| matches!(value.trim().to_lowercase().as_str(), "1" | "true"))
+        .unwrap_or(false)
+}
+
+/// Returns the directory temporary image download directories are created in.
+/// If the OCI_DOWNLOAD_DIR environment variable is set, returns that path.
+/// Otherwise, returns None and the system temporary directory is used.
+pub fn get_oci_download_dir() -> Option<PathBuf> {
+    std::env::var(OCI_DOWNLOAD_DIR_ENV_VAR)
+        .ok()
+        .filter(|dir| !dir.trim().is_empty())
+        .map(PathBuf::from)
+}
+
+/// Returns the path to the file `secret://` environment references are resolved against.
+/// If the MICROSANDBOX_SECRETS_FILE environment variable is set, returns that path.
+/// Otherwise, returns the secrets file in the microsandbox home directory.
+pub fn get_secrets_file_path() -> PathBuf {
+    if let Ok(secrets_file) = std::env::var(MICROSANDBOX_SECRETS_FILE_ENV_VAR) {
+        PathBuf::from(secrets_file)
+    } else {
+        get_microsandbox_home_path().join(SECRETS_FILE)
+    }
+}
+
+/// Returns the size in bytes a log file is rotated at.
+/// If the MSB_LOG_MAX_SIZE environment variable is set to a positive number, returns that value.
+/// Otherwise, returns the default maximum log file size.
+pub fn get_log_max_size() -> u64 {
+    std::env::var(LOG_MAX_SIZE_ENV_VAR)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(DEFAULT_LOG_MAX_SIZE)
+}
+
+/// Returns the number of rotated log files kept next to the active one.
+/// If the MSB_LOG_MAX_FILES environment variable is set to a number, returns that value.
+/// Otherwise, returns the default number of rotated log files.
+pub fn get_log_max_files() -> usize {
+    std::env::var(LOG_MAX_FILES_ENV_VAR)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_LOG_MAX_FILES)
+}
+
+/// Returns the memory in MiB given to sandboxes that don't set `memory`.
+/// If the MSB_DEFAULT_MEMORY_MIB environment variable is set to a positive number, returns that value.
+/// Otherwise, returns the built-in default memory.
+pub fn get_default_memory_mib() -> u32 {
+    parse_positive(std::env::var(DEFAULT_MEMORY_MIB_ENV_VAR).ok().as_deref())
+        .unwrap_or(DEFAULT_MEMORY_MIB)
+}
+
+/// Returns the number of vCPUs given to sandboxes that don't set `cpus`.
+/// If the MSB_DEFAULT_CPUS environment variable is set to a positive number, returns that value.
+/// Otherwise, returns the built-in default number of vCPUs.
+pub fn get_default_num_vcpus() -> u8 {
+    parse_positive(std::env::var(DEFAULT_NUM_VCPUS_ENV_VAR).ok().as_deref())
+        .unwrap_or(DEFAULT_NUM_VCPUS)
+}
+
+/// Returns how many sandboxes are started at once when a project is brought up.
+/// If the MSB_MAX_CONCURRENT_SANDBOXES environment variable is set to a positive number, returns
+/// that value. Otherwise, returns None, leaving the limit to be derived from the host.
+pub fn get_max_concurrent_sandboxes() -> Option<usize> {
+    std::env::var(MAX_CONCURRENT_SANDBOXES_ENV_VAR)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|max| *max > 0)
+}
+
+/// Parses a positive number, returning None when the value is missing or isn't one.
+fn parse_positive<T>(value: Option<&str>) -> Option<T>
+where
+    T: std::str::FromStr + Default + PartialOrd,
+{
+    value
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|number| *number > T::default())
+}
+
+/// Splits a list of image rewrite rules.
+///
+/// Rules are separated by whitespace only, which image references never contain. Commas are
+/// kept as part of a rule, since `regex:` patterns may contain them, e.g. in `\d{1,3}`.
+fn split_image_rewrites(rules: &str) -> Vec<String> {
+    rules.split_whitespace().map(String::from).collect()
+}
+
+/// Reads a positive number of seconds from an environment variable, falling back to `default`.
+fn get_timeout_secs(env_var: &str, default: u64) -> Duration {
+    parse_timeout_secs(std::env::var(env_var).ok().as_deref(), default)
+}
+
+/// Parses a positive number of seconds, falling back to `default` when the value is missing or
+/// isn't one.
+fn parse_timeout_secs(value: Option<&str>, default: u64) -> Duration {
+    let secs = value
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(default);
+
+    Duration::from_secs(secs)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_resources_fall_back_from_env_to_built_in() {
+        assert_eq!(parse_positive::<u32>(Some("2048")), Some(2048));
+        assert_eq!(parse_positive::<u8>(Some(" 4 ")), Some(4));
+
+        // Values that aren't positive numbers are ignored
+        assert_eq!(parse_positive::<u32>(None), None);
+        assert_eq!(parse_positive::<u32>(Some("0")), None);
+        assert_eq!(parse_positive::<u8>(Some("lots")), None);
+        assert_eq!(parse_positive::<u8>(Some("300")), None);
+    }
+
+    #[test]
+    fn test_oci_registry_timeouts_fall_back_from_env_to_built_in() {
+        assert_eq!(parse_timeout_secs(Some(" 5 "), 30), Duration::from_secs(5));
+        assert_eq!(
+            parse_timeout_secs(Some("120"), 30),
+            Duration::from_secs(120)
+        );
+
+        // Values that aren't positive numbers of seconds are ignored
+        assert_eq!(parse_timeout_secs(None, 30), Duration::from_secs(30));
+        assert_eq!(parse_timeout_secs(Some("0"), 30), Duration::from_secs(30));
+        assert_eq!(parse_timeout_secs(Some("1m"), 30), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_image_rewrites_split_on_whitespace_only() {
+        // Prefix and regex rules mixed, separated by spaces, tabs and newlines
+        let rules = "docker.io/*=mirror.corp/dockerhub/*\tquay.io/=mirror.corp/quay/\n\
+                     regex:^10\\.\\d{1,3}/(.+)$=mirror.corp/$1  ghcr.io/=mirror.corp/ghcr/";
+        assert_eq!(
+            split_image_rewrites(rules),
+            vec![
+                "docker.io/*=mirror.corp/dockerhub/*",
+                "quay.io/=mirror.corp/quay/",
+                r"regex:^10\.\d{1,3}/(.+)$=mirror.corp/$1",
+                "ghcr.io/=mirror.corp/ghcr/",
+            ]
+        );
+
+        // Commas don't separate rules of any kind
+        assert_eq!(split_image_rewrites("a/=b/,c/=d/"), vec!["a/=b/,c/=d/"]);
+        assert!(split_image_rewrites(" \n ").is_empty());
+    }
+
+    #[test]
+    fn test_oci_registry_prefers_override_over_env() {
+        // `msb --registry` takes precedence over whatever the environment holds, without
+        // changing it
+        let env_registry = std::env::var(OCI_REGISTRY_ENV_VAR).ok();
+        set_oci_registry("flag.example");
+        assert_eq!(get_oci_registry_override(), Some("flag.example"));
+        assert_eq!(get_oci_registry(), "flag.example");
+        assert_eq!(std::env::var(OCI_REGISTRY_ENV_VAR).ok(), env_registry);
+
+        // Only the first override takes effect
+        set_oci_registry("other.example");
+        assert_eq!(get_oci_registry(), "flag.example");
+    }
+}
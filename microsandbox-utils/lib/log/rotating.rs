@@ -2,9 +2,11 @@
 //!
 //! This module provides a rotating log implementation that automatically rotates log files
 //! when they reach a specified size. The rotation process involves:
-//! 1. Renaming the current log file to .old extension
-//! 2. Creating a new empty log file
-//! 3. Continuing writing to the new file
+//! 1. Shifting the rotated files up by one (`.old` to `.old.1`, `.old.1` to `.old.2`, ...)
+//! 2. Deleting rotated files beyond the retained count
+//! 3. Renaming the current log file to .old extension
+//! 4. Creating a new empty log file
+//! 5. Continuing writing to the new file
 //!
 //! The implementation is fully asynchronous and implements AsyncWrite.
 
@@ -26,7 +28,7 @@ use tokio::{
     task::JoinHandle,
 };
 
-use crate::DEFAULT_LOG_MAX_SIZE;
+use crate::{DEFAULT_LOG_MAX_FILES, env};
 
 //--------------------------------------------------------------------------------------------------
 // Types
@@ -34,8 +36,9 @@ use crate::DEFAULT_LOG_MAX_SIZE;
 
 /// A rotating log file that automatically rotates when reaching a maximum size.
 ///
-/// The log rotation process preserves the last full log file with a ".old" extension
-/// while continuing to write to a new log file with the original name.
+/// The log rotation process preserves the most recent full log files with ".old", ".old.1",
+/// ".old.2", ... extensions, newest first, while continuing to write to a new log file with the
+/// original name.
 ///
 /// # Example
 ///
@@ -44,7 +47,7 @@ use crate::DEFAULT_LOG_MAX_SIZE;
 ///
 /// #[tokio::main]
 /// async fn main() -> std::io::Result<()> {
-///     let log = RotatingLog::new("app.log").await?; // 1MB max size
+///     let log = RotatingLog::new("app.log").await?; // Size and retention from the environment
 ///     Ok(())
 /// }
 /// ```
@@ -58,6 +61,9 @@ pub struct RotatingLog {
     /// Maximum size in bytes before rotation
     max_size: u64,
 
+    /// Number of rotated log files to keep
+    max_files: usize,
+
     /// Current size of the log file (shared between sync and async paths)
     current_size: Arc<AtomicU64>,
 
@@ -95,10 +101,11 @@ type RotationFuture = BoxFuture<'static, io::Result<(File, PathBuf)>>;
 //--------------------------------------------------------------------------------------------------
 
 impl RotatingLog {
-    /// Creates a new rotating log file with the default maximum size.
+    /// Creates a new rotating log file with the configured maximum size and retention.
     ///
-    /// This is a convenience wrapper around [`with_max_size`] that uses the default
-    /// maximum log file size defined in `DEFAULT_LOG_MAX_SIZE`.
+    /// This is a convenience wrapper around [`with_rotation`] that uses the size and number of
+    /// rotated files from the `MSB_LOG_MAX_SIZE` and `MSB_LOG_MAX_FILES` environment variables,
+    /// falling back to `DEFAULT_LOG_MAX_SIZE` and `DEFAULT_LOG_MAX_FILES`.
     ///
     /// ## Arguments
     ///
@@ -110,10 +117,10 @@ impl RotatingLog {
     /// * The file cannot be created or opened
     /// * File metadata cannot be read
     pub async fn new(path: impl AsRef<Path>) -> io::Result<Self> {
-        Self::with_max_size(path, DEFAULT_LOG_MAX_SIZE).await
+        Self::with_rotation(path, env::get_log_max_size(), env::get_log_max_files()).await
     }
 
-    /// Creates a new rotating log file.
+    /// Creates a new rotating log file that keeps the default number of rotated files.
     ///
     /// ## Errors
     ///
@@ -121,6 +128,28 @@ impl RotatingLog {
     /// * The file cannot be created or opened
     /// * File metadata cannot be read
     pub async fn with_max_size(path: impl AsRef<Path>, max_size: u64) -> io::Result<Self> {
+        Self::with_rotation(path, max_size, DEFAULT_LOG_MAX_FILES).await
+    }
+
+    /// Creates a new rotating log file.
+    ///
+    /// ## Arguments
+    ///
+    /// * `path` - Path to the log file
+    /// * `max_size` - Size in bytes the log file is rotated at
+    /// * `max_files` - Number of rotated log files to keep. With `0`, the log is truncated when it
+    ///   reaches `max_size`
+    ///
+    /// ## Errors
+    ///
+    /// Will return an error if:
+    /// * The file cannot be created or opened
+    /// * File metadata cannot be read
+    pub async fn with_rotation(
+        path: impl AsRef<Path>,
+        max_size: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
         let path = path.as_ref().to_path_buf();
         let file = OpenOptions::new()
             .create(true)
@@ -141,13 +170,14 @@ impl RotatingLog {
 
         // Spawn background task to handle channel data
         let background_task = tokio::spawn(async move {
-            handle_channel_data(rx, bg_file, bg_path, bg_max_size, bg_size).await
+            handle_channel_data(rx, bg_file, bg_path, bg_max_size, max_files, bg_size).await
         });
 
         Ok(Self {
             file,
             path,
             max_size,
+            max_files,
             current_size,
             state: State::Idle,
             tx,
@@ -172,12 +202,41 @@ impl SyncChannelWriter {
 // Functions
 //--------------------------------------------------------------------------------------------------
 
+/// Returns the path of a rotated log file.
+///
+/// Index `0` is the most recently rotated file, `<name>.old`, and index `n` is `<name>.old.<n>`.
+pub fn rotated_log_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        path.with_extension("old")
+    } else {
+        path.with_extension(format!("old.{index}"))
+    }
+}
+
+/// Returns the existing files of a rotating log, oldest first and ending with the active file.
+///
+/// Reading the files in the returned order yields the log's lines in the order they were written.
+pub fn log_segments(path: &Path) -> Vec<PathBuf> {
+    let mut rotated: Vec<PathBuf> = (0..)
+        .map(|index| rotated_log_path(path, index))
+        .take_while(|rotated| rotated.exists())
+        .collect();
+    rotated.reverse();
+
+    if path.exists() {
+        rotated.push(path.to_path_buf());
+    }
+
+    rotated
+}
+
 /// Performs the actual log rotation operation.
 ///
 /// # Arguments
 ///
 /// * `file` - The current log file to be rotated
 /// * `path` - Path to the current log file
+/// * `max_files` - Number of rotated log files to keep
 ///
 /// # Returns
 ///
@@ -189,17 +248,35 @@ impl SyncChannelWriter {
 ///
 /// Will return an error if:
 /// * File synchronization fails
-/// * Old backup file cannot be removed
+/// * Old backup files cannot be removed
 /// * File rename operation fails
 /// * New log file cannot be created
-async fn do_rotation(file: File, path: PathBuf) -> io::Result<(File, PathBuf)> {
+async fn do_rotation(file: File, path: PathBuf, max_files: usize) -> io::Result<(File, PathBuf)> {
     file.sync_all().await?;
-    let backup_path = path.with_extension("old");
-    if backup_path.exists() {
-        remove_file(&backup_path).await?;
+
+    // Delete the oldest retained file along with any left over from a larger retention
+    let mut index = max_files.saturating_sub(1);
+    loop {
+        let rotated = rotated_log_path(&path, index);
+        if !rotated.exists() {
+            break;
+        }
+        remove_file(&rotated).await?;
+        index += 1;
     }
 
-    rename(&path, &backup_path).await?;
+    if max_files == 0 {
+        remove_file(&path).await?;
+    } else {
+        for index in (0..max_files - 1).rev() {
+            let rotated = rotated_log_path(&path, index);
+            if rotated.exists() {
+                rename(&rotated, rotated_log_path(&path, index + 1)).await?;
+            }
+        }
+
+        rename(&path, rotated_log_path(&path, 0)).await?;
+    }
 
     let new_file = OpenOptions::new()
         .create(true)
@@ -216,6 +293,7 @@ async fn handle_channel_data(
     mut file: File,
     path: PathBuf,
     max_size: u64,
+    max_files: usize,
     current_size: Arc<AtomicU64>,
 ) {
     while let Some(data) = rx.recv().await {
@@ -225,10 +303,11 @@ async fn handle_channel_data(
         if size + data_len > max_size {
             // Clone the file handle before rotation
             if let Ok(file_clone) = file.try_clone().await {
-                match do_rotation(file_clone, path.clone()).await {
+                match do_rotation(file_clone, path.clone(), max_files).await {
                     Ok((new_file, _)) => {
                         file = new_file;
-                        current_size.store(0, Ordering::Relaxed);
+                        // The new file starts with the data about to be written
+                        current_size.store(data_len, Ordering::Relaxed);
                     }
                     Err(e) => {
                         tracing::error!("failed to rotate log file: {}", e);
@@ -272,7 +351,7 @@ impl AsyncWrite for RotatingLog {
                             File::from_std(std::fs::File::open("/dev/null").unwrap()),
                         );
                         let old_path = this.path.clone();
-                        let fut = Box::pin(do_rotation(old_file, old_path));
+                        let fut = Box::pin(do_rotation(old_file, old_path, this.max_files));
                         this.state = State::Rotating(fut);
                     } else {
                         this.state = State::Writing;
@@ -290,7 +369,8 @@ impl AsyncWrite for RotatingLog {
                         Poll::Ready(Ok((new_file, new_path))) => {
                             this.file = new_file;
                             this.path = new_path;
-                            this.current_size.store(0, Ordering::Relaxed);
+                            // The new file starts with the buffer about to be written
+                            this.current_size.store(buf_len, Ordering::Relaxed);
                             this.state = State::Writing;
                         }
                     }
@@ -486,4 +566,51 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_rotation_prunes_files_beyond_retention() -> io::Result<()> {
+        let dir = tempdir()?;
+        let log_path = dir.path().join("test.log");
+
+        let mut log = RotatingLog::with_rotation(&log_path, 20, 2).await?;
+
+        // Each entry is large enough to rotate the one before it out
+        for i in 0..5 {
+            log.write_all(format!("rotation entry {}\n", i).as_bytes())
+                .await?;
+            log.flush().await?;
+        }
+
+        assert_eq!(fs::read_to_string(&log_path)?, "rotation entry 4\n");
+        assert_eq!(
+            fs::read_to_string(rotated_log_path(&log_path, 0))?,
+            "rotation entry 3\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_log_path(&log_path, 1))?,
+            "rotation entry 2\n"
+        );
+        assert!(!rotated_log_path(&log_path, 2).exists());
+
+        // Segments are returned oldest first, ending with the active file
+        assert_eq!(
+            log_segments(&log_path),
+            vec![
+                rotated_log_path(&log_path, 1),
+                rotated_log_path(&log_path, 0),
+                log_path.clone(),
+            ]
+        );
+
+        // Lowering the retention removes the files the new count no longer covers
+        drop(log);
+        let mut log = RotatingLog::with_rotation(&log_path, 20, 1).await?;
+        log.write_all(b"rotation entry 5\n").await?;
+        log.flush().await?;
+
+        assert!(rotated_log_path(&log_path, 0).exists());
+        assert!(!rotated_log_path(&log_path, 1).exists());
+
+        Ok(())
+    }
 }
@@ -29,6 +29,11 @@ pub const RW_SUBDIR: &str = "rw";
 /// Example: <PROJECT_ROOT>/<MICROSANDBOX_ENV_DIR>/<PATCH_SUBDIR>
 pub const PATCH_SUBDIR: &str = "patch";
 
+/// The directory where the merged root filesystems of native-mode image sandboxes are stored
+///
+/// Example: <PROJECT_ROOT>/<MICROSANDBOX_ENV_DIR>/<ROOTFS_SUBDIR>
+pub const ROOTFS_SUBDIR: &str = "rootfs";
+
 /// The directory where project logs are stored
 ///
 /// Example: <PROJECT_ROOT>/<MICROSANDBOX_ENV_DIR>/<LOG_SUBDIR>
@@ -39,11 +44,26 @@ pub const LOG_SUBDIR: &str = "log";
 /// Example: <MICROSANDBOX_HOME_DIR>/<LAYERS_SUBDIR>
 pub const LAYERS_SUBDIR: &str = "layers";
 
+/// The directory where downloaded layer blobs are shared between image pulls
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<BLOBS_SUBDIR>/<LAYER_DIGEST>.tar
+pub const BLOBS_SUBDIR: &str = "blobs";
+
 /// The directory where installed sandboxes are stored
 ///
 /// Example: <MICROSANDBOX_HOME_DIR>/<INSTALLS_SUBDIR>
 pub const INSTALLS_SUBDIR: &str = "installs";
 
+/// The directory where sandbox read-write layer snapshots are stored
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<SNAPSHOTS_SUBDIR>/<SNAPSHOT_NAME>.tar
+pub const SNAPSHOTS_SUBDIR: &str = "snapshots";
+
+/// The directory the lock files of layers are kept in, next to the layers directory
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<LAYER_LOCKS_SUBDIR>/<LAYER_ID>.lock
+pub const LAYER_LOCKS_SUBDIR: &str = "layer-locks";
+
 /// The filename for the project active sandbox database
 ///
 /// Example: <PROJECT_ROOT>/<MICROSANDBOX_ENV_DIR>/<SANDBOX_DB_FILENAME>
@@ -67,6 +87,17 @@ pub const SCRIPTS_DIR: &str = "scripts";
 /// Example: <MICROSANDBOX_HOME_DIR>/<LAYERS_SUBDIR>/<LAYER_ID>.<EXTRACTED_LAYER_SUFFIX>
 pub const EXTRACTED_LAYER_SUFFIX: &str = "extracted";
 
+/// The suffix of the marker file written next to an extracted layer directory once the layer has
+/// been fully extracted
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<LAYERS_SUBDIR>/<LAYER_ID>.<EXTRACTED_LAYER_SUFFIX>.<EXTRACTION_COMPLETE_SUFFIX>
+pub const EXTRACTION_COMPLETE_SUFFIX: &str = "complete";
+
+/// The suffix added to directories holding entries extracted individually from an indexed layer
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<LAYERS_SUBDIR>/<LAYER_ID>.<INDEXED_LAYER_SUFFIX>
+pub const INDEXED_LAYER_SUFFIX: &str = "indexed";
+
 /// The microsandbox config file name.
 ///
 /// Example: <PROJECT_ROOT>/<MICROSANDBOX_ENV_DIR>/<SANDBOX_DB_FILENAME>
@@ -92,11 +123,36 @@ pub const SERVER_PID_FILE: &str = "server.pid";
 /// Example: <MICROSANDBOX_HOME_DIR>/<SERVER_KEY_FILE>
 pub const SERVER_KEY_FILE: &str = "server.key";
 
+/// The file the identifier a development server key is derived from is kept in
+///
+/// Example: <PROJECT_ROOT>/<MICROSANDBOX_ENV_DIR>/<DEV_SERVER_ID_FILE>
+pub const DEV_SERVER_ID_FILE: &str = "dev-server.id";
+
+/// The file `secret://` environment references are resolved against, in env file format
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<SECRETS_FILE>
+pub const SECRETS_FILE: &str = "secrets.env";
+
+/// The file registry credentials saved by `msb login` are stored in
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<CREDENTIALS_FILE>
+pub const CREDENTIALS_FILE: &str = "credentials.json";
+
+/// The template new configuration files are created from in place of the built-in default
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<CONFIG_TEMPLATE_FILE>
+pub const CONFIG_TEMPLATE_FILE: &str = "Sandboxfile.template";
+
 /// The file where sandbox portal ports are stored
 ///
 /// Example: <MICROSANDBOX_HOME_DIR>/<PROJECTS_SUBDIR>/<PORTAL_PORTS_FILE>
 pub const PORTAL_PORTS_FILE: &str = "portal.ports";
 
+/// The file where the idle policies of sandboxes started through the server are stored
+///
+/// Example: <MICROSANDBOX_HOME_DIR>/<PROJECTS_SUBDIR>/<IDLE_POLICIES_FILE>
+pub const IDLE_POLICIES_FILE: &str = "idle.policies";
+
 /// The XDG home directory
 ///
 /// Example: <HOME>/.local
@@ -116,6 +172,9 @@ pub const XDG_LIB_DIR: &str = "lib";
 /// The suffix for log files
 pub const LOG_SUFFIX: &str = "log";
 
+/// The suffix for files capturing a microVM's guest console output
+pub const CONSOLE_LOG_SUFFIX: &str = "console.log";
+
 /// The filename for the supervisor's log file
 pub const SUPERVISOR_LOG_FILENAME: &str = "supervisor.log";
 
@@ -1,6 +1,7 @@
 //! `microsandbox_utils::runtime` is a module containing runtime utilities for the microsandbox project.
 
 mod monitor;
+mod shutdown;
 mod supervisor;
 
 //--------------------------------------------------------------------------------------------------
@@ -8,4 +9,5 @@ mod supervisor;
 //--------------------------------------------------------------------------------------------------
 
 pub use monitor::*;
+pub use shutdown::*;
 pub use supervisor::*;
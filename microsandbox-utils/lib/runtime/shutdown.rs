@@ -0,0 +1,199 @@
+//! Graceful shutdown of child processes on SIGINT and SIGTERM.
+//!
+//! A process that runs a child in the foreground should take the child down with it when it is
+//! interrupted or terminated, rather than leaving it running. The child is asked to stop with
+//! `SIGTERM` first and killed with `SIGKILL` if it hasn't exited after a grace period, and it is
+//! always reaped.
+
+use std::{future::Future, process::ExitStatus, time::Duration};
+
+use nix::{
+    sys::signal::{self, Signal},
+    unistd::Pid,
+};
+use tokio::{
+    process::Child,
+    signal::unix::{self as unix_signal, SignalKind},
+};
+
+use crate::MicrosandboxUtilsResult;
+
+//--------------------------------------------------------------------------------------------------
+// Constants
+//--------------------------------------------------------------------------------------------------
+
+/// How long a child is given to exit after `SIGTERM` before it is killed.
+pub const DEFAULT_SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Handlers for the signals that ask a process to shut down, SIGINT and SIGTERM.
+///
+/// While this is alive, the signals no longer terminate the process and are delivered through
+/// [`ShutdownSignals::recv`] instead.
+pub struct ShutdownSignals {
+    /// The SIGINT handler
+    sigint: unix_signal::Signal,
+
+    /// The SIGTERM handler
+    sigterm: unix_signal::Signal,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl ShutdownSignals {
+    /// Installs the SIGINT and SIGTERM handlers.
+    pub fn new() -> MicrosandboxUtilsResult<Self> {
+        Ok(Self {
+            sigint: unix_signal::signal(SignalKind::interrupt())?,
+            sigterm: unix_signal::signal(SignalKind::terminate())?,
+        })
+    }
+
+    /// Waits for SIGINT or SIGTERM and returns the one that was received.
+    pub async fn recv(&mut self) -> Signal {
+        tokio::select! {
+            _ = self.sigint.recv() => Signal::SIGINT,
+            _ = self.sigterm.recv() => Signal::SIGTERM,
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Functions
+//--------------------------------------------------------------------------------------------------
+
+/// Waits for a child to exit, stopping it if a shutdown signal arrives first.
+///
+/// ## Arguments
+///
+/// * `child` - The child to wait for
+/// * `shutdown` - Resolves with the signal that asks for the child to be stopped, usually
+///   [`ShutdownSignals::recv`]
+/// * `grace_period` - How long the child is given to exit after `SIGTERM` before it is killed
+///
+/// ## Returns
+///
+/// The exit status of the child, and the shutdown signal if one stopped it
+pub async fn wait_or_terminate(
+    child: &mut Child,
+    shutdown: impl Future<Output = Signal>,
+    grace_period: Duration,
+) -> MicrosandboxUtilsResult<(ExitStatus, Option<Signal>)> {
+    tokio::select! {
+        status = child.wait() => Ok((status?, None)),
+        signal = shutdown => {
+            tracing::info!("received {}, stopping child process", signal);
+            let status = terminate_child(child, grace_period).await?;
+            Ok((status, Some(signal)))
+        }
+    }
+}
+
+/// Stops a child with `SIGTERM`, killing it if it hasn't exited after `grace_period`, and reaps
+/// it.
+///
+/// ## Returns
+///
+/// The exit status of the child
+pub async fn terminate_child(
+    child: &mut Child,
+    grace_period: Duration,
+) -> MicrosandboxUtilsResult<ExitStatus> {
+    // A child that has already exited has no pid, and only needs reaping
+    if let Some(pid) = child.id()
+        && let Err(e) = signal::kill(Pid::from_raw(pid as i32), Signal::SIGTERM)
+    {
+        tracing::error!("failed to send SIGTERM to process {}: {}", pid, e);
+    }
+
+    match tokio::time::timeout(grace_period, child.wait()).await {
+        Ok(status) => Ok(status?),
+        Err(_) => {
+            tracing::warn!(
+                "child process did not exit within {:?} of SIGTERM, killing it",
+                grace_period
+            );
+            child.kill().await?;
+            Ok(child.wait().await?)
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+
+    use tokio::process::Command;
+
+    use super::*;
+
+    fn spawn_stub(script: &str) -> std::io::Result<Child> {
+        Command::new("sh").arg("-c").arg(script).spawn()
+    }
+
+    #[tokio::test]
+    async fn test_wait_or_terminate_forwards_signal() -> anyhow::Result<()> {
+        // The stub shuts down cleanly with a distinct exit code when asked to
+        let mut child = spawn_stub("trap 'exit 42' TERM; while true; do sleep 0.1; done")?;
+        let pid = child.id().expect("child should be running");
+
+        // Give the stub time to install its trap before the signal arrives
+        let shutdown = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            Signal::SIGINT
+        };
+        let (status, signal) =
+            wait_or_terminate(&mut child, shutdown, Duration::from_secs(5)).await?;
+
+        assert_eq!(signal, Some(Signal::SIGINT));
+        assert_eq!(status.code(), Some(42));
+
+        // The child has been reaped
+        assert!(child.id().is_none());
+        assert_eq!(
+            signal::kill(Pid::from_raw(pid as i32), None),
+            Err(nix::errno::Errno::ESRCH)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_terminate_child_kills_after_grace_period() -> anyhow::Result<()> {
+        // The stub ignores SIGTERM, so only SIGKILL stops it
+        let mut child = spawn_stub("trap '' TERM; while true; do sleep 0.1; done")?;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let status = terminate_child(&mut child, Duration::from_millis(200)).await?;
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert!(child.id().is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_wait_or_terminate_returns_exit_status() -> anyhow::Result<()> {
+        let mut child = spawn_stub("exit 3")?;
+
+        let (status, signal) = wait_or_terminate(
+            &mut child,
+            std::future::pending(),
+            DEFAULT_SHUTDOWN_GRACE_PERIOD,
+        )
+        .await?;
+
+        assert_eq!(signal, None);
+        assert_eq!(status.code(), Some(3));
+
+        Ok(())
+    }
+}
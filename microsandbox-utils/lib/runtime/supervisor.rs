@@ -1,7 +1,6 @@
 use nix::{
     fcntl::{FcntlArg, OFlag, fcntl},
     pty::openpty,
-    unistd::Pid,
 };
 use std::{
     os::unix::io::{FromRawFd, IntoRawFd},
@@ -12,12 +11,11 @@ use tokio::{
     fs::{File, create_dir_all},
     io::unix::AsyncFd,
     process::Command,
-    signal::unix::{SignalKind, signal},
 };
 
 use crate::{
-    ChildIo, MicrosandboxUtilsResult, ProcessMonitor, RotatingLog, path::SUPERVISOR_LOG_FILENAME,
-    term,
+    ChildIo, DEFAULT_SHUTDOWN_GRACE_PERIOD, MicrosandboxUtilsResult, ProcessMonitor, RotatingLog,
+    ShutdownSignals, path::SUPERVISOR_LOG_FILENAME, term, terminate_child,
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -188,8 +186,7 @@ where
         self.process_monitor.start(child_pid, child_io).await?;
 
         // Setup signal handlers
-        let mut sigterm = signal(SignalKind::terminate())?;
-        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut signals = ShutdownSignals::new()?;
 
         // Wait for either child process to exit or signal to be received
         tokio::select! {
@@ -222,36 +219,15 @@ where
                     );
                 }
             }
-            _ = sigterm.recv() => {
+            signal = signals.recv() => {
                 // Stop process monitoring
                 self.process_monitor.stop().await?;
 
-                tracing::info!("received SIGTERM signal");
+                tracing::info!("received {} signal", signal);
 
-                if let Some(pid) = self.child_pid.take()
-                    && let Err(e) = nix::sys::signal::kill(Pid::from_raw(pid as i32), nix::sys::signal::Signal::SIGTERM) {
-                    tracing::error!("failed to send SIGTERM to process {pid}: {e}");
-                }
-
-                // Wait for child to exit after sending signal
-                if let Err(e) = child.wait().await {
-                    tracing::error!("error waiting for child after SIGTERM: {e}");
-                }
-            }
-            _ = sigint.recv() => {
-                // Stop process monitoring
-                self.process_monitor.stop().await?;
-
-                tracing::info!("received SIGINT signal");
-
-                if let Some(pid) = self.child_pid.take()
-                    && let Err(e) = nix::sys::signal::kill(Pid::from_raw(pid as i32), nix::sys::signal::Signal::SIGTERM) {
-                    tracing::error!("failed to send SIGTERM to process {pid}: {e}");
-                }
-
-                // Wait for child to exit after sending signal
-                if let Err(e) = child.wait().await {
-                    tracing::error!("error waiting for child after SIGINT: {e}");
+                // Ask the child to shut the sandbox down, killing it if it doesn't in time
+                if let Err(e) = terminate_child(&mut child, DEFAULT_SHUTDOWN_GRACE_PERIOD).await {
+                    tracing::error!("error stopping child after {}: {}", signal, e);
                 }
             }
         }
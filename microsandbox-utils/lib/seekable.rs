@@ -19,6 +19,40 @@ pub struct EmptySeekableReader;
 #[derive(Debug)]
 pub struct EmptySeekableWriter;
 
+/// A reader that reads several readers one after the other, as if they were a single stream.
+///
+/// This lets the layer tars of an image be streamed out back to back, e.g. to export the image,
+/// without writing them out to disk first. A read never spans two readers, so the boundary between
+/// them is invisible to the caller: the next reader is only moved on to once the current one hits
+/// EOF, and EOF is only reported once the last reader hits it.
+#[derive(Debug)]
+pub struct ConcatReader<R> {
+    /// The readers to read from, in order.
+    readers: Vec<R>,
+
+    /// The index of the reader currently being read from.
+    current: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl<R> ConcatReader<R> {
+    /// Creates a reader that reads `readers` one after the other.
+    pub fn new(readers: impl IntoIterator<Item = R>) -> Self {
+        Self {
+            readers: readers.into_iter().collect(),
+            current: 0,
+        }
+    }
+
+    /// Returns the underlying readers, including those already read to the end.
+    pub fn into_inner(self) -> Vec<R> {
+        self.readers
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Traits
 //--------------------------------------------------------------------------------------------------
@@ -58,3 +92,77 @@ impl AsyncSeek for EmptySeekableReader {
         Poll::Ready(Ok(0))
     }
 }
+
+impl<R> AsyncRead for ConcatReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        while let Some(reader) = this.readers.get_mut(this.current) {
+            let filled = buf.filled().len();
+            match Pin::new(reader).poll_read(cx, buf) {
+                Poll::Ready(Ok(())) if buf.filled().len() == filled => {
+                    // The current reader is exhausted, so carry on with the next one
+                    this.current += 1;
+                }
+                poll => return poll,
+            }
+        }
+
+        // All readers are exhausted
+        Poll::Ready(Ok(()))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_concat_reader_reads_readers_in_order() -> anyhow::Result<()> {
+        let mut reader = ConcatReader::new([&b"first layer"[..], &b""[..], &b", second layer"[..]]);
+
+        let mut contents = Vec::new();
+        reader.read_to_end(&mut contents).await?;
+        assert_eq!(contents, b"first layer, second layer");
+
+        // Reads past the end keep reporting EOF
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concat_reader_does_not_span_readers_in_one_read() -> anyhow::Result<()> {
+        let mut reader = ConcatReader::new([&b"abc"[..], &b"defg"[..]]);
+        let mut buf = [0; 5];
+
+        assert_eq!(reader.read(&mut buf).await?, 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(reader.read(&mut buf).await?, 4);
+        assert_eq!(&buf[..4], b"defg");
+        assert_eq!(reader.read(&mut buf).await?, 0);
+
+        // A reader over no readers is empty
+        let mut reader = ConcatReader::new(Vec::<&[u8]>::new());
+        assert_eq!(reader.read(&mut buf).await?, 0);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,13 @@
 //! Module containing terminal utilities
 
-use indicatif::{MultiProgress, MultiProgressAlignment, ProgressBar, ProgressStyle};
-use std::sync::{Arc, LazyLock};
+use indicatif::{HumanBytes, MultiProgress, MultiProgressAlignment, ProgressBar, ProgressStyle};
+use std::{
+    io::Write,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+};
 
 //--------------------------------------------------------------------------------------------------
 // Constants
@@ -29,6 +35,263 @@ pub static TICK_STRINGS: LazyLock<[&str; 11]> =
 /// The error tick strings for CLI visualizations
 pub static ERROR_TICK_STRINGS: LazyLock<[&str; 2]> = LazyLock::new(|| ["⠏", &ERROR_MARK]);
 
+//--------------------------------------------------------------------------------------------------
+// Types
+//--------------------------------------------------------------------------------------------------
+
+/// Progress of an operation made up of several items, such as the layers of an image.
+///
+/// On an interactive terminal each item gets a byte progress bar in [`MULTI_PROGRESS`]. Otherwise
+/// progress is written as plain lines, one when an item starts and one when it finishes, so logs
+/// stay readable. Nothing is shown when the multi-progress display is hidden.
+///
+/// Cloning the progress gives another handle to the same operation.
+#[derive(Clone)]
+pub struct MultiItemProgress {
+    inner: Arc<MultiItemInner>,
+}
+
+/// Progress of a single item of a [`MultiItemProgress`].
+///
+/// An item that is dropped without being finished is counted as failed.
+pub struct ItemProgress {
+    /// The operation the item belongs to
+    group: Arc<MultiItemInner>,
+
+    /// The name the item is shown with
+    name: String,
+
+    /// The progress bar of the item, when rendering bars
+    bar: Option<ProgressBar>,
+
+    /// The bytes processed so far
+    bytes: AtomicU64,
+
+    /// Whether the item has been finished
+    done: AtomicBool,
+}
+
+/// State shared between a [`MultiItemProgress`] and its items.
+struct MultiItemInner {
+    /// The name of the operation, used to prefix plain lines and the summary
+    label: String,
+
+    /// Where progress is rendered
+    output: ProgressOutput,
+
+    /// Counts of the items of the operation
+    counts: Mutex<ItemCounts>,
+}
+
+/// Where the progress of a [`MultiItemProgress`] is rendered.
+enum ProgressOutput {
+    /// Progress bars in [`MULTI_PROGRESS`]
+    Bars,
+
+    /// Plain lines written to a writer
+    Plain(Mutex<Box<dyn Write + Send>>),
+
+    /// Nowhere
+    Hidden,
+}
+
+/// Counts of the items of a [`MultiItemProgress`].
+#[derive(Debug, Default)]
+struct ItemCounts {
+    started: usize,
+    finished: usize,
+    failed: usize,
+    bytes: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Methods
+//--------------------------------------------------------------------------------------------------
+
+impl MultiItemProgress {
+    /// Creates the progress of an operation, rendered to suit the terminal.
+    ///
+    /// Progress bars are used on an interactive ANSI terminal and plain lines on stderr otherwise.
+    /// When `visible` is false, or the multi-progress display is hidden, nothing is shown.
+    ///
+    /// ## Arguments
+    ///
+    /// * `label` - The name of the operation, e.g. "Extracting layers"
+    /// * `visible` - Whether progress should be shown at all
+    pub fn new(label: impl Into<String>, visible: bool) -> Self {
+        let output = if !visible || MULTI_PROGRESS.is_hidden() {
+            ProgressOutput::Hidden
+        } else if is_ansi_interactive_terminal() {
+            ProgressOutput::Bars
+        } else {
+            ProgressOutput::Plain(Mutex::new(Box::new(std::io::stderr())))
+        };
+
+        Self::with_output(label.into(), output)
+    }
+
+    /// Creates the progress of an operation that is written as plain lines to `writer`.
+    pub fn plain(label: impl Into<String>, writer: impl Write + Send + 'static) -> Self {
+        Self::with_output(
+            label.into(),
+            ProgressOutput::Plain(Mutex::new(Box::new(writer))),
+        )
+    }
+
+    /// Creates the progress of an operation that isn't shown.
+    pub fn hidden(label: impl Into<String>) -> Self {
+        Self::with_output(label.into(), ProgressOutput::Hidden)
+    }
+
+    fn with_output(label: String, output: ProgressOutput) -> Self {
+        Self {
+            inner: Arc::new(MultiItemInner {
+                label,
+                output,
+                counts: Mutex::new(ItemCounts::default()),
+            }),
+        }
+    }
+
+    /// Starts an item of the operation.
+    ///
+    /// ## Arguments
+    ///
+    /// * `name` - The name the item is shown with, e.g. a short layer digest
+    /// * `total_bytes` - The number of bytes the item is expected to process
+    pub fn start_item(&self, name: impl Into<String>, total_bytes: u64) -> ItemProgress {
+        let name = name.into();
+        self.inner.counts.lock().unwrap().started += 1;
+
+        let bar = match &self.inner.output {
+            ProgressOutput::Bars => {
+                let pb = MULTI_PROGRESS.add(ProgressBar::new(total_bytes));
+                pb.set_style(
+                    ProgressStyle::with_template(
+                        "{prefix:.bold.dim} {bar:40.green/green.dim} {bytes:.bold} / {total_bytes:.dim}",
+                    )
+                    .unwrap()
+                    .progress_chars("=+-"),
+                );
+                pb.set_prefix(name.clone());
+                Some(pb)
+            }
+            _ => {
+                self.inner
+                    .write_line(&format!("{} started ({})", name, HumanBytes(total_bytes)));
+                None
+            }
+        };
+
+        ItemProgress {
+            group: Arc::clone(&self.inner),
+            name,
+            bar,
+            bytes: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns a one-line summary of the items of the operation.
+    pub fn summary(&self) -> String {
+        let counts = self.inner.counts.lock().unwrap();
+        let mut summary = format!(
+            "{}: {} of {} done ({})",
+            self.inner.label,
+            counts.finished,
+            counts.started,
+            HumanBytes(counts.bytes)
+        );
+        if counts.failed > 0 {
+            summary.push_str(&format!(", {} failed", counts.failed));
+        }
+
+        summary
+    }
+
+    /// Finishes the operation, showing its summary line.
+    pub fn finish(&self) {
+        let summary = self.summary();
+        match &self.inner.output {
+            ProgressOutput::Bars => {
+                let _ = MULTI_PROGRESS.println(format!("{} {}", *CHECKMARK, summary));
+            }
+            ProgressOutput::Plain(writer) => {
+                let _ = writeln!(writer.lock().unwrap(), "{}", summary);
+            }
+            ProgressOutput::Hidden => {}
+        }
+    }
+}
+
+impl ItemProgress {
+    /// Sets the number of bytes processed so far, e.g. when resuming a download.
+    pub fn set_position(&self, bytes: u64) {
+        self.bytes.store(bytes, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.set_position(bytes);
+        }
+    }
+
+    /// Adds to the number of bytes processed so far.
+    pub fn inc(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        if let Some(bar) = &self.bar {
+            bar.inc(bytes);
+        }
+    }
+
+    /// Finishes the item successfully.
+    pub fn finish(&self) {
+        self.complete(true);
+    }
+
+    fn complete(&self, success: bool) {
+        if self.done.swap(true, Ordering::AcqRel) {
+            return;
+        }
+
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        {
+            let mut counts = self.group.counts.lock().unwrap();
+            if success {
+                counts.finished += 1;
+                counts.bytes += bytes;
+            } else {
+                counts.failed += 1;
+            }
+        }
+
+        match &self.bar {
+            Some(bar) => bar.finish_and_clear(),
+            None if success => {
+                self.group
+                    .write_line(&format!("{} done ({})", self.name, HumanBytes(bytes)))
+            }
+            None => self.group.write_line(&format!("{} failed", self.name)),
+        }
+    }
+}
+
+impl MultiItemInner {
+    /// Writes a line prefixed with the operation's label, when rendering plain lines.
+    fn write_line(&self, line: &str) {
+        if let ProgressOutput::Plain(writer) = &self.output {
+            let _ = writeln!(writer.lock().unwrap(), "{}: {}", self.label, line);
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Trait Implementations
+//--------------------------------------------------------------------------------------------------
+
+impl Drop for ItemProgress {
+    fn drop(&mut self) {
+        self.complete(false);
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Functions
 //--------------------------------------------------------------------------------------------------
@@ -119,3 +382,81 @@ pub fn finish_with_error(pb: &ProgressBar) {
     pb.set_style(style);
     pb.finish();
 }
+
+//--------------------------------------------------------------------------------------------------
+// Tests
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A writer whose contents can be read after it has been handed off
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_multi_item_progress_plain_output() {
+        let buffer = SharedBuffer::default();
+        let progress = MultiItemProgress::plain("Extracting layers", buffer.clone());
+
+        let first = progress.start_item("a1b2c3d4", 300);
+        let second = progress.start_item("e5f6a7b8", 200);
+
+        first.inc(100);
+        first.inc(200);
+        first.finish();
+
+        second.set_position(200);
+        second.finish();
+
+        // Finishing twice doesn't count the item again
+        second.finish();
+
+        progress.finish();
+
+        assert_eq!(
+            buffer.contents(),
+            "Extracting layers: a1b2c3d4 started (300 B)\n\
+             Extracting layers: e5f6a7b8 started (200 B)\n\
+             Extracting layers: a1b2c3d4 done (300 B)\n\
+             Extracting layers: e5f6a7b8 done (200 B)\n\
+             Extracting layers: 2 of 2 done (500 B)\n"
+        );
+    }
+
+    #[test]
+    fn test_multi_item_progress_counts_dropped_items_as_failed() {
+        let buffer = SharedBuffer::default();
+        let progress = MultiItemProgress::plain("Downloading layers", buffer.clone());
+
+        progress.start_item("a1b2c3d4", 10).finish();
+        drop(progress.start_item("e5f6a7b8", 10));
+
+        assert_eq!(
+            progress.summary(),
+            "Downloading layers: 1 of 2 done (0 B), 1 failed"
+        );
+        assert!(
+            buffer
+                .contents()
+                .contains("Downloading layers: e5f6a7b8 failed\n")
+        );
+    }
+}